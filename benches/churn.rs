@@ -0,0 +1,83 @@
+//! Connects, echoes a few bytes and disconnects in a tight loop, then
+//! reports how many heap allocations the server thread made for the
+//! first connection, which finds no closed client to reuse, and per
+//! connection after it.
+//!
+//!     cargo bench --bench churn
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mio_echo_server::Config;
+
+mod support;
+
+use support::TestServer;
+
+const CONNECTIONS: u64 = 10_000;
+
+// Counts the allocations of the threads that opted in
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static COUNTED: Cell<bool> = const { Cell::new(false) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if COUNTED.with(Cell::get) {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn main() {
+    let server = TestServer::with_hook(Config::new("127.0.0.1:0"), || {
+        COUNTED.with(|counted| counted.set(true));
+    });
+
+    let mut buf = [0; 16];
+    let mut echo = || {
+        let mut sock = TcpStream::connect(server.addr).expect("connect failed");
+        sock.write_all(b"0123456789abcdef").unwrap();
+        sock.read_exact(&mut buf).unwrap();
+    };
+
+    echo();
+    // Leaves the server time to notice the close
+    thread::sleep(Duration::from_millis(100));
+    let first = ALLOCATIONS.load(Ordering::Relaxed);
+
+    let start = Instant::now();
+    for _ in 1..CONNECTIONS {
+        echo();
+    }
+    let elapsed = start.elapsed();
+
+    thread::sleep(Duration::from_millis(100));
+    server.stop();
+    let recycled = ALLOCATIONS.load(Ordering::Relaxed) - first;
+
+    println!(
+        "first connection: {} allocations, then {} connections in {:?}: {:.2} allocations per connection",
+        first,
+        CONNECTIONS - 1,
+        elapsed,
+        recycled as f64 / (CONNECTIONS - 1) as f64
+    );
+}
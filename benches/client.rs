@@ -0,0 +1,130 @@
+//! Client buffering against an in-memory socket, no network involved.
+//!
+//!     cargo bench --bench client
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use mio::event::Source;
+use mio::{Interest, Registry, Token};
+use mio_echo_server::{Client, PeerAddr, Socket, Transport};
+
+const STREAM_SIZE: usize = 1 << 20;
+const QUEUED_BUF_SIZE: usize = 1024;
+
+// Hands out `input` then would block, and swallows whatever is written,
+// both at most `chunk` bytes per call
+struct MemSocket {
+    input: Vec<u8>,
+    pos: usize,
+    chunk: usize,
+}
+
+impl MemSocket {
+    fn new(input: Vec<u8>, chunk: usize) -> MemSocket {
+        MemSocket { input, pos: 0, chunk }
+    }
+}
+
+impl Read for MemSocket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.chunk).min(self.input.len() - self.pos);
+        if len == 0 {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        buf[..len].copy_from_slice(&self.input[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+impl Write for MemSocket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len().min(self.chunk))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Source for MemSocket {
+    fn register(&mut self, _: &Registry, _: Token, _: Interest) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn reregister(&mut self, _: &Registry, _: Token, _: Interest) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn deregister(&mut self, _: &Registry) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Socket for MemSocket {}
+
+fn client(sock: MemSocket, bufs: VecDeque<Vec<u8>>) -> Client<MemSocket> {
+    let peer = PeerAddr::Inet("127.0.0.1:7".parse().unwrap());
+    Client::new(sock, peer, Transport::Tcp, None, bufs)
+}
+
+fn write(c: &mut Criterion) {
+    let mut group = c.benchmark_group("client_write");
+    for &count in &[1, 16, 256] {
+        group.throughput(Throughput::Bytes((count * QUEUED_BUF_SIZE) as u64));
+        group.bench_function(BenchmarkId::new("buffers", count), |b| {
+            b.iter_batched(
+                || {
+                    let mut client = client(MemSocket::new(Vec::new(), usize::MAX), VecDeque::new());
+                    client.bufs.extend((0..count).map(|_| vec![0; QUEUED_BUF_SIZE]));
+                    client
+                },
+                |mut client| client.write(false).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn read(c: &mut Criterion) {
+    let mut group = c.benchmark_group("client_read");
+    group.throughput(Throughput::Bytes(STREAM_SIZE as u64));
+    for &chunk in &[64, 1024, 16 * 1024] {
+        group.bench_function(BenchmarkId::new("chunk", chunk), |b| {
+            b.iter_batched(
+                || client(MemSocket::new(vec![0; STREAM_SIZE], chunk), VecDeque::new()),
+                |mut client| client.read().unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+// One short echo per connection, the queue either recycled or fresh
+fn churn(c: &mut Criterion) {
+    let mut group = c.benchmark_group("queue_churn");
+    group.bench_function("recycled", |b| {
+        let mut spare = Some(VecDeque::new());
+        b.iter(|| {
+            let mut client = client(MemSocket::new(vec![0; 16], usize::MAX), spare.take().unwrap());
+            client.read().unwrap();
+            client.write(false).unwrap();
+            spare = Some(client.into_bufs());
+        })
+    });
+    group.bench_function("fresh", |b| {
+        b.iter(|| {
+            let mut client = client(MemSocket::new(vec![0; 16], usize::MAX), VecDeque::new());
+            client.read().unwrap();
+            client.write(false).unwrap();
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, write, read, churn);
+criterion_main!(benches);
@@ -0,0 +1,71 @@
+//! End-to-end loopback echo throughput.
+//!
+//!     cargo bench --bench echo
+//!
+//! With `--features io-uring` the uring backend runs the same benches
+//! under `echo/uring-connections`, next to the mio ones.
+//!
+//! Criterion compares every run with the previous one. To compare with a
+//! fixed point instead, record it with `cargo bench -- --save-baseline
+//! main` and compare with `cargo bench -- --baseline main`.
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mio_echo_server::{Backend, Config};
+
+mod support;
+
+use support::TestServer;
+
+// Echoed in one write: a reply split over several would wait on Nagle
+// and delayed ACKs, and the bench would time those instead
+const PAYLOAD_SIZE: usize = 1024;
+
+fn echo(c: &mut Criterion) {
+    let mut group = c.benchmark_group("echo");
+    let payload = vec![0x5a; PAYLOAD_SIZE];
+    let mut reply = vec![0; PAYLOAD_SIZE];
+
+    let mut backends = vec![(Backend::Mio, "connections")];
+    if cfg!(feature = "io-uring") {
+        backends.push((Backend::Uring, "uring-connections"));
+    }
+    for &(backend, name) in &backends {
+        for &connections in &[1, 100] {
+            let server = TestServer::with_config(Config {
+                backend,
+                ..Config::new("127.0.0.1:0")
+            });
+            let mut socks: Vec<TcpStream> = (0..connections)
+                .map(|_| {
+                    let sock = TcpStream::connect(server.addr).expect("connect failed");
+                    sock.set_nodelay(true).unwrap();
+                    sock
+                })
+                .collect();
+
+            group.throughput(Throughput::Bytes((PAYLOAD_SIZE * connections) as u64));
+            group.bench_function(BenchmarkId::new(name, connections), |b| {
+                b.iter(|| {
+                    // Everyone writes before anyone reads, so the loop
+                    // sees all connections busy at once
+                    for sock in &mut socks {
+                        sock.write_all(&payload).unwrap();
+                    }
+                    for sock in &mut socks {
+                        sock.read_exact(&mut reply).unwrap();
+                    }
+                })
+            });
+
+            drop(socks);
+            server.stop();
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, echo);
+criterion_main!(benches);
@@ -0,0 +1,58 @@
+//! Loopback echo round-trip times of one connection, with and without
+//! `Config::spin`, reported as percentiles.
+//!
+//!     cargo bench --bench rtt
+//!
+//! Spinning needs a core of its own to pay off: with fewer cores than
+//! the server and the client have threads, it only gets in the way.
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use mio_echo_server::Config;
+
+mod support;
+
+use support::TestServer;
+
+const ROUND_TRIPS: usize = 20_000;
+const WARMUP: usize = 1_000;
+const PAYLOAD_SIZE: usize = 64;
+
+fn main() {
+    for &spin in &[None, Some(Duration::from_micros(200))] {
+        let server = TestServer::with_config(Config {
+            spin,
+            ..Config::new("127.0.0.1:0")
+        });
+        let mut sock = TcpStream::connect(server.addr).expect("connect failed");
+        sock.set_nodelay(true).unwrap();
+
+        let payload = [0x5a; PAYLOAD_SIZE];
+        let mut reply = [0; PAYLOAD_SIZE];
+        let mut rtts = Vec::with_capacity(ROUND_TRIPS);
+        for i in 0..WARMUP + ROUND_TRIPS {
+            let start = Instant::now();
+            sock.write_all(&payload).unwrap();
+            sock.read_exact(&mut reply).unwrap();
+            if i >= WARMUP {
+                rtts.push(start.elapsed());
+            }
+        }
+        drop(sock);
+        let stats = server.stop();
+        rtts.sort();
+
+        let percentile = |p: usize| rtts[(rtts.len() * p / 100).min(rtts.len() - 1)];
+        let lp = &stats.event_loop;
+        println!(
+            "spin {:?}: p50 {:?}, p99 {:?}, max {:?}, {:.1}% of events spun",
+            spin,
+            percentile(50),
+            percentile(99),
+            rtts[rtts.len() - 1],
+            lp.spun_events as f64 * 100.0 / lp.events.max(1) as f64
+        );
+    }
+}
@@ -0,0 +1,87 @@
+//! Runs a server on a free loopback port for the length of a bench.
+
+// Every bench uses a different part of it
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use mio_echo_server::{Config, Server, Stats};
+
+/// A server running on its own thread, stopped when dropped.
+pub struct TestServer {
+    pub addr: SocketAddr,
+    /// Address of the UDP socket, if configured.
+    pub udp_addr: Option<SocketAddr>,
+    /// Addresses of the entries of `Config::listeners`, in order.
+    pub listener_addrs: Vec<SocketAddr>,
+    /// Address of the admin socket, if configured.
+    pub admin_addr: Option<SocketAddr>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Stats>>,
+}
+
+impl TestServer {
+    pub fn spawn() -> TestServer {
+        TestServer::with_config(Config::new("127.0.0.1:0"))
+    }
+
+    pub fn with_config(config: Config) -> TestServer {
+        TestServer::with_hook(config, || {})
+    }
+
+    /// Runs `hook` on the server thread right before serving.
+    pub fn with_hook<F>(config: Config, hook: F) -> TestServer
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopping = stop.clone();
+        let mut server = Server::builder(config)
+            .tick(Duration::from_millis(10), move |ctx| {
+                if stopping.load(Ordering::Relaxed) {
+                    ctx.shutdown();
+                }
+            })
+            .build()
+            .expect("bind failed");
+        let addr = server.local_addr().expect("no TCP listener");
+        let udp_addr = server.udp_addr();
+        let listener_addrs = (0..).map_while(|entry| server.listener_addr(entry)).collect();
+        let admin_addr = server.admin_addr();
+        let thread = thread::spawn(move || {
+            hook();
+            server.run().expect("server failed");
+            *server.stats()
+        });
+        TestServer {
+            addr,
+            udp_addr,
+            listener_addrs,
+            admin_addr,
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    /// Stops the server and returns its final stats.
+    pub fn stop(mut self) -> Stats {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> Stats {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread.take().map(|thread| thread.join().unwrap()).unwrap_or_default()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if self.thread.is_some() {
+            self.shutdown();
+        }
+    }
+}
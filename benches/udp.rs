@@ -0,0 +1,117 @@
+//! Loopback UDP echo packet rate, with replies batched by recvmmsg and
+//! sendmmsg (`Config::udp_batch`, Linux only) and with a syscall per
+//! datagram, then with the datagrams numbered for `Config::udp_sequence`.
+//!
+//!     cargo bench --bench udp
+
+use std::net::UdpSocket;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mio_echo_server::Config;
+
+mod support;
+
+use support::TestServer;
+
+const SENDERS: usize = 8;
+// Sent by every sender before anyone reads, so the server finds a batch
+// waiting
+const DATAGRAMS_PER_SENDER: usize = 8;
+const PAYLOAD_SIZE: usize = 64;
+
+fn udp(c: &mut Criterion) {
+    let mut group = c.benchmark_group("udp");
+    group.throughput(Throughput::Elements((SENDERS * DATAGRAMS_PER_SENDER) as u64));
+    let payload = [0x5a; PAYLOAD_SIZE];
+    let mut reply = [0; PAYLOAD_SIZE];
+
+    for &batch in &[1, 64] {
+        // The UDP port is only known once bound, so pick a free one
+        let port = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let server = TestServer::with_config(Config {
+            udp: Some(format!("127.0.0.1:{}", port)),
+            udp_batch: batch,
+            ..Config::new("127.0.0.1:0")
+        });
+        let socks: Vec<UdpSocket> = (0..SENDERS)
+            .map(|_| {
+                let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+                sock.connect(("127.0.0.1", port)).unwrap();
+                // A lost datagram fails the bench instead of hanging it
+                sock.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+                sock
+            })
+            .collect();
+
+        group.bench_function(BenchmarkId::new("batch", batch), |b| {
+            b.iter(|| {
+                for sock in &socks {
+                    for _ in 0..DATAGRAMS_PER_SENDER {
+                        sock.send(&payload).unwrap();
+                    }
+                }
+                for sock in &socks {
+                    for _ in 0..DATAGRAMS_PER_SENDER {
+                        assert_eq!(sock.recv(&mut reply).expect("datagram lost"), PAYLOAD_SIZE);
+                    }
+                }
+            })
+        });
+
+        drop(socks);
+        server.stop();
+    }
+    group.finish();
+}
+
+// Every sender numbers its datagrams from 0, the server counting what it
+// lost, duplicated or reordered on the way
+fn udp_sequence(c: &mut Criterion) {
+    let mut group = c.benchmark_group("udp");
+    group.throughput(Throughput::Elements((SENDERS * DATAGRAMS_PER_SENDER) as u64));
+    let mut datagram = [0x5a; PAYLOAD_SIZE];
+    let mut reply = [0; PAYLOAD_SIZE];
+
+    let port = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let server = TestServer::with_config(Config {
+        udp: Some(format!("127.0.0.1:{}", port)),
+        udp_sequence: true,
+        ..Config::new("127.0.0.1:0")
+    });
+    let mut socks: Vec<(UdpSocket, u64)> = (0..SENDERS)
+        .map(|_| {
+            let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+            sock.connect(("127.0.0.1", port)).unwrap();
+            sock.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+            (sock, 0)
+        })
+        .collect();
+
+    group.bench_function("sequenced", |b| {
+        b.iter(|| {
+            for (sock, seq) in &mut socks {
+                for _ in 0..DATAGRAMS_PER_SENDER {
+                    datagram[..8].copy_from_slice(&seq.to_be_bytes());
+                    sock.send(&datagram).unwrap();
+                    *seq += 1;
+                }
+            }
+            for (sock, seq) in &socks {
+                let first = seq - DATAGRAMS_PER_SENDER as u64;
+                for expected in first..*seq {
+                    assert_eq!(sock.recv(&mut reply).expect("datagram lost"), PAYLOAD_SIZE);
+                    assert_eq!(reply[..8], expected.to_be_bytes());
+                }
+            }
+        })
+    });
+
+    drop(socks);
+    let sequence = server.stop().sequence;
+    assert_eq!((sequence.lost, sequence.duplicates, sequence.reorders), (0, 0, 0));
+    group.finish();
+}
+
+criterion_group!(benches, udp, udp_sequence);
+criterion_main!(benches);
@@ -0,0 +1,64 @@
+//! Runs the echo server inside an application's own mio loop, next to an
+//! unrelated UDP socket that prints whatever it receives.
+//!
+//!     cargo run --example embedded -- 127.0.0.1:7000 127.0.0.1:7001
+
+use std::io;
+use std::process;
+
+use mio::net::UdpSocket;
+use mio::{Events, Interest, Poll, Token};
+use mio_echo_server::{Config, Error, Server};
+
+// The application keeps token 0, the server gets everything after it
+const APP_TOKEN: Token = Token(0);
+const SERVER_TOKENS: std::ops::Range<usize> = 1..1100;
+
+fn run(echo_addr: &str, app_addr: &str) -> Result<(), Error> {
+    let mut poll = Poll::new()?;
+    // The server keeps its registry while the loop polls
+    let registry = poll.registry().try_clone()?;
+
+    let mut app = UdpSocket::bind(app_addr.parse()?)?;
+    registry.register(&mut app, APP_TOKEN, Interest::READABLE)?;
+
+    let mut server = Server::builder(Config::new(echo_addr)).build_embedded(&registry, SERVER_TOKENS)?;
+
+    let mut events = Events::with_capacity(1024);
+    let mut buf = [0; 65536];
+    loop {
+        poll.poll(&mut events, server.next_timeout())?;
+
+        for event in &events {
+            if server.handle_event(event)? {
+                continue;
+            }
+            if event.token() == APP_TOKEN {
+                loop {
+                    match app.recv_from(&mut buf) {
+                        Ok((len, addr)) => {
+                            println!("app got {:?} from {}", String::from_utf8_lossy(&buf[..len]), addr);
+                        }
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+            }
+        }
+
+        server.expire_timers()?;
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: embedded ECHO_HOST:PORT APP_HOST:PORT");
+        process::exit(1);
+    }
+
+    if let Err(err) = run(&args[1], &args[2]) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
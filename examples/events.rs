@@ -0,0 +1,36 @@
+//! The echo written against `Server::next_events`, the application owning
+//! the loop, with each connection logged as it comes and goes.
+//!
+//!     cargo run --example events -- 127.0.0.1:7000
+
+use std::process;
+
+use mio_echo_server::{Config, Error, Server, ServerEvent};
+
+fn run(addr: &str) -> Result<(), Error> {
+    let mut server = Server::builder(Config::new(addr)).events().build()?;
+
+    loop {
+        for event in server.next_events(None)? {
+            match event {
+                ServerEvent::Accepted { conn, peer } => println!("{} connected from {}", conn, peer),
+                ServerEvent::Data { conn, bytes } => server.send(conn, bytes),
+                ServerEvent::WritableAgain { .. } => {}
+                ServerEvent::Closed { conn, reason } => println!("{} closed: {}", conn, reason),
+            }
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 2 {
+        eprintln!("usage: events HOST:PORT");
+        process::exit(1);
+    }
+
+    if let Err(err) = run(&args[1]) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
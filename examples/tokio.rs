@@ -0,0 +1,39 @@
+//! Runs the echo server as a task of a tokio application, next to an
+//! unrelated task, until the application gets Ctrl-C.
+//!
+//!     cargo run --example tokio --features tokio -- 127.0.0.1:7000
+
+use std::process;
+use std::time::Duration;
+
+use mio_echo_server::{serve, Config};
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 2 {
+        eprintln!("usage: tokio HOST:PORT");
+        process::exit(1);
+    }
+
+    // The runtime keeps running the application while the server waits
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(Duration::from_secs(5));
+        loop {
+            interval.tick().await;
+            println!("app still running");
+        }
+    });
+
+    let shutdown = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    let server = tokio::spawn(serve(Config::new(&args[1]), shutdown));
+    match server.await.expect("server task panicked") {
+        Ok(stats) => println!("{}", stats),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
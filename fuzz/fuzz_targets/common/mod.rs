@@ -0,0 +1,21 @@
+//! Splitting the input of a target into reads.
+
+/// The input `data` stands for, and the reads it comes in: the first byte
+/// is the number of cuts, the next ones the length of each read up to
+/// them, and the input what remains, its rest read at once.
+pub fn reads(data: &[u8]) -> (&[u8], Vec<&[u8]>) {
+    let (&count, data) = match data.split_first() {
+        Some(split) => split,
+        None => return (data, Vec::new()),
+    };
+    let (lengths, input) = data.split_at((count as usize % 32).min(data.len()));
+    let mut reads = Vec::new();
+    let mut rest = input;
+    for &len in lengths {
+        let (read, after) = rest.split_at((len as usize).min(rest.len()));
+        reads.push(read);
+        rest = after;
+    }
+    reads.push(rest);
+    (input, reads)
+}
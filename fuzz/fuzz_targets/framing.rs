@@ -0,0 +1,30 @@
+//! Frames decode the same whatever the reads, within `MAX_FRAME_SIZE`.
+
+#![no_main]
+
+mod common;
+
+use libfuzzer_sys::fuzz_target;
+use mio_echo_server::{Framer, Framing, MAX_FRAME_SIZE};
+
+fuzz_target!(|data: &[u8]| {
+    let (input, reads) = common::reads(data);
+    for &framing in &[Framing::Line, Framing::Length] {
+        for &verify in &[false, true] {
+            let mut whole = Vec::new();
+            let at_once = Framer::new(framing, true, verify).decode(input, &mut whole);
+
+            let mut framer = Framer::new(framing, true, verify);
+            let mut out = Vec::new();
+            let chunked = reads.iter().try_for_each(|read| framer.decode(read, &mut out));
+            match (at_once, chunked) {
+                (Ok(()), Ok(())) => {
+                    assert_eq!(out, whole);
+                    assert!(framer.usage().0 <= 4 + MAX_FRAME_SIZE);
+                }
+                (Err(at_once), Err(chunked)) => assert_eq!(at_once.to_string(), chunked.to_string()),
+                (at_once, chunked) => panic!("{:?} at once, {:?} in reads", at_once, chunked),
+            }
+        }
+    }
+});
@@ -0,0 +1,25 @@
+//! Requests are answered the same whatever the reads, holding no more than
+//! a request.
+
+#![no_main]
+
+mod common;
+
+use libfuzzer_sys::fuzz_target;
+use mio_echo_server::{Http, MAX_BODY_SIZE, MAX_HEADER_SIZE};
+
+fuzz_target!(|data: &[u8]| {
+    let (input, reads) = common::reads(data);
+    let mut at_once = Http::default();
+    let mut whole = Vec::new();
+    at_once.decode(input, &mut whole);
+
+    let mut http = Http::default();
+    let mut out = Vec::new();
+    for read in reads {
+        http.decode(read, &mut out);
+        assert!(http.usage().0 <= MAX_HEADER_SIZE + MAX_BODY_SIZE);
+    }
+    assert_eq!(out, whole);
+    assert_eq!(http.closing(), at_once.closing());
+});
@@ -0,0 +1,26 @@
+//! A first line decodes the same whatever the reads, holding less than a
+//! command.
+
+#![no_main]
+
+mod common;
+
+use libfuzzer_sys::fuzz_target;
+use mio_echo_server::Negotiator;
+
+// The longest command line, see `negotiate::MAX_COMMAND`
+const MAX_COMMAND: usize = 64;
+
+fuzz_target!(|data: &[u8]| {
+    let (input, reads) = common::reads(data);
+    let mut whole = Vec::new();
+    Negotiator::default().decode(input, &mut whole);
+
+    let mut negotiator = Negotiator::default();
+    let mut out = Vec::new();
+    for read in reads {
+        negotiator.decode(read, &mut out);
+        assert!(negotiator.usage().0 < MAX_COMMAND);
+    }
+    assert_eq!(out, whole);
+});
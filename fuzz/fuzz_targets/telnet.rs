@@ -0,0 +1,22 @@
+//! Telnet decodes the same whatever the reads, at most doubling the input.
+
+#![no_main]
+
+mod common;
+
+use libfuzzer_sys::fuzz_target;
+use mio_echo_server::Telnet;
+
+fuzz_target!(|data: &[u8]| {
+    let (input, reads) = common::reads(data);
+    let mut whole = Vec::new();
+    Telnet::default().decode(input, &mut whole);
+    assert!(whole.len() <= 2 * input.len());
+
+    let mut telnet = Telnet::default();
+    let mut out = Vec::new();
+    for read in reads {
+        telnet.decode(read, &mut out);
+    }
+    assert_eq!(out, whole);
+});
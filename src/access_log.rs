@@ -0,0 +1,250 @@
+//! One line per closed connection, appended to a file by a writer thread.
+
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::{error, info};
+
+use crate::config::AccessLogFormat;
+use crate::reactor::CloseReason;
+use crate::stream::PeerAddr;
+use crate::Error;
+
+/// Entries waiting for the writer thread, more are dropped.
+const QUEUE_LEN: usize = 4096;
+/// The file trails the closed connections by at most this long.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+const CSV_HEADER: &str = "accepted,peer,duration,bytes_in,bytes_out,reason,id";
+
+/// Set by SIGHUP, the writer thread reopens the file.
+static REOPEN: AtomicBool = AtomicBool::new(false);
+
+/// A closed connection.
+pub struct Entry {
+    pub accepted_at: SystemTime,
+    pub peer: PeerAddr,
+    pub duration: Duration,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub reason: CloseReason,
+    pub id: u64,
+    /// See `Config::log_original_dst`.
+    pub original_dst: Option<SocketAddr>,
+    /// See `Config::tls_client_ca`.
+    pub peer_cert: Option<String>,
+}
+
+/// Handle queueing entries for the writer thread, without ever blocking.
+pub struct AccessLog {
+    tx: SyncSender<Entry>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl AccessLog {
+    /// Opens `path` for appending and starts the writer thread, which
+    /// reopens it on SIGHUP so logrotate can move it away.
+    ///
+    /// With `original_dst`, every line ends with the original destination
+    /// of the connection, `-` if it wasn't redirected. With `peer_cert`, it
+    /// ends with the client's certificate, quoted in the text format, `-`
+    /// if there was none.
+    ///
+    /// The thread exits once the handle is dropped.
+    pub fn start(
+        path: &Path,
+        format: AccessLogFormat,
+        original_dst: bool,
+        peer_cert: bool,
+    ) -> Result<(AccessLog, JoinHandle<()>), Error> {
+        let file = LogFile::open(path.to_path_buf(), format, original_dst, peer_cert)?;
+        #[cfg(unix)]
+        crate::signal::watch(libc::SIGHUP, &REOPEN)?;
+        let (tx, rx) = mpsc::sync_channel(QUEUE_LEN);
+        let thread = thread::Builder::new()
+            .name("access-log".to_string())
+            .spawn(move || file.write_all(rx))?;
+        let access_log = AccessLog {
+            tx,
+            dropped: Arc::new(AtomicU64::new(0)),
+        };
+        Ok((access_log, thread))
+    }
+
+    /// Entries dropped because the writer couldn't keep up.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn log(&self, entry: Entry) {
+        match self.tx.try_send(entry) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+struct LogFile {
+    path: PathBuf,
+    format: AccessLogFormat,
+    original_dst: bool,
+    peer_cert: bool,
+    out: BufWriter<File>,
+    line: String,
+}
+
+impl LogFile {
+    fn open(path: PathBuf, format: AccessLogFormat, original_dst: bool, peer_cert: bool) -> io::Result<LogFile> {
+        let out = open(&path, format, original_dst, peer_cert)?;
+        Ok(LogFile {
+            path,
+            format,
+            original_dst,
+            peer_cert,
+            out,
+            line: String::new(),
+        })
+    }
+
+    fn write_all(mut self, rx: Receiver<Entry>) {
+        let mut flushed_at = Instant::now();
+        loop {
+            let result = match rx.recv_timeout(FLUSH_INTERVAL) {
+                Ok(entry) => self.write(&entry),
+                Err(RecvTimeoutError::Timeout) => Ok(()),
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            let result = result.and_then(|()| {
+                if REOPEN.swap(false, Ordering::Relaxed) {
+                    info!("reopening access log {}", self.path.display());
+                    self.out.flush()?;
+                    self.out = open(&self.path, self.format, self.original_dst, self.peer_cert)?;
+                    flushed_at = Instant::now();
+                } else if flushed_at.elapsed() >= FLUSH_INTERVAL {
+                    self.out.flush()?;
+                    flushed_at = Instant::now();
+                }
+                Ok(())
+            });
+            if let Err(e) = result {
+                error!("access log to {} failed, stopping: {}", self.path.display(), e);
+                return;
+            }
+        }
+        if let Err(e) = self.out.flush() {
+            error!("access log to {} failed: {}", self.path.display(), e);
+        }
+    }
+
+    fn write(&mut self, entry: &Entry) -> io::Result<()> {
+        self.line.clear();
+        let accepted = rfc3339(entry.accepted_at);
+        let duration = format!("{:.3}", entry.duration.as_secs_f64());
+        let reason = entry.reason.to_string();
+        let _ = match self.format {
+            AccessLogFormat::Text => write!(
+                self.line,
+                "{} {} {} {} {} \"{}\" {}",
+                accepted, entry.peer, duration, entry.bytes_in, entry.bytes_out, reason, entry.id,
+            ),
+            AccessLogFormat::Csv => write!(
+                self.line,
+                "{},{},{},{},{},{},{}",
+                accepted,
+                csv_field(&entry.peer.to_string()),
+                duration,
+                entry.bytes_in,
+                entry.bytes_out,
+                csv_field(&reason),
+                entry.id,
+            ),
+        };
+        let csv = self.format == AccessLogFormat::Csv;
+        let sep = if csv { ',' } else { ' ' };
+        if self.original_dst {
+            let _ = match entry.original_dst {
+                Some(addr) => write!(self.line, "{}{}", sep, addr),
+                None if csv => write!(self.line, "{}", sep),
+                None => write!(self.line, "{}-", sep),
+            };
+        }
+        if self.peer_cert {
+            let _ = match entry.peer_cert {
+                Some(ref cert) if csv => write!(self.line, ",{}", csv_field(cert)),
+                Some(ref cert) => write!(self.line, " \"{}\"", cert),
+                None if csv => write!(self.line, ","),
+                None => write!(self.line, " -"),
+            };
+        }
+        self.line.push('\n');
+        self.out.write_all(self.line.as_bytes())
+    }
+}
+
+// Opens for appending, starting a new CSV file with its header
+fn open(path: &Path, format: AccessLogFormat, original_dst: bool, peer_cert: bool) -> io::Result<BufWriter<File>> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let empty = file.metadata()?.len() == 0;
+    let mut out = BufWriter::new(file);
+    if format == AccessLogFormat::Csv && empty {
+        out.write_all(CSV_HEADER.as_bytes())?;
+        if original_dst {
+            out.write_all(b",original_dst")?;
+        }
+        if peer_cert {
+            out.write_all(b",peer_cert")?;
+        }
+        out.write_all(b"\n")?;
+    }
+    Ok(out)
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// UTC with milliseconds, e.g. 2019-06-01T12:00:00.000Z
+pub(crate) fn rfc3339(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs();
+    let (year, month, day) = civil_date((secs / 86400) as i64);
+    let secs_of_day = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since.subsec_millis(),
+    )
+}
+
+// Days since the epoch to year, month and day, from Howard Hinnant's
+// algorithms
+pub(crate) fn civil_date(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,258 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use log::debug;
+use mio::net::{TcpListener, TcpStream};
+use mio::{Interest, Registry, Token};
+use slab::Slab;
+
+use crate::config::parse_duration;
+
+/// Admin connections served at once, more are refused.
+pub const MAX_ADMINS: usize = 4;
+/// Longer command lines close the admin connection.
+const MAX_LINE_SIZE: usize = 1024;
+
+/// A parsed admin command.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Command {
+    /// Closes the client with this id, flushing its queued echo first
+    /// when `flush` is set.
+    Kick { id: u64, flush: bool, reason: String },
+    /// Closes every client, the listeners keep accepting.
+    KickAll { flush: bool, reason: String },
+    /// Refuses the connections of an address for a while.
+    Ban { ip: IpAddr, duration: Duration },
+    Unban { ip: IpAddr },
+    /// Lists the bans.
+    Bans,
+    /// Percentiles of the time echoed data waits in the server.
+    Latency,
+    /// The sequence numbers counted for each UDP sender, see
+    /// `Config::udp_sequence`.
+    UdpSources,
+    /// The `Stats` so far, on one line.
+    Stats,
+    /// The counters and address of each entry of `Config::listeners`.
+    ListenerStats,
+}
+
+impl Command {
+    /// Parses a line such as `kick [-f] ID [REASON]`,
+    /// `kick-all [-f] [REASON]`, `ban IP DURATION`, `unban IP`, `bans`,
+    /// `latency`, `udp-sources` or `stats [listeners]`.
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let line = line.trim();
+        let (name, mut rest) = line.split_once(' ').unwrap_or((line, ""));
+        let flush = match rest.trim_start().strip_prefix("-f") {
+            Some(after) if after.is_empty() || after.starts_with(' ') => {
+                rest = after;
+                true
+            }
+            _ => false,
+        };
+        let rest = rest.trim_start();
+        match name {
+            "kick" => {
+                let (id, reason) = rest.split_once(' ').unwrap_or((rest, ""));
+                let id = id.parse().map_err(|_| format!("invalid connection id: {:?}", id))?;
+                Ok(Command::Kick {
+                    id,
+                    flush,
+                    reason: reason.trim().to_string(),
+                })
+            }
+            "kick-all" => Ok(Command::KickAll {
+                flush,
+                reason: rest.to_string(),
+            }),
+            "ban" => {
+                let mut args = rest.split_whitespace();
+                match (args.next(), args.next(), args.next()) {
+                    (Some(ip), Some(duration), None) => Ok(Command::Ban {
+                        ip: parse_ip(ip)?,
+                        duration: parse_duration(duration).map_err(|e| e.to_string())?,
+                    }),
+                    _ => Err("usage: ban IP DURATION".to_string()),
+                }
+            }
+            "unban" => Ok(Command::Unban { ip: parse_ip(rest)? }),
+            "bans" => Ok(Command::Bans),
+            "latency" => Ok(Command::Latency),
+            "udp-sources" => Ok(Command::UdpSources),
+            "stats" => match rest {
+                "" => Ok(Command::Stats),
+                "listeners" => Ok(Command::ListenerStats),
+                _ => Err("usage: stats [listeners]".to_string()),
+            },
+            "" => Err("empty command".to_string()),
+            _ => Err(format!("unknown command: {}", name)),
+        }
+    }
+}
+
+fn parse_ip(s: &str) -> Result<IpAddr, String> {
+    s.trim().parse().map_err(|_| format!("invalid address: {:?}", s))
+}
+
+/// Line based admin socket: one command per line, one reply line each.
+///
+/// Uses `1 + MAX_ADMINS` tokens from its base: the listener, then the
+/// connections. Replies are small and written straight away; an admin
+/// client that doesn't read them is disconnected.
+pub struct Admin {
+    listener: TcpListener,
+    conns: Slab<AdminConn>,
+    /// Complete lines waiting for a reply, with their connection.
+    commands: VecDeque<(usize, String)>,
+    token_base: usize,
+}
+
+struct AdminConn {
+    sock: TcpStream,
+    pending: Vec<u8>,
+    /// The peer is done sending, close once its commands are answered.
+    closing: bool,
+}
+
+impl Admin {
+    pub fn new(listener: TcpListener) -> Admin {
+        Admin {
+            listener,
+            conns: Slab::with_capacity(MAX_ADMINS),
+            commands: VecDeque::new(),
+            token_base: 0,
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn register(&mut self, registry: &Registry, token_base: usize) -> io::Result<()> {
+        self.token_base = token_base;
+        registry.register(&mut self.listener, Token(token_base), Interest::READABLE)
+    }
+
+    /// Deregisters the listener and drops the admin connections.
+    pub fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        self.commands.clear();
+        for mut conn in self.conns.drain() {
+            let _ = registry.deregister(&mut conn.sock);
+        }
+        registry.deregister(&mut self.listener)
+    }
+
+    /// Handles an event, returns false if its token isn't ours.
+    ///
+    /// Complete lines are queued for `next_command`.
+    pub fn ready(&mut self, registry: &Registry, token: Token) -> io::Result<bool> {
+        let index = match token.0.checked_sub(self.token_base) {
+            Some(0) => {
+                self.accept(registry)?;
+                return Ok(true);
+            }
+            Some(index) if index <= MAX_ADMINS => index - 1,
+            _ => return Ok(false),
+        };
+        if self.conns.contains(index) {
+            self.read(index);
+            self.close_if_done(registry, index);
+        }
+        Ok(true)
+    }
+
+    /// Next line to run, to be answered with `reply`.
+    pub fn next_command(&mut self) -> Option<(usize, String)> {
+        self.commands.pop_front()
+    }
+
+    /// Sends the reply line of a command.
+    pub fn reply(&mut self, registry: &Registry, index: usize, reply: &str) {
+        let conn = match self.conns.get_mut(index) {
+            Some(conn) => conn,
+            None => return,
+        };
+        let line = format!("{}\n", reply);
+        if let Err(e) = conn.sock.write_all(line.as_bytes()) {
+            debug!("admin write failed: {}", e);
+            conn.closing = true;
+            self.commands.retain(|&(i, _)| i != index);
+        }
+        self.close_if_done(registry, index);
+    }
+
+    fn close_if_done(&mut self, registry: &Registry, index: usize) {
+        if !self.conns[index].closing || self.commands.iter().any(|&(i, _)| i == index) {
+            return;
+        }
+        let mut conn = self.conns.remove(index);
+        // Dropping the socket unregisters it anyway
+        let _ = registry.deregister(&mut conn.sock);
+    }
+
+    fn accept(&mut self, registry: &Registry) -> io::Result<()> {
+        loop {
+            match self.listener.accept() {
+                Ok((mut sock, addr)) => {
+                    if self.conns.len() == MAX_ADMINS {
+                        debug!("too many admin connections, connection refused : {}", addr);
+                        continue;
+                    }
+                    let entry = self.conns.vacant_entry();
+                    let token = Token(self.token_base + 1 + entry.key());
+                    registry.register(&mut sock, token, Interest::READABLE)?;
+                    entry.insert(AdminConn {
+                        sock,
+                        pending: Vec::new(),
+                        closing: false,
+                    });
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Queues the complete lines read, marks the connection closing on EOF,
+    // error or an overlong line
+    fn read(&mut self, index: usize) {
+        let conn = &mut self.conns[index];
+        let mut rbuf = [0; 1024];
+
+        loop {
+            match conn.sock.read(&mut rbuf) {
+                Ok(0) => {
+                    conn.closing = true;
+                    break;
+                }
+                Ok(len) => conn.pending.extend_from_slice(&rbuf[..len]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => {
+                    conn.closing = true;
+                    break;
+                }
+            }
+        }
+
+        while let Some(end) = conn.pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = conn.pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line).trim_end().to_string();
+            self.commands.push_back((index, line));
+        }
+        if conn.pending.len() > MAX_LINE_SIZE {
+            conn.closing = true;
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for Admin {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
@@ -0,0 +1,82 @@
+//! Headers telling clients which server, connection and chunk an echo
+//! comes from, in the format described at `Config::annotate`.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// Makes the headers of one connection and tracks those queued, so that
+/// what is written of them isn't counted as echoed payload.
+pub struct Annotator {
+    server: Arc<str>,
+    seq: u64,
+    /// Offsets of the headers still queued in the stream of bytes taken
+    /// off the queue, and their lengths.
+    spans: VecDeque<(u64, usize)>,
+    /// Header bytes queued and not written yet.
+    pending: usize,
+}
+
+impl Annotator {
+    /// Headers name `server` as the listener.
+    pub fn new(server: Arc<str>) -> Annotator {
+        Annotator {
+            server,
+            seq: 0,
+            spans: VecDeque::new(),
+            pending: 0,
+        }
+    }
+
+    /// The header of the next chunk of connection `conn`, holding `len`
+    /// bytes of payload.
+    pub fn header(&mut self, conn: u64, len: usize) -> Vec<u8> {
+        self.seq += 1;
+        format!("[srv {} conn {} seq {} len {}] ", self.server, conn, self.seq, len).into_bytes()
+    }
+
+    /// Notes a header of `len` bytes queued at offset `at`.
+    pub fn queued(&mut self, at: u64, len: usize) {
+        self.spans.push_back((at, len));
+        self.pending += len;
+    }
+
+    /// Header bytes queued and not written yet.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Notes the `len` bytes at offset `at` dropped from the middle of
+    /// the queue, the bytes queued before them moving up as many. Returns
+    /// how many of them were headers.
+    pub fn dropped(&mut self, at: u64, len: u64) -> u64 {
+        let mut headers = 0;
+        self.spans.retain(|&(start, header)| {
+            let kept = start < at || start >= at + len;
+            if !kept {
+                headers += header;
+            }
+            kept
+        });
+        self.pending -= headers;
+        for span in self.spans.iter_mut().take_while(|&&mut (start, _)| start < at) {
+            span.0 += len;
+        }
+        headers as u64
+    }
+
+    /// Header bytes between offsets `from` and `to`, just taken off the
+    /// queue.
+    pub fn written(&mut self, from: u64, to: u64) -> u64 {
+        let mut headers = 0;
+        while let Some(&(start, len)) = self.spans.front() {
+            let end = start + len as u64;
+            headers += end.min(to).saturating_sub(start.max(from));
+            if end > to {
+                break;
+            }
+            self.spans.pop_front();
+        }
+        self.pending -= headers as usize;
+        headers
+    }
+}
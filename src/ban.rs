@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+struct Ban {
+    until: Instant,
+    /// Connections refused since the ban started.
+    refused: u64,
+}
+
+/// Addresses whose connections are refused until their ban expires.
+///
+/// Expiry is driven by the reactor's timers: `expire` is called with the
+/// deadline `ban` returned.
+#[derive(Default)]
+pub struct Bans {
+    bans: HashMap<IpAddr, Ban>,
+}
+
+impl Bans {
+    /// Bans `ip` for `duration` from `now`, replacing any previous ban,
+    /// and returns when it ends.
+    pub fn ban(&mut self, ip: IpAddr, duration: Duration, now: Instant) -> Instant {
+        let until = now + duration;
+        let ban = self.bans.entry(ip).or_insert(Ban { until, refused: 0 });
+        ban.until = until;
+        until
+    }
+
+    /// Lifts the ban of `ip`, returns false if there was none.
+    pub fn unban(&mut self, ip: IpAddr) -> bool {
+        self.bans.remove(&ip).is_some()
+    }
+
+    /// Whether connections from `ip` must be refused, counting them if so.
+    pub fn refuse(&mut self, ip: IpAddr, now: Instant) -> bool {
+        match self.bans.get_mut(&ip) {
+            Some(ban) if ban.until > now => {
+                ban.refused += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Drops the ban of `ip` if it has run out, returns whether it did.
+    pub fn expire(&mut self, ip: IpAddr, now: Instant) -> bool {
+        match self.bans.get(&ip) {
+            Some(ban) if ban.until <= now => {
+                self.bans.remove(&ip);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// One line listing every ban with its time left and refusal count.
+    pub fn list(&self, now: Instant) -> String {
+        if self.bans.is_empty() {
+            return "none".to_string();
+        }
+        let mut bans: Vec<_> = self.bans.iter().collect();
+        bans.sort_by_key(|&(ip, _)| *ip);
+        let mut list = String::new();
+        for (ip, ban) in bans {
+            if !list.is_empty() {
+                list.push_str(", ");
+            }
+            let left = ban.until.saturating_duration_since(now);
+            let _ = write!(list, "{} ({}s left, {} refused)", ip, left.as_secs(), ban.refused);
+        }
+        list
+    }
+}
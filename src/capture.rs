@@ -0,0 +1,304 @@
+//! Records the echoed traffic to a pcap file readable by Wireshark.
+//!
+//! Every read and write becomes one synthetic IPv4 or IPv6 packet with a
+//! fabricated TCP or UDP header, so the usual stream following works.
+//! Only sockets with IP addresses are captured.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::error;
+
+use crate::Error;
+
+/// Records waiting for the writer thread, more are dropped.
+const QUEUE_LEN: usize = 4096;
+const LINKTYPE_RAW: u32 = 101;
+const SNAPLEN: u32 = 65535;
+/// Largest payload of one synthetic packet, bigger ones are split.
+const MAX_SEGMENT: usize = 65535 - 60 - 20;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+enum Record {
+    Data {
+        time: SystemTime,
+        protocol: Protocol,
+        src: SocketAddr,
+        dst: SocketAddr,
+        data: Vec<u8>,
+    },
+    // Forgets the sequence numbers of a TCP connection
+    Closed { local: SocketAddr, peer: SocketAddr },
+}
+
+/// Handle queueing records for the writer thread, without ever blocking.
+#[derive(Clone)]
+pub struct Capture {
+    tx: SyncSender<Record>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Capture {
+    /// Creates `path` and starts the writer thread, which rotates the file
+    /// to `path.1` once it grows past `max_size` bytes.
+    ///
+    /// The thread exits once every handle is dropped.
+    pub fn start(path: &Path, max_size: u64) -> Result<(Capture, JoinHandle<()>), Error> {
+        let file = PcapFile::create(path.to_path_buf(), max_size)?;
+        let (tx, rx) = mpsc::sync_channel(QUEUE_LEN);
+        let thread = thread::Builder::new()
+            .name("capture".to_string())
+            .spawn(move || file.write_all(rx))?;
+        let capture = Capture {
+            tx,
+            dropped: Arc::new(AtomicU64::new(0)),
+        };
+        Ok((capture, thread))
+    }
+
+    /// Records dropped because the writer couldn't keep up.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Records `data` sent from `src` to `dst`.
+    pub fn record(&self, protocol: Protocol, src: SocketAddr, dst: SocketAddr, data: &[u8]) {
+        self.send(Record::Data {
+            time: SystemTime::now(),
+            protocol,
+            src,
+            dst,
+            data: data.to_vec(),
+        });
+    }
+
+    /// The capture of one TCP connection.
+    pub fn tap(&self, local: SocketAddr, peer: SocketAddr) -> Tap {
+        Tap {
+            capture: self.clone(),
+            local,
+            peer,
+        }
+    }
+
+    fn send(&self, record: Record) {
+        match self.tx.try_send(record) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Captures the traffic of one TCP connection until dropped.
+pub struct Tap {
+    capture: Capture,
+    local: SocketAddr,
+    peer: SocketAddr,
+}
+
+impl Tap {
+    pub fn read(&self, data: &[u8]) {
+        self.capture.record(Protocol::Tcp, self.peer, self.local, data);
+    }
+
+    pub fn write(&self, data: &[u8]) {
+        self.capture.record(Protocol::Tcp, self.local, self.peer, data);
+    }
+}
+
+impl Drop for Tap {
+    fn drop(&mut self) {
+        self.capture.send(Record::Closed {
+            local: self.local,
+            peer: self.peer,
+        });
+    }
+}
+
+struct PcapFile {
+    path: PathBuf,
+    max_size: u64,
+    out: BufWriter<File>,
+    size: u64,
+    // Next TCP sequence number per direction
+    seqs: HashMap<(SocketAddr, SocketAddr), u32>,
+}
+
+impl PcapFile {
+    fn create(path: PathBuf, max_size: u64) -> Result<PcapFile, Error> {
+        let out = open(&path)?;
+        Ok(PcapFile {
+            path,
+            max_size,
+            out,
+            size: 24,
+            seqs: HashMap::new(),
+        })
+    }
+
+    fn write_all(mut self, rx: Receiver<Record>) {
+        // Flush once the queue is drained, so the file trails by a batch
+        while let Ok(record) = rx.recv() {
+            let mut result = self.write(record);
+            while result.is_ok() {
+                match rx.try_recv() {
+                    Ok(record) => result = self.write(record),
+                    Err(_) => break,
+                }
+            }
+            if let Err(e) = result.and_then(|()| self.out.flush()) {
+                error!("capture to {} failed, stopping: {}", self.path.display(), e);
+                return;
+            }
+        }
+    }
+
+    fn write(&mut self, record: Record) -> io::Result<()> {
+        let (time, protocol, src, dst, data) = match record {
+            Record::Data {
+                time,
+                protocol,
+                src,
+                dst,
+                data,
+            } => (time, protocol, src, dst, data),
+            Record::Closed { local, peer } => {
+                self.seqs.remove(&(local, peer));
+                self.seqs.remove(&(peer, local));
+                return Ok(());
+            }
+        };
+
+        // An empty read or write still shows up, as an empty packet
+        for segment in data.chunks(MAX_SEGMENT).chain(data.is_empty().then_some(&[][..])) {
+            let mut packet = Vec::with_capacity(60 + segment.len());
+            match protocol {
+                Protocol::Tcp => {
+                    let next = self.seqs.entry((src, dst)).or_insert(1);
+                    let seq = *next;
+                    *next = seq.wrapping_add(segment.len() as u32);
+                    let ack = *self.seqs.entry((dst, src)).or_insert(1);
+                    let header = tcp_header(src, dst, seq, ack);
+                    ip_packet(&mut packet, src, dst, 6, &header, segment);
+                }
+                Protocol::Udp => {
+                    let mut header = [0; 8];
+                    header[0..2].copy_from_slice(&src.port().to_be_bytes());
+                    header[2..4].copy_from_slice(&dst.port().to_be_bytes());
+                    header[4..6].copy_from_slice(&((8 + segment.len()) as u16).to_be_bytes());
+                    ip_packet(&mut packet, src, dst, 17, &header, segment);
+                }
+            }
+            if packet.is_empty() {
+                // Mixed address families can't make an IP packet
+                return Ok(());
+            }
+            self.write_packet(time, &packet)?;
+        }
+        Ok(())
+    }
+
+    fn write_packet(&mut self, time: SystemTime, packet: &[u8]) -> io::Result<()> {
+        if self.size + 16 + packet.len() as u64 > self.max_size && self.size > 24 {
+            self.rotate()?;
+        }
+        let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let captured = packet.len().min(SNAPLEN as usize);
+        let mut header = [0; 16];
+        header[0..4].copy_from_slice(&(since.as_secs() as u32).to_le_bytes());
+        header[4..8].copy_from_slice(&since.subsec_micros().to_le_bytes());
+        header[8..12].copy_from_slice(&(captured as u32).to_le_bytes());
+        header[12..16].copy_from_slice(&(packet.len() as u32).to_le_bytes());
+        self.out.write_all(&header)?;
+        self.out.write_all(&packet[..captured])?;
+        self.size += 16 + captured as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.out.flush()?;
+        let mut old = self.path.clone().into_os_string();
+        old.push(".1");
+        fs::rename(&self.path, old)?;
+        self.out = open(&self.path)?;
+        self.size = 24;
+        Ok(())
+    }
+}
+
+// Creates a pcap file and writes its global header
+fn open(path: &Path) -> io::Result<BufWriter<File>> {
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(&0xa1b2_c3d4u32.to_le_bytes())?;
+    out.write_all(&2u16.to_le_bytes())?;
+    out.write_all(&4u16.to_le_bytes())?;
+    out.write_all(&[0; 8])?;
+    out.write_all(&SNAPLEN.to_le_bytes())?;
+    out.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+    Ok(out)
+}
+
+// A 20 byte header with PSH and ACK set, the checksum left out
+fn tcp_header(src: SocketAddr, dst: SocketAddr, seq: u32, ack: u32) -> [u8; 20] {
+    let mut header = [0; 20];
+    header[0..2].copy_from_slice(&src.port().to_be_bytes());
+    header[2..4].copy_from_slice(&dst.port().to_be_bytes());
+    header[4..8].copy_from_slice(&seq.to_be_bytes());
+    header[8..12].copy_from_slice(&ack.to_be_bytes());
+    header[12] = 5 << 4;
+    header[13] = 0x18;
+    header[14..16].copy_from_slice(&0xffffu16.to_be_bytes());
+    header
+}
+
+// Leaves `packet` empty if the addresses aren't of the same family
+fn ip_packet(packet: &mut Vec<u8>, src: SocketAddr, dst: SocketAddr, protocol: u8, header: &[u8], payload: &[u8]) {
+    let len = header.len() + payload.len();
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            let mut ip = [0; 20];
+            ip[0] = 0x45;
+            ip[2..4].copy_from_slice(&((20 + len) as u16).to_be_bytes());
+            // Don't fragment
+            ip[6] = 0x40;
+            ip[8] = 64;
+            ip[9] = protocol;
+            ip[12..16].copy_from_slice(&src.octets());
+            ip[16..20].copy_from_slice(&dst.octets());
+            let checksum = !ip.chunks(2).fold(0u32, |sum, word| {
+                let sum = sum + u32::from(u16::from_be_bytes([word[0], word[1]]));
+                (sum & 0xffff) + (sum >> 16)
+            }) as u16;
+            ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+            packet.extend_from_slice(&ip);
+        }
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            let mut ip = [0; 40];
+            ip[0] = 0x60;
+            ip[4..6].copy_from_slice(&(len as u16).to_be_bytes());
+            ip[6] = protocol;
+            ip[7] = 64;
+            ip[8..24].copy_from_slice(&src.octets());
+            ip[24..40].copy_from_slice(&dst.octets());
+            packet.extend_from_slice(&ip);
+        }
+        _ => return,
+    }
+    packet.extend_from_slice(header);
+    packet.extend_from_slice(payload);
+}
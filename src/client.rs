@@ -0,0 +1,2226 @@
+use std::any::Any;
+use std::collections::VecDeque;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::trace;
+use mio::{Interest, Registry, Token};
+
+use crate::annotate::Annotator;
+use crate::capture::Tap;
+use crate::clock::{self, Clock};
+use crate::config::Overflow;
+use crate::dump::{HexDump, DEFAULT_DUMP_LIMIT};
+use crate::framing::Framer;
+use crate::handler::Action;
+use crate::http::Http;
+use crate::mirror::Mirror;
+use crate::negotiate::Negotiator;
+use crate::stats::Transport;
+use crate::stream::{Peer, PeerAddr, Socket, Stream, TcpInfo};
+use crate::telnet::Telnet;
+use crate::zerocopy::ZeroCopy;
+
+/// Default of `Config::read_buf_size`.
+pub const DEFAULT_BUF_SIZE: usize = 1024;
+/// Reads stop appending to a buffer this large, or as large as
+/// `read_buf_size` if larger, so it can be flushed.
+const MAX_BUF_SIZE: usize = 16 * 1024;
+const MAX_PACKET_SIZE: usize = 65536;
+/// Largest buffer reads fill for a client writing zerocopy, and the
+/// most read into it at once, the spare capacity read into being zeroed
+/// first.
+const MAX_ZEROCOPY_BUF_SIZE: usize = 1 << 20;
+const ZEROCOPY_READ_SIZE: usize = 64 * 1024;
+/// Peer of a reset client.
+const NO_PEER: PeerAddr = PeerAddr::Inet(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0));
+
+/// Turns what a client sends into what is echoed back.
+pub enum Decoder {
+    Telnet(Telnet),
+    Http(Http),
+    Framer(Framer),
+    Negotiate(Negotiator),
+}
+
+impl Decoder {
+    // Bytes held and allocated for input not decoded yet
+    fn usage(&self) -> (usize, usize) {
+        match self {
+            Decoder::Telnet(_) => (0, 0),
+            Decoder::Http(http) => http.usage(),
+            Decoder::Framer(framer) => framer.usage(),
+            Decoder::Negotiate(negotiator) => negotiator.usage(),
+        }
+    }
+
+    fn shrink(&mut self) {
+        match self {
+            Decoder::Telnet(_) | Decoder::Negotiate(_) => {}
+            Decoder::Http(http) => http.shrink(),
+            Decoder::Framer(framer) => framer.shrink(),
+        }
+    }
+
+    fn decode(&mut self, input: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        match self {
+            Decoder::Telnet(telnet) => telnet.decode(input, out),
+            Decoder::Http(http) => http.decode(input, out),
+            Decoder::Framer(framer) => return framer.decode(input, out),
+            Decoder::Negotiate(negotiator) => negotiator.decode(input, out),
+        }
+        Ok(())
+    }
+}
+
+/// What the interest of a client's socket follows.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct InterestState {
+    /// Data is queued for writing, by the client or by the socket itself.
+    pub queued: bool,
+    /// Reading hasn't stopped for good.
+    pub read_open: bool,
+    /// Reading waits for the queue to get room, see
+    /// `Overflow::Backpressure`.
+    pub backpressured: bool,
+    /// Writes may report their completion on the error queue, see
+    /// `Config::zerocopy`.
+    pub zerocopy: bool,
+}
+
+impl InterestState {
+    /// The interest for this state: writable while data is queued,
+    /// readable while reading goes on. A backpressured client isn't woken
+    /// by input it would leave unread, the reactor resumes reading itself
+    /// once the queue has room. `None` when there is nothing to wait for,
+    /// the socket being deregistered meanwhile.
+    pub fn interest(self) -> Option<Interest> {
+        let readable = self.read_open && !self.backpressured;
+        match (readable, self.queued) {
+            (true, true) => Some(Interest::READABLE | Interest::WRITABLE),
+            (true, false) => Some(Interest::READABLE),
+            (false, true) => Some(Interest::WRITABLE),
+            // epoll reports the error queue whatever the interest, and
+            // writable readiness with nothing queued costs nothing
+            (false, false) if self.zerocopy => Some(Interest::WRITABLE),
+            (false, false) => None,
+        }
+    }
+}
+
+/// A connection and the data queued for echoing back, generic over the
+/// socket so the buffering logic doesn't depend on a real one.
+pub struct Client<S = Stream> {
+    sock: S,
+    peer: PeerAddr,
+    pub transport: Transport,
+    /// Identifies the connection to the admin socket, unique per server.
+    pub id: u64,
+    /// What the socket is registered for, `None` until `register` and
+    /// while deregistered.
+    interest: Option<Interest>,
+    pub bufs: VecDeque<Vec<u8>>,
+    pos: usize,
+    /// Bytes taken off the queue over the whole connection, written or
+    /// dropped.
+    written: u64,
+    /// When each read queued its data, with the value `written` will have
+    /// once that data is written.
+    queued_at: VecDeque<(u64, Instant)>,
+    max_write_chunk: Option<usize>,
+    /// Caps `queued_bytes`, past which `overflow` applies.
+    pub max_queued: Option<usize>,
+    pub overflow: Overflow,
+    /// Set while reading waits for the queue to drain, see
+    /// `Overflow::Backpressure`.
+    read_paused: bool,
+    /// Set once the queue overflowed under `Overflow::Disconnect`.
+    pub overflowed: bool,
+    /// Messages and bytes dropped by the overflow policy, reset by their
+    /// reader.
+    pub dropped_newest: (u64, u64),
+    pub dropped_oldest: (u64, u64),
+    /// Set while writing is paused between two chunks.
+    pub resume_at: Option<Instant>,
+    /// Set while the echo is held until the client goes quiet.
+    pub flush_at: Option<Instant>,
+    /// Set while writing waits for the global rate cap to refill.
+    pub throttled: bool,
+    /// Set while writing waits for the next round of events, see
+    /// `Config::write_budget`.
+    pub write_parked: bool,
+    pub accepted_at: Instant,
+    /// Bytes read from and written to the connection.
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// Last time the client sent something, heartbeats don't count.
+    pub last_activity: Instant,
+    pub heartbeat_at: Option<Instant>,
+    /// When `Config::idle_timeout` runs out if nothing comes meanwhile.
+    pub idle_at: Option<Instant>,
+    /// Payload bytes hex dumped per read and write at trace level.
+    pub dump_limit: usize,
+    pub tap: Option<Tap>,
+    /// Gets a copy of everything read, see `Config::mirror`.
+    pub mirror: Option<Mirror>,
+    /// Decodes the input, stream transports only.
+    pub decoder: Option<Decoder>,
+    /// Prefixes what is echoed with headers, see `Config::annotate`.
+    pub annotator: Option<Annotator>,
+    /// Header bytes written, reset by their reader.
+    pub header_bytes: u64,
+    /// Index of the entry of `Config::listeners` that accepted the client,
+    /// if any.
+    pub listener: Option<usize>,
+    /// Index of the reactor's listener that accepted the client.
+    pub source: usize,
+    /// Where the connection was headed before netfilter redirected it
+    /// here, see `Config::log_original_dst`.
+    pub original_dst: Option<SocketAddr>,
+    /// Host name of the peer, see `Config::resolve_peers`.
+    pub peer_name: Option<Arc<str>>,
+    /// Set once a handler returned `Action::Silence`, the input is dropped
+    /// from then on.
+    pub muted: bool,
+    /// Set once a handler returned `Action::ReplyThenClose`: the input is
+    /// dropped from then on, and the client is done once its queue is
+    /// written.
+    pub closing: bool,
+    /// Set once a handler returned `Action::CloseNow`.
+    pub aborted: bool,
+    /// What the handler attached to the connection, see
+    /// `HandlerContext::set_data`.
+    pub handler_data: Option<Box<dyn Any + Send>>,
+    /// Set while data of `Server::send` waits in the queue, to report
+    /// `ServerEvent::WritableAgain` once it is written.
+    pub send_queued: bool,
+    /// Bytes `read_owned` handed out that the caller may still send back,
+    /// counted against `max_queued` until the reactor clears it.
+    pub handed_out: usize,
+    /// Stops reading a stream once a read returns less than asked for,
+    /// see `Config::short_read_drained`.
+    pub short_read_drained: bool,
+    /// Capacity of the buffers the input is queued in, see
+    /// `Config::read_buf_size`.
+    pub read_buf_size: usize,
+    /// What stream reads land in before being queued or decoded, sized
+    /// to `read_buf_size` for each and kept for the next.
+    scratch: Vec<u8>,
+    /// Set once large buffers are written with MSG_ZEROCOPY, see
+    /// `Config::zerocopy`.
+    zerocopy: Option<ZeroCopy>,
+    /// Zerocopy writes and their bytes, and the writes the kernel had no
+    /// memory to take that way, written as usual, reset by their reader.
+    pub zerocopy_writes: (u64, u64),
+    pub zerocopy_fallbacks: u64,
+    /// Since when the buffers have held at most `Config::shrink_watermark`
+    /// bytes, as sampled by the reactor.
+    pub low_since: Option<Instant>,
+    /// Stamps the reads and the queued data.
+    clock: Arc<dyn Clock>,
+}
+
+impl<S> Client<S> {
+    /// Forgets the connection: clears the counters, the flags, the queues,
+    /// the peer and what was set up or attached for it, keeping only the
+    /// socket, the transport, the write chunk and the allocations of the
+    /// queues and of the read buffer. The data attached by the handler is dropped.
+    pub fn reset(&mut self) {
+        let clock = clock::system();
+        let now = clock.now();
+        // Spelled out so that a new field can't be forgotten
+        let Client {
+            sock: _,
+            peer,
+            transport: _,
+            id,
+            interest,
+            bufs,
+            pos,
+            written,
+            queued_at,
+            max_write_chunk: _,
+            max_queued,
+            overflow,
+            read_paused,
+            overflowed,
+            dropped_newest,
+            dropped_oldest,
+            resume_at,
+            flush_at,
+            throttled,
+            write_parked,
+            accepted_at,
+            bytes_read,
+            bytes_written,
+            last_activity,
+            heartbeat_at,
+            idle_at,
+            dump_limit,
+            tap,
+            mirror,
+            decoder,
+            annotator,
+            header_bytes,
+            listener,
+            source,
+            original_dst,
+            peer_name,
+            muted,
+            closing,
+            aborted,
+            handler_data,
+            send_queued,
+            handed_out,
+            short_read_drained,
+            read_buf_size,
+            scratch,
+            zerocopy,
+            zerocopy_writes,
+            zerocopy_fallbacks,
+            low_since,
+            clock: own_clock,
+        } = self;
+        *peer = NO_PEER;
+        *id = 0;
+        *interest = None;
+        bufs.clear();
+        *pos = 0;
+        *written = 0;
+        queued_at.clear();
+        *max_queued = None;
+        *overflow = Overflow::Backpressure;
+        *read_paused = false;
+        *overflowed = false;
+        *dropped_newest = (0, 0);
+        *dropped_oldest = (0, 0);
+        *resume_at = None;
+        *flush_at = None;
+        *throttled = false;
+        *write_parked = false;
+        *accepted_at = now;
+        *bytes_read = 0;
+        *bytes_written = 0;
+        *last_activity = now;
+        *heartbeat_at = None;
+        *idle_at = None;
+        *dump_limit = DEFAULT_DUMP_LIMIT;
+        *tap = None;
+        *mirror = None;
+        *decoder = None;
+        *annotator = None;
+        *header_bytes = 0;
+        *listener = None;
+        *source = 0;
+        *original_dst = None;
+        *peer_name = None;
+        *muted = false;
+        *closing = false;
+        *aborted = false;
+        *handler_data = None;
+        *send_queued = false;
+        *handed_out = 0;
+        *short_read_drained = false;
+        *read_buf_size = DEFAULT_BUF_SIZE;
+        scratch.clear();
+        *zerocopy = None;
+        *zerocopy_writes = (0, 0);
+        *zerocopy_fallbacks = 0;
+        *low_since = None;
+        *own_clock = clock;
+    }
+
+    // Moves the client as is onto another socket, handing back its own
+    fn with_sock<T>(self, sock: T) -> (S, Client<T>) {
+        let Client {
+            sock: own_sock,
+            peer,
+            transport,
+            id,
+            interest,
+            bufs,
+            pos,
+            written,
+            queued_at,
+            max_write_chunk,
+            max_queued,
+            overflow,
+            read_paused,
+            overflowed,
+            dropped_newest,
+            dropped_oldest,
+            resume_at,
+            flush_at,
+            throttled,
+            write_parked,
+            accepted_at,
+            bytes_read,
+            bytes_written,
+            last_activity,
+            heartbeat_at,
+            idle_at,
+            dump_limit,
+            tap,
+            mirror,
+            decoder,
+            annotator,
+            header_bytes,
+            listener,
+            source,
+            original_dst,
+            peer_name,
+            muted,
+            closing,
+            aborted,
+            handler_data,
+            send_queued,
+            handed_out,
+            short_read_drained,
+            read_buf_size,
+            scratch,
+            zerocopy,
+            zerocopy_writes,
+            zerocopy_fallbacks,
+            low_since,
+            clock,
+        } = self;
+        let client = Client {
+            sock,
+            peer,
+            transport,
+            id,
+            interest,
+            bufs,
+            pos,
+            written,
+            queued_at,
+            max_write_chunk,
+            max_queued,
+            overflow,
+            read_paused,
+            overflowed,
+            dropped_newest,
+            dropped_oldest,
+            resume_at,
+            flush_at,
+            throttled,
+            write_parked,
+            accepted_at,
+            bytes_read,
+            bytes_written,
+            last_activity,
+            heartbeat_at,
+            idle_at,
+            dump_limit,
+            tap,
+            mirror,
+            decoder,
+            annotator,
+            header_bytes,
+            listener,
+            source,
+            original_dst,
+            peer_name,
+            muted,
+            closing,
+            aborted,
+            handler_data,
+            send_queued,
+            handed_out,
+            short_read_drained,
+            read_buf_size,
+            scratch,
+            zerocopy,
+            zerocopy_writes,
+            zerocopy_fallbacks,
+            low_since,
+            clock,
+        };
+        (own_sock, client)
+    }
+}
+
+impl Client<()> {
+    /// A client of a new connection, as `Client::new` makes it, reusing
+    /// the allocations of a shell from `into_shell`.
+    pub fn reuse<S: Socket>(
+        self,
+        sock: S,
+        peer: PeerAddr,
+        transport: Transport,
+        max_write_chunk: Option<usize>,
+    ) -> Client<S> {
+        let ((), mut client) = self.with_sock(sock);
+        client.peer = peer;
+        client.transport = transport;
+        client.max_write_chunk = max_write_chunk;
+        client.accepted_at = client.clock.now();
+        client.last_activity = client.accepted_at;
+        client
+    }
+}
+
+impl<S: Socket> Client<S> {
+    /// Reuses the allocation of `bufs`, e.g. from `into_bufs`.
+    pub fn new(
+        sock: S,
+        peer: PeerAddr,
+        transport: Transport,
+        max_write_chunk: Option<usize>,
+        mut bufs: VecDeque<Vec<u8>>,
+    ) -> Client<S> {
+        bufs.clear();
+        let clock = clock::system();
+        let now = clock.now();
+        Client {
+            sock,
+            peer,
+            transport,
+            id: 0,
+            interest: None,
+            bufs,
+            pos: 0,
+            written: 0,
+            queued_at: VecDeque::new(),
+            max_write_chunk,
+            max_queued: None,
+            overflow: Overflow::Backpressure,
+            read_paused: false,
+            overflowed: false,
+            dropped_newest: (0, 0),
+            dropped_oldest: (0, 0),
+            resume_at: None,
+            flush_at: None,
+            throttled: false,
+            write_parked: false,
+            accepted_at: now,
+            bytes_read: 0,
+            bytes_written: 0,
+            last_activity: now,
+            heartbeat_at: None,
+            idle_at: None,
+            dump_limit: DEFAULT_DUMP_LIMIT,
+            tap: None,
+            mirror: None,
+            decoder: None,
+            annotator: None,
+            header_bytes: 0,
+            listener: None,
+            source: 0,
+            original_dst: None,
+            peer_name: None,
+            muted: false,
+            closing: false,
+            aborted: false,
+            handler_data: None,
+            send_queued: false,
+            handed_out: 0,
+            short_read_drained: false,
+            read_buf_size: DEFAULT_BUF_SIZE,
+            scratch: Vec::new(),
+            zerocopy: None,
+            zerocopy_writes: (0, 0),
+            zerocopy_fallbacks: 0,
+            low_since: None,
+            clock,
+        }
+    }
+
+    /// Takes the time from `clock` rather than the system clock, starting
+    /// with the time of acceptance.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.accepted_at = clock.now();
+        self.last_activity = self.accepted_at;
+        self.clock = clock;
+    }
+
+    /// Closes the socket and keeps only the emptied buffer queue.
+    pub fn into_bufs(self) -> VecDeque<Vec<u8>> {
+        let mut bufs = self.bufs;
+        bufs.clear();
+        bufs
+    }
+
+    /// Takes the socket, e.g. to keep it for `take_zerocopy`, and leaves
+    /// a reset shell holding the allocations of the queues, shrunk to
+    /// `queue_capacity` entries, for `reuse` to serve another connection.
+    pub fn into_shell(self, queue_capacity: usize) -> (S, Client<()>) {
+        let (sock, mut shell) = self.with_sock(());
+        shell.reset();
+        shell.bufs.shrink_to(queue_capacity);
+        shell.queued_at.shrink_to(queue_capacity);
+        (sock, shell)
+    }
+
+    /// Writes the buffers of at least `threshold` bytes with
+    /// MSG_ZEROCOPY, failing if the socket can't.
+    pub fn enable_zerocopy(&mut self, threshold: usize) -> io::Result<()> {
+        self.sock.enable_zerocopy()?;
+        self.zerocopy = Some(ZeroCopy::new(threshold));
+        Ok(())
+    }
+
+    /// Reads the completions of the zerocopy writes, freeing the buffers
+    /// the kernel is done with. Returns how many of the writes done were
+    /// copied by the kernel after all.
+    pub fn zerocopy_ready(&mut self) -> io::Result<u64> {
+        match self.zerocopy {
+            Some(ref mut zerocopy) if !zerocopy.is_empty() => {
+                self.sock.zerocopy_done(&mut |lo, hi, copied| zerocopy.complete(lo, hi, copied))?;
+                Ok(mem::take(&mut zerocopy.copied))
+            }
+            _ => Ok(0),
+        }
+    }
+
+    /// Takes the zerocopy writes the kernel isn't done with, along with
+    /// the buffers they read from, the one being written included, for
+    /// the socket to outlive the client until they are done.
+    pub fn take_zerocopy(&mut self) -> Option<ZeroCopy> {
+        let mut zerocopy = self.zerocopy.take()?;
+        if zerocopy.front_pinned() {
+            zerocopy.retire(self.bufs.pop_front().expect("pinned buffer"));
+            self.pos = 0;
+        }
+        Some(zerocopy).filter(|zerocopy| !zerocopy.is_empty())
+    }
+
+    /// What the kernel measured of the connection, TCP on Linux only.
+    pub fn tcp_info(&self) -> Option<TcpInfo> {
+        self.sock.tcp_info()
+    }
+
+    /// See `Socket::peer_cert`.
+    pub fn peer_cert(&self) -> Option<&str> {
+        self.sock.peer_cert()
+    }
+
+    /// Whether the decoder or the handler ended the connection and
+    /// everything was written.
+    pub fn done(&self) -> bool {
+        let closing = self.closing || matches!(self.decoder, Some(Decoder::Http(ref http)) if http.closing());
+        closing && self.bufs.is_empty() && !self.sock.wants_write()
+    }
+
+    /// How long to hold the echo for the transform the client negotiated,
+    /// if it queued data since the last call.
+    pub fn take_echo_delay(&mut self) -> Option<Duration> {
+        match self.decoder {
+            Some(Decoder::Negotiate(ref mut negotiator)) => negotiator.take_delay(),
+            _ => None,
+        }
+    }
+
+    /// Bytes waiting to be echoed back.
+    pub fn queued_bytes(&self) -> usize {
+        self.bufs.iter().map(Vec::len).sum::<usize>() - self.pos
+    }
+
+    // Bytes queued counting against `max_queued`, headers excluded
+    fn budgeted_bytes(&self) -> usize {
+        self.queued_bytes() - self.annotator.as_ref().map_or(0, Annotator::pending)
+    }
+
+    /// Bytes held by the queue and the decoder, and allocated for them and
+    /// the reads, bookkeeping included.
+    pub fn buffer_usage(&self) -> (usize, usize) {
+        let (decoder_used, decoder_reserved) = self.decoder.as_ref().map_or((0, 0), Decoder::usage);
+        let reserved = self.bufs.iter().map(Vec::capacity).sum::<usize>()
+            + self.scratch.capacity()
+            + self.zerocopy.as_ref().map_or(0, ZeroCopy::held_bytes)
+            + self.bufs.capacity() * mem::size_of::<Vec<u8>>()
+            + self.queued_at.capacity() * mem::size_of::<(u64, Instant)>();
+        (self.queued_bytes() + decoder_used, reserved + decoder_reserved)
+    }
+
+    /// Gives back the memory the queue and the decoder grew to beyond
+    /// what they hold, and the buffer of the next read.
+    pub fn shrink(&mut self) {
+        // The kernel may still read from a buffer being written zerocopy
+        let pinned = self.zerocopy.as_ref().is_some_and(ZeroCopy::front_pinned);
+        for buf in self.bufs.iter_mut().skip(usize::from(pinned)) {
+            buf.shrink_to_fit();
+        }
+        self.bufs.shrink_to_fit();
+        self.queued_at.shrink_to_fit();
+        // Holds nothing between reads, the next one sizes it again
+        self.scratch = Vec::new();
+        if let Some(ref mut decoder) = self.decoder {
+            decoder.shrink();
+        }
+    }
+
+    /// Passes how long the data of each read waited in the queue, once
+    /// it is written.
+    pub fn drain_queued_latencies<F: FnMut(Duration)>(&mut self, mut record: F) {
+        match self.queued_at.front() {
+            Some(&(end, _)) if end <= self.written => {}
+            _ => return,
+        }
+        let now = self.clock.now();
+        while let Some(&(end, at)) = self.queued_at.front() {
+            if end > self.written {
+                break;
+            }
+            record(now - at);
+            self.queued_at.pop_front();
+        }
+    }
+
+    // Timestamps what the read just queued, with the time of the read
+    fn mark_queued(&mut self) {
+        let end = self.written + self.queued_bytes() as u64;
+        if self.queued_at.back().map_or(self.written, |&(end, _)| end) < end {
+            self.queued_at.push_back((end, self.last_activity));
+        }
+    }
+
+    /// Whether reading stopped on a full queue, with data possibly left
+    /// unread.
+    pub fn read_paused(&self) -> bool {
+        self.read_paused
+    }
+
+    /// Whether reading stopped on a full queue which now has room, no
+    /// readiness event will tell.
+    pub fn can_resume_reading(&self) -> bool {
+        self.read_paused && self.max_queued.is_some_and(|max| self.budgeted_bytes() + self.handed_out < max)
+    }
+
+    // Stops reading under backpressure once the queue is full
+    fn pause_reading(&mut self) -> bool {
+        self.read_paused = self.overflow == Overflow::Backpressure
+            && self.max_queued.is_some_and(|max| self.budgeted_bytes() + self.handed_out >= max);
+        self.read_paused
+    }
+
+    // Stops handing out data once the caller holds a queue's worth, under
+    // any policy: it is only queued, or overflows, once sent back
+    fn pause_handing_out(&mut self) -> bool {
+        self.read_paused = self.pause_reading() || self.max_queued.is_some_and(|max| self.handed_out >= max);
+        self.read_paused
+    }
+
+    // Whether each message needs its own buffer, for the overflow policy
+    // or its header
+    fn enqueues(&self) -> bool {
+        (self.max_queued.is_some() && self.overflow != Overflow::Backpressure) || self.annotator.is_some()
+    }
+
+    // Queues a message under the overflow policy
+    fn enqueue(&mut self, message: Vec<u8>) {
+        self.enqueue_annotated(message, 0..0);
+    }
+
+    // Queues what was read as a message, behind its header if annotating
+    fn enqueue_chunk(&mut self, chunk: &[u8]) {
+        let id = self.id;
+        match self.annotator {
+            Some(ref mut annotator) => {
+                let mut message = annotator.header(id, chunk.len());
+                let header = 0..message.len();
+                message.extend_from_slice(chunk);
+                self.enqueue_annotated(message, header);
+            }
+            None => self.enqueue(chunk.to_vec()),
+        }
+    }
+
+    // Same as `enqueue` for a message with a header at `header`, which
+    // the overflow policy doesn't count
+    fn enqueue_annotated(&mut self, message: Vec<u8>, header: Range<usize>) {
+        let max = self.max_queued.unwrap_or(usize::MAX);
+        let len = message.len() - header.len();
+        // The front buffer may be partly written already
+        let first = usize::from(self.pos > 0);
+        let writing = self.bufs.front().filter(|_| first == 1).map_or(0, |buf| buf.len() - self.pos);
+        if self.overflow == Overflow::DropOldest && writing + len <= max {
+            while self.budgeted_bytes() + len > max && self.bufs.len() > first {
+                let old = self.bufs.remove(first).expect("queued buffer");
+                let at = self.written + writing as u64;
+                let headers = self.annotator.as_mut().map_or(0, |annotator| annotator.dropped(at, old.len() as u64));
+                self.written += old.len() as u64;
+                self.dropped_oldest.0 += 1;
+                self.dropped_oldest.1 += old.len() as u64 - headers;
+            }
+        }
+        if self.overflow == Overflow::Backpressure || self.budgeted_bytes() + len <= max {
+            let at = self.written + self.queued_bytes() as u64;
+            if let (Some(annotator), false) = (self.annotator.as_mut(), header.is_empty()) {
+                annotator.queued(at + header.start as u64, header.len());
+            }
+            self.bufs.push_back(message);
+        } else if self.overflow == Overflow::Disconnect {
+            self.overflowed = true;
+        } else {
+            self.dropped_newest.0 += 1;
+            self.dropped_newest.1 += len as u64;
+        }
+    }
+
+    // Queues each frame as a message, or else what the input decodes to
+    fn enqueue_decoded(&mut self, input: &[u8]) -> io::Result<()> {
+        let mut decoder = self.decoder.take().expect("decoded client");
+        let mut message = Vec::new();
+        let result = match decoder {
+            Decoder::Framer(ref mut framer) => {
+                framer.feed(input);
+                let id = self.id;
+                loop {
+                    let annotator = &mut self.annotator;
+                    let header = |len| annotator.as_mut().map_or_else(Vec::new, |annotator| annotator.header(id, len));
+                    match framer.next_frame_with(&mut message, header) {
+                        Ok(Some(header)) => self.enqueue_annotated(mem::take(&mut message), header),
+                        Ok(None) => break Ok(()),
+                        Err(e) => break Err(e),
+                    }
+                }
+            }
+            _ => decoder.decode(input, &mut message).map(|()| {
+                if !message.is_empty() {
+                    self.enqueue_chunk(&message);
+                }
+            }),
+        };
+        self.decoder = Some(decoder);
+        result
+    }
+
+    /// Merges the queued buffers so they go out in a single write. Packet
+    /// streams keep one buffer per message.
+    pub fn coalesce(&mut self) {
+        if self.bufs.len() < 2 || self.sock.is_packet() || self.zerocopy.as_ref().is_some_and(ZeroCopy::front_pinned) {
+            return;
+        }
+        let mut merged = Vec::with_capacity(self.queued_bytes());
+        let pos = mem::replace(&mut self.pos, 0);
+        for (i, buf) in self.bufs.drain(..).enumerate() {
+            merged.extend_from_slice(if i == 0 { &buf[pos..] } else { &buf });
+        }
+        self.bufs.push_back(merged);
+    }
+
+    /// The socket itself, for backends staging its I/O.
+    pub fn sock_mut(&mut self) -> &mut S {
+        &mut self.sock
+    }
+
+    pub fn peer_addr(&self) -> PeerAddr {
+        self.peer
+    }
+
+    /// The peer as logged, with its host name once known.
+    pub fn peer(&self) -> Peer<'_> {
+        Peer {
+            addr: self.peer,
+            name: self.peer_name.as_deref(),
+        }
+    }
+
+    /// What the socket is registered for.
+    pub fn interest(&self) -> Option<Interest> {
+        self.interest
+    }
+
+    pub fn interest_state(&self) -> InterestState {
+        InterestState {
+            queued: !self.bufs.is_empty() || self.sock.wants_write(),
+            read_open: !self.overflowed && !self.aborted,
+            backpressured: self.read_paused && !self.can_resume_reading(),
+            zerocopy: self.zerocopy.is_some(),
+        }
+    }
+
+    /// What the socket should be registered for in the current state.
+    pub fn desired_interest(&self) -> Option<Interest> {
+        self.interest_state().interest()
+    }
+
+    pub fn register(&mut self, registry: &Registry, token: Token) -> io::Result<()> {
+        // Data may be queued before the first read, e.g. a banner
+        let interest = self.desired_interest();
+        if let Some(interest) = interest {
+            registry.register(&mut self.sock, token, interest)?;
+        }
+        self.interest = interest;
+        Ok(())
+    }
+
+    /// Updates the interest, registering or deregistering the socket as
+    /// it starts or stops waiting for anything, returns whether a
+    /// reregistration was needed.
+    pub fn reregister(&mut self, registry: &Registry, token: Token) -> io::Result<bool> {
+        let interest = self.desired_interest();
+        if interest == self.interest {
+            return Ok(false);
+        }
+        match (self.interest, interest) {
+            (Some(_), Some(interest)) => registry.reregister(&mut self.sock, token, interest)?,
+            (None, Some(interest)) => registry.register(&mut self.sock, token, interest)?,
+            (Some(_), None) => registry.deregister(&mut self.sock)?,
+            (None, None) => {}
+        }
+        self.interest = interest;
+        Ok(true)
+    }
+
+    pub fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        match self.interest.take() {
+            Some(_) => registry.deregister(&mut self.sock),
+            None => Ok(()),
+        }
+    }
+
+    /// Queues everything readable, `None` means the peer has closed.
+    pub fn read(&mut self) -> io::Result<Option<usize>> {
+        if self.sock.is_packet() {
+            return self.read_packets();
+        }
+        let mut rbuf = mem::take(&mut self.scratch);
+        rbuf.resize(self.read_buf_size, 0);
+        let result = if self.decoder.is_some() { self.read_decoded(&mut rbuf) } else { self.read_stream(&mut rbuf) };
+        self.scratch = rbuf;
+        result
+    }
+
+    // Queues what the stream holds, reading into `rbuf` while nothing is
+    // queued
+    fn read_stream(&mut self, rbuf: &mut [u8]) -> io::Result<Option<usize>> {
+        let mut tot_len = 0;
+        let enqueues = self.enqueues();
+        // Buffers large enough to be written zerocopy
+        let (buf_size, max_buf_size, read_size) = match self.zerocopy {
+            Some(ref zerocopy) => {
+                let size = zerocopy.threshold.min(MAX_ZEROCOPY_BUF_SIZE);
+                (size, size.max(MAX_BUF_SIZE), ZEROCOPY_READ_SIZE)
+            }
+            None => {
+                let max_buf_size = self.read_buf_size.max(MAX_BUF_SIZE);
+                (self.read_buf_size, max_buf_size, max_buf_size)
+            }
+        };
+
+        while !self.pause_reading() && !self.overflowed {
+            let mut wanted = rbuf.len();
+            let res = match self.bufs.back_mut() {
+                // Fill the spare capacity of the last buffer first
+                Some(buf) if !enqueues && buf.len() < buf.capacity().min(max_buf_size) => {
+                    let start = buf.len();
+                    buf.resize(buf.capacity().min(max_buf_size).min(start + read_size), 0);
+                    wanted = buf.len() - start;
+                    let res = self.sock.read(&mut buf[start..]);
+                    buf.truncate(start + *res.as_ref().unwrap_or(&0));
+                    if buf.len() > start {
+                        trace!("read from {}:\n{}", self.peer, HexDump::new(&buf[start..], self.dump_limit));
+                        if let Some(ref tap) = self.tap {
+                            tap.read(&buf[start..]);
+                        }
+                        if let Some(ref mut mirror) = self.mirror {
+                            mirror.send(&buf[start..]);
+                        }
+                    }
+                    res
+                }
+                // Allocate only once there is something to keep
+                _ => self.sock.read(rbuf).inspect(|&len| {
+                    if len > 0 {
+                        trace!("read from {}:\n{}", self.peer, HexDump::new(&rbuf[..len], self.dump_limit));
+                        if let Some(ref tap) = self.tap {
+                            tap.read(&rbuf[..len]);
+                        }
+                        if let Some(ref mut mirror) = self.mirror {
+                            mirror.send(&rbuf[..len]);
+                        }
+                        if enqueues {
+                            self.enqueue_chunk(&rbuf[..len]);
+                            return;
+                        }
+                        let mut buf = Vec::with_capacity(buf_size);
+                        buf.extend_from_slice(&rbuf[..len]);
+                        self.bufs.push_back(buf);
+                    }
+                }),
+            };
+            match res {
+                Ok(0) => return Ok(None),
+                Ok(len) => {
+                    self.last_activity = self.clock.now();
+                    tot_len += len;
+                    if self.short_read_drained && len < wanted {
+                        break;
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    // Socket is not ready anymore, stop reading
+                    break;
+                }
+                // Cut short by a signal, try again
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.mark_queued();
+        Ok(Some(tot_len))
+    }
+
+    // Queues one buffer per message, including empty ones
+    fn read_packets(&mut self) -> io::Result<Option<usize>> {
+        let mut tot_len = 0;
+        let mut rbuf = vec![0; MAX_PACKET_SIZE];
+
+        while !self.pause_reading() && !self.overflowed {
+            match self.sock.recv_packet(&mut rbuf) {
+                Ok(None) => return Ok(None),
+                Ok(Some(len)) => {
+                    trace!("read from {}:\n{}", self.peer, HexDump::new(&rbuf[..len], self.dump_limit));
+                    if let Some(ref mut mirror) = self.mirror {
+                        mirror.send(&rbuf[..len]);
+                    }
+                    self.enqueue_chunk(&rbuf[..len]);
+                    self.last_activity = self.clock.now();
+                    tot_len += len;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    // Socket is not ready anymore, stop reading
+                    break;
+                }
+                // Cut short by a signal, try again
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.mark_queued();
+        Ok(Some(tot_len))
+    }
+
+    // Queues the decoded input, counting what was read
+    fn read_decoded(&mut self, rbuf: &mut [u8]) -> io::Result<Option<usize>> {
+        let mut tot_len = 0;
+        let enqueues = self.enqueues();
+        let max_buf_size = self.read_buf_size.max(MAX_BUF_SIZE);
+
+        while !self.pause_reading() && !self.overflowed {
+            match self.sock.read(rbuf) {
+                Ok(0) => return Ok(None),
+                Ok(len) => {
+                    trace!("read from {}:\n{}", self.peer, HexDump::new(&rbuf[..len], self.dump_limit));
+                    if let Some(ref tap) = self.tap {
+                        tap.read(&rbuf[..len]);
+                    }
+                    if let Some(ref mut mirror) = self.mirror {
+                        mirror.send(&rbuf[..len]);
+                    }
+                    // Growing a buffer being written zerocopy would move it
+                    let pinned = self.bufs.len() == 1 && self.zerocopy.as_ref().is_some_and(ZeroCopy::front_pinned);
+                    let decoder = self.decoder.as_mut().expect("decoded client");
+                    match self.bufs.back_mut() {
+                        _ if enqueues => self.enqueue_decoded(&rbuf[..len])?,
+                        Some(buf) if buf.len() < max_buf_size && !pinned => decoder.decode(&rbuf[..len], buf)?,
+                        _ => {
+                            let mut buf = Vec::with_capacity(self.read_buf_size);
+                            decoder.decode(&rbuf[..len], &mut buf)?;
+                            // e.g. telnet negotiation refused, or an HTTP
+                            // request still incomplete
+                            if !buf.is_empty() {
+                                self.bufs.push_back(buf);
+                            }
+                        }
+                    }
+                    self.last_activity = self.clock.now();
+                    tot_len += len;
+                    if self.short_read_drained && len < rbuf.len() {
+                        break;
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    // Socket is not ready anymore, stop reading
+                    break;
+                }
+                // Cut short by a signal, try again
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.mark_queued();
+        Ok(Some(tot_len))
+    }
+
+    /// Like `read`, acting on what `handle` returns for the data of each
+    /// read instead, whatever the decoder. Every packet of a packet stream
+    /// gets a reply, even empty. Once muted or closing, the input is
+    /// dropped without calling `handle`.
+    pub fn read_handled(&mut self, handle: &mut dyn FnMut(&[u8]) -> Action) -> io::Result<Option<usize>> {
+        let mut tot_len = 0;
+        let mut rbuf = vec![0; MAX_PACKET_SIZE];
+
+        while !self.pause_reading() && !self.overflowed {
+            let res = if self.sock.is_packet() {
+                self.sock.recv_packet(&mut rbuf)
+            } else {
+                self.sock.read(&mut rbuf).map(|len| Some(len).filter(|&len| len > 0))
+            };
+            match res {
+                Ok(None) => return Ok(None),
+                Ok(Some(len)) => {
+                    trace!("read from {}:\n{}", self.peer, HexDump::new(&rbuf[..len], self.dump_limit));
+                    if let Some(ref tap) = self.tap {
+                        tap.read(&rbuf[..len]);
+                    }
+                    if let Some(ref mut mirror) = self.mirror {
+                        mirror.send(&rbuf[..len]);
+                    }
+                    self.last_activity = self.clock.now();
+                    tot_len += len;
+                    if self.muted || self.closing {
+                        continue;
+                    }
+                    let reply = match handle(&rbuf[..len]) {
+                        Action::Reply(reply) => reply,
+                        Action::ReplyThenClose(reply) => {
+                            self.closing = true;
+                            reply
+                        }
+                        Action::Silence => {
+                            self.muted = true;
+                            continue;
+                        }
+                        Action::CloseNow => {
+                            self.aborted = true;
+                            break;
+                        }
+                    };
+                    if !reply.is_empty() || self.sock.is_packet() {
+                        self.enqueue(reply);
+                    }
+                    if self.short_read_drained && !self.sock.is_packet() && len < rbuf.len() {
+                        break;
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    // Socket is not ready anymore, stop reading
+                    break;
+                }
+                // Cut short by a signal, try again
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.mark_queued();
+        Ok(Some(tot_len))
+    }
+
+    /// Hands the data of every read, or every packet, to `emit` in the
+    /// buffer it was read into, queueing nothing. `None` means the peer
+    /// has closed.
+    pub fn read_owned(&mut self, emit: &mut dyn FnMut(Vec<u8>)) -> io::Result<Option<usize>> {
+        let mut tot_len = 0;
+        let size = if self.sock.is_packet() { MAX_PACKET_SIZE } else { MAX_BUF_SIZE };
+
+        while !self.pause_handing_out() && !self.overflowed {
+            let mut buf = vec![0; size];
+            let res = if self.sock.is_packet() {
+                self.sock.recv_packet(&mut buf)
+            } else {
+                self.sock.read(&mut buf).map(|len| Some(len).filter(|&len| len > 0))
+            };
+            match res {
+                Ok(None) => return Ok(None),
+                Ok(Some(len)) => {
+                    trace!("read from {}:\n{}", self.peer, HexDump::new(&buf[..len], self.dump_limit));
+                    if let Some(ref tap) = self.tap {
+                        tap.read(&buf[..len]);
+                    }
+                    if let Some(ref mut mirror) = self.mirror {
+                        mirror.send(&buf[..len]);
+                    }
+                    self.last_activity = self.clock.now();
+                    tot_len += len;
+                    self.handed_out += len;
+                    buf.truncate(len);
+                    if len < size / 2 {
+                        // Most reads are small, a full-sized buffer each
+                        // would add up wherever they are kept
+                        buf.shrink_to_fit();
+                    }
+                    emit(buf);
+                    if self.short_read_drained && !self.sock.is_packet() && len < size {
+                        break;
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    // Socket is not ready anymore, stop reading
+                    break;
+                }
+                // Cut short by a signal, try again
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(Some(tot_len))
+    }
+
+    /// Queues `data` under the overflow policy, as the echo of a read
+    /// would be.
+    pub fn send(&mut self, data: Vec<u8>) {
+        if !data.is_empty() || self.sock.is_packet() {
+            self.enqueue(data);
+        }
+    }
+
+    /// Writes what the socket holds of its own, see `Socket::wants_write`.
+    pub fn flush_socket(&mut self) -> io::Result<()> {
+        if !self.sock.wants_write() {
+            return Ok(());
+        }
+        match self.sock.flush() {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Flushes the queued buffers. With `pause` set, at most one chunk is
+    /// written before returning.
+    pub fn write(&mut self, pause: bool) -> io::Result<usize> {
+        self.write_limited(pause, usize::MAX)
+    }
+
+    /// Like `write`, stopping after `limit` bytes. A message of a packet
+    /// stream is never split, so the first one is written whole even when
+    /// it is larger.
+    pub fn write_limited(&mut self, pause: bool, limit: usize) -> io::Result<usize> {
+        let mut tot_len = 0;
+
+        while let (Some(buf), true) = (self.bufs.front(), tot_len < limit) {
+            let left = limit - tot_len;
+            let end = if self.sock.is_packet() {
+                // Splitting a message would break its boundary
+                if tot_len > 0 && buf.len() > left {
+                    break;
+                }
+                buf.len()
+            } else {
+                let chunk = self.max_write_chunk.unwrap_or(usize::MAX).min(left);
+                buf.len().min(self.pos.saturating_add(chunk))
+            };
+            let zerocopy = self.zerocopy.as_mut().filter(|zerocopy| buf.len() >= zerocopy.threshold);
+            let result = match zerocopy {
+                Some(zerocopy) => match self.sock.write_zerocopy(&buf[self.pos..end]) {
+                    Ok(Some(len)) => {
+                        if len > 0 {
+                            zerocopy.sent();
+                            self.zerocopy_writes.0 += 1;
+                            self.zerocopy_writes.1 += len as u64;
+                        }
+                        Ok(len)
+                    }
+                    Ok(None) => {
+                        self.zerocopy_fallbacks += 1;
+                        self.sock.write(&buf[self.pos..end])
+                    }
+                    Err(e) => Err(e),
+                },
+                None => self.sock.write(&buf[self.pos..end]),
+            };
+            match result {
+                // Retrying would spin, the socket takes nothing
+                Ok(0) if end > self.pos => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(len) => {
+                    let written = &buf[self.pos..self.pos + len];
+                    trace!("write to {}:\n{}", self.peer, HexDump::new(written, self.dump_limit));
+                    if let Some(ref tap) = self.tap {
+                        tap.write(written);
+                    }
+                    self.pos += len;
+                    if let Some(ref mut annotator) = self.annotator {
+                        self.header_bytes += annotator.written(self.written, self.written + len as u64);
+                    }
+                    self.written += len as u64;
+                    if buf.len() == self.pos {
+                        let buf = self.bufs.pop_front().expect("written buffer");
+                        self.pos = 0;
+                        if let Some(ref mut zerocopy) = self.zerocopy {
+                            zerocopy.retire(buf);
+                        }
+                    }
+                    tot_len += len;
+                    if pause {
+                        break;
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    // Socket is not ready anymore, stop writing
+                    break;
+                }
+                // Cut short by a signal, try again
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                e => return e,
+            }
+        }
+
+        Ok(tot_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use mio::event::Source;
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::clock::ManualClock;
+
+    // Plays back the results scripted for reads and writes, then would
+    // block on reads and takes everything written
+    #[derive(Default)]
+    struct MockStream {
+        reads: VecDeque<io::Result<Vec<u8>>>,
+        writes: VecDeque<io::Result<usize>>,
+        written: Vec<u8>,
+        packet: bool,
+        read_calls: usize,
+        write_calls: usize,
+        register_calls: usize,
+        reregister_calls: usize,
+        deregister_calls: usize,
+    }
+
+    impl MockStream {
+        fn reading(reads: Vec<io::Result<Vec<u8>>>) -> MockStream {
+            MockStream {
+                reads: reads.into(),
+                ..MockStream::default()
+            }
+        }
+
+        fn writing(writes: Vec<io::Result<usize>>) -> MockStream {
+            MockStream {
+                writes: writes.into(),
+                ..MockStream::default()
+            }
+        }
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.read_calls += 1;
+            match self.reads.pop_front() {
+                Some(Ok(mut data)) => {
+                    let len = data.len().min(buf.len());
+                    buf[..len].copy_from_slice(&data[..len]);
+                    if len < data.len() {
+                        self.reads.push_front(Ok(data.split_off(len)));
+                    }
+                    Ok(len)
+                }
+                Some(Err(e)) => Err(e),
+                None => Err(io::ErrorKind::WouldBlock.into()),
+            }
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.write_calls += 1;
+            let len = match self.writes.pop_front() {
+                Some(Ok(len)) => len.min(buf.len()),
+                Some(Err(e)) => return Err(e),
+                None => buf.len(),
+            };
+            self.written.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Source for MockStream {
+        fn register(&mut self, _: &Registry, _: Token, _: Interest) -> io::Result<()> {
+            self.register_calls += 1;
+            Ok(())
+        }
+
+        fn reregister(&mut self, _: &Registry, _: Token, _: Interest) -> io::Result<()> {
+            self.reregister_calls += 1;
+            Ok(())
+        }
+
+        fn deregister(&mut self, _: &Registry) -> io::Result<()> {
+            self.deregister_calls += 1;
+            Ok(())
+        }
+    }
+
+    impl Socket for MockStream {
+        fn is_packet(&self) -> bool {
+            self.packet
+        }
+    }
+
+    fn data(bytes: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(bytes.to_vec())
+    }
+
+    fn error<T>(kind: io::ErrorKind) -> io::Result<T> {
+        Err(kind.into())
+    }
+
+    fn client(sock: MockStream) -> Client<MockStream> {
+        let peer = PeerAddr::Inet("127.0.0.1:7".parse().unwrap());
+        Client::new(sock, peer, Transport::Tcp, None, VecDeque::new())
+    }
+
+    fn queued(client: &Client<MockStream>) -> Vec<u8> {
+        let mut queued: Vec<u8> = client.bufs.iter().flatten().copied().collect();
+        queued.drain(..client.pos);
+        queued
+    }
+
+    // Everything about a client but its socket, spelled out so that a new
+    // field can't be forgotten
+    fn state<S>(client: &Client<S>) -> String {
+        let Client {
+            sock: _,
+            peer,
+            transport,
+            id,
+            interest,
+            bufs,
+            pos,
+            written,
+            queued_at,
+            max_write_chunk,
+            max_queued,
+            overflow,
+            read_paused,
+            overflowed,
+            dropped_newest,
+            dropped_oldest,
+            resume_at,
+            flush_at,
+            throttled,
+            write_parked,
+            accepted_at,
+            bytes_read,
+            bytes_written,
+            last_activity,
+            heartbeat_at,
+            idle_at,
+            dump_limit,
+            tap,
+            mirror,
+            decoder,
+            annotator,
+            header_bytes,
+            listener,
+            source,
+            original_dst,
+            peer_name,
+            muted,
+            closing,
+            aborted,
+            handler_data,
+            send_queued,
+            handed_out,
+            short_read_drained,
+            read_buf_size,
+            scratch,
+            zerocopy,
+            zerocopy_writes,
+            zerocopy_fallbacks,
+            low_since,
+            clock,
+        } = client;
+        format!(
+            "{:?}",
+            (
+                (peer, transport, id, interest, bufs, pos, written, queued_at, max_write_chunk, max_queued),
+                (overflow, read_paused, overflowed, dropped_newest, dropped_oldest, resume_at, flush_at),
+                (throttled, write_parked, accepted_at, bytes_read, bytes_written, last_activity, heartbeat_at),
+                (idle_at, dump_limit, tap.is_some(), mirror.is_some(), decoder.is_some(), annotator.is_some()),
+                (header_bytes, listener, source, original_dst, peer_name, muted, closing, aborted),
+                (handler_data.is_some(), send_queued, handed_out, short_read_drained, read_buf_size, scratch),
+                (zerocopy.is_some(), zerocopy_writes, zerocopy_fallbacks, low_since, clock.now()),
+            )
+        )
+    }
+
+    // Counts how many times it was dropped
+    struct Dropped(Arc<AtomicUsize>);
+
+    impl Drop for Dropped {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn recycled_client_is_a_new_one() {
+        let mut used = client(MockStream {
+            reads: vec![data(b"abcdef")].into(),
+            writes: vec![Ok(2), error(io::ErrorKind::WouldBlock)].into(),
+            ..MockStream::default()
+        });
+        used.read().unwrap();
+        used.write(false).unwrap();
+        used.id = 7;
+        used.max_queued = Some(100);
+        used.overflow = Overflow::DropOldest;
+        used.dropped_newest = (1, 2);
+        used.resume_at = Some(Instant::now());
+        used.throttled = true;
+        used.bytes_read = 6;
+        used.heartbeat_at = Some(Instant::now());
+        used.idle_at = Some(Instant::now());
+        used.dump_limit = 0;
+        used.decoder = Some(Decoder::Telnet(Telnet::default()));
+        used.listener = Some(1);
+        used.source = 2;
+        used.original_dst = Some("10.0.0.1:80".parse().unwrap());
+        used.peer_name = Some("host".into());
+        used.muted = true;
+        used.closing = true;
+        used.handler_data = Some(Box::new(3u8));
+        used.short_read_drained = true;
+        used.read_buf_size = 10;
+        used.low_since = Some(Instant::now());
+        assert_eq!(used.queued_bytes(), 4);
+
+        let (sock, shell) = used.into_shell(16);
+        assert_eq!(sock.written, b"ab");
+        assert!(shell.bufs.capacity() > 0);
+        let peer = PeerAddr::Inet("127.0.0.2:8".parse().unwrap());
+        let mut recycled = shell.reuse(MockStream::default(), peer, Transport::Unix, Some(5));
+        let mut new = Client::new(MockStream::default(), peer, Transport::Unix, Some(5), VecDeque::new());
+        let clock = Arc::new(ManualClock::new());
+        recycled.set_clock(clock.clone());
+        new.set_clock(clock);
+        assert_eq!(state(&recycled), state(&new));
+    }
+
+    #[test]
+    fn reset_drops_the_handler_data_once() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let mut client = client(MockStream::default());
+        client.handler_data = Some(Box::new(Dropped(drops.clone())));
+        let (_, shell) = client.into_shell(16);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+        drop(shell);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn into_shell_bounds_the_queues() {
+        let mut client = client(MockStream::default());
+        client.bufs.extend((0..100).map(|_| vec![0]));
+        let (_, shell) = client.into_shell(16);
+        assert!(shell.bufs.is_empty());
+        assert!(shell.bufs.capacity() < 100);
+    }
+
+    #[test]
+    fn read_until_would_block() {
+        let mut client = client(MockStream::reading(vec![data(b"abc"), data(b"de")]));
+        assert_eq!(client.read().unwrap(), Some(5));
+        assert_eq!(client.bufs, [b"abcde"]);
+        assert_eq!(client.sock.read_calls, 3);
+    }
+
+    #[test]
+    fn read_nothing() {
+        let mut client = client(MockStream::default());
+        assert_eq!(client.read().unwrap(), Some(0));
+        assert!(client.bufs.is_empty());
+    }
+
+    #[test]
+    fn read_eof_first() {
+        let mut client = client(MockStream::reading(vec![data(b"")]));
+        assert_eq!(client.read().unwrap(), None);
+        assert!(client.bufs.is_empty());
+    }
+
+    #[test]
+    fn read_eof_mid_loop_keeps_what_was_read() {
+        let mut client = client(MockStream::reading(vec![data(b"abc"), data(b""), data(b"unread")]));
+        assert_eq!(client.read().unwrap(), None);
+        assert_eq!(queued(&client), b"abc");
+        assert_eq!(client.sock.read_calls, 2);
+    }
+
+    #[test]
+    fn read_error_mid_loop() {
+        let mut client = client(MockStream::reading(vec![data(b"abc"), error(io::ErrorKind::ConnectionReset)]));
+        let e = client.read().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!(queued(&client), b"abc");
+    }
+
+    #[test]
+    fn read_first_buffer_has_read_buf_size_capacity() {
+        let mut client = client(MockStream::reading(vec![data(b"abc")]));
+        client.read_buf_size = 100;
+        client.read().unwrap();
+        assert_eq!(client.bufs[0].capacity(), 100);
+    }
+
+    #[test]
+    fn read_takes_read_buf_size_at_once_into_a_kept_buffer() {
+        let mut client = client(MockStream::reading(vec![Ok(vec![1; 3000])]));
+        client.read_buf_size = 4096;
+        client.short_read_drained = true;
+        assert_eq!(client.read().unwrap(), Some(3000));
+        assert_eq!(client.sock.read_calls, 1);
+        let scratch = client.scratch.as_ptr();
+
+        client.sock.reads.push_back(data(b"more"));
+        client.bufs.clear();
+        assert_eq!(client.read().unwrap(), Some(4));
+        assert_eq!(client.scratch.as_ptr(), scratch);
+    }
+
+    #[test]
+    fn read_buffer_counts_as_reserved_until_shrunk() {
+        let mut client = client(MockStream::reading(vec![data(b"abc")]));
+        client.read_buf_size = 4096;
+        client.read().unwrap();
+        client.bufs.clear();
+        let (used, reserved) = client.buffer_usage();
+        assert_eq!(used, 0);
+        assert!(reserved >= 4096, "{} bytes reserved", reserved);
+
+        client.shrink();
+        assert_eq!(client.scratch.capacity(), 0);
+        assert!(client.buffer_usage().1 <= reserved - 4096);
+    }
+
+    #[test]
+    fn read_short_read_drained_skips_the_confirming_read() {
+        let mut client = client(MockStream::reading(vec![data(b"abc"), data(b"de")]));
+        client.short_read_drained = true;
+        assert_eq!(client.read().unwrap(), Some(3));
+        assert_eq!(client.sock.read_calls, 1);
+    }
+
+    #[test]
+    fn read_stops_when_backpressured() {
+        let mut client = client(MockStream::reading(vec![data(b"abcd"), data(b"ef")]));
+        client.max_queued = Some(4);
+        assert_eq!(client.read().unwrap(), Some(4));
+        assert!(client.read_paused());
+        assert!(!client.can_resume_reading());
+        assert_eq!(client.sock.read_calls, 1);
+
+        client.write(false).unwrap();
+        assert!(client.can_resume_reading());
+        assert_eq!(client.read().unwrap(), Some(2));
+        assert_eq!(queued(&client), b"ef");
+    }
+
+    #[test]
+    fn read_appends_up_to_the_capacity() {
+        let mut client = client(MockStream::reading(vec![data(b"abc")]));
+        client.read_buf_size = 8;
+        client.read().unwrap();
+        client.sock.reads.push_back(data(b"defghij"));
+        assert_eq!(client.read().unwrap(), Some(7));
+        assert_eq!(client.bufs, [&b"abcdefgh"[..], b"ij"]);
+        assert_eq!(client.bufs[0].capacity(), 8);
+        assert_eq!(client.bufs[1].capacity(), 8);
+    }
+
+    #[test]
+    fn read_appends_to_a_partly_written_buffer() {
+        let mut client = client(MockStream {
+            reads: vec![data(b"abc")].into(),
+            writes: vec![Ok(2)].into(),
+            ..MockStream::default()
+        });
+        client.read_buf_size = 8;
+        client.read().unwrap();
+        client.write(true).unwrap();
+        assert_eq!((client.bufs.len(), client.pos), (1, 2));
+
+        client.sock.reads.push_back(data(b"de"));
+        client.read().unwrap();
+        assert_eq!(client.bufs, [b"abcde"]);
+        assert_eq!(queued(&client), b"cde");
+
+        client.write(false).unwrap();
+        assert_eq!(client.sock.written, b"abcde");
+        assert!(client.bufs.is_empty());
+        assert_eq!(client.pos, 0);
+
+        // Written and gone, the next read starts a buffer of its own
+        client.sock.reads.push_back(data(b"f"));
+        client.read().unwrap();
+        assert_eq!(client.bufs, [b"f"]);
+        assert_eq!(client.pos, 0);
+    }
+
+    #[test]
+    fn read_appends_behind_a_partly_written_front() {
+        let mut client = client(MockStream {
+            reads: vec![data(b"abcdefgh"), data(b"ij")].into(),
+            writes: vec![Ok(3), error(io::ErrorKind::WouldBlock)].into(),
+            ..MockStream::default()
+        });
+        client.read_buf_size = 8;
+        client.read().unwrap();
+        assert_eq!(client.bufs, [&b"abcdefgh"[..], b"ij"]);
+        client.write(false).unwrap();
+
+        client.sock.reads.push_back(data(b"kl"));
+        client.read().unwrap();
+        assert_eq!(client.bufs, [&b"abcdefgh"[..], b"ijkl"]);
+        assert_eq!(client.pos, 3);
+        assert_eq!(queued(&client), b"defghijkl");
+    }
+
+    #[test]
+    fn read_stops_appending_to_a_large_buffer() {
+        let mut client = client(MockStream::reading(vec![Ok(vec![1; MAX_BUF_SIZE + 100])]));
+        let mut banner = Vec::with_capacity(2 * MAX_BUF_SIZE);
+        banner.push(0);
+        client.bufs.push_back(banner);
+        assert_eq!(client.read().unwrap(), Some(MAX_BUF_SIZE + 100));
+        assert_eq!(client.bufs[0].len(), MAX_BUF_SIZE);
+        assert_eq!(client.bufs.iter().map(Vec::len).sum::<usize>(), MAX_BUF_SIZE + 101);
+    }
+
+    #[test]
+    fn read_overflow_disconnect() {
+        let mut client = client(MockStream::reading(vec![data(b"abc"), data(b"de"), data(b"f")]));
+        client.max_queued = Some(4);
+        client.overflow = Overflow::Disconnect;
+        assert_eq!(client.read().unwrap(), Some(5));
+        assert!(client.overflowed);
+        assert_eq!(queued(&client), b"abc");
+        assert_eq!(client.sock.read_calls, 2);
+    }
+
+    #[test]
+    fn read_overflow_drop_newest() {
+        let mut client = client(MockStream::reading(vec![data(b"abc"), data(b"de"), data(b"f")]));
+        client.max_queued = Some(4);
+        client.overflow = Overflow::DropNewest;
+        assert_eq!(client.read().unwrap(), Some(6));
+        assert_eq!(client.bufs, [&b"abc"[..], b"f"]);
+        assert_eq!(client.dropped_newest, (1, 2));
+    }
+
+    #[test]
+    fn read_overflow_drop_oldest() {
+        let mut client = client(MockStream::reading(vec![data(b"abc"), data(b"de"), data(b"f")]));
+        client.max_queued = Some(4);
+        client.overflow = Overflow::DropOldest;
+        assert_eq!(client.read().unwrap(), Some(6));
+        assert_eq!(client.bufs, [&b"de"[..], b"f"]);
+        assert_eq!(client.dropped_oldest, (1, 3));
+    }
+
+    #[test]
+    fn read_packets_one_buffer_each() {
+        let mut sock = MockStream::reading(vec![data(b"ab"), data(b"c")]);
+        sock.packet = true;
+        let mut client = client(sock);
+        assert_eq!(client.read().unwrap(), Some(3));
+        assert_eq!(client.bufs, [&b"ab"[..], b"c"]);
+    }
+
+    #[test]
+    fn write_everything() {
+        let mut client = client(MockStream::default());
+        client.bufs.extend([b"abc".to_vec(), b"de".to_vec()]);
+        assert_eq!(client.write(false).unwrap(), 5);
+        assert!(client.bufs.is_empty());
+        assert_eq!(client.pos, 0);
+        assert_eq!(client.sock.written, b"abcde");
+    }
+
+    #[test]
+    fn write_nothing_queued() {
+        let mut client = client(MockStream::default());
+        assert_eq!(client.write(false).unwrap(), 0);
+        assert_eq!(client.sock.write_calls, 0);
+    }
+
+    #[test]
+    fn write_partial_then_would_block() {
+        let mut client = client(MockStream::writing(vec![Ok(3), error(io::ErrorKind::WouldBlock)]));
+        client.bufs.extend([b"abc".to_vec(), b"defg".to_vec(), b"h".to_vec()]);
+        assert_eq!(client.write(false).unwrap(), 3);
+        assert_eq!(client.bufs.len(), 2);
+        assert_eq!(client.pos, 0);
+
+        assert_eq!(client.write(false).unwrap(), 5);
+        assert_eq!(client.sock.written, b"abcdefgh");
+        assert!(client.bufs.is_empty());
+    }
+
+    #[test]
+    fn write_partial_across_buffers() {
+        let writes = vec![Ok(2), Ok(1), Ok(3), error(io::ErrorKind::WouldBlock), Ok(1), Ok(1)];
+        let mut client = client(MockStream::writing(writes));
+        client.bufs.extend([b"abc".to_vec(), b"defg".to_vec(), b"hi".to_vec()]);
+
+        assert_eq!(client.write(false).unwrap(), 6);
+        assert_eq!((client.bufs.len(), client.pos), (2, 3));
+        assert_eq!(client.queued_bytes(), 3);
+        assert_eq!(queued(&client), b"ghi");
+
+        assert_eq!(client.write(false).unwrap(), 3);
+        assert_eq!(client.sock.written, b"abcdefghi");
+        assert_eq!(client.pos, 0);
+        assert_eq!(client.written, 9);
+    }
+
+    #[test]
+    fn write_error_keeps_the_queue() {
+        let mut client = client(MockStream::writing(vec![Ok(1), error(io::ErrorKind::BrokenPipe)]));
+        client.bufs.push_back(b"abc".to_vec());
+        let e = client.write(false).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(queued(&client), b"bc");
+    }
+
+    #[test]
+    fn write_zero_is_an_error() {
+        let mut client = client(MockStream::writing(vec![Ok(0)]));
+        client.bufs.push_back(b"abc".to_vec());
+        let e = client.write(false).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::WriteZero);
+        assert_eq!(client.sock.write_calls, 1);
+        assert_eq!(queued(&client), b"abc");
+    }
+
+    #[test]
+    fn write_pause_writes_one_chunk() {
+        let mut client = client(MockStream::default());
+        client.max_write_chunk = Some(2);
+        client.bufs.push_back(b"abcde".to_vec());
+        assert_eq!(client.write(true).unwrap(), 2);
+        assert_eq!(client.write(true).unwrap(), 2);
+        assert_eq!(client.write(true).unwrap(), 1);
+        assert_eq!(client.sock.written, b"abcde");
+        assert_eq!(client.sock.write_calls, 3);
+    }
+
+    #[test]
+    fn write_in_chunks() {
+        let mut client = client(MockStream::default());
+        client.max_write_chunk = Some(2);
+        client.bufs.push_back(b"abcde".to_vec());
+        assert_eq!(client.write(false).unwrap(), 5);
+        assert_eq!(client.sock.write_calls, 3);
+    }
+
+    #[test]
+    fn write_limited_stops_at_the_limit() {
+        let mut client = client(MockStream::default());
+        client.bufs.extend([b"abc".to_vec(), b"de".to_vec()]);
+        assert_eq!(client.write_limited(false, 4).unwrap(), 4);
+        assert_eq!(queued(&client), b"e");
+    }
+
+    #[test]
+    fn write_limited_never_splits_a_packet() {
+        let mut client = client(MockStream {
+            packet: true,
+            ..MockStream::default()
+        });
+        client.bufs.extend([b"abc".to_vec(), b"de".to_vec(), Vec::new()]);
+        // The first message goes whole even past the limit
+        assert_eq!(client.write_limited(false, 2).unwrap(), 3);
+        assert_eq!(client.write_limited(false, 2).unwrap(), 2);
+        assert_eq!(client.bufs.len(), 1);
+        // An empty message is written too
+        assert_eq!(client.write_limited(false, 3).unwrap(), 0);
+        assert!(client.bufs.is_empty());
+        assert_eq!(client.sock.write_calls, 3);
+    }
+
+    #[test]
+    fn interest_of_every_state() {
+        let (r, w) = (Some(Interest::READABLE), Some(Interest::WRITABLE));
+        let rw = Some(Interest::READABLE | Interest::WRITABLE);
+        let table = [
+            // queued, read_open, backpressured, zerocopy
+            ((false, false, false, false), None),
+            ((false, false, false, true), w),
+            ((false, false, true, false), None),
+            ((false, false, true, true), w),
+            ((false, true, false, false), r),
+            ((false, true, false, true), r),
+            ((false, true, true, false), None),
+            ((false, true, true, true), w),
+            ((true, false, false, false), w),
+            ((true, false, false, true), w),
+            ((true, false, true, false), w),
+            ((true, false, true, true), w),
+            ((true, true, false, false), rw),
+            ((true, true, false, true), rw),
+            ((true, true, true, false), w),
+            ((true, true, true, true), w),
+        ];
+        for ((queued, read_open, backpressured, zerocopy), interest) in table {
+            let state = InterestState {
+                queued,
+                read_open,
+                backpressured,
+                zerocopy,
+            };
+            assert_eq!(state.interest(), interest, "{:?}", state);
+        }
+    }
+
+    #[test]
+    fn queued_banner_registers_writable_too() {
+        let poll = mio::Poll::new().unwrap();
+        let mut client = client(MockStream::default());
+        client.bufs.push_back(b"hello\r\n".to_vec());
+        client.register(poll.registry(), Token(0)).unwrap();
+        assert_eq!(client.interest, Some(Interest::READABLE | Interest::WRITABLE));
+        client.write(false).unwrap();
+        assert!(client.reregister(poll.registry(), Token(0)).unwrap());
+        assert_eq!(client.interest, Some(Interest::READABLE));
+        assert_eq!((client.sock.register_calls, client.sock.reregister_calls), (1, 1));
+    }
+
+    #[test]
+    fn write_draining_the_queue_keeps_the_interest() {
+        let poll = mio::Poll::new().unwrap();
+        let mut client = client(MockStream::reading(vec![data(b"abc")]));
+        client.register(poll.registry(), Token(0)).unwrap();
+        client.read().unwrap();
+        client.write(false).unwrap();
+        assert!(!client.reregister(poll.registry(), Token(0)).unwrap());
+        assert_eq!(client.interest, Some(Interest::READABLE));
+        assert_eq!(client.sock.reregister_calls, 0);
+    }
+
+    #[test]
+    fn more_queued_while_writable_keeps_the_interest() {
+        let poll = mio::Poll::new().unwrap();
+        let mut client = client(MockStream {
+            reads: vec![data(b"abc")].into(),
+            writes: vec![Ok(1), error(io::ErrorKind::WouldBlock), Ok(1), error(io::ErrorKind::WouldBlock)].into(),
+            ..MockStream::default()
+        });
+        client.register(poll.registry(), Token(0)).unwrap();
+        client.read().unwrap();
+        client.write(false).unwrap();
+        assert!(client.reregister(poll.registry(), Token(0)).unwrap());
+        assert_eq!(client.interest, Some(Interest::READABLE | Interest::WRITABLE));
+
+        // Another partial flush, then more input
+        client.write(false).unwrap();
+        assert!(!client.reregister(poll.registry(), Token(0)).unwrap());
+        client.sock.reads.push_back(data(b"def"));
+        client.read().unwrap();
+        assert!(!client.reregister(poll.registry(), Token(0)).unwrap());
+        assert_eq!(client.sock.reregister_calls, 1);
+
+        client.write(false).unwrap();
+        assert!(client.reregister(poll.registry(), Token(0)).unwrap());
+        assert_eq!(client.interest, Some(Interest::READABLE));
+        assert_eq!(client.sock.written, b"abcdef");
+    }
+
+    #[test]
+    fn backpressure_drops_readable_until_the_queue_has_room() {
+        let poll = mio::Poll::new().unwrap();
+        let mut client = client(MockStream {
+            reads: vec![data(b"abcdef")].into(),
+            writes: vec![error(io::ErrorKind::WouldBlock)].into(),
+            ..MockStream::default()
+        });
+        client.max_queued = Some(4);
+        client.overflow = Overflow::Backpressure;
+        client.register(poll.registry(), Token(0)).unwrap();
+        client.read().unwrap();
+        client.write(false).unwrap();
+        client.reregister(poll.registry(), Token(0)).unwrap();
+        assert_eq!(client.interest, Some(Interest::WRITABLE));
+
+        client.write(false).unwrap();
+        client.reregister(poll.registry(), Token(0)).unwrap();
+        assert_eq!(client.interest, Some(Interest::READABLE));
+        assert_eq!(client.sock.reregister_calls, 2);
+    }
+
+    #[test]
+    fn read_owned_hands_out_a_queue_worth_per_call() {
+        let mut client = client(MockStream {
+            reads: vec![data(b"abcd"), data(b"efgh"), data(b"ij")].into(),
+            ..MockStream::default()
+        });
+        client.max_queued = Some(6);
+        client.overflow = Overflow::Disconnect;
+        let mut handed = Vec::new();
+        assert_eq!(client.read_owned(&mut |bytes| handed.push(bytes)).unwrap(), Some(8));
+        assert_eq!(handed, [b"abcd".to_vec(), b"efgh".to_vec()]);
+        assert!(client.read_paused());
+        assert!(!client.can_resume_reading());
+
+        // Until the reactor takes them back on the next call
+        client.handed_out = 0;
+        assert!(client.can_resume_reading());
+        assert_eq!(client.read_owned(&mut |bytes| handed.push(bytes)).unwrap(), Some(2));
+        assert_eq!(handed[2], b"ij");
+        assert!(!client.read_paused());
+        assert!(!client.overflowed);
+    }
+
+    // Echoes `messages` a readable event each as the reactor does, reading,
+    // writing, then updating the interest, and returns the client once
+    // the writable events the partial writes ask for are handled too
+    fn echo_events(messages: usize, writes: Vec<io::Result<usize>>, short_read_drained: bool) -> Client<MockStream> {
+        let poll = mio::Poll::new().unwrap();
+        let mut client = client(MockStream::writing(writes));
+        client.short_read_drained = short_read_drained;
+        client.register(poll.registry(), Token(0)).unwrap();
+        for i in 0..messages {
+            client.sock.reads.push_back(data(format!("message {}", i).as_bytes()));
+            client.read().unwrap();
+            client.write(false).unwrap();
+            client.reregister(poll.registry(), Token(0)).unwrap();
+            while client.interest.is_some_and(|interest| interest.is_writable()) {
+                client.write(false).unwrap();
+                client.reregister(poll.registry(), Token(0)).unwrap();
+            }
+        }
+        client
+    }
+
+    #[test]
+    fn steady_echo_takes_a_read_and_a_write_per_message() {
+        let client = echo_events(10, Vec::new(), true);
+        assert_eq!(client.sock.read_calls, 10);
+        assert_eq!(client.sock.write_calls, 10);
+        // Readable from the start, and readable only all along
+        assert_eq!(client.sock.register_calls, 1);
+        assert_eq!(client.sock.reregister_calls, 0);
+        let messages: String = (0..10).map(|i| format!("message {}", i)).collect();
+        assert_eq!(client.sock.written, messages.as_bytes());
+    }
+
+    #[test]
+    fn steady_echo_confirms_the_drain_without_the_heuristic() {
+        let client = echo_events(10, Vec::new(), false);
+        assert_eq!(client.sock.read_calls, 20);
+        assert_eq!(client.sock.write_calls, 10);
+        assert_eq!(client.sock.reregister_calls, 0);
+    }
+
+    #[test]
+    fn partial_write_reregisters_there_and_back_once() {
+        // The third message is written in two goes
+        let writes = vec![Ok(9), Ok(9), Ok(4), error(io::ErrorKind::WouldBlock), Ok(5)];
+        let client = echo_events(5, writes, true);
+        assert_eq!(client.sock.read_calls, 5);
+        // The blocked write, and the one the writable event made
+        assert_eq!(client.sock.write_calls, 7);
+        assert_eq!(client.sock.reregister_calls, 2);
+        assert_eq!(client.sock.deregister_calls, 0);
+        assert_eq!(client.sock.written, b"message 0message 1message 2message 3message 4");
+    }
+
+    #[test]
+    fn write_event_with_nothing_queued_writes_nothing() {
+        let mut client = echo_events(1, Vec::new(), true);
+        client.write(false).unwrap();
+        client.write(true).unwrap();
+        assert_eq!(client.sock.write_calls, 1);
+    }
+
+    // A client reading `reads` and writing in `writes`, the handler
+    // replying to each read with its action from `actions`
+    fn handled(reads: &[&[u8]], writes: Vec<io::Result<usize>>, actions: Vec<Action>) -> Client<MockStream> {
+        let mut client = client(MockStream {
+            reads: reads.iter().map(|read| data(read)).collect(),
+            writes: writes.into(),
+            ..MockStream::default()
+        });
+        let mut actions = VecDeque::from(actions);
+        let len = client.read_handled(&mut |_| actions.pop_front().expect("handler called again")).unwrap();
+        // Every read is taken, handled or not
+        assert_eq!(len, Some(reads.iter().map(|read| read.len()).sum()));
+        client
+    }
+
+    #[test]
+    fn reply_is_written_over_partial_writes() {
+        let writes = vec![Ok(2), error(io::ErrorKind::WouldBlock), Ok(1), error(io::ErrorKind::WouldBlock)];
+        let actions = vec![Action::Reply(b"ABC".to_vec()), Action::Reply(b"DE".to_vec())];
+        let mut client = handled(&[b"abc", b"de"], writes, actions);
+        assert_eq!(client.write(false).unwrap(), 2);
+        assert_eq!(client.write(false).unwrap(), 1);
+        assert_eq!(queued(&client), b"DE");
+        assert_eq!(client.write(false).unwrap(), 2);
+        assert_eq!(client.sock.written, b"ABCDE");
+        assert!(!client.done());
+    }
+
+    #[test]
+    fn reply_then_close_is_done_once_written() {
+        let writes = vec![Ok(1), error(io::ErrorKind::WouldBlock), Ok(1), error(io::ErrorKind::WouldBlock)];
+        // What follows the close is read and dropped
+        let mut client = handled(&[b"bye", b"more"], writes, vec![Action::ReplyThenClose(b"BYE".to_vec())]);
+        assert!(client.closing);
+        assert_eq!(queued(&client), b"BYE");
+        client.write(false).unwrap();
+        assert!(!client.done());
+        client.write(false).unwrap();
+        assert!(!client.done());
+        client.write(false).unwrap();
+        assert_eq!(client.sock.written, b"BYE");
+        assert!(client.done());
+    }
+
+    #[test]
+    fn close_now_stops_reading_and_leaves_the_queue_unwritten() {
+        let mut client = client(MockStream {
+            reads: vec![data(b"one"), data(b"two"), data(b"three")].into(),
+            writes: vec![Ok(1), error(io::ErrorKind::WouldBlock)].into(),
+            ..MockStream::default()
+        });
+        client.read_handled(&mut |data| Action::Reply(data.to_ascii_uppercase())).unwrap();
+        client.write(false).unwrap();
+        assert_eq!(queued(&client), b"NETWOTHREE");
+
+        client.sock.reads.push_back(data(b"quit"));
+        client.sock.reads.push_back(data(b"unread"));
+        let mut actions = VecDeque::from(vec![Action::CloseNow]);
+        client.read_handled(&mut |_| actions.pop_front().expect("handler called again")).unwrap();
+        assert!(client.aborted);
+        assert!(!client.closing);
+        // Closed as it is, nothing more written
+        assert_eq!(client.sock.reads.len(), 1);
+        assert_eq!(queued(&client), b"NETWOTHREE");
+        assert_eq!(client.sock.written, b"O");
+    }
+
+    #[test]
+    fn silence_flushes_what_was_queued_and_keeps_reading() {
+        let writes = vec![Ok(1), error(io::ErrorKind::WouldBlock)];
+        let actions = vec![Action::Reply(b"ONE".to_vec()), Action::Silence];
+        let mut client = handled(&[b"one", b"mute", b"three"], writes, actions);
+        assert!(client.muted);
+        assert_eq!(queued(&client), b"ONE");
+        client.write(false).unwrap();
+        client.write(false).unwrap();
+        assert_eq!(client.sock.written, b"ONE");
+
+        client.sock.reads.push_back(data(b"four"));
+        let len = client.read_handled(&mut |_| panic!("handler called once silenced")).unwrap();
+        assert_eq!(len, Some(4));
+        assert!(client.bufs.is_empty());
+        assert!(!client.done());
+    }
+
+    // What a scripted read or write does besides moving data
+    #[derive(Clone, Copy, Debug)]
+    enum Step {
+        Bytes(usize),
+        WouldBlock,
+        Interrupted,
+    }
+
+    fn steps() -> impl Strategy<Value = Vec<Step>> {
+        let step = prop_oneof![
+            3 => (1..300usize).prop_map(Step::Bytes),
+            1 => Just(Step::WouldBlock),
+            1 => Just(Step::Interrupted),
+        ];
+        prop::collection::vec(step, 0..40)
+    }
+
+    // The payload cut as `steps` says, the rest in one read, then the end
+    // of the stream
+    fn script_reads(payload: &[u8], steps: &[Step]) -> VecDeque<io::Result<Vec<u8>>> {
+        let mut reads = VecDeque::new();
+        let mut rest = payload;
+        for &step in steps {
+            match step {
+                Step::Bytes(len) if !rest.is_empty() => {
+                    let (chunk, after) = rest.split_at(len.min(rest.len()));
+                    reads.push_back(data(chunk));
+                    rest = after;
+                }
+                Step::Bytes(_) => {}
+                Step::WouldBlock => reads.push_back(error(io::ErrorKind::WouldBlock)),
+                Step::Interrupted => reads.push_back(error(io::ErrorKind::Interrupted)),
+            }
+        }
+        if !rest.is_empty() {
+            reads.push_back(data(rest));
+        }
+        reads.push_back(data(b""));
+        reads
+    }
+
+    fn script_writes(steps: &[Step]) -> VecDeque<io::Result<usize>> {
+        let write = |&step: &Step| match step {
+            Step::Bytes(len) => Ok(len),
+            Step::WouldBlock => error(io::ErrorKind::WouldBlock),
+            Step::Interrupted => error(io::ErrorKind::Interrupted),
+        };
+        steps.iter().map(write).collect()
+    }
+
+    proptest! {
+        #[test]
+        fn echo_loses_and_duplicates_nothing(
+            payload in prop::collection::vec(any::<u8>(), 0..2000),
+            reads in steps(),
+            writes in steps(),
+            read_buf_size in 1..2048usize,
+            max_write_chunk in prop::option::of(1..100usize),
+            short_read_drained in any::<bool>(),
+            pause in any::<bool>(),
+        ) {
+            let mut client = client(MockStream {
+                reads: script_reads(&payload, &reads),
+                writes: script_writes(&writes),
+                ..MockStream::default()
+            });
+            client.read_buf_size = read_buf_size;
+            client.max_write_chunk = max_write_chunk;
+            client.short_read_drained = short_read_drained;
+
+            let mut eof = false;
+            for _ in 0..100_000 {
+                if eof && client.bufs.is_empty() {
+                    break;
+                }
+                if !eof {
+                    eof = client.read().unwrap().is_none();
+                }
+                client.write(pause).unwrap();
+
+                let read = payload.len() - client.sock.reads.iter().flatten().map(Vec::len).sum::<usize>();
+                let written = client.sock.written.len();
+                prop_assert_eq!(&client.sock.written[..], &payload[..written]);
+                prop_assert_eq!(queued(&client), &payload[written..read]);
+                prop_assert_eq!(client.written, written as u64);
+                prop_assert!(client.pos < client.bufs.front().map_or(1, Vec::len));
+            }
+            prop_assert!(eof && client.bufs.is_empty(), "never done");
+            prop_assert_eq!(client.sock.written, payload);
+        }
+    }
+}
@@ -0,0 +1,2056 @@
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::LevelFilter;
+
+use crate::client::DEFAULT_BUF_SIZE;
+use crate::dump::DEFAULT_DUMP_LIMIT;
+use crate::reactor::{EVENTS_CAPACITY, MAX_CLIENTS};
+use crate::stats::MAX_LISTENERS;
+use crate::Error;
+
+/// Largest `Config::udp_batch`, the kernel's UIO_MAXIOV.
+const MAX_UDP_BATCH: usize = 1024;
+
+/// What drives the sockets of a `Server`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Backend {
+    /// The mio event loop, supporting every option.
+    Mio,
+    /// Experimental io_uring loop, needs the `io-uring` feature on Linux.
+    /// It serves TCP only, with a subset of the options.
+    Uring,
+}
+
+/// What a `Server` answers with.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Mode {
+    /// Every byte read, as is.
+    Echo,
+    /// An HTTP/1.1 response to every request, stream transports only.
+    Http,
+    /// Every line, once complete, stream transports only.
+    Line,
+    /// Every message prefixed by its length, 4 bytes big-endian, once
+    /// complete, stream transports only.
+    Length,
+}
+
+/// What happens to a client's data over `Config::max_queued`.
+///
+/// Drops keep messages whole: a frame in the line and length modes, a
+/// packet on packet transports, what a single read returned otherwise.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum Overflow {
+    /// Stop reading the client until its queue drains.
+    Backpressure,
+    /// Drop the message that doesn't fit.
+    DropNewest,
+    /// Drop the oldest messages not being written to make room.
+    DropOldest,
+    /// Close the client.
+    Disconnect,
+}
+
+/// Line format of `Config::access_log`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum AccessLogFormat {
+    /// Space separated, the close reason quoted.
+    Text,
+    /// Comma separated, with a header line at the top of the file.
+    Csv,
+}
+
+/// Whether TLS clients must present a certificate, see
+/// `Config::tls_client_ca`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum ClientAuth {
+    /// A client without a valid certificate fails the handshake.
+    Required,
+    /// Every client is let through, those without a valid certificate
+    /// being logged as unverified.
+    Optional,
+}
+
+/// Syslog daemon receiving the log, see `Config::log_syslog`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum SyslogTarget {
+    /// Local datagram socket, usually `/dev/log`, Unix only.
+    Unix(PathBuf),
+    /// `HOST:PORT` of a daemon listening on UDP.
+    Udp(String),
+}
+
+/// One more TCP listener, `[[listener]]` in a config file. Its clients
+/// get the settings set here, and those of `Config` for the rest.
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct ListenerConfig {
+    pub addr: String,
+    pub mode: Option<Mode>,
+    pub checksum: Option<bool>,
+    pub verify_checksum: Option<bool>,
+    pub telnet: Option<bool>,
+    /// Most clients of this listener at once, out of `Config::max_clients`.
+    pub max_clients: Option<usize>,
+    pub max_queued: Option<usize>,
+    pub overflow: Option<Overflow>,
+    /// PEM certificate chain and private key the clients of this listener
+    /// are served TLS with, in place of `Config::tls_cert` and
+    /// `Config::tls_key`.
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+}
+
+impl ListenerConfig {
+    pub fn new(addr: &str) -> ListenerConfig {
+        ListenerConfig {
+            addr: addr.to_string(),
+            mode: None,
+            checksum: None,
+            verify_checksum: None,
+            telnet: None,
+            max_clients: None,
+            max_queued: None,
+            overflow: None,
+            tls_cert: None,
+            tls_key: None,
+        }
+    }
+}
+
+/// Server settings, usually produced from the command line.
+///
+/// With the `serde` feature it can also be deserialized, missing fields
+/// taking their `Default` value.
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct Config {
+    /// TCP listen address.
+    pub listen: Option<String>,
+    /// UDP listen address, may share the port number of the TCP one.
+    pub udp: Option<String>,
+    /// Datagrams received and replies sent per syscall over UDP, with
+    /// recvmmsg and sendmmsg, 64 by default. Each takes a syscall of its
+    /// own with 1, and everywhere but on Linux. The socket keeps a 64k
+    /// buffer per datagram of a batch.
+    pub udp_batch: usize,
+    /// Reads the first 8 bytes of every UDP datagram as a big-endian
+    /// sequence number of its sender, to count the datagrams lost,
+    /// duplicated and reordered on the way in. Datagrams are echoed
+    /// unchanged, shorter ones counted as malformed.
+    pub udp_sequence: bool,
+    /// How long a UDP source stays quiet before the summary of its
+    /// sequence numbers is logged and it is forgotten, 30s by default.
+    pub udp_source_idle: Duration,
+    /// vsock port to listen on for any CID, needs the `vsock` feature.
+    pub vsock_port: Option<u32>,
+    /// SOCK_SEQPACKET Unix socket path, Linux only.
+    pub unix_seqpacket: Option<String>,
+    /// SOCK_STREAM Unix socket path, Linux only. Clients are logged by
+    /// their pid and uid.
+    pub unix_stream: Option<String>,
+    /// SCTP listen address, needs the `sctp` feature. Every message is
+    /// echoed on the stream it came in on.
+    pub sctp: Option<String>,
+    /// Named pipe to serve as `\\.\pipe\NAME`, Windows only.
+    pub pipe_name: Option<String>,
+    /// PEM certificate chain to terminate TLS with on the TCP listeners,
+    /// needs the `tls` feature and `tls_key`. Clients are read and echoed
+    /// the decrypted data.
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key of `tls_cert`.
+    pub tls_key: Option<PathBuf>,
+    /// PEM certificates of the CAs the TLS clients' certificates must be
+    /// issued by, asking every client for one. The subject and
+    /// alternative names of a client's certificate are logged once the
+    /// handshake is done, and in the state dump and `access_log`. A
+    /// handshake failing, e.g. on a missing certificate, closes the
+    /// client as `CloseReason::Handshake`.
+    pub tls_client_ca: Option<PathBuf>,
+    pub tls_client_auth: ClientAuth,
+    /// PEM certificate chain to terminate DTLS with on `udp`, needs the
+    /// `dtls` feature and `dtls_key`. Every peer address gets a session
+    /// of its own, its datagrams echoed encrypted once the handshake is
+    /// done. Handshakes start with a cookie exchange, so a spoofed address
+    /// is only ever sent a HelloVerifyRequest.
+    pub dtls_cert: Option<PathBuf>,
+    /// PEM private key of `dtls_cert`.
+    pub dtls_key: Option<PathBuf>,
+    /// PEM certificates of the CAs the DTLS peers' certificates must be
+    /// issued by, asking every peer for one.
+    pub dtls_client_ca: Option<PathBuf>,
+    /// How long a DTLS session stays quiet before it is dropped, 60s by
+    /// default. A session still shaking hands gets 10s at most.
+    pub dtls_session_idle: Duration,
+    /// Most DTLS sessions kept at once, 1024 by default. Past it, a new
+    /// peer takes the place of a session still shaking hands, or else of
+    /// the least recently used one.
+    pub dtls_max_sessions: usize,
+    /// Serves QUIC on `udp` instead of echoing its datagrams, needs the
+    /// `quic` feature and `tls_cert`, presented in the handshakes along
+    /// with `tls_client_ca`. The data of every bidirectional stream is
+    /// echoed on the stream. A new address is sent a Retry first, so a
+    /// spoofed one never gets the handshake.
+    pub quic: bool,
+    /// Echoes the unreliable datagrams of the QUIC connections too, which
+    /// are refused otherwise.
+    pub quic_datagrams: bool,
+    /// ALPN protocols offered in the QUIC handshakes, e.g. `h3`, none by
+    /// default. A client offering protocols must find one of them here.
+    pub quic_alpn: Vec<String>,
+    /// More TCP listeners, each with its own mode, framing and limits.
+    #[cfg_attr(feature = "serde", serde(rename = "listener"))]
+    pub listeners: Vec<ListenerConfig>,
+    /// Caps every write syscall at this many bytes.
+    pub max_write_chunk: Option<usize>,
+    /// Pause between two capped chunks of the same client.
+    pub inter_chunk_delay: Option<Duration>,
+    pub mode: Mode,
+    /// Appends a CRC32 of each message to its echo, in the line and
+    /// length modes.
+    pub checksum: bool,
+    /// Closes the connection of a client whose message doesn't end with
+    /// the CRC32 of the rest, in the line and length modes.
+    pub verify_checksum: bool,
+    /// Strips telnet negotiation from the input, refusing every option,
+    /// and echoes lines with CR LF.
+    pub telnet: bool,
+    /// Prefixes every chunk echoed to a connection with a header, always
+    /// in this format, ASCII with single spaces and ending with a space:
+    ///
+    /// ```text
+    /// [srv ADDR conn ID seq N len L] PAYLOAD
+    /// ```
+    ///
+    /// - `ADDR` is the address of the listener that accepted the
+    ///   connection as bound, e.g. `10.0.0.1:7000`, or else as
+    ///   configured: the socket path, `vsock:PORT` or the pipe path.
+    /// - `ID` is the id of the connection, as in the logs, the admin
+    ///   socket and the access log.
+    /// - `N` counts the chunks of the connection from 1. A gap means the
+    ///   overflow policy dropped the chunks in between.
+    /// - `L` is the length of `PAYLOAD` in bytes, the header excluded.
+    ///
+    /// A chunk is the data of one read in the echo mode, one packet on
+    /// packet transports, or one line or message in the line and length
+    /// modes. There the header starts the frame: a line is echoed as the
+    /// header, the payload, the CRC if any and `\n`, a message as its
+    /// length prefix, the header, the payload and the CRC if any, the
+    /// prefix and the CRC covering the header too.
+    ///
+    /// The headers don't count as payload in the byte counts or against
+    /// `max_queued`. Not for UDP or the http mode.
+    pub annotate: bool,
+    /// Lets stream clients of the plain echo mode pick a transform with
+    /// their first line: `MODE upper` echoes ASCII letters in upper case,
+    /// `MODE delay MS` holds each burst of echo MS milliseconds (at most
+    /// a minute), `MODE echo` keeps the plain echo. The server replies
+    /// `OK`, or `ERR` and a reason and keeps the plain echo, each on a
+    /// line of its own. A first line not starting with `MODE ` is echoed
+    /// as data, and only the first line is looked at.
+    pub allow_mode_negotiation: bool,
+    /// Sent to every client right after the connection is established.
+    pub banner: Option<Vec<u8>>,
+    /// Sends `heartbeat_payload` to clients silent for this long.
+    pub heartbeat_interval: Option<Duration>,
+    pub heartbeat_payload: Vec<u8>,
+    /// Keeps retrying a bind to a busy address for this long.
+    pub bind_retry: Option<Duration>,
+    /// Binds `listen`, `listeners` and `udp` with IP_FREEBIND, Linux only,
+    /// so that an address not configured on any interface yet, e.g. a
+    /// virtual IP held by another host until failover, can be bound
+    /// anyway. Such a listener is only reachable once the address shows
+    /// up.
+    pub freebind: bool,
+    /// How many times in a row binding a TCP listener again may fail once
+    /// it broke at runtime, e.g. its interface went down and up, before
+    /// the server gives up and drains. 0 drains on the first breakage.
+    pub listener_rebind_attempts: u32,
+    pub backend: Backend,
+    /// Most verbose log level printed by the binary.
+    pub log_level: LevelFilter,
+    /// File the binary logs to instead of stdout, rotated to `PATH.1` once
+    /// it would grow past `log_rotate_size` bytes, and on SIGHUP on Unix.
+    /// `log_keep` rotated files are kept.
+    pub log_file: Option<PathBuf>,
+    pub log_rotate_size: Option<u64>,
+    pub log_keep: usize,
+    /// Syslog daemon the binary also logs to, as RFC 5424 messages or RFC
+    /// 3164 ones with `syslog_rfc3164`. `syslog_facility` is the facility
+    /// code, 3 (daemon) by default.
+    pub log_syslog: Option<SyslogTarget>,
+    pub syslog_facility: u8,
+    pub syslog_rfc3164: bool,
+    /// Confines `Server::run` to a seccomp allowlist, needs the `seccomp`
+    /// feature on Linux.
+    pub seccomp: bool,
+    /// Directory `Server::run` chroots into once the listeners are bound.
+    pub chroot: Option<PathBuf>,
+    /// Binary started on SIGUSR2, with the arguments of this process, to
+    /// take over the sockets of `listen`, `listeners`, `udp`, `health_addr`
+    /// and `admin_addr`. Once it serves, this server drains and stops; if it
+    /// exits or doesn't serve within 10s, this one keeps serving. Unix
+    /// only.
+    pub upgrade_binary: Option<PathBuf>,
+    /// Worker processes `run_config` starts instead of serving itself.
+    /// Each serves the sockets of `listen`, `listeners` and `udp`, bound
+    /// once by this process, which restarts workers that fail, drains
+    /// them on SIGTERM or SIGINT and sums up what they served. Unix only.
+    pub workers_processes: Option<usize>,
+    /// Registers the TCP listeners with EPOLLEXCLUSIVE, so that only one
+    /// of the processes sharing them, e.g. the `workers_processes`, is
+    /// woken per incoming connection instead of all of them. Linux 4.5 and
+    /// later, elsewhere the listeners are registered as usual with a
+    /// warning. `LoopStats::empty_accepts` counts the wakeups that found
+    /// nothing to accept.
+    pub exclusive_accept: bool,
+    /// Most clients served at once.
+    pub max_clients: usize,
+    /// Fails at startup instead of serving fewer clients when the open
+    /// file limit can't be raised far enough.
+    pub strict_limits: bool,
+    /// statsd agent the stats are pushed to every `stats_interval`.
+    pub statsd: Option<String>,
+    pub statsd_prefix: String,
+    pub stats_interval: Duration,
+    /// Iterations of the mio event loop taking longer than this, from the
+    /// poll returning to the next poll, are logged with the phase that
+    /// took the most of them: accept, read, write, timers or handler.
+    /// Zero turns the warning off.
+    pub stall_threshold: Duration,
+    /// Payload bytes hex dumped per read and write at trace level.
+    pub dump_limit: usize,
+    /// pcap file recording every read and write, rotated to `PATH.1` once
+    /// it grows past `capture_max_size` bytes.
+    pub capture: Option<PathBuf>,
+    pub capture_max_size: u64,
+    /// Address getting a copy of everything the stream clients send, over
+    /// one TCP connection per client. What it doesn't take in time is
+    /// dropped and counted, the echo never waits for it.
+    pub mirror: Option<String>,
+    /// File getting a line per closed connection: when it was accepted,
+    /// the peer, how long it lasted, bytes in and out, why it closed and
+    /// its id. Reopened on SIGHUP, within the chroot if any.
+    pub access_log: Option<PathBuf>,
+    pub access_log_format: AccessLogFormat,
+    /// Looks up where each accepted TCP connection was originally headed
+    /// with SO_ORIGINAL_DST, for servers receiving traffic redirected by
+    /// e.g. an iptables REDIRECT rule, and adds it to the connection
+    /// established line, the state dump and the `access_log`. Nothing is
+    /// added for connections that weren't redirected, nor anywhere but on
+    /// Linux.
+    pub log_original_dst: bool,
+    /// Looks up the host name of each peer by reverse DNS on a thread of
+    /// its own, logging it next to the address once known, e.g.
+    /// `host (10.3.7.112:53122)`. Connections are logged without waiting
+    /// for the name, those without one keep the bare address. Linux only.
+    pub resolve_peers: bool,
+    /// How long `resolve_peers` caches a name, or the lack of one.
+    pub resolve_ttl: Duration,
+    /// Drains and stops the server once it has run this long.
+    pub duration: Option<Duration>,
+    /// How long draining waits for the clients to leave before closing
+    /// them.
+    pub drain_timeout: Duration,
+    /// Drains and stops the server once it has accepted this many
+    /// connections.
+    pub max_connections_total: Option<u64>,
+    /// Stops the server once it has had no client for this long.
+    pub exit_when_idle: Option<Duration>,
+    /// Closes stream clients that haven't sent a single byte this long
+    /// after being accepted, e.g. port scans.
+    pub first_byte_timeout: Option<Duration>,
+    /// Closes stream clients that haven't sent anything for this long.
+    pub idle_timeout: Option<Duration>,
+    /// HTTP health check address, answering 200 while serving and 503
+    /// while draining.
+    pub health_addr: Option<String>,
+    /// Admin socket address, taking line commands such as `kick ID`.
+    /// Anyone who can connect can disconnect clients.
+    pub admin_addr: Option<String>,
+    /// Caps the echo rate of all the clients together, in bytes per
+    /// second.
+    pub global_rate: Option<u64>,
+    /// Written to connections refused at capacity before closing them.
+    pub busy_message: Option<Vec<u8>>,
+    /// Connections accepted at capacity wait in a queue this long, oldest
+    /// first, for a client to leave, rather than being refused at once.
+    /// Refused when it's full or once they have waited
+    /// `pending_timeout`.
+    pub pending_queue: Option<usize>,
+    pub pending_timeout: Duration,
+    /// Addresses refused for a while from startup, as read from
+    /// `--ban-file`.
+    pub bans: Vec<(IpAddr, Duration)>,
+    /// File of networks whose connections are refused, one address or
+    /// CIDR network per line, blank lines and `#` comments skipped. It is
+    /// reread on SIGHUP and when its modification time changes, checked
+    /// every second, within the chroot if any; a version with a line that
+    /// doesn't parse is ignored.
+    pub deny_file: Option<PathBuf>,
+    /// Also closes the connected clients a reload of `deny_file` denies.
+    pub deny_existing: bool,
+    /// Bytes a client may have queued for writing, past which `overflow`
+    /// applies.
+    pub max_queued: Option<usize>,
+    pub overflow: Overflow,
+    /// Holds a client's echo until it has been silent this long, then
+    /// sends it in one write, or as soon as `quiesce_max` bytes are held,
+    /// which can't exceed `max_queued`.
+    pub quiesce: Option<Duration>,
+    pub quiesce_max: usize,
+    /// Most bytes written to one client per round of events with the mio
+    /// backend. A client with more to write is revisited after the
+    /// clients with events in the same round, without waiting for a new
+    /// readiness event, so a deep queue can't hold up the others.
+    pub write_budget: usize,
+    /// Capacity of the buffers a stream client's input is queued in, which
+    /// reads fill before another is allocated, and the most a read takes
+    /// while nothing is queued or when decoding. Larger ones take bulk
+    /// transfers in fewer reads and writes, smaller ones hold less memory
+    /// per client. The mio backend only.
+    pub read_buf_size: usize,
+    /// Most readiness events taken from one poll of the mio backend.
+    pub events_capacity: usize,
+    /// Takes a read returning less than asked for as having drained the
+    /// socket of a stream client, instead of reading again until it would
+    /// block. This saves a syscall per readiness event in the common case
+    /// of small messages. It relies on the socket signaling readiness
+    /// again when more data comes in after the short read, as TCP and
+    /// Unix stream sockets do under epoll and kqueue.
+    pub short_read_drained: bool,
+    /// Gives back the memory a client's queue and decoder grew to once
+    /// they have held at most `shrink_watermark` bytes for this long, and
+    /// the client slots and spare queues once mostly unused for as long.
+    pub shrink_after: Duration,
+    pub shrink_watermark: usize,
+    /// SO_RCVBUF and SO_SNDBUF of the TCP listeners and of every accepted
+    /// connection, before the kernel adjusts them.
+    pub so_rcvbuf: Option<usize>,
+    pub so_sndbuf: Option<usize>,
+    /// TCP_USER_TIMEOUT of accepted connections, Linux only: data left
+    /// unacknowledged this long closes the connection. The server never
+    /// sets SO_LINGER, so the kernel keeps sending what a closed socket
+    /// left behind, for at most this long too.
+    pub tcp_user_timeout: Option<Duration>,
+    /// TCP_DEFER_ACCEPT of the TCP listeners, Linux only: connections are
+    /// only accepted once they sent something, or kept silent for about
+    /// this long, in whole seconds. The server can't speak first then, so
+    /// it excludes `banner`.
+    pub defer_accept: Option<Duration>,
+    /// Before blocking in the poll, keeps polling without blocking for up
+    /// to this long, so events arriving meanwhile are handled without the
+    /// wakeup latency of the blocking poll. This keeps a core busy while
+    /// the server is idle: meant for latency benchmarks, off by default.
+    /// `LoopStats::spun_events` counts the events found this way.
+    pub spin: Option<Duration>,
+    /// SO_BUSY_POLL of accepted TCP connections, Linux only: how long a
+    /// read with nothing received busy polls the device queue, in whole
+    /// microseconds. Raising it past `net.core.busy_read` needs
+    /// CAP_NET_ADMIN, a failure to set it is only logged.
+    pub busy_poll: Option<Duration>,
+    /// Queued buffers of TCP connections at least this large are written
+    /// with MSG_ZEROCOPY, Linux only with the `zerocopy` feature: the
+    /// kernel sends from the buffer itself instead of a copy, and the
+    /// buffer is kept until the kernel reports it done with it, a closed
+    /// connection's socket being kept until then too. Reads fill buffers
+    /// of this size, up to 1m, for echoes to reach it. Smaller buffers
+    /// are written as usual, and so is everything on a kernel without
+    /// SO_ZEROCOPY (before 4.14). Over the loopback the kernel copies the
+    /// data anyway.
+    pub zerocopy: Option<usize>,
+}
+
+impl Default for Config {
+    /// No listener, everything else as on the command line.
+    fn default() -> Config {
+        Config {
+            listen: None,
+            udp: None,
+            udp_batch: 64,
+            udp_sequence: false,
+            udp_source_idle: Duration::from_secs(30),
+            vsock_port: None,
+            unix_seqpacket: None,
+            unix_stream: None,
+            sctp: None,
+            pipe_name: None,
+            tls_cert: None,
+            tls_key: None,
+            tls_client_ca: None,
+            tls_client_auth: ClientAuth::Required,
+            dtls_cert: None,
+            dtls_key: None,
+            dtls_client_ca: None,
+            dtls_session_idle: Duration::from_secs(60),
+            dtls_max_sessions: 1024,
+            quic: false,
+            quic_datagrams: false,
+            quic_alpn: Vec::new(),
+            listeners: Vec::new(),
+            max_write_chunk: None,
+            inter_chunk_delay: None,
+            mode: Mode::Echo,
+            checksum: false,
+            verify_checksum: false,
+            telnet: false,
+            annotate: false,
+            allow_mode_negotiation: false,
+            banner: None,
+            heartbeat_interval: None,
+            heartbeat_payload: b"\n".to_vec(),
+            bind_retry: None,
+            freebind: false,
+            listener_rebind_attempts: 10,
+            backend: Backend::Mio,
+            log_level: LevelFilter::Info,
+            log_file: None,
+            log_rotate_size: None,
+            log_keep: 5,
+            log_syslog: None,
+            syslog_facility: 3,
+            syslog_rfc3164: false,
+            seccomp: false,
+            chroot: None,
+            upgrade_binary: None,
+            workers_processes: None,
+            exclusive_accept: false,
+            max_clients: MAX_CLIENTS,
+            strict_limits: false,
+            statsd: None,
+            statsd_prefix: "mio_echo_server".to_string(),
+            stats_interval: Duration::from_secs(10),
+            stall_threshold: Duration::from_millis(100),
+            dump_limit: DEFAULT_DUMP_LIMIT,
+            capture: None,
+            capture_max_size: 64 << 20,
+            mirror: None,
+            access_log: None,
+            access_log_format: AccessLogFormat::Text,
+            log_original_dst: false,
+            resolve_peers: false,
+            resolve_ttl: Duration::from_secs(300),
+            duration: None,
+            drain_timeout: Duration::from_secs(5),
+            max_connections_total: None,
+            exit_when_idle: None,
+            first_byte_timeout: None,
+            idle_timeout: None,
+            health_addr: None,
+            admin_addr: None,
+            bans: Vec::new(),
+            deny_file: None,
+            deny_existing: false,
+            global_rate: None,
+            busy_message: None,
+            pending_queue: None,
+            pending_timeout: Duration::from_secs(2),
+            max_queued: None,
+            overflow: Overflow::Backpressure,
+            quiesce: None,
+            quiesce_max: 64 << 10,
+            write_budget: 4 << 20,
+            read_buf_size: DEFAULT_BUF_SIZE,
+            events_capacity: EVENTS_CAPACITY,
+            short_read_drained: false,
+            shrink_after: Duration::from_secs(10),
+            shrink_watermark: 16 << 10,
+            so_rcvbuf: None,
+            so_sndbuf: None,
+            tcp_user_timeout: None,
+            defer_accept: None,
+            spin: None,
+            busy_poll: None,
+            zerocopy: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn new(addr: &str) -> Config {
+        Config {
+            listen: Some(addr.to_string()),
+            ..Config::default()
+        }
+    }
+
+    /// Parses the command line arguments, without the program name.
+    pub fn from_args<I>(args: I) -> Result<Config, Error>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let args: Vec<String> = args.into_iter().collect();
+        // The file is what the other options apply to, wherever it is given
+        let mut config = match args.iter().position(|arg| arg == "--config") {
+            Some(i) => {
+                let path = args.get(i + 1).ok_or_else(|| Error::config("missing value for --config"))?;
+                Config::from_file(path)?
+            }
+            None => Config::default(),
+        };
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| Error::config(format!("missing value for {}", name)))
+            };
+            match arg.as_str() {
+                "--max-write-chunk" => config.max_write_chunk = Some(parse_size(&value(&arg)?)?),
+                "--inter-chunk-delay" => {
+                    config.inter_chunk_delay = Some(parse_duration(&value(&arg)?)?);
+                }
+                "--quiesce" => config.quiesce = Some(parse_duration(&value(&arg)?)?),
+                "--quiesce-max" => config.quiesce_max = parse_size(&value(&arg)?)?,
+                "--write-budget" => config.write_budget = parse_size(&value(&arg)?)?,
+                "--read-buf-size" => config.read_buf_size = parse_size(&value(&arg)?)?,
+                "--short-read-drained" => config.short_read_drained = true,
+                "--shrink-after" => config.shrink_after = parse_duration(&value(&arg)?)?,
+                "--shrink-watermark" => config.shrink_watermark = parse_size(&value(&arg)?)?,
+                "--mode" => config.mode = parse_mode(&value(&arg)?)?,
+                "--checksum" => {
+                    config.checksum = match &value(&arg)?[..] {
+                        "crc32" => true,
+                        checksum => return Err(Error::config(format!("unsupported checksum: {}", checksum))),
+                    };
+                }
+                "--verify-checksum" => config.verify_checksum = true,
+                "--telnet" => config.telnet = true,
+                "--annotate" => config.annotate = true,
+                "--allow-mode-negotiation" => config.allow_mode_negotiation = true,
+                "--banner" => {
+                    config.banner = Some(unescape(&value(&arg)?)?);
+                }
+                "--banner-file" => {
+                    let path = value(&arg)?;
+                    let banner = fs::read(&path).map_err(|source| Error::File {
+                        path: path.into(),
+                        source,
+                    })?;
+                    config.banner = Some(banner);
+                }
+                "--heartbeat-interval" => {
+                    config.heartbeat_interval = Some(parse_duration(&value(&arg)?)?);
+                }
+                "--heartbeat-payload" => {
+                    config.heartbeat_payload = unescape(&value(&arg)?)?;
+                }
+                "--bind-retry" => {
+                    config.bind_retry = Some(parse_duration(&value(&arg)?)?);
+                }
+                "--freebind" => config.freebind = true,
+                "--listener-rebind-attempts" => {
+                    let n = value(&arg)?;
+                    config.listener_rebind_attempts = n
+                        .parse()
+                        .map_err(|_| Error::config(format!("invalid rebind attempts: {}", n)))?;
+                }
+                "--so-rcvbuf" => config.so_rcvbuf = Some(parse_size(&value(&arg)?)?),
+                "--so-sndbuf" => config.so_sndbuf = Some(parse_size(&value(&arg)?)?),
+                "--tcp-user-timeout" => config.tcp_user_timeout = Some(parse_duration(&value(&arg)?)?),
+                "--spin" => config.spin = Some(parse_duration(&value(&arg)?)?),
+                "--busy-poll" => config.busy_poll = Some(parse_duration(&value(&arg)?)?),
+                "--zerocopy" => config.zerocopy = Some(parse_size(&value(&arg)?)?),
+                "--defer-accept" => config.defer_accept = Some(parse_duration(&value(&arg)?)?),
+                "--dump-limit" => config.dump_limit = parse_size(&value(&arg)?)?,
+                "--capture" => config.capture = Some(value(&arg)?.into()),
+                "--capture-max-size" => config.capture_max_size = parse_size(&value(&arg)?)? as u64,
+                "--mirror" => config.mirror = Some(value(&arg)?),
+                "--access-log" => config.access_log = Some(value(&arg)?.into()),
+                "--access-log-format" => {
+                    config.access_log_format = match &value(&arg)?[..] {
+                        "text" => AccessLogFormat::Text,
+                        "csv" => AccessLogFormat::Csv,
+                        format => return Err(Error::config(format!("invalid access log format: {}", format))),
+                    };
+                }
+                "--log-original-dst" => config.log_original_dst = true,
+                "--resolve-peers" => config.resolve_peers = true,
+                "--resolve-ttl" => config.resolve_ttl = parse_duration(&value(&arg)?)?,
+                "--backend" => {
+                    config.backend = match &value(&arg)?[..] {
+                        "mio" => Backend::Mio,
+                        "uring" => Backend::Uring,
+                        backend => return Err(Error::config(format!("invalid backend: {}", backend))),
+                    };
+                }
+                "--log-level" => {
+                    let level = value(&arg)?;
+                    config.log_level = level
+                        .parse()
+                        .map_err(|_| Error::config(format!("invalid log level: {}", level)))?;
+                }
+                "--log-file" => config.log_file = Some(value(&arg)?.into()),
+                "--log-rotate-size" => config.log_rotate_size = Some(parse_size(&value(&arg)?)? as u64),
+                "--log-keep" => {
+                    let n = value(&arg)?;
+                    config.log_keep = n
+                        .parse()
+                        .map_err(|_| Error::config(format!("invalid log keep count: {}", n)))?;
+                }
+                "--log-syslog" => config.log_syslog = Some(parse_syslog_target(&value(&arg)?)?),
+                "--syslog-facility" => config.syslog_facility = parse_facility(&value(&arg)?)?,
+                "--syslog-rfc3164" => config.syslog_rfc3164 = true,
+                "--max-clients" => {
+                    let n = value(&arg)?;
+                    config.max_clients = n
+                        .parse()
+                        .map_err(|_| Error::config(format!("invalid client count: {}", n)))?;
+                }
+                "--events-capacity" => {
+                    let n = value(&arg)?;
+                    config.events_capacity = n
+                        .parse()
+                        .map_err(|_| Error::config(format!("invalid event count: {}", n)))?;
+                }
+                "--max-connections-total" => {
+                    let n = value(&arg)?;
+                    let n = n
+                        .parse()
+                        .map_err(|_| Error::config(format!("invalid connection count: {}", n)))?;
+                    config.max_connections_total = Some(n);
+                }
+                "--global-rate" => config.global_rate = Some(parse_rate(&value(&arg)?)?),
+                "--notify-busy" => config.busy_message = Some(b"server busy\r\n".to_vec()),
+                "--busy-message" => config.busy_message = Some(unescape(&value(&arg)?)?),
+                "--pending-queue" => {
+                    let n = value(&arg)?;
+                    let n = n.parse().map_err(|_| Error::config(format!("invalid queue length: {}", n)))?;
+                    config.pending_queue = Some(n);
+                }
+                "--pending-timeout" => config.pending_timeout = parse_duration(&value(&arg)?)?,
+                "--max-queued" => config.max_queued = Some(parse_size(&value(&arg)?)?),
+                "--overflow" => config.overflow = parse_overflow(&value(&arg)?)?,
+                "--strict-limits" => config.strict_limits = true,
+                "--statsd" => config.statsd = Some(value(&arg)?),
+                "--statsd-prefix" => config.statsd_prefix = value(&arg)?,
+                "--stats-interval" => config.stats_interval = parse_duration(&value(&arg)?)?,
+                "--stall-threshold" => config.stall_threshold = parse_duration(&value(&arg)?)?,
+                "--duration" => config.duration = Some(parse_duration(&value(&arg)?)?),
+                "--exit-when-idle" => config.exit_when_idle = Some(parse_duration(&value(&arg)?)?),
+                "--first-byte-timeout" => config.first_byte_timeout = Some(parse_duration(&value(&arg)?)?),
+                "--idle-timeout" => config.idle_timeout = Some(parse_duration(&value(&arg)?)?),
+                "--drain-timeout" => config.drain_timeout = parse_duration(&value(&arg)?)?,
+                "--health-addr" => config.health_addr = Some(value(&arg)?),
+                "--admin-addr" => config.admin_addr = Some(value(&arg)?),
+                "--ban-file" => {
+                    let path = value(&arg)?;
+                    let text = fs::read_to_string(&path).map_err(|source| Error::File {
+                        path: PathBuf::from(&path),
+                        source,
+                    })?;
+                    let bans = parse_bans(&text).map_err(|e| Error::Invalid {
+                        path: path.into(),
+                        message: e.to_string(),
+                    })?;
+                    config.bans.extend(bans);
+                }
+                "--deny-file" => config.deny_file = Some(value(&arg)?.into()),
+                "--deny-existing" => config.deny_existing = true,
+                "--seccomp" => config.seccomp = true,
+                "--chroot" => config.chroot = Some(value(&arg)?.into()),
+                "--upgrade-binary" => config.upgrade_binary = Some(value(&arg)?.into()),
+                "--workers-processes" => {
+                    let n = value(&arg)?;
+                    let n = n.parse().map_err(|_| Error::config(format!("invalid process count: {}", n)))?;
+                    config.workers_processes = Some(n);
+                }
+                "--exclusive-accept" => config.exclusive_accept = true,
+                "--config" => {
+                    value(&arg)?;
+                }
+                "--listen" => config.listen = Some(value(&arg)?),
+                "--listener" => config.listeners.push(parse_listener(&value(&arg)?)?),
+                "--udp" => config.udp = Some(value(&arg)?),
+                "--udp-batch" => {
+                    let n = value(&arg)?;
+                    config.udp_batch = n.parse().map_err(|_| Error::config(format!("invalid batch size: {}", n)))?;
+                }
+                "--udp-sequence" => config.udp_sequence = true,
+                "--udp-source-idle" => config.udp_source_idle = parse_duration(&value(&arg)?)?,
+                "--unix-seqpacket" => config.unix_seqpacket = Some(value(&arg)?),
+                "--unix-stream" => config.unix_stream = Some(value(&arg)?),
+                "--sctp" => config.sctp = Some(value(&arg)?),
+                "--pipe-name" => config.pipe_name = Some(value(&arg)?),
+                "--tls-cert" => config.tls_cert = Some(value(&arg)?.into()),
+                "--tls-key" => config.tls_key = Some(value(&arg)?.into()),
+                "--tls-client-ca" => config.tls_client_ca = Some(value(&arg)?.into()),
+                "--tls-client-auth" => config.tls_client_auth = parse_client_auth(&value(&arg)?)?,
+                "--dtls-cert" => config.dtls_cert = Some(value(&arg)?.into()),
+                "--dtls-key" => config.dtls_key = Some(value(&arg)?.into()),
+                "--dtls-client-ca" => config.dtls_client_ca = Some(value(&arg)?.into()),
+                "--dtls-session-idle" => config.dtls_session_idle = parse_duration(&value(&arg)?)?,
+                "--quic" => config.quic = true,
+                "--quic-datagrams" => config.quic_datagrams = true,
+                "--quic-alpn" => config.quic_alpn = value(&arg)?.split(',').map(str::to_string).collect(),
+                "--dtls-max-sessions" => {
+                    let n = value(&arg)?;
+                    config.dtls_max_sessions =
+                        n.parse().map_err(|_| Error::config(format!("invalid session count: {}", n)))?;
+                }
+                "--vsock-port" => {
+                    let port = value(&arg)?;
+                    let port = port
+                        .parse()
+                        .map_err(|_| Error::config(format!("invalid vsock port: {}", port)))?;
+                    config.vsock_port = Some(port);
+                }
+                _ if arg.starts_with("--") => {
+                    return Err(Error::config(format!("unknown option: {}", arg)));
+                }
+                _ if config.listen.is_none() => config.listen = Some(arg),
+                _ => return Err(Error::config(format!("unexpected argument: {}", arg))),
+            }
+        }
+
+        if config.listener_count() == 0 {
+            return Err(Error::config("missing HOST:PORT"));
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Reads a TOML file of `Config` fields, e.g. `listen = "0.0.0.0:7"`,
+    /// missing ones taking their default, with a `[[listener]]` table per
+    /// listener. Durations are tables of `secs` and `nanos`. Needs the
+    /// `serde` feature. Not validated, see `validate`.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Config, Error> {
+        let path = path.as_ref();
+        #[cfg(feature = "serde")]
+        {
+            let text = fs::read_to_string(path).map_err(|source| Error::File {
+                path: path.into(),
+                source,
+            })?;
+            toml::from_str(&text).map_err(|e| Error::Invalid {
+                path: path.into(),
+                message: e.to_string().trim_end().to_string(),
+            })
+        }
+        #[cfg(not(feature = "serde"))]
+        {
+            Err(Error::config(format!("reading {} needs the serde feature", path.display())))
+        }
+    }
+
+    /// Rejects settings the server can't honour, naming the field at
+    /// fault.
+    pub fn validate(&self) -> Result<(), Error> {
+        let zero = Duration::from_secs(0);
+        if self.listener_count() == 0 {
+            return Err(Error::config("no listener configured"));
+        }
+        if self.pipe_name.is_some() && cfg!(not(windows)) {
+            return Err(Error::config("pipe_name is only supported on Windows"));
+        }
+        if self.tls_cert.is_some() || self.tls_key.is_some() {
+            if cfg!(not(feature = "tls")) {
+                return Err(Error::config("tls_cert needs the tls feature"));
+            }
+            if self.tls_cert.is_none() || self.tls_key.is_none() {
+                return Err(Error::config("tls_cert and tls_key go together"));
+            }
+            if self.listen.is_none() && self.listeners.is_empty() && !self.quic {
+                return Err(Error::config("tls_cert needs listen, listeners or quic"));
+            }
+            if self.busy_message.is_some() {
+                return Err(Error::config("busy_message would be sent in clear over tls"));
+            }
+            if self.short_read_drained {
+                return Err(Error::config("short_read_drained can't tell when tls records are drained"));
+            }
+        }
+        if self.tls_client_ca.is_some() && self.tls_cert.is_none() {
+            return Err(Error::config("tls_client_ca needs tls_cert"));
+        }
+        if self.tls_client_auth == ClientAuth::Optional && self.tls_client_ca.is_none() {
+            return Err(Error::config("tls_client_auth needs tls_client_ca"));
+        }
+        if self.dtls_cert.is_some() || self.dtls_key.is_some() {
+            if cfg!(not(feature = "dtls")) {
+                return Err(Error::config("dtls_cert needs the dtls feature"));
+            }
+            if self.dtls_cert.is_none() || self.dtls_key.is_none() {
+                return Err(Error::config("dtls_cert and dtls_key go together"));
+            }
+            if self.udp.is_none() {
+                return Err(Error::config("dtls_cert needs udp"));
+            }
+            if self.udp_sequence {
+                return Err(Error::config("udp_sequence can't read the sequence numbers of dtls datagrams"));
+            }
+        }
+        if self.quic {
+            if cfg!(not(feature = "quic")) {
+                return Err(Error::config("quic needs the quic feature"));
+            }
+            if self.udp.is_none() || self.tls_cert.is_none() {
+                return Err(Error::config("quic needs udp and tls_cert"));
+            }
+            if self.dtls_cert.is_some() {
+                return Err(Error::config("quic and dtls_cert can't share udp"));
+            }
+            if self.udp_sequence {
+                return Err(Error::config("udp_sequence can't read the sequence numbers of quic packets"));
+            }
+        }
+        if (self.quic_datagrams || !self.quic_alpn.is_empty()) && !self.quic {
+            return Err(Error::config("quic_datagrams and quic_alpn need quic"));
+        }
+        if self.dtls_client_ca.is_some() && self.dtls_cert.is_none() {
+            return Err(Error::config("dtls_client_ca needs dtls_cert"));
+        }
+        if self.dtls_session_idle == Duration::ZERO {
+            return Err(Error::config("dtls_session_idle must be positive"));
+        }
+        if self.dtls_max_sessions == 0 {
+            return Err(Error::config("dtls_max_sessions must be positive"));
+        }
+        if self.telnet && self.mode != Mode::Echo {
+            return Err(Error::config("telnet only applies to the echo mode"));
+        }
+        if self.annotate && self.mode == Mode::Http {
+            return Err(Error::config("annotate doesn't apply to the http mode"));
+        }
+        if self.allow_mode_negotiation && (self.mode != Mode::Echo || self.telnet) {
+            return Err(Error::config("allow_mode_negotiation needs the plain echo mode"));
+        }
+        if self.allow_mode_negotiation && self.annotate {
+            return Err(Error::config("allow_mode_negotiation and annotate can't be combined"));
+        }
+        if self.annotate && self.udp.is_some() {
+            return Err(Error::config("annotate needs connections, it doesn't apply to udp"));
+        }
+        if (self.checksum || self.verify_checksum) && !matches!(self.mode, Mode::Line | Mode::Length) {
+            return Err(Error::config("checksum and verify_checksum need the line or length mode"));
+        }
+        if self.mode == Mode::Http && (self.banner.is_some() || self.heartbeat_interval.is_some()) {
+            return Err(Error::config("banner and heartbeat_interval don't apply to the http mode"));
+        }
+        if self.max_clients == 0 {
+            return Err(Error::config("max_clients must be positive"));
+        }
+        if self.udp_batch == 0 || self.udp_batch > MAX_UDP_BATCH {
+            return Err(Error::config(format!("udp_batch must be between 1 and {}", MAX_UDP_BATCH)));
+        }
+        if self.udp_sequence && self.udp.is_none() {
+            return Err(Error::config("udp_sequence needs udp"));
+        }
+        if self.udp_source_idle == Duration::ZERO {
+            return Err(Error::config("udp_source_idle must be positive"));
+        }
+        if self.max_write_chunk == Some(0) {
+            return Err(Error::config("max_write_chunk must be positive"));
+        }
+        if self.so_rcvbuf == Some(0) {
+            return Err(Error::config("so_rcvbuf must be positive"));
+        }
+        if self.so_sndbuf == Some(0) {
+            return Err(Error::config("so_sndbuf must be positive"));
+        }
+        if let Some(timeout) = self.tcp_user_timeout {
+            if cfg!(not(target_os = "linux")) {
+                return Err(Error::config("tcp_user_timeout is only supported on Linux"));
+            }
+            if timeout.as_millis() == 0 || timeout.as_millis() > u32::MAX as u128 {
+                return Err(Error::config(format!("tcp_user_timeout must be between 1ms and {}ms", u32::MAX)));
+            }
+        }
+        if self.spin == Some(zero) {
+            return Err(Error::config("spin must be positive"));
+        }
+        if let Some(busy_poll) = self.busy_poll {
+            if cfg!(not(target_os = "linux")) {
+                return Err(Error::config("busy_poll is only supported on Linux"));
+            }
+            if busy_poll.as_micros() == 0 || busy_poll.as_micros() > i32::MAX as u128 {
+                return Err(Error::config(format!("busy_poll must be between 1us and {}us", i32::MAX)));
+            }
+        }
+        if let Some(threshold) = self.zerocopy {
+            if cfg!(not(all(target_os = "linux", feature = "zerocopy"))) {
+                return Err(Error::config("zerocopy needs the zerocopy feature on Linux"));
+            }
+            if threshold == 0 {
+                return Err(Error::config("zerocopy must be positive"));
+            }
+        }
+        if self.freebind {
+            if cfg!(not(target_os = "linux")) {
+                return Err(Error::config("freebind is only supported on Linux"));
+            }
+            if self.listen.is_none() && self.listeners.is_empty() && self.udp.is_none() {
+                return Err(Error::config("freebind needs listen, listeners or udp"));
+            }
+        }
+        if let Some(defer) = self.defer_accept {
+            if cfg!(not(target_os = "linux")) {
+                return Err(Error::config("defer_accept is only supported on Linux"));
+            }
+            if self.listen.is_none() && self.listeners.is_empty() {
+                return Err(Error::config("defer_accept needs listen or listeners"));
+            }
+            if defer.as_secs() == 0 || defer.as_secs() > i32::MAX as u64 || defer.subsec_nanos() != 0 {
+                return Err(Error::config("defer_accept must be a whole number of seconds, at least 1s"));
+            }
+            // The client would wait for the banner and the server for data
+            if self.banner.is_some() {
+                return Err(Error::config("banner and defer_accept exclude each other"));
+            }
+        }
+        if self.global_rate == Some(0) {
+            return Err(Error::config("global_rate must be positive"));
+        }
+        if self.busy_message.as_ref().is_some_and(Vec::is_empty) {
+            return Err(Error::config("busy_message can't be empty"));
+        }
+        if self.pending_queue == Some(0) {
+            return Err(Error::config("pending_queue must be positive"));
+        }
+        if self.pending_timeout == zero {
+            return Err(Error::config("pending_timeout must be positive"));
+        }
+        if self.max_queued == Some(0) {
+            return Err(Error::config("max_queued must be positive"));
+        }
+        if self.deny_existing && self.deny_file.is_none() {
+            return Err(Error::config("deny_existing needs deny_file"));
+        }
+        if self.overflow != Overflow::Backpressure && self.max_queued.is_none() {
+            return Err(Error::config("overflow needs max_queued"));
+        }
+        if matches!(self.overflow, Overflow::DropNewest | Overflow::DropOldest) && self.mode == Mode::Http {
+            return Err(Error::config("dropping responses would break the http mode, use another overflow"));
+        }
+        // Echoes take the stream of the oldest message not answered yet,
+        // anything else written or any echo missing shifts them all
+        if self.sctp.is_some()
+            && (self.banner.is_some()
+                || self.heartbeat_interval.is_some()
+                || matches!(self.overflow, Overflow::DropNewest | Overflow::DropOldest))
+        {
+            return Err(Error::config("banner, heartbeat_interval and dropping overflow policies don't apply to sctp"));
+        }
+        if self.upgrade_binary.is_some() {
+            if cfg!(not(unix)) {
+                return Err(Error::config("upgrade_binary is only supported on Unix"));
+            }
+            if self.vsock_port.is_some()
+                || self.unix_seqpacket.is_some()
+                || self.unix_stream.is_some()
+                || self.sctp.is_some()
+            {
+                return Err(Error::config(
+                    "upgrade_binary only hands over listen, listeners, udp, health_addr and admin_addr"
+                ));
+            }
+            if self.seccomp {
+                return Err(Error::config("the seccomp filter doesn't let upgrade_binary be started"));
+            }
+        }
+        if let Some(workers) = self.workers_processes {
+            if cfg!(not(unix)) {
+                return Err(Error::config("workers_processes is only supported on Unix"));
+            }
+            if workers == 0 {
+                return Err(Error::config("workers_processes must be positive"));
+            }
+            if self.vsock_port.is_some()
+                || self.unix_seqpacket.is_some()
+                || self.unix_stream.is_some()
+                || self.sctp.is_some()
+                || self.health_addr.is_some()
+                || self.admin_addr.is_some()
+                || self.upgrade_binary.is_some()
+            {
+                return Err(Error::config("workers_processes only shares listen, listeners and udp"));
+            }
+            // Every worker would write the same file
+            if self.capture.is_some() || self.access_log.is_some() || self.log_rotate_size.is_some() {
+                return Err(Error::config("capture, access_log and log_rotate_size don't apply to workers_processes"));
+            }
+        }
+        if self.quiesce == Some(zero) {
+            return Err(Error::config("quiesce must be positive"));
+        }
+        if self.quiesce_max == 0 {
+            return Err(Error::config("quiesce_max must be positive"));
+        }
+        // The queue would overflow before the held echo is flushed
+        if self.quiesce.is_some() && self.max_queued.is_some_and(|max| self.quiesce_max > max) {
+            return Err(Error::config("quiesce_max can't exceed max_queued"));
+        }
+        if self.write_budget == 0 {
+            return Err(Error::config("write_budget must be positive"));
+        }
+        if self.read_buf_size == 0 {
+            return Err(Error::config("read_buf_size must be positive"));
+        }
+        if self.events_capacity == 0 {
+            return Err(Error::config("events_capacity must be positive"));
+        }
+        if self.shrink_after == zero {
+            return Err(Error::config("shrink_after must be positive"));
+        }
+        if self.heartbeat_interval == Some(zero) {
+            return Err(Error::config("heartbeat_interval must be positive"));
+        }
+        if self.heartbeat_interval.is_some() && self.heartbeat_payload.is_empty() {
+            return Err(Error::config("heartbeat_payload can't be empty with heartbeat_interval"));
+        }
+        if self.stats_interval == zero {
+            return Err(Error::config("stats_interval must be positive"));
+        }
+        if self.capture_max_size == 0 {
+            return Err(Error::config("capture_max_size must be positive"));
+        }
+        if self.mirror.as_ref().is_some_and(|addr| addr.parse::<SocketAddr>().is_err()) {
+            return Err(Error::config("mirror must be an IP address and port"));
+        }
+        if self.log_rotate_size == Some(0) {
+            return Err(Error::config("log_rotate_size must be positive"));
+        }
+        if self.log_keep == 0 {
+            return Err(Error::config("log_keep must be positive"));
+        }
+        if self.log_rotate_size.is_some() && self.log_file.is_none() {
+            return Err(Error::config("log_rotate_size needs log_file"));
+        }
+        if self.syslog_facility > 23 {
+            return Err(Error::config("syslog_facility must be between 0 and 23"));
+        }
+        if cfg!(not(unix)) && matches!(self.log_syslog, Some(SyslogTarget::Unix(_))) {
+            return Err(Error::config("syslog over a Unix socket is only supported on Unix"));
+        }
+        if self.max_connections_total == Some(0) {
+            return Err(Error::config("max_connections_total must be positive"));
+        }
+        if self.exit_when_idle == Some(zero) {
+            return Err(Error::config("exit_when_idle must be positive"));
+        }
+        if self.first_byte_timeout == Some(zero) {
+            return Err(Error::config("first_byte_timeout must be positive"));
+        }
+        if self.idle_timeout == Some(zero) {
+            return Err(Error::config("idle_timeout must be positive"));
+        }
+        if self.resolve_peers {
+            if cfg!(not(target_os = "linux")) {
+                return Err(Error::config("resolve_peers is only supported on Linux"));
+            }
+            if self.seccomp {
+                return Err(Error::config("the seccomp filter doesn't let resolve_peers query DNS"));
+            }
+        }
+        if self.resolve_ttl == zero {
+            return Err(Error::config("resolve_ttl must be positive"));
+        }
+        if self.listeners.len() > MAX_LISTENERS {
+            return Err(Error::config(format!("at most {} listeners can be configured", MAX_LISTENERS)));
+        }
+        for (index, listener) in self.listeners.iter().enumerate() {
+            if listener.max_clients.is_some_and(|n| n > self.max_clients) {
+                return Err(Error::config(format!(
+                    "listener {}: max_clients can't exceed the global one",
+                    listener.addr
+                )));
+            }
+            self.for_listener(index)
+                .validate()
+                .map_err(|e| Error::config(format!("listener {}: {}", listener.addr, e)))?;
+        }
+        if same_port(&self.health_addr, &self.listen) {
+            return Err(Error::config("health_addr must differ from listen"));
+        }
+        if same_port(&self.admin_addr, &self.listen) || same_port(&self.admin_addr, &self.health_addr) {
+            return Err(Error::config("admin_addr must differ from listen and health_addr"));
+        }
+        if self.backend == Backend::Uring {
+            self.validate_uring()?;
+        }
+        Ok(())
+    }
+
+    fn validate_uring(&self) -> Result<(), Error> {
+        if cfg!(not(all(target_os = "linux", feature = "io-uring"))) {
+            return Err(Error::config("io_uring support is not compiled in"));
+        }
+        if self.listen.is_none() {
+            return Err(Error::config("the uring backend needs listen"));
+        }
+        let unsupported = [
+            ("udp", self.udp.is_some()),
+            ("vsock_port", self.vsock_port.is_some()),
+            ("unix_seqpacket", self.unix_seqpacket.is_some()),
+            ("unix_stream", self.unix_stream.is_some()),
+            ("sctp", self.sctp.is_some()),
+            ("pipe_name", self.pipe_name.is_some()),
+            ("listeners", !self.listeners.is_empty()),
+            ("inter_chunk_delay", self.inter_chunk_delay.is_some()),
+            ("short_read_drained", self.short_read_drained),
+            ("read_buf_size", self.read_buf_size != DEFAULT_BUF_SIZE),
+            ("events_capacity", self.events_capacity != EVENTS_CAPACITY),
+            ("tls_cert", self.tls_cert.is_some()),
+            ("telnet", self.telnet),
+            ("annotate", self.annotate),
+            ("allow_mode_negotiation", self.allow_mode_negotiation),
+            ("mode", self.mode != Mode::Echo),
+            ("heartbeat_interval", self.heartbeat_interval.is_some()),
+            ("seccomp", self.seccomp),
+            ("statsd", self.statsd.is_some()),
+            ("capture", self.capture.is_some()),
+            ("mirror", self.mirror.is_some()),
+            ("access_log", self.access_log.is_some()),
+            ("log_original_dst", self.log_original_dst),
+            ("resolve_peers", self.resolve_peers),
+            ("duration", self.duration.is_some()),
+            ("max_connections_total", self.max_connections_total.is_some()),
+            ("exit_when_idle", self.exit_when_idle.is_some()),
+            ("first_byte_timeout", self.first_byte_timeout.is_some()),
+            ("idle_timeout", self.idle_timeout.is_some()),
+            ("health_addr", self.health_addr.is_some()),
+            ("admin_addr", self.admin_addr.is_some()),
+            ("global_rate", self.global_rate.is_some()),
+            ("busy_message", self.busy_message.is_some()),
+            ("pending_queue", self.pending_queue.is_some()),
+            ("bans", !self.bans.is_empty()),
+            ("deny_file", self.deny_file.is_some()),
+            ("max_queued", self.max_queued.is_some()),
+            ("quiesce", self.quiesce.is_some()),
+            ("so_rcvbuf", self.so_rcvbuf.is_some()),
+            ("so_sndbuf", self.so_sndbuf.is_some()),
+            ("tcp_user_timeout", self.tcp_user_timeout.is_some()),
+            ("spin", self.spin.is_some()),
+            ("busy_poll", self.busy_poll.is_some()),
+            ("zerocopy", self.zerocopy.is_some()),
+            ("defer_accept", self.defer_accept.is_some()),
+            ("freebind", self.freebind),
+            ("upgrade_binary", self.upgrade_binary.is_some()),
+            ("workers_processes", self.workers_processes.is_some()),
+            ("exclusive_accept", self.exclusive_accept),
+        ];
+        match unsupported.iter().find(|&&(_, set)| set) {
+            Some((name, _)) => Err(Error::config(format!("{} is not supported by the uring backend", name))),
+            None => Ok(()),
+        }
+    }
+
+    /// The settings of the clients of `listeners[index]`: its own where
+    /// set, these otherwise, listening on its address only.
+    pub fn for_listener(&self, index: usize) -> Config {
+        let listener = &self.listeners[index];
+        // A certificate goes with its own key only
+        let (tls_cert, tls_key) = if listener.tls_cert.is_some() || listener.tls_key.is_some() {
+            (listener.tls_cert.clone(), listener.tls_key.clone())
+        } else {
+            (self.tls_cert.clone(), self.tls_key.clone())
+        };
+        Config {
+            listen: Some(listener.addr.clone()),
+            udp: None,
+            vsock_port: None,
+            unix_seqpacket: None,
+            unix_stream: None,
+            sctp: None,
+            pipe_name: None,
+            listeners: Vec::new(),
+            mode: listener.mode.unwrap_or(self.mode),
+            checksum: listener.checksum.unwrap_or(self.checksum),
+            verify_checksum: listener.verify_checksum.unwrap_or(self.verify_checksum),
+            telnet: listener.telnet.unwrap_or(self.telnet),
+            max_clients: listener.max_clients.unwrap_or(self.max_clients),
+            max_queued: listener.max_queued.or(self.max_queued),
+            overflow: listener.overflow.unwrap_or(self.overflow),
+            tls_cert,
+            tls_key,
+            // The settings of the UDP socket, which isn't this listener
+            udp_sequence: false,
+            dtls_cert: None,
+            dtls_key: None,
+            dtls_client_ca: None,
+            quic: false,
+            quic_datagrams: false,
+            quic_alpn: Vec::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Number of listening sockets the configuration asks for.
+    pub fn listener_count(&self) -> usize {
+        self.listen.iter().count()
+            + self.listeners.len()
+            + self.udp.iter().count()
+            + self.vsock_port.iter().count()
+            + self.unix_seqpacket.iter().count()
+            + self.unix_stream.iter().count()
+            + self.sctp.iter().count()
+            + self.pipe_name.iter().count()
+    }
+}
+
+// Whether both are set to the same address, port 0 picking a different
+// free port for each
+fn same_port(a: &Option<String>, b: &Option<String>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a == b && !a.ends_with(":0"),
+        _ => false,
+    }
+}
+
+/// Parses a `Mode` by its lowercase name.
+pub fn parse_mode(s: &str) -> Result<Mode, Error> {
+    match s {
+        "echo" => Ok(Mode::Echo),
+        "http" => Ok(Mode::Http),
+        "line" => Ok(Mode::Line),
+        "length" => Ok(Mode::Length),
+        _ => Err(Error::config(format!("invalid mode: {}", s))),
+    }
+}
+
+/// Parses `required` or `optional`.
+pub fn parse_client_auth(s: &str) -> Result<ClientAuth, Error> {
+    match s {
+        "required" => Ok(ClientAuth::Required),
+        "optional" => Ok(ClientAuth::Optional),
+        _ => Err(Error::config(format!("invalid client authentication: {}", s))),
+    }
+}
+
+/// Parses an `Overflow` policy such as `drop-oldest`.
+pub fn parse_overflow(s: &str) -> Result<Overflow, Error> {
+    match s {
+        "backpressure" => Ok(Overflow::Backpressure),
+        "drop-newest" => Ok(Overflow::DropNewest),
+        "drop-oldest" => Ok(Overflow::DropOldest),
+        "disconnect" => Ok(Overflow::Disconnect),
+        _ => Err(Error::config(format!("invalid overflow policy: {}", s))),
+    }
+}
+
+/// Parses `HOST:PORT[,KEY=VALUE...]`, the keys being `mode`, `checksum`
+/// (`crc32` or `none`), `verify-checksum` and `telnet` (`on` or `off`, on
+/// when bare), `max-clients`, `max-queued`, `overflow`, `tls-cert` and
+/// `tls-key`.
+pub fn parse_listener(s: &str) -> Result<ListenerConfig, Error> {
+    let mut fields = s.split(',');
+    let mut listener = ListenerConfig::new(fields.next().unwrap_or(""));
+    for field in fields {
+        let (key, value) = match field.split_once('=') {
+            Some((key, value)) => (key, Some(value)),
+            None => (field, None),
+        };
+        let switch = || match value {
+            None | Some("on") => Ok(true),
+            Some("off") => Ok(false),
+            Some(value) => Err(Error::config(format!("invalid {} in {}: {}", key, s, value))),
+        };
+        let value = || value.ok_or_else(|| Error::config(format!("missing value for {} in {}", key, s)));
+        match key {
+            "mode" => listener.mode = Some(parse_mode(value()?)?),
+            "checksum" => {
+                listener.checksum = match value()? {
+                    "crc32" => Some(true),
+                    "none" => Some(false),
+                    checksum => return Err(Error::config(format!("unsupported checksum: {}", checksum))),
+                };
+            }
+            "verify-checksum" => listener.verify_checksum = Some(switch()?),
+            "telnet" => listener.telnet = Some(switch()?),
+            "max-clients" => {
+                let n = value()?;
+                let n = n.parse().map_err(|_| Error::config(format!("invalid client count: {}", n)))?;
+                listener.max_clients = Some(n);
+            }
+            "max-queued" => listener.max_queued = Some(parse_size(value()?)?),
+            "overflow" => listener.overflow = Some(parse_overflow(value()?)?),
+            "tls-cert" => listener.tls_cert = Some(value()?.into()),
+            "tls-key" => listener.tls_key = Some(value()?.into()),
+            _ => return Err(Error::config(format!("unknown listener setting {} in {}", key, s))),
+        }
+    }
+    Ok(listener)
+}
+
+/// Parses a byte count such as `512`, `64k` or `1m`.
+pub fn parse_size(s: &str) -> Result<usize, Error> {
+    let (num, mult) = match s.char_indices().find(|&(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => {
+            let mult = match &s[i..].to_ascii_lowercase()[..] {
+                "k" | "kb" | "kib" => 1 << 10,
+                "m" | "mb" | "mib" => 1 << 20,
+                "g" | "gb" | "gib" => 1 << 30,
+                _ => return Err(Error::config(format!("invalid size: {}", s))),
+            };
+            (&s[..i], mult)
+        }
+        None => (s, 1),
+    };
+    let num: usize = num.parse().map_err(|_| Error::config(format!("invalid size: {}", s)))?;
+    num.checked_mul(mult)
+        .ok_or_else(|| Error::config(format!("size too large: {}", s)))
+}
+
+/// Parses a bit rate such as `800kbps`, `200mbps` or `1gbps` into bytes
+/// per second.
+pub fn parse_rate(s: &str) -> Result<u64, Error> {
+    let i = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let num: u64 = s[..i].parse().map_err(|_| Error::config(format!("invalid rate: {}", s)))?;
+    let mult = match &s[i..].to_ascii_lowercase()[..] {
+        "bps" => 1,
+        "kbps" => 1_000,
+        "mbps" => 1_000_000,
+        "gbps" => 1_000_000_000,
+        _ => return Err(Error::config(format!("invalid rate: {}", s))),
+    };
+    num.checked_mul(mult)
+        .map(|bits| bits / 8)
+        .ok_or_else(|| Error::config(format!("rate too large: {}", s)))
+}
+
+/// Parses a duration such as `5ms`, `30s`, `2m` or `1h`.
+/// A bare number is taken as seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, Error> {
+    let i = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let num: u64 = s[..i]
+        .parse()
+        .map_err(|_| Error::config(format!("invalid duration: {}", s)))?;
+    match &s[i..] {
+        "us" => Ok(Duration::from_micros(num)),
+        "ms" => Ok(Duration::from_millis(num)),
+        "" | "s" => Ok(Duration::from_secs(num)),
+        "m" => Ok(Duration::from_secs(num * 60)),
+        "h" => Ok(Duration::from_secs(num * 60 * 60)),
+        _ => Err(Error::config(format!("invalid duration: {}", s))),
+    }
+}
+
+/// Parses `unix`, `unix:PATH` or `udp:HOST:PORT`, `unix` meaning
+/// `/dev/log`.
+pub fn parse_syslog_target(s: &str) -> Result<SyslogTarget, Error> {
+    match s.split_once(':') {
+        None if s == "unix" => Ok(SyslogTarget::Unix(PathBuf::from("/dev/log"))),
+        Some(("unix", path)) if !path.is_empty() => Ok(SyslogTarget::Unix(PathBuf::from(path))),
+        Some(("udp", addr)) => Ok(SyslogTarget::Udp(addr.to_string())),
+        _ => Err(Error::config(format!("invalid syslog target: {}", s))),
+    }
+}
+
+/// Parses a syslog facility name such as `daemon` or `local0`, or its
+/// code.
+pub fn parse_facility(s: &str) -> Result<u8, Error> {
+    const NAMES: [&str; 12] = [
+        "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv", "ftp",
+    ];
+    if let Some(code) = NAMES.iter().position(|&name| name == s) {
+        return Ok(code as u8);
+    }
+    match s.strip_prefix("local").map(str::parse::<u8>) {
+        Some(Ok(n)) if n < 8 => Ok(16 + n),
+        _ => match s.parse::<u8>() {
+            Ok(code) if code < 24 => Ok(code),
+            _ => Err(Error::config(format!("invalid syslog facility: {}", s))),
+        },
+    }
+}
+
+/// Parses `IP DURATION` lines, skipping blank ones and `#` comments.
+pub fn parse_bans(text: &str) -> Result<Vec<(IpAddr, Duration)>, Error> {
+    let mut bans = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let ban = match (fields.next(), fields.next(), fields.next()) {
+            (Some(ip), Some(duration), None) => ip
+                .parse()
+                .map_err(|_| Error::config(format!("invalid address: {}", ip)))
+                .and_then(|ip| Ok((ip, parse_duration(duration)?))),
+            _ => Err(Error::config("expected IP DURATION")),
+        };
+        bans.push(ban.map_err(|e| Error::config(format!("line {}: {}", n + 1, e)))?);
+    }
+    Ok(bans)
+}
+
+/// Expands the `\r`, `\n`, `\t` and `\\` escapes of a command line string.
+pub fn unescape(s: &str) -> Result<Vec<u8>, Error> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut utf8 = [0; 4];
+            out.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('r') => out.push(b'\r'),
+            Some('n') => out.push(b'\n'),
+            Some('t') => out.push(b'\t'),
+            Some('\\') => out.push(b'\\'),
+            Some(c) => return Err(Error::config(format!("invalid escape \\{} in {:?}", c, s))),
+            None => return Err(Error::config(format!("trailing backslash in {:?}", s))),
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid() -> Config {
+        Config::new("127.0.0.1:7")
+    }
+
+    fn with<F: FnOnce(&mut Config)>(change: F) -> Config {
+        let mut config = valid();
+        change(&mut config);
+        config
+    }
+
+    fn args(line: &str) -> Result<Config, Error> {
+        Config::from_args(line.split_whitespace().map(String::from))
+    }
+
+    /// A test per rejection, checking its message.
+    macro_rules! rejects {
+        ($($(#[$attr:meta])* $name:ident: $config:expr => $message:expr;)*) => {
+            $(
+                $(#[$attr])*
+                #[test]
+                fn $name() {
+                    let config: Config = $config;
+                    assert_eq!(config.validate().unwrap_err().to_string(), $message);
+                }
+            )*
+        };
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        valid().validate().unwrap();
+        assert_eq!(valid().max_clients, MAX_CLIENTS);
+        assert_eq!(valid().read_buf_size, DEFAULT_BUF_SIZE);
+        assert_eq!(valid().mode, Mode::Echo);
+        assert_eq!(valid().max_queued, None);
+    }
+
+    #[test]
+    fn from_args_sets_and_validates() {
+        let config = args("127.0.0.1:7 --mode line --max-queued 64k --idle-timeout 30s").unwrap();
+        assert_eq!(config.listen.as_deref(), Some("127.0.0.1:7"));
+        assert_eq!(config.mode, Mode::Line);
+        assert_eq!(config.max_queued, Some(64 << 10));
+        assert_eq!(config.idle_timeout, Some(Duration::from_secs(30)));
+
+        assert_eq!(args("--mode line").unwrap_err().to_string(), "missing HOST:PORT");
+        assert_eq!(args("127.0.0.1:7 --read-buf-size 0").unwrap_err().to_string(), "read_buf_size must be positive");
+        assert_eq!(args("127.0.0.1:7 --bogus").unwrap_err().to_string(), "unknown option: --bogus");
+        assert_eq!(args("127.0.0.1:7 --mode").unwrap_err().to_string(), "missing value for --mode");
+    }
+
+    #[test]
+    fn listeners_take_the_global_settings_they_dont_set() {
+        let config = with(|c| {
+            c.mode = Mode::Line;
+            c.max_queued = Some(100);
+            c.listeners.push(ListenerConfig {
+                mode: Some(Mode::Length),
+                ..ListenerConfig::new("127.0.0.1:8")
+            });
+        });
+        let listener = config.for_listener(0);
+        assert_eq!(listener.listen.as_deref(), Some("127.0.0.1:8"));
+        assert_eq!(listener.mode, Mode::Length);
+        assert_eq!(listener.max_queued, Some(100));
+        assert!(listener.listeners.is_empty());
+    }
+
+    #[test]
+    fn listeners_take_a_certificate_with_its_key() {
+        let config = with(|c| {
+            c.tls_cert = Some("global.pem".into());
+            c.tls_key = Some("global.key".into());
+            c.listeners.push(ListenerConfig::new("127.0.0.1:8"));
+            c.listeners.push(ListenerConfig {
+                tls_cert: Some("own.pem".into()),
+                tls_key: Some("own.key".into()),
+                ..ListenerConfig::new("127.0.0.1:9")
+            });
+            c.listeners.push(ListenerConfig {
+                tls_cert: Some("own.pem".into()),
+                ..ListenerConfig::new("127.0.0.1:10")
+            });
+        });
+        let global = config.for_listener(0);
+        assert_eq!(global.tls_cert, config.tls_cert);
+        assert_eq!(global.tls_key, config.tls_key);
+        let own = config.for_listener(1);
+        assert_eq!(own.tls_cert.as_deref(), Some(Path::new("own.pem")));
+        assert_eq!(own.tls_key.as_deref(), Some(Path::new("own.key")));
+        // Never the key of another certificate
+        assert_eq!(config.for_listener(2).tls_key, None);
+    }
+
+    #[test]
+    fn listeners_leave_the_udp_settings_to_udp() {
+        let config = with(|c| {
+            c.udp = Some("127.0.0.1:7".into());
+            c.udp_sequence = true;
+            c.listeners.push(ListenerConfig::new("127.0.0.1:8"));
+        });
+        config.validate().unwrap();
+        assert!(!config.for_listener(0).udp_sequence);
+    }
+
+    #[test]
+    fn parse_listener_reads_every_key() {
+        let listener = parse_listener(
+            "127.0.0.1:8,mode=line,checksum=crc32,verify-checksum,telnet=off,max-clients=5,max-queued=1k,\
+             overflow=drop-oldest,tls-cert=cert.pem,tls-key=key.pem",
+        )
+        .unwrap();
+        assert_eq!(
+            listener,
+            ListenerConfig {
+                mode: Some(Mode::Line),
+                checksum: Some(true),
+                verify_checksum: Some(true),
+                telnet: Some(false),
+                max_clients: Some(5),
+                max_queued: Some(1 << 10),
+                overflow: Some(Overflow::DropOldest),
+                tls_cert: Some("cert.pem".into()),
+                tls_key: Some("key.pem".into()),
+                ..ListenerConfig::new("127.0.0.1:8")
+            }
+        );
+        assert_eq!(
+            parse_listener("127.0.0.1:8,tls-cert").unwrap_err().to_string(),
+            "missing value for tls-cert in 127.0.0.1:8,tls-cert"
+        );
+    }
+
+    rejects! {
+        no_listener: Config::default() => "no listener configured";
+        #[cfg(not(windows))]
+        pipe_name_off_windows: with(|c| c.pipe_name = Some("echo".into())) => "pipe_name is only supported on Windows";
+        #[cfg(not(feature = "tls"))]
+        tls_without_the_feature: with(|c| c.tls_cert = Some("cert.pem".into())) => "tls_cert needs the tls feature";
+        #[cfg(feature = "tls")]
+        tls_cert_without_key: with(|c| c.tls_cert = Some("cert.pem".into())) => "tls_cert and tls_key go together";
+        #[cfg(feature = "tls")]
+        tls_without_tcp: with(|c| {
+            c.listen = None;
+            c.udp = Some("127.0.0.1:7".into());
+            c.tls_cert = Some("cert.pem".into());
+            c.tls_key = Some("key.pem".into());
+        }) => "tls_cert needs listen, listeners or quic";
+        #[cfg(feature = "tls")]
+        tls_busy_message: with(|c| {
+            c.tls_cert = Some("cert.pem".into());
+            c.tls_key = Some("key.pem".into());
+            c.busy_message = Some(b"busy".to_vec());
+        }) => "busy_message would be sent in clear over tls";
+        #[cfg(feature = "tls")]
+        tls_short_read_drained: with(|c| {
+            c.tls_cert = Some("cert.pem".into());
+            c.tls_key = Some("key.pem".into());
+            c.short_read_drained = true;
+        }) => "short_read_drained can't tell when tls records are drained";
+        tls_client_ca_without_tls: with(|c| c.tls_client_ca = Some("ca.pem".into())) => "tls_client_ca needs tls_cert";
+        #[cfg(feature = "tls")]
+        optional_client_auth_without_ca: with(|c| {
+            c.tls_cert = Some("cert.pem".into());
+            c.tls_key = Some("key.pem".into());
+            c.tls_client_auth = ClientAuth::Optional;
+        }) => "tls_client_auth needs tls_client_ca";
+        #[cfg(not(feature = "dtls"))]
+        dtls_without_the_feature: with(|c| c.dtls_cert = Some("cert.pem".into())) => "dtls_cert needs the dtls feature";
+        #[cfg(feature = "dtls")]
+        dtls_cert_without_key: with(|c| c.dtls_cert = Some("cert.pem".into())) => "dtls_cert and dtls_key go together";
+        #[cfg(feature = "dtls")]
+        dtls_without_udp: with(|c| {
+            c.dtls_cert = Some("cert.pem".into());
+            c.dtls_key = Some("key.pem".into());
+        }) => "dtls_cert needs udp";
+        #[cfg(feature = "dtls")]
+        dtls_udp_sequence: with(|c| {
+            c.udp = Some("127.0.0.1:7".into());
+            c.udp_sequence = true;
+            c.dtls_cert = Some("cert.pem".into());
+            c.dtls_key = Some("key.pem".into());
+        }) => "udp_sequence can't read the sequence numbers of dtls datagrams";
+        #[cfg(not(feature = "quic"))]
+        quic_without_the_feature: with(|c| c.quic = true) => "quic needs the quic feature";
+        #[cfg(feature = "quic")]
+        quic_without_tls_cert: with(|c| {
+            c.udp = Some("127.0.0.1:7".into());
+            c.quic = true;
+        }) => "quic needs udp and tls_cert";
+        #[cfg(feature = "quic")]
+        quic_with_dtls: with(|c| {
+            c.udp = Some("127.0.0.1:7".into());
+            c.tls_cert = Some("cert.pem".into());
+            c.tls_key = Some("key.pem".into());
+            c.dtls_cert = Some("cert.pem".into());
+            c.dtls_key = Some("key.pem".into());
+            c.quic = true;
+        }) => "quic and dtls_cert can't share udp";
+        #[cfg(feature = "quic")]
+        quic_udp_sequence: with(|c| {
+            c.udp = Some("127.0.0.1:7".into());
+            c.udp_sequence = true;
+            c.tls_cert = Some("cert.pem".into());
+            c.tls_key = Some("key.pem".into());
+            c.quic = true;
+        }) => "udp_sequence can't read the sequence numbers of quic packets";
+        quic_datagrams_without_quic: with(|c| c.quic_datagrams = true) => "quic_datagrams and quic_alpn need quic";
+        dtls_client_ca_without_dtls: with(|c| c.dtls_client_ca = Some("ca.pem".into())) => "dtls_client_ca needs dtls_cert";
+        dtls_session_idle_zero: with(|c| c.dtls_session_idle = Duration::ZERO) => "dtls_session_idle must be positive";
+        dtls_max_sessions_zero: with(|c| c.dtls_max_sessions = 0) => "dtls_max_sessions must be positive";
+        telnet_outside_echo: with(|c| {
+            c.telnet = true;
+            c.mode = Mode::Line;
+        }) => "telnet only applies to the echo mode";
+        annotate_http: with(|c| {
+            c.annotate = true;
+            c.mode = Mode::Http;
+        }) => "annotate doesn't apply to the http mode";
+        negotiation_outside_echo: with(|c| {
+            c.allow_mode_negotiation = true;
+            c.mode = Mode::Length;
+        }) => "allow_mode_negotiation needs the plain echo mode";
+        negotiation_with_telnet: with(|c| {
+            c.allow_mode_negotiation = true;
+            c.telnet = true;
+        }) => "allow_mode_negotiation needs the plain echo mode";
+        negotiation_with_annotate: with(|c| {
+            c.allow_mode_negotiation = true;
+            c.annotate = true;
+        }) => "allow_mode_negotiation and annotate can't be combined";
+        annotate_udp: with(|c| {
+            c.annotate = true;
+            c.udp = Some("127.0.0.1:7".into());
+        }) => "annotate needs connections, it doesn't apply to udp";
+        checksum_outside_framing: with(|c| c.checksum = true) => "checksum and verify_checksum need the line or length mode";
+        verify_checksum_outside_framing: with(|c| c.verify_checksum = true)
+            => "checksum and verify_checksum need the line or length mode";
+        http_banner: with(|c| {
+            c.mode = Mode::Http;
+            c.banner = Some(b"hi".to_vec());
+        }) => "banner and heartbeat_interval don't apply to the http mode";
+        zero_max_clients: with(|c| c.max_clients = 0) => "max_clients must be positive";
+        zero_udp_batch: with(|c| c.udp_batch = 0) => "udp_batch must be between 1 and 1024";
+        huge_udp_batch: with(|c| c.udp_batch = MAX_UDP_BATCH + 1) => "udp_batch must be between 1 and 1024";
+        udp_sequence_without_udp: with(|c| c.udp_sequence = true) => "udp_sequence needs udp";
+        zero_udp_source_idle: with(|c| c.udp_source_idle = Duration::ZERO) => "udp_source_idle must be positive";
+        zero_max_write_chunk: with(|c| c.max_write_chunk = Some(0)) => "max_write_chunk must be positive";
+        zero_so_rcvbuf: with(|c| c.so_rcvbuf = Some(0)) => "so_rcvbuf must be positive";
+        zero_so_sndbuf: with(|c| c.so_sndbuf = Some(0)) => "so_sndbuf must be positive";
+        #[cfg(target_os = "linux")]
+        zero_tcp_user_timeout: with(|c| c.tcp_user_timeout = Some(Duration::ZERO))
+            => "tcp_user_timeout must be between 1ms and 4294967295ms";
+        zero_spin: with(|c| c.spin = Some(Duration::ZERO)) => "spin must be positive";
+        #[cfg(target_os = "linux")]
+        zero_busy_poll: with(|c| c.busy_poll = Some(Duration::ZERO)) => "busy_poll must be between 1us and 2147483647us";
+        #[cfg(not(all(target_os = "linux", feature = "zerocopy")))]
+        zerocopy_without_the_feature: with(|c| c.zerocopy = Some(1 << 16)) => "zerocopy needs the zerocopy feature on Linux";
+        #[cfg(all(target_os = "linux", feature = "zerocopy"))]
+        zero_zerocopy: with(|c| c.zerocopy = Some(0)) => "zerocopy must be positive";
+        #[cfg(target_os = "linux")]
+        freebind_without_ip: with(|c| {
+            c.listen = None;
+            c.unix_stream = Some("/tmp/echo.sock".into());
+            c.freebind = true;
+        }) => "freebind needs listen, listeners or udp";
+        #[cfg(target_os = "linux")]
+        defer_accept_without_tcp: with(|c| {
+            c.listen = None;
+            c.udp = Some("127.0.0.1:7".into());
+            c.defer_accept = Some(Duration::from_secs(1));
+        }) => "defer_accept needs listen or listeners";
+        #[cfg(target_os = "linux")]
+        fractional_defer_accept: with(|c| c.defer_accept = Some(Duration::from_millis(1500)))
+            => "defer_accept must be a whole number of seconds, at least 1s";
+        #[cfg(target_os = "linux")]
+        defer_accept_banner: with(|c| {
+            c.defer_accept = Some(Duration::from_secs(1));
+            c.banner = Some(b"hi".to_vec());
+        }) => "banner and defer_accept exclude each other";
+        zero_global_rate: with(|c| c.global_rate = Some(0)) => "global_rate must be positive";
+        empty_busy_message: with(|c| c.busy_message = Some(Vec::new())) => "busy_message can't be empty";
+        zero_pending_queue: with(|c| c.pending_queue = Some(0)) => "pending_queue must be positive";
+        zero_pending_timeout: with(|c| c.pending_timeout = Duration::ZERO) => "pending_timeout must be positive";
+        zero_max_queued: with(|c| c.max_queued = Some(0)) => "max_queued must be positive";
+        deny_existing_without_deny_file: with(|c| c.deny_existing = true) => "deny_existing needs deny_file";
+        overflow_without_max_queued: with(|c| c.overflow = Overflow::Disconnect) => "overflow needs max_queued";
+        http_dropping_responses: with(|c| {
+            c.mode = Mode::Http;
+            c.max_queued = Some(1 << 16);
+            c.overflow = Overflow::DropNewest;
+        }) => "dropping responses would break the http mode, use another overflow";
+        sctp_banner: with(|c| {
+            c.sctp = Some("127.0.0.1:7".into());
+            c.banner = Some(b"hi".to_vec());
+        }) => "banner, heartbeat_interval and dropping overflow policies don't apply to sctp";
+        #[cfg(unix)]
+        upgrade_binary_unix_stream: with(|c| {
+            c.upgrade_binary = Some("/usr/bin/true".into());
+            c.unix_stream = Some("/tmp/echo.sock".into());
+        }) => "upgrade_binary only hands over listen, listeners, udp, health_addr and admin_addr";
+        #[cfg(unix)]
+        upgrade_binary_seccomp: with(|c| {
+            c.upgrade_binary = Some("/usr/bin/true".into());
+            c.seccomp = true;
+        }) => "the seccomp filter doesn't let upgrade_binary be started";
+        #[cfg(unix)]
+        zero_workers_processes: with(|c| c.workers_processes = Some(0)) => "workers_processes must be positive";
+        #[cfg(unix)]
+        workers_processes_health_addr: with(|c| {
+            c.workers_processes = Some(2);
+            c.health_addr = Some("127.0.0.1:8".into());
+        }) => "workers_processes only shares listen, listeners and udp";
+        #[cfg(unix)]
+        workers_processes_capture: with(|c| {
+            c.workers_processes = Some(2);
+            c.capture = Some("echo.pcap".into());
+        }) => "capture, access_log and log_rotate_size don't apply to workers_processes";
+        zero_quiesce: with(|c| c.quiesce = Some(Duration::ZERO)) => "quiesce must be positive";
+        zero_quiesce_max: with(|c| c.quiesce_max = 0) => "quiesce_max must be positive";
+        quiesce_max_above_max_queued: with(|c| {
+            c.quiesce = Some(Duration::from_millis(10));
+            c.quiesce_max = 2 << 10;
+            c.max_queued = Some(1 << 10);
+        }) => "quiesce_max can't exceed max_queued";
+        zero_write_budget: with(|c| c.write_budget = 0) => "write_budget must be positive";
+        zero_read_buf_size: with(|c| c.read_buf_size = 0) => "read_buf_size must be positive";
+        zero_events_capacity: with(|c| c.events_capacity = 0) => "events_capacity must be positive";
+        zero_shrink_after: with(|c| c.shrink_after = Duration::ZERO) => "shrink_after must be positive";
+        zero_heartbeat_interval: with(|c| c.heartbeat_interval = Some(Duration::ZERO)) => "heartbeat_interval must be positive";
+        empty_heartbeat_payload: with(|c| {
+            c.heartbeat_interval = Some(Duration::from_secs(1));
+            c.heartbeat_payload = Vec::new();
+        }) => "heartbeat_payload can't be empty with heartbeat_interval";
+        zero_stats_interval: with(|c| c.stats_interval = Duration::ZERO) => "stats_interval must be positive";
+        zero_capture_max_size: with(|c| c.capture_max_size = 0) => "capture_max_size must be positive";
+        mirror_host_name: with(|c| c.mirror = Some("localhost:9".into())) => "mirror must be an IP address and port";
+        zero_log_rotate_size: with(|c| {
+            c.log_file = Some("echo.log".into());
+            c.log_rotate_size = Some(0);
+        }) => "log_rotate_size must be positive";
+        zero_log_keep: with(|c| c.log_keep = 0) => "log_keep must be positive";
+        log_rotate_size_without_log_file: with(|c| c.log_rotate_size = Some(1 << 20)) => "log_rotate_size needs log_file";
+        bad_syslog_facility: with(|c| c.syslog_facility = 24) => "syslog_facility must be between 0 and 23";
+        zero_max_connections_total: with(|c| c.max_connections_total = Some(0)) => "max_connections_total must be positive";
+        zero_exit_when_idle: with(|c| c.exit_when_idle = Some(Duration::ZERO)) => "exit_when_idle must be positive";
+        zero_first_byte_timeout: with(|c| c.first_byte_timeout = Some(Duration::ZERO)) => "first_byte_timeout must be positive";
+        zero_idle_timeout: with(|c| c.idle_timeout = Some(Duration::ZERO)) => "idle_timeout must be positive";
+        #[cfg(target_os = "linux")]
+        resolve_peers_seccomp: with(|c| {
+            c.resolve_peers = true;
+            c.seccomp = true;
+        }) => "the seccomp filter doesn't let resolve_peers query DNS";
+        zero_resolve_ttl: with(|c| c.resolve_ttl = Duration::ZERO) => "resolve_ttl must be positive";
+        too_many_listeners: with(|c| {
+            c.listeners = (0..=MAX_LISTENERS).map(|i| ListenerConfig::new(&format!("127.0.0.1:{}", 8000 + i))).collect();
+        }) => format!("at most {} listeners can be configured", MAX_LISTENERS);
+        listener_max_clients_above_global: with(|c| {
+            c.listeners.push(ListenerConfig {
+                max_clients: Some(c.max_clients + 1),
+                ..ListenerConfig::new("127.0.0.1:8")
+            });
+        }) => "listener 127.0.0.1:8: max_clients can't exceed the global one";
+        invalid_listener: with(|c| {
+            c.listeners.push(ListenerConfig {
+                checksum: Some(true),
+                ..ListenerConfig::new("127.0.0.1:8")
+            });
+        }) => "listener 127.0.0.1:8: checksum and verify_checksum need the line or length mode";
+        listener_quiesce_max_above_its_max_queued: with(|c| {
+            c.quiesce = Some(Duration::from_millis(10));
+            c.listeners.push(ListenerConfig {
+                max_queued: Some(1 << 10),
+                ..ListenerConfig::new("127.0.0.1:8")
+            });
+        }) => "listener 127.0.0.1:8: quiesce_max can't exceed max_queued";
+        #[cfg(feature = "tls")]
+        listener_tls_cert_without_key: with(|c| {
+            c.listeners.push(ListenerConfig {
+                tls_cert: Some("cert.pem".into()),
+                ..ListenerConfig::new("127.0.0.1:8")
+            });
+        }) => "listener 127.0.0.1:8: tls_cert and tls_key go together";
+        #[cfg(not(feature = "tls"))]
+        listener_tls_without_the_feature: with(|c| {
+            c.listeners.push(ListenerConfig {
+                tls_cert: Some("cert.pem".into()),
+                tls_key: Some("key.pem".into()),
+                ..ListenerConfig::new("127.0.0.1:8")
+            });
+        }) => "listener 127.0.0.1:8: tls_cert needs the tls feature";
+        health_addr_on_listen: with(|c| c.health_addr = c.listen.clone()) => "health_addr must differ from listen";
+        admin_addr_on_health_addr: with(|c| {
+            c.health_addr = Some("127.0.0.1:8".into());
+            c.admin_addr = c.health_addr.clone();
+        }) => "admin_addr must differ from listen and health_addr";
+        #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+        uring_not_compiled_in: with(|c| c.backend = Backend::Uring) => "io_uring support is not compiled in";
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        uring_without_listen: with(|c| {
+            c.backend = Backend::Uring;
+            c.listen = None;
+            c.unix_stream = Some("/tmp/echo.sock".into());
+        }) => "the uring backend needs listen";
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        uring_unsupported_option: with(|c| {
+            c.backend = Backend::Uring;
+            c.read_buf_size = 2 * DEFAULT_BUF_SIZE;
+        }) => "read_buf_size is not supported by the uring backend";
+    }
+
+    #[cfg(feature = "serde")]
+    mod files {
+        use std::process;
+
+        use super::*;
+
+        // A file of its own per test, removed when dropped
+        struct TempFile(PathBuf);
+
+        impl TempFile {
+            fn new(name: &str, contents: &str) -> TempFile {
+                let path = std::env::temp_dir().join(format!("mio-echo-server-{}-{}.toml", process::id(), name));
+                fs::write(&path, contents).unwrap();
+                TempFile(path)
+            }
+        }
+
+        impl Drop for TempFile {
+            fn drop(&mut self) {
+                let _ = fs::remove_file(&self.0);
+            }
+        }
+
+        fn round_trip(config: &Config) -> Config {
+            toml::from_str(&toml::to_string(config).unwrap()).unwrap()
+        }
+
+        #[test]
+        fn defaults_round_trip() {
+            assert_eq!(round_trip(&Config::default()), Config::default());
+            assert_eq!(round_trip(&valid()), valid());
+        }
+
+        #[test]
+        fn every_kind_of_field_round_trips() {
+            let config = with(|c| {
+                c.udp = Some("127.0.0.1:7".into());
+                c.listeners.push(ListenerConfig {
+                    mode: Some(Mode::Length),
+                    checksum: Some(true),
+                    max_queued: Some(1 << 10),
+                    overflow: Some(Overflow::DropOldest),
+                    tls_cert: Some("cert.pem".into()),
+                    tls_key: Some("key.pem".into()),
+                    ..ListenerConfig::new("127.0.0.1:8")
+                });
+                c.mode = Mode::Line;
+                c.banner = Some(b"hello\r\n".to_vec());
+                c.idle_timeout = Some(Duration::from_millis(1500));
+                c.stats_interval = Duration::from_secs(1);
+                c.log_level = LevelFilter::Debug;
+                c.log_syslog = Some(SyslogTarget::Udp("127.0.0.1:514".into()));
+                c.bans = vec![("10.0.0.1".parse().unwrap(), Duration::from_secs(60))];
+                c.max_queued = Some(64 << 10);
+                c.overflow = Overflow::Disconnect;
+                c.read_buf_size = 16 << 10;
+                c.backend = Backend::Mio;
+                c.access_log_format = AccessLogFormat::Csv;
+            });
+            assert_eq!(round_trip(&config), config);
+        }
+
+        #[test]
+        fn from_file_fills_in_the_defaults() {
+            let file = TempFile::new(
+                "defaults",
+                r#"
+                    listen = "127.0.0.1:7"
+                    mode = "line"
+                    idle_timeout = { secs = 30, nanos = 0 }
+
+                    [[listener]]
+                    addr = "127.0.0.1:8"
+                    mode = "length"
+                "#,
+            );
+            let config = Config::from_file(&file.0).unwrap();
+            assert_eq!(
+                config,
+                with(|c| {
+                    c.mode = Mode::Line;
+                    c.idle_timeout = Some(Duration::from_secs(30));
+                    c.listeners.push(ListenerConfig {
+                        mode: Some(Mode::Length),
+                        ..ListenerConfig::new("127.0.0.1:8")
+                    });
+                })
+            );
+        }
+
+        #[test]
+        fn from_file_rejects_unknown_fields() {
+            let file = TempFile::new("unknown", "listen = \"127.0.0.1:7\"\nmax_client = 10\n");
+            let e = Config::from_file(&file.0).unwrap_err();
+            assert!(matches!(e, Error::Invalid { .. }), "{:?}", e);
+            assert!(e.to_string().contains("max_client"), "{}", e);
+        }
+
+        #[test]
+        fn from_file_reports_missing_files() {
+            let e = Config::from_file("/nonexistent/echo.toml").unwrap_err();
+            assert!(matches!(e, Error::File { .. }), "{:?}", e);
+        }
+
+        #[test]
+        fn options_override_the_file() {
+            let file = TempFile::new("override", "listen = \"127.0.0.1:7\"\nmode = \"line\"\nmax_clients = 10\n");
+            let line = format!("--mode length --config {} --listener 127.0.0.1:8", file.0.display());
+            let config = args(&line).unwrap();
+            assert_eq!(config.listen.as_deref(), Some("127.0.0.1:7"));
+            assert_eq!(config.mode, Mode::Length);
+            assert_eq!(config.max_clients, 10);
+            assert_eq!(config.listeners, vec![ListenerConfig::new("127.0.0.1:8")]);
+        }
+
+        #[test]
+        fn options_validate_the_file() {
+            let file = TempFile::new("invalid", "listen = \"127.0.0.1:7\"\nread_buf_size = 0\n");
+            let e = args(&format!("--config {}", file.0.display())).unwrap_err();
+            assert_eq!(e.to_string(), "read_buf_size must be positive");
+        }
+    }
+}
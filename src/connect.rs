@@ -0,0 +1,199 @@
+//! A netcat-style client bridging stdin and stdout to a TCP connection,
+//! for poking at a server by hand.
+//!
+//! Lines read from stdin are sent as they complete and whatever comes
+//! back is printed. Once stdin ends, the write half is shut down and the
+//! client waits for the server to close.
+
+use std::io::{self, BufRead, Read, Write};
+use std::net::{Shutdown, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mio::net::TcpStream;
+use mio::{Events, Interest, Poll, Token, Waker};
+
+use crate::config::parse_duration;
+use crate::dump::HexDump;
+use crate::Error;
+
+const SOCKET: Token = Token(0);
+const STDIN: Token = Token(1);
+const READ_SIZE: usize = 16 * 1024;
+
+/// How `connect` sends and prints.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectOptions {
+    /// Ends the lines sent with CR LF instead of LF, e.g. for telnet mode.
+    pub crlf: bool,
+    /// Prints what comes back as a hex dump.
+    pub hex: bool,
+    /// Ends the session after this long.
+    pub timeout: Option<Duration>,
+}
+
+impl ConnectOptions {
+    /// Parses the arguments following `connect`: `HOST:PORT [--crlf]
+    /// [--hex] [--timeout TIME]`, returning the address too.
+    pub fn from_args<I>(args: I) -> Result<(String, ConnectOptions), Error>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut options = ConnectOptions::default();
+        let mut addr = None;
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--crlf" => options.crlf = true,
+                "--hex" => options.hex = true,
+                "--timeout" => {
+                    let value = args.next().ok_or_else(|| Error::config("missing value for --timeout"))?;
+                    options.timeout = Some(parse_duration(&value)?);
+                }
+                _ if arg.starts_with("--") => return Err(Error::config(format!("unknown option: {}", arg))),
+                _ if addr.is_none() => addr = Some(arg),
+                _ => return Err(Error::config(format!("unexpected argument: {}", arg))),
+            }
+        }
+        let addr = addr.ok_or_else(|| Error::config("connect needs HOST:PORT"))?;
+        Ok((addr, options))
+    }
+}
+
+/// Sends stdin to `addr` line by line and prints what comes back until
+/// the server closes or the timeout runs out, reporting on stderr why the
+/// session ended and the bytes sent and received.
+pub fn connect(addr: &str, options: &ConnectOptions) -> Result<(), Error> {
+    let addr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| Error::config(format!("{}: no address found", addr)))?;
+    let mut poll = Poll::new()?;
+    let mut sock = TcpStream::connect(addr)?;
+    poll.registry().register(&mut sock, SOCKET, Interest::READABLE | Interest::WRITABLE)?;
+    // Kept here too, the reader closing it on EOF would lose a wakeup not
+    // polled yet
+    let waker = Arc::new(Waker::new(poll.registry(), STDIN)?);
+    let lines = read_stdin(Arc::clone(&waker), options.crlf);
+
+    let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+    let mut events = Events::with_capacity(16);
+    let mut out = Vec::new();
+    let mut buf = vec![0; READ_SIZE];
+    let (mut sent, mut received) = (0u64, 0u64);
+    let mut stdin_done = false;
+    let mut shut = false;
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let end = 'session: loop {
+        let timeout = match deadline {
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    break "session timeout";
+                }
+                Some(deadline - now)
+            }
+            None => None,
+        };
+        poll.poll(&mut events, timeout)?;
+        for event in &events {
+            if event.token() == STDIN {
+                for line in lines.try_iter() {
+                    match line {
+                        Some(line) => out.extend_from_slice(&line),
+                        None => stdin_done = true,
+                    }
+                }
+                continue;
+            }
+            if event.is_readable() {
+                loop {
+                    match sock.read(&mut buf) {
+                        Ok(0) => break 'session "connection closed by server",
+                        Ok(len) => {
+                            received += len as u64;
+                            if options.hex {
+                                writeln!(stdout, "{}", HexDump::new(&buf[..len], len))?;
+                            } else {
+                                stdout.write_all(&buf[..len])?;
+                            }
+                            stdout.flush()?;
+                        }
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                        Err(ref e) if e.kind() == io::ErrorKind::ConnectionReset => {
+                            break 'session "connection reset by server";
+                        }
+                        Err(e) => return Err(Error::peer(addr, e)),
+                    }
+                }
+            }
+        }
+
+        // Sent once the connection is up, a write before that would block
+        while !out.is_empty() {
+            match sock.write(&out) {
+                Ok(len) => {
+                    sent += len as u64;
+                    out.drain(..len);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::NotConnected => break,
+                Err(ref e) if matches!(e.kind(), io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset) => {
+                    break 'session "connection closed by server";
+                }
+                Err(e) => return Err(Error::peer(addr, e)),
+            }
+        }
+        if stdin_done && out.is_empty() && !shut {
+            match sock.shutdown(Shutdown::Write) {
+                Ok(()) => shut = true,
+                Err(ref e) if e.kind() == io::ErrorKind::NotConnected => {}
+                Err(e) => return Err(Error::peer(addr, e)),
+            }
+        }
+    };
+
+    eprintln!("{}, {} bytes sent, {} bytes received", end, sent, received);
+    Ok(())
+}
+
+// Reads stdin on a thread of its own, which mio can't poll, and passes
+// on every line, ended as asked, then `None` once stdin ends
+fn read_stdin(waker: Arc<Waker>, crlf: bool) -> Receiver<Option<Vec<u8>>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut stdin = stdin.lock();
+        loop {
+            let mut line = Vec::new();
+            let line = match stdin.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => None,
+                Ok(_) => {
+                    if line.ends_with(b"\n") {
+                        line.pop();
+                        if line.ends_with(b"\r") {
+                            line.pop();
+                        }
+                        line.extend_from_slice(if crlf { b"\r\n" } else { b"\n" });
+                    }
+                    Some(line)
+                }
+            };
+            let done = line.is_none();
+            if tx.send(line).is_err() {
+                return;
+            }
+            // Woken after queueing, the loop reads the whole queue
+            let _ = waker.wake();
+            if done {
+                return;
+            }
+        }
+    });
+    rx
+}
@@ -0,0 +1,125 @@
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use log::debug;
+use mio::{Interest, Registry, Token};
+use slab::Slab;
+
+use crate::stream::{PeerAddr, Stream};
+
+/// Refused connections being told why at once, more are just dropped.
+pub const MAX_COURTESY: usize = 64;
+/// How long a refused connection may take to accept the message.
+pub const COURTESY_TIMEOUT: Duration = Duration::from_secs(1);
+
+struct Refused {
+    sock: Stream,
+    peer: PeerAddr,
+    written: usize,
+    deadline: Instant,
+}
+
+/// Writes a short message to connections refused at capacity before
+/// closing them, apart from the clients.
+///
+/// Uses `MAX_COURTESY` tokens from its base.
+pub struct Courtesy {
+    message: Vec<u8>,
+    refused: Slab<Refused>,
+    token_base: usize,
+}
+
+impl Courtesy {
+    pub fn new(message: Vec<u8>, token_base: usize) -> Courtesy {
+        Courtesy {
+            message,
+            refused: Slab::with_capacity(MAX_COURTESY),
+            token_base,
+        }
+    }
+
+    /// Sends the message to a refused connection, returns the deadline of
+    /// the timer to arm if it must wait for the socket to be writable.
+    pub fn refuse(&mut self, registry: &Registry, sock: Stream, peer: PeerAddr, now: Instant) -> Option<(usize, Instant)> {
+        if self.refused.len() == MAX_COURTESY {
+            debug!("too many refused connections, dropped : {}", peer);
+            return None;
+        }
+        let deadline = now + COURTESY_TIMEOUT;
+        let entry = self.refused.vacant_entry();
+        let index = entry.key();
+        entry.insert(Refused {
+            sock,
+            peer,
+            written: 0,
+            deadline,
+        });
+        if self.write(index) {
+            self.close(index);
+            return None;
+        }
+        let token = Token(self.token_base + index);
+        if let Err(e) = registry.register(&mut self.refused[index].sock, token, Interest::WRITABLE) {
+            debug!("register failed: {} : {}", e, peer);
+            self.close(index);
+            return None;
+        }
+        Some((index, deadline))
+    }
+
+    /// Handles an event, returns false if its token isn't ours.
+    pub fn ready(&mut self, token: Token) -> bool {
+        let index = match token.0.checked_sub(self.token_base) {
+            Some(index) if index < MAX_COURTESY => index,
+            _ => return false,
+        };
+        if self.refused.contains(index) && self.write(index) {
+            self.close(index);
+        }
+        true
+    }
+
+    /// Gives up on a connection that didn't take the message in time.
+    pub fn expire(&mut self, index: usize, now: Instant) {
+        match self.refused.get(index) {
+            Some(refused) if refused.deadline <= now => {
+                debug!("busy message timed out : {}", refused.peer);
+                self.close(index);
+            }
+            _ => {}
+        }
+    }
+
+    /// Drops every refused connection.
+    pub fn clear(&mut self, registry: &Registry) {
+        for mut refused in self.refused.drain() {
+            let _ = registry.deregister(&mut refused.sock);
+        }
+    }
+
+    // Returns whether the connection is done with
+    fn write(&mut self, index: usize) -> bool {
+        let refused = &mut self.refused[index];
+        while refused.written < self.message.len() {
+            match refused.sock.write(&self.message[refused.written..]) {
+                Ok(len) => refused.written += len,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return false,
+                Err(_) => return true,
+            }
+        }
+        true
+    }
+
+    // Dropping the socket unregisters it
+    fn close(&mut self, index: usize) {
+        let mut refused = self.refused.remove(index);
+        // Unread data would make closing reset the connection, and the
+        // peer could lose the message
+        let mut rbuf = [0; 1024];
+        while let Ok(len) = refused.sock.read(&mut rbuf) {
+            if len == 0 {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Networks refused from `Config::deny_file`, reloaded when it changes.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+#[cfg(unix)]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+
+use crate::Error;
+
+/// Set by SIGHUP, taken by `reload_requested`.
+#[cfg(unix)]
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+/// Whether SIGHUP arrived since the last call.
+#[cfg(unix)]
+pub fn reload_requested() -> bool {
+    RELOAD.swap(false, Ordering::Relaxed)
+}
+
+/// A network in CIDR notation, a lone address being a /32 or /128.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Net {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Net {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        ip.is_ipv4() == self.addr.is_ipv4() && mask(ip, self.prefix) == self.addr
+    }
+}
+
+// `ip` with the bits past `prefix` cleared
+fn mask(ip: IpAddr, prefix: u8) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            IpAddr::V4((u32::from(ip) & mask).into())
+        }
+        IpAddr::V6(ip) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            IpAddr::V6((u128::from(ip) & mask).into())
+        }
+    }
+}
+
+impl FromStr for Net {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Net, Error> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| Error::config(format!("invalid address: {}", addr)))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => match prefix.parse::<u8>() {
+                Ok(prefix) if prefix <= max => prefix,
+                _ => return Err(Error::config(format!("invalid prefix length: {}", prefix))),
+            },
+            None => max,
+        };
+        // 10.1.2.3/8 is 10.0.0.0/8
+        Ok(Net {
+            addr: mask(addr, prefix),
+            prefix,
+        })
+    }
+}
+
+impl fmt::Display for Net {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Parses one network per line, skipping blank ones and `#` comments.
+pub fn parse_nets(text: &str) -> Result<BTreeSet<Net>, Error> {
+    let mut nets = BTreeSet::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        nets.insert(line.parse().map_err(|e| Error::config(format!("line {}: {}", n + 1, e)))?);
+    }
+    Ok(nets)
+}
+
+/// What a reload changed.
+pub struct Reload {
+    pub added: Vec<Net>,
+    pub removed: Vec<Net>,
+}
+
+/// The networks of a deny file, as last read.
+pub struct DenyFile {
+    path: PathBuf,
+    /// Modification time of the file last read, or which failed to parse.
+    modified: Option<SystemTime>,
+    nets: BTreeSet<Net>,
+}
+
+impl DenyFile {
+    /// Reads `path`, failing if any line doesn't parse. Reloads are then
+    /// asked for with SIGHUP.
+    pub fn load(path: &Path) -> Result<DenyFile, Error> {
+        let modified = fs::metadata(path).and_then(|meta| meta.modified()).ok();
+        let text = fs::read_to_string(path).map_err(|source| Error::File {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let nets = parse_nets(&text).map_err(|e| Error::Invalid {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        #[cfg(unix)]
+        crate::signal::watch(libc::SIGHUP, &RELOAD)?;
+        Ok(DenyFile {
+            path: path.to_path_buf(),
+            modified,
+            nets,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> usize {
+        self.nets.len()
+    }
+
+    /// Whether connections from `ip` must be refused.
+    pub fn denies(&self, ip: IpAddr) -> bool {
+        self.nets.iter().any(|net| net.contains(ip))
+    }
+
+    /// Rereads the file if `forced` or it was modified since the last
+    /// read, swapping the networks in only if the whole file parses.
+    /// Returns what changed, `Ok(None)` if it wasn't reread.
+    pub fn check(&mut self, forced: bool) -> Result<Option<Reload>, Error> {
+        let modified = fs::metadata(&self.path).and_then(|meta| meta.modified()).ok();
+        if !forced && modified == self.modified {
+            return Ok(None);
+        }
+        // A broken file is reported once, not on every check
+        self.modified = modified;
+        let text = fs::read_to_string(&self.path)?;
+        let nets = parse_nets(&text)?;
+        let reload = Reload {
+            added: nets.difference(&self.nets).copied().collect(),
+            removed: self.nets.difference(&nets).copied().collect(),
+        };
+        self.nets = nets;
+        Ok(Some(reload))
+    }
+}
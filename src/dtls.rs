@@ -0,0 +1,325 @@
+//! DTLS termination of the UDP peers with OpenSSL, see `Config::dtls_cert`.
+//!
+//! Every peer address gets a session of its own, fed the datagrams of the
+//! address one at a time. A session only ever sends datagrams back to its
+//! peer: the flights of the handshake, then the echo of what was
+//! decrypted, encrypted again.
+//!
+//! A handshake starts with a cookie exchange: the first ClientHello of a
+//! peer is answered with a HelloVerifyRequest carrying an HMAC of its
+//! address, the certificate only going to a peer that sends the cookie
+//! back. A spoofed address can't, so it can't have the server send a
+//! large flight to a victim, and its session, still shaking hands, is the
+//! first dropped to make room.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use openssl::error::ErrorStack;
+use openssl::ex_data::Index;
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkey::{PKey, Private};
+use openssl::rand::rand_bytes;
+use openssl::sign::Signer;
+use openssl::ssl::{self, ErrorCode, Ssl, SslContext, SslFiletype, SslMethod, SslOptions, SslStream, SslVerifyMode};
+use openssl::x509::{X509Name, X509Ref};
+
+use crate::config::Config;
+use crate::stats::DtlsStats;
+use crate::Error;
+
+/// Largest datagram sent, which fits the IPv6 minimum MTU with room to
+/// spare.
+const MTU: u32 = 1200;
+/// Largest plaintext of a record.
+const MAX_PLAINTEXT: usize = 16384;
+/// Longest a session may stay quiet before its handshake is done.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The sessions of the UDP peers, `None` without `Config::dtls_cert`.
+pub fn sessions(config: &Config) -> Result<Option<Sessions>, Error> {
+    let (cert, key) = match (&config.dtls_cert, &config.dtls_key) {
+        (Some(cert), Some(key)) => (cert, key),
+        _ => return Ok(None),
+    };
+    let mut builder = SslContext::builder(SslMethod::dtls()).map_err(setup)?;
+    builder.set_certificate_chain_file(cert).map_err(|e| invalid(cert, e))?;
+    builder.set_private_key_file(key, SslFiletype::PEM).map_err(|e| invalid(key, e))?;
+    builder.check_private_key().map_err(|e| invalid(key, e))?;
+    // The MTU is set on each session, the datagrams never reaching a real
+    // BIO to ask
+    builder.set_options(SslOptions::COOKIE_EXCHANGE | SslOptions::NO_QUERY_MTU);
+    if let Some(ref ca) = config.dtls_client_ca {
+        builder.set_ca_file(ca).map_err(|e| invalid(ca, e))?;
+        builder.set_client_ca_list(X509Name::load_client_ca_file(ca).map_err(|e| invalid(ca, e))?);
+        builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    }
+
+    let peer = Ssl::new_ex_index().map_err(setup)?;
+    let mut secret = [0; 32];
+    rand_bytes(&mut secret).map_err(setup)?;
+    let secret = PKey::hmac(&secret).map_err(setup)?;
+    let generate_secret = secret.clone();
+    builder.set_cookie_generate_cb(move |ssl, buf| {
+        let cookie = cookie(&generate_secret, ssl.ex_data(peer))?;
+        buf[..cookie.len()].copy_from_slice(&cookie);
+        Ok(cookie.len())
+    });
+    builder.set_cookie_verify_cb(move |ssl, received| match cookie(&secret, ssl.ex_data(peer)) {
+        Ok(cookie) => cookie.len() == received.len() && memcmp::eq(&cookie, received),
+        Err(_) => false,
+    });
+
+    Ok(Some(Sessions {
+        context: builder.build(),
+        peer,
+        sessions: HashMap::new(),
+        idle: config.dtls_session_idle,
+        max: config.dtls_max_sessions,
+        buf: vec![0; MAX_PLAINTEXT],
+        totals: DtlsStats::default(),
+    }))
+}
+
+// The cookie of a peer, an HMAC of its address
+fn cookie(secret: &PKey<Private>, peer: Option<&SocketAddr>) -> Result<Vec<u8>, ErrorStack> {
+    let mut signer = Signer::new(MessageDigest::sha256(), secret)?;
+    if let Some(peer) = peer {
+        signer.update(peer.to_string().as_bytes())?;
+    }
+    signer.sign_to_vec()
+}
+
+fn setup(e: ErrorStack) -> Error {
+    Error::System {
+        what: "dtls setup".to_string(),
+        source: io::Error::other(e),
+    }
+}
+
+fn invalid(path: &Path, e: ErrorStack) -> Error {
+    Error::Invalid {
+        path: path.to_path_buf(),
+        message: e.to_string(),
+    }
+}
+
+/// The DTLS sessions of a UDP socket, by peer address.
+pub struct Sessions {
+    context: SslContext,
+    /// Where each session keeps its peer address, for the cookie
+    /// callbacks.
+    peer: Index<Ssl, SocketAddr>,
+    sessions: HashMap<SocketAddr, Session>,
+    idle: Duration,
+    max: usize,
+    /// Plaintext of the record being echoed.
+    buf: Vec<u8>,
+    pub totals: DtlsStats,
+}
+
+struct Session {
+    stream: SslStream<Channel>,
+    established: bool,
+    /// When the peer last sent a datagram.
+    last: Instant,
+}
+
+/// The datagrams in and out of a session: a read takes the datagram
+/// received, if not taken yet, a write sends a datagram.
+#[derive(Default)]
+struct Channel {
+    received: Option<Vec<u8>>,
+    sent: Vec<Vec<u8>>,
+}
+
+impl Read for Channel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.received.take() {
+            Some(datagram) => {
+                let len = datagram.len().min(buf.len());
+                buf[..len].copy_from_slice(&datagram[..len]);
+                Ok(len)
+            }
+            None => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl Write for Channel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sent.push(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Sessions {
+    /// Feeds a datagram of `peer` to its session, opening one for a
+    /// ClientHello, and returns the datagrams to send back.
+    pub fn received(&mut self, peer: SocketAddr, data: &[u8], now: Instant) -> Vec<Vec<u8>> {
+        if self.sessions.get(&peer).is_some_and(|session| session.expired(now, self.idle)) {
+            debug!("dtls session expired : {}", peer);
+            self.sessions.remove(&peer);
+            self.totals.expired += 1;
+        }
+        if !self.sessions.contains_key(&peer) {
+            if !is_client_hello(data) {
+                self.totals.strays += 1;
+                return Vec::new();
+            }
+            if self.sessions.len() >= self.max {
+                self.evict();
+            }
+            match self.open(peer, now) {
+                Ok(session) => {
+                    self.sessions.insert(peer, session);
+                }
+                Err(e) => {
+                    warn!("dtls session failed: {} : {}", e, peer);
+                    return Vec::new();
+                }
+            }
+        }
+
+        let session = self.sessions.get_mut(&peer).expect("session");
+        session.last = now;
+        session.stream.get_mut().received = Some(data.to_vec());
+        let established = session.established;
+        let result = session.ready(peer, &mut self.buf);
+        if let Ok(false) = result {
+            // Acknowledge the close_notify
+            let _ = session.stream.shutdown();
+        }
+        if !established && session.established {
+            self.totals.handshakes += 1;
+        }
+        let sent = mem::take(&mut session.stream.get_mut().sent);
+        match result {
+            Ok(true) => {}
+            Ok(false) => {
+                debug!("dtls session closed : {}", peer);
+                self.sessions.remove(&peer);
+                self.totals.closed += 1;
+            }
+            Err(e) => {
+                if session.established {
+                    warn!("dtls error={} : {}", e, peer);
+                    self.totals.errors += 1;
+                } else {
+                    warn!("dtls handshake failed: {} : {}", e, peer);
+                    self.totals.handshake_failures += 1;
+                }
+                self.sessions.remove(&peer);
+            }
+        }
+        self.totals.sessions = self.sessions.len() as u64;
+        sent
+    }
+
+    /// Drops the sessions quiet for too long.
+    pub fn expire(&mut self, now: Instant) {
+        let idle = self.idle;
+        let before = self.sessions.len();
+        self.sessions.retain(|peer, session| {
+            let expired = session.expired(now, idle);
+            if expired {
+                debug!("dtls session expired : {}", peer);
+            }
+            !expired
+        });
+        self.totals.expired += (before - self.sessions.len()) as u64;
+        self.totals.sessions = self.sessions.len() as u64;
+    }
+
+    fn open(&self, peer: SocketAddr, now: Instant) -> Result<Session, ErrorStack> {
+        let mut ssl = Ssl::new(&self.context)?;
+        ssl.set_ex_data(self.peer, peer);
+        ssl.set_mtu(MTU)?;
+        Ok(Session {
+            stream: SslStream::new(ssl, Channel::default())?,
+            established: false,
+            last: now,
+        })
+    }
+
+    // Drops a session still shaking hands, or else the least recently
+    // used one
+    fn evict(&mut self) {
+        let victim = self
+            .sessions
+            .iter()
+            .min_by_key(|(_, session)| (session.established, session.last))
+            .map(|(&peer, _)| peer);
+        if let Some(peer) = victim {
+            debug!("dtls session evicted : {}", peer);
+            self.sessions.remove(&peer);
+            self.totals.evicted += 1;
+        }
+    }
+}
+
+impl Session {
+    fn expired(&self, now: Instant, idle: Duration) -> bool {
+        let timeout = if self.established { idle } else { idle.min(HANDSHAKE_TIMEOUT) };
+        now.saturating_duration_since(self.last) >= timeout
+    }
+
+    // Goes on with the handshake, then echoes the records received, as
+    // far as the datagram received allows. `Ok(false)` once the peer
+    // closed the session
+    fn ready(&mut self, peer: SocketAddr, buf: &mut [u8]) -> Result<bool, ssl::Error> {
+        if !self.established {
+            match self.stream.accept() {
+                Ok(()) => {
+                    self.established = true;
+                    match self.stream.ssl().peer_certificate() {
+                        Some(cert) => info!("dtls session established, client certificate {} : {}", subject(&cert), peer),
+                        None => info!("dtls session established : {}", peer),
+                    }
+                }
+                Err(ref e) if e.code() == ErrorCode::WANT_READ => return Ok(true),
+                Err(e) => return Err(e),
+            }
+        }
+        loop {
+            match self.stream.ssl_read(buf) {
+                Ok(len) => {
+                    self.stream.ssl_write(&buf[..len])?;
+                }
+                Err(ref e) if e.code() == ErrorCode::WANT_READ => return Ok(true),
+                Err(ref e) if e.code() == ErrorCode::ZERO_RETURN => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+// A handshake record of epoch 0 holding a ClientHello, the start of every
+// handshake
+fn is_client_hello(datagram: &[u8]) -> bool {
+    datagram.len() > 13 && datagram[0] == 22 && datagram[3..5] == [0, 0] && datagram[13] == 1
+}
+
+// The subject of a certificate, e.g. `CN=alice, O=Lab`
+fn subject(cert: &X509Ref) -> String {
+    let entries: Vec<String> = cert
+        .subject_name()
+        .entries()
+        .map(|entry| {
+            let name = entry.object().nid().short_name().unwrap_or("?");
+            let value = entry.data().to_string().unwrap_or_default();
+            format!("{}={}", name, value)
+        })
+        .collect();
+    entries.join(", ")
+}
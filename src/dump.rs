@@ -0,0 +1,100 @@
+//! Hex dumps of payloads for trace logging.
+
+use std::fmt;
+
+/// Payload bytes shown per I/O event unless configured otherwise.
+pub const DEFAULT_DUMP_LIMIT: usize = 256;
+
+const ROW: usize = 16;
+
+/// Formats bytes as offset, hex and ASCII columns, 16 bytes per row:
+///
+/// ```text
+/// 00000000  68 65 6c 6c 6f 0a 00 ff                           |hello...|
+/// ```
+///
+/// Nothing is formatted, let alone allocated, until it is displayed, so a
+/// dump passed to a disabled log level costs nothing.
+pub struct HexDump<'a> {
+    data: &'a [u8],
+    limit: usize,
+}
+
+impl<'a> HexDump<'a> {
+    /// Dumps at most `limit` bytes of `data`, then counts the rest.
+    pub fn new(data: &'a [u8], limit: usize) -> HexDump<'a> {
+        HexDump { data, limit }
+    }
+}
+
+impl<'a> fmt::Display for HexDump<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let shown = &self.data[..self.data.len().min(self.limit)];
+
+        for (i, row) in shown.chunks(ROW).enumerate() {
+            if i > 0 {
+                f.write_str("\n")?;
+            }
+            write!(f, "{:08x} ", i * ROW)?;
+            for col in 0..ROW {
+                // Extra space between the two halves of a row
+                if col % 8 == 0 {
+                    f.write_str(" ")?;
+                }
+                match row.get(col) {
+                    Some(byte) => write!(f, "{:02x} ", byte)?,
+                    None => f.write_str("   ")?,
+                }
+            }
+            f.write_str(" |")?;
+            for &byte in row {
+                let c = if byte == b' ' || byte.is_ascii_graphic() { byte as char } else { '.' };
+                write!(f, "{}", c)?;
+            }
+            f.write_str("|")?;
+        }
+
+        let rest = self.data.len() - shown.len();
+        if rest > 0 {
+            if !shown.is_empty() {
+                f.write_str("\n")?;
+            }
+            write!(f, "\u{2026} {} more bytes", rest)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A full row, then a half one with bytes that don't print
+    const DATA: &[u8] = b"hello, world!\n\x00\x01\x7f\xff\x80 tail";
+
+    const FIRST_ROW: &str = "00000000  68 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 01  |hello, world!...|";
+    const SECOND_ROW: &str = "00000010  7f ff 80 20 74 61 69 6c                           |... tail|";
+
+    #[test]
+    fn rows_of_hex_and_ascii() {
+        let dump = HexDump::new(DATA, DEFAULT_DUMP_LIMIT).to_string();
+        assert_eq!(dump, format!("{}\n{}", FIRST_ROW, SECOND_ROW));
+    }
+
+    #[test]
+    fn the_rest_is_counted_past_the_limit() {
+        assert_eq!(HexDump::new(DATA, 16).to_string(), format!("{}\n\u{2026} 8 more bytes", FIRST_ROW));
+        assert_eq!(
+            HexDump::new(DATA, 20).to_string(),
+            "00000000  68 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 01  |hello, world!...|\n\
+             00000010  7f ff 80 20                                       |... |\n\
+             \u{2026} 4 more bytes"
+        );
+        assert_eq!(HexDump::new(DATA, 0).to_string(), "\u{2026} 24 more bytes");
+    }
+
+    #[test]
+    fn nothing_to_dump() {
+        assert_eq!(HexDump::new(&[], DEFAULT_DUMP_LIMIT).to_string(), "");
+    }
+}
@@ -0,0 +1,119 @@
+//! What can go wrong, by category so callers can tell a busy address from
+//! a bad setting without parsing messages.
+
+use std::io;
+use std::net::{AddrParseError, SocketAddr};
+use std::path::PathBuf;
+
+use mio::Token;
+use thiserror::Error;
+
+use crate::handle::ListenerId;
+
+/// The error of every fallible call of the crate.
+#[derive(Debug, Error)]
+pub enum EchoError {
+    /// A setting or an argument is invalid, conflicts with another, or
+    /// needs support that isn't compiled in or available on this
+    /// platform.
+    #[error("{0}")]
+    Config(String),
+    /// Binding a listening socket failed, after `Config::bind_retry` if
+    /// set.
+    #[error("bind {addr} failed: {source}")]
+    Bind { addr: SocketAddr, source: io::Error },
+    /// Accepting failed for a reason retrying won't fix, e.g. the open
+    /// file limit.
+    #[error("accept failed: {0}")]
+    Accept(#[source] io::Error),
+    /// An I/O error, with the token of the socket and its peer when known.
+    #[error("{}", describe_io(*.token, .peer.as_deref(), .source))]
+    Io {
+        token: Option<Token>,
+        peer: Option<String>,
+        source: io::Error,
+    },
+    /// More clients asked for than can be served at once: past what the
+    /// open file limit fits, or past the `Config::max_clients` the server
+    /// started with.
+    #[error("too many clients: {requested} asked for, {max} at most")]
+    TooManyClients { requested: usize, max: usize },
+    /// The token range leaves no room for what was asked for.
+    #[error("no token left for {0}")]
+    OutOfTokens(&'static str),
+    /// The listener wasn't added through a `ServerHandle`, or was removed.
+    #[error("no {0}")]
+    UnknownListener(ListenerId),
+    /// The server stopped accepting, nothing can be added to it.
+    #[error("the server is draining")]
+    Draining,
+    /// The server a `ServerHandle` controls was dropped.
+    #[error("the server is gone")]
+    ServerGone,
+    /// A file couldn't be read or written.
+    #[error("{path}: {source}")]
+    File { path: PathBuf, source: io::Error },
+    /// A file was read but its contents don't make sense, e.g. a
+    /// certificate or a capture.
+    #[error("{path}: {message}")]
+    Invalid { path: PathBuf, message: String },
+    /// A system call setting up the process failed, e.g. chroot.
+    #[error("{what} failed: {source}")]
+    System { what: String, source: io::Error },
+}
+
+impl EchoError {
+    pub(crate) fn config<S: Into<String>>(message: S) -> EchoError {
+        EchoError::Config(message.into())
+    }
+
+    /// An I/O error with the peer it happened with.
+    pub(crate) fn peer<P: ToString>(peer: P, source: io::Error) -> EchoError {
+        EchoError::Io {
+            token: None,
+            peer: Some(peer.to_string()),
+            source,
+        }
+    }
+
+    /// An I/O error of the socket registered with `token`.
+    pub(crate) fn token(token: Token, source: io::Error) -> EchoError {
+        EchoError::Io {
+            token: Some(token),
+            peer: None,
+            source,
+        }
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn system<S: Into<String>>(what: S, source: io::Error) -> EchoError {
+        EchoError::System {
+            what: what.into(),
+            source,
+        }
+    }
+}
+
+impl From<io::Error> for EchoError {
+    fn from(source: io::Error) -> EchoError {
+        EchoError::Io {
+            token: None,
+            peer: None,
+            source,
+        }
+    }
+}
+
+impl From<AddrParseError> for EchoError {
+    fn from(e: AddrParseError) -> EchoError {
+        EchoError::Config(e.to_string())
+    }
+}
+
+fn describe_io(token: Option<Token>, peer: Option<&str>, source: &io::Error) -> String {
+    match (peer, token) {
+        (Some(peer), _) => format!("{}: {}", peer, source),
+        (None, Some(token)) => format!("token {}: {}", token.0, source),
+        (None, None) => source.to_string(),
+    }
+}
@@ -0,0 +1,43 @@
+//! What a server driven with `Server::next_events` reports, see
+//! `ServerBuilder::events`.
+
+use std::fmt;
+
+use crate::reactor::CloseReason;
+use crate::stream::PeerAddr;
+
+/// A connection of a server driven with `Server::next_events`, valid
+/// until its `ServerEvent::Closed`. A stale one is never mistaken for a
+/// later connection.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Conn {
+    pub(crate) index: usize,
+    pub(crate) id: u64,
+}
+
+impl Conn {
+    /// Identifies the connection as the logs and the admin socket do.
+    pub fn id(self) -> u64 {
+        self.id
+    }
+}
+
+impl fmt::Display for Conn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}", self.id)
+    }
+}
+
+/// What happened on the connections since the last `Server::next_events`.
+#[derive(Debug)]
+pub enum ServerEvent {
+    Accepted { conn: Conn, peer: PeerAddr },
+    /// What one read got, in the buffer it was read into, or one packet
+    /// on packet transports.
+    Data { conn: Conn, bytes: Vec<u8> },
+    /// Everything sent was written after some of it had to wait in the
+    /// queue.
+    WritableAgain { conn: Conn },
+    /// The connection is gone, sending to it does nothing anymore.
+    Closed { conn: Conn, reason: CloseReason },
+}
@@ -0,0 +1,309 @@
+//! Message framing for the line and length modes, with the optional CRC32
+//! trailer.
+
+use std::io;
+use std::ops::Range;
+
+/// Frames larger than this close the connection.
+pub const MAX_FRAME_SIZE: usize = 1 << 20;
+
+const CRC_LEN: usize = 4;
+
+/// How messages are delimited.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Framing {
+    /// Ended by `\n`, which isn't part of the payload.
+    Line,
+    /// Prefixed by their length, 4 bytes big-endian.
+    Length,
+}
+
+/// Echoes whole messages, appending a CRC32 of the payload if `checksum`
+/// is set, and checking the CRC32 the client put at the end of the
+/// payload if `verify` is.
+///
+/// A line is echoed as the payload, the CRC and `\n`, a length prefixed
+/// message as the prefix counting the CRC, the payload and the CRC. The
+/// CRC is 4 bytes big-endian.
+pub struct Framer {
+    framing: Framing,
+    checksum: bool,
+    verify: bool,
+    input: Vec<u8>,
+    /// Start of what is left to decode in `input`.
+    start: usize,
+}
+
+impl Framer {
+    pub fn new(framing: Framing, checksum: bool, verify: bool) -> Framer {
+        Framer {
+            framing,
+            checksum,
+            verify,
+            input: Vec::new(),
+            start: 0,
+        }
+    }
+
+    /// Appends the echo of the messages completed by `input` to `out`.
+    ///
+    /// Fails with `InvalidData` on a frame over `MAX_FRAME_SIZE` or a CRC
+    /// mismatch.
+    pub fn decode(&mut self, input: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        self.feed(input);
+        while self.next_frame(out)? {}
+        Ok(())
+    }
+
+    /// Adds `input` to what `next_frame` decodes.
+    pub fn feed(&mut self, input: &[u8]) {
+        self.input.drain(..self.start);
+        self.start = 0;
+        self.input.extend_from_slice(input);
+    }
+
+    /// Bytes of input not decoded yet, and allocated for the input.
+    pub fn usage(&self) -> (usize, usize) {
+        (self.input.len() - self.start, self.input.capacity())
+    }
+
+    /// Gives back the memory the input holds beyond what is left to
+    /// decode.
+    pub fn shrink(&mut self) {
+        self.input.drain(..self.start);
+        self.start = 0;
+        self.input.shrink_to_fit();
+    }
+
+    /// Appends the echo of the next complete message to `out`, returns
+    /// false if there is none yet. Fails as `decode` does.
+    pub fn next_frame(&mut self, out: &mut Vec<u8>) -> io::Result<bool> {
+        self.next_frame_with(out, |_| Vec::new()).map(|header| header.is_some())
+    }
+
+    /// Like `next_frame`, putting the `header` made for the length of the
+    /// payload at the start of the frame, see `Config::annotate`. Returns
+    /// where the header went in `out`, `None` if there is no message yet.
+    pub fn next_frame_with<F>(&mut self, out: &mut Vec<u8>, header: F) -> io::Result<Option<Range<usize>>>
+    where
+        F: FnOnce(usize) -> Vec<u8>,
+    {
+        let rest = &self.input[self.start..];
+        let (payload, len) = match self.framing {
+            Framing::Line => match rest.iter().position(|&b| b == b'\n') {
+                Some(end) => (&rest[..end], end + 1),
+                None => return check_size(rest.len()).map(|()| None),
+            },
+            Framing::Length => {
+                if rest.len() < 4 {
+                    return Ok(None);
+                }
+                let size = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+                check_size(size)?;
+                if rest.len() < 4 + size {
+                    return Ok(None);
+                }
+                (&rest[4..4 + size], 4 + size)
+            }
+        };
+        if self.verify {
+            verify(payload)?;
+        }
+        let header = header(payload.len());
+        let trailer = if self.checksum { CRC_LEN } else { 0 };
+        if self.framing == Framing::Length {
+            out.extend_from_slice(&((header.len() + payload.len() + trailer) as u32).to_be_bytes());
+        }
+        let start = out.len();
+        out.extend_from_slice(&header);
+        out.extend_from_slice(payload);
+        if self.checksum {
+            let crc = crc32(&out[start..]);
+            out.extend_from_slice(&crc.to_be_bytes());
+        }
+        if self.framing == Framing::Line {
+            out.push(b'\n');
+        }
+        self.start += len;
+        Ok(Some(start..start + header.len()))
+    }
+}
+
+fn check_size(size: usize) -> io::Result<()> {
+    if size > MAX_FRAME_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too large"));
+    }
+    Ok(())
+}
+
+fn verify(payload: &[u8]) -> io::Result<()> {
+    if payload.len() < CRC_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too short for its checksum"));
+    }
+    let (data, trailer) = payload.split_at(payload.len() - CRC_LEN);
+    let sent = u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    let computed = crc32(data);
+    if sent != computed {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("checksum mismatch: sent {:08x}, computed {:08x}", sent, computed),
+        ));
+    }
+    Ok(())
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// CRC-32 as used by Ethernet, zlib and PNG.
+pub fn crc32(data: &[u8]) -> u32 {
+    !data
+        .iter()
+        .fold(!0, |crc, &b| (crc >> 8) ^ CRC_TABLE[((crc ^ b as u32) & 0xff) as usize])
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn line(payload: &[u8]) -> Vec<u8> {
+        let mut frame = payload.to_vec();
+        frame.push(b'\n');
+        frame
+    }
+
+    fn length_prefixed(payload: &[u8]) -> Vec<u8> {
+        let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    // Feeds `input` in chunks of `cuts` bytes, and the rest at once
+    fn decode_in_chunks(framer: &mut Framer, input: &[u8], cuts: &[usize]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut rest = input;
+        for &cut in cuts {
+            let (chunk, after) = rest.split_at(cut.min(rest.len()));
+            framer.decode(chunk, &mut out)?;
+            rest = after;
+        }
+        framer.decode(rest, &mut out)?;
+        Ok(out)
+    }
+
+    fn messages() -> impl Strategy<Value = Vec<Vec<u8>>> {
+        prop::collection::vec(prop::collection::vec(any::<u8>(), 0..200), 0..20)
+    }
+
+    fn cuts() -> impl Strategy<Value = Vec<usize>> {
+        prop::collection::vec(0..64usize, 0..100)
+    }
+
+    proptest! {
+        #[test]
+        fn lines_round_trip(messages in messages(), cuts in cuts()) {
+            let lines: Vec<Vec<u8>> = messages
+                .into_iter()
+                .map(|message| message.into_iter().filter(|&b| b != b'\n').collect())
+                .collect();
+            let input: Vec<u8> = lines.iter().flat_map(|message| line(message)).collect();
+            let mut framer = Framer::new(Framing::Line, false, false);
+            prop_assert_eq!(decode_in_chunks(&mut framer, &input, &cuts).unwrap(), input);
+            prop_assert_eq!(framer.usage().0, 0);
+        }
+
+        #[test]
+        fn length_prefixed_round_trip(messages in messages(), cuts in cuts()) {
+            let input: Vec<u8> = messages.iter().flat_map(|message| length_prefixed(message)).collect();
+            let mut framer = Framer::new(Framing::Length, false, false);
+            prop_assert_eq!(decode_in_chunks(&mut framer, &input, &cuts).unwrap(), input);
+            prop_assert_eq!(framer.usage().0, 0);
+        }
+
+        #[test]
+        fn checksums_verify(messages in messages(), cuts in cuts()) {
+            // What a checksumming framer echoes is what a verifying one takes. Not for lines, whose CRC may hold a
+            // `\n`
+            let input: Vec<u8> = messages.iter().flat_map(|message| length_prefixed(message)).collect();
+            let signed = decode_in_chunks(&mut Framer::new(Framing::Length, true, false), &input, &cuts).unwrap();
+            let verified = decode_in_chunks(&mut Framer::new(Framing::Length, false, true), &signed, &cuts).unwrap();
+            prop_assert_eq!(verified, signed);
+        }
+
+        #[test]
+        fn partial_frames_wait(message in prop::collection::vec(any::<u8>(), 1..200), keep in 1..4usize) {
+            let input = length_prefixed(&message);
+            let cut = input.len() - keep.min(input.len() - 1);
+            let mut framer = Framer::new(Framing::Length, false, false);
+            let mut out = Vec::new();
+            framer.decode(&input[..cut], &mut out).unwrap();
+            prop_assert!(out.is_empty());
+            framer.decode(&input[cut..], &mut out).unwrap();
+            prop_assert_eq!(out, input);
+        }
+
+        #[test]
+        fn any_input_decodes_the_same_in_chunks(input in prop::collection::vec(any::<u8>(), 0..600), cuts in cuts()) {
+            let framers = [
+                (Framing::Line, false),
+                (Framing::Line, true),
+                (Framing::Length, false),
+                (Framing::Length, true),
+            ];
+            for &(framing, verify) in &framers {
+                let mut whole = Vec::new();
+                let at_once = Framer::new(framing, true, verify).decode(&input, &mut whole);
+                let mut framer = Framer::new(framing, true, verify);
+                match (at_once, decode_in_chunks(&mut framer, &input, &cuts)) {
+                    (Ok(()), Ok(chunked)) => {
+                        prop_assert_eq!(chunked, whole);
+                        prop_assert!(framer.usage().0 <= 4 + MAX_FRAME_SIZE);
+                    }
+                    (Err(at_once), Err(chunked)) => prop_assert_eq!(at_once.to_string(), chunked.to_string()),
+                    (at_once, chunked) => prop_assert!(false, "{:?} at once, {:?} in chunks", at_once, chunked),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_frames_fail() {
+        let mut framer = Framer::new(Framing::Length, false, false);
+        let prefix = (MAX_FRAME_SIZE as u32 + 1).to_be_bytes();
+        let e = framer.decode(&prefix, &mut Vec::new()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        let mut framer = Framer::new(Framing::Line, false, false);
+        let e = framer.decode(&vec![b'a'; MAX_FRAME_SIZE + 1], &mut Vec::new()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn bad_checksums_fail() {
+        let mut framer = Framer::new(Framing::Line, false, true);
+        let e = framer.decode(b"abcdefgh\n", &mut Vec::new()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+}
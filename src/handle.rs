@@ -0,0 +1,193 @@
+//! Controlling a running `Server` from other threads, see
+//! `Server::handle` and `Server::start`.
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+
+use mio::{Registry, Token, Waker};
+
+use crate::Error;
+
+/// A listener added through a `ServerHandle`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ListenerId {
+    pub(crate) index: usize,
+    pub(crate) addr: SocketAddr,
+}
+
+impl ListenerId {
+    /// The address it was bound to, e.g. to learn the port picked for a
+    /// `127.0.0.1:0` address.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl fmt::Display for ListenerId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "listener {} ({})", self.index, self.addr)
+    }
+}
+
+/// What a `ServerHandle` asks of the event loop, with where to send the
+/// outcome.
+pub enum Request {
+    AddListener {
+        addr: String,
+        reply: Sender<Result<ListenerId, Error>>,
+    },
+    RemoveListener {
+        id: ListenerId,
+        close_clients: bool,
+        reply: Sender<Result<(), Error>>,
+    },
+    SetMaxClients {
+        max_clients: usize,
+        evict_newest: bool,
+        reply: Sender<Result<(), Error>>,
+    },
+    SetIdleTimeout {
+        timeout: Option<Duration>,
+        reply: Sender<Result<(), Error>>,
+    },
+    SetRateLimit {
+        rate: Option<u64>,
+        reply: Sender<Result<(), Error>>,
+    },
+    Shutdown {
+        reply: Sender<Result<(), Error>>,
+    },
+    /// The answer of the resolver thread, see `PeerNames`.
+    Resolved { ip: IpAddr, name: Option<String> },
+}
+
+/// Sends requests to the event loop of a `Server`, which handles them
+/// between two rounds of events.
+///
+/// Every call waits for the loop to answer, so the server must be served
+/// by another thread meanwhile, e.g. with `Server::run`: calling from the
+/// thread stepping it with `Server::poll_once` would wait forever. Calls
+/// fail once the server is dropped.
+#[derive(Clone)]
+pub struct ServerHandle {
+    tx: Sender<Request>,
+    waker: Arc<Waker>,
+}
+
+impl ServerHandle {
+    /// Binds a TCP listener to `addr` and starts accepting on it, its
+    /// clients getting the top-level settings of the server's `Config`.
+    pub fn add_listener(&self, addr: &str) -> Result<ListenerId, Error> {
+        let (reply, rx) = mpsc::channel();
+        self.send(Request::AddListener {
+            addr: addr.to_string(),
+            reply,
+        })?;
+        rx.recv().map_err(|_| Error::ServerGone)?
+    }
+
+    /// Stops accepting on a listener added with `add_listener` and closes
+    /// it. The clients it accepted are served until they leave, unless
+    /// `close_clients` is set: they are then closed as kicked, dropping
+    /// whatever is queued for them.
+    pub fn remove_listener(&self, id: ListenerId, close_clients: bool) -> Result<(), Error> {
+        let (reply, rx) = mpsc::channel();
+        self.send(Request::RemoveListener { id, close_clients, reply })?;
+        rx.recv().map_err(|_| Error::ServerGone)?
+    }
+
+    /// Serves at most `max_clients` clients at once, which can't exceed
+    /// the `Config::max_clients` the server started with. Clients past a
+    /// lowered limit are served until they leave, new ones being refused
+    /// or queued meanwhile, unless `evict_newest` is set: the most
+    /// recently accepted ones are then closed as kicked.
+    pub fn set_max_clients(&self, max_clients: usize, evict_newest: bool) -> Result<(), Error> {
+        let (reply, rx) = mpsc::channel();
+        self.send(Request::SetMaxClients {
+            max_clients,
+            evict_newest,
+            reply,
+        })?;
+        rx.recv().map_err(|_| Error::ServerGone)?
+    }
+
+    /// Replaces `Config::idle_timeout`, `None` turning it off. Clients
+    /// already silent for longer than a lowered timeout are closed on the
+    /// next round of timers.
+    pub fn set_idle_timeout(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        let (reply, rx) = mpsc::channel();
+        self.send(Request::SetIdleTimeout { timeout, reply })?;
+        rx.recv().map_err(|_| Error::ServerGone)?
+    }
+
+    /// Replaces `Config::global_rate`, in bytes per second, `None` lifting
+    /// the cap.
+    pub fn set_rate_limit(&self, rate: Option<u64>) -> Result<(), Error> {
+        let (reply, rx) = mpsc::channel();
+        self.send(Request::SetRateLimit { rate, reply })?;
+        rx.recv().map_err(|_| Error::ServerGone)?
+    }
+
+    /// Stops the server as SIGTERM would: the listeners are closed and
+    /// every client once its queue is written, or after
+    /// `Config::drain_timeout`, then `run` returns. A second call closes
+    /// the clients right away. Returns once the loop took the request,
+    /// not once it stopped.
+    pub fn shutdown(&self) -> Result<(), Error> {
+        let (reply, rx) = mpsc::channel();
+        self.send(Request::Shutdown { reply })?;
+        rx.recv().map_err(|_| Error::ServerGone)?
+    }
+
+    /// Hands the loop the name of `ip`, without waiting for it.
+    pub(crate) fn resolved(&self, ip: IpAddr, name: Option<String>) -> Result<(), Error> {
+        self.send(Request::Resolved { ip, name })
+    }
+
+    fn send(&self, request: Request) -> Result<(), Error> {
+        self.tx.send(request).map_err(|_| Error::ServerGone)?;
+        // Woken after queueing, the loop reads the whole queue
+        self.waker.wake()?;
+        Ok(())
+    }
+}
+
+/// The event loop's end of the `ServerHandle`s.
+///
+/// It wakes the poll with a `mio::Waker`, of which a poll may only have
+/// one: an application embedding the server must not make its own.
+pub struct Control {
+    waker: Arc<Waker>,
+    rx: Receiver<Request>,
+    tx: Sender<Request>,
+}
+
+impl Control {
+    /// Wakes the loop with `token` whenever a request comes.
+    pub fn new(registry: &Registry, token: Token) -> Result<Control, Error> {
+        let waker = Arc::new(Waker::new(registry, token)?);
+        let (tx, rx) = mpsc::channel();
+        Ok(Control { waker, rx, tx })
+    }
+
+    pub fn handle(&self) -> ServerHandle {
+        ServerHandle {
+            tx: self.tx.clone(),
+            waker: self.waker.clone(),
+        }
+    }
+
+    /// Wakes the loop as a request would, see `signal::wake_on_signals`.
+    #[cfg(unix)]
+    pub fn waker(&self) -> &Arc<Waker> {
+        &self.waker
+    }
+
+    /// The requests queued since the last call.
+    pub fn requests(&self) -> Vec<Request> {
+        self.rx.try_iter().collect()
+    }
+}
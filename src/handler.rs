@@ -0,0 +1,166 @@
+//! User code deciding what is echoed back.
+
+use std::any::Any;
+
+use crate::reactor::CloseReason;
+use crate::stream::PeerAddr;
+
+/// Turns what a client sends into what it gets back, in place of the plain
+/// echo.
+///
+/// It is called from inside the event loop with the data of every read, or
+/// every packet on packet transports, and the `Action` it returns decides
+/// what is queued, as the echo would be, and whether the connection goes
+/// on. A panic closes only the client being handled, as "handler
+/// panicked", and the handler keeps being called for the others: it must
+/// cope with whatever state the panic left it in, unless
+/// `ServerBuilder::handler_panic_limit` retires it.
+///
+/// State of its own per connection can be attached to the context, see
+/// `HandlerContext::set_data`.
+pub trait Handler: Send {
+    /// Called once the connection is accepted, before any of its data. A
+    /// panic closes it, `on_disconnect` being called all the same.
+    fn on_connect(&mut self, _ctx: &mut HandlerContext) {}
+
+    fn on_data(&mut self, ctx: &mut HandlerContext, data: &[u8]) -> Action;
+
+    /// Called once the connection is removed, whatever the reason, the
+    /// data attached to it being dropped right after. Also called for the
+    /// connections left when the server is closed or dropped, with
+    /// `CloseReason::Shutdown`.
+    fn on_disconnect(&mut self, _ctx: &mut HandlerContext, _reason: CloseReason) {}
+}
+
+impl<F, A> Handler for F
+where
+    F: FnMut(&mut HandlerContext, &[u8]) -> A + Send,
+    A: Into<Action>,
+{
+    fn on_data(&mut self, ctx: &mut HandlerContext, data: &[u8]) -> Action {
+        self(ctx, data).into()
+    }
+}
+
+/// What a `Handler` makes of the data of one read.
+///
+/// Once a connection is silenced or closing, the rest of its input is read
+/// and dropped without calling the handler again, so that closing it
+/// doesn't reset it over unread data.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Action {
+    /// Queue these bytes, which may be none.
+    Reply(Vec<u8>),
+    /// Queue these bytes, then close the connection, as "closed by
+    /// handler", once everything queued is written.
+    ReplyThenClose(Vec<u8>),
+    /// Queue nothing, now or for the rest of the connection, which stays
+    /// open.
+    Silence,
+    /// Close the connection, as "aborted by handler", dropping whatever is
+    /// still queued.
+    CloseNow,
+}
+
+impl From<Vec<u8>> for Action {
+    fn from(reply: Vec<u8>) -> Action {
+        Action::Reply(reply)
+    }
+}
+
+/// The connection a `Handler` is called for, and the data the handler
+/// attached to it.
+pub struct HandlerContext {
+    id: u64,
+    peer: PeerAddr,
+    data: Option<Box<dyn Any + Send>>,
+}
+
+impl HandlerContext {
+    pub(crate) fn new(id: u64, peer: PeerAddr, data: Option<Box<dyn Any + Send>>) -> HandlerContext {
+        HandlerContext { id, peer, data }
+    }
+
+    pub(crate) fn into_data(self) -> Option<Box<dyn Any + Send>> {
+        self.data
+    }
+
+    /// Identifies the connection, as the admin socket's `kick` takes it.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn peer(&self) -> PeerAddr {
+        self.peer
+    }
+
+    /// Attaches `data` to the connection, in place of whatever was
+    /// attached before, which is dropped. It is handed back to every
+    /// later call for the connection and dropped once the connection is
+    /// removed.
+    pub fn set_data<T: Any + Send>(&mut self, data: T) {
+        self.data = Some(Box::new(data));
+    }
+
+    /// The data attached to the connection, `None` if there is none or it
+    /// isn't a `T`. A mismatched type never panics, it is up to the
+    /// handler to treat it as a bug.
+    pub fn data<T: Any>(&mut self) -> Option<&mut T> {
+        self.data.as_deref_mut()?.downcast_mut()
+    }
+
+    /// Detaches the data attached to the connection if it is a `T`,
+    /// leaving it attached otherwise.
+    pub fn take_data<T: Any>(&mut self) -> Option<T> {
+        if !self.data.as_deref()?.is::<T>() {
+            return None;
+        }
+        self.data.take()?.downcast().ok().map(|data| *data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn ctx() -> HandlerContext {
+        HandlerContext::new(1, PeerAddr::Inet("127.0.0.1:7".parse().unwrap()), None)
+    }
+
+    #[test]
+    fn data_of_another_type_is_none_and_stays_attached() {
+        let mut ctx = ctx();
+        assert_eq!(ctx.data::<u32>(), None);
+        ctx.set_data(7u32);
+        assert_eq!(ctx.data::<String>(), None);
+        assert_eq!(ctx.take_data::<String>(), None);
+        *ctx.data::<u32>().unwrap() += 1;
+        assert_eq!(ctx.take_data::<u32>(), Some(8));
+        assert_eq!(ctx.data::<u32>(), None);
+    }
+
+    #[test]
+    fn data_is_dropped_once_replaced_or_with_the_context() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let mut ctx = ctx();
+        ctx.set_data(Counted(drops.clone()));
+        ctx.set_data(Counted(drops.clone()));
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+        // Handed back to the client, then dropped with it
+        let data = ctx.into_data();
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+        drop(data);
+        assert_eq!(drops.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,132 @@
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use log::debug;
+use mio::net::{TcpListener, TcpStream};
+use mio::{Interest, Registry, Token};
+use slab::Slab;
+
+/// Probes served at once, more are refused.
+pub const MAX_PROBES: usize = 8;
+/// Requests larger than this are cut off.
+const MAX_REQUEST_SIZE: usize = 4096;
+
+const OK: &[u8] = b"HTTP/1.0 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+const DRAINING: &[u8] =
+    b"HTTP/1.0 503 Service Unavailable\r\nContent-Length: 8\r\nConnection: close\r\n\r\ndraining";
+
+/// Answers HTTP health probes, out of the echo stats and client limit.
+///
+/// Uses `1 + MAX_PROBES` tokens from its base: the listener, then the
+/// probes.
+pub struct Health {
+    listener: TcpListener,
+    probes: Slab<(TcpStream, Vec<u8>)>,
+    token_base: usize,
+}
+
+impl Health {
+    pub fn new(listener: TcpListener) -> Health {
+        Health {
+            listener,
+            probes: Slab::with_capacity(MAX_PROBES),
+            token_base: 0,
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn register(&mut self, registry: &Registry, token_base: usize) -> io::Result<()> {
+        self.token_base = token_base;
+        registry.register(&mut self.listener, Token(token_base), Interest::READABLE)
+    }
+
+    /// Deregisters the listener and drops the probes in flight.
+    pub fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        for (mut sock, _) in self.probes.drain() {
+            let _ = registry.deregister(&mut sock);
+        }
+        registry.deregister(&mut self.listener)
+    }
+
+    /// Handles an event, returns false if its token isn't ours.
+    ///
+    /// While `draining`, probes get a 503 so load balancers stop routing
+    /// to the server.
+    pub fn ready(&mut self, registry: &Registry, token: Token, draining: bool) -> io::Result<bool> {
+        let index = match token.0.checked_sub(self.token_base) {
+            Some(0) => {
+                self.accept(registry)?;
+                return Ok(true);
+            }
+            Some(index) if index <= MAX_PROBES => index - 1,
+            _ => return Ok(false),
+        };
+        if self.probes.contains(index) && self.read(index, draining) {
+            let (mut sock, _) = self.probes.remove(index);
+            // Dropping the socket unregisters it anyway
+            let _ = registry.deregister(&mut sock);
+        }
+        Ok(true)
+    }
+
+    fn accept(&mut self, registry: &Registry) -> io::Result<()> {
+        loop {
+            match self.listener.accept() {
+                Ok((mut sock, addr)) => {
+                    if self.probes.len() == MAX_PROBES {
+                        debug!("too many health probes, connection refused : {}", addr);
+                        continue;
+                    }
+                    let entry = self.probes.vacant_entry();
+                    let token = Token(self.token_base + 1 + entry.key());
+                    registry.register(&mut sock, token, Interest::READABLE)?;
+                    entry.insert((sock, Vec::new()));
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Reads the request and answers it once complete, returns whether the
+    // probe is done with
+    fn read(&mut self, index: usize, draining: bool) -> bool {
+        let (ref mut sock, ref mut request) = self.probes[index];
+        let mut rbuf = [0; 1024];
+
+        loop {
+            match sock.read(&mut rbuf) {
+                Ok(0) => return true,
+                Ok(len) => request.extend_from_slice(&rbuf[..len]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => return true,
+            }
+        }
+        if request.len() > MAX_REQUEST_SIZE {
+            return true;
+        }
+        if !request.windows(4).any(|w| w == b"\r\n\r\n") {
+            return false;
+        }
+
+        // The response fits in any socket buffer
+        let response = if draining { DRAINING } else { OK };
+        if let Err(e) = sock.write_all(response) {
+            debug!("health probe write failed: {}", e);
+        }
+        let _ = sock.shutdown(Shutdown::Write);
+        true
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for Health {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
@@ -0,0 +1,259 @@
+//! HTTP/1.1 echo, for testing HTTP clients and proxies.
+
+use std::fmt::Write;
+use std::str;
+
+/// Request line and headers larger than this get a 400.
+pub const MAX_HEADER_SIZE: usize = 8 << 10;
+/// Bodies larger than this get a 413.
+pub const MAX_BODY_SIZE: usize = 1 << 20;
+
+// What the start of the input holds
+enum Parsed {
+    /// Not a whole request yet.
+    Partial,
+    /// The response to a request of `len` bytes.
+    Request {
+        len: usize,
+        response: Vec<u8>,
+        keep_alive: bool,
+    },
+    /// The status of a request that can't be served, which ends the
+    /// connection.
+    Invalid(&'static str),
+}
+
+/// Answers the requests of a connection in order, pipelined ones included.
+///
+/// A request with a body gets the body back, one without a summary of its
+/// method, target and headers. The connection is kept alive by default
+/// from HTTP/1.1 on, as `Connection` asks otherwise. Chunked bodies are
+/// refused with 411.
+#[derive(Default)]
+pub struct Http {
+    input: Vec<u8>,
+    closing: bool,
+}
+
+impl Http {
+    /// Appends the responses to the requests completed by `input` to
+    /// `out`.
+    pub fn decode(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        if self.closing {
+            // Past the last response
+            return;
+        }
+        self.input.extend_from_slice(input);
+        let mut start = 0;
+        while !self.closing {
+            match parse(&self.input[start..]) {
+                Parsed::Partial => break,
+                Parsed::Request { len, response, keep_alive } => {
+                    out.extend_from_slice(&response);
+                    self.closing = !keep_alive;
+                    start += len;
+                }
+                Parsed::Invalid(status) => {
+                    out.extend_from_slice(&respond(status, "text/plain", status.as_bytes(), false, false));
+                    self.closing = true;
+                }
+            }
+        }
+        if self.closing {
+            self.input = Vec::new();
+        } else {
+            self.input.drain(..start);
+        }
+    }
+
+    /// Bytes of an incomplete request, and allocated for it.
+    pub fn usage(&self) -> (usize, usize) {
+        (self.input.len(), self.input.capacity())
+    }
+
+    pub fn shrink(&mut self) {
+        self.input.shrink_to_fit();
+    }
+
+    /// Whether the connection ends once the responses are written.
+    pub fn closing(&self) -> bool {
+        self.closing
+    }
+}
+
+fn parse(input: &[u8]) -> Parsed {
+    let head_len = match input.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(end) => end + 4,
+        None if input.len() > MAX_HEADER_SIZE => return Parsed::Invalid("400 Bad Request"),
+        None => return Parsed::Partial,
+    };
+    if head_len > MAX_HEADER_SIZE {
+        return Parsed::Invalid("400 Bad Request");
+    }
+    let head = match str::from_utf8(&input[..head_len - 4]) {
+        Ok(head) => head,
+        Err(_) => return Parsed::Invalid("400 Bad Request"),
+    };
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next().unwrap_or_default();
+    let (method, version) = match request_line.split(' ').collect::<Vec<_>>()[..] {
+        [method, target, version] if !method.is_empty() && !target.is_empty() => (method, version),
+        _ => return Parsed::Invalid("400 Bad Request"),
+    };
+    let mut keep_alive = match version {
+        "HTTP/1.1" => true,
+        "HTTP/1.0" => false,
+        _ => return Parsed::Invalid("400 Bad Request"),
+    };
+
+    let mut content_length = 0;
+    let mut headers = Vec::new();
+    for line in lines {
+        let (name, value) = match line.split_once(':') {
+            Some((name, value)) if !name.is_empty() && !name.ends_with(' ') => (name, value.trim()),
+            _ => return Parsed::Invalid("400 Bad Request"),
+        };
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = match value.parse::<usize>() {
+                Ok(len) if len > MAX_BODY_SIZE => return Parsed::Invalid("413 Payload Too Large"),
+                Ok(len) => len,
+                Err(_) => return Parsed::Invalid("400 Bad Request"),
+            };
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            return Parsed::Invalid("411 Length Required");
+        } else if name.eq_ignore_ascii_case("connection") {
+            for option in value.split(',').map(str::trim) {
+                if option.eq_ignore_ascii_case("close") {
+                    keep_alive = false;
+                } else if option.eq_ignore_ascii_case("keep-alive") {
+                    keep_alive = true;
+                }
+            }
+        }
+        headers.push(line);
+    }
+
+    let len = head_len + content_length;
+    if input.len() < len {
+        return Parsed::Partial;
+    }
+    let response = if content_length > 0 {
+        respond("200 OK", "application/octet-stream", &input[head_len..len], method == "HEAD", keep_alive)
+    } else {
+        let mut summary = format!("{}\n", request_line);
+        for header in headers {
+            let _ = writeln!(summary, "{}", header);
+        }
+        respond("200 OK", "text/plain", summary.as_bytes(), method == "HEAD", keep_alive)
+    };
+    Parsed::Request { len, response, keep_alive }
+}
+
+fn respond(status: &str, content_type: &str, body: &[u8], head: bool, keep_alive: bool) -> Vec<u8> {
+    let mut response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: {}\r\n\r\n",
+        status,
+        content_type,
+        body.len(),
+        if keep_alive { "keep-alive" } else { "close" },
+    )
+    .into_bytes();
+    if !head {
+        response.extend_from_slice(body);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    // Feeds `input` in chunks of `cuts` bytes, and the rest at once
+    fn decode_in_chunks(http: &mut Http, input: &[u8], cuts: &[usize]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut rest = input;
+        for &cut in cuts {
+            let (chunk, after) = rest.split_at(cut.min(rest.len()));
+            http.decode(chunk, &mut out);
+            rest = after;
+        }
+        http.decode(rest, &mut out);
+        out
+    }
+
+    // Requests, near misses among them, pieced together from their parts
+    fn requests() -> impl Strategy<Value = Vec<u8>> {
+        let part = prop_oneof![
+            Just(b"GET / HTTP/1.1\r\n".to_vec()),
+            Just(b"POST /echo HTTP/1.0\r\n".to_vec()),
+            Just(b"HEAD / HTTP/1.1\r\n".to_vec()),
+            Just(b"Content-Length: 5\r\n".to_vec()),
+            Just(b"Connection: close\r\n".to_vec()),
+            Just(b"Transfer-Encoding: chunked\r\n".to_vec()),
+            Just(b"\r\n".to_vec()),
+            prop::collection::vec(any::<u8>(), 0..8),
+        ];
+        prop::collection::vec(part, 0..30).prop_map(|parts| parts.concat())
+    }
+
+    fn cuts() -> impl Strategy<Value = Vec<usize>> {
+        prop::collection::vec(0..64usize, 0..100)
+    }
+
+    proptest! {
+        #[test]
+        fn any_input_is_answered_the_same_in_chunks(input in requests(), cuts in cuts()) {
+            let mut whole = Vec::new();
+            let mut at_once = Http::default();
+            at_once.decode(&input, &mut whole);
+            let mut http = Http::default();
+            prop_assert_eq!(decode_in_chunks(&mut http, &input, &cuts), whole);
+            prop_assert_eq!(http.closing(), at_once.closing());
+            prop_assert!(http.usage().0 <= MAX_HEADER_SIZE + MAX_BODY_SIZE);
+        }
+    }
+
+    fn decode(http: &mut Http, input: &[u8]) -> String {
+        let mut out = Vec::new();
+        http.decode(input, &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn a_request_line_waits_for_its_end() {
+        let mut http = Http::default();
+        assert_eq!(decode(&mut http, b"GET /pa"), "");
+        assert_eq!(decode(&mut http, b"th HTTP/1.1\r\nHost: x\r"), "");
+        let response = decode(&mut http, b"\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\nGET /path HTTP/1.1\nHost: x\n"), "{}", response);
+        assert_eq!(http.usage().0, 0);
+        assert!(!http.closing());
+    }
+
+    #[test]
+    fn oversized_headers_get_a_400() {
+        let mut http = Http::default();
+        let header = format!("GET / HTTP/1.1\r\nX-Padding: {}\r\n", "x".repeat(MAX_HEADER_SIZE));
+        let response = decode(&mut http, header.as_bytes());
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+        assert!(http.closing());
+        assert_eq!(http.usage(), (0, 0));
+        // Nothing past the last response
+        assert_eq!(decode(&mut http, b"GET / HTTP/1.1\r\n\r\n"), "");
+    }
+
+    #[test]
+    fn pipelined_requests_are_answered_in_order() {
+        let mut http = Http::default();
+        let response = decode(&mut http, b"POST / HTTP/1.1\r\nContent-Length: 3\r\n\r\nabcGET /b HTTP/1.0\r\n\r\n");
+        let (first, second) = response.split_at(response.find("abc").unwrap() + 3);
+        assert!(first.ends_with("Content-Length: 3\r\nConnection: keep-alive\r\n\r\nabc"), "{}", first);
+        assert!(second.starts_with("HTTP/1.1 200 OK\r\n"), "{}", second);
+        assert!(second.ends_with("Connection: close\r\n\r\nGET /b HTTP/1.0\n"), "{}", second);
+        // HTTP/1.0 closes by default
+        assert!(http.closing());
+    }
+}
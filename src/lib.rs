@@ -1,224 +1,141 @@
-use std::io::{self, Read, Write};
-use std::net::SocketAddr;
-use std::collections::VecDeque;
+/// The error of the crate's signatures, see `EchoError`.
+pub type Error = EchoError;
+
+mod access_log;
+mod admin;
+mod annotate;
+mod ban;
+mod capture;
+mod client;
+mod clock;
+mod config;
+mod connect;
+mod courtesy;
+mod deny;
+#[cfg(feature = "dtls")]
+mod dtls;
+mod dump;
+mod error;
+mod events;
+mod framing;
+mod handle;
+mod handler;
+mod health;
+mod http;
+#[cfg(unix)]
+mod limits;
+mod log_file;
+mod mirror;
+mod negotiate;
+#[cfg(feature = "quic")]
+mod quic;
+#[cfg(windows)]
+mod pipe;
+mod reactor;
+mod refusal;
+mod replay;
+mod resolve;
+#[cfg(all(target_os = "linux", feature = "seccomp", any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod seccomp;
+#[cfg(all(target_os = "linux", feature = "sctp"))]
+mod sctp;
+#[cfg(target_os = "linux")]
+mod seqpacket;
+mod sequence;
+mod server;
+#[cfg(unix)]
+mod signal;
+mod stats;
+mod statsd;
+mod syslog;
+#[cfg(unix)]
+mod systemd;
+mod stream;
+#[cfg(target_os = "linux")]
+mod sys;
+mod telnet;
+mod throttle;
+mod timer;
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(all(unix, feature = "tokio"))]
+mod tokio_serve;
+mod udp;
+#[cfg(target_os = "linux")]
+mod unix;
+#[cfg(unix)]
+mod upgrade;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+#[cfg(all(target_os = "linux", feature = "vsock"))]
+mod vsock;
+#[cfg(unix)]
+mod workers;
+mod zerocopy;
+
+pub use crate::clock::{Clock, ManualClock, SystemClock};
+pub use crate::events::{Conn, ServerEvent};
+pub use crate::connect::{connect, ConnectOptions};
+pub use crate::config::{AccessLogFormat, Backend, ClientAuth, Config, ListenerConfig, Mode, Overflow, SyslogTarget};
+pub use crate::error::EchoError;
+pub use crate::handle::{ListenerId, ServerHandle};
+pub use crate::handler::{Action, Handler, HandlerContext};
+pub use crate::log_file::LogFile;
+pub use crate::reactor::{CloseReason, TickContext};
+pub use crate::refusal::Refusal;
+pub use crate::replay::replay;
+pub use crate::resolve::Resolver;
+#[cfg(target_os = "linux")]
+pub use crate::resolve::SystemResolver;
+pub use crate::server::{EmbeddedServer, Server, ServerBuilder};
+pub use crate::stats::{
+    BufferStats, DtlsStats, ListenerStats, LoopStats, OverflowStats, QuicStats, RefusalStats, SequenceStats, Stats, Transport, TransportStats, ZeroCopyStats, EVENTS_PER_POLL_BUCKETS,
+    MAX_LISTENERS, QUEUE_LATENCY_BUCKETS, RTT_BUCKETS,
+};
+pub use crate::syslog::Syslog;
+#[cfg(all(unix, feature = "tokio"))]
+pub use crate::tokio_serve::serve;
+
+// Internals driven directly by the benches and the fuzz targets, not part of the API
+#[doc(hidden)]
+pub use crate::client::{Client, InterestState};
+#[doc(hidden)]
+pub use crate::framing::{Framer, Framing, MAX_FRAME_SIZE};
+#[doc(hidden)]
+pub use crate::http::{Http, MAX_BODY_SIZE, MAX_HEADER_SIZE};
+#[doc(hidden)]
+pub use crate::negotiate::Negotiator;
+#[doc(hidden)]
+pub use crate::stream::{PeerAddr, Socket};
+#[doc(hidden)]
+pub use crate::telnet::Telnet;
 
-use mio::net::{TcpListener, TcpStream};
-use mio::{Events, Poll, PollOpt, Ready, Token};
-use slab::Slab;
-
-pub use failure::Error;
-use failure::format_err;
-
-const MAX_CLIENTS: usize = 1024;
-const DEFAULT_BUF_SIZE: usize = 1024;
-
-struct Client {
-    sock: TcpStream,
-    writable: bool,
-    bufs: VecDeque<Vec<u8>>,
-    pos: usize,
-}
-
-impl Client {
-    pub fn new(sock: TcpStream) -> Client {
-        Client {
-            sock,
-            writable: false,
-            bufs: VecDeque::new(),
-            pos: 0,
-        }
-    }
-
-    pub fn peer_addr(&self) -> SocketAddr {
-        self.sock.peer_addr().unwrap()
-    }
-
-    pub fn register(&mut self, poll: &Poll, index: usize) -> io::Result<()> {
-        poll.register(&self.sock, Token(index), Ready::readable(), PollOpt::edge())
-    }
-
-    pub fn reregister(&mut self, poll: &Poll, index: usize) -> io::Result<()> {
-        if self.bufs.is_empty() == self.writable {
-            self.writable = ! self.writable;
-            let ready = if self.writable {
-                Ready::readable() | Ready::writable()
-            } else {
-                Ready::readable()
-            };
-            poll.reregister(&self.sock, Token(index), ready, PollOpt::edge())?;
-        }
-        Ok(())
-    }
-
-    pub fn deregister(&self, poll: &Poll) -> io::Result<()> {
-        poll.deregister(&self.sock)
-    }
-
-    pub fn read(&mut self) -> io::Result<usize> {
-        let mut tot_len = 0;
-        let mut rbuf = [0; DEFAULT_BUF_SIZE];
-
-        loop {
-            match self.sock.read(&mut rbuf) {
-                Ok(0) => return Ok(0),
-                Ok(len) => {
-                    let mut buf = rbuf.to_vec();
-                    if len < DEFAULT_BUF_SIZE {
-                        unsafe {
-                            buf.set_len(len);
-                        }
-                    }
-                    self.bufs.push_back(buf);
-                    tot_len += len;
-                }
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    // Socket is not ready anymore, stop reading
-                    break;
-                }
-                e => return e,
-            }
-        }
-
-        Ok(tot_len)
-    }
-
-    pub fn write(&mut self) -> io::Result<usize> {
-        let mut tot_len = 0;
-
-        while let Some(buf) = self.bufs.get(0) {
-            match self.sock.write(&buf[self.pos..]) {
-                Ok(len) => {
-                    self.pos += len;
-                    if buf.len() == self.pos {
-                        self.bufs.pop_front();
-                        self.pos = 0;
-                    }
-                    tot_len += len;
-                }
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    // Socket is not ready anymore, stop writing
-                    break;
-                }
-                e => return e,
-            }
-        }
-
-        Ok(tot_len)
-    }
-}
-
-fn new_client(sock: TcpStream, clients: &mut Slab<Client>, poll: &Poll) -> Result<(), Error> {
-    let index = clients.insert(Client::new(sock));
-    clients.get_mut(index).unwrap().register(poll, index)?;
-    Ok(())
-}
-
-fn accept(server: &TcpListener, clients: &mut Slab<Client>, poll: &Poll) -> Result<(), Error> {
-    // Perform operations in a loop until `WouldBlock` is encountered.
-    loop {
-        match server.accept() {
-            Ok((sock, addr)) => {
-                if clients.len() < MAX_CLIENTS - 1 {
-                    println!("connection established : {}", addr);
-                    new_client(sock, clients, poll)?;
-                } else {
-                    return Err(format_err!("too many clients"));
-                }
-            }
-            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                // Socket is not ready anymore, stop accepting
-                return Ok(())
-            }
-            Err(e) => {
-                return Err(e.into());
-            }
-        }
-    }
-}
-
-#[derive(PartialEq, Debug)]
-enum ClientState {
-    Ok,
-    Closed,
-    Unknown,
+pub fn run(addr: &str) -> Result<(), Error> {
+    run_config(&Config::new(addr))
 }
 
-fn read(client: &mut Client, poll: &Poll) -> Result<ClientState, Error> {
-    match client.read() {
-        Ok(0) => {
-            // Socket is closed, remove it
-            client.deregister(poll)?;
-            println!("connection closed : {}", client.peer_addr());
-            return Ok(ClientState::Closed);
-        }
-        Ok(len) => {
-            println!("read {} bytes : {}", len, client.peer_addr());
-            return Ok(ClientState::Ok);
-        }
-        Err(e) => {
-            client.deregister(poll)?;
-            println!("error={} : {}", e, client.peer_addr());
-            return Err(e.into());
-        }
-    }
+/// Echoes over a Unix stream socket at `path` only, Linux only.
+pub fn run_unix(path: &str) -> Result<(), Error> {
+    run_config(&Config {
+        unix_stream: Some(path.to_string()),
+        ..Config::default()
+    })
 }
 
-fn write(client: &mut Client, poll: &Poll, token: usize) -> Result<(), Error> {
-    match client.write() {
-        Ok(len) => {
-            println!("write {} bytes : {}", len, client.peer_addr());
-            client.reregister(poll, token)?;
-            return Ok(());
-        }
-        Err(e) => {
-            client.deregister(&poll)?;
-            println!("error={} : {}", e, client.peer_addr());
-            return Err(e.into());
-        }
-    }
-}
-
-pub fn run(addr: &str) -> Result<(), Error> {
-    const SERVER_TOKEN: Token = Token(MAX_CLIENTS);
-
-    // Tcp listener
-    let server = TcpListener::bind(&addr.parse()?)?;
-
-    let poll = Poll::new()?;
-
-    // Register the listener
-    poll.register(&server, SERVER_TOKEN, Ready::readable(), PollOpt::edge())?;
-
-    // Create storage for events
-    let mut events = Events::with_capacity(1024);
-
-    // Used to store the clients.
-    let mut clients = Slab::with_capacity(MAX_CLIENTS);
-
-    // The main event loop
-    loop {
-        // Wait for events
-        poll.poll(&mut events, None)?;
-
-        for event in &events {
-            match event.token() {
-                SERVER_TOKEN => {
-                    accept(&server, &mut clients, &poll)?;
-                }
-                Token(index) => {
-                    let state = if event.readiness().is_readable() {
-                        read(clients.get_mut(index).unwrap(), &poll)?
-                    } else {
-                        ClientState::Unknown
-                    };
-
-                    if state != ClientState::Closed {
-                        write(clients.get_mut(index).unwrap(), &poll, index)?;
-                    } else {
-                        clients.remove(index);
-                    }
-                }
-            }
-        }
+/// Serves `config` until the server stops, then prints its stats.
+///
+/// With `Config::workers_processes`, this process only supervises the
+/// workers, copies of the running binary that serve `config` instead.
+pub fn run_config(config: &Config) -> Result<(), Error> {
+    #[cfg(unix)]
+    if config.workers_processes.is_some() && !workers::is_worker() {
+        config.validate()?;
+        return workers::supervise(config);
     }
+    let mut server = Server::from_config(config.clone())?;
+    let result = server.run();
+    #[cfg(unix)]
+    workers::report(server.stats());
+    println!("{}", server.stats());
+    result
 }
@@ -0,0 +1,193 @@
+//! Fits the file descriptor limit to the configured number of clients.
+
+use std::io;
+
+use log::{info, warn};
+
+use crate::admin::MAX_ADMINS;
+use crate::config::Config;
+use crate::courtesy::MAX_COURTESY;
+use crate::health::MAX_PROBES;
+use crate::Error;
+
+/// Descriptors beyond those `needed_fds` counts: stdio, the poll instance
+/// and whatever the embedding program holds.
+pub const SLACK_FDS: u64 = 32;
+
+/// Descriptors needed to serve `config.max_clients`: those of the clients
+/// and of their mirror connections, and those `fixed_fds` counts.
+pub fn needed_fds(config: &Config) -> u64 {
+    per_client(config).saturating_mul(config.max_clients as u64).saturating_add(fixed_fds(config))
+}
+
+/// Largest client count that fits in `limit` descriptors.
+pub fn clamp_clients(config: &Config, limit: u64) -> usize {
+    let available = limit.saturating_sub(fixed_fds(config)) / per_client(config);
+    if available < config.max_clients as u64 {
+        available as usize
+    } else {
+        config.max_clients
+    }
+}
+
+// A client, and its mirror connection
+fn per_client(config: &Config) -> u64 {
+    1 + u64::from(config.mirror.is_some())
+}
+
+// Descriptors whatever the number of clients: the listeners, the
+// connections waiting in the pending queue or for the busy message, the
+// health, admin, statsd and syslog sockets with their connections, the
+// files written to, and `SLACK_FDS`
+fn fixed_fds(config: &Config) -> u64 {
+    let sockets = config.listener_count()
+        + config.pending_queue.unwrap_or(0)
+        + config.busy_message.as_ref().map_or(0, |_| MAX_COURTESY)
+        + config.health_addr.as_ref().map_or(0, |_| 1 + MAX_PROBES)
+        + config.admin_addr.as_ref().map_or(0, |_| 1 + MAX_ADMINS)
+        + usize::from(config.statsd.is_some())
+        + usize::from(config.log_syslog.is_some());
+    let files = usize::from(config.capture.is_some())
+        + usize::from(config.access_log.is_some())
+        + usize::from(config.log_file.is_some());
+    (sockets + files) as u64 + SLACK_FDS
+}
+
+/// Raises the soft RLIMIT_NOFILE toward the hard one until `config` fits.
+///
+/// When even the hard limit is too low, `config.max_clients` is clamped,
+/// or an error is returned with `config.strict_limits`.
+#[allow(clippy::unnecessary_cast)] // rlim_t is not u64 everywhere
+pub fn fit_nofile(config: &mut Config) -> Result<(), Error> {
+    let needed = needed_fds(config);
+
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } < 0 {
+        return Err(Error::system("getrlimit", io::Error::last_os_error()));
+    }
+    let soft = limit.rlim_cur as u64;
+    let hard = limit.rlim_max as u64;
+
+    if soft < needed {
+        limit.rlim_cur = needed.min(hard) as libc::rlim_t;
+        if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) } < 0 {
+            return Err(Error::system("setrlimit", io::Error::last_os_error()));
+        }
+        info!("raised open file limit from {} to {} (hard {})", soft, limit.rlim_cur, hard);
+    }
+
+    let effective = limit.rlim_cur as u64;
+    if effective < needed {
+        let clients = clamp_clients(config, effective);
+        if config.strict_limits || clients == 0 {
+            warn!(
+                "open file limit {} is too low for {} clients, {} needed",
+                effective, config.max_clients, needed
+            );
+            return Err(Error::TooManyClients {
+                requested: config.max_clients,
+                max: clients,
+            });
+        }
+        warn!(
+            "open file limit {} is too low for {} clients, serving {} at most",
+            effective, config.max_clients, clients
+        );
+        config.max_clients = clients;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn config(max_clients: usize) -> Config {
+        let mut config = Config::new("127.0.0.1:0");
+        config.max_clients = max_clients;
+        config
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    fn hard_limit() -> u64 {
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        assert_eq!(unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) }, 0);
+        limit.rlim_max as u64
+    }
+
+    #[test]
+    fn needed_fds_counts_the_clients_listeners_and_slack() {
+        assert_eq!(needed_fds(&config(100)), 100 + 1 + SLACK_FDS);
+    }
+
+    #[test]
+    fn needed_fds_counts_every_other_descriptor() {
+        let mut config = config(100);
+        config.udp = Some("127.0.0.1:0".into());
+        config.mirror = Some("127.0.0.1:9".into());
+        config.pending_queue = Some(10);
+        config.busy_message = Some(b"busy".to_vec());
+        config.health_addr = Some("127.0.0.1:0".into());
+        config.admin_addr = Some("127.0.0.1:0".into());
+        config.statsd = Some("127.0.0.1:8125".into());
+        config.capture = Some(PathBuf::from("capture.pcap"));
+        config.access_log = Some(PathBuf::from("access.log"));
+        config.log_file = Some(PathBuf::from("server.log"));
+        let sockets = 2 + 10 + MAX_COURTESY + 1 + MAX_PROBES + 1 + MAX_ADMINS + 1;
+        assert_eq!(needed_fds(&config), 2 * 100 + sockets as u64 + 3 + SLACK_FDS);
+    }
+
+    #[test]
+    fn needed_fds_saturates() {
+        assert_eq!(needed_fds(&config(usize::MAX)), u64::MAX);
+    }
+
+    #[test]
+    fn clamp_clients_leaves_room_for_the_rest() {
+        let mut config = config(100);
+        config.mirror = Some("127.0.0.1:9".into());
+        let needed = needed_fds(&config);
+        assert_eq!(clamp_clients(&config, needed + 10), 100);
+        assert_eq!(clamp_clients(&config, needed), 100);
+        // A client short of its mirror connection doesn't fit
+        assert_eq!(clamp_clients(&config, needed - 1), 99);
+        assert_eq!(clamp_clients(&config, needed - 10), 95);
+        assert_eq!(clamp_clients(&config, SLACK_FDS), 0);
+    }
+
+    #[test]
+    fn fit_nofile_keeps_a_config_that_fits() {
+        let mut config = config(10);
+        fit_nofile(&mut config).unwrap();
+        assert_eq!(config.max_clients, 10);
+    }
+
+    #[test]
+    fn fit_nofile_clamps_past_the_hard_limit() {
+        let hard = hard_limit();
+        // Unlimited, or past what the kernel lets the soft limit reach
+        if hard > 1 << 30 {
+            return;
+        }
+        let mut strict = config(hard as usize);
+        strict.strict_limits = true;
+        let max = clamp_clients(&strict, hard);
+        assert!(matches!(
+            fit_nofile(&mut strict),
+            Err(Error::TooManyClients { requested, max: m }) if requested == hard as usize && m == max
+        ));
+
+        let mut config = config(hard as usize);
+        fit_nofile(&mut config).unwrap();
+        assert_eq!(config.max_clients, max);
+        assert!(needed_fds(&config) <= hard);
+    }
+}
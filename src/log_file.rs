@@ -0,0 +1,87 @@
+//! A log file rotating itself by size, for long runs.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by SIGHUP, the next line starts a new file.
+static ROTATE: AtomicBool = AtomicBool::new(false);
+
+/// Appends lines to a file, rotated to `PATH.1` once the next line would
+/// take it past `rotate_size` bytes, or on SIGHUP on Unix. Older files
+/// move up to `PATH.2` and so on, the one past `PATH.keep` is deleted.
+///
+/// The size is counted as lines are written rather than read back, so the
+/// check is free and a rotation costs `keep` renames and an open. Lines are
+/// flushed as they are written, and never split across two files.
+pub struct LogFile {
+    path: PathBuf,
+    rotate_size: Option<u64>,
+    keep: usize,
+    out: LineWriter<File>,
+    size: u64,
+}
+
+impl LogFile {
+    /// Opens `path` for appending, counting what it already holds.
+    pub fn open(path: &Path, rotate_size: Option<u64>, keep: usize) -> io::Result<LogFile> {
+        let file = open(path)?;
+        let size = file.metadata()?.len();
+        #[cfg(unix)]
+        crate::signal::watch(libc::SIGHUP, &ROTATE)?;
+        Ok(LogFile {
+            path: path.to_path_buf(),
+            rotate_size,
+            keep,
+            out: LineWriter::new(file),
+            size,
+        })
+    }
+
+    /// Appends `line` and a newline, rotating first if due.
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        let forced = ROTATE.swap(false, Ordering::Relaxed);
+        let full = self.rotate_size.is_some_and(|max| self.size + len > max);
+        // An empty file stays, it would only push a useful one out
+        if (forced || full) && self.size > 0 {
+            self.rotate()?;
+        }
+        self.out.write_all(line.as_bytes())?;
+        self.out.write_all(b"\n")?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.out.flush()?;
+        ignore_missing(fs::remove_file(numbered(&self.path, self.keep)))?;
+        for n in (1..self.keep).rev() {
+            ignore_missing(fs::rename(numbered(&self.path, n), numbered(&self.path, n + 1)))?;
+        }
+        fs::rename(&self.path, numbered(&self.path, 1))?;
+        self.out = LineWriter::new(open(&self.path)?);
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+// `path` with `.n` appended
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+// Fewer files than `keep` are there until enough rotations happened
+fn ignore_missing(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
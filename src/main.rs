@@ -1,13 +1,344 @@
+use std::convert::TryFrom;
+use std::path::PathBuf;
 use std::process;
+use std::sync::{Mutex, OnceLock, PoisonError};
 
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        eprintln!("usage: mio-echo-server HOST:PORT");
+use log::{Log, Metadata, Record};
+use mio_echo_server::{Config, ConnectOptions, LogFile, Syslog};
+
+const USAGE: &str = "usage: mio-echo-server [OPTIONS] [HOST:PORT]
+       mio-echo-server replay FILE HOST:PORT [--speed FACTOR]
+       mio-echo-server connect HOST:PORT [--crlf] [--hex] [--timeout TIME]
+
+replays the TCP sessions of a --capture file against HOST:PORT, FACTOR
+times faster than recorded, and checks the echoes match
+
+connects to HOST:PORT, sends the lines of stdin, ended with CR LF with
+--crlf, and prints what comes back, as a hex dump with --hex, until the
+server closes or TIME runs out
+
+options:
+    --config FILE              read the settings from a TOML file of Config
+                               fields, the other options overriding them
+                               (needs the serde feature)
+    --listen HOST:PORT         echo over TCP, same as the positional address
+    --listener HOST:PORT[,KEY=VALUE...]
+                               another TCP listener with its own settings,
+                               repeatable; keys: mode, checksum, telnet,
+                               verify-checksum, max-clients, max-queued,
+                               overflow, tls-cert and tls-key, e.g.
+                               127.0.0.1:7001,mode=line
+    --udp HOST:PORT            echo over UDP
+    --udp-batch N              receive and send up to N datagrams per syscall
+                               (Linux only, default 64, 1 for one each)
+    --udp-sequence             count the UDP datagrams lost, duplicated and
+                               reordered from the 8-byte big-endian sequence
+                               number each starts with
+    --udp-source-idle TIME     log the counts of a source once it is quiet
+                               for TIME (default 30s)
+    --vsock-port PORT          echo over AF_VSOCK on PORT of any CID
+    --unix-seqpacket PATH      echo packets over a SOCK_SEQPACKET socket
+    --unix-stream PATH         echo over a Unix stream socket (Linux only)
+    --sctp HOST:PORT           echo SCTP messages, each on the stream it came
+                               in on (Linux only)
+    --pipe-name NAME           echo over the named pipe \\\\.\\pipe\\NAME (Windows only)
+    --tls-cert PATH            terminate TLS on the TCP listeners with the PEM
+                               certificate chain of PATH (needs the tls
+                               feature)
+    --tls-key PATH             PEM private key of the certificate
+    --tls-client-ca PATH       require TLS clients to present a certificate
+                               issued by a CA of the PEM file PATH
+    --tls-client-auth POLICY   required (default), or optional to let clients
+                               without a valid certificate through, logged
+    --dtls-cert PATH           terminate DTLS on the UDP socket with the PEM
+                               certificate chain of PATH (needs the dtls
+                               feature)
+    --dtls-key PATH            PEM private key of the certificate
+    --dtls-client-ca PATH      require DTLS peers to present a certificate
+                               issued by a CA of the PEM file PATH
+    --dtls-session-idle TIME   drop a DTLS session quiet for TIME (default 60s)
+    --dtls-max-sessions N      keep at most N DTLS sessions (default 1024)
+    --quic                     serve QUIC on the UDP socket with the TLS
+                               certificate, echoing every stream (needs the
+                               quic feature)
+    --quic-datagrams           echo the QUIC datagrams too
+    --quic-alpn LIST           ALPN protocols to offer, comma separated
+    --max-write-chunk N        cap every write syscall at N bytes
+    --inter-chunk-delay TIME   pause between capped chunks (e.g. 5ms)
+    --max-queued SIZE          cap the data queued for a client at SIZE
+    --overflow POLICY          past it: backpressure (default), drop-newest,
+                               drop-oldest or disconnect
+    --quiesce TIME             hold echoes until the client is silent for TIME
+    --quiesce-max N            send held echoes once N bytes are held
+                               (default 64k)
+    --write-budget SIZE        write at most SIZE to a client per round of
+                               events before serving the others (default 4m)
+    --read-buf-size SIZE       queue a client's input in buffers of SIZE, filled
+                               before another is allocated (default 1k)
+    --short-read-drained       don't read again after a read shorter than asked
+                               for, saving a syscall per event
+    --shrink-after TIME        free the memory a client's buffers grew to
+                               once they held at most --shrink-watermark
+                               bytes for TIME (default 10s)
+    --shrink-watermark SIZE    (default 16k)
+    --mode MODE                echo (default), http to answer HTTP/1.1
+                               requests with their body or a summary of their
+                               headers, line or length to echo whole lines or
+                               messages prefixed by a 4-byte big-endian length
+    --checksum crc32           append a CRC32 of each line or message
+    --verify-checksum          close clients whose line or message doesn't end
+                               with the CRC32 of the rest
+    --telnet                   strip telnet negotiation and echo lines with CR LF
+    --annotate                 prefix every echoed chunk, line or message with
+                               \"[srv ADDR conn ID seq N len L] \"
+    --allow-mode-negotiation
+                               let a client's first line MODE upper, MODE delay
+                               MS or MODE echo pick its transform
+    --banner TEXT              greet every client with TEXT (\\r \\n \\t escapes)
+    --banner-file PATH         greet every client with the contents of PATH
+    --heartbeat-interval TIME  send a heartbeat to clients silent for TIME
+    --heartbeat-payload TEXT   heartbeat contents (default \\n)
+    --bind-retry TIME          keep retrying a busy address for TIME
+    --freebind                 bind the TCP and UDP addresses even if they
+                               aren't configured yet (IP_FREEBIND, Linux only)
+    --listener-rebind-attempts N
+                               bind a TCP listener that broke at runtime
+                               again up to N times in a row before draining
+                               (default 10)
+    --so-rcvbuf N              SO_RCVBUF of the TCP sockets
+    --so-sndbuf N              SO_SNDBUF of the TCP sockets
+    --tcp-user-timeout TIME    close connections whose data stays unacknowledged
+                               for TIME (Linux only)
+    --defer-accept TIME        only accept TCP connections once they sent
+                               data, or after about TIME in seconds (Linux
+                               only, excludes --banner)
+    --spin TIME                poll without blocking for up to TIME before
+                               waiting, burning a core to cut wakeup latency
+    --busy-poll TIME           SO_BUSY_POLL of the TCP connections (Linux only)
+    --zerocopy SIZE            write queued buffers of at least SIZE with
+                               MSG_ZEROCOPY (Linux only, needs the zerocopy
+                               feature)
+    --max-clients N            serve at most N clients at once (default 1024)
+    --events-capacity N        take at most N events per poll (default 1024)
+    --max-connections-total N  drain and exit once N connections were accepted
+    --global-rate RATE         cap the echo of all clients together at RATE,
+                               e.g. 200mbps
+    --notify-busy              tell clients refused at capacity \"server busy\"
+    --busy-message TEXT        tell them TEXT instead (\\r \\n \\t escapes)
+    --pending-queue N          let up to N connections wait for a free slot at
+                               capacity instead of refusing them
+    --pending-timeout TIME     refuse them after waiting TIME (default 2s)
+    --strict-limits            fail if the open file limit can't fit the clients
+    --statsd HOST:PORT         push stats to a statsd agent
+    --statsd-prefix PREFIX     statsd metric prefix (default mio_echo_server)
+    --stats-interval TIME      how often stats are pushed (default 10s)
+    --stall-threshold TIME     warn about event loop iterations taking longer
+                               (default 100ms, 0 to turn off)
+    --log-level LEVEL          off, error, warn, info (default), debug or trace
+    --log-file PATH            log to PATH instead of stdout, rotated to
+                               PATH.1 on SIGHUP
+    --log-rotate-size SIZE     also rotate it once it would grow past SIZE
+    --log-keep N               keep N rotated files (default 5)
+    --log-syslog TARGET        log to syslog too, TARGET being unix (/dev/log),
+                               unix:PATH or udp:HOST:PORT
+    --syslog-facility NAME     daemon (default), local0 to local7, ...
+    --syslog-rfc3164           send RFC 3164 messages instead of RFC 5424
+    --dump-limit N             hex dump N bytes per read and write at trace
+                               level (default 256)
+    --capture PATH             record the traffic to a pcap file
+    --capture-max-size N       rotate the capture to PATH.1 past N bytes
+                               (default 64m)
+    --mirror HOST:PORT         forward a copy of what each stream client sends
+                               to HOST:PORT, dropping what it can't take
+    --access-log PATH          append a line per closed connection to PATH,
+                               reopened on SIGHUP
+    --access-log-format FMT    text (default) or csv
+    --log-original-dst         log where iptables REDIRECTed TCP connections
+                               were headed (Linux only)
+    --resolve-peers            log the host names of peers, looked up by
+                               reverse DNS without delaying them (Linux only)
+    --resolve-ttl TIME         cache the names looked up for TIME
+                               (default 5m)
+    --duration TIME            drain and exit after running for TIME
+    --drain-timeout TIME       wait TIME for clients to leave when draining
+                               (default 5s)
+    --exit-when-idle TIME      exit after TIME without any client
+    --first-byte-timeout TIME  close connections that send nothing for TIME
+                               after being accepted
+    --idle-timeout TIME        close connections that send nothing for TIME
+    --health-addr HOST:PORT    answer HTTP health checks, 503 while draining
+    --admin-addr HOST:PORT     take admin commands, one per line:
+                               kick [-f] ID [REASON], kick-all [-f] [REASON],
+                               ban IP DURATION, unban IP, bans, latency,
+                               udp-sources, stats [listeners]
+    --ban-file PATH            refuse the IP DURATION pairs of PATH, one per
+                               line, from startup
+    --deny-file PATH           refuse the addresses and CIDR networks of PATH,
+                               one per line, reread on SIGHUP or once modified
+    --deny-existing            also close the clients a reread denies
+    --backend mio|uring        event loop, uring is experimental and TCP only
+                               (needs the io-uring feature, default mio)
+    --seccomp                  confine the server to a syscall allowlist
+    --chroot DIR               chroot into DIR once the listeners are bound
+    --upgrade-binary PATH      on SIGUSR2, start PATH with the same options,
+                               hand it the listeners, then drain and exit
+    --workers-processes N      serve from N worker processes sharing the
+                               listeners, restarted when they fail and
+                               drained on SIGTERM (Unix only)
+    --exclusive-accept         wake only one of the workers per connection
+                               (EPOLLEXCLUSIVE, Linux only)
+
+on Unix, SIGUSR1 logs the counters and one line per client, with the mio
+backend
+
+on Unix, SIGTERM and SIGINT stop accepting and close each client once its
+echo is written, or after the drain timeout, with the mio backend; a
+second one closes them right away
+
+under systemd with WatchdogSec=, WATCHDOG=1 is sent to NOTIFY_SOCKET at
+half the interval of WATCHDOG_USEC from the event loop";
+
+// Prints the server's own records as plain lines to --log-file and
+// --log-syslog, or on stdout without either
+struct Logger {
+    file: OnceLock<Mutex<LogFile>>,
+    syslog: OnceLock<Syslog>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target().starts_with("mio_echo_server")
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if self.file.get().is_none() && self.syslog.get().is_none() {
+            println!("{}", record.args());
+            return;
+        }
+        let line = record.args().to_string();
+        if let Some(file) = self.file.get() {
+            let mut file = file.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Err(e) = file.write_line(&line) {
+                eprintln!("log file: {}", e);
+            }
+        }
+        if let Some(syslog) = self.syslog.get() {
+            syslog.send(record.level(), &line);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: Logger = Logger {
+    file: OnceLock::new(),
+    syslog: OnceLock::new(),
+};
+
+// Parses the arguments following `replay`
+fn replay_args<I: Iterator<Item = String>>(mut args: I) -> Result<(PathBuf, String, f64), String> {
+    let mut positional = Vec::new();
+    let mut speed = 1.0;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--speed" => {
+                let value = args.next().ok_or("missing value for --speed")?;
+                speed = match value.parse::<f64>() {
+                    Ok(speed) if speed > 0.0 && speed.is_finite() => speed,
+                    _ => return Err(format!("invalid speed: {}", value)),
+                };
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+            _ => positional.push(arg),
+        }
+    }
+    match <[String; 2]>::try_from(positional) {
+        Ok([file, addr]) => Ok((PathBuf::from(file), addr, speed)),
+        Err(_) => Err("replay needs FILE and HOST:PORT".to_string()),
+    }
+}
+
+fn replay<I: Iterator<Item = String>>(args: I) {
+    let (file, addr, speed) = replay_args(args).unwrap_or_else(|err| {
+        eprintln!("{}\n{}", err, USAGE);
+        process::exit(1);
+    });
+    match mio_echo_server::replay(&file, &addr, speed) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+fn connect<I: Iterator<Item = String>>(args: I) {
+    let (addr, options) = ConnectOptions::from_args(args).unwrap_or_else(|err| {
+        eprintln!("{}\n{}", err, USAGE);
+        process::exit(1);
+    });
+    if let Err(err) = mio_echo_server::connect(&addr, &options) {
+        eprintln!("{}", err);
         process::exit(1);
     }
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1).peekable();
+    match args.peek().map(String::as_str) {
+        Some("replay") => {
+            args.next();
+            return replay(args);
+        }
+        Some("connect") => {
+            args.next();
+            return connect(args);
+        }
+        _ => {}
+    }
+
+    let config = match Config::from_args(args) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            process::exit(1);
+        }
+    };
 
-    if let Err(err) = mio_echo_server::run(&args[1]) {
+    if let Some(ref path) = config.log_file {
+        match LogFile::open(path, config.log_rotate_size, config.log_keep) {
+            Ok(file) => {
+                let _ = LOGGER.file.set(Mutex::new(file));
+            }
+            Err(err) => {
+                eprintln!("{}: {}", path.display(), err);
+                process::exit(1);
+            }
+        }
+    }
+    if let Some(ref target) = config.log_syslog {
+        match Syslog::connect(target, config.syslog_facility, config.syslog_rfc3164) {
+            Ok(syslog) => {
+                let _ = LOGGER.syslog.set(syslog);
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+    }
+    log::set_logger(&LOGGER).expect("logger already set");
+    log::set_max_level(config.log_level);
+
+    let result = mio_echo_server::run_config(&config);
+    if let Some(dropped) = LOGGER.syslog.get().map(Syslog::dropped).filter(|&n| n > 0) {
+        eprintln!("{} syslog messages dropped", dropped);
+    }
+    if let Err(err) = result {
         eprintln!("{}", err);
         process::exit(1);
     }
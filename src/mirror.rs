@@ -0,0 +1,93 @@
+//! Copies of what clients send, forwarded to `Config::mirror`.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::mem;
+use std::net::SocketAddr;
+
+use mio::net::TcpStream;
+use mio::{Interest, Registry, Token};
+
+/// Most bytes queued for one mirror connection, more are dropped.
+pub const MAX_MIRROR_QUEUED: usize = 256 * 1024;
+
+/// The mirror connection of one client, fire-and-forget: nothing is read
+/// from it, and what it can't take in time is dropped rather than
+/// slowing the echo down.
+pub struct Mirror {
+    sock: TcpStream,
+    queue: VecDeque<u8>,
+    /// Bytes dropped since the last `take_dropped`.
+    dropped: u64,
+    /// Set once the connection failed, nothing is sent anymore.
+    failed: Option<io::Error>,
+}
+
+impl Mirror {
+    /// Starts connecting, data sent meanwhile is queued.
+    pub fn connect(addr: &SocketAddr) -> io::Result<Mirror> {
+        Ok(Mirror {
+            sock: TcpStream::connect(*addr)?,
+            queue: VecDeque::new(),
+            dropped: 0,
+            failed: None,
+        })
+    }
+
+    pub fn register(&mut self, registry: &Registry, token: Token) -> io::Result<()> {
+        registry.register(&mut self.sock, token, Interest::WRITABLE)
+    }
+
+    pub fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        registry.deregister(&mut self.sock)
+    }
+
+    /// Forwards a copy of `data`, or drops it if the queue is full.
+    pub fn send(&mut self, data: &[u8]) {
+        if self.failed.is_some() || self.queue.len() + data.len() > MAX_MIRROR_QUEUED {
+            self.dropped += data.len() as u64;
+            return;
+        }
+        self.queue.extend(data);
+        self.flush();
+    }
+
+    /// Handles a writable event: notes a failed connect, or writes what
+    /// the socket takes of the queue.
+    pub fn ready(&mut self) {
+        match self.sock.take_error() {
+            Ok(None) => self.flush(),
+            Ok(Some(e)) | Err(e) => self.fail(e),
+        }
+    }
+
+    fn flush(&mut self) {
+        while !self.queue.is_empty() && self.failed.is_none() {
+            let (front, _) = self.queue.as_slices();
+            match self.sock.write(front) {
+                Ok(len) => {
+                    self.queue.drain(..len);
+                }
+                // Also while still connecting
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => self.fail(e),
+            }
+        }
+    }
+
+    fn fail(&mut self, e: io::Error) {
+        self.dropped += self.queue.len() as u64;
+        self.queue.clear();
+        self.failed = Some(e);
+    }
+
+    /// Why the connection failed, if it did.
+    pub fn failed(&self) -> Option<&io::Error> {
+        self.failed.as_ref()
+    }
+
+    /// Bytes dropped since the last call.
+    pub fn take_dropped(&mut self) -> u64 {
+        mem::take(&mut self.dropped)
+    }
+}
@@ -0,0 +1,187 @@
+//! Transforms a client picks with its first line, see
+//! `Config::allow_mode_negotiation`.
+
+use std::mem;
+use std::str;
+use std::time::Duration;
+
+const PREFIX: &[u8] = b"MODE ";
+/// Longest command line, newline included, a longer one is refused.
+const MAX_COMMAND: usize = 64;
+/// Longest delay a client may ask for.
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// What is done to the input before it is echoed back.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Transform {
+    Echo,
+    /// ASCII letters in upper case.
+    Upper,
+    /// Echoes held this long, see `Config::allow_mode_negotiation`.
+    Delay(Duration),
+}
+
+/// Reads the first line of a connection as a `MODE` command if it starts
+/// with `MODE `, then applies the transform it picked to the rest.
+///
+/// The line is held until it is complete or can't be a command anymore,
+/// so it may be split anywhere across reads.
+pub struct Negotiator {
+    /// The first line so far, `None` once settled.
+    line: Option<Vec<u8>>,
+    transform: Transform,
+    /// Set when input to delay was decoded, see `take_delay`.
+    delayed: bool,
+}
+
+impl Default for Negotiator {
+    fn default() -> Negotiator {
+        Negotiator {
+            line: Some(Vec::new()),
+            transform: Transform::Echo,
+            delayed: false,
+        }
+    }
+}
+
+impl Negotiator {
+    /// Appends the echo of `input` to `out`, or the reply to the command.
+    pub fn decode(&mut self, mut input: &[u8], out: &mut Vec<u8>) {
+        if let Some(ref mut line) = self.line {
+            // Never more than a command, the rest of a longer line is echoed wherever the reads split it
+            let end = input.iter().position(|&b| b == b'\n').map_or(input.len(), |i| i + 1);
+            let end = end.min(MAX_COMMAND - line.len());
+            line.extend_from_slice(&input[..end]);
+            input = &input[end..];
+            let n = line.len().min(PREFIX.len());
+            if line[..n] != PREFIX[..n] {
+                // Ordinary data, echoed as such
+                out.extend_from_slice(line);
+            } else if line.ends_with(b"\n") {
+                let reply = match parse(&line[PREFIX.len()..]) {
+                    Ok(transform) => {
+                        self.transform = transform;
+                        "OK\n"
+                    }
+                    Err(reply) => reply,
+                };
+                out.extend_from_slice(reply.as_bytes());
+            } else if line.len() >= MAX_COMMAND {
+                out.extend_from_slice(b"ERR command too long\n");
+            } else {
+                return;
+            }
+            self.line = None;
+        }
+        if input.is_empty() {
+            return;
+        }
+        match self.transform {
+            Transform::Upper => out.extend(input.iter().map(u8::to_ascii_uppercase)),
+            Transform::Echo | Transform::Delay(_) => out.extend_from_slice(input),
+        }
+        self.delayed |= matches!(self.transform, Transform::Delay(_));
+    }
+
+    /// The delay to hold the echo for, if input to delay was decoded since
+    /// the last call.
+    pub fn take_delay(&mut self) -> Option<Duration> {
+        match self.transform {
+            Transform::Delay(delay) if mem::take(&mut self.delayed) => Some(delay),
+            _ => None,
+        }
+    }
+
+    /// Bytes held and allocated for the first line.
+    pub fn usage(&self) -> (usize, usize) {
+        self.line.as_ref().map_or((0, 0), |line| (line.len(), line.capacity()))
+    }
+}
+
+// `upper`, `echo` or `delay MS`, the error reply otherwise
+fn parse(command: &[u8]) -> Result<Transform, &'static str> {
+    let command = str::from_utf8(command).map_err(|_| "ERR unknown mode\n")?;
+    let mut words = command.split_whitespace();
+    match (words.next(), words.next(), words.next()) {
+        (Some("echo"), None, _) => Ok(Transform::Echo),
+        (Some("upper"), None, _) => Ok(Transform::Upper),
+        (Some("delay"), Some(ms), None) => match ms.parse().map(Duration::from_millis) {
+            Ok(delay) if delay > Duration::ZERO && delay <= MAX_DELAY => Ok(Transform::Delay(delay)),
+            _ => Err("ERR invalid delay\n"),
+        },
+        _ => Err("ERR unknown mode\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn decode(negotiator: &mut Negotiator, input: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        negotiator.decode(input, &mut out);
+        out
+    }
+
+    // A first line that is a command more often than not
+    fn first_lines() -> impl Strategy<Value = Vec<u8>> {
+        let part = prop_oneof![
+            Just(b"MODE ".to_vec()),
+            Just(b"upper".to_vec()),
+            Just(b"delay 10".to_vec()),
+            Just(b" ".to_vec()),
+            Just(b"\n".to_vec()),
+            prop::collection::vec(any::<u8>(), 0..8),
+        ];
+        prop::collection::vec(part, 0..20).prop_map(|parts| parts.concat())
+    }
+
+    proptest! {
+        #[test]
+        fn any_input_decodes_the_same_in_chunks(input in first_lines(), cuts in prop::collection::vec(0..16usize, 0..50)) {
+            let mut at_once = Negotiator::default();
+            let whole = decode(&mut at_once, &input);
+            let mut negotiator = Negotiator::default();
+            let mut out = Vec::new();
+            let mut rest = &input[..];
+            for cut in cuts {
+                let (chunk, after) = rest.split_at(cut.min(rest.len()));
+                out.extend(decode(&mut negotiator, chunk));
+                prop_assert!(negotiator.usage().0 < MAX_COMMAND);
+                rest = after;
+            }
+            out.extend(decode(&mut negotiator, rest));
+            prop_assert_eq!(out, whole);
+            prop_assert_eq!(negotiator.transform, at_once.transform);
+        }
+    }
+
+    #[test]
+    fn a_command_split_across_reads() {
+        let mut negotiator = Negotiator::default();
+        assert_eq!(decode(&mut negotiator, b"MO"), b"");
+        assert_eq!(decode(&mut negotiator, b"DE up"), b"");
+        assert_eq!(decode(&mut negotiator, b"per\nhello"), b"OK\nHELLO");
+        assert_eq!(negotiator.usage(), (0, 0));
+    }
+
+    #[test]
+    fn a_first_line_that_isnt_a_command_is_echoed() {
+        let mut negotiator = Negotiator::default();
+        assert_eq!(decode(&mut negotiator, b"MOVE on\nMODE upper\n"), b"MOVE on\nMODE upper\n");
+        assert_eq!(negotiator.transform, Transform::Echo);
+    }
+
+    #[test]
+    fn commands_are_bounded() {
+        let mut negotiator = Negotiator::default();
+        // The rest of the line is echoed, as it would be read after the first `MAX_COMMAND` bytes
+        let long = format!("MODE {}\n", "x".repeat(MAX_COMMAND));
+        assert_eq!(decode(&mut negotiator, long.as_bytes()), b"ERR command too long\nxxxxx\n");
+        assert_eq!(decode(&mut negotiator, b"MODE delay 99999999\n"), b"MODE delay 99999999\n");
+        assert_eq!(parse(b"delay 99999999\n"), Err("ERR invalid delay\n"));
+        assert_eq!(parse(b"delay 0\n"), Err("ERR invalid delay\n"));
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::VecDeque;
+use std::io;
+use std::os::windows::io::{FromRawHandle, IntoRawHandle};
+
+use log::debug;
+use mio::{Interest, Registry, Token};
+use mio::windows::NamedPipe;
+use miow::pipe::NamedPipeBuilder;
+
+/// Instances kept waiting for a client, so a burst of clients doesn't
+/// find every instance taken.
+pub const PENDING_INSTANCES: usize = 4;
+
+/// Accepts the clients of `\\.\pipe\NAME`.
+///
+/// A pipe instance serves a single client: the listener keeps
+/// `PENDING_INSTANCES` of them waiting, registered under its token, and
+/// creates a new one for each instance a client connects to. The client
+/// takes over the registration of its instance.
+pub struct PipeListener {
+    path: String,
+    pending: VecDeque<(u64, NamedPipe)>,
+    token: Option<Token>,
+    created: u64,
+}
+
+impl PipeListener {
+    pub fn bind(name: &str) -> io::Result<PipeListener> {
+        let mut listener = PipeListener {
+            path: format!(r"\\.\pipe\{}", name),
+            pending: VecDeque::new(),
+            token: None,
+            created: 0,
+        };
+        for _ in 0..PENDING_INSTANCES {
+            let instance = listener.create()?;
+            listener.pending.push_back(instance);
+        }
+        Ok(listener)
+    }
+
+    /// Registers the waiting instances, which only start waiting then.
+    pub fn register(&mut self, registry: &Registry, token: Token) -> io::Result<()> {
+        self.token = Some(token);
+        for (_, pipe) in &mut self.pending {
+            listen(registry, pipe, token)?;
+        }
+        Ok(())
+    }
+
+    pub fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        for (_, pipe) in &mut self.pending {
+            registry.deregister(pipe)?;
+        }
+        Ok(())
+    }
+
+    /// Hands out an instance a client connected to, with its id, or
+    /// `WouldBlock` if there is none.
+    pub fn accept(&mut self, registry: &Registry) -> io::Result<(NamedPipe, u64)> {
+        let token = self.token.ok_or_else(|| io::Error::from(io::ErrorKind::WouldBlock))?;
+        let mut i = 0;
+        while i < self.pending.len() {
+            let (id, ref pipe) = self.pending[i];
+            // Connecting again only tells whether a client came
+            let connected = match pipe.take_error()? {
+                Some(e) => Err(e),
+                None => pipe.connect(),
+            };
+            match connected {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    i += 1;
+                    continue;
+                }
+                Err(ref e) => debug!("pipe instance {} failed: {}", id, e),
+                Ok(()) => {}
+            }
+            let (id, pipe) = self.pending.remove(i).expect("pending instance");
+            let mut instance = self.create()?;
+            listen(registry, &mut instance.1, token)?;
+            self.pending.push_back(instance);
+            if connected.is_ok() {
+                return Ok((pipe, id));
+            }
+        }
+        Err(io::ErrorKind::WouldBlock.into())
+    }
+
+    fn create(&mut self) -> io::Result<(u64, NamedPipe)> {
+        // Only the first instance may claim the name, so a second server
+        // fails instead of sharing it
+        let pipe = NamedPipeBuilder::new(&self.path)
+            .first(self.created == 0)
+            .create()?;
+        self.created += 1;
+        let pipe = unsafe { NamedPipe::from_raw_handle(pipe.into_raw_handle()) };
+        Ok((self.created, pipe))
+    }
+}
+
+// A connection completes as writable readiness
+fn listen(registry: &Registry, pipe: &mut NamedPipe, token: Token) -> io::Result<()> {
+    registry.register(pipe, token, Interest::READABLE | Interest::WRITABLE)?;
+    match pipe.connect() {
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+        result => result,
+    }
+}
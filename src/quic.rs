@@ -0,0 +1,364 @@
+//! QUIC echo on the UDP socket with quinn-proto, see `Config::quic`.
+//!
+//! The endpoint and its connections are state machines: they are fed the
+//! datagrams of the socket, then polled for what to send, each connection
+//! with a deadline of its own the event loop arms a timer for. The
+//! datagrams of a read are all fed before the connections they touched
+//! are polled, so the acknowledgements and echoes of a batch go out
+//! together, as far as the send queue of the socket takes them.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use bytes::{Buf, Bytes, BytesMut};
+use log::{debug, info, warn};
+use quinn_proto::crypto::rustls::QuicServerConfig;
+use quinn_proto::{
+    Connection, ConnectionError, ConnectionHandle, DatagramEvent, Dir, Endpoint, EndpointConfig, Event, ReadError,
+    ServerConfig, StreamEvent, StreamId, Transmit, TransportConfig, VarInt, WriteError,
+};
+use rustls::pki_types::CertificateDer;
+
+use crate::config::Config;
+use crate::stats::QuicStats;
+use crate::tls::{self, Acceptor};
+use crate::Error;
+
+/// Most bytes read from a stream and not yet taken back by it, past which
+/// the stream isn't read until they are, its flow control holding the
+/// client back.
+const MAX_BACKLOG: usize = 64 * 1024;
+
+/// The QUIC endpoint of the UDP socket, `None` without `Config::quic`.
+pub fn echo(config: &Config) -> Result<Option<QuicEcho>, Error> {
+    if !config.quic {
+        return Ok(None);
+    }
+    let acceptor = tls::acceptor(config)?.ok_or_else(|| Error::config("quic needs udp and tls_cert"))?;
+    let crypto = QuicServerConfig::try_from(acceptor.quic_config(&config.quic_alpn))
+        .map_err(|e| Error::config(format!("quic: {}", e)))?;
+    let mut transport = TransportConfig::default();
+    // Nothing to echo a unidirectional stream on
+    transport.max_concurrent_uni_streams(VarInt::from_u32(0));
+    if !config.quic_datagrams {
+        transport.datagram_receive_buffer_size(None);
+    }
+    let mut server = ServerConfig::with_crypto(Arc::new(crypto));
+    server.transport_config(Arc::new(transport));
+    let endpoint = Endpoint::new(Arc::new(EndpointConfig::default()), Some(Arc::new(server)), false, None);
+    Ok(Some(QuicEcho {
+        endpoint,
+        acceptor,
+        connections: HashMap::new(),
+        touched: HashSet::new(),
+        responses: VecDeque::new(),
+        buf: Vec::new(),
+        armed: None,
+        totals: QuicStats::default(),
+    }))
+}
+
+/// The QUIC connections of a UDP socket.
+pub struct QuicEcho {
+    endpoint: Endpoint,
+    acceptor: Arc<Acceptor>,
+    connections: HashMap<ConnectionHandle, Conn>,
+    /// Connections fed datagrams or timeouts since they were last polled,
+    /// or which had more to send than the queue took.
+    touched: HashSet<ConnectionHandle>,
+    /// Datagrams the endpoint answered itself, e.g. with a Retry.
+    responses: VecDeque<(SocketAddr, Vec<u8>)>,
+    /// What the endpoint and connections write a datagram to send in.
+    buf: Vec<u8>,
+    /// Deadline of the timer armed last, see `next_timer`.
+    armed: Option<Instant>,
+    pub totals: QuicStats,
+}
+
+struct Conn {
+    conn: Connection,
+    peer: SocketAddr,
+    established: bool,
+    /// What was read from the streams and not taken back by them yet.
+    backlogs: HashMap<StreamId, Backlog>,
+}
+
+#[derive(Default)]
+struct Backlog {
+    chunks: VecDeque<Bytes>,
+    len: usize,
+    /// Whether the stream finishes after the chunks.
+    fin: bool,
+}
+
+impl QuicEcho {
+    /// Feeds a datagram of `peer` to the endpoint. Addresses are sent a
+    /// Retry before their connection is accepted.
+    pub fn received(&mut self, peer: SocketAddr, data: &[u8], now: Instant) {
+        self.buf.clear();
+        match self.endpoint.handle(now, peer, None, None, BytesMut::from(data), &mut self.buf) {
+            Some(DatagramEvent::ConnectionEvent(handle, event)) => {
+                if let Some(conn) = self.connections.get_mut(&handle) {
+                    conn.conn.handle_event(event);
+                    self.touched.insert(handle);
+                }
+            }
+            Some(DatagramEvent::NewConnection(incoming)) => {
+                if !incoming.remote_address_validated() {
+                    let transmit = if incoming.may_retry() {
+                        self.totals.retries += 1;
+                        match self.endpoint.retry(incoming, &mut self.buf) {
+                            Ok(transmit) => transmit,
+                            Err(e) => return self.endpoint.ignore(e.into_incoming()),
+                        }
+                    } else {
+                        debug!("quic connection refused, invalid token : {}", peer);
+                        self.endpoint.refuse(incoming, &mut self.buf)
+                    };
+                    self.responses.push_back(datagram(&self.buf, &transmit));
+                    return;
+                }
+                match self.endpoint.accept(incoming, now, &mut self.buf, None) {
+                    Ok((handle, conn)) => {
+                        debug!("quic connection accepted : {}", peer);
+                        self.totals.connections += 1;
+                        self.connections.insert(
+                            handle,
+                            Conn {
+                                conn,
+                                peer,
+                                established: false,
+                                backlogs: HashMap::new(),
+                            },
+                        );
+                        self.touched.insert(handle);
+                    }
+                    Err(e) => {
+                        warn!("quic connection refused: {} : {}", e.cause, peer);
+                        if let Some(transmit) = e.response {
+                            self.responses.push_back(datagram(&self.buf, &transmit));
+                        }
+                    }
+                }
+            }
+            Some(DatagramEvent::Response(transmit)) => self.responses.push_back(datagram(&self.buf, &transmit)),
+            None => {}
+        }
+    }
+
+    /// Hands the connections their timeouts due by `now`.
+    pub fn expire(&mut self, now: Instant) {
+        if self.armed.is_some_and(|armed| armed <= now) {
+            self.armed = None;
+        }
+        for (&handle, conn) in &mut self.connections {
+            if conn.conn.poll_timeout().is_some_and(|deadline| deadline <= now) {
+                conn.conn.handle_timeout(now);
+                self.touched.insert(handle);
+            }
+        }
+    }
+
+    /// Deadline of a timer to arm for `expire`, `None` if the timer armed
+    /// last comes first or nothing is due.
+    pub fn next_timer(&mut self) -> Option<Instant> {
+        let next = self.connections.values_mut().filter_map(|conn| conn.conn.poll_timeout()).min()?;
+        if self.armed.is_some_and(|armed| armed <= next) {
+            return None;
+        }
+        self.armed = Some(next);
+        Some(next)
+    }
+
+    /// Echoes what the connections touched received, and pushes up to
+    /// `room` datagrams they have to send to `queue`. Returns how many.
+    pub fn transmit(&mut self, now: Instant, room: usize, queue: &mut VecDeque<(SocketAddr, Vec<u8>)>) -> usize {
+        let mut queued = 0;
+        while queued < room {
+            match self.responses.pop_front() {
+                Some(response) => queue.push_back(response),
+                None => break,
+            }
+            queued += 1;
+        }
+        let touched: Vec<ConnectionHandle> = self.touched.drain().collect();
+        for handle in touched {
+            let conn = match self.connections.get_mut(&handle) {
+                Some(conn) => conn,
+                None => continue,
+            };
+            conn.handle_events(&self.acceptor, &mut self.totals);
+            loop {
+                if queued == room {
+                    self.touched.insert(handle);
+                    break;
+                }
+                self.buf.clear();
+                match conn.conn.poll_transmit(now, 1, &mut self.buf) {
+                    Some(transmit) => queue.push_back(datagram(&self.buf, &transmit)),
+                    None => break,
+                }
+                queued += 1;
+            }
+            while let Some(event) = conn.conn.poll_endpoint_events() {
+                if let Some(event) = self.endpoint.handle_event(handle, event) {
+                    // New connection ids to tell the peer about
+                    conn.conn.handle_event(event);
+                    self.touched.insert(handle);
+                }
+            }
+            if conn.conn.is_drained() {
+                debug!("quic connection drained : {}", conn.peer);
+                self.connections.remove(&handle);
+                self.touched.remove(&handle);
+            }
+        }
+        // Not those closed and only draining
+        let ended = self.totals.handshake_failures + self.totals.closed + self.totals.lost;
+        self.totals.active = self.totals.connections.saturating_sub(ended);
+        queued
+    }
+}
+
+// The datagram `transmit` wrote to `buf`, and where to send it
+fn datagram(buf: &[u8], transmit: &Transmit) -> (SocketAddr, Vec<u8>) {
+    (transmit.destination, buf[..transmit.size].to_vec())
+}
+
+impl Conn {
+    fn handle_events(&mut self, acceptor: &Acceptor, totals: &mut QuicStats) {
+        while let Some(event) = self.conn.poll() {
+            match event {
+                Event::HandshakeDataReady => {}
+                Event::Connected => {
+                    self.established = true;
+                    info!("quic connection established : {}", self.peer);
+                    let certs = self
+                        .conn
+                        .crypto_session()
+                        .peer_identity()
+                        .and_then(|identity| identity.downcast::<Vec<CertificateDer<'static>>>().ok());
+                    acceptor.peer_cert(certs.as_deref().map(Vec::as_slice), self.peer);
+                }
+                Event::ConnectionLost { reason } => self.lost(reason, totals),
+                Event::Stream(StreamEvent::Opened { dir: Dir::Bi }) => {
+                    while let Some(id) = self.conn.streams().accept(Dir::Bi) {
+                        totals.streams += 1;
+                        self.echo(id, totals);
+                    }
+                }
+                Event::Stream(StreamEvent::Readable { id }) | Event::Stream(StreamEvent::Writable { id }) => {
+                    self.echo(id, totals)
+                }
+                Event::Stream(StreamEvent::Stopped { id, .. }) => {
+                    // The echo isn't wanted anymore, nor what it would be of
+                    self.backlogs.remove(&id);
+                    let _ = self.conn.recv_stream(id).stop(VarInt::from_u32(0));
+                }
+                Event::Stream(_) => {}
+                Event::DatagramReceived => {
+                    while let Some(datagram) = self.conn.datagrams().recv() {
+                        totals.datagrams += 1;
+                        // Makes room by dropping the oldest echoes not sent yet
+                        let _ = self.conn.datagrams().send(datagram, true);
+                    }
+                }
+                Event::DatagramsUnblocked => {}
+            }
+        }
+    }
+
+    fn lost(&mut self, reason: ConnectionError, totals: &mut QuicStats) {
+        match reason {
+            _ if !self.established => {
+                info!("quic handshake failed: {} : {}", reason, self.peer);
+                totals.handshake_failures += 1;
+            }
+            ConnectionError::ApplicationClosed(_) | ConnectionError::ConnectionClosed(_) | ConnectionError::LocallyClosed => {
+                debug!("quic connection closed: {} : {}", reason, self.peer);
+                totals.closed += 1;
+            }
+            _ => {
+                info!("quic connection lost: {} : {}", reason, self.peer);
+                totals.lost += 1;
+            }
+        }
+    }
+
+    // Sends what was read from a stream back on it, then reads more, until
+    // either side of the stream blocks
+    fn echo(&mut self, id: StreamId, totals: &mut QuicStats) {
+        let backlog = self.backlogs.entry(id).or_default();
+        loop {
+            while let Some(chunk) = backlog.chunks.front_mut() {
+                match self.conn.send_stream(id).write(chunk) {
+                    Ok(len) => {
+                        totals.bytes_written += len as u64;
+                        backlog.len -= len;
+                        chunk.advance(len);
+                        if chunk.is_empty() {
+                            backlog.chunks.pop_front();
+                        }
+                    }
+                    Err(WriteError::Blocked) => return,
+                    Err(_) => {
+                        // Stopped by the peer, which the event tells too
+                        self.backlogs.remove(&id);
+                        return;
+                    }
+                }
+            }
+            if backlog.fin {
+                let _ = self.conn.send_stream(id).finish();
+                self.backlogs.remove(&id);
+                return;
+            }
+
+            let mut stream = self.conn.recv_stream(id);
+            let mut chunks = match stream.read(true) {
+                Ok(chunks) => chunks,
+                Err(_) => {
+                    // Read to the end or reset already
+                    self.backlogs.remove(&id);
+                    return;
+                }
+            };
+            let mut read = false;
+            let mut reset = None;
+            while backlog.len < MAX_BACKLOG {
+                match chunks.next(MAX_BACKLOG - backlog.len) {
+                    Ok(Some(chunk)) => {
+                        totals.bytes_read += chunk.bytes.len() as u64;
+                        backlog.len += chunk.bytes.len();
+                        backlog.chunks.push_back(chunk.bytes);
+                        read = true;
+                    }
+                    Ok(None) => {
+                        backlog.fin = true;
+                        read = true;
+                        break;
+                    }
+                    Err(ReadError::Blocked) => break,
+                    Err(ReadError::Reset(code)) => {
+                        reset = Some(code);
+                        break;
+                    }
+                }
+            }
+            // Whether the window update needs sending now is up to the
+            // next transmit
+            let _ = chunks.finalize();
+            if let Some(code) = reset {
+                let _ = self.conn.send_stream(id).reset(code);
+                self.backlogs.remove(&id);
+                return;
+            }
+            if !read {
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,3496 @@
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::hint;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Shutdown, SocketAddr};
+use std::ops::Range;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(unix)]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use log::{debug, error, info, warn};
+use mio::event::Event;
+use mio::net::{TcpListener, TcpStream, UdpSocket};
+#[cfg(unix)]
+use mio::unix::SourceFd;
+use mio::{Events, Interest, Poll, Registry, Token};
+use slab::Slab;
+use socket2::SockRef;
+
+use crate::admin::{self, Admin, Command};
+use crate::annotate::Annotator;
+use crate::ban::Bans;
+use crate::access_log::{AccessLog, Entry};
+use crate::capture::Capture;
+use crate::client::{Client, Decoder};
+use crate::clock::Clock;
+use crate::config::{Config, Mode, Overflow};
+use crate::courtesy::{self, Courtesy};
+use crate::deny::DenyFile;
+use crate::events::{Conn, ServerEvent};
+use crate::framing::{Framer, Framing};
+use crate::handle::{Control, ListenerId, Request, ServerHandle};
+use crate::handler::{Action, Handler, HandlerContext};
+use crate::health::{self, Health};
+use crate::http::Http;
+use crate::mirror::Mirror;
+use crate::negotiate::Negotiator;
+#[cfg(windows)]
+use crate::pipe::PipeListener;
+#[cfg(all(target_os = "linux", feature = "sctp"))]
+use crate::sctp::SctpListener;
+#[cfg(target_os = "linux")]
+use crate::seqpacket::SeqpacketListener;
+#[cfg(target_os = "linux")]
+use crate::unix::UnixListener;
+use crate::refusal::{Audit, Load, Refusal};
+use crate::resolve::{PeerNames, Resolver};
+use crate::stats::{Stats, Transport};
+use crate::statsd::Statsd;
+use crate::stream::{Listener, Peer, PeerAddr, Socket, Stream};
+use crate::telnet::Telnet;
+use crate::throttle::Throttle;
+use crate::timer::{Timeout, Timers};
+#[cfg(feature = "tls")]
+use crate::tls::{self, Acceptor, TlsStream};
+use crate::udp::UdpEcho;
+#[cfg(unix)]
+use crate::deny;
+#[cfg(unix)]
+use crate::systemd::Watchdog;
+#[cfg(unix)]
+use crate::upgrade::{self, Progress, Upgrader};
+#[cfg(unix)]
+use crate::workers;
+#[cfg(all(target_os = "linux", feature = "vsock"))]
+use crate::vsock::VsockListener;
+use crate::zerocopy::ZeroCopy;
+use crate::Error;
+
+/// Default for `Config::max_clients`.
+pub const MAX_CLIENTS: usize = 1024;
+/// Default for `Config::events_capacity`.
+pub const EVENTS_CAPACITY: usize = 1024;
+/// Shells of closed clients kept for reuse, and the most entries their
+/// queues keep room for.
+const MAX_SPARE_CLIENTS: usize = 64;
+const MAX_SPARE_BUF_CAPACITY: usize = 16;
+/// How often the pending queue is checked for free slots and expired
+/// connections, besides when a client leaves.
+const PENDING_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+/// How often the buffers are measured, and shrunk when due, and the
+/// sockets kept for their zerocopy writes checked on.
+const SHRINK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How often UDP sources are checked for `Config::udp_source_idle`, and
+/// DTLS sessions for `Config::dtls_session_idle`.
+const SOURCES_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Longest the socket of a closed client is kept for the kernel to be
+/// done with its zerocopy writes, the peer being taken for gone then.
+const ZEROCOPY_LINGER: Duration = Duration::from_secs(60);
+/// How often `Config::deny_file` is checked for changes, SIGHUP rereads
+/// it right away.
+const DENY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the upgrade started by SIGUSR2 is checked on.
+#[cfg(unix)]
+const UPGRADE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+/// How long after a state dump the next one is refused, and the
+/// connections listed by one at most.
+#[cfg(unix)]
+const DUMP_MIN_INTERVAL: Duration = Duration::from_secs(1);
+#[cfg(unix)]
+const DUMP_MAX_CLIENTS: usize = 1000;
+const BIND_RETRY_MIN: Duration = Duration::from_millis(100);
+const BIND_RETRY_MAX: Duration = Duration::from_secs(2);
+/// How long a listener stops accepting once out of descriptors or memory,
+/// doubling while that goes on, for the clients leaving to free some.
+const ACCEPT_PAUSE_MIN: Duration = Duration::from_millis(50);
+const ACCEPT_PAUSE_MAX: Duration = Duration::from_secs(1);
+/// Accept errors in a row after which a TCP listener is deemed broken,
+/// closed and bound again.
+const LISTENER_ERRORS_MAX: u32 = 3;
+
+/// Set by SIGUSR1, taken by the reactor checking for a state dump.
+#[cfg(unix)]
+static DUMP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// The parts of a loop iteration timed to blame a stall on, see
+/// `Config::stall_threshold`.
+#[derive(Clone, Copy)]
+enum Phase {
+    Accept,
+    Read,
+    Write,
+    Timers,
+    Handler,
+}
+
+impl Phase {
+    const ALL: [Phase; 5] = [Phase::Accept, Phase::Read, Phase::Write, Phase::Timers, Phase::Handler];
+
+    fn name(self) -> &'static str {
+        match self {
+            Phase::Accept => "accept",
+            Phase::Read => "read",
+            Phase::Write => "write",
+            Phase::Timers => "timers",
+            Phase::Handler => "handler",
+        }
+    }
+}
+
+/// Why a client was removed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CloseReason {
+    /// The peer closed the connection.
+    Eof,
+    /// The peer reset or aborted the connection, or stopped reading.
+    Reset(io::ErrorKind),
+    /// Disconnected through the admin socket, with the listener that
+    /// accepted it, see `ServerHandle::remove_listener`, or to get down to
+    /// a lowered limit, see `ServerHandle::set_max_clients`.
+    Kicked,
+    /// Closed by the server once the last response was written, e.g. in
+    /// HTTP mode.
+    Done,
+    /// Sent data stayed unacknowledged for `Config::tcp_user_timeout`.
+    TimedOut,
+    /// The input broke the framing or failed `Config::verify_checksum`.
+    Corrupt,
+    /// The TLS handshake failed, e.g. on a client certificate missing or
+    /// not issued by `Config::tls_client_ca`.
+    Handshake,
+    /// The write queue overflowed under `Overflow::Disconnect`.
+    Overflow,
+    /// The `Handler` panicked while handling the client's data.
+    HandlerPanic,
+    /// Everything queued was written after the `Handler` returned
+    /// `Action::ReplyThenClose`, or `Server::close_conn` was called.
+    HandlerClose,
+    /// The `Handler` returned `Action::CloseNow`.
+    HandlerAbort,
+    /// Nothing came within `Config::first_byte_timeout` of the accept.
+    Silent,
+    /// Nothing came for `Config::idle_timeout`.
+    Idle,
+    /// A reload of `Config::deny_file` denied the peer, see
+    /// `Config::deny_existing`.
+    Denied,
+    /// Any other I/O error on the connection.
+    Error(io::ErrorKind),
+    /// Still connected when the server closed.
+    Shutdown,
+}
+
+impl CloseReason {
+    fn from_error(e: &io::Error) -> CloseReason {
+        #[cfg(feature = "tls")]
+        if tls::is_handshake_error(e) {
+            return CloseReason::Handshake;
+        }
+        match e.kind() {
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionAborted => CloseReason::Reset(e.kind()),
+            io::ErrorKind::TimedOut => CloseReason::TimedOut,
+            // Only decoders fail with it
+            io::ErrorKind::InvalidData => CloseReason::Corrupt,
+            kind => CloseReason::Error(kind),
+        }
+    }
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CloseReason::Eof => f.write_str("eof"),
+            CloseReason::Reset(kind) => write!(f, "reset: {}", kind),
+            CloseReason::Kicked => f.write_str("kicked"),
+            CloseReason::Done => f.write_str("done"),
+            CloseReason::Corrupt => f.write_str("corrupt input"),
+            CloseReason::Handshake => f.write_str("tls handshake failed"),
+            CloseReason::Overflow => f.write_str("write queue overflow"),
+            CloseReason::HandlerPanic => f.write_str("handler panicked"),
+            CloseReason::HandlerClose => f.write_str("closed by handler"),
+            CloseReason::HandlerAbort => f.write_str("aborted by handler"),
+            CloseReason::Silent => f.write_str("first byte timeout"),
+            CloseReason::Idle => f.write_str("idle timeout"),
+            CloseReason::Denied => f.write_str("denied"),
+            CloseReason::TimedOut => f.write_str("timed out"),
+            CloseReason::Error(kind) => write!(f, "error: {}", kind),
+            CloseReason::Shutdown => f.write_str("shutdown"),
+        }
+    }
+}
+
+/// A socket registered next to the clients, its token follows the client
+/// tokens in the order of `Reactor::listeners`.
+// A handful per server, the size difference doesn't matter
+#[allow(clippy::large_enum_variant)]
+enum Source {
+    Tcp(TcpListener),
+    Udp(UdpEcho),
+    #[cfg(all(target_os = "linux", feature = "vsock"))]
+    Vsock(VsockListener),
+    #[cfg(target_os = "linux")]
+    Seqpacket(SeqpacketListener),
+    #[cfg(target_os = "linux")]
+    Unix(UnixListener),
+    #[cfg(all(target_os = "linux", feature = "sctp"))]
+    Sctp(SctpListener),
+    #[cfg(windows)]
+    Pipe(PipeListener),
+    /// A listener closed while draining or removed, keeping the tokens
+    /// of the next ones in place.
+    Closed,
+}
+
+impl Source {
+    // Only pipes create their next instance when accepting
+    #[cfg_attr(not(windows), allow(unused_variables))]
+    fn accept(&mut self, registry: &Registry) -> Option<io::Result<(Stream, PeerAddr, Transport)>> {
+        fn tag<L: Listener>(listener: &L, transport: Transport) -> Option<io::Result<(Stream, PeerAddr, Transport)>> {
+            Some(listener.accept_stream().map(|(sock, addr)| (sock, addr, transport)))
+        }
+
+        match *self {
+            Source::Tcp(ref listener) => tag(listener, Transport::Tcp),
+            Source::Udp(_) | Source::Closed => None,
+            #[cfg(all(target_os = "linux", feature = "vsock"))]
+            Source::Vsock(ref listener) => tag(listener, Transport::Vsock),
+            #[cfg(target_os = "linux")]
+            Source::Seqpacket(ref listener) => tag(listener, Transport::Seqpacket),
+            #[cfg(target_os = "linux")]
+            Source::Unix(ref listener) => tag(listener, Transport::Unix),
+            #[cfg(all(target_os = "linux", feature = "sctp"))]
+            Source::Sctp(ref listener) => tag(listener, Transport::Sctp),
+            #[cfg(windows)]
+            Source::Pipe(ref mut listener) => Some(listener.accept(registry).map(|(pipe, instance)| {
+                (Stream::Pipe(pipe), PeerAddr::Pipe { instance }, Transport::Pipe)
+            })),
+        }
+    }
+
+    fn register(&mut self, registry: &Registry, token: Token, exclusive: bool) -> io::Result<()> {
+        match *self {
+            Source::Tcp(ref mut l) => register_listener(registry, l, token, exclusive),
+            Source::Udp(ref mut udp) => udp.register(registry, token),
+            #[cfg(all(target_os = "linux", feature = "vsock"))]
+            Source::Vsock(ref mut l) => registry.register(l, token, Interest::READABLE),
+            #[cfg(target_os = "linux")]
+            Source::Seqpacket(ref mut l) => registry.register(l, token, Interest::READABLE),
+            #[cfg(target_os = "linux")]
+            Source::Unix(ref mut l) => registry.register(l, token, Interest::READABLE),
+            #[cfg(all(target_os = "linux", feature = "sctp"))]
+            Source::Sctp(ref mut l) => registry.register(l, token, Interest::READABLE),
+            #[cfg(windows)]
+            Source::Pipe(ref mut l) => l.register(registry, token),
+            Source::Closed => Ok(()),
+        }
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        match *self {
+            // By descriptor, mio doesn't know of the registrations of
+            // `Config::exclusive_accept`
+            #[cfg(unix)]
+            Source::Tcp(ref l) => registry.deregister(&mut SourceFd(&l.as_raw_fd())),
+            #[cfg(not(unix))]
+            Source::Tcp(ref mut l) => registry.deregister(l),
+            Source::Udp(ref mut udp) => udp.deregister(registry),
+            #[cfg(all(target_os = "linux", feature = "vsock"))]
+            Source::Vsock(ref mut l) => registry.deregister(l),
+            #[cfg(target_os = "linux")]
+            Source::Seqpacket(ref mut l) => registry.deregister(l),
+            #[cfg(target_os = "linux")]
+            Source::Unix(ref mut l) => registry.deregister(l),
+            #[cfg(all(target_os = "linux", feature = "sctp"))]
+            Source::Sctp(ref mut l) => registry.deregister(l),
+            #[cfg(windows)]
+            Source::Pipe(ref mut l) => l.deregister(registry),
+            Source::Closed => Ok(()),
+        }
+    }
+}
+
+/// The settings a listener gives the clients it accepts, those of
+/// `Config` or of an entry of `Config::listeners`.
+#[derive(Clone)]
+struct Profile {
+    /// Index of the entry in `Config::listeners` and `Stats::listeners`.
+    entry: Option<usize>,
+    /// How the headers name the listener, set with `Config::annotate`.
+    server: Option<Arc<str>>,
+    mode: Mode,
+    telnet: bool,
+    checksum: bool,
+    verify_checksum: bool,
+    max_queued: Option<usize>,
+    overflow: Overflow,
+    /// Terminates TLS on the TCP clients, see `Config::tls_cert` and
+    /// `ListenerConfig::tls_cert`.
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<Acceptor>>,
+}
+
+impl Profile {
+    fn new(config: &Config, entry: Option<usize>) -> Profile {
+        Profile {
+            entry,
+            server: None,
+            mode: config.mode,
+            telnet: config.telnet,
+            checksum: config.checksum,
+            verify_checksum: config.verify_checksum,
+            max_queued: config.max_queued,
+            overflow: config.overflow,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+}
+
+/// A connection accepted at capacity, waiting for a slot without being
+/// registered.
+struct Parked {
+    sock: Stream,
+    addr: PeerAddr,
+    transport: Transport,
+    /// Index of the listener that accepted it.
+    listener: usize,
+    deadline: Instant,
+}
+
+/// Handed to the tick callback between two rounds of events.
+pub struct TickContext<'a> {
+    stats: &'a Stats,
+    shutdown: bool,
+}
+
+impl<'a> TickContext<'a> {
+    pub fn stats(&self) -> &Stats {
+        self.stats
+    }
+
+    /// Makes `Server::run` return once the current loop iteration is done.
+    pub fn shutdown(&mut self) {
+        self.shutdown = true;
+    }
+}
+
+/// User work run periodically from inside the event loop.
+pub struct Tick {
+    interval: Duration,
+    callback: Box<dyn FnMut(&mut TickContext) + Send>,
+    deadline: Instant,
+}
+
+impl Tick {
+    pub fn new(interval: Duration, callback: Box<dyn FnMut(&mut TickContext) + Send>) -> Tick {
+        Tick {
+            interval,
+            callback,
+            deadline: Instant::now() + interval,
+        }
+    }
+
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Runs the callback and moves the deadline to the next tick, returns
+    /// whether the callback asked for a shutdown.
+    pub fn fire(&mut self, stats: &Stats, now: Instant) -> bool {
+        let mut ctx = TickContext { stats, shutdown: false };
+        let callback = &mut self.callback;
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| callback(&mut ctx))) {
+            error!("tick callback panicked: {}", panic_message(&*payload));
+        }
+
+        // Keep the cadence, but don't try to catch up after a stall
+        self.deadline += self.interval;
+        if self.deadline <= now {
+            self.deadline = now + self.interval;
+        }
+        ctx.shutdown
+    }
+}
+
+/// What the reactor registers its sockets with: the `Poll` it owns, or
+/// the `Registry` of the poll of an application it is embedded in.
+pub trait Registrar {
+    fn registry(&self) -> &Registry;
+}
+
+impl Registrar for Poll {
+    fn registry(&self) -> &Registry {
+        Poll::registry(self)
+    }
+}
+
+impl Registrar for &Registry {
+    fn registry(&self) -> &Registry {
+        self
+    }
+}
+
+/// The event loop: owns the listeners, the clients, the timers and the
+/// stats, and usually the poll too.
+///
+/// Every registration uses a token of the range given at construction:
+/// clients first, then the listeners, then the health check, the admin
+/// socket, the connections refused with a busy message and the mirror
+/// connections of the clients, then the requests of the `ServerHandle`s
+/// and the listeners they add.
+pub struct Reactor<P = Poll> {
+    poll: P,
+    token_base: usize,
+    /// End of the token range.
+    token_end: usize,
+    max_clients: usize,
+    /// Clients served at most, `max_clients` unless a `ServerHandle`
+    /// lowered it.
+    client_limit: usize,
+    listeners: Vec<Source>,
+    /// Listeners bound at construction, the first ones, which have their
+    /// tokens right after the clients'.
+    fixed_listeners: usize,
+    /// What the clients of each listener get, in the same order.
+    profiles: Vec<Profile>,
+    /// Where each TCP listener is bound, to bind it again once it broke.
+    listener_addrs: Vec<Option<SocketAddr>>,
+    /// Broken listeners waiting for `Timeout::Rebind`, with how many times
+    /// binding them again failed, see `Config::listener_rebind_attempts`.
+    rebinds: HashMap<usize, u32>,
+    /// Listeners that stopped accepting on running out of descriptors or
+    /// memory, with how many times in a row, until one accept succeeds.
+    /// Those unregistered wait for `Timeout::ResumeAccept`.
+    accept_pauses: HashMap<usize, u32>,
+    /// Clients of each entry of `Config::listeners`, and the most it may
+    /// have.
+    entry_clients: Vec<usize>,
+    entry_max_clients: Vec<usize>,
+    health: Option<Health>,
+    admin: Option<Admin>,
+    courtesy: Option<Courtesy>,
+    /// Where the clients' input is mirrored to, and the token of the
+    /// mirror connection of client 0.
+    mirror: Option<SocketAddr>,
+    mirror_token_base: usize,
+    /// Takes the requests of the `ServerHandle`s, once one was made.
+    control: Option<Control>,
+    /// Host names of the peers, see `Config::resolve_peers`.
+    names: Option<PeerNames>,
+    bans: Bans,
+    /// Networks of `Config::deny_file`, rechecked on `Timeout::Deny` and
+    /// SIGHUP.
+    deny: Option<DenyFile>,
+    clients: Slab<Client>,
+    /// Connections waiting for a slot, oldest first, see
+    /// `Config::pending_queue`.
+    pending: VecDeque<Parked>,
+    /// A `Timeout::Pending` is armed.
+    pending_check: bool,
+    /// Shells of closed clients, reused by the next ones.
+    spare_clients: Vec<Client<()>>,
+    /// Sockets of closed clients kept until the kernel is done with their
+    /// zerocopy writes, with the buffers these read from, until
+    /// `ZEROCOPY_LINGER` after the close at most.
+    lingering: Vec<(Instant, Stream, ZeroCopy)>,
+    /// Since when at most a quarter of the client slots are used, as
+    /// sampled with the buffers.
+    sparse_since: Option<Instant>,
+    statsd: Option<Statsd>,
+    capture: Option<Capture>,
+    /// Terminates TLS on the clients of the listeners without a
+    /// certificate of their own, see `Config::tls_cert`.
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<Acceptor>>,
+    capture_thread: Option<JoinHandle<()>>,
+    access_log: Option<AccessLog>,
+    access_log_thread: Option<JoinHandle<()>>,
+    /// Logs the refused connections.
+    audit: Audit,
+    /// Caps the aggregate echo rate at `Config::global_rate`.
+    throttle: Option<Throttle>,
+    /// Clients parked by `Config::write_budget`, revisited on
+    /// `Timeout::PendingWrites`.
+    pending_writers: Vec<usize>,
+    timers: Timers,
+    stats: Stats,
+    config: Config,
+    tick: Option<Tick>,
+    handler: Option<Box<dyn Handler>>,
+    /// Panics after which the handler is dropped.
+    handler_panic_limit: Option<u64>,
+    /// What happened since the last `take_events`, when the caller drives
+    /// the connections, see `ServerBuilder::events`.
+    events: Option<Vec<ServerEvent>>,
+    /// Clients with data in those events, see `Client::handed_out`.
+    handed_out: Vec<Conn>,
+    /// Time spent in each `Phase` during the current iteration.
+    phase_times: [Duration; 5],
+    /// Connections accepted so far, over every stream transport.
+    accepted: u64,
+    /// When the last client left, or the server started, while there is
+    /// none.
+    idle_since: Option<Instant>,
+    /// The time of the timers and deadlines, see `ServerBuilder::clock`.
+    clock: Arc<dyn Clock>,
+    /// Starts `Config::upgrade_binary` on SIGUSR2.
+    #[cfg(unix)]
+    upgrader: Option<Upgrader>,
+    /// When the state was last dumped on SIGUSR1.
+    #[cfg(unix)]
+    last_dump: Option<Instant>,
+    /// Pinged by `Timeout::Watchdog` when systemd asks for it.
+    #[cfg(unix)]
+    watchdog: Option<Watchdog>,
+    /// Listeners are closed and the loop stops once the clients are gone.
+    draining: bool,
+    /// Set by SIGTERM, SIGINT or `ServerHandle::shutdown`: the clients
+    /// are closed once their queue is written.
+    terminating: bool,
+    /// Whether SIGTERM and SIGINT are this loop's to take, see
+    /// `watch_terminate`.
+    #[cfg(unix)]
+    terminate_watched: bool,
+    shutdown: bool,
+}
+
+impl<P: Registrar> Reactor<P> {
+    /// Binds and registers every configured listener.
+    pub fn new(
+        config: Config,
+        mut tick: Option<Tick>,
+        poll: P,
+        tokens: Range<usize>,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Reactor<P>, Error> {
+        let mut listeners = Vec::new();
+        let mut profiles = Vec::new();
+
+        let (capture, capture_thread) = match config.capture {
+            Some(ref path) => {
+                let (capture, thread) = Capture::start(path, config.capture_max_size)?;
+                (Some(capture), Some(thread))
+            }
+            None => (None, None),
+        };
+
+        let (access_log, access_log_thread) = match config.access_log {
+            Some(ref path) => {
+                let (access_log, thread) = AccessLog::start(
+                    path,
+                    config.access_log_format,
+                    config.log_original_dst,
+                    config.tls_client_ca.is_some(),
+                )?;
+                (Some(access_log), Some(thread))
+            }
+            None => (None, None),
+        };
+
+        #[cfg(feature = "tls")]
+        let tls = tls::acceptor(&config)?;
+
+        // Tcp listener
+        if let Some(ref addr) = config.listen {
+            let listener = bind_or_inherit("listen", addr, config.bind_retry, |addr| bind_tcp(addr, config.freebind))?;
+            size_listener_buffers(&listener, &config)?;
+            #[cfg(target_os = "linux")]
+            if let Some(defer) = config.defer_accept {
+                crate::sys::set_tcp_defer_accept(&listener, defer)?;
+            }
+            listeners.push(Source::Tcp(listener));
+            profiles.push(Profile::new(&config, None));
+        }
+
+        // Tcp listeners with their own settings
+        for (index, entry) in config.listeners.iter().enumerate() {
+            let name = format!("listener.{}", index);
+            let listener = bind_or_inherit(&name, &entry.addr, config.bind_retry, |addr| bind_tcp(addr, config.freebind))?;
+            size_listener_buffers(&listener, &config)?;
+            #[cfg(target_os = "linux")]
+            if let Some(defer) = config.defer_accept {
+                crate::sys::set_tcp_defer_accept(&listener, defer)?;
+            }
+            listeners.push(Source::Tcp(listener));
+            profiles.push(Profile::new(&config.for_listener(index), Some(index)));
+        }
+
+        // Udp socket
+        if let Some(ref addr) = config.udp {
+            let sock = bind_or_inherit("udp", addr, config.bind_retry, |addr| bind_udp(addr, config.freebind))?;
+            let mut udp = UdpEcho::new(sock, config.dump_limit, capture.clone(), config.udp_batch)?;
+            if config.udp_sequence {
+                udp.track_sequences(config.udp_source_idle);
+            }
+            #[cfg(feature = "dtls")]
+            if let Some(sessions) = crate::dtls::sessions(&config)? {
+                udp.terminate_dtls(sessions);
+            }
+            #[cfg(feature = "quic")]
+            if let Some(endpoint) = crate::quic::echo(&config)? {
+                udp.serve_quic(endpoint);
+            }
+            listeners.push(Source::Udp(udp));
+        }
+
+        // Vsock listener
+        if let Some(port) = config.vsock_port {
+            #[cfg(all(target_os = "linux", feature = "vsock"))]
+            listeners.push(Source::Vsock(VsockListener::bind(port)?));
+            #[cfg(not(all(target_os = "linux", feature = "vsock")))]
+            {
+                let _ = port;
+                return Err(Error::config("vsock support is not compiled in"));
+            }
+        }
+
+        // Seqpacket listener
+        if let Some(ref path) = config.unix_seqpacket {
+            #[cfg(target_os = "linux")]
+            listeners.push(Source::Seqpacket(SeqpacketListener::bind(path)?));
+            #[cfg(not(target_os = "linux"))]
+            {
+                let _ = path;
+                return Err(Error::config("seqpacket sockets are only supported on Linux"));
+            }
+        }
+
+        // Unix stream listener
+        if let Some(ref path) = config.unix_stream {
+            #[cfg(target_os = "linux")]
+            listeners.push(Source::Unix(UnixListener::bind(path)?));
+            #[cfg(not(target_os = "linux"))]
+            {
+                let _ = path;
+                return Err(Error::config("unix stream sockets are only supported on Linux"));
+            }
+        }
+
+        // Sctp listener
+        if let Some(ref addr) = config.sctp {
+            #[cfg(all(target_os = "linux", feature = "sctp"))]
+            listeners.push(Source::Sctp(bind(addr, config.bind_retry, SctpListener::bind)?));
+            #[cfg(not(all(target_os = "linux", feature = "sctp")))]
+            {
+                let _ = addr;
+                return Err(Error::config("sctp support is not compiled in"));
+            }
+        }
+
+        // Named pipe
+        if let Some(ref name) = config.pipe_name {
+            #[cfg(windows)]
+            listeners.push(Source::Pipe(PipeListener::bind(name)?));
+            #[cfg(not(windows))]
+            {
+                let _ = name;
+                return Err(Error::config("named pipes are only supported on Windows"));
+            }
+        }
+
+        // The listeners after the entries have the top-level settings
+        profiles.resize(listeners.len(), Profile::new(&config, None));
+        if config.annotate {
+            for (profile, listener) in profiles.iter_mut().zip(&listeners) {
+                profile.server = Some(server_name(listener, &config).into());
+            }
+        }
+        #[cfg(feature = "tls")]
+        for profile in &mut profiles {
+            profile.tls = match profile.entry {
+                Some(index) if config.listeners[index].tls_cert.is_some() => tls::acceptor(&config.for_listener(index))?,
+                _ => tls.clone(),
+            };
+        }
+        let listener_addrs = listeners
+            .iter()
+            .map(|listener| match *listener {
+                Source::Tcp(ref l) => l.local_addr().ok(),
+                _ => None,
+            })
+            .collect();
+        let entry_max_clients: Vec<usize> = config
+            .listeners
+            .iter()
+            .map(|entry| entry.max_clients.unwrap_or(usize::MAX))
+            .collect();
+
+        // Health check listener, it outlives the others while draining
+        let mut health = match config.health_addr {
+            Some(ref addr) => Some(Health::new(bind_or_inherit(
+                "health_addr",
+                addr,
+                config.bind_retry,
+                |addr| TcpListener::bind(*addr),
+            )?)),
+            None => None,
+        };
+
+        // Admin socket, also kept while draining
+        let mut admin = match config.admin_addr {
+            Some(ref addr) => Some(Admin::new(bind_or_inherit(
+                "admin_addr",
+                addr,
+                config.bind_retry,
+                |addr| TcpListener::bind(*addr),
+            )?)),
+            None => None,
+        };
+
+        // Clients get whatever the listeners leave of the range
+        let health_tokens = health.as_ref().map_or(0, |_| 1 + health::MAX_PROBES);
+        let admin_tokens = admin.as_ref().map_or(0, |_| 1 + admin::MAX_ADMINS);
+        let courtesy_tokens = config.busy_message.as_ref().map_or(0, |_| courtesy::MAX_COURTESY);
+        let reserved = listeners.len() + health_tokens + admin_tokens + courtesy_tokens;
+        // A mirrored client takes a second token
+        let mirror: Option<SocketAddr> = config.mirror.as_ref().map(|addr| addr.parse()).transpose()?;
+        let per_client = if mirror.is_some() { 2 } else { 1 };
+        if tokens.len() < reserved + per_client {
+            return Err(Error::OutOfTokens("a client"));
+        }
+        let max_clients = config.max_clients.min((tokens.len() - reserved) / per_client);
+
+        // Register the listeners
+        for (index, listener) in listeners.iter_mut().enumerate() {
+            let token = Token(tokens.start + max_clients + index);
+            listener.register(poll.registry(), token, config.exclusive_accept)?;
+        }
+        let base = tokens.start + max_clients + listeners.len();
+        if let Some(ref mut health) = health {
+            health.register(poll.registry(), base)?;
+        }
+        if let Some(ref mut admin) = admin {
+            admin.register(poll.registry(), base + health_tokens)?;
+        }
+        let courtesy = config
+            .busy_message
+            .as_ref()
+            .map(|message| Courtesy::new(message.clone(), base + health_tokens + admin_tokens));
+        let mirror_token_base = base + health_tokens + admin_tokens + courtesy_tokens;
+
+        let now = clock.now();
+        let mut timers = Timers::new();
+        timers.insert(now + SHRINK_CHECK_INTERVAL, Timeout::Shrink);
+        if config.udp_sequence || config.dtls_cert.is_some() {
+            timers.insert(now + SOURCES_CHECK_INTERVAL, Timeout::UdpSources);
+        }
+        if let Some(ref mut tick) = tick {
+            tick.deadline = now + tick.interval;
+            timers.insert(tick.deadline, Timeout::Tick);
+        }
+        if let Some(duration) = config.duration {
+            timers.insert(now + duration, Timeout::Deadline);
+        }
+        if let Some(idle) = config.exit_when_idle {
+            timers.insert(now + idle, Timeout::Idle);
+        }
+        let mut bans = Bans::default();
+        for &(ip, duration) in &config.bans {
+            let until = bans.ban(ip, duration, now);
+            timers.insert(until, Timeout::Unban(ip));
+        }
+        let deny = match config.deny_file {
+            Some(ref path) => {
+                let deny = DenyFile::load(path)?;
+                info!("denying the {} networks of {}", deny.len(), path.display());
+                timers.insert(now + DENY_CHECK_INTERVAL, Timeout::Deny);
+                Some(deny)
+            }
+            None => None,
+        };
+        let statsd = match config.statsd {
+            Some(ref addr) => {
+                timers.insert(now + config.stats_interval, Timeout::Statsd);
+                Some(Statsd::new(addr, &config.statsd_prefix)?)
+            }
+            None => None,
+        };
+        #[cfg(unix)]
+        let upgrader = match config.upgrade_binary {
+            Some(ref binary) => {
+                upgrade::watch_signal()?;
+                Some(Upgrader::new(binary.clone()))
+            }
+            None => None,
+        };
+        // The handler only sets a flag, the dump is made by the loop
+        #[cfg(unix)]
+        crate::signal::watch(libc::SIGUSR1, &DUMP_REQUESTED)?;
+        // Connected before any chroot or seccomp filter
+        #[cfg(unix)]
+        let watchdog = Watchdog::from_env()?;
+        #[cfg(unix)]
+        if let Some(ref watchdog) = watchdog {
+            info!("systemd watchdog enabled, pinging every {:?}", watchdog.interval());
+            watchdog.ping();
+            timers.insert(now + watchdog.interval(), Timeout::Watchdog);
+        }
+
+        Ok(Reactor {
+            poll,
+            token_base: tokens.start,
+            token_end: tokens.end,
+            max_clients,
+            client_limit: max_clients,
+            fixed_listeners: listeners.len(),
+            listeners,
+            profiles,
+            listener_addrs,
+            rebinds: HashMap::new(),
+            accept_pauses: HashMap::new(),
+            entry_clients: vec![0; entry_max_clients.len()],
+            entry_max_clients,
+            health,
+            admin,
+            courtesy,
+            mirror,
+            mirror_token_base,
+            control: None,
+            names: None,
+            bans,
+            deny,
+            // Used to store the clients.
+            clients: Slab::with_capacity(max_clients),
+            pending: VecDeque::new(),
+            pending_check: false,
+            spare_clients: Vec::new(),
+            lingering: Vec::new(),
+            sparse_since: None,
+            timers,
+            statsd,
+            capture,
+            capture_thread,
+            #[cfg(feature = "tls")]
+            tls,
+            access_log,
+            access_log_thread,
+            audit: Audit::default(),
+            throttle: config.global_rate.map(|rate| Throttle::new(rate, now)),
+            pending_writers: Vec::new(),
+            stats: Stats {
+                max_clients,
+                idle_timeout: config.idle_timeout,
+                global_rate: config.global_rate,
+                ..Stats::default()
+            },
+            config,
+            tick,
+            handler: None,
+            handler_panic_limit: None,
+            events: None,
+            handed_out: Vec::new(),
+            phase_times: [Duration::from_secs(0); 5],
+            accepted: 0,
+            idle_since: Some(now),
+            clock,
+            #[cfg(unix)]
+            upgrader,
+            #[cfg(unix)]
+            last_dump: None,
+            #[cfg(unix)]
+            watchdog,
+            draining: false,
+            terminating: false,
+            #[cfg(unix)]
+            terminate_watched: false,
+            shutdown: false,
+        })
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Echoes what `handler` makes of the input instead, dropping it
+    /// after `panic_limit` panics.
+    pub fn set_handler(&mut self, handler: Box<dyn Handler>, panic_limit: Option<u64>) {
+        self.handler = Some(handler);
+        self.handler_panic_limit = panic_limit;
+    }
+
+    /// Looks up the host names of the peers with `resolver`, on a thread
+    /// of its own, see `Config::resolve_peers`.
+    pub fn set_resolver(&mut self, resolver: Box<dyn Resolver>) -> Result<(), Error> {
+        let handle = self.handle()?;
+        self.names = Some(PeerNames::start(resolver, handle, self.config.resolve_ttl));
+        Ok(())
+    }
+
+    /// Reports the connections, their data and their closing as
+    /// `ServerEvent`s instead of echoing, the caller sending and closing.
+    pub fn enable_events(&mut self) {
+        self.events = Some(Vec::new());
+    }
+
+    /// The events since the last call.
+    pub fn take_events(&mut self) -> Vec<ServerEvent> {
+        self.events.as_mut().map(mem::take).unwrap_or_default()
+    }
+
+    /// Stops counting the data of the events taken so far against
+    /// `Config::max_queued`, the caller having sent back what it would,
+    /// and reads on for the clients that paused on it.
+    pub fn release_handed_out(&mut self) {
+        for conn in mem::take(&mut self.handed_out) {
+            match self.clients.get_mut(conn.index) {
+                Some(client) if client.id == conn.id => client.handed_out = 0,
+                _ => continue,
+            }
+            if let Some(reason) = self.resume_reading(conn.index) {
+                self.remove_client(conn.index, reason);
+            }
+        }
+    }
+
+    pub fn has_events(&self) -> bool {
+        self.events.as_ref().is_some_and(|events| !events.is_empty())
+    }
+
+    /// Queues `data` for `conn` and writes what its socket takes, unless
+    /// it is gone or closing.
+    pub fn send(&mut self, conn: Conn, data: Vec<u8>) {
+        let client = match self.clients.get_mut(conn.index) {
+            Some(client) if client.id == conn.id && !client.closing => client,
+            _ => return,
+        };
+        client.send(data);
+        let overflow = &mut self.stats.overflow;
+        let (messages, bytes) = mem::take(&mut client.dropped_newest);
+        overflow.dropped_newest += messages;
+        overflow.dropped_newest_bytes += bytes;
+        let (messages, bytes) = mem::take(&mut client.dropped_oldest);
+        overflow.dropped_oldest += messages;
+        overflow.dropped_oldest_bytes += bytes;
+        if client.overflowed {
+            self.remove_client(conn.index, CloseReason::Overflow);
+            return;
+        }
+        self.flush(conn.index);
+        if let Some(client) = self.clients.get_mut(conn.index) {
+            client.send_queued |= !client.bufs.is_empty();
+        }
+    }
+
+    /// Closes `conn` once what was sent to it is written, unless it is
+    /// already gone.
+    pub fn close_conn(&mut self, conn: Conn) {
+        match self.clients.get_mut(conn.index) {
+            Some(client) if client.id == conn.id => client.closing = true,
+            _ => return,
+        }
+        self.flush(conn.index);
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listeners.iter().find_map(|listener| match *listener {
+            Source::Tcp(ref listener) => listener.local_addr().ok(),
+            _ => None,
+        })
+    }
+
+    pub fn udp_addr(&self) -> Option<SocketAddr> {
+        self.listeners.iter().find_map(|listener| match *listener {
+            Source::Udp(ref udp) => udp.local_addr().ok(),
+            _ => None,
+        })
+    }
+
+    pub fn listener_addr(&self, entry: usize) -> Option<SocketAddr> {
+        let listener = self.profiles.iter().position(|profile| profile.entry == Some(entry))?;
+        self.listener_addrs[listener]
+    }
+
+    pub fn admin_addr(&self) -> Option<SocketAddr> {
+        self.admin.as_ref().and_then(|admin| admin.local_addr().ok())
+    }
+
+    pub fn health_addr(&self) -> Option<SocketAddr> {
+        self.health.as_ref().and_then(|health| health.local_addr().ok())
+    }
+
+    pub fn shutdown_requested(&self) -> bool {
+        self.shutdown
+    }
+
+    /// The time by the clock of the timers.
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// Time left until the next timer, to be used as the poll timeout.
+    pub fn next_timeout(&self, now: Instant) -> Option<Duration> {
+        self.timers.next_timeout(now)
+    }
+
+    // Records how long the iteration took and warns past
+    // `Config::stall_threshold`, blaming the phase that took longest
+    fn check_stall(&mut self, iteration: Duration, events: usize) {
+        let lp = &mut self.stats.event_loop;
+        lp.max_iteration = lp.max_iteration.max(iteration);
+        let threshold = self.config.stall_threshold;
+        if threshold == Duration::from_secs(0) || iteration <= threshold {
+            return;
+        }
+        lp.stalls += 1;
+        let times = self.phase_times;
+        let phase = Phase::ALL.iter().copied().max_by_key(|&phase| times[phase as usize]).expect("phases");
+        warn!(
+            "event loop stalled for {:?} over {} events, mostly in {} ({:?})",
+            iteration,
+            events,
+            phase.name(),
+            times[phase as usize]
+        );
+    }
+
+    /// Dispatches an event, returns false if its token isn't ours.
+    pub fn handle_event(&mut self, event: &Event) -> Result<bool, Error> {
+        let token = event.token();
+        let index = match token.0.checked_sub(self.token_base) {
+            Some(index) if index < self.max_clients + self.fixed_listeners => index,
+            _ => {
+                if let Some(listener) = self.added_listener(token) {
+                    return self.listener_ready(listener, token, event);
+                }
+                if self.control.is_some() && token == self.control_token() {
+                    self.control_ready();
+                    return Ok(true);
+                }
+                if let Some(index) = self.mirror_index(token) {
+                    self.mirror_ready(index);
+                    return Ok(true);
+                }
+                if let Some(ref mut health) = self.health {
+                    if health.ready(self.poll.registry(), token, self.draining).map_err(|e| Error::token(token, e))? {
+                        return Ok(true);
+                    }
+                }
+                if let Some(ref mut courtesy) = self.courtesy {
+                    if courtesy.ready(token) {
+                        return Ok(true);
+                    }
+                }
+                return self.admin_ready(token);
+            }
+        };
+
+        if index < self.max_clients {
+            if self.clients.contains(index) {
+                self.client_ready(index, event)?;
+            }
+            return Ok(true);
+        }
+
+        self.listener_ready(index - self.max_clients, token, event)
+    }
+
+    fn listener_ready(&mut self, listener: usize, token: Token, event: &Event) -> Result<bool, Error> {
+        let start = Instant::now();
+        let phase = match self.listeners[listener] {
+            Source::Udp(ref mut udp) => {
+                udp.ready(self.poll.registry(), token, event.is_readable(), self.clock.now(), &mut self.stats)
+                    .map_err(|e| Error::token(token, e))?;
+                #[cfg(feature = "quic")]
+                if let Some(deadline) = udp.quic_timer() {
+                    self.timers.insert(deadline, Timeout::Quic(listener));
+                }
+                Phase::Read
+            }
+            _ => {
+                self.accept_ready(listener)?;
+                Phase::Accept
+            }
+        };
+        self.phase_times[phase as usize] += start.elapsed();
+        Ok(true)
+    }
+
+    // The requests of the `ServerHandle`s come after everything else
+    fn control_token(&self) -> Token {
+        Token(self.mirror_token_base.saturating_add(self.max_clients))
+    }
+
+    // The listener added at runtime with this token
+    fn added_listener(&self, token: Token) -> Option<usize> {
+        let offset = token.0.checked_sub(self.control_token().0.checked_add(1)?)?;
+        Some(self.fixed_listeners + offset).filter(|&index| index < self.listeners.len())
+    }
+
+    /// A handle to add and remove listeners from other threads, see
+    /// `ServerHandle`.
+    pub fn handle(&mut self) -> Result<ServerHandle, Error> {
+        if self.control.is_none() {
+            if self.control_token().0 >= self.token_end {
+                return Err(Error::OutOfTokens("a handle"));
+            }
+            self.control = Some(Control::new(self.poll.registry(), self.control_token())?);
+        }
+        Ok(self.control.as_ref().expect("control registered").handle())
+    }
+
+    fn control_ready(&mut self) {
+        let requests = match self.control {
+            Some(ref control) => control.requests(),
+            None => return,
+        };
+        for request in requests {
+            // A handle that stopped waiting doesn't matter
+            match request {
+                Request::AddListener { addr, reply } => {
+                    let _ = reply.send(self.add_listener(&addr));
+                }
+                Request::RemoveListener { id, close_clients, reply } => {
+                    let _ = reply.send(self.remove_listener(id, close_clients));
+                }
+                Request::SetMaxClients {
+                    max_clients,
+                    evict_newest,
+                    reply,
+                } => {
+                    let _ = reply.send(self.set_max_clients(max_clients, evict_newest));
+                }
+                Request::SetIdleTimeout { timeout, reply } => {
+                    let _ = reply.send(self.set_idle_timeout(timeout));
+                }
+                Request::SetRateLimit { rate, reply } => {
+                    let _ = reply.send(self.set_rate_limit(rate));
+                }
+                Request::Shutdown { reply } => {
+                    self.terminate(self.clock.now());
+                    let _ = reply.send(Ok(()));
+                }
+                Request::Resolved { ip, name } => self.peer_resolved(ip, name),
+            }
+        }
+    }
+
+    // Names the clients of `ip` once the resolver thread answered
+    fn peer_resolved(&mut self, ip: IpAddr, name: Option<String>) {
+        let now = self.clock.now();
+        let name = match self.names {
+            Some(ref mut names) => names.resolved(ip, name, now),
+            None => return,
+        };
+        let name = match name {
+            Some(name) => name,
+            None => {
+                debug!("no name for {}", ip);
+                return;
+            }
+        };
+        for (_, client) in self.clients.iter_mut() {
+            match client.peer_addr() {
+                PeerAddr::Inet(peer) if peer.ip() == ip && client.peer_name.as_ref() != Some(&name) => {
+                    client.peer_name = Some(name.clone());
+                    info!("peer resolved : {} (#{})", client.peer(), client.id);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Binds another TCP listener with the top-level settings and starts
+    /// accepting on it.
+    pub fn add_listener(&mut self, addr: &str) -> Result<ListenerId, Error> {
+        if self.draining {
+            return Err(Error::Draining);
+        }
+        let index = self.listeners.len();
+        let token = self.control_token().0.saturating_add(1 + index - self.fixed_listeners);
+        if token >= self.token_end {
+            return Err(Error::OutOfTokens("another listener"));
+        }
+        let mut listener = open_listener(&addr.parse()?, &self.config)?;
+        register_listener(self.poll.registry(), &mut listener, Token(token), self.config.exclusive_accept)?;
+        let addr = listener.local_addr()?;
+        info!("listening on {} as listener {}", addr, index);
+        let listener = Source::Tcp(listener);
+        let mut profile = Profile::new(&self.config, None);
+        #[cfg(feature = "tls")]
+        profile.tls.clone_from(&self.tls);
+        if self.config.annotate {
+            profile.server = Some(server_name(&listener, &self.config).into());
+        }
+        self.listeners.push(listener);
+        self.profiles.push(profile);
+        self.listener_addrs.push(Some(addr));
+        Ok(ListenerId { index, addr })
+    }
+
+    /// Closes a listener `add_listener` added, and its clients too with
+    /// `close_clients`.
+    pub fn remove_listener(&mut self, id: ListenerId, close_clients: bool) -> Result<(), Error> {
+        let index = id.index;
+        match self.listeners.get(index) {
+            Some(Source::Tcp(_)) if index >= self.fixed_listeners => {}
+            // Broken, it just won't be bound again
+            Some(Source::Closed) if index >= self.fixed_listeners && self.rebinds.contains_key(&index) => {
+                self.rebinds.remove(&index);
+            }
+            _ => return Err(Error::UnknownListener(id)),
+        }
+        let mut listener = mem::replace(&mut self.listeners[index], Source::Closed);
+        if let Err(e) = listener.deregister(self.poll.registry()) {
+            debug!("listener deregister failed: {}", e);
+        }
+        info!("closed {}", id);
+        if close_clients {
+            let indexes: Vec<usize> = self
+                .clients
+                .iter()
+                .filter(|(_, client)| client.source == index)
+                .map(|(index, _)| index)
+                .collect();
+            for index in indexes {
+                let client = &self.clients[index];
+                info!("closing {} (#{}): listener removed", client.peer(), client.id);
+                self.remove_client(index, CloseReason::Kicked);
+            }
+        }
+        Ok(())
+    }
+
+    /// Serves at most `max_clients` clients, closing the newest ones past
+    /// it if `evict_newest` is set.
+    pub fn set_max_clients(&mut self, max_clients: usize, evict_newest: bool) -> Result<(), Error> {
+        if max_clients == 0 {
+            return Err(Error::config("max_clients must be positive"));
+        }
+        if max_clients > self.max_clients {
+            return Err(Error::TooManyClients {
+                requested: max_clients,
+                max: self.max_clients,
+            });
+        }
+        info!("max clients set to {} (was {})", max_clients, self.client_limit);
+        self.client_limit = max_clients;
+        self.stats.max_clients = max_clients;
+        let excess = self.clients.len().saturating_sub(max_clients);
+        if evict_newest && excess > 0 {
+            let mut newest: Vec<(u64, usize)> = self.clients.iter().map(|(index, client)| (client.id, index)).collect();
+            newest.sort_unstable_by(|a, b| b.cmp(a));
+            for &(id, index) in &newest[..excess] {
+                info!("closing {} (#{}): over max clients", self.clients[index].peer(), id);
+                self.stats.evictions += 1;
+                self.remove_client(index, CloseReason::Kicked);
+            }
+        } else if excess > 0 {
+            info!("{} clients over the limit, refusing new ones until they leave", excess);
+        }
+        Ok(())
+    }
+
+    /// Replaces `Config::idle_timeout`, rearming the timer of every client
+    /// for the new deadline.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        if timeout == Some(Duration::ZERO) {
+            return Err(Error::config("idle_timeout must be positive"));
+        }
+        info!("idle timeout set to {:?} (was {:?})", timeout, self.config.idle_timeout);
+        self.config.idle_timeout = timeout;
+        self.stats.idle_timeout = timeout;
+        for (index, client) in self.clients.iter_mut() {
+            client.idle_at = timeout.map(|timeout| client.last_activity + timeout);
+            if let Some(deadline) = client.idle_at {
+                self.timers.insert(deadline, Timeout::IdleClient(index));
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces `Config::global_rate`, releasing the clients held by the
+    /// cap when it is lifted.
+    pub fn set_rate_limit(&mut self, rate: Option<u64>) -> Result<(), Error> {
+        if rate == Some(0) {
+            return Err(Error::config("global_rate must be positive"));
+        }
+        info!("rate limit set to {:?} (was {:?})", rate, self.config.global_rate);
+        self.config.global_rate = rate;
+        self.stats.global_rate = rate;
+        let now = self.clock.now();
+        match (rate, self.throttle.as_mut()) {
+            (Some(rate), Some(throttle)) => throttle.set_rate(rate, now),
+            (Some(rate), None) => self.throttle = Some(Throttle::new(rate, now)),
+            (None, _) => {
+                self.stats.rate_utilization = 0;
+                if let Some(mut throttle) = self.throttle.take() {
+                    for index in throttle.release(now).0 {
+                        match self.clients.get_mut(index) {
+                            Some(client) if client.throttled => client.throttled = false,
+                            _ => continue,
+                        }
+                        self.flush(index);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Accepts every pending connection of a stream listener.
+    pub fn accept_ready(&mut self, listener: usize) -> Result<(), Error> {
+        let mut accepted = false;
+        let mut errors = 0;
+        // Perform operations in a loop until `WouldBlock` is encountered.
+        loop {
+            match self.listeners[listener].accept(self.poll.registry()) {
+                Some(Ok((sock, addr, transport))) => {
+                    if !accepted && !self.accept_pauses.is_empty() {
+                        self.accept_pauses.remove(&listener);
+                    }
+                    accepted = true;
+                    errors = 0;
+                    if let PeerAddr::Inet(peer) = addr {
+                        if self.bans.refuse(peer.ip(), self.clock.now()) {
+                            self.refuse(sock, addr, transport, listener, Refusal::Banned);
+                            continue;
+                        }
+                        if self.deny.as_ref().is_some_and(|deny| deny.denies(peer.ip())) {
+                            self.refuse(sock, addr, transport, listener, Refusal::Denied);
+                            continue;
+                        }
+                    }
+                    if self.entry_full(listener) {
+                        self.refuse(sock, addr, transport, listener, Refusal::ListenerFull);
+                    } else if self.clients.len() < self.client_limit {
+                        self.admit(sock, addr, transport, listener)?;
+                        if self.draining {
+                            // Closing the listeners refuses the rest of the burst
+                            return Ok(());
+                        }
+                    } else if self.pending.len() < self.config.pending_queue.unwrap_or(0) {
+                        debug!("too many clients, connection deferred : {}", addr);
+                        self.stats.deferred += 1;
+                        let now = self.clock.now();
+                        let deadline = now + self.config.pending_timeout;
+                        if !self.pending_check {
+                            self.pending_check = true;
+                            self.timers.insert(now + PENDING_CHECK_INTERVAL, Timeout::Pending);
+                        }
+                        self.pending.push_back(Parked {
+                            sock,
+                            addr,
+                            transport,
+                            listener,
+                            deadline,
+                        });
+                    } else {
+                        self.refuse(sock, addr, transport, listener, Refusal::Capacity);
+                    }
+                }
+                Some(Err(ref e)) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if !accepted {
+                        self.stats.event_loop.empty_accepts += 1;
+                    }
+                    // Socket is not ready anymore, stop accepting
+                    return Ok(());
+                }
+                Some(Err(ref e)) if transient_accept_error(e) => {
+                    debug!("accept failed: {}", e);
+                }
+                Some(Err(ref e)) if resource_error(e) => {
+                    self.pause_accepting(listener, e);
+                    return Ok(());
+                }
+                Some(Err(e)) if !matches!(self.listeners[listener], Source::Tcp(_)) => {
+                    return Err(Error::Accept(e));
+                }
+                Some(Err(e)) => {
+                    errors += 1;
+                    if errors == LISTENER_ERRORS_MAX {
+                        self.restart_listener(listener, &e);
+                        return Ok(());
+                    }
+                    debug!("accept on listener {} failed: {}", listener, e);
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+
+    // Stops accepting on a listener out of descriptors or memory, which
+    // keeps its socket and the connections waiting in its backlog, and
+    // arms the timer registering it again
+    fn pause_accepting(&mut self, listener: usize, e: &io::Error) {
+        if let Err(e) = self.listeners[listener].deregister(self.poll.registry()) {
+            debug!("listener deregister failed: {}", e);
+        }
+        self.stats.accept_pauses += 1;
+        let pauses = self.accept_pauses.entry(listener).or_insert(0);
+        let wait = ACCEPT_PAUSE_MIN.saturating_mul(1 << (*pauses).min(16)).min(ACCEPT_PAUSE_MAX);
+        *pauses += 1;
+        warn!("accept on listener {} failed: {}, pausing it for {:?}", listener, e, wait);
+        self.timers.insert(self.clock.now() + wait, Timeout::ResumeAccept(listener));
+    }
+
+    // Registers a paused listener again, which accepts whatever waited in
+    // its backlog
+    fn resume_accepting(&mut self, listener: usize) -> Result<(), Error> {
+        // Removed or closed by a drain since
+        if self.draining || matches!(self.listeners[listener], Source::Closed) {
+            self.accept_pauses.remove(&listener);
+            return Ok(());
+        }
+        debug!("listener {} accepts again", listener);
+        let token = self.listener_token(listener);
+        self.listeners[listener]
+            .register(self.poll.registry(), token, self.config.exclusive_accept)
+            .map_err(|e| Error::token(token, e))?;
+        // Edge triggered, the backlog may not signal again
+        self.accept_ready(listener)
+    }
+
+    // Closes a TCP listener accept keeps failing on and arms the timer
+    // binding it again
+    fn restart_listener(&mut self, listener: usize, e: &io::Error) {
+        let now = self.clock.now();
+        if let Err(e) = mem::replace(&mut self.listeners[listener], Source::Closed).deregister(self.poll.registry()) {
+            debug!("listener deregister failed: {}", e);
+        }
+        self.stats.listener_restarts += 1;
+        let addr = match self.listener_addrs[listener] {
+            Some(addr) if self.config.listener_rebind_attempts > 0 => addr,
+            _ => {
+                error!("listener {} broke: {}, draining", listener, e);
+                self.drain(now);
+                return;
+            }
+        };
+        warn!("listener {} on {} broke: {}, closed it to bind it again", listener, addr, e);
+        self.rebinds.insert(listener, 0);
+        self.timers.insert(now + BIND_RETRY_MIN, Timeout::Rebind(listener));
+    }
+
+    // Binds a broken TCP listener again, draining once that failed
+    // `Config::listener_rebind_attempts` times
+    fn rebind_listener(&mut self, listener: usize, now: Instant) {
+        // Removed or draining since
+        let failed = match self.rebinds.get(&listener) {
+            Some(&failed) if !self.draining => failed,
+            _ => return,
+        };
+        let addr = self.listener_addrs[listener].expect("rebound listeners have an address");
+        let token = self.listener_token(listener);
+        let bound = open_listener(&addr, &self.config).and_then(|mut l| {
+            register_listener(self.poll.registry(), &mut l, token, self.config.exclusive_accept)?;
+            Ok(l)
+        });
+        match bound {
+            Ok(l) => {
+                info!("listener {} bound again on {}", listener, addr);
+                self.listeners[listener] = Source::Tcp(l);
+                self.rebinds.remove(&listener);
+            }
+            Err(e) if failed + 1 >= self.config.listener_rebind_attempts => {
+                error!("binding listener {} on {} again failed {} times: {}, draining", listener, addr, failed + 1, e);
+                self.rebinds.remove(&listener);
+                self.drain(now);
+            }
+            Err(e) => {
+                let wait = BIND_RETRY_MIN.saturating_mul(1 << (failed + 1).min(16)).min(BIND_RETRY_MAX);
+                warn!("binding listener {} on {} again failed: {}, retrying in {:?}", listener, addr, e, wait);
+                self.rebinds.insert(listener, failed + 1);
+                self.timers.insert(now + wait, Timeout::Rebind(listener));
+            }
+        }
+    }
+
+    // The token of the listener at this index
+    fn listener_token(&self, listener: usize) -> Token {
+        if listener < self.fixed_listeners {
+            Token(self.token_base + self.max_clients + listener)
+        } else {
+            Token(self.control_token().0 + 1 + listener - self.fixed_listeners)
+        }
+    }
+
+    // Hands the QUIC connections of a UDP socket their timeouts, arming
+    // the timer for the next
+    #[cfg(feature = "quic")]
+    fn quic_timeout(&mut self, listener: usize, now: Instant) -> Result<(), Error> {
+        let token = self.listener_token(listener);
+        // Removed since
+        if let Some(Source::Udp(ref mut udp)) = self.listeners.get_mut(listener) {
+            udp.quic_timeout(self.poll.registry(), token, now, &mut self.stats)
+                .map_err(|e| Error::token(token, e))?;
+            if let Some(deadline) = udp.quic_timer() {
+                self.timers.insert(deadline, Timeout::Quic(listener));
+            }
+        }
+        Ok(())
+    }
+
+    // Whether the entry of `Config::listeners` behind a listener has as
+    // many clients as it may
+    fn entry_full(&self, listener: usize) -> bool {
+        self.profiles[listener]
+            .entry
+            .is_some_and(|entry| self.entry_clients[entry] >= self.entry_max_clients[entry])
+    }
+
+    // Makes a client of an accepted connection with the settings of its
+    // listener, draining once it's the last of
+    // `Config::max_connections_total`
+    fn admit(&mut self, sock: Stream, addr: PeerAddr, transport: Transport, listener: usize) -> Result<(), Error> {
+        let profile = self.profiles[listener].clone();
+        self.accepted += 1;
+        let original_dst = if self.config.log_original_dst { original_dst(&sock, addr) } else { None };
+        // Cached names only, the others are logged once they come
+        let peer_name = match (&mut self.names, addr) {
+            (Some(names), PeerAddr::Inet(peer)) => names.get(peer.ip(), self.clock.now()),
+            _ => None,
+        };
+        let peer = Peer {
+            addr,
+            name: peer_name.as_deref(),
+        };
+        match original_dst {
+            Some(dst) => info!("connection established : {} (#{}, original dst {})", peer, self.accepted, dst),
+            None => info!("connection established : {} (#{})", peer, self.accepted),
+        }
+        if let Stream::Tcp(ref sock) = sock {
+            size_buffers(sock, &self.config, addr);
+            #[cfg(target_os = "linux")]
+            if let Some(timeout) = self.config.tcp_user_timeout {
+                if let Err(e) = crate::sys::set_tcp_user_timeout(sock, timeout) {
+                    warn!("setting TCP_USER_TIMEOUT failed: {} : {}", e, addr);
+                }
+            }
+            #[cfg(target_os = "linux")]
+            if let Some(busy_poll) = self.config.busy_poll {
+                if let Err(e) = crate::sys::set_busy_poll(sock, busy_poll) {
+                    warn!("setting SO_BUSY_POLL failed: {} : {}", e, addr);
+                }
+            }
+        }
+        #[cfg(feature = "tls")]
+        let sock = match (sock, &profile.tls) {
+            (Stream::Tcp(sock), Some(tls)) => Stream::Tls(Box::new(TlsStream::new(sock, tls.clone(), addr)?)),
+            (sock, _) => sock,
+        };
+        self.stats.transport_mut(transport).connections += 1;
+        if let Some(entry) = profile.entry {
+            self.stats.listeners[entry].connections += 1;
+            self.entry_clients[entry] += 1;
+        }
+        // getsockname is only allowed by seccomp when capturing
+        let tap = match (&self.capture, addr) {
+            (Some(capture), PeerAddr::Inet(peer)) => sock.local_addr().map(|local| capture.tap(local, peer)),
+            _ => None,
+        };
+        let max_write_chunk = self.config.max_write_chunk;
+        let mut client = match self.spare_clients.pop() {
+            Some(shell) => shell.reuse(sock, addr, transport, max_write_chunk),
+            None => Client::new(sock, addr, transport, max_write_chunk, VecDeque::new()),
+        };
+        client.set_clock(self.clock.clone());
+        client.dump_limit = self.config.dump_limit;
+        client.max_queued = profile.max_queued;
+        client.overflow = profile.overflow;
+        client.short_read_drained = self.config.short_read_drained;
+        client.read_buf_size = self.config.read_buf_size;
+        client.original_dst = original_dst;
+        client.peer_name = peer_name;
+        if let (Some(threshold), Transport::Tcp) = (self.config.zerocopy, transport) {
+            if let Err(e) = client.enable_zerocopy(threshold) {
+                debug!("setting SO_ZEROCOPY failed, writing as usual: {} : {}", e, addr);
+                self.stats.zerocopy.unsupported += 1;
+            }
+        }
+        client.tap = tap;
+        let framer = |framing| Framer::new(framing, profile.checksum, profile.verify_checksum);
+        client.decoder = match profile.mode {
+            Mode::Http => Some(Decoder::Http(Http::default())),
+            Mode::Line => Some(Decoder::Framer(framer(Framing::Line))),
+            Mode::Length => Some(Decoder::Framer(framer(Framing::Length))),
+            Mode::Echo if profile.telnet => Some(Decoder::Telnet(Telnet::default())),
+            Mode::Echo if self.config.allow_mode_negotiation => Some(Decoder::Negotiate(Negotiator::default())),
+            Mode::Echo => None,
+        };
+        client.annotator = profile.server.map(Annotator::new);
+        client.listener = profile.entry;
+        client.source = listener;
+        client.id = self.accepted;
+        self.new_client(client)?;
+        if self.config.max_connections_total == Some(self.accepted) {
+            info!("accepted {} connections", self.accepted);
+            self.drain(self.clock.now());
+        }
+        Ok(())
+    }
+
+    // Every refusal goes through here to be audited, dropping the socket
+    // refuses this client only
+    fn refuse(&mut self, sock: Stream, addr: PeerAddr, transport: Transport, listener: usize, reason: Refusal) {
+        let load = Load {
+            clients: self.clients.len(),
+            max_clients: self.client_limit,
+            pending: self.pending.len(),
+        };
+        self.audit.record(&mut self.stats, self.clock.now(), &addr, reason, load);
+        match reason {
+            // Not worth a busy message
+            Refusal::Banned => {
+                self.stats.banned += 1;
+                return;
+            }
+            Refusal::Denied => return,
+            _ => {}
+        }
+        self.stats.transport_mut(transport).rejected += 1;
+        if let Some(entry) = self.profiles[listener].entry {
+            self.stats.listeners[entry].rejected += 1;
+        }
+        if let Some(ref mut courtesy) = self.courtesy {
+            if let Some((index, deadline)) = courtesy.refuse(self.poll.registry(), sock, addr, self.clock.now()) {
+                self.timers.insert(deadline, Timeout::Courtesy(index));
+            }
+        }
+    }
+
+    // Moves the oldest pending connections into the free slots and refuses
+    // those that waited too long
+    fn promote_pending(&mut self, now: Instant) {
+        while let Some(parked) = self.pending.pop_front() {
+            if parked.deadline <= now {
+                self.stats.deferred_expired += 1;
+                self.refuse(parked.sock, parked.addr, parked.transport, parked.listener, Refusal::PendingTimeout);
+                continue;
+            }
+            if self.entry_full(parked.listener) {
+                self.refuse(parked.sock, parked.addr, parked.transport, parked.listener, Refusal::ListenerFull);
+                continue;
+            }
+            if self.clients.len() >= self.client_limit {
+                self.pending.push_front(parked);
+                return;
+            }
+            let addr = parked.addr;
+            if let Err(e) = self.admit(parked.sock, addr, parked.transport, parked.listener) {
+                error!("promoting a pending connection failed: {} : {}", e, addr);
+            }
+        }
+    }
+
+    fn new_client(&mut self, mut client: Client) -> Result<(), Error> {
+        let connected = self.call_handler(&mut client, |handler, ctx| handler.on_connect(ctx));
+        let index = self.clients.insert(client);
+        self.idle_since = None;
+        let client = &mut self.clients[index];
+        if let Some(ref banner) = self.config.banner {
+            client.bufs.push_back(banner.clone());
+        }
+        if let Some(interval) = self.config.heartbeat_interval {
+            let deadline = client.last_activity + interval;
+            client.heartbeat_at = Some(deadline);
+            self.timers.insert(deadline, Timeout::Heartbeat(index));
+        }
+        if let Some(timeout) = self.config.first_byte_timeout {
+            self.timers.insert(client.accepted_at + timeout, Timeout::FirstByte(index));
+        }
+        if let Some(timeout) = self.config.idle_timeout {
+            let deadline = client.last_activity + timeout;
+            client.idle_at = Some(deadline);
+            self.timers.insert(deadline, Timeout::IdleClient(index));
+        }
+        client.register(self.poll.registry(), Token(self.token_base + index))?;
+        if let Some(ref mut events) = self.events {
+            let conn = Conn { index, id: client.id };
+            events.push(ServerEvent::Accepted { conn, peer: client.peer_addr() });
+        }
+        if let Some(ref addr) = self.mirror {
+            let registry = self.poll.registry();
+            let token = Token(self.mirror_token_base + index);
+            match Mirror::connect(addr).and_then(|mut mirror| mirror.register(registry, token).map(|()| mirror)) {
+                Ok(mirror) => client.mirror = Some(mirror),
+                Err(e) => {
+                    warn!("mirror connect failed: {} : {}", e, client.peer());
+                    self.stats.mirror_errors += 1;
+                }
+            }
+        }
+        if !connected {
+            self.remove_client(index, CloseReason::HandlerPanic);
+        }
+        Ok(())
+    }
+
+    // The client whose mirror connection has this token
+    fn mirror_index(&self, token: Token) -> Option<usize> {
+        self.mirror?;
+        token.0.checked_sub(self.mirror_token_base).filter(|&index| index < self.max_clients)
+    }
+
+    fn mirror_ready(&mut self, index: usize) {
+        if let Some(client) = self.clients.get_mut(index) {
+            if let Some(ref mut mirror) = client.mirror {
+                mirror.ready();
+            }
+            check_mirror(client, self.poll.registry(), &mut self.stats);
+        }
+    }
+
+    // Runs the commands completed by an admin socket event
+    fn admin_ready(&mut self, token: Token) -> Result<bool, Error> {
+        // Taken out so the commands can borrow the reactor
+        let mut admin = match self.admin.take() {
+            Some(admin) => admin,
+            None => return Ok(false),
+        };
+        let ours = admin.ready(self.poll.registry(), token);
+        while let Some((index, line)) = admin.next_command() {
+            let reply = self.admin_command(&line);
+            admin.reply(self.poll.registry(), index, &reply);
+        }
+        self.admin = Some(admin);
+        ours.map_err(|e| Error::token(token, e))
+    }
+
+    fn admin_command(&mut self, line: &str) -> String {
+        let command = match Command::parse(line) {
+            Ok(command) => command,
+            Err(e) => return format!("error: {}", e),
+        };
+        match command {
+            Command::Kick { id, flush, reason } => {
+                match self.clients.iter().find(|(_, client)| client.id == id) {
+                    Some((index, _)) => {
+                        self.kick(index, flush, &reason);
+                        "ok".to_string()
+                    }
+                    None => "not-found".to_string(),
+                }
+            }
+            Command::KickAll { flush, reason } => {
+                let indexes: Vec<usize> = self.clients.iter().map(|(index, _)| index).collect();
+                for index in indexes {
+                    self.kick(index, flush, &reason);
+                }
+                "ok".to_string()
+            }
+            Command::Ban { ip, duration } => {
+                info!("banning {} for {:?}", ip, duration);
+                let until = self.bans.ban(ip, duration, self.clock.now());
+                self.timers.insert(until, Timeout::Unban(ip));
+                "ok".to_string()
+            }
+            Command::Unban { ip } => {
+                if self.bans.unban(ip) {
+                    info!("unbanned {}", ip);
+                    "ok".to_string()
+                } else {
+                    "not-found".to_string()
+                }
+            }
+            Command::Bans => self.bans.list(self.clock.now()),
+            Command::Latency => self
+                .stats
+                .queue_latency_summary()
+                .unwrap_or_else(|| "no data".to_string()),
+            Command::UdpSources => self
+                .listeners
+                .iter()
+                .find_map(|listener| match *listener {
+                    Source::Udp(ref udp) => udp.sources(),
+                    _ => None,
+                })
+                .unwrap_or_else(|| "not tracked".to_string()),
+            Command::Stats => self.stats.to_string(),
+            Command::ListenerStats => {
+                let entries: Vec<String> = (0..self.config.listeners.len())
+                    .map(|entry| {
+                        let addr = self.listener_addr(entry).map_or("-".to_string(), |addr| addr.to_string());
+                        format!("listener {} ({}): {}", entry, addr, self.stats.listeners[entry])
+                    })
+                    .collect();
+                if entries.is_empty() {
+                    "none".to_string()
+                } else {
+                    entries.join("; ")
+                }
+            }
+        }
+    }
+
+    /// Closes a client on behalf of the admin socket, after writing what
+    /// its socket takes of the queued echo when `flush` is set.
+    pub fn kick(&mut self, index: usize, flush: bool, reason: &str) {
+        let client = &mut self.clients[index];
+        let reason = if reason.is_empty() { "no reason given" } else { reason };
+        info!("kicking {} (#{}): {}", client.peer(), client.id, reason);
+        if flush {
+            client.resume_at = None;
+            client.flush_at = None;
+            client.throttled = false;
+            client.write_parked = false;
+            client.coalesce();
+            if let Some(reason) = self.write(index) {
+                debug!("flush before kick failed ({}) : {}", reason, self.clients[index].peer());
+            }
+        }
+        self.stats.admin_kicks += 1;
+        if let Some(entry) = self.clients[index].listener {
+            self.stats.listeners[entry].kicks += 1;
+        }
+        self.remove_client(index, CloseReason::Kicked);
+    }
+
+    /// Reads and echoes back whatever a client's readiness allows.
+    pub fn client_ready(&mut self, index: usize, event: &Event) -> Result<(), Error> {
+        let start = Instant::now();
+        #[cfg(unix)]
+        if event.is_error() {
+            let client = &mut self.clients[index];
+            match client.zerocopy_ready() {
+                Ok(copied) => self.stats.zerocopy.copied += copied,
+                Err(e) => {
+                    let reason = io_error(&e, client);
+                    self.remove_client(index, reason);
+                    return Ok(());
+                }
+            }
+        }
+        if event.is_readable() {
+            // The handler's part is timed on its own
+            let handler = self.phase_times[Phase::Handler as usize];
+            let reason = self.read(index);
+            let handled = self.phase_times[Phase::Handler as usize] - handler;
+            self.phase_times[Phase::Read as usize] += start.elapsed().saturating_sub(handled);
+            if let Some(reason) = reason {
+                self.remove_client(index, reason);
+                return Ok(());
+            }
+        }
+        let start = Instant::now();
+        self.flush(index);
+        self.phase_times[Phase::Write as usize] += start.elapsed();
+        Ok(())
+    }
+
+    /// Deregisters and drops a client.
+    pub fn remove_client(&mut self, index: usize, reason: CloseReason) {
+        let mut client = self.clients.remove(index);
+        // Dropping the socket unregisters it anyway
+        if let Err(e) = client.deregister(self.poll.registry()) {
+            debug!("deregister failed: {} : {}", e, client.peer());
+        }
+        if let Some(mut mirror) = client.mirror.take() {
+            self.stats.mirror_dropped += mirror.take_dropped();
+            if let Err(e) = mirror.deregister(self.poll.registry()) {
+                debug!("mirror deregister failed: {} : {}", e, client.peer());
+            }
+        }
+        if let Some(entry) = client.listener {
+            self.entry_clients[entry] -= 1;
+        }
+        self.call_handler(&mut client, |handler, ctx| handler.on_disconnect(ctx, reason));
+        record_close(&mut self.stats, &client, reason);
+        self.log_access(&client, reason);
+        if let Some(ref mut events) = self.events {
+            let conn = Conn { index, id: client.id };
+            events.push(ServerEvent::Closed { conn, reason });
+        }
+        let now = self.clock.now();
+        self.promote_pending(now);
+        if self.clients.is_empty() {
+            if self.draining {
+                self.shutdown = true;
+            }
+            self.idle_since = Some(now);
+            if let Some(idle) = self.config.exit_when_idle {
+                self.timers.insert(now + idle, Timeout::Idle);
+            }
+        }
+
+        let zerocopy = client.take_zerocopy();
+        // A queue a burst made huge isn't worth keeping around
+        let (sock, shell) = client.into_shell(MAX_SPARE_BUF_CAPACITY);
+        if let Some(zerocopy) = zerocopy {
+            // Closing the socket would let the kernel go on sending from
+            // buffers freed meanwhile, only the peer is told
+            if let Stream::Tcp(ref sock) = sock {
+                let _ = sock.shutdown(Shutdown::Both);
+            }
+            self.stats.zerocopy.lingered += 1;
+            self.lingering.push((now + ZEROCOPY_LINGER, sock, zerocopy));
+        }
+        if self.spare_clients.len() < MAX_SPARE_CLIENTS {
+            self.spare_clients.push(shell);
+        }
+    }
+
+    // Returns why the client must be closed, if it must
+    fn read(&mut self, index: usize) -> Option<CloseReason> {
+        let client = &mut self.clients[index];
+        let paused = client.read_paused();
+        let result = match self.handler {
+            // Drops the input of those the caller closed
+            _ if self.events.is_some() && client.closing => client.read_handled(&mut |_| Action::Silence),
+            _ if self.events.is_some() => {
+                let events = self.events.as_mut().expect("events enabled");
+                let conn = Conn { index, id: client.id };
+                let handed_out = client.handed_out;
+                let result = client.read_owned(&mut |bytes| events.push(ServerEvent::Data { conn, bytes }));
+                if handed_out == 0 && client.handed_out > 0 {
+                    self.handed_out.push(conn);
+                }
+                result
+            }
+            Some(ref mut handler) => {
+                let mut ctx = HandlerContext::new(client.id, client.peer_addr(), client.handler_data.take());
+                let handled = &mut self.phase_times[Phase::Handler as usize];
+                let mut on_data = |data: &[u8]| {
+                    let start = Instant::now();
+                    let action = handler.on_data(&mut ctx, data);
+                    *handled += start.elapsed();
+                    action
+                };
+                // The handler is documented to cope with being called
+                // again after a panic
+                let read = AssertUnwindSafe(|| client.read_handled(&mut on_data));
+                let result = panic::catch_unwind(read);
+                client.handler_data = ctx.into_data();
+                match result {
+                    Ok(result) => result,
+                    Err(payload) => {
+                        let peer = client.peer_addr();
+                        self.handler_panicked(&*payload, peer);
+                        return Some(CloseReason::HandlerPanic);
+                    }
+                }
+            }
+            // Drops the input of those the handler was done with before it
+            // got disabled
+            None if client.muted || client.closing => client.read_handled(&mut |_| Action::Silence),
+            None => client.read(),
+        };
+        if client.aborted {
+            return Some(CloseReason::HandlerAbort);
+        }
+        match result {
+            Ok(None) => Some(CloseReason::Eof),
+            Ok(Some(len)) => {
+                info!("read {} bytes : {}", len, client.peer());
+                self.stats.transport_mut(client.transport).bytes_read += len as u64;
+                if let Some(entry) = client.listener {
+                    self.stats.listeners[entry].bytes_read += len as u64;
+                }
+                client.bytes_read += len as u64;
+                let overflow = &mut self.stats.overflow;
+                overflow.pauses += u64::from(client.read_paused() && !paused);
+                let (messages, bytes) = mem::take(&mut client.dropped_newest);
+                overflow.dropped_newest += messages;
+                overflow.dropped_newest_bytes += bytes;
+                let (messages, bytes) = mem::take(&mut client.dropped_oldest);
+                overflow.dropped_oldest += messages;
+                overflow.dropped_oldest_bytes += bytes;
+                check_mirror(client, self.poll.registry(), &mut self.stats);
+                if client.overflowed {
+                    return Some(CloseReason::Overflow);
+                }
+                if let (Some(quiesce), true) = (self.config.quiesce, len > 0) {
+                    if client.queued_bytes() >= self.config.quiesce_max {
+                        // Held long enough, don't wait for a pause
+                        client.flush_at = None;
+                        client.coalesce();
+                    } else {
+                        let deadline = self.clock.now() + quiesce;
+                        if client.flush_at.is_none() {
+                            self.timers.insert(deadline, Timeout::Quiesce(index));
+                        }
+                        client.flush_at = Some(deadline);
+                    }
+                }
+                if let Some(delay) = client.take_echo_delay() {
+                    // Held from the first read of a burst, a quiesce hold
+                    // may push it back
+                    if client.flush_at.is_none() {
+                        let deadline = self.clock.now() + delay;
+                        self.timers.insert(deadline, Timeout::Quiesce(index));
+                        client.flush_at = Some(deadline);
+                    }
+                }
+                None
+            }
+            Err(e) => Some(io_error(&e, client)),
+        }
+    }
+
+    // Writes what the client allows and closes it on error
+    fn flush(&mut self, index: usize) {
+        if let Some(reason) = self.write(index).or_else(|| self.resume_reading(index)) {
+            self.remove_client(index, reason);
+        }
+    }
+
+    // Reads and writes a client paused by backpressure for as long as its
+    // queue has room, the data it left unread raises no event
+    fn resume_reading(&mut self, index: usize) -> Option<CloseReason> {
+        while self.clients[index].can_resume_reading() {
+            if let Some(reason) = self.read(index).or_else(|| self.write(index)) {
+                return Some(reason);
+            }
+        }
+        None
+    }
+
+    fn write(&mut self, index: usize) -> Option<CloseReason> {
+        let client = &mut self.clients[index];
+        if client.bufs.is_empty() {
+            // Nothing queued, the socket may still hold output of its own
+            if let Err(e) = client.flush_socket() {
+                return Some(io_error(&e, client));
+            }
+            // The interest may still have to drop writable
+            return self.update_interest(index);
+        }
+        // First come, the clients held once it ran out split the refills
+        let share = match self.throttle {
+            Some(ref mut throttle) => throttle.share(self.clock.now(), 1),
+            None => usize::MAX,
+        };
+        self.write_share(index, share)
+    }
+
+    // Writes at most `share` bytes, holding the client until the next
+    // refill of the rate cap if that wasn't enough, or at most the write
+    // budget, parking it until the next round
+    fn write_share(&mut self, index: usize, share: usize) -> Option<CloseReason> {
+        let client = &mut self.clients[index];
+        if client.resume_at.is_some() || client.flush_at.is_some() || client.throttled || client.write_parked {
+            // Paused between two chunks or held, a timer resumes writing
+            return None;
+        }
+
+        let delay = self.config.inter_chunk_delay;
+        let budget = self.config.write_budget;
+        let len = match client.write_limited(delay.is_some(), share.min(budget)) {
+            Ok(len) => len,
+            Err(e) => return Some(io_error(&e, client)),
+        };
+        info!("write {} bytes : {}", len, client.peer());
+        let (writes, bytes) = mem::take(&mut client.zerocopy_writes);
+        self.stats.zerocopy.writes += writes;
+        self.stats.zerocopy.bytes += bytes;
+        self.stats.zerocopy.fallbacks += mem::take(&mut client.zerocopy_fallbacks);
+        // Headers aren't echoed payload
+        let headers = mem::take(&mut client.header_bytes);
+        self.stats.annotation_bytes += headers;
+        let payload = len as u64 - headers;
+        self.stats.transport_mut(client.transport).bytes_written += payload;
+        if let Some(entry) = client.listener {
+            self.stats.listeners[entry].bytes_written += payload;
+        }
+        client.bytes_written += payload;
+        let stats = &mut self.stats;
+        client.drain_queued_latencies(|latency| stats.record_queue_latency(latency));
+        if let Some(ref mut throttle) = self.throttle {
+            throttle.take(len);
+            if len >= share && !client.bufs.is_empty() {
+                // The socket may still be writable, no event would resume it
+                client.throttled = true;
+                self.stats.throttled += 1;
+                if let Some(deadline) = throttle.hold(index, self.clock.now()) {
+                    self.timers.insert(deadline, Timeout::Refill);
+                }
+            }
+        }
+        if len >= budget && !client.bufs.is_empty() && !client.throttled {
+            // The socket may still be writable, no event would resume it
+            client.write_parked = true;
+            self.stats.write_parks += 1;
+            if self.pending_writers.is_empty() {
+                self.timers.insert(self.clock.now(), Timeout::PendingWrites);
+            }
+            self.pending_writers.push(index);
+        }
+        if let Some(delay) = delay {
+            if len > 0 && !client.bufs.is_empty() {
+                let deadline = self.clock.now() + delay;
+                client.resume_at = Some(deadline);
+                self.timers.insert(deadline, Timeout::ResumeWrite(index));
+            }
+        }
+        self.update_interest(index)
+    }
+
+    // Closes a client done once its queue is written, or else registers
+    // for writable events while anything is queued
+    fn update_interest(&mut self, index: usize) -> Option<CloseReason> {
+        let client = &mut self.clients[index];
+        if client.done() {
+            return Some(if client.closing { CloseReason::HandlerClose } else { CloseReason::Done });
+        }
+        if self.terminating && client.bufs.is_empty() && !client.sock_mut().wants_write() {
+            return Some(CloseReason::Shutdown);
+        }
+        match client.reregister(self.poll.registry(), Token(self.token_base + index)) {
+            Ok(true) => self.stats.event_loop.reregisters += 1,
+            Ok(false) => {}
+            Err(e) => return Some(io_error(&e, client)),
+        }
+        if client.bufs.is_empty() && mem::take(&mut client.send_queued) {
+            if let Some(ref mut events) = self.events {
+                let conn = Conn { index, id: client.id };
+                events.push(ServerEvent::WritableAgain { conn });
+            }
+        }
+        None
+    }
+
+    /// Fires every timer whose deadline has passed.
+    pub fn expire_timers(&mut self, now: Instant) -> Result<(), Error> {
+        #[cfg(unix)]
+        self.check_signals(now);
+        if let Some(ref capture) = self.capture {
+            self.stats.capture_dropped = capture.dropped();
+        }
+        if let Some(ref access_log) = self.access_log {
+            self.stats.access_log_dropped = access_log.dropped();
+        }
+        if let Some(ref mut throttle) = self.throttle {
+            self.stats.rate_utilization = throttle.utilization(now);
+        }
+        while let Some(timeout) = self.timers.pop_expired(now) {
+            match timeout {
+                Timeout::ResumeWrite(index) => {
+                    match self.clients.get_mut(index) {
+                        Some(client) if client.resume_at.is_some_and(|at| at <= now) => {
+                            client.resume_at = None;
+                        }
+                        _ => continue,
+                    }
+                    self.flush(index);
+                }
+                Timeout::Quiesce(index) => {
+                    let client = match self.clients.get_mut(index) {
+                        Some(client) => client,
+                        None => continue,
+                    };
+                    match client.flush_at {
+                        Some(at) if at <= now => {
+                            client.flush_at = None;
+                            client.coalesce();
+                        }
+                        Some(at) => {
+                            // Pushed back by reads since this timer was armed
+                            self.timers.insert(at, Timeout::Quiesce(index));
+                            continue;
+                        }
+                        None => continue,
+                    }
+                    self.flush(index);
+                }
+                Timeout::Refill => {
+                    let (held, share) = match self.throttle {
+                        Some(ref mut throttle) => throttle.release(now),
+                        None => continue,
+                    };
+                    for index in held {
+                        match self.clients.get_mut(index) {
+                            Some(client) if client.throttled => client.throttled = false,
+                            _ => continue,
+                        }
+                        if let Some(reason) = self.write_share(index, share).or_else(|| self.resume_reading(index)) {
+                            self.remove_client(index, reason);
+                        }
+                    }
+                }
+                Timeout::PendingWrites => {
+                    for index in mem::take(&mut self.pending_writers) {
+                        match self.clients.get_mut(index) {
+                            Some(client) if client.write_parked => client.write_parked = false,
+                            _ => continue,
+                        }
+                        self.flush(index);
+                    }
+                }
+                Timeout::Courtesy(index) => {
+                    if let Some(ref mut courtesy) = self.courtesy {
+                        courtesy.expire(index, now);
+                    }
+                }
+                Timeout::Pending => {
+                    self.promote_pending(now);
+                    self.pending_check = match self.pending.front() {
+                        Some(parked) => {
+                            let at = parked.deadline.min(now + PENDING_CHECK_INTERVAL);
+                            self.timers.insert(at, Timeout::Pending);
+                            true
+                        }
+                        None => false,
+                    };
+                }
+                Timeout::Heartbeat(index) => {
+                    match self.clients.get(index) {
+                        Some(client) if client.heartbeat_at.is_some_and(|at| at <= now) => {}
+                        _ => continue,
+                    }
+                    self.heartbeat(index, now);
+                }
+                Timeout::FirstByte(index) => {
+                    let timeout = match self.config.first_byte_timeout {
+                        Some(timeout) => timeout,
+                        None => continue,
+                    };
+                    // Stale if the slot went to a newer client since
+                    match self.clients.get(index) {
+                        Some(client) if client.bytes_read == 0 && client.accepted_at + timeout <= now => {
+                            debug!("nothing received in {:?} : {}", timeout, client.peer());
+                        }
+                        _ => continue,
+                    }
+                    self.remove_client(index, CloseReason::Silent);
+                }
+                Timeout::IdleClient(index) => {
+                    match self.clients.get(index) {
+                        Some(client) if client.idle_at.is_some_and(|at| at <= now) => {}
+                        _ => continue,
+                    }
+                    self.check_idle(index, now);
+                }
+                Timeout::Tick => self.tick(now),
+                Timeout::Statsd => self.push_stats(now),
+                Timeout::Deadline => {
+                    info!("run duration elapsed");
+                    self.drain(now);
+                }
+                Timeout::Idle => {
+                    let idle = match self.config.exit_when_idle {
+                        Some(idle) => idle,
+                        None => continue,
+                    };
+                    // Stale if a client connected since this timer was armed
+                    if self.idle_since.is_some_and(|since| since + idle <= now) {
+                        info!("idle for {:?}", idle);
+                        self.drain(now);
+                    }
+                }
+                Timeout::Unban(ip) => {
+                    // Stale if the ban was lifted or renewed since
+                    if self.bans.expire(ip, now) {
+                        info!("ban of {} expired", ip);
+                    }
+                }
+                #[cfg(unix)]
+                Timeout::Upgrade => self.check_upgrade(now),
+                Timeout::Shrink => {
+                    self.reap_lingering(now);
+                    self.shrink_buffers(now);
+                }
+                Timeout::Deny => {
+                    self.timers.insert(now + DENY_CHECK_INTERVAL, Timeout::Deny);
+                    self.reload_deny(false);
+                }
+                Timeout::Rebind(listener) => self.rebind_listener(listener, now),
+                Timeout::ResumeAccept(listener) => self.resume_accepting(listener)?,
+                #[cfg(feature = "quic")]
+                Timeout::Quic(listener) => self.quic_timeout(listener, now)?,
+                Timeout::UdpSources => {
+                    for listener in &mut self.listeners {
+                        if let Source::Udp(ref mut udp) = *listener {
+                            udp.expire_sources(now, &mut self.stats);
+                        }
+                    }
+                    self.timers.insert(now + SOURCES_CHECK_INTERVAL, Timeout::UdpSources);
+                }
+                #[cfg(unix)]
+                Timeout::Watchdog => {
+                    if let Some(ref watchdog) = self.watchdog {
+                        watchdog.ping();
+                        self.timers.insert(now + watchdog.interval(), Timeout::Watchdog);
+                    }
+                }
+                Timeout::Drain => {
+                    if !self.clients.is_empty() {
+                        info!("drain timeout, closing {} clients", self.clients.len());
+                    }
+                    self.shutdown = true;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Closes the listeners and stops once the clients have left, or
+    /// `Config::drain_timeout` later.
+    pub fn drain(&mut self, now: Instant) {
+        if self.draining {
+            return;
+        }
+        self.draining = true;
+        for listener in &mut self.listeners {
+            // Dropping the socket unregisters it
+            *listener = Source::Closed;
+        }
+        // They would never get a slot
+        for parked in mem::take(&mut self.pending) {
+            self.refuse(parked.sock, parked.addr, parked.transport, parked.listener, Refusal::Draining);
+        }
+        if self.clients.is_empty() {
+            self.shutdown = true;
+        } else {
+            info!("draining {} clients", self.clients.len());
+            self.timers.insert(now + self.config.drain_timeout, Timeout::Drain);
+        }
+    }
+
+    /// Deregisters and drops every client and listener, then waits for
+    /// the capture file to be flushed. Closing again does nothing.
+    pub fn close(&mut self) {
+        for (_, mut client) in mem::take(&mut self.clients) {
+            if let Err(e) = client.deregister(self.poll.registry()) {
+                debug!("deregister failed: {} : {}", e, client.peer());
+            }
+            self.call_handler(&mut client, |handler, ctx| handler.on_disconnect(ctx, CloseReason::Shutdown));
+            self.log_access(&client, CloseReason::Shutdown);
+        }
+        self.pending.clear();
+        let registry = self.poll.registry();
+        for mut listener in self.listeners.drain(..) {
+            if let Err(e) = listener.deregister(registry) {
+                debug!("listener deregister failed: {}", e);
+            }
+        }
+        if let Some(mut health) = self.health.take() {
+            if let Err(e) = health.deregister(registry) {
+                debug!("health deregister failed: {}", e);
+            }
+        }
+        if let Some(mut admin) = self.admin.take() {
+            if let Err(e) = admin.deregister(registry) {
+                debug!("admin deregister failed: {}", e);
+            }
+        }
+        if let Some(mut courtesy) = self.courtesy.take() {
+            courtesy.clear(registry);
+        }
+        // The handles fail once it's gone, so the waker goes quiet
+        self.control = None;
+        self.join_writers();
+    }
+
+    // Starts an upgrade on SIGUSR2, followed by `check_upgrade`
+    #[cfg(unix)]
+    fn start_upgrade(&mut self, now: Instant) {
+        if self.draining {
+            return;
+        }
+        let fds = self.handover_fds();
+        let upgrader = match self.upgrader {
+            Some(ref mut upgrader) => upgrader,
+            None => return,
+        };
+        if upgrader.in_progress() {
+            warn!("upgrade already in progress, SIGUSR2 ignored");
+            return;
+        }
+        match upgrader.start(&fds, now) {
+            Ok(pid) => {
+                info!("upgrading, started pid {}", pid);
+                self.timers.insert(now + UPGRADE_CHECK_INTERVAL, Timeout::Upgrade);
+            }
+            Err(e) => error!("upgrade failed, still accepting: {}", e),
+        }
+    }
+
+    // Follows the upgrade in progress, handing over once the new process
+    // serves
+    #[cfg(unix)]
+    fn check_upgrade(&mut self, now: Instant) {
+        let upgrader = match self.upgrader {
+            Some(ref mut upgrader) if !self.draining => upgrader,
+            _ => return,
+        };
+        match upgrader.poll(now) {
+            Progress::Idle => {}
+            Progress::Waiting => {
+                self.timers.insert(now + UPGRADE_CHECK_INTERVAL, Timeout::Upgrade);
+            }
+            Progress::Ready(pid) => {
+                info!("pid {} took over the listeners", pid);
+                self.hand_over(now);
+            }
+            Progress::Failed(e) => error!("upgrade failed, still accepting: {}", e),
+        }
+    }
+
+    // The sockets `Config::upgrade_binary` takes over, by config field
+    #[cfg(unix)]
+    fn handover_fds(&self) -> Vec<(String, RawFd)> {
+        let mut fds = Vec::new();
+        // The ones added at runtime aren't in the config
+        for (listener, profile) in self.listeners.iter().zip(&self.profiles).take(self.fixed_listeners) {
+            match (listener, profile.entry) {
+                (Source::Tcp(l), None) => fds.push(("listen".to_string(), l.as_raw_fd())),
+                (Source::Tcp(l), Some(entry)) => fds.push((format!("listener.{}", entry), l.as_raw_fd())),
+                (Source::Udp(udp), _) => fds.push(("udp".to_string(), udp.as_raw_fd())),
+                _ => {}
+            }
+        }
+        if let Some(ref health) = self.health {
+            fds.push(("health_addr".to_string(), health.as_raw_fd()));
+        }
+        if let Some(ref admin) = self.admin {
+            fds.push(("admin_addr".to_string(), admin.as_raw_fd()));
+        }
+        fds
+    }
+
+    // Leaves the health probes and admin commands to the new process too,
+    // then drains
+    #[cfg(unix)]
+    fn hand_over(&mut self, now: Instant) {
+        let registry = self.poll.registry();
+        if let Some(mut health) = self.health.take() {
+            if let Err(e) = health.deregister(registry) {
+                debug!("health deregister failed: {}", e);
+            }
+        }
+        if let Some(mut admin) = self.admin.take() {
+            if let Err(e) = admin.deregister(registry) {
+                debug!("admin deregister failed: {}", e);
+            }
+        }
+        self.drain(now);
+    }
+
+    // Closes the sockets of closed clients once the kernel is done with
+    // their zerocopy writes, freeing the buffers, resetting those still
+    // waiting after `ZEROCOPY_LINGER` so that the kernel drops the data
+    fn reap_lingering(&mut self, now: Instant) {
+        let stats = &mut self.stats.zerocopy;
+        self.lingering.retain_mut(|(deadline, sock, zerocopy)| {
+            let result = sock.zerocopy_done(&mut |lo, hi, copied| zerocopy.complete(lo, hi, copied));
+            stats.copied += mem::take(&mut zerocopy.copied);
+            if let Err(e) = result {
+                debug!("reading zerocopy completions failed: {}", e);
+            } else if zerocopy.is_empty() {
+                return false;
+            } else if now < *deadline {
+                return true;
+            }
+            stats.abandoned += 1;
+            if let Stream::Tcp(ref sock) = *sock {
+                let _ = SockRef::from(sock).set_linger(Some(Duration::from_secs(0)));
+            }
+            false
+        });
+    }
+
+    // Measures the buffers into the stats, shrinking those of the clients
+    // that stayed under the watermark for `Config::shrink_after` with
+    // more than the watermark to give back, and the slots and spare
+    // queues once mostly unused for as long
+    fn shrink_buffers(&mut self, now: Instant) {
+        self.timers.insert(now + SHRINK_CHECK_INTERVAL, Timeout::Shrink);
+        let watermark = self.config.shrink_watermark;
+        let grace = self.config.shrink_after;
+        let (mut used, mut reserved) = (0, 0);
+        for (_, client) in self.clients.iter_mut() {
+            let (mut client_used, mut client_reserved) = client.buffer_usage();
+            if client_used > watermark {
+                client.low_since = None;
+            } else if now - *client.low_since.get_or_insert(now) >= grace && client_reserved - client_used > watermark {
+                client.shrink();
+                self.stats.buffers.shrinks += 1;
+                (client_used, client_reserved) = client.buffer_usage();
+            }
+            used += client_used;
+            reserved += client_reserved;
+        }
+
+        if self.clients.len() > self.clients.capacity() / 4 {
+            self.sparse_since = None;
+        } else if now - *self.sparse_since.get_or_insert(now) >= grace {
+            // Trailing free slots only, the clients keep their index
+            let capacity = self.clients.capacity();
+            self.clients.shrink_to_fit();
+            if self.clients.capacity() < capacity || !self.spare_clients.is_empty() {
+                self.spare_clients = Vec::new();
+                self.stats.buffers.shrinks += 1;
+            }
+        }
+        let slot = mem::size_of::<Client>();
+        let spare: usize = self
+            .spare_clients
+            .iter()
+            .map(|shell| mem::size_of::<Client<()>>() + shell.bufs.capacity() * mem::size_of::<Vec<u8>>())
+            .sum();
+        self.stats.buffers.used = (used + self.clients.len() * slot) as u64;
+        self.stats.buffers.reserved = (reserved + self.clients.capacity() * slot + spare) as u64;
+    }
+
+    // Rereads the deny file if it changed, or at once if `forced`, closing
+    // the clients it now denies with `Config::deny_existing`
+    fn reload_deny(&mut self, forced: bool) {
+        let deny = match self.deny {
+            Some(ref mut deny) => deny,
+            None => return,
+        };
+        let reload = match deny.check(forced) {
+            Ok(Some(reload)) => reload,
+            Ok(None) => return,
+            Err(e) => {
+                warn!("deny list {} not reloaded, keeping the previous one: {}", deny.path().display(), e);
+                self.stats.deny_reload_errors += 1;
+                return;
+            }
+        };
+        info!(
+            "deny list {} reloaded: {} networks, {} added, {} removed",
+            deny.path().display(),
+            deny.len(),
+            reload.added.len(),
+            reload.removed.len(),
+        );
+        for net in &reload.added {
+            debug!("deny list: added {}", net);
+        }
+        for net in &reload.removed {
+            debug!("deny list: removed {}", net);
+        }
+        self.stats.deny_reloads += 1;
+        if !self.config.deny_existing || reload.added.is_empty() {
+            return;
+        }
+        let denied: Vec<usize> = self
+            .clients
+            .iter()
+            .filter(|(_, client)| matches!(client.peer_addr(), PeerAddr::Inet(peer) if deny.denies(peer.ip())))
+            .map(|(index, _)| index)
+            .collect();
+        for index in denied {
+            self.remove_client(index, CloseReason::Denied);
+        }
+    }
+
+    /// Drains on SIGTERM and SIGINT, closing every client once its queue
+    /// is written. Another one closes them right away.
+    #[cfg(unix)]
+    pub fn watch_terminate(&mut self) -> Result<(), Error> {
+        workers::watch_terminate()?;
+        self.terminate_watched = true;
+        Ok(())
+    }
+
+    /// Wakes the poll as soon as a signal arrives, instead of at the next
+    /// event or timer, through the waker of the `ServerHandle`s. Only for
+    /// a poll of its own, the caller's can't have another waker.
+    #[cfg(unix)]
+    pub fn wake_on_signals(&mut self) -> Result<(), Error> {
+        self.handle()?;
+        let control = self.control.as_ref().expect("control registered");
+        crate::signal::wake_on_signals(control.waker())?;
+        Ok(())
+    }
+
+    // Acts on the signals that arrived since the last round, the handlers
+    // only setting flags
+    #[cfg(unix)]
+    fn check_signals(&mut self, now: Instant) {
+        if self.terminate_watched && workers::terminate_requested() {
+            self.terminate(now);
+        }
+        if DUMP_REQUESTED.swap(false, Ordering::Relaxed) {
+            self.requested_dump(now);
+        }
+        if self.upgrader.is_some() && upgrade::requested() {
+            self.start_upgrade(now);
+        }
+        if self.deny.is_some() && deny::reload_requested() {
+            self.reload_deny(true);
+        }
+    }
+
+    // Stops accepting and closes every client once its queue is written,
+    // or after `Config::drain_timeout`. Terminating again closes them
+    // right away
+    fn terminate(&mut self, now: Instant) {
+        if self.terminating {
+            info!("terminating again, closing {} clients", self.clients.len());
+            self.shutdown = true;
+            return;
+        }
+        info!("terminating, closing the clients once their queues are written");
+        self.terminating = true;
+        self.drain(now);
+        // The idle ones raise no event to be closed on
+        let indexes: Vec<usize> = self.clients.iter().map(|(index, _)| index).collect();
+        for index in indexes {
+            if self.clients.contains(index) {
+                self.flush(index);
+            }
+        }
+    }
+
+    // Dumps the state on SIGUSR1, at most once per `DUMP_MIN_INTERVAL`
+    #[cfg(unix)]
+    fn requested_dump(&mut self, now: Instant) {
+        match self.last_dump {
+            Some(last) if now - last < DUMP_MIN_INTERVAL => {
+                warn!("state dumped {:?} ago, SIGUSR1 ignored", now - last);
+            }
+            _ => {
+                self.last_dump = Some(now);
+                self.dump_state(now);
+            }
+        }
+    }
+
+    /// Logs the stats, the loop's own state and one line per client.
+    #[cfg(unix)]
+    fn dump_state(&self, now: Instant) {
+        info!("state dump: {}", self.stats);
+        info!(
+            "state dump: {} clients, {} pending, {} timers armed{}",
+            self.clients.len(),
+            self.pending.len(),
+            self.timers.len(),
+            if self.draining { ", draining" } else { "" }
+        );
+        for (_, client) in self.clients.iter().take(DUMP_MAX_CLIENTS) {
+            let interest = client.interest();
+            let interest = match (interest.is_some_and(|i| i.is_readable()), interest.is_some_and(|i| i.is_writable())) {
+                (true, true) => "rw",
+                (true, false) => "r",
+                (false, true) => "w",
+                (false, false) => "-",
+            };
+            let mut state = Vec::new();
+            if client.read_paused() {
+                state.push("read-paused");
+            }
+            if client.resume_at.is_some() {
+                state.push("between-chunks");
+            }
+            if client.flush_at.is_some() {
+                state.push("quiescing");
+            }
+            if client.throttled {
+                state.push("throttled");
+            }
+            if client.write_parked {
+                state.push("write-parked");
+            }
+            if client.muted {
+                state.push("muted");
+            }
+            if client.closing {
+                state.push("closing");
+            }
+            if state.is_empty() {
+                state.push(if client.bufs.is_empty() { "idle" } else { "writing" });
+            }
+            info!(
+                "state dump: #{} {}{}{} {:?}, age {:.3}s, idle {:.3}s, {} bytes queued, interest {}, {}",
+                client.id,
+                client.peer(),
+                client.original_dst.map_or_else(String::new, |dst| format!(" (original dst {})", dst)),
+                client.peer_cert().map_or_else(String::new, |cert| format!(" (certificate {})", cert)),
+                client.transport,
+                now.saturating_duration_since(client.accepted_at).as_secs_f64(),
+                now.saturating_duration_since(client.last_activity).as_secs_f64(),
+                client.queued_bytes(),
+                interest,
+                state.join(","),
+            );
+        }
+        if self.clients.len() > DUMP_MAX_CLIENTS {
+            info!("state dump: {} more clients not listed", self.clients.len() - DUMP_MAX_CLIENTS);
+        }
+    }
+
+    fn tick(&mut self, now: Instant) {
+        let tick = match self.tick {
+            Some(ref mut tick) => tick,
+            None => return,
+        };
+        self.shutdown |= tick.fire(&self.stats, now);
+        self.timers.insert(tick.deadline(), Timeout::Tick);
+    }
+
+    fn push_stats(&mut self, now: Instant) {
+        let statsd = match self.statsd {
+            Some(ref mut statsd) => statsd,
+            None => return,
+        };
+        let queued = self.clients.iter().map(|(_, client)| client.queued_bytes()).sum();
+        if !statsd.push(&self.stats, self.clients.len(), queued) {
+            self.stats.statsd_errors += 1;
+        }
+        self.timers.insert(now + self.config.stats_interval, Timeout::Statsd);
+    }
+
+    // Closes a client silent for `Config::idle_timeout`, or rearms its
+    // timer for when it will be
+    fn check_idle(&mut self, index: usize, now: Instant) {
+        let timeout = match self.config.idle_timeout {
+            Some(timeout) => timeout,
+            None => return,
+        };
+        let client = &mut self.clients[index];
+        if now - client.last_activity >= timeout {
+            debug!("nothing received in {:?} : {}", timeout, client.peer());
+            self.remove_client(index, CloseReason::Idle);
+            return;
+        }
+        let deadline = client.last_activity + timeout;
+        client.idle_at = Some(deadline);
+        self.timers.insert(deadline, Timeout::IdleClient(index));
+    }
+
+    fn heartbeat(&mut self, index: usize, now: Instant) {
+        let interval = match self.config.heartbeat_interval {
+            Some(interval) => interval,
+            None => return,
+        };
+
+        let client = &mut self.clients[index];
+        let deadline = if now - client.last_activity >= interval {
+            // Don't pile heartbeats behind data the client isn't reading
+            if client.bufs.is_empty() {
+                client.bufs.push_back(self.config.heartbeat_payload.clone());
+                if let Some(reason) = self.write(index) {
+                    self.remove_client(index, reason);
+                    return;
+                }
+            }
+            now + interval
+        } else {
+            client.last_activity + interval
+        };
+        self.clients[index].heartbeat_at = Some(deadline);
+        self.timers.insert(deadline, Timeout::Heartbeat(index));
+    }
+}
+
+impl<P> Reactor<P> {
+    // Calls `on_connect` or `on_disconnect` of the handler, if any, with
+    // the data attached to `client`, returning false if it panicked
+    fn call_handler<F>(&mut self, client: &mut Client, hook: F) -> bool
+    where
+        F: FnOnce(&mut dyn Handler, &mut HandlerContext),
+    {
+        let handler = match self.handler {
+            Some(ref mut handler) => handler,
+            None => return true,
+        };
+        let mut ctx = HandlerContext::new(client.id, client.peer_addr(), client.handler_data.take());
+        let result = panic::catch_unwind(AssertUnwindSafe(|| hook(&mut **handler, &mut ctx)));
+        client.handler_data = ctx.into_data();
+        match result {
+            Ok(()) => true,
+            Err(payload) => {
+                self.handler_panicked(&*payload, client.peer_addr());
+                false
+            }
+        }
+    }
+
+    // Retires the handler once it panicked `handler_panic_limit` times
+    fn handler_panicked(&mut self, payload: &(dyn Any + Send), peer: PeerAddr) {
+        error!("handler panicked: {} : {}", panic_message(payload), peer);
+        self.stats.handler_panics += 1;
+        if self.handler_panic_limit == Some(self.stats.handler_panics) {
+            warn!("handler disabled after {} panics", self.stats.handler_panics);
+            self.handler = None;
+        }
+    }
+
+    fn log_access(&self, client: &Client, reason: CloseReason) {
+        if let Some(ref access_log) = self.access_log {
+            let duration = self.clock.now().saturating_duration_since(client.accepted_at);
+            access_log.log(Entry {
+                accepted_at: SystemTime::now() - duration,
+                peer: client.peer_addr(),
+                duration,
+                bytes_in: client.bytes_read,
+                bytes_out: client.bytes_written,
+                reason,
+                id: client.id,
+                original_dst: client.original_dst,
+                peer_cert: client.peer_cert().map(str::to_string),
+            });
+        }
+    }
+
+    // The capture and access log threads exit and flush once every handle
+    // is gone, and would be killed half way if the process exited first
+    fn join_writers(&mut self) {
+        if let Some(thread) = self.capture_thread.take() {
+            self.clients.clear();
+            self.listeners.clear();
+            self.capture = None;
+            let _ = thread.join();
+        }
+        if let Some(thread) = self.access_log_thread.take() {
+            self.access_log = None;
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<P> Drop for Reactor<P> {
+    /// Best effort `close`: the sockets are dropped without deregistering,
+    /// which closing them does anyway unless they were duplicated.
+    fn drop(&mut self) {
+        for (_, mut client) in mem::take(&mut self.clients) {
+            self.call_handler(&mut client, |handler, ctx| handler.on_disconnect(ctx, CloseReason::Shutdown));
+            self.log_access(&client, CloseReason::Shutdown);
+        }
+        self.listeners.clear();
+        self.health = None;
+        self.admin = None;
+        self.courtesy = None;
+        self.join_writers();
+    }
+}
+
+/// Logs an I/O error of a client and classifies it.
+pub fn io_error<S: Socket>(e: &io::Error, client: &Client<S>) -> CloseReason {
+    let reason = CloseReason::from_error(e);
+    match reason {
+        CloseReason::Reset(_) => debug!("error={} : {}", e, client.peer()),
+        _ => warn!("error={} : {}", e, client.peer()),
+    }
+    reason
+}
+
+impl Reactor<Poll> {
+    /// Runs the event loop until an error occurs or a shutdown is requested.
+    pub fn run(&mut self) -> Result<(), Error> {
+        // Create storage for events
+        let mut events = Events::with_capacity(self.config.events_capacity);
+
+        // single-threaded: this loop alone serves every listener and client
+        // of the process, the other threads only resolve peer names, write
+        // the capture and access log or relay signals. There are no
+        // workers to pin to cores, more cores take more processes, see
+        // `Config::workers_processes`.
+        while !self.shutdown {
+            let timeout = self.next_timeout(self.clock.now());
+            self.turn(&mut events, timeout)?;
+        }
+        Ok(())
+    }
+
+    /// Waits at most `timeout` for events, then handles them and the
+    /// expired timers. Returns the number of events, which fill `events`
+    /// when more may be pending.
+    pub fn turn(&mut self, events: &mut Events, timeout: Option<Duration>) -> Result<usize, Error> {
+        let now = Instant::now();
+        let spun = self.spin(events, timeout)?;
+        if !spun {
+            let timeout = match self.config.spin {
+                Some(_) => timeout.map(|timeout| timeout.saturating_sub(now.elapsed())),
+                None => timeout,
+            };
+            poll_events(&mut self.poll, events, timeout)?;
+        }
+        let woken = Instant::now();
+        self.stats.event_loop.waiting += woken - now;
+
+        self.phase_times = Default::default();
+        let mut count = 0;
+        for event in &*events {
+            self.handle_event(event)?;
+            count += 1;
+        }
+        self.stats.event_loop.record_poll(count);
+        if spun {
+            self.stats.event_loop.spun_events += count as u64;
+        }
+
+        self.stats.event_loop.busy += Instant::now() - woken;
+        let timers = Instant::now();
+        self.expire_timers(self.clock.now())?;
+        self.phase_times[Phase::Timers as usize] += timers.elapsed();
+        self.check_stall(woken.elapsed(), count);
+        Ok(count)
+    }
+
+    // Polls without blocking for up to `Config::spin`, or `timeout` if
+    // shorter, returns whether that found events
+    fn spin(&mut self, events: &mut Events, timeout: Option<Duration>) -> io::Result<bool> {
+        let spin = match self.config.spin {
+            Some(spin) => timeout.map_or(spin, |timeout| timeout.min(spin)),
+            None => return Ok(false),
+        };
+        let start = Instant::now();
+        loop {
+            poll_events(&mut self.poll, events, Some(Duration::from_secs(0)))?;
+            if !events.is_empty() {
+                return Ok(true);
+            }
+            if start.elapsed() >= spin {
+                return Ok(false);
+            }
+            hint::spin_loop();
+        }
+    }
+
+    #[cfg(all(unix, feature = "tokio"))]
+    pub fn poll(&self) -> &Poll {
+        &self.poll
+    }
+}
+
+// Polls, a signal cutting the wait short counting as no events: its
+// flag is checked with the timers
+fn poll_events(poll: &mut Poll, events: &mut Events, timeout: Option<Duration>) -> io::Result<()> {
+    match poll.poll(events, timeout) {
+        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {
+            events.clear();
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+// Counts what the mirror connection of a client dropped, and lets go of
+// it once it failed
+fn check_mirror(client: &mut Client, registry: &Registry, stats: &mut Stats) {
+    let mut mirror = match client.mirror.take() {
+        Some(mirror) => mirror,
+        None => return,
+    };
+    stats.mirror_dropped += mirror.take_dropped();
+    match mirror.failed() {
+        None => client.mirror = Some(mirror),
+        Some(e) => {
+            warn!("mirror failed, no longer forwarding: {} : {}", e, client.peer());
+            stats.mirror_errors += 1;
+            if let Err(e) = mirror.deregister(registry) {
+                debug!("mirror deregister failed: {} : {}", e, client.peer());
+            }
+        }
+    }
+}
+
+/// Counts a closed client in the stats and logs it.
+pub fn record_close<S: Socket>(stats: &mut Stats, client: &Client<S>, reason: CloseReason) {
+    let transport_stats = stats.transport_mut(client.transport);
+    match reason {
+        // Handler panics are counted as they happen
+        CloseReason::Eof
+        | CloseReason::Kicked
+        | CloseReason::Done
+        | CloseReason::HandlerPanic
+        | CloseReason::Shutdown => {}
+        CloseReason::HandlerClose => stats.handler_closes += 1,
+        CloseReason::HandlerAbort => stats.handler_aborts += 1,
+        CloseReason::Reset(_) => transport_stats.resets += 1,
+        CloseReason::TimedOut => transport_stats.timeouts += 1,
+        CloseReason::Error(_) => transport_stats.errors += 1,
+        CloseReason::Corrupt => stats.corrupt += 1,
+        CloseReason::Handshake => stats.tls_handshake_failures += 1,
+        CloseReason::Overflow => stats.overflow.disconnects += 1,
+        CloseReason::Silent => stats.silent += 1,
+        CloseReason::Idle => stats.idle_closes += 1,
+        CloseReason::Denied => stats.deny_closes += 1,
+    }
+    match client.tcp_info() {
+        Some(tcp_info) => {
+            stats.record_rtt(tcp_info.rtt);
+            info!("connection closed : {} ({}; {})", client.peer(), reason, tcp_info);
+        }
+        None => info!("connection closed : {} ({})", client.peer(), reason),
+    }
+}
+
+// Where a TCP connection redirected by netfilter was headed, see
+// `Config::log_original_dst`
+#[cfg(target_os = "linux")]
+fn original_dst(sock: &Stream, peer: PeerAddr) -> Option<SocketAddr> {
+    match (sock, peer) {
+        (Stream::Tcp(sock), PeerAddr::Inet(peer)) => {
+            // IPv4 clients of an IPv6 listener are tracked as IPv4
+            crate::sys::original_dst(sock, !peer.ip().to_canonical().is_ipv4()).ok()
+        }
+        _ => None,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn original_dst(_: &Stream, _: PeerAddr) -> Option<SocketAddr> {
+    None
+}
+
+// Applies the configured buffer sizes to an accepted connection, and logs
+// what the kernel made of them
+fn size_buffers(sock: &TcpStream, config: &Config, addr: PeerAddr) {
+    if config.so_rcvbuf.is_none() && config.so_sndbuf.is_none() {
+        return;
+    }
+    let sock = SockRef::from(sock);
+    let sized = (|| {
+        if let Some(size) = config.so_rcvbuf {
+            sock.set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = config.so_sndbuf {
+            sock.set_send_buffer_size(size)?;
+        }
+        Ok::<_, io::Error>((sock.recv_buffer_size()?, sock.send_buffer_size()?))
+    })();
+    match sized {
+        Ok((rcvbuf, sndbuf)) => info!("socket buffers: {} rcvbuf, {} sndbuf : {}", rcvbuf, sndbuf, addr),
+        Err(e) => warn!("sizing socket buffers failed: {} : {}", e, addr),
+    }
+}
+
+// Binds a TCP listener at runtime, with the options of those bound at
+// startup
+fn open_listener(addr: &SocketAddr, config: &Config) -> Result<TcpListener, Error> {
+    let listener = bind_tcp(addr, config.freebind).map_err(|source| Error::Bind { addr: *addr, source })?;
+    size_listener_buffers(&listener, config)?;
+    #[cfg(target_os = "linux")]
+    if let Some(defer) = config.defer_accept {
+        crate::sys::set_tcp_defer_accept(&listener, defer)?;
+    }
+    Ok(listener)
+}
+
+// Errors of accept about the connection being taken rather than the
+// listener, retried right away as accept(2) advises
+fn transient_accept_error(e: &io::Error) -> bool {
+    if matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset | io::ErrorKind::Interrupted
+    ) {
+        return true;
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(
+        libc::ENETDOWN
+        | libc::EPROTO
+        | libc::ENOPROTOOPT
+        | libc::EHOSTDOWN
+        | libc::ENONET
+        | libc::EHOSTUNREACH
+        | libc::EOPNOTSUPP
+        | libc::ENETUNREACH,
+    ) = e.raw_os_error()
+    {
+        return true;
+    }
+    false
+}
+
+// Out of descriptors or memory, which binding the listener again won't
+// fix
+#[cfg(unix)]
+fn resource_error(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM)
+    )
+}
+
+#[cfg(not(unix))]
+fn resource_error(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::OutOfMemory
+}
+
+// Sized before the first connection, accepted ones inherit it on Linux
+// and the window scale offered in the handshake depends on it
+#[cfg(unix)]
+fn size_listener_buffers(listener: &TcpListener, config: &Config) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    for &(opt, size) in &[(libc::SO_RCVBUF, config.so_rcvbuf), (libc::SO_SNDBUF, config.so_sndbuf)] {
+        if let Some(size) = size {
+            let size = size.min(libc::c_int::MAX as usize) as libc::c_int;
+            let ret = unsafe {
+                libc::setsockopt(
+                    listener.as_raw_fd(),
+                    libc::SOL_SOCKET,
+                    opt,
+                    &size as *const libc::c_int as *const libc::c_void,
+                    std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+                )
+            };
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn size_listener_buffers(_: &TcpListener, _: &Config) -> io::Result<()> {
+    Ok(())
+}
+
+// With `Config::exclusive_accept`, only one of the processes sharing the
+// listener is woken per connection, where the kernel supports it. By
+// descriptor on Unix, as `Source::deregister` deregisters it, so that it
+// can be registered again
+#[cfg(target_os = "linux")]
+fn register_listener(registry: &Registry, listener: &mut TcpListener, token: Token, exclusive: bool) -> io::Result<()> {
+    if exclusive {
+        match crate::sys::register_exclusive(registry, listener, token) {
+            Ok(()) => return Ok(()),
+            Err(ref e) if e.raw_os_error() == Some(libc::EINVAL) => {
+                warn!("EPOLLEXCLUSIVE is not supported, registering the listener normally");
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    registry.register(&mut SourceFd(&listener.as_raw_fd()), token, Interest::READABLE)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn register_listener(registry: &Registry, listener: &mut TcpListener, token: Token, exclusive: bool) -> io::Result<()> {
+    if exclusive {
+        warn!("exclusive_accept is only supported on Linux, registering the listener normally");
+    }
+    #[cfg(unix)]
+    return registry.register(&mut SourceFd(&listener.as_raw_fd()), token, Interest::READABLE);
+    #[cfg(not(unix))]
+    registry.register(listener, token, Interest::READABLE)
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg
+    } else {
+        "non-string payload"
+    }
+}
+
+// How the `Config::annotate` headers name a listener: by its bound
+// address, resolving port 0, or else as configured
+#[cfg_attr(not(any(target_os = "linux", windows)), allow(unused_variables))]
+fn server_name(listener: &Source, config: &Config) -> String {
+    match *listener {
+        Source::Tcp(ref l) => l.local_addr().map_or_else(|_| "-".to_string(), |addr| addr.to_string()),
+        #[cfg(all(target_os = "linux", feature = "vsock"))]
+        Source::Vsock(_) => format!("vsock:{}", config.vsock_port.unwrap_or_default()),
+        #[cfg(target_os = "linux")]
+        Source::Seqpacket(_) => config.unix_seqpacket.clone().unwrap_or_default(),
+        #[cfg(target_os = "linux")]
+        Source::Unix(_) => config.unix_stream.clone().unwrap_or_default(),
+        #[cfg(all(target_os = "linux", feature = "sctp"))]
+        Source::Sctp(_) => config.sctp.clone().unwrap_or_default(),
+        #[cfg(windows)]
+        Source::Pipe(_) => format!(r"\\.\pipe\{}", config.pipe_name.as_deref().unwrap_or_default()),
+        Source::Udp(_) | Source::Closed => String::new(),
+    }
+}
+
+/// Takes the socket handed over as `name` by the process upgraded from,
+/// see `Config::upgrade_binary`, or binds as `bind` does.
+#[cfg(unix)]
+fn bind_or_inherit<T, F>(name: &str, addr: &str, retry: Option<Duration>, bind_fn: F) -> Result<T, Error>
+where
+    T: FromRawFd,
+    F: Fn(&SocketAddr) -> io::Result<T>,
+{
+    match upgrade::inherited(name) {
+        Some(sock) => Ok(sock),
+        None => bind(addr, retry, bind_fn),
+    }
+}
+
+#[cfg(not(unix))]
+fn bind_or_inherit<T, F>(_: &str, addr: &str, retry: Option<Duration>, bind_fn: F) -> Result<T, Error>
+where
+    F: Fn(&SocketAddr) -> io::Result<T>,
+{
+    bind(addr, retry, bind_fn)
+}
+
+/// Binds a TCP listener, with IP_FREEBIND if `freebind`, see
+/// `Config::freebind`.
+pub fn bind_tcp(addr: &SocketAddr, freebind: bool) -> io::Result<TcpListener> {
+    #[cfg(target_os = "linux")]
+    if freebind {
+        let fd = crate::sys::bind_freebind(addr, libc::SOCK_STREAM)?;
+        info!("listening on {} with freebind, reachable only once the address is configured", addr);
+        let listener = std::net::TcpListener::from(fd);
+        listener.set_nonblocking(true)?;
+        return Ok(TcpListener::from_std(listener));
+    }
+    let _ = freebind;
+    TcpListener::bind(*addr)
+}
+
+/// Binds a UDP socket, with IP_FREEBIND if `freebind`.
+pub fn bind_udp(addr: &SocketAddr, freebind: bool) -> io::Result<UdpSocket> {
+    #[cfg(target_os = "linux")]
+    if freebind {
+        let fd = crate::sys::bind_freebind(addr, libc::SOCK_DGRAM)?;
+        info!("bound udp {} with freebind, reachable only once the address is configured", addr);
+        let sock = std::net::UdpSocket::from(fd);
+        sock.set_nonblocking(true)?;
+        return Ok(UdpSocket::from_std(sock));
+    }
+    let _ = freebind;
+    UdpSocket::bind(*addr)
+}
+
+/// Binds with `bind`, retrying for `retry` while the address is busy or
+/// not there yet.
+pub fn bind<T, F>(addr: &str, retry: Option<Duration>, bind: F) -> Result<T, Error>
+where
+    F: Fn(&SocketAddr) -> io::Result<T>,
+{
+    let addr = addr.parse()?;
+    let deadline = retry.map(|retry| Instant::now() + retry);
+    let mut backoff = BIND_RETRY_MIN;
+
+    loop {
+        let e = match bind(&addr) {
+            Ok(sock) => return Ok(sock),
+            Err(e) => e,
+        };
+        let retryable = matches!(
+            e.kind(),
+            io::ErrorKind::AddrInUse | io::ErrorKind::AddrNotAvailable
+        );
+        let now = Instant::now();
+        match deadline {
+            Some(deadline) if retryable && now < deadline => {
+                let wait = backoff.min(deadline - now);
+                warn!("bind {} failed: {}, retrying in {:?}", addr, e, wait);
+                // SIGINT still terminates the process while sleeping
+                thread::sleep(wait);
+                backoff = (backoff * 2).min(BIND_RETRY_MAX);
+            }
+            _ => return Err(Error::Bind { addr, source: e }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net;
+
+    use mio::Waker;
+
+    use super::*;
+    use crate::clock;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    // A reactor registering with a clone of the registry of `poll`, left
+    // free for the test to poll
+    fn reactor(config: Config, registry: &Registry) -> Reactor<&Registry> {
+        Reactor::new(config, None, registry, 0..64, clock::system()).expect("reactor")
+    }
+
+    fn connect(reactor: &Reactor<&Registry>) -> net::TcpStream {
+        let sock = net::TcpStream::connect(reactor.local_addr().expect("listener")).expect("connect");
+        sock.set_read_timeout(Some(TIMEOUT)).unwrap();
+        sock
+    }
+
+    // Hands every event to the reactor until `done` holds
+    fn turn_until<F>(reactor: &mut Reactor<&Registry>, poll: &mut Poll, done: F)
+    where
+        F: Fn(&Reactor<&Registry>) -> bool,
+    {
+        let mut events = Events::with_capacity(16);
+        let start = Instant::now();
+        while !done(reactor) {
+            assert!(start.elapsed() < TIMEOUT, "timed out");
+            poll.poll(&mut events, Some(Duration::from_millis(10))).unwrap();
+            for event in &events {
+                assert!(reactor.handle_event(event).unwrap());
+            }
+        }
+    }
+
+    // Hands the client events of one poll to `client_ready` directly
+    fn client_events(reactor: &mut Reactor<&Registry>, poll: &mut Poll) -> usize {
+        let mut events = Events::with_capacity(16);
+        poll.poll(&mut events, Some(TIMEOUT)).unwrap();
+        let mut handled = 0;
+        for event in &events {
+            let index = event.token().0;
+            if index < reactor.max_clients && reactor.clients.contains(index) {
+                reactor.client_ready(index, event).unwrap();
+                handled += 1;
+            }
+        }
+        handled
+    }
+
+    fn closed(reactor: &mut Reactor<&Registry>) -> Vec<CloseReason> {
+        let events = reactor.take_events().into_iter();
+        events
+            .filter_map(|event| match event {
+                ServerEvent::Closed { reason, .. } => Some(reason),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn assert_eof(sock: &mut net::TcpStream) {
+        let mut buf = [0; 16];
+        assert!(matches!(sock.read(&mut buf), Ok(0) | Err(_)), "still open");
+    }
+
+    #[test]
+    fn handle_event_leaves_foreign_tokens() {
+        let mut poll = Poll::new().unwrap();
+        let registry = poll.registry().try_clone().unwrap();
+        let mut reactor = reactor(Config::new("127.0.0.1:0"), &registry);
+        let waker = Waker::new(poll.registry(), Token(1000)).unwrap();
+        waker.wake().unwrap();
+
+        let mut events = Events::with_capacity(16);
+        poll.poll(&mut events, Some(TIMEOUT)).unwrap();
+        let event = events.iter().next().expect("wake event");
+        assert_eq!(event.token(), Token(1000));
+        assert!(!reactor.handle_event(event).unwrap());
+    }
+
+    #[test]
+    fn handle_event_accepts_and_echoes() {
+        let mut poll = Poll::new().unwrap();
+        let registry = poll.registry().try_clone().unwrap();
+        let mut reactor = reactor(Config::new("127.0.0.1:0"), &registry);
+        let mut sock = connect(&reactor);
+        turn_until(&mut reactor, &mut poll, |reactor| reactor.clients.len() == 1);
+
+        sock.write_all(b"hello").unwrap();
+        turn_until(&mut reactor, &mut poll, |reactor| reactor.stats.tcp.bytes_written == 5);
+        let mut buf = [0; 5];
+        sock.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+        assert_eq!(reactor.stats.tcp.connections, 1);
+    }
+
+    #[test]
+    fn accept_ready_takes_the_whole_backlog() {
+        let poll = Poll::new().unwrap();
+        let registry = poll.registry().try_clone().unwrap();
+        let mut reactor = reactor(Config::new("127.0.0.1:0"), &registry);
+        let _socks: Vec<_> = (0..3).map(|_| connect(&reactor)).collect();
+
+        reactor.accept_ready(0).unwrap();
+        assert_eq!(reactor.clients.len(), 3);
+        assert_eq!(reactor.stats.tcp.connections, 3);
+        let ids: Vec<u64> = reactor.clients.iter().map(|(_, client)| client.id).collect();
+        assert_eq!(ids, [1, 2, 3]);
+
+        // Nothing left, counted as an empty accept
+        reactor.accept_ready(0).unwrap();
+        assert_eq!(reactor.stats.event_loop.empty_accepts, 1);
+    }
+
+    #[test]
+    fn accept_ready_refuses_past_max_clients() {
+        let poll = Poll::new().unwrap();
+        let registry = poll.registry().try_clone().unwrap();
+        let config = Config {
+            max_clients: 1,
+            ..Config::new("127.0.0.1:0")
+        };
+        let mut reactor = reactor(config, &registry);
+        let _first = connect(&reactor);
+        let mut second = connect(&reactor);
+
+        reactor.accept_ready(0).unwrap();
+        assert_eq!(reactor.clients.len(), 1);
+        assert_eq!(reactor.stats.tcp.rejected, 1);
+        assert_eq!(reactor.stats.refusals.capacity, 1);
+        assert_eof(&mut second);
+    }
+
+    #[test]
+    fn client_ready_echoes() {
+        let mut poll = Poll::new().unwrap();
+        let registry = poll.registry().try_clone().unwrap();
+        let mut reactor = reactor(Config::new("127.0.0.1:0"), &registry);
+        let mut sock = connect(&reactor);
+        reactor.accept_ready(0).unwrap();
+
+        sock.write_all(b"ping").unwrap();
+        while reactor.clients[0].bytes_written < 4 {
+            client_events(&mut reactor, &mut poll);
+        }
+        let mut buf = [0; 4];
+        sock.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+        assert_eq!(reactor.clients[0].bytes_read, 4);
+        assert!(reactor.clients[0].bufs.is_empty());
+        assert_eq!(reactor.clients[0].interest(), Some(Interest::READABLE));
+    }
+
+    #[test]
+    fn client_ready_removes_on_eof() {
+        let mut poll = Poll::new().unwrap();
+        let registry = poll.registry().try_clone().unwrap();
+        let mut reactor = reactor(Config::new("127.0.0.1:0"), &registry);
+        reactor.enable_events();
+        let sock = connect(&reactor);
+        reactor.accept_ready(0).unwrap();
+        reactor.take_events();
+
+        drop(sock);
+        while !reactor.clients.is_empty() {
+            client_events(&mut reactor, &mut poll);
+        }
+        assert_eq!(closed(&mut reactor), [CloseReason::Eof]);
+    }
+
+    #[test]
+    fn remove_client_closes_and_recycles() {
+        let poll = Poll::new().unwrap();
+        let registry = poll.registry().try_clone().unwrap();
+        let mut reactor = reactor(Config::new("127.0.0.1:0"), &registry);
+        reactor.enable_events();
+        let mut sock = connect(&reactor);
+        reactor.accept_ready(0).unwrap();
+        reactor.take_events();
+
+        reactor.remove_client(0, CloseReason::Kicked);
+        assert!(reactor.clients.is_empty());
+        assert!(reactor.idle_since.is_some());
+        assert_eq!(reactor.spare_clients.len(), 1);
+        assert_eq!(closed(&mut reactor), [CloseReason::Kicked]);
+        assert_eof(&mut sock);
+    }
+
+    #[test]
+    fn remove_client_promotes_a_pending_connection() {
+        let poll = Poll::new().unwrap();
+        let registry = poll.registry().try_clone().unwrap();
+        let config = Config {
+            max_clients: 1,
+            pending_queue: Some(1),
+            ..Config::new("127.0.0.1:0")
+        };
+        let mut reactor = reactor(config, &registry);
+        let _first = connect(&reactor);
+        let _second = connect(&reactor);
+        reactor.accept_ready(0).unwrap();
+        assert_eq!((reactor.clients.len(), reactor.pending.len()), (1, 1));
+        assert_eq!(reactor.stats.deferred, 1);
+
+        reactor.remove_client(0, CloseReason::Kicked);
+        assert_eq!((reactor.clients.len(), reactor.pending.len()), (1, 0));
+        assert_eq!(reactor.clients.iter().next().map(|(_, client)| client.id), Some(2));
+    }
+}
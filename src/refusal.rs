@@ -0,0 +1,105 @@
+//! The audit trail of the connections turned away, one log line per
+//! refusal.
+
+use std::fmt;
+use std::time::{Duration, Instant, SystemTime};
+
+use log::warn;
+
+use crate::access_log::rfc3339;
+use crate::stats::Stats;
+use crate::stream::PeerAddr;
+
+/// Most refusals logged per second, the others are only counted, so that
+/// a flood of them can't flood the log too.
+const MAX_LOGGED_PER_SEC: u32 = 20;
+
+/// Why a connection was refused, its code as logged.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Refusal {
+    /// The server had `Config::max_clients` clients and the pending queue
+    /// no room, `capacity`.
+    Capacity,
+    /// The entry of `Config::listeners` had its `max_clients` clients,
+    /// `listener-full`.
+    ListenerFull,
+    /// The address was banned, `banned`.
+    Banned,
+    /// The address is in a network of `Config::deny_file`, `denied`.
+    Denied,
+    /// It waited `Config::pending_timeout` in the pending queue,
+    /// `pending-timeout`.
+    PendingTimeout,
+    /// The server started draining, `draining`.
+    Draining,
+}
+
+impl Refusal {
+    pub fn code(self) -> &'static str {
+        match self {
+            Refusal::Capacity => "capacity",
+            Refusal::ListenerFull => "listener-full",
+            Refusal::Banned => "banned",
+            Refusal::Denied => "denied",
+            Refusal::PendingTimeout => "pending-timeout",
+            Refusal::Draining => "draining",
+        }
+    }
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+/// How loaded the server was when it refused a connection.
+pub struct Load {
+    pub clients: usize,
+    pub max_clients: usize,
+    pub pending: usize,
+}
+
+/// Counts the refusals in `Stats::refusals` and logs them, at most
+/// `MAX_LOGGED_PER_SEC` a second.
+#[derive(Default)]
+pub struct Audit {
+    window: Option<Instant>,
+    logged: u32,
+    /// Refusals not logged since the last line.
+    unlogged: u64,
+}
+
+impl Audit {
+    /// Records the refusal of `peer`, logging a line such as
+    /// `connection refused : 10.0.0.1:5000 reason=capacity
+    /// at=2019-06-01T12:00:00.000Z clients=1024/1024 pending=0
+    /// refused=17`, `refused` counting the refusals for that reason.
+    pub fn record(&mut self, stats: &mut Stats, now: Instant, peer: &PeerAddr, reason: Refusal, load: Load) {
+        let refused = stats.refusals.record(reason);
+        if self.window.is_none_or(|start| now.saturating_duration_since(start) >= Duration::from_secs(1)) {
+            self.window = Some(now);
+            self.logged = 0;
+        }
+        if self.logged == MAX_LOGGED_PER_SEC {
+            self.unlogged += 1;
+            stats.refusals.unlogged += 1;
+            return;
+        }
+        self.logged += 1;
+        if self.unlogged > 0 {
+            warn!("{} refusals not logged", self.unlogged);
+            self.unlogged = 0;
+        }
+        warn!(
+            "connection refused : {} reason={} at={} clients={}/{} pending={} refused={}",
+            peer,
+            reason,
+            rfc3339(SystemTime::now()),
+            load.clients,
+            load.max_clients,
+            load.pending,
+            refused,
+        );
+    }
+}
@@ -0,0 +1,366 @@
+//! Replays the TCP sessions of a capture against a live server.
+//!
+//! Each recorded connection is reopened, its client bytes are sent again
+//! with the recorded timing, and the echoes are compared against what the
+//! server sent back then.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use mio::net::TcpStream;
+use mio::{Events, Interest, Poll, Token};
+
+use crate::dump::HexDump;
+use crate::Error;
+
+const LINKTYPE_RAW: u32 = 101;
+/// Sessions still waiting for echoes give up after this long without
+/// progress.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Bytes shown around a mismatch.
+const CONTEXT: usize = 32;
+
+/// Replays the sessions of the pcap file at `path` against `addr`, `speed`
+/// times faster than recorded. Returns whether every session matched.
+pub fn replay(path: &Path, addr: &str, speed: f64) -> Result<bool, Error> {
+    let addr = addr.parse()?;
+    let data = fs::read(path).map_err(|source| Error::File {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let invalid = |message: String| Error::Invalid {
+        path: path.to_path_buf(),
+        message,
+    };
+    let mut sessions = sessions(&parse_pcap(&data).map_err(invalid)?);
+    if sessions.is_empty() {
+        return Err(invalid("no TCP session found".to_string()));
+    }
+
+    let mut poll = Poll::new()?;
+    let mut events = Events::with_capacity(1024);
+    let start = Instant::now();
+    let mut last_progress = start;
+    let scale = |offset: Duration| start + Duration::from_secs_f64(offset.as_secs_f64() / speed);
+
+    loop {
+        let now = Instant::now();
+        let mut next = None;
+        for (index, session) in sessions.iter_mut().enumerate() {
+            if session.sock.is_none() && !session.done && scale(session.start) <= now {
+                let mut sock = TcpStream::connect(addr)?;
+                poll.registry().register(&mut sock, Token(index), Interest::READABLE | Interest::WRITABLE)?;
+                session.sock = Some(sock);
+            }
+            if session.sock.is_some() && session.send(now, &scale)? {
+                last_progress = Instant::now();
+            }
+            let due = session.next_due().map(&scale).or(if session.sock.is_none() && !session.done {
+                Some(scale(session.start))
+            } else {
+                None
+            });
+            next = match (next, due) {
+                (Some(a), Some(b)) => Some(std::cmp::min(a, b)),
+                (a, b) => a.or(b),
+            };
+        }
+
+        if sessions.iter().all(|session| session.done) {
+            break;
+        }
+        if next.is_none() && now - last_progress >= IDLE_TIMEOUT {
+            break;
+        }
+
+        let deadline = next.unwrap_or(last_progress + IDLE_TIMEOUT);
+        poll.poll(&mut events, Some(deadline.saturating_duration_since(now)))?;
+        for event in &events {
+            let session = &mut sessions[event.token().0];
+            if event.is_readable() && session.receive()? {
+                last_progress = Instant::now();
+            }
+        }
+    }
+
+    let mut ok = true;
+    for session in &sessions {
+        ok &= session.report();
+    }
+    Ok(ok)
+}
+
+// One direction of a TCP segment
+struct Segment {
+    time: Duration,
+    src: SocketAddr,
+    dst: SocketAddr,
+    payload: Vec<u8>,
+}
+
+struct Session {
+    client: SocketAddr,
+    start: Duration,
+    // Client bytes with the offset they were sent at
+    sends: Vec<(Duration, Vec<u8>)>,
+    next_send: usize,
+    pending: Vec<u8>,
+    expected: Vec<u8>,
+    received: Vec<u8>,
+    sock: Option<TcpStream>,
+    eof: bool,
+    done: bool,
+}
+
+impl Session {
+    fn next_due(&self) -> Option<Duration> {
+        self.sends.get(self.next_send).map(|&(offset, _)| offset)
+    }
+
+    // Sends whatever is due, returns whether anything was written
+    fn send<F: Fn(Duration) -> Instant>(&mut self, now: Instant, scale: &F) -> io::Result<bool> {
+        let mut progress = false;
+        while let Some(&(offset, ref data)) = self.sends.get(self.next_send) {
+            if scale(offset) > now {
+                break;
+            }
+            self.pending.extend_from_slice(data);
+            self.next_send += 1;
+        }
+        let sock = match self.sock {
+            Some(ref mut sock) => sock,
+            None => return Ok(progress),
+        };
+        while !self.pending.is_empty() {
+            match sock.write(&self.pending) {
+                Ok(len) => {
+                    self.pending.drain(..len);
+                    progress = true;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::NotConnected => {
+                    break
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        self.check_done();
+        Ok(progress)
+    }
+
+    // Reads the echoes, returns whether anything arrived
+    fn receive(&mut self) -> io::Result<bool> {
+        let sock = match self.sock {
+            Some(ref mut sock) => sock,
+            None => return Ok(false),
+        };
+        let mut buf = [0; 16 * 1024];
+        let mut progress = false;
+        loop {
+            match sock.read(&mut buf) {
+                Ok(0) => {
+                    self.eof = true;
+                    progress = true;
+                    break;
+                }
+                Ok(len) => {
+                    self.received.extend_from_slice(&buf[..len]);
+                    progress = true;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        self.check_done();
+        Ok(progress)
+    }
+
+    // Closes the connection once everything was sent and echoed
+    fn check_done(&mut self) {
+        let sent_all = self.next_send == self.sends.len() && self.pending.is_empty();
+        if self.eof || (sent_all && self.received.len() >= self.expected.len()) {
+            self.done = true;
+            self.sock = None;
+        }
+    }
+
+    // Prints the outcome, returns whether the echoes matched
+    fn report(&self) -> bool {
+        let sent: usize = self.sends.iter().map(|(_, data)| data.len()).sum();
+        let mismatch = self
+            .expected
+            .iter()
+            .zip(&self.received)
+            .position(|(a, b)| a != b)
+            .or(if self.received.len() != self.expected.len() {
+                Some(self.received.len().min(self.expected.len()))
+            } else {
+                None
+            });
+
+        let offset = match mismatch {
+            None => {
+                println!("session {}: ok, {} bytes sent, {} echoed", self.client, sent, self.received.len());
+                return true;
+            }
+            Some(offset) => offset,
+        };
+        println!(
+            "session {}: mismatch at offset {}, {} bytes expected, {} received",
+            self.client,
+            offset,
+            self.expected.len(),
+            self.received.len()
+        );
+        let from = offset - offset % 16;
+        let context = |data: &[u8]| {
+            let end = data.len().min(from + CONTEXT);
+            data.get(from..end).map(|data| data.to_vec()).unwrap_or_default()
+        };
+        println!("expected at {:#x}:\n{}", from, HexDump::new(&context(&self.expected), CONTEXT));
+        println!("received at {:#x}:\n{}", from, HexDump::new(&context(&self.received), CONTEXT));
+        false
+    }
+}
+
+// Groups segments into sessions. The server is the endpoint shared by
+// most connections, or the first receiver of a lone one
+fn sessions(segments: &[Segment]) -> Vec<Session> {
+    let mut flows: Vec<(SocketAddr, SocketAddr)> = Vec::new();
+    let mut flow_of = HashMap::new();
+    for segment in segments {
+        let key = if segment.src < segment.dst {
+            (segment.src, segment.dst)
+        } else {
+            (segment.dst, segment.src)
+        };
+        flow_of.entry(key).or_insert_with(|| {
+            flows.push((segment.src, segment.dst));
+            flows.len() - 1
+        });
+    }
+
+    let mut endpoints: HashMap<SocketAddr, usize> = HashMap::new();
+    for &(a, b) in &flows {
+        *endpoints.entry(a).or_default() += 1;
+        *endpoints.entry(b).or_default() += 1;
+    }
+    let mut sessions: Vec<Session> = flows
+        .iter()
+        .map(|&(first_src, first_dst)| {
+            let client = if endpoints[&first_src] > endpoints[&first_dst] {
+                first_dst
+            } else {
+                first_src
+            };
+            Session {
+                client,
+                start: Duration::from_secs(0),
+                sends: Vec::new(),
+                next_send: 0,
+                pending: Vec::new(),
+                expected: Vec::new(),
+                received: Vec::new(),
+                sock: None,
+                eof: false,
+                done: false,
+            }
+        })
+        .collect();
+
+    let epoch = segments.first().map(|segment| segment.time).unwrap_or_default();
+    let mut started = vec![false; sessions.len()];
+    for segment in segments {
+        let key = if segment.src < segment.dst {
+            (segment.src, segment.dst)
+        } else {
+            (segment.dst, segment.src)
+        };
+        let index = flow_of[&key];
+        let session = &mut sessions[index];
+        let offset = segment.time.saturating_sub(epoch);
+        if !started[index] {
+            started[index] = true;
+            session.start = offset;
+        }
+        if segment.src == session.client {
+            session.sends.push((offset, segment.payload.clone()));
+        } else {
+            session.expected.extend_from_slice(&segment.payload);
+        }
+    }
+    sessions
+}
+
+// Fails with what is wrong with the file
+fn parse_pcap(data: &[u8]) -> Result<Vec<Segment>, String> {
+    let invalid = || "not a pcap file written by the server".to_string();
+    if data.len() < 24 {
+        return Err(invalid());
+    }
+    let little = match data[..4] {
+        [0xd4, 0xc3, 0xb2, 0xa1] => true,
+        [0xa1, 0xb2, 0xc3, 0xd4] => false,
+        _ => return Err(invalid()),
+    };
+    let u32_at = |at: usize| {
+        let bytes = [data[at], data[at + 1], data[at + 2], data[at + 3]];
+        if little {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    };
+    if u32_at(20) != LINKTYPE_RAW {
+        return Err(format!("unsupported pcap link type {}", u32_at(20)));
+    }
+
+    let mut segments = Vec::new();
+    let mut at = 24;
+    while at + 16 <= data.len() {
+        let time = Duration::new(u64::from(u32_at(at)), u32_at(at + 4) * 1000);
+        let len = u32_at(at + 8) as usize;
+        let packet = data.get(at + 16..at + 16 + len).ok_or_else(invalid)?;
+        at += 16 + len;
+        if let Some(segment) = parse_tcp(time, packet) {
+            segments.push(segment);
+        }
+    }
+    Ok(segments)
+}
+
+// Skips anything that isn't a TCP segment with a payload
+fn parse_tcp(time: Duration, packet: &[u8]) -> Option<Segment> {
+    let (src, dst, protocol, rest) = match packet.first()? >> 4 {
+        4 => {
+            let ihl = usize::from(packet[0] & 0x0f) * 4;
+            let src: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            (IpAddr::from(Ipv4Addr::from(src)), IpAddr::from(Ipv4Addr::from(dst)), *packet.get(9)?, packet.get(ihl..)?)
+        }
+        6 => {
+            let src: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            (IpAddr::from(Ipv6Addr::from(src)), IpAddr::from(Ipv6Addr::from(dst)), *packet.get(6)?, packet.get(40..)?)
+        }
+        _ => return None,
+    };
+    if protocol != 6 || rest.len() < 20 {
+        return None;
+    }
+    let offset = usize::from(rest[12] >> 4) * 4;
+    let payload = rest.get(offset..)?;
+    if payload.is_empty() {
+        return None;
+    }
+    Some(Segment {
+        time,
+        src: SocketAddr::new(src, u16::from_be_bytes([rest[0], rest[1]])),
+        dst: SocketAddr::new(dst, u16::from_be_bytes([rest[2], rest[3]])),
+        payload: payload.to_vec(),
+    })
+}
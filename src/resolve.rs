@@ -0,0 +1,123 @@
+//! Host names of the peers, looked up by reverse DNS on a thread of its
+//! own so the event loop never waits on one, see `Config::resolve_peers`.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use crate::handle::ServerHandle;
+
+/// Names cached at once, past which the expired ones and then the
+/// closest to expiring make room.
+const MAX_NAMES: usize = 4096;
+/// How long a lookup may take before its address counts as nameless,
+/// until the TTL runs out. A late answer is still cached.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Looks up the host name of a peer, see `ServerBuilder::resolver`.
+pub trait Resolver: Send {
+    /// The name of `ip`, `None` without one. Called on the resolver
+    /// thread, so it may block.
+    fn lookup(&self, ip: IpAddr) -> Option<String>;
+}
+
+/// Asks the system resolver, through getnameinfo.
+#[cfg(target_os = "linux")]
+pub struct SystemResolver;
+
+#[cfg(target_os = "linux")]
+impl Resolver for SystemResolver {
+    fn lookup(&self, ip: IpAddr) -> Option<String> {
+        crate::sys::reverse_lookup(ip)
+    }
+}
+
+struct Cached {
+    name: Option<Arc<str>>,
+    expires: Instant,
+}
+
+/// The names known so far, and the end of the channel to the resolver
+/// thread asking for the others.
+pub struct PeerNames {
+    names: HashMap<IpAddr, Cached>,
+    /// Addresses asked for, with when.
+    pending: HashMap<IpAddr, Instant>,
+    ttl: Duration,
+    tx: Sender<IpAddr>,
+}
+
+impl PeerNames {
+    /// Starts the resolver thread, which hands its answers to the loop
+    /// through `handle` and stops once the `PeerNames` is dropped. It
+    /// isn't joined, a lookup can take long.
+    pub fn start(resolver: Box<dyn Resolver>, handle: ServerHandle, ttl: Duration) -> PeerNames {
+        let (tx, rx) = mpsc::channel::<IpAddr>();
+        thread::spawn(move || {
+            for ip in rx {
+                let name = resolver.lookup(ip);
+                if handle.resolved(ip, name).is_err() {
+                    return;
+                }
+            }
+        });
+        PeerNames {
+            names: HashMap::new(),
+            pending: HashMap::new(),
+            ttl,
+            tx,
+        }
+    }
+
+    /// The cached name of `ip`, asking for it when it isn't cached or
+    /// expired. Never waits for the answer.
+    pub fn get(&mut self, ip: IpAddr, now: Instant) -> Option<Arc<str>> {
+        if let Some(cached) = self.names.get(&ip) {
+            if cached.expires > now {
+                return cached.name.clone();
+            }
+        }
+        match self.pending.get(&ip) {
+            Some(&asked) if now.saturating_duration_since(asked) < LOOKUP_TIMEOUT => return None,
+            Some(_) => {
+                debug!("reverse lookup of {} timed out", ip);
+                self.pending.remove(&ip);
+                self.insert(ip, None, now);
+                return None;
+            }
+            None => {}
+        }
+        if self.pending.len() < MAX_NAMES && self.tx.send(ip).is_ok() {
+            self.pending.insert(ip, now);
+        }
+        None
+    }
+
+    /// Caches the answer of the resolver thread for `ip`, returning the
+    /// name to give its clients.
+    pub fn resolved(&mut self, ip: IpAddr, name: Option<String>, now: Instant) -> Option<Arc<str>> {
+        self.pending.remove(&ip);
+        let name: Option<Arc<str>> = name.map(Into::into);
+        self.insert(ip, name.clone(), now);
+        name
+    }
+
+    fn insert(&mut self, ip: IpAddr, name: Option<Arc<str>>, now: Instant) {
+        if self.names.len() >= MAX_NAMES && !self.names.contains_key(&ip) {
+            self.names.retain(|_, cached| cached.expires > now);
+            if self.names.len() >= MAX_NAMES {
+                let soonest = self.names.iter().min_by_key(|(_, cached)| cached.expires).map(|(&ip, _)| ip);
+                if let Some(soonest) = soonest {
+                    self.names.remove(&soonest);
+                }
+            }
+        }
+        let expires = now + self.ttl;
+        self.names.insert(ip, Cached { name, expires });
+    }
+}
@@ -0,0 +1,230 @@
+//! One-to-one style SCTP sockets, which mio doesn't provide.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::SocketAddr;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::ptr;
+
+use crate::sys::{cvt, evented_fd, sockaddr, socket_addr};
+
+// From linux/sctp.h, which libc doesn't cover
+const SOL_SCTP: libc::c_int = 132;
+const SCTP_RECVRCVINFO: libc::c_int = 32;
+const SCTP_EVENT: libc::c_int = 127;
+const SCTP_SNDINFO: libc::c_int = 2;
+const SCTP_RCVINFO: libc::c_int = 3;
+const SCTP_SHUTDOWN_EVENT: u16 = 0x8005;
+const MSG_NOTIFICATION: libc::c_int = 0x8000;
+
+#[repr(C)]
+struct SctpEvent {
+    se_assoc_id: i32,
+    se_type: u16,
+    se_on: u8,
+}
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)] // Only some fields are read, the rest is layout
+struct SctpRcvInfo {
+    rcv_sid: u16,
+    rcv_ssn: u16,
+    rcv_flags: u16,
+    rcv_ppid: u32,
+    rcv_tsn: u32,
+    rcv_cumtsn: u32,
+    rcv_context: u32,
+    rcv_assoc_id: i32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct SctpSndInfo {
+    snd_sid: u16,
+    snd_flags: u16,
+    snd_ppid: u32,
+    snd_context: u32,
+    snd_assoc_id: i32,
+}
+
+// Room for one SCTP_RCVINFO or SCTP_SNDINFO header, aligned for cmsghdr
+type Control = [u64; 8];
+
+/// A non-blocking SCTP listener.
+pub struct SctpListener {
+    fd: OwnedFd,
+}
+
+impl SctpListener {
+    pub fn bind(addr: &SocketAddr) -> io::Result<SctpListener> {
+        let family = if addr.is_ipv4() { libc::AF_INET } else { libc::AF_INET6 };
+        let fd = cvt(unsafe {
+            libc::socket(
+                family,
+                libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::IPPROTO_SCTP,
+            )
+        })?;
+        let listener = SctpListener {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        };
+
+        setsockopt(fd, libc::SOL_SOCKET, libc::SO_REUSEADDR, &1 as *const libc::c_int, mem::size_of::<libc::c_int>())?;
+        let (storage, len) = sockaddr(addr);
+        cvt(unsafe { libc::bind(fd, &storage as *const libc::sockaddr_storage as *const libc::sockaddr, len) })?;
+        cvt(unsafe { libc::listen(fd, 1024) })?;
+        Ok(listener)
+    }
+
+    /// Accepts an association, returning it with the peer's primary
+    /// address.
+    pub fn accept(&self) -> io::Result<(SctpStream, SocketAddr)> {
+        let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        let fd = cvt(unsafe {
+            libc::accept4(
+                self.fd.as_raw_fd(),
+                &mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr,
+                &mut len,
+                libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            )
+        })?;
+        let stream = SctpStream {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            streams: VecDeque::new(),
+        };
+
+        // Stream ids come with every message, and the peer's SHUTDOWN
+        // ends the association like an EOF
+        setsockopt(fd, SOL_SCTP, SCTP_RECVRCVINFO, &1 as *const libc::c_int, mem::size_of::<libc::c_int>())?;
+        let event = SctpEvent {
+            se_assoc_id: 0,
+            se_type: SCTP_SHUTDOWN_EVENT,
+            se_on: 1,
+        };
+        setsockopt(fd, SOL_SCTP, SCTP_EVENT, &event, mem::size_of::<SctpEvent>())?;
+
+        let addr = socket_addr(&storage)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unknown peer address family"))?;
+        Ok((stream, addr))
+    }
+}
+
+/// A connected, non-blocking SCTP association.
+///
+/// Every `recv_packet` returns one message, and every `write` sends one
+/// on the stream, and with the payload protocol id, of the oldest message
+/// received and not answered yet, stream 0 once they all are. Messages
+/// larger than the receive buffer arrive in pieces, each echoed as a
+/// message of its own.
+pub struct SctpStream {
+    fd: OwnedFd,
+    /// Stream id and payload protocol id of the messages received and not
+    /// echoed yet.
+    streams: VecDeque<(u16, u32)>,
+}
+
+impl SctpStream {
+    /// Receives one message, `None` means the peer has shut the
+    /// association down.
+    pub fn recv_packet(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        loop {
+            let mut iov = libc::iovec {
+                iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buf.len(),
+            };
+            let mut control: Control = [0; 8];
+            let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = mem::size_of::<Control>() as _;
+            let ret = unsafe { libc::recvmsg(self.fd.as_raw_fd(), &mut msg, 0) };
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let len = ret as usize;
+
+            if msg.msg_flags & MSG_NOTIFICATION != 0 {
+                // Only SHUTDOWN is subscribed to
+                if len >= 2 && u16::from_ne_bytes([buf[0], buf[1]]) == SCTP_SHUTDOWN_EVENT {
+                    return Ok(None);
+                }
+                continue;
+            }
+            // SCTP has no empty messages, 0 is the end of the association
+            if len == 0 {
+                return Ok(None);
+            }
+            let info = unsafe { rcvinfo(&msg) }.unwrap_or_default();
+            self.streams.push_back((info.rcv_sid, info.rcv_ppid));
+            return Ok(Some(len));
+        }
+    }
+}
+
+impl Read for SctpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(self.recv_packet(buf)?.unwrap_or(0))
+    }
+}
+
+impl Write for SctpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let (sid, ppid) = self.streams.front().copied().unwrap_or((0, 0));
+        let info = SctpSndInfo {
+            snd_sid: sid,
+            snd_ppid: ppid,
+            ..SctpSndInfo::default()
+        };
+        let mut iov = libc::iovec {
+            iov_base: buf.as_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        let mut control: Control = [0; 8];
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        unsafe {
+            let space = libc::CMSG_SPACE(mem::size_of::<SctpSndInfo>() as u32);
+            msg.msg_controllen = space as _;
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = SOL_SCTP;
+            (*cmsg).cmsg_type = SCTP_SNDINFO;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<SctpSndInfo>() as u32) as _;
+            ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut SctpSndInfo, info);
+        }
+        let ret = unsafe { libc::sendmsg(self.fd.as_raw_fd(), &msg, libc::MSG_NOSIGNAL) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.streams.pop_front();
+        Ok(ret as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// The SCTP_RCVINFO of a received message, if the kernel attached it
+unsafe fn rcvinfo(msg: &libc::msghdr) -> Option<SctpRcvInfo> {
+    let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+    while !cmsg.is_null() {
+        if (*cmsg).cmsg_level == SOL_SCTP && (*cmsg).cmsg_type == SCTP_RCVINFO {
+            return Some(ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const SctpRcvInfo));
+        }
+        cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+    }
+    None
+}
+
+fn setsockopt<T>(fd: RawFd, level: libc::c_int, name: libc::c_int, value: *const T, len: usize) -> io::Result<()> {
+    cvt(unsafe { libc::setsockopt(fd, level, name, value as *const libc::c_void, len as libc::socklen_t) }).map(drop)
+}
+
+evented_fd!(SctpListener);
+evented_fd!(SctpStream);
@@ -0,0 +1,230 @@
+//! Seccomp-bpf allowlist confining the server once it is set up.
+//!
+//! The filter only allows the syscalls the event loop makes for the
+//! configured transports, anything else kills the process.
+
+use std::io;
+
+use crate::config::Config;
+use crate::sys::cvt;
+use crate::Error;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+// Offsets into `struct seccomp_data`
+const NR_OFFSET: u32 = 0;
+const ARCH_OFFSET: u32 = 4;
+
+/// Syscalls of the loop itself: polling, socket I/O, memory, timers,
+/// panics and exit.
+const BASE: &[libc::c_long] = &[
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_epoll_wait,
+    libc::SYS_epoll_pwait,
+    libc::SYS_epoll_ctl,
+    libc::SYS_read,
+    libc::SYS_readv,
+    libc::SYS_write,
+    libc::SYS_writev,
+    libc::SYS_recvfrom,
+    libc::SYS_sendto,
+    libc::SYS_close,
+    libc::SYS_futex,
+    libc::SYS_clock_gettime,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_madvise,
+    libc::SYS_brk,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_sigaltstack,
+    libc::SYS_sched_yield,
+    libc::SYS_gettid,
+    libc::SYS_getcwd,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+];
+
+/// Accepting a connection and making it non-blocking.
+const ACCEPT: &[libc::c_long] = &[libc::SYS_accept4, libc::SYS_ioctl, libc::SYS_fcntl];
+
+/// TCP_INFO of closing TCP connections.
+const TCP: &[libc::c_long] = &[libc::SYS_getsockopt];
+
+/// Random numbers of the TLS handshakes.
+const TLS: &[libc::c_long] = &[libc::SYS_getrandom];
+
+/// Batched UDP receives and sends.
+const UDP_BATCH: &[libc::c_long] = &[libc::SYS_recvmmsg, libc::SYS_sendmmsg];
+
+/// Unix socket peer credentials, SOCK_SEQPACKET EOF checks and unlinking
+/// the paths.
+const SEQPACKET: &[libc::c_long] = &[
+    libc::SYS_getsockopt,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_poll,
+    libc::SYS_ppoll,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_unlink,
+    libc::SYS_unlinkat,
+];
+
+/// SCTP stream ids: subscribing accepted associations and the messages
+/// carrying them.
+const SCTP: &[libc::c_long] = &[libc::SYS_setsockopt, libc::SYS_recvmsg, libc::SYS_sendmsg];
+
+/// Turning on and making MSG_ZEROCOPY writes, and reading their
+/// completions off the error queue.
+const ZEROCOPY: &[libc::c_long] = &[libc::SYS_setsockopt, libc::SYS_sendmsg, libc::SYS_recvmsg];
+
+/// Local addresses of accepted connections and rotating the capture file.
+const CAPTURE: &[libc::c_long] = &[
+    libc::SYS_getsockname,
+    libc::SYS_openat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_rename,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_renameat,
+    libc::SYS_renameat2,
+];
+
+/// Reopening the access log.
+const ACCESS_LOG: &[libc::c_long] = &[
+    libc::SYS_openat,
+    libc::SYS_fstat,
+    libc::SYS_statx,
+];
+
+/// Rereading the deny file.
+const DENY_FILE: &[libc::c_long] = &[
+    libc::SYS_openat,
+    libc::SYS_fstat,
+    libc::SYS_statx,
+];
+
+/// Tuning accepted connections and reading their buffer sizes back.
+const SOCKET_OPTIONS: &[libc::c_long] = &[libc::SYS_setsockopt, libc::SYS_getsockopt];
+
+/// Connecting a mirror connection per client and checking it connected.
+const MIRROR: &[libc::c_long] = &[
+    libc::SYS_socket,
+    libc::SYS_connect,
+    libc::SYS_ioctl,
+    libc::SYS_fcntl,
+    libc::SYS_getsockopt,
+];
+
+/// Half-closing answered health probes.
+const HEALTH: &[libc::c_long] = &[libc::SYS_shutdown];
+
+/// Lists the syscalls needed to serve `config`.
+pub fn allowlist(config: &Config) -> Vec<libc::c_long> {
+    let mut syscalls = BASE.to_vec();
+    let streams = config.listen.is_some()
+        || !config.listeners.is_empty()
+        || config.vsock_port.is_some()
+        || config.unix_seqpacket.is_some()
+        || config.unix_stream.is_some()
+        || config.sctp.is_some();
+    if streams || config.health_addr.is_some() || config.admin_addr.is_some() {
+        syscalls.extend_from_slice(ACCEPT);
+    }
+    if config.health_addr.is_some() {
+        syscalls.extend_from_slice(HEALTH);
+    }
+    if config.so_rcvbuf.is_some() || config.so_sndbuf.is_some() || config.tcp_user_timeout.is_some() {
+        syscalls.extend_from_slice(SOCKET_OPTIONS);
+    }
+    if config.listen.is_some() || !config.listeners.is_empty() {
+        syscalls.extend_from_slice(TCP);
+    }
+    if config.udp.is_some() && config.udp_batch > 1 {
+        syscalls.extend_from_slice(UDP_BATCH);
+    }
+    if config.zerocopy.is_some() {
+        syscalls.extend_from_slice(ZEROCOPY);
+    }
+    if config.unix_seqpacket.is_some() || config.unix_stream.is_some() {
+        syscalls.extend_from_slice(SEQPACKET);
+    }
+    if config.sctp.is_some() {
+        syscalls.extend_from_slice(SCTP);
+    }
+    if config.tls_cert.is_some() {
+        syscalls.extend_from_slice(TLS);
+    }
+    if config.capture.is_some() {
+        syscalls.extend_from_slice(CAPTURE);
+    }
+    if config.access_log.is_some() {
+        syscalls.extend_from_slice(ACCESS_LOG);
+    }
+    if config.deny_file.is_some() {
+        syscalls.extend_from_slice(DENY_FILE);
+    }
+    if config.mirror.is_some() {
+        syscalls.extend_from_slice(MIRROR);
+    }
+    syscalls.sort_unstable();
+    syscalls.dedup();
+    syscalls
+}
+
+/// Installs the allowlist for `config` on the whole process.
+///
+/// Nothing can be opened, bound or spawned afterwards, so this must run
+/// once every listener is bound and every file is read. That includes
+/// tick callbacks, and panics with `RUST_BACKTRACE` set.
+pub fn install(config: &Config) -> Result<(), Error> {
+    let mut filter = program(&allowlist(config));
+    let prog = libc::sock_fprog {
+        len: filter.len() as libc::c_ushort,
+        filter: filter.as_mut_ptr(),
+    };
+
+    unsafe {
+        cvt(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))
+            .map_err(|e| Error::system("PR_SET_NO_NEW_PRIVS", e))?;
+        cvt(libc::prctl(
+            libc::PR_SET_SECCOMP,
+            libc::SECCOMP_MODE_FILTER,
+            &prog as *const libc::sock_fprog,
+        ))
+        .map_err(|e: io::Error| Error::system("seccomp filter install", e))?;
+    }
+    Ok(())
+}
+
+// Checks the architecture, then compares the syscall number against
+// every allowed one in turn
+fn program(syscalls: &[libc::c_long]) -> Vec<libc::sock_filter> {
+    let mut prog = vec![
+        stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, ARCH_OFFSET),
+        jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, AUDIT_ARCH, 1, 0),
+        stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, NR_OFFSET),
+    ];
+    for &nr in syscalls {
+        prog.push(jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, nr as u32, 0, 1));
+        prog.push(stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW));
+    }
+    prog.push(stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS));
+    prog
+}
+
+fn stmt(code: u32, k: u32) -> libc::sock_filter {
+    jump(code, k, 0, 0)
+}
+
+fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    }
+}
@@ -0,0 +1,86 @@
+//! SOCK_SEQPACKET Unix sockets, which keep message boundaries.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+
+use crate::sys::{accept_unix, bind_unix, cvt, evented_fd, recv_fd, send_fd};
+
+/// A non-blocking SOCK_SEQPACKET listener, unlinking its path on drop.
+pub struct SeqpacketListener {
+    fd: OwnedFd,
+    path: PathBuf,
+}
+
+impl SeqpacketListener {
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<SeqpacketListener> {
+        let path = path.as_ref();
+        Ok(SeqpacketListener {
+            fd: bind_unix(path, libc::SOCK_SEQPACKET)?,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Accepts a connection, returning it with the peer's (pid, uid).
+    pub fn accept(&self) -> io::Result<(SeqpacketStream, (i32, u32))> {
+        let (fd, cred) = accept_unix(&self.fd)?;
+        Ok((SeqpacketStream { fd }, cred))
+    }
+}
+
+impl Drop for SeqpacketListener {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// A connected, non-blocking SOCK_SEQPACKET stream.
+///
+/// Every `write` sends one packet and every `recv_packet` returns one.
+pub struct SeqpacketStream {
+    fd: OwnedFd,
+}
+
+impl SeqpacketStream {
+    /// Receives one packet, `None` means the peer has closed.
+    ///
+    /// `recv` returns 0 both for an empty packet and at end of stream, so a 0
+    /// is only taken as EOF once the peer has hung up. An empty packet sent
+    /// right before closing is therefore lost, which no peer can notice.
+    pub fn recv_packet(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        match recv_fd(&self.fd, buf, 0)? {
+            0 if self.hung_up()? => Ok(None),
+            len => Ok(Some(len)),
+        }
+    }
+
+    fn hung_up(&self) -> io::Result<bool> {
+        let mut pfd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLRDHUP,
+            revents: 0,
+        };
+        cvt(unsafe { libc::poll(&mut pfd, 1, 0) })?;
+        Ok(pfd.revents & (libc::POLLRDHUP | libc::POLLHUP) != 0)
+    }
+}
+
+impl Read for SeqpacketStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(self.recv_packet(buf)?.unwrap_or(0))
+    }
+}
+
+impl Write for SeqpacketStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        send_fd(&self.fd, buf, libc::MSG_NOSIGNAL)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+evented_fd!(SeqpacketListener);
+evented_fd!(SeqpacketStream);
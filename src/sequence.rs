@@ -0,0 +1,184 @@
+//! Loss, duplicates and reordering of UDP datagrams numbered by their
+//! senders, see `Config::udp_sequence`.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use log::info;
+
+use crate::stats::SequenceStats;
+
+/// Bytes of the big-endian sequence number starting every datagram.
+pub const SEQ_SIZE: usize = 8;
+/// Sequence numbers below the highest one whose arrival is remembered, to
+/// tell a late datagram from a duplicate. Older ones count as late.
+const WINDOW: u64 = 1024;
+/// Sources tracked at once, the datagrams of others are only echoed.
+const MAX_SOURCES: usize = 4096;
+
+/// What a source sent so far.
+struct Source {
+    /// `None` until a well-formed datagram came.
+    highest: Option<u64>,
+    received: u64,
+    /// Sequence numbers skipped and not received since.
+    lost: u64,
+    duplicates: u64,
+    /// Datagrams that came after one with a higher sequence number.
+    reorders: u64,
+    malformed: u64,
+    last_seen: Instant,
+    /// Bit `seq % WINDOW` is set once `seq` arrived, for the sequence
+    /// numbers within `WINDOW` of the highest.
+    seen: [u64; (WINDOW / 64) as usize],
+}
+
+impl Source {
+    fn new(now: Instant) -> Source {
+        Source {
+            highest: None,
+            received: 0,
+            lost: 0,
+            duplicates: 0,
+            reorders: 0,
+            malformed: 0,
+            last_seen: now,
+            seen: [0; (WINDOW / 64) as usize],
+        }
+    }
+
+    // Sets the bit of `seq`, returns whether it was set already
+    fn mark(&mut self, seq: u64) -> bool {
+        let bit = seq % WINDOW;
+        let word = &mut self.seen[(bit / 64) as usize];
+        let mask = 1 << (bit % 64);
+        let was = *word & mask != 0;
+        *word |= mask;
+        was
+    }
+
+    fn unmark(&mut self, seq: u64) {
+        let bit = seq % WINDOW;
+        self.seen[(bit / 64) as usize] &= !(1 << (bit % 64));
+    }
+
+    // Accounts for `seq` in the source and in `totals`
+    fn record(&mut self, seq: u64, totals: &mut SequenceStats) {
+        self.received += 1;
+        let highest = match self.highest {
+            Some(highest) => highest,
+            None => {
+                self.highest = Some(seq);
+                self.mark(seq);
+                return;
+            }
+        };
+        if seq > highest {
+            let skipped = seq - highest - 1;
+            self.lost += skipped;
+            totals.lost += skipped;
+            // Forget the bits the window slides over
+            if seq - highest >= WINDOW {
+                self.seen = [0; (WINDOW / 64) as usize];
+            } else {
+                for old in highest + 1..=seq {
+                    self.unmark(old);
+                }
+            }
+            self.highest = Some(seq);
+            self.mark(seq);
+        } else if highest - seq < WINDOW && self.mark(seq) {
+            self.duplicates += 1;
+            totals.duplicates += 1;
+        } else {
+            // Counted lost when it was skipped
+            self.reorders += 1;
+            totals.reorders += 1;
+            if self.lost > 0 {
+                self.lost -= 1;
+                totals.lost = totals.lost.saturating_sub(1);
+            }
+        }
+    }
+}
+
+/// Tracks the sequence numbers of every source, forgetting the sources
+/// quiet for `idle` after logging what they sent.
+pub struct Sequencer {
+    sources: HashMap<SocketAddr, Source>,
+    idle: Duration,
+    /// Totals of every source, past ones included.
+    pub totals: SequenceStats,
+}
+
+impl Sequencer {
+    pub fn new(idle: Duration) -> Sequencer {
+        Sequencer {
+            sources: HashMap::new(),
+            idle,
+            totals: SequenceStats::default(),
+        }
+    }
+
+    /// Accounts for a datagram of `addr`, which should start with its
+    /// sequence number.
+    pub fn record(&mut self, addr: SocketAddr, data: &[u8], now: Instant) {
+        let seq = data.get(..SEQ_SIZE).map(|seq| u64::from_be_bytes(seq.try_into().expect("8 bytes")));
+        if seq.is_none() {
+            self.totals.malformed += 1;
+        }
+        if !self.sources.contains_key(&addr) {
+            if self.sources.len() == MAX_SOURCES {
+                self.totals.untracked += 1;
+                return;
+            }
+            self.totals.sources += 1;
+        }
+        let source = self.sources.entry(addr).or_insert_with(|| Source::new(now));
+        source.last_seen = now;
+        match seq {
+            Some(seq) => source.record(seq, &mut self.totals),
+            None => source.malformed += 1,
+        }
+    }
+
+    /// Logs a summary of every source quiet for `idle` and forgets it.
+    pub fn expire(&mut self, now: Instant) {
+        let idle = self.idle;
+        self.sources.retain(|addr, source| {
+            if now.saturating_duration_since(source.last_seen) < idle {
+                return true;
+            }
+            info!("udp source quiet for {:?} : {} ({})", idle, addr, summary(source));
+            false
+        });
+    }
+
+    /// The counts of every source, for the admin socket.
+    pub fn list(&self) -> String {
+        if self.sources.is_empty() {
+            return "none".to_string();
+        }
+        let mut sources: Vec<_> = self.sources.iter().collect();
+        sources.sort_by_key(|&(addr, _)| *addr);
+        let mut list = String::new();
+        for (addr, source) in sources {
+            if !list.is_empty() {
+                list.push_str(", ");
+            }
+            let _ = write!(list, "{} ({})", addr, summary(source));
+        }
+        list
+    }
+}
+
+fn summary(source: &Source) -> String {
+    let highest = source.highest.map_or("-".to_string(), |highest| highest.to_string());
+    format!(
+        "{} received, highest {}, {} lost, {} duplicates, {} reordered, {} malformed",
+        source.received, highest, source.lost, source.duplicates, source.reorders, source.malformed
+    )
+}
@@ -0,0 +1,566 @@
+use std::env;
+use std::io;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::info;
+use mio::event::Event;
+use mio::{Events, Poll, Registry};
+
+use crate::clock::{self, Clock};
+use crate::config::{Backend, Config, Mode};
+use crate::events::{Conn, ServerEvent};
+use crate::handle::ServerHandle;
+use crate::handler::Handler;
+use crate::reactor::{Reactor, Tick, TickContext};
+use crate::resolve::Resolver;
+use crate::stats::Stats;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use crate::uring::Uring;
+use crate::Error;
+
+/// Builds a `Server` from a `Config` plus the options that can't live in
+/// one, such as callbacks.
+pub struct ServerBuilder {
+    config: Config,
+    tick: Option<Tick>,
+    handler: Option<Box<dyn Handler>>,
+    handler_panic_limit: Option<u64>,
+    clock: Option<Arc<dyn Clock>>,
+    resolver: Option<Box<dyn Resolver>>,
+    events: bool,
+}
+
+impl ServerBuilder {
+    pub fn new(config: Config) -> ServerBuilder {
+        ServerBuilder {
+            config,
+            tick: None,
+            handler: None,
+            handler_panic_limit: None,
+            clock: None,
+            resolver: None,
+            events: false,
+        }
+    }
+
+    /// Calls `callback` every `interval` from inside the event loop.
+    ///
+    /// Ticks are deadline based, so they keep their cadence under load.
+    /// A panicking callback is logged and called again on the next tick.
+    pub fn tick<F>(mut self, interval: Duration, callback: F) -> ServerBuilder
+    where
+        F: FnMut(&mut TickContext) + Send + 'static,
+    {
+        self.tick = Some(Tick::new(interval, Box::new(callback)));
+        self
+    }
+
+    /// Echoes what `handler` makes of the input instead of the input
+    /// itself, letting it also silence or close connections, see `Action`.
+    /// Needs the mio backend and the plain echo mode.
+    pub fn handler<H: Handler + 'static>(mut self, handler: H) -> ServerBuilder {
+        self.handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Drops the handler once it has panicked `panics` times, falling back
+    /// to the plain echo. By default it is kept however often it panics.
+    pub fn handler_panic_limit(mut self, panics: u64) -> ServerBuilder {
+        self.handler_panic_limit = Some(panics);
+        self
+    }
+
+    /// Takes the time of every timer and deadline from `clock` instead of
+    /// the system clock, e.g. a `ManualClock` to step through timeouts.
+    /// Needs the mio backend.
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> ServerBuilder {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Looks up the host names of `Config::resolve_peers` with `resolver`
+    /// instead of the system resolver. Needs the mio backend.
+    pub fn resolver<R: Resolver + 'static>(mut self, resolver: R) -> ServerBuilder {
+        self.resolver = Some(Box::new(resolver));
+        self
+    }
+
+    /// Lets the caller drive the connections instead of echoing: the
+    /// server reports them, their data and their closing through
+    /// `Server::next_events`, and writes what `Server::send` hands it,
+    /// queued and under the overflow policy as the echo would be. A call
+    /// hands out at most `Config::max_queued` of the data of a connection,
+    /// the rest is read by later ones. Under backpressure, the data of the
+    /// events also counts against it until the next call, as if sent back.
+    /// Needs the mio backend and the plain echo mode, without a handler.
+    pub fn events(mut self) -> ServerBuilder {
+        self.events = true;
+        self
+    }
+
+    #[cfg(all(unix, feature = "tokio"))]
+    pub(crate) fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Validates the config and raises the open file limit to fit
+    /// `Config::max_clients`, then binds the listeners.
+    pub fn build(mut self) -> Result<Server, Error> {
+        self.validate()?;
+        #[cfg(unix)]
+        crate::limits::fit_nofile(&mut self.config)?;
+        let inner = match self.config.backend {
+            Backend::Mio => {
+                let clock = self.clock.unwrap_or_else(clock::system);
+                let resolver = resolver(&self.config, self.resolver);
+                let mut reactor = Reactor::new(self.config, self.tick, Poll::new()?, 0..usize::MAX, clock)?;
+                #[cfg(unix)]
+                reactor.wake_on_signals()?;
+                if let Some(handler) = self.handler {
+                    reactor.set_handler(handler, self.handler_panic_limit);
+                }
+                if self.events {
+                    reactor.enable_events();
+                }
+                if let Some(resolver) = resolver {
+                    reactor.set_resolver(resolver)?;
+                }
+                Inner::Mio(reactor)
+            }
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Backend::Uring => Inner::Uring(Uring::new(self.config, self.tick)?),
+            #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+            Backend::Uring => return Err(Error::config("io_uring support is not compiled in")),
+        };
+        Ok(Server { inner, events: None })
+    }
+
+    /// Binds the listeners and registers them with the `registry` of the
+    /// caller's poll, using only the tokens of `tokens`. Polling needs the
+    /// poll itself mutably, so the registry is best a `try_clone` of its
+    /// own.
+    ///
+    /// The range holds the clients followed by one token per listener,
+    /// then nine tokens for `Config::health_addr`, five for
+    /// `Config::admin_addr` and 64 for `Config::busy_message` if set, and
+    /// one more per client for `Config::mirror`; the client limit shrinks
+    /// to fit when the range is short.
+    ///
+    /// Only the mio backend can be embedded. With
+    /// `Config::resolve_peers`, the server wakes the poll with a
+    /// `mio::Waker`, and the caller can't make one of its own. Signals,
+    /// which can't wake the caller's poll otherwise, are acted on by the
+    /// next `EmbeddedServer::expire_timers`.
+    pub fn build_embedded(self, registry: &Registry, tokens: Range<usize>) -> Result<EmbeddedServer<'_>, Error> {
+        self.validate()?;
+        if self.config.backend != Backend::Mio {
+            return Err(Error::config("only the mio backend can be embedded"));
+        }
+        let clock = self.clock.unwrap_or_else(clock::system);
+        let resolver = resolver(&self.config, self.resolver);
+        let mut reactor = Reactor::new(self.config, self.tick, registry, tokens, clock)?;
+        if let Some(handler) = self.handler {
+            reactor.set_handler(handler, self.handler_panic_limit);
+        }
+        if let Some(resolver) = resolver {
+            reactor.set_resolver(resolver)?;
+        }
+        Ok(EmbeddedServer { reactor })
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        self.config.validate()?;
+        if self.handler.is_some() {
+            if self.config.backend != Backend::Mio {
+                return Err(Error::config("a handler needs the mio backend"));
+            }
+            let decodes = |config: &Config| config.mode != Mode::Echo || config.telnet;
+            let config = &self.config;
+            if decodes(config) || (0..config.listeners.len()).any(|index| decodes(&config.for_listener(index))) {
+                return Err(Error::config("a handler replaces the echo, it needs the plain echo mode"));
+            }
+            if config.annotate {
+                return Err(Error::config("a handler replaces the echo, it can't be annotated"));
+            }
+            if config.allow_mode_negotiation {
+                return Err(Error::config("a handler replaces the echo, no mode can be negotiated"));
+            }
+        }
+        if self.events {
+            if self.config.backend != Backend::Mio {
+                return Err(Error::config("events need the mio backend"));
+            }
+            if self.handler.is_some() {
+                return Err(Error::config("events and a handler can't be combined"));
+            }
+            let decodes = |config: &Config| config.mode != Mode::Echo || config.telnet;
+            let config = &self.config;
+            if decodes(config) || (0..config.listeners.len()).any(|index| decodes(&config.for_listener(index))) {
+                return Err(Error::config("events replace the echo, they need the plain echo mode"));
+            }
+            if config.annotate || config.allow_mode_negotiation {
+                return Err(Error::config("events replace the echo, it can't be annotated or negotiated"));
+            }
+        }
+        if self.clock.is_some() && self.config.backend != Backend::Mio {
+            return Err(Error::config("a clock needs the mio backend"));
+        }
+        if self.resolver.is_some() && self.config.backend != Backend::Mio {
+            return Err(Error::config("a resolver needs the mio backend"));
+        }
+        if self.handler_panic_limit == Some(0) {
+            return Err(Error::config("handler_panic_limit must be positive"));
+        }
+        Ok(())
+    }
+}
+
+// The resolver of `Config::resolve_peers`, the system one unless the
+// builder was given another
+fn resolver(config: &Config, custom: Option<Box<dyn Resolver>>) -> Option<Box<dyn Resolver>> {
+    if !config.resolve_peers {
+        return None;
+    }
+    #[cfg(target_os = "linux")]
+    let custom = custom.or_else(|| Some(Box::new(crate::resolve::SystemResolver)));
+    custom
+}
+
+/// A bound echo server.
+pub struct Server {
+    inner: Inner,
+    /// Storage of `poll_once`, allocated by its first call.
+    events: Option<Events>,
+}
+
+// One per server, the size difference doesn't matter
+#[allow(clippy::large_enum_variant)]
+enum Inner {
+    Mio(Reactor),
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    Uring(Uring),
+}
+
+impl Server {
+    pub fn builder(config: Config) -> ServerBuilder {
+        ServerBuilder::new(config)
+    }
+
+    /// Builds a server without callbacks.
+    pub fn from_config(config: Config) -> Result<Server, Error> {
+        ServerBuilder::new(config).build()
+    }
+
+    /// Serves until an error occurs, a tick callback requests a shutdown,
+    /// the server stays idle for `Config::exit_when_idle` or the drain of
+    /// `Config::duration` or `Config::max_connections_total` is over.
+    ///
+    /// On Unix with the mio backend, SIGTERM and SIGINT stop the accepts
+    /// and close every client once its queue is written, or after
+    /// `Config::drain_timeout`, then this returns `Ok`. A second signal
+    /// closes them right away. The handlers stay once this returns.
+    ///
+    /// With `Config::chroot` or `Config::seccomp`, the process is confined
+    /// before serving and stays confined once this returns.
+    /// Started by the `Config::upgrade_binary` of an older server, it then
+    /// tells that one to stop accepting.
+    pub fn run(&mut self) -> Result<(), Error> {
+        self.serve(true)
+    }
+
+    /// Serves as `run` does on a thread of its own, returning a handle to
+    /// stop it with `ServerHandle::shutdown` and the thread, which returns
+    /// the final stats once the server stopped. SIGTERM and SIGINT are
+    /// left to the application. Only the mio backend can be started.
+    pub fn start(mut self) -> Result<(ServerHandle, JoinHandle<Result<Stats, Error>>), Error> {
+        let handle = self.handle()?;
+        let thread = thread::Builder::new().name("server".to_string()).spawn(move || {
+            self.serve(false)?;
+            Ok(self.close())
+        })?;
+        Ok((handle, thread))
+    }
+
+    // `run`, draining on SIGTERM and SIGINT if `signals` is set
+    fn serve(&mut self, signals: bool) -> Result<(), Error> {
+        match self.inner {
+            Inner::Mio(ref mut reactor) => {
+                confine(reactor.config())?;
+                #[cfg(unix)]
+                crate::upgrade::notify_ready();
+                #[cfg(unix)]
+                if signals {
+                    reactor.watch_terminate()?;
+                }
+                #[cfg(not(unix))]
+                let _ = signals;
+                reactor.run()
+            }
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Inner::Uring(ref mut uring) => {
+                confine(uring.config())?;
+                uring.run()
+            }
+        }
+    }
+
+    /// Runs one iteration of the event loop: waits at most `timeout` for
+    /// events, or until the next timer when `None`, then handles them and
+    /// the timers expired by the server's clock. Returns the number of
+    /// events.
+    ///
+    /// Unlike `run`, this neither confines the process, see `confine`, nor
+    /// checks for a shutdown request. With the uring backend the events are the
+    /// completions, and the timers those of the tick and the watchdog.
+    pub fn poll_once(&mut self, timeout: Option<Duration>) -> Result<usize, Error> {
+        match self.inner {
+            Inner::Mio(ref mut reactor) => {
+                let capacity = reactor.config().events_capacity;
+                let events = self.events.get_or_insert_with(|| Events::with_capacity(capacity));
+                let timeout = timeout.or_else(|| reactor.next_timeout(reactor.now()));
+                reactor.turn(events, timeout)
+            }
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Inner::Uring(ref mut uring) => uring.turn(timeout),
+        }
+    }
+
+    /// Applies `Config::chroot` and `Config::seccomp` as `run` does first,
+    /// for a server stepped with `poll_once` instead. Nothing the filter
+    /// doesn't allow can be done from this thread afterwards.
+    pub fn confine(&self) -> Result<(), Error> {
+        match self.inner {
+            Inner::Mio(ref reactor) => confine(reactor.config()),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Inner::Uring(ref uring) => confine(uring.config()),
+        }
+    }
+
+    /// Runs the event loop as `poll_once` does, without waiting if events
+    /// are already pending, and returns what happened since the last call.
+    /// Needs `ServerBuilder::events`, without it nothing is ever reported.
+    ///
+    /// The data of each `ServerEvent::Data` comes in the buffer it was
+    /// read into, which `send` takes as is: see the `events` example for
+    /// an echo written this way.
+    pub fn next_events(&mut self, timeout: Option<Duration>) -> Result<Vec<ServerEvent>, Error> {
+        let pending = match self.inner {
+            Inner::Mio(ref mut reactor) => {
+                reactor.release_handed_out();
+                reactor.has_events()
+            }
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Inner::Uring(_) => false,
+        };
+        self.poll_once(if pending { Some(Duration::from_secs(0)) } else { timeout })?;
+        match self.inner {
+            Inner::Mio(ref mut reactor) => Ok(reactor.take_events()),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Inner::Uring(_) => Ok(Vec::new()),
+        }
+    }
+
+    /// Queues `data` for `conn` and writes what its socket takes, see
+    /// `ServerBuilder::events`. Data for a connection that is closed or
+    /// closing is dropped.
+    pub fn send(&mut self, conn: Conn, data: Vec<u8>) {
+        match self.inner {
+            Inner::Mio(ref mut reactor) => reactor.send(conn, data),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Inner::Uring(_) => {}
+        }
+    }
+
+    /// Closes `conn` once what was sent to it is written, its input read
+    /// meanwhile being dropped.
+    pub fn close_conn(&mut self, conn: Conn) {
+        match self.inner {
+            Inner::Mio(ref mut reactor) => reactor.close_conn(conn),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Inner::Uring(_) => {}
+        }
+    }
+
+    /// Whether a tick callback asked the server to stop, or a drain is
+    /// over.
+    pub fn shutdown_requested(&self) -> bool {
+        match self.inner {
+            Inner::Mio(ref reactor) => reactor.shutdown_requested(),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Inner::Uring(_) => false,
+        }
+    }
+
+    /// A handle to add and remove listeners from other threads while
+    /// `run` serves, see `ServerHandle`. Only the mio backend has one.
+    pub fn handle(&mut self) -> Result<ServerHandle, Error> {
+        match self.inner {
+            Inner::Mio(ref mut reactor) => reactor.handle(),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Inner::Uring(_) => Err(Error::config("only the mio backend has a handle")),
+        }
+    }
+
+    // The mio loop, `None` with the uring backend
+    #[cfg(all(unix, feature = "tokio"))]
+    pub(crate) fn reactor_mut(&mut self) -> Option<&mut Reactor> {
+        match self.inner {
+            Inner::Mio(ref mut reactor) => Some(reactor),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Inner::Uring(_) => None,
+        }
+    }
+
+    pub fn stats(&self) -> &Stats {
+        match self.inner {
+            Inner::Mio(ref reactor) => reactor.stats(),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Inner::Uring(ref uring) => uring.stats(),
+        }
+    }
+
+    /// Deregisters and closes every socket now rather than whenever the
+    /// server is dropped, and returns the final stats.
+    pub fn close(mut self) -> Stats {
+        match self.inner {
+            Inner::Mio(ref mut reactor) => reactor.close(),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Inner::Uring(ref mut uring) => uring.close(),
+        }
+        *self.stats()
+    }
+
+    /// Address of the TCP listener, e.g. to learn the port picked for a
+    /// `127.0.0.1:0` listen address.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match self.inner {
+            Inner::Mio(ref reactor) => reactor.local_addr(),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Inner::Uring(ref uring) => uring.local_addr(),
+        }
+    }
+
+    /// Address of the UDP socket, see `local_addr`.
+    pub fn udp_addr(&self) -> Option<SocketAddr> {
+        match self.inner {
+            Inner::Mio(ref reactor) => reactor.udp_addr(),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Inner::Uring(_) => None,
+        }
+    }
+
+    /// Address of the entry of `Config::listeners` at this index, see
+    /// `local_addr`.
+    pub fn listener_addr(&self, entry: usize) -> Option<SocketAddr> {
+        match self.inner {
+            Inner::Mio(ref reactor) => reactor.listener_addr(entry),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Inner::Uring(_) => None,
+        }
+    }
+
+    /// Address of `Config::admin_addr`, see `local_addr`.
+    pub fn admin_addr(&self) -> Option<SocketAddr> {
+        match self.inner {
+            Inner::Mio(ref reactor) => reactor.admin_addr(),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Inner::Uring(_) => None,
+        }
+    }
+
+    /// Address of `Config::health_addr`, see `local_addr`.
+    pub fn health_addr(&self) -> Option<SocketAddr> {
+        match self.inner {
+            Inner::Mio(ref reactor) => reactor.health_addr(),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Inner::Uring(_) => None,
+        }
+    }
+}
+
+/// An echo server driven by the caller's event loop.
+///
+/// It never polls by itself: the caller hands it every event and calls
+/// `expire_timers` once per loop iteration, waiting at most
+/// `next_timeout` in its own poll.
+pub struct EmbeddedServer<'a> {
+    reactor: Reactor<&'a Registry>,
+}
+
+impl<'a> EmbeddedServer<'a> {
+    /// Handles an event, returns false if it belongs to someone else.
+    pub fn handle_event(&mut self, event: &Event) -> Result<bool, Error> {
+        self.reactor.handle_event(event)
+    }
+
+    /// Time left until the server's next timer, if any, by its clock.
+    pub fn next_timeout(&self) -> Option<Duration> {
+        self.reactor.next_timeout(self.reactor.now())
+    }
+
+    pub fn expire_timers(&mut self) -> Result<(), Error> {
+        let now = self.reactor.now();
+        self.reactor.expire_timers(now)
+    }
+
+    /// Whether a tick callback asked the server to stop, or a drain is
+    /// over.
+    pub fn shutdown_requested(&self) -> bool {
+        self.reactor.shutdown_requested()
+    }
+
+    pub fn stats(&self) -> &Stats {
+        self.reactor.stats()
+    }
+
+    /// Deregisters every socket from the caller's poll and closes it, and
+    /// returns the final stats.
+    pub fn close(mut self) -> Stats {
+        self.reactor.close();
+        *self.reactor.stats()
+    }
+}
+
+// Applies the confinement requested by `config`, chroot first since the
+// seccomp filter forbids it
+fn confine(config: &Config) -> Result<(), Error> {
+    if let Some(ref dir) = config.chroot {
+        chroot(dir)?;
+        info!("chrooted into {}", dir.display());
+    }
+    if config.seccomp {
+        #[cfg(all(target_os = "linux", feature = "seccomp", any(target_arch = "x86_64", target_arch = "aarch64")))]
+        {
+            crate::seccomp::install(config)?;
+            info!("seccomp filter installed");
+        }
+        #[cfg(not(all(target_os = "linux", feature = "seccomp", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+        return Err(Error::config("seccomp support is not compiled in"));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn chroot(dir: &Path) -> Result<(), Error> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(dir.as_os_str().as_bytes())
+        .map_err(|_| Error::config(format!("invalid chroot directory: {}", dir.display())))?;
+    if unsafe { libc::chroot(path.as_ptr()) } < 0 {
+        let e = io::Error::last_os_error();
+        return Err(Error::system(format!("chroot {}", dir.display()), e));
+    }
+    env::set_current_dir("/").map_err(|e| Error::system("chdir /", e))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn chroot(_: &Path) -> Result<(), Error> {
+    Err(Error::config("chroot is only supported on Unix"))
+}
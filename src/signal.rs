@@ -0,0 +1,181 @@
+//! Signals seen as flags, so several parts of the server can watch the
+//! same one, and the event loops woken to look at them.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem;
+use std::os::unix::io::{FromRawFd, IntoRawFd, OwnedFd};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicPtr, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+
+use mio::Waker;
+
+use crate::upgrade::set_flag;
+
+/// Most flags watched at once.
+const WATCHERS: usize = 8;
+
+// Signal number and flag of each watcher, the flag is null while free
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_SIGNAL: AtomicI32 = AtomicI32::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_FLAG: AtomicPtr<AtomicBool> = AtomicPtr::new(ptr::null_mut());
+static SIGNALS: [AtomicI32; WATCHERS] = [NO_SIGNAL; WATCHERS];
+static FLAGS: [AtomicPtr<AtomicBool>; WATCHERS] = [NO_FLAG; WATCHERS];
+
+/// Write end of the pipe the handler wakes the relay thread through, -1
+/// until `wake_on_signals` started it. Never closed once open.
+static PIPE: AtomicI32 = AtomicI32::new(-1);
+/// What the relay thread wakes, those dropped are forgotten.
+static WAKERS: Mutex<Vec<Weak<Waker>>> = Mutex::new(Vec::new());
+
+/// Sets `flag` whenever `signum` arrives, along with the other flags
+/// watching it. Watching again with the same flag does nothing, watching
+/// with more than `WATCHERS` flags fails.
+pub fn watch(signum: libc::c_int, flag: &'static AtomicBool) -> io::Result<()> {
+    extern "C" fn handler(signum: libc::c_int) {
+        for (watched, flag) in SIGNALS.iter().zip(&FLAGS) {
+            let flag = flag.load(Ordering::Acquire);
+            if !flag.is_null() && watched.load(Ordering::Acquire) == signum {
+                unsafe { &*flag }.store(true, Ordering::Relaxed);
+            }
+        }
+        // A full pipe already has the relay thread to wake
+        let pipe = PIPE.load(Ordering::Acquire);
+        if pipe >= 0 {
+            unsafe { libc::write(pipe, [0u8].as_ptr().cast(), 1) };
+        }
+    }
+
+    let wanted = flag as *const AtomicBool as *mut AtomicBool;
+    for (watched, slot) in SIGNALS.iter().zip(&FLAGS) {
+        if slot.load(Ordering::Acquire) == wanted && watched.load(Ordering::Acquire) == signum {
+            return Ok(());
+        }
+    }
+    // Slots are never freed, so until its signal number is stored a
+    // claimed one holds 0, which no signal matches
+    let (watched, _) = SIGNALS
+        .iter()
+        .zip(&FLAGS)
+        .find(|&(_, slot)| {
+            slot.compare_exchange(ptr::null_mut(), wanted, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        })
+        .ok_or_else(|| io::Error::other(format!("more than {} signal watchers", WATCHERS)))?;
+    watched.store(signum, Ordering::Release);
+
+    // Restarting the calls it interrupts, other threads block in some;
+    // the poll returns early anyway
+    let mut action: libc::sigaction = unsafe { mem::zeroed() };
+    action.sa_sigaction = handler as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    action.sa_flags = libc::SA_RESTART;
+    unsafe {
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(signum, &action, ptr::null_mut()) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Wakes `waker` whenever a watched signal arrives, once its flags are
+/// set, until it is dropped.
+///
+/// Waking a poll takes more than a signal handler may do, so the handler
+/// writes to a pipe read by a thread of its own, which does the waking.
+pub fn wake_on_signals(waker: &Arc<Waker>) -> io::Result<()> {
+    let mut wakers = WAKERS.lock().unwrap_or_else(|e| e.into_inner());
+    if PIPE.load(Ordering::Acquire) < 0 {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let (read, write) = unsafe { (File::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+        set_flag(fds[0], libc::F_GETFD, libc::F_SETFD, libc::FD_CLOEXEC)?;
+        set_flag(fds[1], libc::F_GETFD, libc::F_SETFD, libc::FD_CLOEXEC)?;
+        set_flag(fds[1], libc::F_GETFL, libc::F_SETFL, libc::O_NONBLOCK)?;
+        thread::Builder::new().name("signals".to_string()).spawn(move || relay(read))?;
+        PIPE.store(write.into_raw_fd(), Ordering::Release);
+    }
+    wakers.push(Arc::downgrade(waker));
+    Ok(())
+}
+
+// Wakes the wakers for each batch of signals the handler noted
+fn relay(mut pipe: File) {
+    let mut buf = [0; 64];
+    loop {
+        match pipe.read(&mut buf) {
+            Ok(0) => return,
+            Ok(_) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return,
+        }
+        let mut wakers = WAKERS.lock().unwrap_or_else(|e| e.into_inner());
+        wakers.retain(|waker| match waker.upgrade() {
+            Some(waker) => waker.wake().is_ok(),
+            None => false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process::Command;
+
+    /// Set in the process the tests rerun themselves in, the table being
+    /// shared by every test of this one.
+    const CHILD_VAR: &str = "MIO_ECHO_SERVER_SIGNAL_TEST";
+
+    // Runs `test` in a process of its own, returns false when in it
+    fn in_child(test: &str) -> bool {
+        if env::var_os(CHILD_VAR).is_some() {
+            return false;
+        }
+        let status = Command::new(env::current_exe().unwrap())
+            .args([test, "--exact", "--test-threads=1"])
+            .env(CHILD_VAR, "1")
+            .status()
+            .unwrap();
+        assert!(status.success(), "{} failed in its own process", test);
+        true
+    }
+
+    #[test]
+    fn every_flag_watching_a_signal_is_set() {
+        if in_child("signal::tests::every_flag_watching_a_signal_is_set") {
+            return;
+        }
+        static FIRST: AtomicBool = AtomicBool::new(false);
+        static SECOND: AtomicBool = AtomicBool::new(false);
+        watch(libc::SIGWINCH, &FIRST).unwrap();
+        watch(libc::SIGWINCH, &SECOND).unwrap();
+        // Again, which changes nothing
+        watch(libc::SIGWINCH, &FIRST).unwrap();
+        assert_eq!(unsafe { libc::raise(libc::SIGWINCH) }, 0);
+        assert!(FIRST.load(Ordering::Relaxed) && SECOND.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn watchers_past_the_table_are_refused() {
+        if in_child("signal::tests::watchers_past_the_table_are_refused") {
+            return;
+        }
+        #[allow(clippy::declare_interior_mutable_const)]
+        const UNSET: AtomicBool = AtomicBool::new(false);
+        static FLAGS: [AtomicBool; WATCHERS + 1] = [UNSET; WATCHERS + 1];
+        for flag in &FLAGS[..WATCHERS] {
+            watch(libc::SIGWINCH, flag).unwrap();
+        }
+        assert!(watch(libc::SIGWINCH, &FLAGS[WATCHERS]).is_err());
+        // Those watching still are
+        assert_eq!(unsafe { libc::raise(libc::SIGWINCH) }, 0);
+        assert!(FLAGS[..WATCHERS].iter().all(|flag| flag.load(Ordering::Relaxed)));
+        assert!(!FLAGS[WATCHERS].load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,641 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::refusal::Refusal;
+
+/// The transports a client can be connected over.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Transport {
+    Tcp,
+    Udp,
+    Vsock,
+    Seqpacket,
+    /// Unix stream socket.
+    Unix,
+    Sctp,
+    /// Windows named pipe.
+    Pipe,
+}
+
+/// Counters of one transport.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct TransportStats {
+    /// Accepted connections, stream transports only.
+    pub connections: u64,
+    /// Received datagrams, UDP only.
+    pub datagrams: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// Datagrams dropped because the send queue was full, UDP only.
+    pub dropped: u64,
+    /// Connections refused because the server was full.
+    pub rejected: u64,
+    /// Connections reset or aborted by the peer, routine disconnects.
+    pub resets: u64,
+    /// Connections closed because sent data stayed unacknowledged for the
+    /// TCP user timeout.
+    pub timeouts: u64,
+    /// Connections closed on any other I/O error.
+    pub errors: u64,
+}
+
+/// What `Config::overflow` did to clients over `Config::max_queued`.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct OverflowStats {
+    /// Times a client stopped being read, `Backpressure`.
+    pub pauses: u64,
+    /// Messages dropped by `DropNewest`, and their bytes.
+    pub dropped_newest: u64,
+    pub dropped_newest_bytes: u64,
+    /// Messages dropped by `DropOldest`, and their bytes.
+    pub dropped_oldest: u64,
+    pub dropped_oldest_bytes: u64,
+    /// Clients closed by `Disconnect`.
+    pub disconnects: u64,
+}
+
+/// Memory held for the clients, as last measured, see
+/// `Config::shrink_after`.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct BufferStats {
+    /// Bytes allocated for the queues, decoders and slots of the clients
+    /// and the spare queues, and the bytes of them in use.
+    pub reserved: u64,
+    pub used: u64,
+    /// Times a client's buffers, or the slots and spare queues, were
+    /// shrunk.
+    pub shrinks: u64,
+}
+
+/// MSG_ZEROCOPY writes, see `Config::zerocopy`.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ZeroCopyStats {
+    /// Zerocopy writes and their bytes.
+    pub writes: u64,
+    pub bytes: u64,
+    /// Writes reported done that the kernel copied after all.
+    pub copied: u64,
+    /// Writes made as usual because the kernel couldn't pin more memory.
+    pub fallbacks: u64,
+    /// Connections whose socket refused SO_ZEROCOPY, written as usual.
+    pub unsupported: u64,
+    /// Closed connections whose socket was kept for their zerocopy
+    /// writes, and those reset after waiting too long.
+    pub lingered: u64,
+    pub abandoned: u64,
+}
+
+/// Sequence numbers of the UDP datagrams, see `Config::udp_sequence`.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct SequenceStats {
+    /// Sources tracked so far, and datagrams of sources past the most
+    /// tracked at once, echoed only.
+    pub sources: u64,
+    pub untracked: u64,
+    /// Sequence numbers skipped and not received since, an estimate of
+    /// the loss.
+    pub lost: u64,
+    pub duplicates: u64,
+    /// Datagrams that came after one with a higher sequence number.
+    pub reorders: u64,
+    /// Datagrams too short for a sequence number, echoed anyway.
+    pub malformed: u64,
+}
+
+/// DTLS sessions of the UDP peers, see `Config::dtls_cert`.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct DtlsStats {
+    /// Sessions kept now.
+    pub sessions: u64,
+    /// Handshakes done, and those that failed, e.g. on a certificate not
+    /// issued by `Config::dtls_client_ca`.
+    pub handshakes: u64,
+    pub handshake_failures: u64,
+    /// Sessions closed by their peer, and on errors past the handshake.
+    pub closed: u64,
+    pub errors: u64,
+    /// Sessions dropped for being quiet for `Config::dtls_session_idle`,
+    /// and for new peers past `Config::dtls_max_sessions`.
+    pub expired: u64,
+    pub evicted: u64,
+    /// Datagrams of peers without a session that didn't start a
+    /// handshake, dropped.
+    pub strays: u64,
+}
+
+/// QUIC connections on the UDP socket, see `Config::quic`.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct QuicStats {
+    /// Connections accepted, and those open now.
+    pub connections: u64,
+    pub active: u64,
+    /// Addresses sent a Retry to validate them.
+    pub retries: u64,
+    /// Connections lost before their handshake was done, closed by either
+    /// side, and lost otherwise, e.g. timed out.
+    pub handshake_failures: u64,
+    pub closed: u64,
+    pub lost: u64,
+    /// Bidirectional streams echoed, and their bytes.
+    pub streams: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// Datagrams echoed, see `Config::quic_datagrams`.
+    pub datagrams: u64,
+}
+
+/// Connections refused, by reason, see `Refusal`.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct RefusalStats {
+    pub capacity: u64,
+    pub listener_full: u64,
+    pub banned: u64,
+    pub denied: u64,
+    pub pending_timeout: u64,
+    pub draining: u64,
+    /// Refusals counted but not logged, past the log rate limit.
+    pub unlogged: u64,
+}
+
+impl RefusalStats {
+    /// Counts a refusal, returns the count of its reason.
+    pub fn record(&mut self, reason: Refusal) -> u64 {
+        let count = match reason {
+            Refusal::Capacity => &mut self.capacity,
+            Refusal::ListenerFull => &mut self.listener_full,
+            Refusal::Banned => &mut self.banned,
+            Refusal::Denied => &mut self.denied,
+            Refusal::PendingTimeout => &mut self.pending_timeout,
+            Refusal::Draining => &mut self.draining,
+        };
+        *count += 1;
+        *count
+    }
+
+    pub fn total(&self) -> u64 {
+        self.capacity + self.listener_full + self.banned + self.denied + self.pending_timeout + self.draining
+    }
+}
+
+/// Number of buckets in `LoopStats::events_per_poll`.
+pub const EVENTS_PER_POLL_BUCKETS: usize = 8;
+
+/// Health of the event loop itself.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct LoopStats {
+    /// Calls to `Poll::poll`.
+    pub polls: u64,
+    /// Polls that returned without any event, e.g. on a timer.
+    pub empty_polls: u64,
+    /// Events dispatched.
+    pub events: u64,
+    /// Of those, events found by the non-blocking polls of `Config::spin`.
+    pub spun_events: u64,
+    /// Non-empty polls by number of events: bucket `i` counts polls with
+    /// `2^i` to `2^(i+1) - 1` events, the last one everything above.
+    pub events_per_poll: [u64; EVENTS_PER_POLL_BUCKETS],
+    /// Interest changes of clients and sockets.
+    pub reregisters: u64,
+    /// Listener events that found no connection to accept, e.g. because
+    /// another process sharing the listener took it.
+    pub empty_accepts: u64,
+    /// Time spent handling events and timers.
+    pub busy: Duration,
+    /// Time spent waiting in `Poll::poll`.
+    pub waiting: Duration,
+    /// Longest iteration, from a poll returning to the next poll.
+    pub max_iteration: Duration,
+    /// Iterations longer than `Config::stall_threshold`.
+    pub stalls: u64,
+}
+
+impl LoopStats {
+    pub fn record_poll(&mut self, events: usize) {
+        self.polls += 1;
+        if events == 0 {
+            self.empty_polls += 1;
+            return;
+        }
+        self.events += events as u64;
+        let bucket = (usize::BITS - 1 - events.leading_zeros()) as usize;
+        self.events_per_poll[bucket.min(EVENTS_PER_POLL_BUCKETS - 1)] += 1;
+    }
+}
+
+/// Most entries of `Config::listeners`, the size of `Stats::listeners`.
+pub const MAX_LISTENERS: usize = 16;
+
+/// Counters of one entry of `Config::listeners`.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ListenerStats {
+    pub connections: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// Connections refused because the server or the listener was full.
+    pub rejected: u64,
+    /// Clients closed by the admin `kick` and `kick-all` commands.
+    pub kicks: u64,
+}
+
+impl fmt::Display for ListenerStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} connections, {} bytes read, {} bytes written, {} rejected, {} kicked",
+            self.connections, self.bytes_read, self.bytes_written, self.rejected, self.kicks,
+        )
+    }
+}
+
+/// Number of buckets in `Stats::close_rtt`.
+pub const RTT_BUCKETS: usize = 16;
+
+/// Number of buckets in `Stats::queue_latency`.
+pub const QUEUE_LATENCY_BUCKETS: usize = 24;
+
+/// Counters shared by every listener of the event loop.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Stats {
+    pub tcp: TransportStats,
+    pub udp: TransportStats,
+    pub vsock: TransportStats,
+    pub seqpacket: TransportStats,
+    pub unix: TransportStats,
+    pub sctp: TransportStats,
+    pub pipe: TransportStats,
+    /// Counters of the entries of `Config::listeners`, by index, also
+    /// counted in `tcp`.
+    pub listeners: [ListenerStats; MAX_LISTENERS],
+    pub event_loop: LoopStats,
+    pub overflow: OverflowStats,
+    pub refusals: RefusalStats,
+    pub buffers: BufferStats,
+    pub zerocopy: ZeroCopyStats,
+    pub sequence: SequenceStats,
+    pub dtls: DtlsStats,
+    pub quic: QuicStats,
+    /// statsd pushes that couldn't be sent.
+    pub statsd_errors: u64,
+    /// Clients disconnected through the admin socket.
+    pub admin_kicks: u64,
+    /// Connections refused because their address was banned.
+    pub banned: u64,
+    /// Connections put in the pending queue at capacity, and those of
+    /// them refused after waiting `Config::pending_timeout`, which count
+    /// as rejected too.
+    pub deferred: u64,
+    pub deferred_expired: u64,
+    /// Reloads of `Config::deny_file`, those that failed, and the clients
+    /// closed by `Config::deny_existing`.
+    pub deny_reloads: u64,
+    pub deny_reload_errors: u64,
+    pub deny_closes: u64,
+    /// Writes cut short by `Config::global_rate`.
+    pub throttled: u64,
+    /// Writes cut short by `Config::write_budget`.
+    pub write_parks: u64,
+    /// Percentage of `Config::global_rate` used over the last second or
+    /// so.
+    pub rate_utilization: u64,
+    /// Connections closed on input breaking the framing or failing
+    /// `Config::verify_checksum`.
+    pub corrupt: u64,
+    /// Connections closed on a failed TLS handshake, e.g. for a client
+    /// certificate missing or not issued by `Config::tls_client_ca`.
+    pub tls_handshake_failures: u64,
+    /// Connections closed for sending nothing within
+    /// `Config::first_byte_timeout`.
+    pub silent: u64,
+    /// Connections closed for sending nothing for `Config::idle_timeout`.
+    pub idle_closes: u64,
+    /// The limits in force, which a `ServerHandle` may change while the
+    /// server runs: the most clients served at once, `Config::idle_timeout`
+    /// and `Config::global_rate`.
+    pub max_clients: usize,
+    pub idle_timeout: Option<Duration>,
+    pub global_rate: Option<u64>,
+    /// Clients closed to get down to a lowered `max_clients`, see
+    /// `ServerHandle::set_max_clients`.
+    pub evictions: u64,
+    /// TCP listeners closed to be bound again after accept kept failing on
+    /// them.
+    pub listener_restarts: u64,
+    /// Times a listener stopped accepting for a while, out of descriptors
+    /// or memory.
+    pub accept_pauses: u64,
+    /// Panics of the `Handler`, each closing the client it was handling.
+    pub handler_panics: u64,
+    /// Connections the `Handler` closed, after their last reply with
+    /// `Action::ReplyThenClose` or at once with `Action::CloseNow`.
+    pub handler_closes: u64,
+    pub handler_aborts: u64,
+    /// Bytes of the `Config::annotate` headers written, not counted in the
+    /// bytes written.
+    pub annotation_bytes: u64,
+    /// Capture records dropped because the file writer lagged behind.
+    pub capture_dropped: u64,
+    /// Access log entries dropped because the file writer lagged behind.
+    pub access_log_dropped: u64,
+    /// Bytes not forwarded to `Config::mirror` because its connection
+    /// lagged behind or failed, and the mirror connections that failed.
+    pub mirror_dropped: u64,
+    pub mirror_errors: u64,
+    /// TCP connections by kernel measured RTT when they closed, Linux
+    /// only: bucket `i` counts RTTs of `2^i` to `2^(i+1) - 1` µs (bucket 0
+    /// from 0), the last one everything above.
+    pub close_rtt: [u64; RTT_BUCKETS],
+    /// How long the data of a read waited before its last byte was
+    /// written, mio backend only: bucket `i` counts waits of `2^i` to
+    /// `2^(i+1) - 1` µs (bucket 0 from 0), the last one everything above.
+    pub queue_latency: [u64; QUEUE_LATENCY_BUCKETS],
+    pub queue_latency_max: Duration,
+}
+
+impl Stats {
+    pub fn transport_mut(&mut self, transport: Transport) -> &mut TransportStats {
+        match transport {
+            Transport::Tcp => &mut self.tcp,
+            Transport::Udp => &mut self.udp,
+            Transport::Vsock => &mut self.vsock,
+            Transport::Seqpacket => &mut self.seqpacket,
+            Transport::Unix => &mut self.unix,
+            Transport::Sctp => &mut self.sctp,
+            Transport::Pipe => &mut self.pipe,
+        }
+    }
+
+    pub fn record_queue_latency(&mut self, latency: Duration) {
+        let micros = latency.as_micros().max(1);
+        let bucket = (u128::BITS - 1 - micros.leading_zeros()) as usize;
+        self.queue_latency[bucket.min(QUEUE_LATENCY_BUCKETS - 1)] += 1;
+        self.queue_latency_max = self.queue_latency_max.max(latency);
+    }
+
+    /// Upper bound of the `percentile` of `queue_latency`, `None` before
+    /// anything was recorded.
+    pub fn queue_latency_percentile(&self, percentile: f64) -> Option<Duration> {
+        let total: u64 = self.queue_latency.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let rank = ((total as f64 * percentile / 100.0).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, &count) in self.queue_latency.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let bound = Duration::from_micros(1 << (bucket + 1));
+                return Some(bound.min(self.queue_latency_max));
+            }
+        }
+        Some(self.queue_latency_max)
+    }
+
+    /// p50, p90, p99 and max of `queue_latency`.
+    pub fn queue_latency_summary(&self) -> Option<String> {
+        let p = |percentile| self.queue_latency_percentile(percentile).unwrap_or_default();
+        self.queue_latency_percentile(50.0).map(|p50| {
+            format!(
+                "p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+                p50,
+                p(90.0),
+                p(99.0),
+                self.queue_latency_max
+            )
+        })
+    }
+
+    pub fn record_rtt(&mut self, rtt: Duration) {
+        let micros = rtt.as_micros().max(1);
+        let bucket = (u128::BITS - 1 - micros.leading_zeros()) as usize;
+        self.close_rtt[bucket.min(RTT_BUCKETS - 1)] += 1;
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "tcp: {} connections, {} bytes read, {} bytes written, \
+             {} rejected, {} resets, {} timeouts, {} errors; \
+             udp: {} datagrams, {} bytes read, {} bytes written, {} dropped",
+            self.tcp.connections,
+            self.tcp.bytes_read,
+            self.tcp.bytes_written,
+            self.tcp.rejected,
+            self.tcp.resets,
+            self.tcp.timeouts,
+            self.tcp.errors,
+            self.udp.datagrams,
+            self.udp.bytes_read,
+            self.udp.bytes_written,
+            self.udp.dropped,
+        )?;
+        for (name, stats) in &[
+            ("vsock", &self.vsock),
+            ("seqpacket", &self.seqpacket),
+            ("unix", &self.unix),
+            ("sctp", &self.sctp),
+            ("pipe", &self.pipe),
+        ] {
+            if stats.connections > 0 {
+                write!(
+                    f,
+                    "; {}: {} connections, {} bytes read, {} bytes written, \
+                     {} rejected, {} resets, {} errors",
+                    name,
+                    stats.connections,
+                    stats.bytes_read,
+                    stats.bytes_written,
+                    stats.rejected,
+                    stats.resets,
+                    stats.errors,
+                )?;
+            }
+        }
+        for (index, stats) in self.listeners.iter().enumerate() {
+            if stats.connections > 0 || stats.rejected > 0 {
+                write!(f, "; listener {}: {}", index, stats)?;
+            }
+        }
+        let lp = &self.event_loop;
+        write!(
+            f,
+            "; loop: {} polls ({} empty), {} events ({} spun) {:?}, {} reregisters, {} empty accepts, {:?} busy, {:?} waiting, {:?} longest iteration, {} stalls",
+            lp.polls,
+            lp.empty_polls,
+            lp.events,
+            lp.spun_events,
+            lp.events_per_poll,
+            lp.reregisters,
+            lp.empty_accepts,
+            lp.busy,
+            lp.waiting,
+            lp.max_iteration,
+            lp.stalls,
+        )?;
+        if self.statsd_errors > 0 {
+            write!(f, "; statsd: {} send errors", self.statsd_errors)?;
+        }
+        if self.close_rtt.iter().any(|&n| n > 0) {
+            write!(f, "; close rtt: {:?}", self.close_rtt)?;
+        }
+        if let Some(summary) = self.queue_latency_summary() {
+            write!(f, "; queue latency: {}", summary)?;
+        }
+        if self.admin_kicks > 0 || self.banned > 0 {
+            write!(f, "; admin: {} kicks, {} banned", self.admin_kicks, self.banned)?;
+        }
+        let overflow = &self.overflow;
+        if overflow.pauses > 0 || overflow.dropped_newest > 0 || overflow.dropped_oldest > 0 || overflow.disconnects > 0 {
+            write!(
+                f,
+                "; overflow: {} pauses, {} newest dropped ({} bytes), {} oldest dropped ({} bytes), {} disconnects",
+                overflow.pauses,
+                overflow.dropped_newest,
+                overflow.dropped_newest_bytes,
+                overflow.dropped_oldest,
+                overflow.dropped_oldest_bytes,
+                overflow.disconnects,
+            )?;
+        }
+        let buffers = &self.buffers;
+        if buffers.reserved > 0 || buffers.shrinks > 0 {
+            write!(
+                f,
+                "; buffers: {} bytes reserved, {} used, {} shrinks",
+                buffers.reserved, buffers.used, buffers.shrinks,
+            )?;
+        }
+        let zerocopy = &self.zerocopy;
+        if zerocopy.writes > 0 || zerocopy.fallbacks > 0 || zerocopy.unsupported > 0 {
+            write!(
+                f,
+                "; zerocopy: {} writes ({} bytes), {} copied, {} fallbacks, {} unsupported, {} lingered, {} abandoned",
+                zerocopy.writes,
+                zerocopy.bytes,
+                zerocopy.copied,
+                zerocopy.fallbacks,
+                zerocopy.unsupported,
+                zerocopy.lingered,
+                zerocopy.abandoned,
+            )?;
+        }
+        let refusals = &self.refusals;
+        if refusals.total() > 0 {
+            write!(
+                f,
+                "; refusals: {} capacity, {} listener full, {} banned, {} denied, {} pending timeout, {} draining, {} not logged",
+                refusals.capacity,
+                refusals.listener_full,
+                refusals.banned,
+                refusals.denied,
+                refusals.pending_timeout,
+                refusals.draining,
+                refusals.unlogged,
+            )?;
+        }
+        if self.deny_reloads > 0 || self.deny_reload_errors > 0 {
+            write!(
+                f,
+                "; deny list: {} reloads, {} failed, {} clients closed",
+                self.deny_reloads, self.deny_reload_errors, self.deny_closes,
+            )?;
+        }
+        if self.deferred > 0 {
+            write!(f, "; pending queue: {} deferred, {} expired", self.deferred, self.deferred_expired)?;
+        }
+        if self.throttled > 0 || self.rate_utilization > 0 {
+            write!(f, "; rate cap: {}% used, {} throttled writes", self.rate_utilization, self.throttled)?;
+        }
+        if self.write_parks > 0 {
+            write!(f, "; write budget: {} parked writes", self.write_parks)?;
+        }
+        if self.corrupt > 0 {
+            write!(f, "; framing: {} corrupt connections", self.corrupt)?;
+        }
+        if self.tls_handshake_failures > 0 {
+            write!(f, "; tls: {} failed handshakes", self.tls_handshake_failures)?;
+        }
+        if self.silent > 0 {
+            write!(f, "; first byte timeout: {} silent connections", self.silent)?;
+        }
+        if self.idle_closes > 0 {
+            write!(f, "; idle timeout: {} idle connections", self.idle_closes)?;
+        }
+        if self.evictions > 0 {
+            write!(f, "; max clients: {} evicted", self.evictions)?;
+        }
+        let sequence = &self.sequence;
+        if sequence.sources > 0 || sequence.malformed > 0 {
+            write!(
+                f,
+                "; udp sequence: {} sources, {} lost, {} duplicates, {} reordered, {} malformed, {} untracked",
+                sequence.sources,
+                sequence.lost,
+                sequence.duplicates,
+                sequence.reorders,
+                sequence.malformed,
+                sequence.untracked,
+            )?;
+        }
+        let dtls = &self.dtls;
+        if dtls.handshakes > 0 || dtls.handshake_failures > 0 || dtls.strays > 0 {
+            write!(
+                f,
+                "; dtls: {} sessions, {} handshakes ({} failed), {} closed, {} errors, {} expired, {} evicted, {} strays",
+                dtls.sessions,
+                dtls.handshakes,
+                dtls.handshake_failures,
+                dtls.closed,
+                dtls.errors,
+                dtls.expired,
+                dtls.evicted,
+                dtls.strays,
+            )?;
+        }
+        let quic = &self.quic;
+        if quic.connections > 0 || quic.retries > 0 {
+            write!(
+                f,
+                "; quic: {} connections ({} active), {} retries, {} failed handshakes, {} closed, {} lost, {} streams, {} bytes read, {} bytes written, {} datagrams",
+                quic.connections,
+                quic.active,
+                quic.retries,
+                quic.handshake_failures,
+                quic.closed,
+                quic.lost,
+                quic.streams,
+                quic.bytes_read,
+                quic.bytes_written,
+                quic.datagrams,
+            )?;
+        }
+        if self.listener_restarts > 0 || self.accept_pauses > 0 {
+            write!(
+                f,
+                "; listeners: {} restarts, {} accept pauses",
+                self.listener_restarts, self.accept_pauses
+            )?;
+        }
+        if self.handler_panics > 0 || self.handler_closes > 0 || self.handler_aborts > 0 {
+            write!(
+                f,
+                "; handler: {} panics, {} closes, {} aborts",
+                self.handler_panics, self.handler_closes, self.handler_aborts
+            )?;
+        }
+        if self.annotation_bytes > 0 {
+            write!(f, "; annotations: {} header bytes", self.annotation_bytes)?;
+        }
+        if self.capture_dropped > 0 {
+            write!(f, "; capture: {} records dropped", self.capture_dropped)?;
+        }
+        if self.access_log_dropped > 0 {
+            write!(f, "; access log: {} entries dropped", self.access_log_dropped)?;
+        }
+        if self.mirror_dropped > 0 || self.mirror_errors > 0 {
+            write!(f, "; mirror: {} bytes dropped, {} failed connections", self.mirror_dropped, self.mirror_errors)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+//! Pushes the server stats to a statsd agent.
+
+use std::fmt::Write;
+use std::net::SocketAddr;
+
+use mio::net::UdpSocket;
+
+use crate::stats::Stats;
+use crate::Error;
+
+/// Non-blocking UDP socket sending one datagram of metrics per push.
+///
+/// Counters are sent as the change since the previous push, gauges as
+/// their current value.
+pub struct Statsd {
+    sock: UdpSocket,
+    prefix: String,
+    sent: Totals,
+    packet: String,
+}
+
+// Counter values as of the previous push
+#[derive(Clone, Copy, Default)]
+struct Totals {
+    accepted: u64,
+    bytes_read: u64,
+    bytes_written: u64,
+    rejected: u64,
+}
+
+impl Totals {
+    fn of(stats: &Stats) -> Totals {
+        let mut totals = Totals::default();
+        for transport in &[stats.tcp, stats.udp, stats.vsock, stats.seqpacket, stats.unix, stats.sctp, stats.pipe] {
+            totals.accepted += transport.connections;
+            totals.bytes_read += transport.bytes_read;
+            totals.bytes_written += transport.bytes_written;
+            totals.rejected += transport.rejected;
+        }
+        totals
+    }
+}
+
+impl Statsd {
+    pub fn new(addr: &str, prefix: &str) -> Result<Statsd, Error> {
+        let addr: SocketAddr = addr.parse()?;
+        let local = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let sock = UdpSocket::bind(local.parse()?)?;
+        sock.connect(addr)?;
+        Ok(Statsd {
+            sock,
+            prefix: prefix.to_string(),
+            sent: Totals::default(),
+            packet: String::new(),
+        })
+    }
+
+    /// Sends the metrics, returns false if the datagram couldn't be sent.
+    pub fn push(&mut self, stats: &Stats, active: usize, queued_bytes: usize) -> bool {
+        let totals = Totals::of(stats);
+        let prefix = &self.prefix;
+        let packet = &mut self.packet;
+        packet.clear();
+
+        let mut gauge = |name: &str, value: u64| {
+            let _ = writeln!(packet, "{}.{}:{}|g", prefix, name, value);
+        };
+        gauge("connections.active", active as u64);
+        gauge("connections.max", stats.max_clients as u64);
+        gauge("queued_bytes", queued_bytes as u64);
+        gauge("buffers.reserved", stats.buffers.reserved);
+        gauge("buffers.used", stats.buffers.used);
+
+        let sent = self.sent;
+        let mut counter = |name: &str, value: u64| {
+            let _ = writeln!(packet, "{}.{}:{}|c", prefix, name, value);
+        };
+        counter("connections.accepted", totals.accepted - sent.accepted);
+        counter("bytes.read", totals.bytes_read - sent.bytes_read);
+        counter("bytes.written", totals.bytes_written - sent.bytes_written);
+        counter("rejected", totals.rejected - sent.rejected);
+
+        // A lost datagram loses its counts too, statsd is lossy anyway
+        self.sent = totals;
+        match self.sock.send(packet.trim_end().as_bytes()) {
+            Ok(len) => len == packet.trim_end().len(),
+            Err(_) => false,
+        }
+    }
+}
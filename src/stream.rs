@@ -0,0 +1,349 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use mio::event::Source;
+use mio::net::{TcpListener, TcpStream};
+#[cfg(windows)]
+use mio::windows::NamedPipe;
+use mio::{Interest, Registry, Token};
+
+#[cfg(all(target_os = "linux", feature = "sctp"))]
+use crate::sctp::{SctpListener, SctpStream};
+#[cfg(target_os = "linux")]
+use crate::seqpacket::{SeqpacketListener, SeqpacketStream};
+#[cfg(feature = "tls")]
+use crate::tls::TlsStream;
+#[cfg(target_os = "linux")]
+use crate::unix::{UnixListener, UnixStream};
+#[cfg(all(target_os = "linux", feature = "vsock"))]
+use crate::vsock::{VsockListener, VsockStream};
+
+/// Identifies the peer of a connection in logs.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PeerAddr {
+    Inet(SocketAddr),
+    #[cfg(all(target_os = "linux", feature = "vsock"))]
+    Vsock { cid: u32, port: u32 },
+    #[cfg(target_os = "linux")]
+    Unix { pid: i32, uid: u32 },
+    /// Numbered in the order the pipe instances were created.
+    #[cfg(windows)]
+    Pipe { instance: u64 },
+}
+
+impl fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PeerAddr::Inet(ref addr) => addr.fmt(f),
+            #[cfg(all(target_os = "linux", feature = "vsock"))]
+            PeerAddr::Vsock { cid, port } => write!(f, "vsock:{}:{}", cid, port),
+            #[cfg(target_os = "linux")]
+            PeerAddr::Unix { pid, uid } => write!(f, "unix:pid={},uid={}", pid, uid),
+            #[cfg(windows)]
+            PeerAddr::Pipe { instance } => write!(f, "pipe:{}", instance),
+        }
+    }
+}
+
+/// The peer of a connection with its host name when known, as `host
+/// (ip:port)`, see `Config::resolve_peers`.
+pub struct Peer<'a> {
+    pub addr: PeerAddr,
+    pub name: Option<&'a str>,
+}
+
+impl fmt::Display for Peer<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name {
+            Some(name) => write!(f, "{} ({})", name, self.addr),
+            None => self.addr.fmt(f),
+        }
+    }
+}
+
+/// What the kernel measured of a TCP connection.
+#[derive(Clone, Copy, Debug)]
+pub struct TcpInfo {
+    pub rtt: Duration,
+    pub rttvar: Duration,
+    /// Segments retransmitted over the whole connection.
+    pub retransmits: u32,
+    /// Bytes per second, `None` before Linux 4.9.
+    pub delivery_rate: Option<u64>,
+}
+
+impl fmt::Display for TcpInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "rtt {:?}, rttvar {:?}, {} retransmits",
+            self.rtt, self.rttvar, self.retransmits
+        )?;
+        if let Some(rate) = self.delivery_rate {
+            write!(f, ", {} B/s delivered", rate)?;
+        }
+        Ok(())
+    }
+}
+
+/// A connected socket of any supported transport.
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<TlsStream>),
+    #[cfg(all(target_os = "linux", feature = "vsock"))]
+    Vsock(VsockStream),
+    #[cfg(target_os = "linux")]
+    Seqpacket(SeqpacketStream),
+    #[cfg(target_os = "linux")]
+    Unix(UnixStream),
+    #[cfg(all(target_os = "linux", feature = "sctp"))]
+    Sctp(SctpStream),
+    #[cfg(windows)]
+    Pipe(NamedPipe),
+}
+
+/// What a client needs from its connected socket.
+pub trait Socket: Read + Write + Source {
+    /// Whether every read returns one message to be echoed as one write.
+    fn is_packet(&self) -> bool {
+        false
+    }
+
+    /// TCP_INFO of a TCP connection on Linux, `None` anywhere else.
+    fn tcp_info(&self) -> Option<TcpInfo> {
+        None
+    }
+
+    /// Whether the socket holds output of its own, such as TLS records,
+    /// that `flush` writes once it is writable.
+    fn wants_write(&self) -> bool {
+        false
+    }
+
+    /// Subject and alternative names of the certificate the peer
+    /// authenticated with, see `Config::tls_client_ca`.
+    fn peer_cert(&self) -> Option<&str> {
+        None
+    }
+
+    /// Receives one message of a packet stream, `None` at end of stream.
+    fn recv_packet(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        self.read(buf).map(|len| if len == 0 { None } else { Some(len) })
+    }
+
+    /// Allows `write_zerocopy`, TCP on Linux with the `zerocopy` feature
+    /// only.
+    fn enable_zerocopy(&self) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Writes with MSG_ZEROCOPY: the kernel reads `buf` until
+    /// `zerocopy_done` reports the write done. `None` means the kernel
+    /// couldn't take it that way now, it is to be written as usual.
+    fn write_zerocopy(&mut self, _buf: &[u8]) -> io::Result<Option<usize>> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Passes the ranges of zerocopy writes the kernel reported done, and
+    /// whether it copied their data after all.
+    fn zerocopy_done(&mut self, _done: &mut dyn FnMut(u32, u32, bool)) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Socket for Stream {
+    fn is_packet(&self) -> bool {
+        match *self {
+            #[cfg(target_os = "linux")]
+            Stream::Seqpacket(_) => true,
+            #[cfg(all(target_os = "linux", feature = "sctp"))]
+            Stream::Sctp(_) => true,
+            _ => false,
+        }
+    }
+
+    fn tcp_info(&self) -> Option<TcpInfo> {
+        match *self {
+            #[cfg(target_os = "linux")]
+            Stream::Tcp(ref sock) => crate::sys::tcp_info(sock).ok(),
+            #[cfg(all(target_os = "linux", feature = "tls"))]
+            Stream::Tls(ref sock) => crate::sys::tcp_info(sock.tcp()).ok(),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+
+    fn wants_write(&self) -> bool {
+        match *self {
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref sock) => sock.wants_write(),
+            _ => false,
+        }
+    }
+
+    fn peer_cert(&self) -> Option<&str> {
+        match *self {
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref sock) => sock.peer_cert(),
+            _ => None,
+        }
+    }
+
+    fn recv_packet(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        match self {
+            #[cfg(target_os = "linux")]
+            Stream::Seqpacket(sock) => sock.recv_packet(buf),
+            #[cfg(all(target_os = "linux", feature = "sctp"))]
+            Stream::Sctp(sock) => sock.recv_packet(buf),
+            _ => self.read(buf).map(|len| if len == 0 { None } else { Some(len) }),
+        }
+    }
+
+    fn enable_zerocopy(&self) -> io::Result<()> {
+        match *self {
+            #[cfg(all(target_os = "linux", feature = "zerocopy"))]
+            Stream::Tcp(ref sock) => crate::sys::set_zerocopy(sock),
+            #[allow(unreachable_patterns)]
+            _ => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+
+    fn write_zerocopy(&mut self, buf: &[u8]) -> io::Result<Option<usize>> {
+        match *self {
+            #[cfg(all(target_os = "linux", feature = "zerocopy"))]
+            Stream::Tcp(ref sock) => crate::sys::send_zerocopy(sock, buf),
+            #[allow(unreachable_patterns)]
+            _ => {
+                let _ = buf;
+                Err(io::ErrorKind::Unsupported.into())
+            }
+        }
+    }
+
+    fn zerocopy_done(&mut self, done: &mut dyn FnMut(u32, u32, bool)) -> io::Result<()> {
+        match *self {
+            #[cfg(all(target_os = "linux", feature = "zerocopy"))]
+            Stream::Tcp(ref sock) => crate::sys::zerocopy_done(sock, done),
+            #[allow(unreachable_patterns)]
+            _ => {
+                let _ = done;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Stream {
+    /// Local address of an IP socket.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match *self {
+            Stream::Tcp(ref sock) => sock.local_addr().ok(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref sock) => sock.tcp().local_addr().ok(),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+}
+
+macro_rules! delegate {
+    ($stream:expr, $sock:ident => $e:expr) => {
+        match $stream {
+            Stream::Tcp($sock) => $e,
+            #[cfg(feature = "tls")]
+            Stream::Tls($sock) => $e,
+            #[cfg(all(target_os = "linux", feature = "vsock"))]
+            Stream::Vsock($sock) => $e,
+            #[cfg(target_os = "linux")]
+            Stream::Seqpacket($sock) => $e,
+            #[cfg(target_os = "linux")]
+            Stream::Unix($sock) => $e,
+            #[cfg(all(target_os = "linux", feature = "sctp"))]
+            Stream::Sctp($sock) => $e,
+            #[cfg(windows)]
+            Stream::Pipe($sock) => $e,
+        }
+    };
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        delegate!(self, sock => sock.read(buf))
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        delegate!(self, sock => sock.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        delegate!(self, sock => sock.flush())
+    }
+}
+
+impl Source for Stream {
+    fn register(&mut self, registry: &Registry, token: Token, interest: Interest) -> io::Result<()> {
+        match self {
+            // Registered by the listener already, only the token changes
+            #[cfg(windows)]
+            Stream::Pipe(sock) => sock.reregister(registry, token, interest),
+            _ => delegate!(self, sock => sock.register(registry, token, interest)),
+        }
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: Token, interest: Interest) -> io::Result<()> {
+        delegate!(self, sock => sock.reregister(registry, token, interest))
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        delegate!(self, sock => sock.deregister(registry))
+    }
+}
+
+/// A listening socket handing out `Stream`s.
+pub trait Listener: Source {
+    fn accept_stream(&self) -> io::Result<(Stream, PeerAddr)>;
+}
+
+impl Listener for TcpListener {
+    fn accept_stream(&self) -> io::Result<(Stream, PeerAddr)> {
+        let (sock, addr) = self.accept()?;
+        Ok((Stream::Tcp(sock), PeerAddr::Inet(addr)))
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "vsock"))]
+impl Listener for VsockListener {
+    fn accept_stream(&self) -> io::Result<(Stream, PeerAddr)> {
+        let (sock, (cid, port)) = self.accept()?;
+        Ok((Stream::Vsock(sock), PeerAddr::Vsock { cid, port }))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Listener for SeqpacketListener {
+    fn accept_stream(&self) -> io::Result<(Stream, PeerAddr)> {
+        let (sock, (pid, uid)) = self.accept()?;
+        Ok((Stream::Seqpacket(sock), PeerAddr::Unix { pid, uid }))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Listener for UnixListener {
+    fn accept_stream(&self) -> io::Result<(Stream, PeerAddr)> {
+        let (sock, (pid, uid)) = self.accept()?;
+        Ok((Stream::Unix(sock), PeerAddr::Unix { pid, uid }))
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "sctp"))]
+impl Listener for SctpListener {
+    fn accept_stream(&self) -> io::Result<(Stream, PeerAddr)> {
+        let (sock, addr) = self.accept()?;
+        Ok((Stream::Sctp(sock), PeerAddr::Inet(addr)))
+    }
+}
@@ -0,0 +1,559 @@
+//! Helpers for sockets driven through raw file descriptors.
+
+use std::ffi::CStr;
+use std::fs;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::Path;
+use std::ptr;
+use std::time::Duration;
+
+use crate::stream::TcpInfo;
+
+/// Turns a negative libc return value into the current `errno`.
+pub fn cvt(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+fn cvt_size(ret: libc::ssize_t) -> io::Result<usize> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret as usize)
+    }
+}
+
+/// Sets TCP_USER_TIMEOUT, the longest sent data may stay unacknowledged.
+pub fn set_tcp_user_timeout<S: AsRawFd>(sock: &S, timeout: Duration) -> io::Result<()> {
+    let millis = timeout.as_millis().min(libc::c_uint::MAX as u128) as libc::c_uint;
+    cvt(unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_USER_TIMEOUT,
+            &millis as *const libc::c_uint as *const libc::c_void,
+            mem::size_of::<libc::c_uint>() as libc::socklen_t,
+        )
+    })
+    .map(drop)
+}
+
+/// Sets TCP_DEFER_ACCEPT, how long a listener waits for the first data
+/// of a connection before accepting it anyway.
+pub fn set_tcp_defer_accept<S: AsRawFd>(sock: &S, timeout: Duration) -> io::Result<()> {
+    let secs = timeout.as_secs().min(libc::c_int::MAX as u64) as libc::c_int;
+    cvt(unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_DEFER_ACCEPT,
+            &secs as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    })
+    .map(drop)
+}
+
+/// Binds a socket of type `ty` to `addr` with IP_FREEBIND, or
+/// IPV6_FREEBIND, set first, so the address needn't be configured yet.
+/// Stream sockets are also made to listen.
+#[cfg(target_os = "linux")]
+pub fn bind_freebind(addr: &SocketAddr, ty: libc::c_int) -> io::Result<OwnedFd> {
+    let (family, level, opt) = match addr {
+        SocketAddr::V4(_) => (libc::AF_INET, libc::IPPROTO_IP, libc::IP_FREEBIND),
+        SocketAddr::V6(_) => (libc::AF_INET6, libc::IPPROTO_IPV6, libc::IPV6_FREEBIND),
+    };
+    let fd = cvt(unsafe { libc::socket(family, ty | libc::SOCK_CLOEXEC, 0) })?;
+    let sock = unsafe { OwnedFd::from_raw_fd(fd) };
+    let enable = |level, opt| {
+        let on: libc::c_int = 1;
+        cvt(unsafe {
+            libc::setsockopt(
+                fd,
+                level,
+                opt,
+                &on as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        })
+    };
+    // As the usual binds of listeners do
+    if ty == libc::SOCK_STREAM {
+        enable(libc::SOL_SOCKET, libc::SO_REUSEADDR)?;
+    }
+    enable(level, opt)?;
+    let (storage, len) = sockaddr(addr);
+    cvt(unsafe { libc::bind(fd, &storage as *const libc::sockaddr_storage as *const libc::sockaddr, len) })?;
+    if ty == libc::SOCK_STREAM {
+        cvt(unsafe { libc::listen(fd, 1024) })?;
+    }
+    Ok(sock)
+}
+
+/// Where a connection redirected by netfilter, e.g. by an iptables
+/// REDIRECT rule, was originally headed: SO_ORIGINAL_DST, or
+/// IP6T_SO_ORIGINAL_DST for IPv6. Fails with ENOENT if netfilter doesn't
+/// track the connection.
+#[cfg(target_os = "linux")]
+pub fn original_dst<S: AsRawFd>(sock: &S, ipv6: bool) -> io::Result<SocketAddr> {
+    let (level, opt) = if ipv6 {
+        (libc::SOL_IPV6, libc::IP6T_SO_ORIGINAL_DST)
+    } else {
+        (libc::SOL_IP, libc::SO_ORIGINAL_DST)
+    };
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    cvt(unsafe {
+        libc::getsockopt(
+            sock.as_raw_fd(),
+            level,
+            opt,
+            &mut storage as *mut libc::sockaddr_storage as *mut libc::c_void,
+            &mut len,
+        )
+    })?;
+    socket_addr(&storage).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unknown address family"))
+}
+
+// The start of the kernel's `struct tcp_info`, up to the delivery rate
+// (Linux 4.9); older kernels fill in less of it
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)] // Only some fields are read, the rest is layout
+struct RawTcpInfo {
+    tcpi_state: u8,
+    tcpi_ca_state: u8,
+    tcpi_retransmits: u8,
+    tcpi_probes: u8,
+    tcpi_backoff: u8,
+    tcpi_options: u8,
+    tcpi_wscale: u8,
+    tcpi_flags: u8,
+    tcpi_rto: u32,
+    tcpi_ato: u32,
+    tcpi_snd_mss: u32,
+    tcpi_rcv_mss: u32,
+    tcpi_unacked: u32,
+    tcpi_sacked: u32,
+    tcpi_lost: u32,
+    tcpi_retrans: u32,
+    tcpi_fackets: u32,
+    tcpi_last_data_sent: u32,
+    tcpi_last_ack_sent: u32,
+    tcpi_last_data_recv: u32,
+    tcpi_last_ack_recv: u32,
+    tcpi_pmtu: u32,
+    tcpi_rcv_ssthresh: u32,
+    tcpi_rtt: u32,
+    tcpi_rttvar: u32,
+    tcpi_snd_ssthresh: u32,
+    tcpi_snd_cwnd: u32,
+    tcpi_advmss: u32,
+    tcpi_reordering: u32,
+    tcpi_rcv_rtt: u32,
+    tcpi_rcv_space: u32,
+    tcpi_total_retrans: u32,
+    tcpi_pacing_rate: u64,
+    tcpi_max_pacing_rate: u64,
+    tcpi_bytes_acked: u64,
+    tcpi_bytes_received: u64,
+    tcpi_segs_out: u32,
+    tcpi_segs_in: u32,
+    tcpi_notsent_bytes: u32,
+    tcpi_min_rtt: u32,
+    tcpi_data_segs_in: u32,
+    tcpi_data_segs_out: u32,
+    tcpi_delivery_rate: u64,
+}
+
+/// Sets SO_BUSY_POLL, how long a read with nothing received busy polls
+/// the device queue.
+pub fn set_busy_poll<S: AsRawFd>(sock: &S, duration: Duration) -> io::Result<()> {
+    let micros = duration.as_micros().min(libc::c_int::MAX as u128) as libc::c_int;
+    cvt(unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BUSY_POLL,
+            &micros as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    })
+    .map(drop)
+}
+
+#[cfg(feature = "zerocopy")]
+/// Sets SO_ZEROCOPY, which MSG_ZEROCOPY sends need. Fails with
+/// `ENOPROTOOPT` before Linux 4.14.
+pub fn set_zerocopy<S: AsRawFd>(sock: &S) -> io::Result<()> {
+    let on: libc::c_int = 1;
+    cvt(unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_ZEROCOPY,
+            &on as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    })
+    .map(drop)
+}
+
+#[cfg(feature = "zerocopy")]
+/// Sends `buf` with MSG_ZEROCOPY, see `zerocopy_done`. The kernel may
+/// read `buf` until then. `None` means it couldn't pin more memory
+/// (`ENOBUFS`), the data is then to be written as usual.
+pub fn send_zerocopy<S: AsRawFd>(sock: &S, buf: &[u8]) -> io::Result<Option<usize>> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    match cvt_size(unsafe { libc::sendmsg(sock.as_raw_fd(), &msg, libc::MSG_ZEROCOPY) }) {
+        Ok(len) => Ok(Some(len)),
+        Err(ref e) if e.raw_os_error() == Some(libc::ENOBUFS) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// From linux/errqueue.h, missing from libc
+#[cfg(feature = "zerocopy")]
+const SO_EE_ORIGIN_ZEROCOPY: u8 = 5;
+#[cfg(feature = "zerocopy")]
+const SO_EE_CODE_ZEROCOPY_COPIED: u8 = 1;
+
+#[cfg(feature = "zerocopy")]
+/// Reads the MSG_ZEROCOPY completions off the error queue, passing each
+/// range of sends done and whether the kernel copied their data after
+/// all.
+pub fn zerocopy_done<S: AsRawFd>(sock: &S, done: &mut dyn FnMut(u32, u32, bool)) -> io::Result<()> {
+    // Room for a few notifications, each one a cmsghdr and a
+    // sock_extended_err
+    let mut control = [0u64; 16];
+    loop {
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = mem::size_of_val(&control) as _;
+        match cvt_size(unsafe { libc::recvmsg(sock.as_raw_fd(), &mut msg, libc::MSG_ERRQUEUE) }) {
+            Ok(_) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+        while let Some(header) = unsafe { cmsg.as_ref() } {
+            let recverr = (header.cmsg_level, header.cmsg_type) == (libc::SOL_IP, libc::IP_RECVERR)
+                || (header.cmsg_level, header.cmsg_type) == (libc::SOL_IPV6, libc::IPV6_RECVERR);
+            if recverr {
+                let err = unsafe { (libc::CMSG_DATA(cmsg) as *const libc::sock_extended_err).read_unaligned() };
+                if err.ee_errno == 0 && err.ee_origin == SO_EE_ORIGIN_ZEROCOPY {
+                    done(err.ee_info, err.ee_data, err.ee_code & SO_EE_CODE_ZEROCOPY_COPIED != 0);
+                }
+            }
+            cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+        }
+    }
+}
+
+/// Registers a listener with EPOLLEXCLUSIVE, so that of the epoll
+/// instances it is registered with, typically one per process sharing it,
+/// only one is woken per incoming connection. Fails with `EINVAL` before
+/// Linux 4.5. The listener must not be reregistered afterwards, which
+/// EPOLLEXCLUSIVE doesn't allow. mio doesn't know of the registration,
+/// deregister it through a `mio::unix::SourceFd`.
+pub fn register_exclusive<S: AsRawFd>(registry: &mio::Registry, listener: &S, token: mio::Token) -> io::Result<()> {
+    let mut event = libc::epoll_event {
+        events: (libc::EPOLLIN | libc::EPOLLET | libc::EPOLLEXCLUSIVE) as u32,
+        // What mio expects to find in the events it reads
+        u64: usize::from(token) as u64,
+    };
+    cvt(unsafe { libc::epoll_ctl(registry.as_raw_fd(), libc::EPOLL_CTL_ADD, listener.as_raw_fd(), &mut event) }).map(drop)
+}
+
+/// Reads TCP_INFO, keeping only the fields the kernel actually filled in.
+pub fn tcp_info<S: AsRawFd>(sock: &S) -> io::Result<TcpInfo> {
+    let mut raw = RawTcpInfo::default();
+    let mut len = mem::size_of::<RawTcpInfo>() as libc::socklen_t;
+    cvt(unsafe {
+        libc::getsockopt(
+            sock.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut raw as *mut RawTcpInfo as *mut libc::c_void,
+            &mut len,
+        )
+    })?;
+
+    let filled = |offset: usize, size: usize| len as usize >= offset + size;
+    if !filled(mem::offset_of!(RawTcpInfo, tcpi_total_retrans), 4) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "short TCP_INFO"));
+    }
+    Ok(TcpInfo {
+        rtt: Duration::from_micros(raw.tcpi_rtt.into()),
+        rttvar: Duration::from_micros(raw.tcpi_rttvar.into()),
+        retransmits: raw.tcpi_total_retrans,
+        delivery_rate: if filled(mem::offset_of!(RawTcpInfo, tcpi_delivery_rate), 8) {
+            Some(raw.tcpi_delivery_rate)
+        } else {
+            None
+        },
+    })
+}
+
+/// `addr` as a C socket address, and its length.
+pub fn sockaddr(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match *addr {
+        SocketAddr::V4(ref addr) => {
+            let sin = unsafe { &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = addr.port().to_be();
+            sin.sin_addr.s_addr = u32::from(*addr.ip()).to_be();
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(ref addr) => {
+            let sin6 = unsafe { &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = addr.port().to_be();
+            sin6.sin6_addr.s6_addr = addr.ip().octets();
+            sin6.sin6_flowinfo = addr.flowinfo();
+            sin6.sin6_scope_id = addr.scope_id();
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
+
+/// The IP address of a C socket address, if it is one.
+pub fn socket_addr(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+    match storage.ss_family as libc::c_int {
+        libc::AF_INET => {
+            let sin = unsafe { &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in) };
+            let ip = Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr));
+            Some(SocketAddr::V4(SocketAddrV4::new(ip, u16::from_be(sin.sin_port))))
+        }
+        libc::AF_INET6 => {
+            let sin6 = unsafe { &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in6) };
+            let ip = Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+            Some(SocketAddr::V6(SocketAddrV6::new(
+                ip,
+                u16::from_be(sin6.sin6_port),
+                sin6.sin6_flowinfo,
+                sin6.sin6_scope_id,
+            )))
+        }
+        _ => None,
+    }
+}
+
+/// Buffers and addresses for receiving datagrams with one recvmmsg and
+/// sending them with one sendmmsg, allocated once.
+pub struct Batch {
+    bufs: Vec<Vec<u8>>,
+    addrs: Vec<libc::sockaddr_storage>,
+    iovecs: Vec<libc::iovec>,
+    msgs: Vec<libc::mmsghdr>,
+}
+
+// The pointers of the headers only ever point into the batch itself, and
+// are set again before every call
+unsafe impl Send for Batch {}
+
+impl Batch {
+    /// Room for `count` datagrams of up to `size` bytes.
+    pub fn new(count: usize, size: usize) -> Batch {
+        Batch {
+            bufs: vec![vec![0; size]; count],
+            addrs: vec![unsafe { mem::zeroed() }; count],
+            iovecs: vec![unsafe { mem::zeroed() }; count],
+            msgs: vec![unsafe { mem::zeroed() }; count],
+        }
+    }
+
+    // Points message `i` at address `i` and at `buf`
+    fn prepare(&mut self, i: usize, buf: *mut u8, len: usize, addr_len: libc::socklen_t) {
+        self.iovecs[i] = libc::iovec {
+            iov_base: buf as *mut libc::c_void,
+            iov_len: len,
+        };
+        let msg = &mut self.msgs[i];
+        msg.msg_hdr = unsafe { mem::zeroed() };
+        msg.msg_hdr.msg_name = &mut self.addrs[i] as *mut libc::sockaddr_storage as *mut libc::c_void;
+        msg.msg_hdr.msg_namelen = addr_len;
+        msg.msg_hdr.msg_iov = &mut self.iovecs[i];
+        msg.msg_hdr.msg_iovlen = 1;
+        msg.msg_len = 0;
+    }
+
+    /// Receives the datagrams waiting, as many as fit, returns how many.
+    pub fn recv<S: AsRawFd>(&mut self, sock: &S) -> io::Result<usize> {
+        for i in 0..self.msgs.len() {
+            let (buf, len) = (self.bufs[i].as_mut_ptr(), self.bufs[i].len());
+            self.prepare(i, buf, len, mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t);
+        }
+        let count = self.msgs.len() as libc::c_uint;
+        cvt(unsafe { libc::recvmmsg(sock.as_raw_fd(), self.msgs.as_mut_ptr(), count, 0, ptr::null_mut()) })
+            .map(|received| received as usize)
+    }
+
+    /// Datagram `i` of the last `recv`, with its sender.
+    pub fn received(&self, i: usize) -> (Option<SocketAddr>, &[u8]) {
+        (socket_addr(&self.addrs[i]), &self.bufs[i][..self.msgs[i].msg_len as usize])
+    }
+
+    /// Sends each datagram to its address, in order, as many as fit.
+    /// Returns how many were sent, the first failure being reported only
+    /// if it was the first datagram.
+    pub fn send<S: AsRawFd>(&mut self, sock: &S, datagrams: &[(SocketAddr, Vec<u8>)]) -> io::Result<usize> {
+        let count = datagrams.len().min(self.msgs.len());
+        for (i, (addr, buf)) in datagrams[..count].iter().enumerate() {
+            let (storage, addr_len) = sockaddr(addr);
+            self.addrs[i] = storage;
+            // Only read by the kernel
+            self.prepare(i, buf.as_ptr() as *mut u8, buf.len(), addr_len);
+        }
+        cvt(unsafe { libc::sendmmsg(sock.as_raw_fd(), self.msgs.as_mut_ptr(), count as libc::c_uint, 0) })
+            .map(|sent| sent as usize)
+    }
+
+    /// Bytes of datagram `i` the last `send` sent.
+    pub fn sent_len(&self, i: usize) -> usize {
+        self.msgs[i].msg_len as usize
+    }
+}
+
+pub fn recv_fd(fd: &OwnedFd, buf: &mut [u8], flags: libc::c_int) -> io::Result<usize> {
+    cvt_size(unsafe {
+        libc::recv(fd.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len(), flags)
+    })
+}
+
+pub fn send_fd(fd: &OwnedFd, buf: &[u8], flags: libc::c_int) -> io::Result<usize> {
+    cvt_size(unsafe {
+        libc::send(fd.as_raw_fd(), buf.as_ptr() as *const libc::c_void, buf.len(), flags)
+    })
+}
+
+/// A non-blocking Unix socket of type `ty` listening on `path`, replacing
+/// a socket left behind by a previous run.
+pub fn bind_unix(path: &Path, ty: libc::c_int) -> io::Result<OwnedFd> {
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    let bytes = path.as_os_str().as_bytes();
+    if bytes.len() >= addr.sun_path.len() || bytes.contains(&0) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid socket path"));
+    }
+    for (dst, src) in addr.sun_path.iter_mut().zip(bytes) {
+        *dst = *src as libc::c_char;
+    }
+
+    // A socket left behind by a previous run would make bind fail
+    if let Ok(meta) = fs::symlink_metadata(path) {
+        if meta.file_type().is_socket() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    let fd = cvt(unsafe { libc::socket(libc::AF_UNIX, ty | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC, 0) })?;
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    cvt(unsafe {
+        libc::bind(
+            fd.as_raw_fd(),
+            &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_un>() as libc::socklen_t,
+        )
+    })?;
+    if let Err(e) = cvt(unsafe { libc::listen(fd.as_raw_fd(), 1024) }) {
+        let _ = fs::remove_file(path);
+        return Err(e);
+    }
+    Ok(fd)
+}
+
+/// Accepts a connection on a Unix listener, returning it with the peer's
+/// (pid, uid).
+pub fn accept_unix(listener: &OwnedFd) -> io::Result<(OwnedFd, (i32, u32))> {
+    let fd = cvt(unsafe {
+        libc::accept4(
+            listener.as_raw_fd(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+        )
+    })?;
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    // Unix peers are unnamed, their credentials identify them better
+    let mut cred: libc::ucred = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    cvt(unsafe {
+        libc::getsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    })?;
+    Ok((fd, (cred.pid, cred.uid)))
+}
+
+/// Implements `mio::event::Source` and `AsRawFd` for a struct with an
+/// `fd: OwnedFd`.
+macro_rules! evented_fd {
+    ($t:ty) => {
+        impl mio::event::Source for $t {
+            fn register(&mut self, registry: &mio::Registry, token: mio::Token, interest: mio::Interest) -> std::io::Result<()> {
+                mio::unix::SourceFd(&std::os::unix::io::AsRawFd::as_raw_fd(&self.fd)).register(registry, token, interest)
+            }
+
+            fn reregister(&mut self, registry: &mio::Registry, token: mio::Token, interest: mio::Interest) -> std::io::Result<()> {
+                mio::unix::SourceFd(&std::os::unix::io::AsRawFd::as_raw_fd(&self.fd)).reregister(registry, token, interest)
+            }
+
+            fn deregister(&mut self, registry: &mio::Registry) -> std::io::Result<()> {
+                mio::unix::SourceFd(&std::os::unix::io::AsRawFd::as_raw_fd(&self.fd)).deregister(registry)
+            }
+        }
+
+        impl std::os::unix::io::AsRawFd for $t {
+            fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+                std::os::unix::io::AsRawFd::as_raw_fd(&self.fd)
+            }
+        }
+    };
+}
+
+pub(crate) use evented_fd;
+
+/// The host name of `ip` by reverse DNS, `None` without one. Blocks for
+/// as long as the system resolver takes.
+pub fn reverse_lookup(ip: IpAddr) -> Option<String> {
+    let (storage, len) = sockaddr(&SocketAddr::new(ip, 0));
+    let mut host = [0 as libc::c_char; 1025];
+    let ret = unsafe {
+        libc::getnameinfo(
+            &storage as *const libc::sockaddr_storage as *const libc::sockaddr,
+            len,
+            host.as_mut_ptr(),
+            host.len() as libc::socklen_t,
+            ptr::null_mut(),
+            0,
+            libc::NI_NAMEREQD,
+        )
+    };
+    if ret != 0 {
+        return None;
+    }
+    let host = unsafe { CStr::from_ptr(host.as_ptr()) };
+    host.to_str().ok().map(str::to_string)
+}
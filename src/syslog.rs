@@ -0,0 +1,149 @@
+//! Log records sent to a syslog daemon.
+
+use std::net::{SocketAddr, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::Level;
+
+use crate::access_log::{civil_date, rfc3339};
+use crate::config::SyslogTarget;
+use crate::Error;
+
+const APP_NAME: &str = "mio-echo-server";
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+enum Sock {
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+    Udp(UdpSocket),
+}
+
+/// Sends log records as RFC 5424 messages, or RFC 3164 ones for older
+/// daemons, without ever blocking: what the socket can't take right away
+/// is dropped and counted.
+pub struct Syslog {
+    sock: Sock,
+    facility: u8,
+    rfc3164: bool,
+    hostname: String,
+    pid: u32,
+    dropped: AtomicU64,
+}
+
+impl Syslog {
+    /// Connects to the daemon of `target`, messages carry `facility`.
+    pub fn connect(target: &SyslogTarget, facility: u8, rfc3164: bool) -> Result<Syslog, Error> {
+        let sock = match *target {
+            #[cfg(unix)]
+            SyslogTarget::Unix(ref path) => {
+                let sock = UnixDatagram::unbound()?;
+                sock.connect(path)
+                    .map_err(|e| Error::peer(format!("syslog {}", path.display()), e))?;
+                sock.set_nonblocking(true)?;
+                Sock::Unix(sock)
+            }
+            #[cfg(not(unix))]
+            SyslogTarget::Unix(_) => return Err(Error::config("syslog over a Unix socket is only supported on Unix")),
+            SyslogTarget::Udp(ref addr) => {
+                let addr: SocketAddr = addr.parse()?;
+                let local = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+                let sock = UdpSocket::bind(local)?;
+                sock.connect(addr)?;
+                sock.set_nonblocking(true)?;
+                Sock::Udp(sock)
+            }
+        };
+        Ok(Syslog {
+            sock,
+            facility,
+            rfc3164,
+            hostname: hostname(),
+            pid: process::id(),
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// Messages that couldn't be sent.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn send(&self, level: Level, message: &str) {
+        let severity = match level {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        };
+        let pri = u32::from(self.facility) * 8 + severity;
+        let now = SystemTime::now();
+        let packet = if self.rfc3164 {
+            format!(
+                "<{}>{} {} {}[{}]: {}",
+                pri,
+                bsd_timestamp(now),
+                self.hostname,
+                APP_NAME,
+                self.pid,
+                message
+            )
+        } else {
+            format!(
+                "<{}>1 {} {} {} {} - - {}",
+                pri,
+                rfc3339(now),
+                self.hostname,
+                APP_NAME,
+                self.pid,
+                message
+            )
+        };
+        let sent = match self.sock {
+            #[cfg(unix)]
+            Sock::Unix(ref sock) => sock.send(packet.as_bytes()),
+            Sock::Udp(ref sock) => sock.send(packet.as_bytes()),
+        };
+        if sent.is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// RFC 3164 has no year or zone, this is UTC, e.g. "Jun  1 12:00:00"
+fn bsd_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (_, month, day) = civil_date((secs / 86400) as i64);
+    let secs_of_day = secs % 86400;
+    format!(
+        "{} {:>2} {:02}:{:02}:{:02}",
+        MONTHS[month as usize - 1],
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+    )
+}
+
+#[cfg(unix)]
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } < 0 {
+        return "-".to_string();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    match String::from_utf8_lossy(&buf[..len]) {
+        name if name.is_empty() => "-".to_string(),
+        name => name.replace(' ', "_"),
+    }
+}
+
+#[cfg(not(unix))]
+fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "-".to_string())
+}
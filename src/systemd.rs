@@ -0,0 +1,81 @@
+//! The watchdog of systemd services with `WatchdogSec=` set.
+
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::process;
+use std::time::Duration;
+
+use log::warn;
+
+use crate::Error;
+
+/// Pets the service watchdog, which systemd advertises in `WATCHDOG_USEC`,
+/// along with `WATCHDOG_PID` when it is meant for one process only.
+///
+/// systemd restarts the service when `WATCHDOG=1` doesn't reach
+/// `NOTIFY_SOCKET` that often. The pings are sent at half the interval
+/// from a timer of the event loop, so a stuck loop stops sending them.
+pub struct Watchdog {
+    sock: UnixDatagram,
+    interval: Duration,
+}
+
+impl Watchdog {
+    /// The watchdog systemd set up for this process, if any. Fails if the
+    /// variables are unusable, the watchdog would restart the service
+    /// anyway.
+    pub fn from_env() -> Result<Option<Watchdog>, Error> {
+        let usec = match env::var("WATCHDOG_USEC") {
+            Ok(usec) => usec,
+            Err(_) => return Ok(None),
+        };
+        // e.g. a worker process, or the new binary of an upgrade
+        if env::var("WATCHDOG_PID").is_ok_and(|pid| pid.parse::<u32>().ok() != Some(process::id())) {
+            return Ok(None);
+        }
+        let interval = match usec.parse::<u64>() {
+            Ok(usec) if usec > 0 => Duration::from_micros(usec) / 2,
+            _ => return Err(Error::config(format!("invalid WATCHDOG_USEC: {}", usec))),
+        };
+        let path =
+            env::var_os("NOTIFY_SOCKET").ok_or_else(|| Error::config("WATCHDOG_USEC is set without NOTIFY_SOCKET"))?;
+        let sock = UnixDatagram::unbound()?;
+        connect(&sock, &path)
+            .map_err(|e| Error::system(format!("connecting to NOTIFY_SOCKET {}", path.to_string_lossy()), e))?;
+        sock.set_nonblocking(true)?;
+        Ok(Some(Watchdog { sock, interval }))
+    }
+
+    /// How often to ping.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Sends `WATCHDOG=1`, a ping the socket can't take right away is
+    /// lost.
+    pub fn ping(&self) {
+        if let Err(e) = self.sock.send(b"WATCHDOG=1") {
+            warn!("systemd watchdog ping failed: {}", e);
+        }
+    }
+}
+
+// A path, or an abstract address starting with '@' on Linux
+#[cfg(target_os = "linux")]
+fn connect(sock: &UnixDatagram, path: &OsStr) -> io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::SocketAddr;
+
+    match path.as_bytes().split_first() {
+        Some((b'@', name)) => sock.connect_addr(&SocketAddr::from_abstract_name(name)?),
+        _ => sock.connect(path),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn connect(sock: &UnixDatagram, path: &OsStr) -> io::Result<()> {
+    sock.connect(path)
+}
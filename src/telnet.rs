@@ -0,0 +1,168 @@
+//! Telnet decoding, so a `telnet` client gets back what was typed.
+
+const SE: u8 = 240;
+const SB: u8 = 250;
+const WILL: u8 = 251;
+const WONT: u8 = 252;
+const DO: u8 = 253;
+const DONT: u8 = 254;
+const IAC: u8 = 255;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum State {
+    Data,
+    /// After a CR, which ends a line whatever follows.
+    Cr,
+    Iac,
+    /// Waiting for the option of WILL, WONT, DO or DONT.
+    Option(u8),
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+/// Decodes what a telnet client sends into the bytes to echo back.
+///
+/// Commands are stripped and every option is refused, answering WILL with
+/// DONT and DO with WONT, so the client settles on plain NVT. CR LF and
+/// CR NUL read as one newline, echoed as CR LF. The state carries over
+/// between reads, so sequences may be split anywhere.
+pub struct Telnet {
+    state: State,
+}
+
+impl Default for Telnet {
+    fn default() -> Telnet {
+        Telnet { state: State::Data }
+    }
+}
+
+impl Telnet {
+    /// Appends the echo of `input` to `out`, the replies to negotiations
+    /// in their place.
+    pub fn decode(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        for &byte in input {
+            self.state = match (self.state, byte) {
+                (State::Cr, b'\n') | (State::Cr, 0) => State::Data,
+                (State::Cr, _) | (State::Data, _) => data(byte, out),
+                (State::Iac, IAC) => {
+                    out.extend_from_slice(&[IAC, IAC]);
+                    State::Data
+                }
+                (State::Iac, WILL) | (State::Iac, WONT) | (State::Iac, DO) | (State::Iac, DONT) => {
+                    State::Option(byte)
+                }
+                (State::Iac, SB) => State::Subnegotiation,
+                // NOP, GA, AYT and the other commands have no argument
+                (State::Iac, _) => State::Data,
+                (State::Option(verb), option) => {
+                    // Refusals need no answer, the option is already off
+                    match verb {
+                        WILL => out.extend_from_slice(&[IAC, DONT, option]),
+                        DO => out.extend_from_slice(&[IAC, WONT, option]),
+                        _ => {}
+                    }
+                    State::Data
+                }
+                (State::Subnegotiation, IAC) => State::SubnegotiationIac,
+                (State::Subnegotiation, _) => State::Subnegotiation,
+                (State::SubnegotiationIac, SE) => State::Data,
+                (State::SubnegotiationIac, _) => State::Subnegotiation,
+            };
+        }
+    }
+}
+
+// A byte outside of any command
+fn data(byte: u8, out: &mut Vec<u8>) -> State {
+    match byte {
+        IAC => State::Iac,
+        b'\r' => {
+            out.extend_from_slice(b"\r\n");
+            State::Cr
+        }
+        b'\n' => {
+            out.extend_from_slice(b"\r\n");
+            State::Data
+        }
+        _ => {
+            out.push(byte);
+            State::Data
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn decode(telnet: &mut Telnet, input: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        telnet.decode(input, &mut out);
+        out
+    }
+
+    // Commands, data and line ends, so every state is reached
+    fn session() -> impl Strategy<Value = Vec<u8>> {
+        let byte = prop_oneof![Just(IAC), Just(SB), Just(SE), Just(WILL), Just(DO), Just(b'\r'), Just(0), any::<u8>()];
+        prop::collection::vec(byte, 0..400)
+    }
+
+    proptest! {
+        #[test]
+        fn any_input_decodes_the_same_in_chunks(input in session(), cuts in prop::collection::vec(0..16usize, 0..50)) {
+            let whole = decode(&mut Telnet::default(), &input);
+            let mut telnet = Telnet::default();
+            let mut out = Vec::new();
+            let mut rest = &input[..];
+            for cut in cuts {
+                let (chunk, after) = rest.split_at(cut.min(rest.len()));
+                out.extend(decode(&mut telnet, chunk));
+                rest = after;
+            }
+            out.extend(decode(&mut telnet, rest));
+            prop_assert_eq!(&out, &whole);
+            // A CR LF for a CR at most, three bytes of refusal for three of offer
+            prop_assert!(whole.len() <= 2 * input.len());
+        }
+    }
+
+    #[test]
+    fn sequences_split_anywhere_across_two_reads() {
+        let sequences: [&[u8]; 6] = [
+            &[b'a', IAC, WILL, 1, b'b'],
+            &[b'a', IAC, DO, 3, b'b'],
+            &[b'a', IAC, IAC, b'b'],
+            &[b'a', IAC, SB, 24, 1, IAC, IAC, IAC, SE, b'b'],
+            b"a\r\nb",
+            &[b'a', b'\r', 0, b'b'],
+        ];
+        for sequence in &sequences {
+            let whole = decode(&mut Telnet::default(), sequence);
+            for split in 0..=sequence.len() {
+                let mut telnet = Telnet::default();
+                let mut out = decode(&mut telnet, &sequence[..split]);
+                out.extend(decode(&mut telnet, &sequence[split..]));
+                assert_eq!(out, whole, "{:?} split at {}", sequence, split);
+            }
+        }
+    }
+
+    #[test]
+    fn plain_lines_echo_with_cr_lf() {
+        assert_eq!(decode(&mut Telnet::default(), b"one\r\ntwo\r\0three\n"), b"one\r\ntwo\r\nthree\r\n");
+    }
+
+    #[test]
+    fn offers_are_refused() {
+        let input = [b'a', IAC, WILL, 1, IAC, DO, 3, IAC, WONT, 5, b'b'];
+        assert_eq!(decode(&mut Telnet::default(), &input), [b'a', IAC, DONT, 1, IAC, WONT, 3, b'b']);
+    }
+
+    #[test]
+    fn subnegotiations_are_dropped() {
+        let input = [b'a', IAC, SB, 24, 1, IAC, IAC, 2, IAC, SE, b'b', IAC, IAC];
+        assert_eq!(decode(&mut Telnet::default(), &input), [b'a', b'b', IAC, IAC]);
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::VecDeque;
+use std::mem;
+use std::time::{Duration, Instant};
+
+/// How often clients held by the cap get another share.
+pub const REFILL_INTERVAL: Duration = Duration::from_millis(10);
+/// Refill intervals the bucket holds, so that a late timer or a client
+/// taking a round trip to be held and released doesn't lose what it
+/// earned meanwhile.
+const BURST_INTERVALS: u32 = 5;
+/// Smallest burst, so low rates still write useful chunks.
+const MIN_BURST: u64 = 4096;
+
+/// Token bucket shared by every client write, capping the aggregate echo
+/// rate.
+///
+/// Clients with something to write take from it first come. One that runs
+/// out of share is held until the next refill, when the held clients split
+/// the bucket evenly in the order they were held.
+pub struct Throttle {
+    /// Bytes per second.
+    rate: u64,
+    burst: u64,
+    /// Negative after a packet larger than what was left, the debt is
+    /// paid off by the next refills.
+    tokens: i64,
+    refilled_at: Instant,
+    held: VecDeque<usize>,
+    armed: bool,
+    window_start: Instant,
+    window_bytes: u64,
+    utilization: u64,
+}
+
+impl Throttle {
+    pub fn new(rate: u64, now: Instant) -> Throttle {
+        let burst = burst(rate);
+        Throttle {
+            rate,
+            burst,
+            tokens: burst as i64,
+            refilled_at: now,
+            held: VecDeque::new(),
+            armed: false,
+            window_start: now,
+            window_bytes: 0,
+            utilization: 0,
+        }
+    }
+
+    /// Switches to `rate`, keeping the held clients and what was earned at
+    /// the old rate.
+    pub fn set_rate(&mut self, rate: u64, now: Instant) {
+        self.refill(now);
+        self.rate = rate;
+        self.burst = burst(rate);
+        self.tokens = self.tokens.min(self.burst as i64);
+        self.window_start = now;
+        self.window_bytes = 0;
+    }
+
+    /// Bytes one of `sharers` clients may write now, 0 when it must be
+    /// held. A client writing on its own turn takes what there is, one
+    /// sharer.
+    pub fn share(&mut self, now: Instant, sharers: usize) -> usize {
+        self.refill(now);
+        if self.tokens <= 0 {
+            return 0;
+        }
+        (self.tokens as u64).div_ceil(sharers.max(1) as u64) as usize
+    }
+
+    /// Accounts for bytes written.
+    pub fn take(&mut self, len: usize) {
+        self.tokens -= len as i64;
+        self.window_bytes += len as u64;
+    }
+
+    /// Holds the client at this slab index until the next refill, returns
+    /// the deadline of the timer to arm if none is.
+    pub fn hold(&mut self, index: usize, now: Instant) -> Option<Instant> {
+        self.held.push_back(index);
+        if self.armed {
+            return None;
+        }
+        self.armed = true;
+        Some(now + REFILL_INTERVAL)
+    }
+
+    /// Releases the held clients, with the share each may write.
+    pub fn release(&mut self, now: Instant) -> (VecDeque<usize>, usize) {
+        self.armed = false;
+        let held = mem::take(&mut self.held);
+        let share = self.share(now, held.len());
+        (held, share)
+    }
+
+    /// Percentage of the rate used, averaged over about a second.
+    pub fn utilization(&mut self, now: Instant) -> u64 {
+        let elapsed = now.saturating_duration_since(self.window_start);
+        if elapsed >= Duration::from_secs(1) {
+            self.utilization = (self.window_bytes as f64 * 100.0 / (self.rate as f64 * elapsed.as_secs_f64())) as u64;
+            self.window_start = now;
+            self.window_bytes = 0;
+        }
+        self.utilization
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        let earned = (elapsed.as_nanos() * self.rate as u128 / 1_000_000_000) as u64;
+        if self.tokens + earned as i64 >= self.burst as i64 {
+            self.tokens = self.burst as i64;
+            self.refilled_at = now;
+        } else if earned > 0 {
+            self.tokens += earned as i64;
+            // Keep the fraction of a byte earned for the next refill
+            self.refilled_at += Duration::from_nanos((earned as u128 * 1_000_000_000 / self.rate as u128) as u64);
+        }
+    }
+}
+
+fn burst(rate: u64) -> u64 {
+    ((rate as f64 * (REFILL_INTERVAL * BURST_INTERVALS).as_secs_f64()) as u64).max(MIN_BURST)
+}
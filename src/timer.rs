@@ -0,0 +1,110 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// What to do when a deadline passes.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Timeout {
+    /// Resume writing to the client at this slab index.
+    ResumeWrite(usize),
+    /// Send the echo held for the client at this slab index if it has
+    /// been quiet long enough.
+    Quiesce(usize),
+    /// Let the clients held by the global rate cap write again.
+    Refill,
+    /// Let the clients parked by the write budget write again.
+    PendingWrites,
+    /// Give up on the refused connection at this slab index if it hasn't
+    /// taken the busy message yet.
+    Courtesy(usize),
+    /// Promote or refuse the connections waiting in the pending queue.
+    Pending,
+    /// Check whether the client at this slab index needs a heartbeat.
+    Heartbeat(usize),
+    /// Close the client at this slab index if it hasn't sent anything
+    /// yet.
+    FirstByte(usize),
+    /// Close the client at this slab index if it has sent nothing for
+    /// `Config::idle_timeout`.
+    IdleClient(usize),
+    /// Run the user's tick callback.
+    Tick,
+    /// Push the stats to statsd.
+    Statsd,
+    /// The run duration is over, start draining.
+    Deadline,
+    /// Lift the ban of this address if it has run out.
+    Unban(IpAddr),
+    /// Check whether the deny file changed.
+    Deny,
+    /// Check on the upgrade SIGUSR2 started.
+    #[cfg(unix)]
+    Upgrade,
+    /// Ping the systemd watchdog.
+    #[cfg(unix)]
+    Watchdog,
+    /// Measure the buffers and shrink those unused for long enough.
+    Shrink,
+    /// Stop waiting for the draining clients.
+    Drain,
+    /// Check whether the server has been without clients for long enough.
+    Idle,
+    /// Log and forget the UDP sources quiet for `Config::udp_source_idle`.
+    UdpSources,
+    /// Bind the broken TCP listener at this index again.
+    Rebind(usize),
+    /// Accept again on the listener at this index, paused once out of
+    /// descriptors.
+    ResumeAccept(usize),
+    /// Hand the QUIC connections of the UDP socket at this listener index
+    /// their timeouts.
+    #[cfg(feature = "quic")]
+    Quic(usize),
+}
+
+/// Deadlines driving the poll timeout of the event loop.
+///
+/// Timers can't be cancelled; the owner of a timeout must check on expiry
+/// that it is still wanted.
+#[derive(Default)]
+pub struct Timers {
+    heap: BinaryHeap<Reverse<(Instant, Timeout)>>,
+}
+
+impl Timers {
+    pub fn new() -> Timers {
+        Timers::default()
+    }
+
+    pub fn insert(&mut self, deadline: Instant, timeout: Timeout) {
+        self.heap.push(Reverse((deadline, timeout)));
+    }
+
+    /// Number of armed timers, stale ones included.
+    #[cfg(unix)]
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    /// Time left until the next deadline, `None` when nothing is armed.
+    pub fn next_timeout(&self, now: Instant) -> Option<Duration> {
+        self.heap.peek().map(|Reverse((deadline, _))| {
+            if *deadline > now {
+                *deadline - now
+            } else {
+                Duration::from_secs(0)
+            }
+        })
+    }
+
+    /// Removes and returns the next timeout whose deadline has passed.
+    pub fn pop_expired(&mut self, now: Instant) -> Option<Timeout> {
+        match self.heap.peek() {
+            Some(Reverse((deadline, _))) if *deadline <= now => {
+                self.heap.pop().map(|Reverse((_, timeout))| timeout)
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,413 @@
+//! TLS termination of the TCP clients with rustls, see `Config::tls_cert`,
+//! and their authentication, see `Config::tls_client_ca`.
+//!
+//! Records are read and written as the socket allows, the client only
+//! ever sees the plaintext: a read returns what was decrypted, and a
+//! write is taken once the records of earlier ones are flushed.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use std::sync::Arc;
+
+use log::info;
+use mio::event::Source;
+use mio::net::TcpStream;
+use mio::{Interest, Registry, Token};
+use rustls::client::danger::HandshakeSignatureValid;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{DigitallySignedStruct, DistinguishedName, RootCertStore, ServerConfig, ServerConnection, SignatureScheme};
+
+use crate::config::{ClientAuth, Config};
+use crate::stream::PeerAddr;
+use crate::Error;
+
+/// What the TCP clients are served TLS with, `None` without
+/// `Config::tls_cert`.
+pub fn acceptor(config: &Config) -> Result<Option<Arc<Acceptor>>, Error> {
+    let (cert, key) = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => (cert, key),
+        _ => return Ok(None),
+    };
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let verifier = match config.tls_client_ca {
+        Some(ref ca) => Some(client_verifier(ca, provider.clone())?),
+        None => None,
+    };
+    let (config, verifier) = match (verifier, config.tls_client_auth) {
+        (Some(verifier), ClientAuth::Optional) => {
+            let lenient = Arc::new(Lenient(verifier.clone()));
+            (server_config(cert, key, provider, Some(lenient))?, Some(verifier))
+        }
+        (verifier, _) => (server_config(cert, key, provider, verifier)?, None),
+    };
+    Ok(Some(Arc::new(Acceptor { config, verifier })))
+}
+
+/// Loads the PEM certificate chain and private key the server presents,
+/// checking client certificates with `verifier` if any.
+pub fn server_config(
+    cert: &Path,
+    key: &Path,
+    provider: Arc<CryptoProvider>,
+    verifier: Option<Arc<dyn ClientCertVerifier>>,
+) -> Result<Arc<ServerConfig>, Error> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid(cert, e))?;
+    if certs.is_empty() {
+        return Err(invalid(cert, "no certificate found"));
+    }
+    let key_der = PrivateKeyDer::from_pem_file(key).map_err(|e| invalid(key, e))?;
+    let config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| match verifier {
+            Some(verifier) => builder.with_client_cert_verifier(verifier).with_single_cert(certs, key_der),
+            None => builder.with_no_client_auth().with_single_cert(certs, key_der),
+        })
+        .map_err(|e| invalid(key, e))?;
+    Ok(Arc::new(config))
+}
+
+// Requires certificates issued by the CAs of the PEM file `ca`
+fn client_verifier(ca: &Path, provider: Arc<CryptoProvider>) -> Result<Arc<dyn ClientCertVerifier>, Error> {
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(ca).map_err(|e| invalid(ca, e))? {
+        roots.add(cert.map_err(|e| invalid(ca, e))?).map_err(|e| invalid(ca, e))?;
+    }
+    if roots.is_empty() {
+        return Err(invalid(ca, "no certificate found"));
+    }
+    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+        .build()
+        .map_err(|e| invalid(ca, e))
+}
+
+fn invalid<M: ToString>(path: &Path, message: M) -> Error {
+    Error::Invalid {
+        path: path.to_path_buf(),
+        message: message.to_string(),
+    }
+}
+
+/// The server side of TLS: the certificate presented, and how client
+/// certificates are checked.
+pub struct Acceptor {
+    config: Arc<ServerConfig>,
+    /// Checks again the certificates `ClientAuth::Optional` let through,
+    /// to log whether they are valid.
+    verifier: Option<Arc<dyn ClientCertVerifier>>,
+}
+
+impl Acceptor {
+    /// Logs the certificate chain a client presented in a finished
+    /// handshake, returns what `Socket::peer_cert` reports of it.
+    pub fn peer_cert<P: fmt::Display>(&self, certs: Option<&[CertificateDer<'_>]>, peer: P) -> Option<String> {
+        let (cert, intermediates) = match certs {
+            Some([cert, intermediates @ ..]) => (cert, intermediates),
+            _ => {
+                if self.verifier.is_some() {
+                    info!("no client certificate, unverified : {}", peer);
+                }
+                return None;
+            }
+        };
+        let verified = match self.verifier {
+            Some(ref verifier) => verifier.verify_client_cert(cert, intermediates, UnixTime::now()),
+            None => Ok(ClientCertVerified::assertion()),
+        };
+        let name = cert_name(cert).unwrap_or_else(|| "unparsable certificate".to_string());
+        let peer_cert = match verified {
+            Ok(_) => name,
+            Err(e) => format!("{} (unverified: {})", name, e),
+        };
+        info!("client certificate {} : {}", peer_cert, peer);
+        Some(peer_cert)
+    }
+
+    /// The settings of the QUIC handshakes, offering `alpn`.
+    #[cfg(feature = "quic")]
+    pub fn quic_config(&self, alpn: &[String]) -> ServerConfig {
+        let mut config = (*self.config).clone();
+        config.alpn_protocols = alpn.iter().map(|protocol| protocol.as_bytes().to_vec()).collect();
+        config
+    }
+}
+
+/// Lets every client through for `ClientAuth::Optional`, with or without
+/// a certificate, valid or not. Signatures are still checked, a client
+/// can't present the certificate of someone else.
+#[derive(Debug)]
+struct Lenient(Arc<dyn ClientCertVerifier>);
+
+impl ClientCertVerifier for Lenient {
+    fn client_auth_mandatory(&self) -> bool {
+        false
+    }
+
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        self.0.root_hint_subjects()
+    }
+
+    fn verify_client_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_verify_schemes()
+    }
+}
+
+/// A handshake that failed, e.g. on a client certificate not issued by
+/// `Config::tls_client_ca`.
+#[derive(Debug)]
+struct HandshakeError(rustls::Error);
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "tls handshake failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for HandshakeError {}
+
+/// Whether `e` is a `TlsStream` read failing the handshake.
+pub fn is_handshake_error(e: &io::Error) -> bool {
+    e.get_ref().is_some_and(|e| e.is::<HandshakeError>())
+}
+
+/// A TCP connection carrying TLS, the handshake included.
+pub struct TlsStream {
+    sock: TcpStream,
+    conn: ServerConnection,
+    acceptor: Arc<Acceptor>,
+    peer: PeerAddr,
+    /// Whether the handshake is done and its outcome logged.
+    established: bool,
+    /// See `Socket::peer_cert`.
+    peer_cert: Option<String>,
+}
+
+impl TlsStream {
+    pub fn new(sock: TcpStream, acceptor: Arc<Acceptor>, peer: PeerAddr) -> io::Result<TlsStream> {
+        let conn = ServerConnection::new(acceptor.config.clone()).map_err(io::Error::other)?;
+        Ok(TlsStream {
+            sock,
+            conn,
+            acceptor,
+            peer,
+            established: false,
+            peer_cert: None,
+        })
+    }
+
+    pub fn tcp(&self) -> &TcpStream {
+        &self.sock
+    }
+
+    /// Subject and alternative names of the client's certificate, ending
+    /// with `(unverified)` if it isn't valid, once the handshake is done.
+    pub fn peer_cert(&self) -> Option<&str> {
+        self.peer_cert.as_deref()
+    }
+
+    // Records and logs the client's certificate once the handshake is done
+    fn establish(&mut self) {
+        self.established = true;
+        self.peer_cert = self.acceptor.peer_cert(self.conn.peer_certificates(), self.peer);
+    }
+
+    /// Whether records are waiting for the socket to take them, e.g. the
+    /// handshake's.
+    pub fn wants_write(&self) -> bool {
+        self.conn.wants_write()
+    }
+
+    // Writes the pending records, `WouldBlock` when some are left
+    fn write_records(&mut self) -> io::Result<()> {
+        while self.conn.wants_write() {
+            self.conn.write_tls(&mut self.sock)?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.conn.reader().read(buf) {
+                Ok(len) => return Ok(len),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                // Closed without close_notify, like a plain TCP close
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(0),
+                Err(e) => return Err(e),
+            }
+            if self.conn.read_tls(&mut self.sock)? == 0 {
+                return Ok(0);
+            }
+            if let Err(e) = self.conn.process_new_packets() {
+                // Tell the peer what went wrong if the socket takes it
+                let _ = self.write_records();
+                if self.established {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+                }
+                info!("tls handshake failed: {} : {}", e, self.peer);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, HandshakeError(e)));
+            }
+            if !self.established && !self.conn.is_handshaking() {
+                self.establish();
+            }
+            // The handshake answers as it goes
+            match self.write_records() {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                result => result?,
+            }
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_records()?;
+        let len = self.conn.writer().write(buf)?;
+        match self.write_records() {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            result => result?,
+        }
+        if len == 0 && !buf.is_empty() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_records()
+    }
+}
+
+impl Source for TlsStream {
+    fn register(&mut self, registry: &Registry, token: Token, interest: Interest) -> io::Result<()> {
+        self.sock.register(registry, token, interest)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: Token, interest: Interest) -> io::Result<()> {
+        self.sock.reregister(registry, token, interest)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        self.sock.deregister(registry)
+    }
+}
+
+// Subject and subject alternative names of a DER certificate, e.g.
+// `CN=alice, O=Lab, DNS:alice.example`
+fn cert_name(cert: &[u8]) -> Option<String> {
+    let (_, cert, _) = der_element(cert)?;
+    let (_, tbs, _) = der_element(cert)?;
+    let mut fields = der_elements(tbs);
+    // The version, explicitly tagged [0], is omitted for v1, then come the
+    // serial number, signature algorithm, issuer and validity
+    if fields.next()?.0 == 0xa0 {
+        fields.next()?;
+    }
+    let (_, subject) = fields.nth(3)?;
+    let mut names = Vec::new();
+    for (_, rdn) in der_elements(subject) {
+        for (_, attribute) in der_elements(rdn) {
+            let mut parts = der_elements(attribute);
+            let (oid, value) = (parts.next()?.1, parts.next()?.1);
+            let key = match oid {
+                [0x55, 0x04, 0x03] => "CN",
+                [0x55, 0x04, 0x06] => "C",
+                [0x55, 0x04, 0x07] => "L",
+                [0x55, 0x04, 0x08] => "ST",
+                [0x55, 0x04, 0x0a] => "O",
+                [0x55, 0x04, 0x0b] => "OU",
+                _ => continue,
+            };
+            names.push(format!("{}={}", key, String::from_utf8_lossy(value)));
+        }
+    }
+    // Subject public key info, then the unique ids and extensions
+    for (tag, extensions) in fields.skip(1) {
+        if tag != 0xa3 {
+            continue;
+        }
+        let (_, extensions, _) = der_element(extensions)?;
+        for (_, extension) in der_elements(extensions) {
+            let mut parts = der_elements(extension);
+            if parts.next()?.1 != [0x55, 0x1d, 0x11] {
+                continue;
+            }
+            // Skips the critical flag if any
+            let (_, value) = parts.find(|&(tag, _)| tag == 0x04)?;
+            let (_, general_names, _) = der_element(value)?;
+            for (tag, name) in der_elements(general_names) {
+                names.push(match (tag, name.len()) {
+                    (0x81, _) => format!("email:{}", String::from_utf8_lossy(name)),
+                    (0x82, _) => format!("DNS:{}", String::from_utf8_lossy(name)),
+                    (0x86, _) => format!("URI:{}", String::from_utf8_lossy(name)),
+                    (0x87, 4) => format!("IP:{}", Ipv4Addr::from(<[u8; 4]>::try_from(name).ok()?)),
+                    (0x87, 16) => format!("IP:{}", Ipv6Addr::from(<[u8; 16]>::try_from(name).ok()?)),
+                    _ => continue,
+                });
+            }
+        }
+    }
+    Some(names.join(", "))
+}
+
+// The tag and contents of the DER element starting `der`, and what follows
+fn der_element(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = der.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = match first {
+        0..=0x7f => (first as usize, rest),
+        0x81..=0x84 => {
+            let (len, rest) = rest.split_at_checked((first & 0x7f) as usize)?;
+            (len.iter().fold(0, |len, &b| len << 8 | b as usize), rest)
+        }
+        _ => return None,
+    };
+    let (contents, rest) = rest.split_at_checked(len)?;
+    Some((tag, contents, rest))
+}
+
+// The tags and contents of the DER elements in a row of `der`, up to the
+// first that doesn't parse
+fn der_elements(mut der: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    std::iter::from_fn(move || {
+        let (tag, contents, rest) = der_element(der)?;
+        der = rest;
+        Some((tag, contents))
+    })
+}
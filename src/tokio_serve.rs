@@ -0,0 +1,176 @@
+//! Runs the echo server as a task of a tokio application.
+
+use std::future::{self, Future};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use mio::Events;
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+
+use crate::config::{Backend, Config};
+use crate::server::ServerBuilder;
+use crate::stats::Stats;
+use crate::Error;
+
+// The descriptor of the server's poll, readable while events are pending
+struct PollFd(RawFd);
+
+impl AsRawFd for PollFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+/// Serves from the current tokio runtime until `shutdown` completes, then
+/// drains like at the end of `Config::duration`, and returns the final
+/// stats.
+pub async fn serve<F: Future<Output = ()>>(config: Config, shutdown: F) -> Result<Stats, Error> {
+    ServerBuilder::new(config).serve(shutdown).await
+}
+
+impl ServerBuilder {
+    /// Like `serve`, with the callbacks of the builder.
+    ///
+    /// The mio loop runs inside the task: the runtime wakes it when the
+    /// poll's descriptor turns readable or the next timer is due, and it
+    /// never blocks. `Config::seccomp` and `Config::chroot` are refused
+    /// since they would confine the whole application.
+    pub async fn serve<F: Future<Output = ()>>(self, shutdown: F) -> Result<Stats, Error> {
+        let config = self.config();
+        if config.seccomp || config.chroot.is_some() {
+            return Err(Error::config("seccomp and chroot can't be used with serve"));
+        }
+        if config.backend != Backend::Mio {
+            return Err(Error::config("only the mio backend can be served from tokio"));
+        }
+        let mut server = self.build()?;
+        let reactor = server.reactor_mut().expect("mio backend");
+        let fd = AsyncFd::with_interest(PollFd(reactor.poll().as_raw_fd()), Interest::READABLE)?;
+        let mut events = Events::with_capacity(reactor.config().events_capacity);
+        tokio::pin!(shutdown);
+        let mut draining = false;
+
+        while !reactor.shutdown_requested() {
+            let timeout = reactor.next_timeout(reactor.now());
+            let timer = async {
+                match timeout {
+                    Some(timeout) => tokio::time::sleep(timeout).await,
+                    None => future::pending().await,
+                }
+            };
+            tokio::select! {
+                ready = fd.readable() => {
+                    let mut guard = ready?;
+                    // Only an empty poll proves nothing is pending, the
+                    // events may not have fit in one
+                    if reactor.turn(&mut events, Some(Duration::from_secs(0)))? == 0 {
+                        guard.clear_ready();
+                    }
+                }
+                _ = &mut shutdown, if !draining => {
+                    draining = true;
+                    reactor.drain(reactor.now());
+                }
+                _ = timer => {
+                    reactor.turn(&mut events, Some(Duration::from_secs(0)))?;
+                }
+            }
+        }
+
+        // Out of the runtime before the poll is closed
+        drop(fd);
+        Ok(server.close())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Instant;
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    // A config listening on a port free a moment ago
+    fn free_config() -> Config {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        Config::new(&format!("127.0.0.1:{}", port))
+    }
+
+    // Connects once the task listens
+    fn connect(config: &Config) -> TcpStream {
+        let addr = config.listen.clone().unwrap();
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            match TcpStream::connect(&addr) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+                    return stream;
+                }
+                Err(e) => assert!(Instant::now() < deadline, "never listened: {}", e),
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn echo(stream: &mut TcpStream, message: &[u8]) {
+        stream.write_all(message).unwrap();
+        let mut reply = vec![0; message.len()];
+        stream.read_exact(&mut reply).unwrap();
+        assert_eq!(reply, message);
+    }
+
+    #[tokio::test]
+    async fn echo_round_trip() {
+        let config = free_config();
+        // Blocking sockets, off the runtime the server runs on
+        let client = tokio::task::spawn_blocking({
+            let config = config.clone();
+            move || {
+                let mut stream = connect(&config);
+                echo(&mut stream, b"hello");
+                stream.shutdown(Shutdown::Write).unwrap();
+                // Closed after the client
+                assert_eq!(stream.read(&mut [0; 16]).unwrap(), 0);
+            }
+        });
+        let stats = serve(config, async { client.await.unwrap() }).await.unwrap();
+        assert_eq!(stats.tcp.connections, 1);
+        assert_eq!(stats.tcp.bytes_read, 5);
+        assert_eq!(stats.tcp.bytes_written, 5);
+    }
+
+    #[tokio::test]
+    async fn a_shutdown_closes_the_clients_left_after_the_drain() {
+        let config = Config {
+            drain_timeout: Duration::from_millis(200),
+            ..free_config()
+        };
+        let left = Arc::new(Mutex::new(None));
+        let client = tokio::task::spawn_blocking({
+            let (config, left) = (config.clone(), Arc::clone(&left));
+            move || {
+                let mut stream = connect(&config);
+                echo(&mut stream, b"still here");
+                *left.lock().unwrap() = Some(stream);
+            }
+        });
+        let start = Instant::now();
+        let stats = serve(config, async { client.await.unwrap() }).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(200), "returned before the drain timeout");
+        assert_eq!(stats.tcp.connections, 1);
+
+        let mut stream = left.lock().unwrap().take().unwrap();
+        match stream.read(&mut [0; 16]) {
+            Ok(len) => assert_eq!(len, 0),
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::ConnectionReset),
+        }
+        // Nobody listens anymore
+        assert!(TcpStream::connect(stream.peer_addr().unwrap()).is_err());
+    }
+}
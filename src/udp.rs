@@ -0,0 +1,358 @@
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+use log::{trace, warn};
+use mio::net::UdpSocket;
+use mio::{Interest, Registry, Token};
+
+use crate::capture::{Capture, Protocol};
+#[cfg(feature = "dtls")]
+use crate::dtls::Sessions;
+use crate::dump::HexDump;
+#[cfg(feature = "quic")]
+use crate::quic::QuicEcho;
+use crate::sequence::Sequencer;
+use crate::stats::{Stats, TransportStats};
+#[cfg(target_os = "linux")]
+use crate::sys::Batch;
+
+const MAX_DATAGRAM_SIZE: usize = 65536;
+const MAX_QUEUED_DATAGRAMS: usize = 1024;
+
+/// Echoes every datagram back to its sender.
+pub struct UdpEcho {
+    sock: UdpSocket,
+    writable: bool,
+    // Replies the socket couldn't take yet
+    queue: VecDeque<(SocketAddr, Vec<u8>)>,
+    dump_limit: usize,
+    capture: Option<(Capture, SocketAddr)>,
+    /// Counts the sequence numbers of the datagrams, see
+    /// `Config::udp_sequence`.
+    sequencer: Option<Box<Sequencer>>,
+    /// The DTLS sessions of the peers, see `Config::dtls_cert`.
+    #[cfg(feature = "dtls")]
+    dtls: Option<Box<Sessions>>,
+    /// The QUIC endpoint the datagrams are for, see `Config::quic`.
+    #[cfg(feature = "quic")]
+    quic: Option<Box<QuicEcho>>,
+    /// Buffers of recvmmsg and sendmmsg, `None` for a syscall per
+    /// datagram.
+    #[cfg(target_os = "linux")]
+    batch: Option<Batch>,
+}
+
+impl UdpEcho {
+    /// Hex dumps at most `dump_limit` bytes of each datagram at trace level,
+    /// and records them to `capture`. Receives and sends up to `batch`
+    /// datagrams per syscall on Linux, see `Config::udp_batch`.
+    pub fn new(sock: UdpSocket, dump_limit: usize, capture: Option<Capture>, batch: usize) -> io::Result<UdpEcho> {
+        let capture = match capture {
+            Some(capture) => Some((capture, sock.local_addr()?)),
+            None => None,
+        };
+        #[cfg(not(target_os = "linux"))]
+        let _ = batch;
+        Ok(UdpEcho {
+            sock,
+            writable: false,
+            queue: VecDeque::new(),
+            dump_limit,
+            capture,
+            sequencer: None,
+            #[cfg(feature = "dtls")]
+            dtls: None,
+            #[cfg(feature = "quic")]
+            quic: None,
+            #[cfg(target_os = "linux")]
+            batch: if batch > 1 { Some(Batch::new(batch, MAX_DATAGRAM_SIZE)) } else { None },
+        })
+    }
+
+    /// Reads a sequence number at the start of every datagram, forgetting
+    /// the senders quiet for `idle`, see `Config::udp_sequence`.
+    pub fn track_sequences(&mut self, idle: Duration) {
+        self.sequencer = Some(Box::new(Sequencer::new(idle)));
+    }
+
+    /// Echoes over the DTLS `sessions` of the peers instead of in clear,
+    /// see `Config::dtls_cert`.
+    #[cfg(feature = "dtls")]
+    pub fn terminate_dtls(&mut self, sessions: Sessions) {
+        self.dtls = Some(Box::new(sessions));
+    }
+
+    /// Hands the datagrams to the QUIC `endpoint`, which echoes the
+    /// streams of its connections, see `Config::quic`.
+    #[cfg(feature = "quic")]
+    pub fn serve_quic(&mut self, endpoint: QuicEcho) {
+        self.quic = Some(Box::new(endpoint));
+    }
+
+    /// Hands the QUIC connections their timeouts due by `now` and sends
+    /// what they have to.
+    #[cfg(feature = "quic")]
+    pub fn quic_timeout(&mut self, registry: &Registry, token: Token, now: Instant, stats: &mut Stats) -> io::Result<()> {
+        if let Some(ref mut quic) = self.quic {
+            quic.expire(now);
+        }
+        self.transmit_quic(now, stats)?;
+        if self.reregister(registry, token)? {
+            stats.event_loop.reregisters += 1;
+        }
+        Ok(())
+    }
+
+    /// When the QUIC connections next need `quic_timeout`, `None` if a
+    /// timer asked for before comes first.
+    #[cfg(feature = "quic")]
+    pub fn quic_timer(&mut self) -> Option<Instant> {
+        self.quic.as_mut().and_then(|quic| quic.next_timer())
+    }
+
+    // Queues what the QUIC connections have to send, as far as the queue
+    // goes, sending it as it fills up
+    #[cfg(feature = "quic")]
+    fn transmit_quic(&mut self, now: Instant, stats: &mut Stats) -> io::Result<()> {
+        let mut quic = match self.quic.take() {
+            Some(quic) => quic,
+            None => return Ok(()),
+        };
+        let mut result = Ok(());
+        loop {
+            let room = MAX_QUEUED_DATAGRAMS.saturating_sub(self.queue.len());
+            if room == 0 || quic.transmit(now, room, &mut self.queue) == 0 {
+                break;
+            }
+            result = self.flush(&mut stats.udp);
+            // Left queued for when the socket is writable again
+            if result.is_err() || !self.queue.is_empty() {
+                break;
+            }
+        }
+        stats.quic = quic.totals;
+        self.quic = Some(quic);
+        result
+    }
+
+    /// Logs the sequence numbers of the senders quiet for long enough and
+    /// forgets them, and drops the DTLS sessions quiet for too long.
+    pub fn expire_sources(&mut self, now: Instant, stats: &mut Stats) {
+        if let Some(ref mut sequencer) = self.sequencer {
+            sequencer.expire(now);
+        }
+        #[cfg(feature = "dtls")]
+        if let Some(ref mut dtls) = self.dtls {
+            dtls.expire(now);
+            stats.dtls = dtls.totals;
+        }
+        #[cfg(not(feature = "dtls"))]
+        let _ = stats;
+    }
+
+    /// The sequence numbers of every sender, `None` unless tracked.
+    pub fn sources(&self) -> Option<String> {
+        self.sequencer.as_ref().map(|sequencer| sequencer.list())
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.sock.local_addr()
+    }
+
+    pub fn register(&mut self, registry: &Registry, token: Token) -> io::Result<()> {
+        registry.register(&mut self.sock, token, Interest::READABLE)
+    }
+
+    pub fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        registry.deregister(&mut self.sock)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: Token) -> io::Result<bool> {
+        if self.queue.is_empty() == self.writable {
+            self.writable = !self.writable;
+            let interest = if self.writable {
+                Interest::READABLE | Interest::WRITABLE
+            } else {
+                Interest::READABLE
+            };
+            registry.reregister(&mut self.sock, token, interest)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Handles a readiness event of the socket.
+    pub fn ready(&mut self, registry: &Registry, token: Token, readable: bool, now: Instant, stats: &mut Stats) -> io::Result<()> {
+        // Flush older replies first so datagrams from one peer stay ordered
+        self.flush(&mut stats.udp)?;
+        if readable {
+            let result = self.read(now, &mut stats.udp);
+            if let Some(ref sequencer) = self.sequencer {
+                stats.sequence = sequencer.totals;
+            }
+            #[cfg(feature = "dtls")]
+            if let Some(ref dtls) = self.dtls {
+                stats.dtls = dtls.totals;
+            }
+            result?;
+        }
+        // After every datagram read is fed, to send the acknowledgements
+        // and echoes of a batch together, or once writable again
+        #[cfg(feature = "quic")]
+        self.transmit_quic(now, stats)?;
+        if self.reregister(registry, token)? {
+            stats.event_loop.reregisters += 1;
+        }
+        Ok(())
+    }
+
+    // Queues the reply to a datagram
+    fn received(&mut self, addr: SocketAddr, data: &[u8], now: Instant, stats: &mut TransportStats) {
+        trace!("read from {}:\n{}", addr, HexDump::new(data, self.dump_limit));
+        if let Some((ref capture, local)) = self.capture {
+            capture.record(Protocol::Udp, addr, local, data);
+        }
+        if let Some(ref mut sequencer) = self.sequencer {
+            sequencer.record(addr, data, now);
+        }
+        stats.datagrams += 1;
+        stats.bytes_read += data.len() as u64;
+        #[cfg(feature = "dtls")]
+        if let Some(ref mut dtls) = self.dtls {
+            for reply in dtls.received(addr, data, now) {
+                self.queue(addr, reply, stats);
+            }
+            return;
+        }
+        #[cfg(feature = "quic")]
+        if let Some(ref mut quic) = self.quic {
+            quic.received(addr, data, now);
+            return;
+        }
+        self.queue(addr, data.to_vec(), stats);
+    }
+
+    fn queue(&mut self, addr: SocketAddr, reply: Vec<u8>, stats: &mut TransportStats) {
+        if self.queue.len() < MAX_QUEUED_DATAGRAMS {
+            self.queue.push_back((addr, reply));
+        } else {
+            stats.dropped += 1;
+        }
+    }
+
+    // Takes the reply at the front of the queue once sent
+    fn sent(&mut self, len: usize, stats: &mut TransportStats) {
+        let (addr, buf) = self.queue.pop_front().expect("queued reply");
+        trace!("write to {}:\n{}", addr, HexDump::new(&buf[..len], self.dump_limit));
+        if let Some((ref capture, local)) = self.capture {
+            capture.record(Protocol::Udp, local, addr, &buf[..len]);
+        }
+        stats.bytes_written += len as u64;
+    }
+
+    // Drops the reply at the front of the queue, which couldn't be sent
+    fn send_failed(&mut self, e: &io::Error, stats: &mut TransportStats) {
+        let (addr, _) = self.queue.pop_front().expect("queued reply");
+        // Only this peer is affected
+        warn!("error={} : {}", e, addr);
+        stats.dropped += 1;
+    }
+
+    fn read(&mut self, now: Instant, stats: &mut TransportStats) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        if let Some(mut batch) = self.batch.take() {
+            let result = self.read_batch(&mut batch, now, stats);
+            self.batch = Some(batch);
+            return result;
+        }
+        let mut rbuf = [0; MAX_DATAGRAM_SIZE];
+
+        loop {
+            match self.sock.recv_from(&mut rbuf) {
+                Ok((len, addr)) => {
+                    self.received(addr, &rbuf[..len], now, stats);
+                    self.flush(stats)?;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    // Socket is not ready anymore, stop reading
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Receives a batch at a time, each datagram in its own buffer with
+    // its own sender, and sends the replies back a batch at a time
+    #[cfg(target_os = "linux")]
+    fn read_batch(&mut self, batch: &mut Batch, now: Instant, stats: &mut TransportStats) -> io::Result<()> {
+        loop {
+            let count = match batch.recv(&self.sock) {
+                Ok(count) => count,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    // Socket is not ready anymore, stop reading
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            };
+            for i in 0..count {
+                // Always an IP address on an IP socket
+                if let (Some(addr), data) = batch.received(i) {
+                    self.received(addr, data, now, stats);
+                }
+            }
+            self.flush_batch(batch, stats);
+        }
+    }
+
+    fn flush(&mut self, stats: &mut TransportStats) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        if let Some(mut batch) = self.batch.take() {
+            self.flush_batch(&mut batch, stats);
+            self.batch = Some(batch);
+            return Ok(());
+        }
+        while let Some((addr, buf)) = self.queue.front() {
+            match self.sock.send_to(buf, *addr) {
+                Ok(len) => self.sent(len, stats),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    // Socket is not ready anymore, stop writing
+                    break;
+                }
+                Err(e) => self.send_failed(&e, stats),
+            }
+        }
+        Ok(())
+    }
+
+    // Sends the queue a batch at a time. Whatever a batch couldn't send
+    // stays queued, in order, the first failing datagram being dropped
+    #[cfg(target_os = "linux")]
+    fn flush_batch(&mut self, batch: &mut Batch, stats: &mut TransportStats) {
+        while !self.queue.is_empty() {
+            match batch.send(&self.sock, self.queue.make_contiguous()) {
+                Ok(sent) => {
+                    for i in 0..sent {
+                        self.sent(batch.sent_len(i), stats);
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    // Socket is not ready anymore, stop writing
+                    break;
+                }
+                Err(e) => self.send_failed(&e, stats),
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for UdpEcho {
+    fn as_raw_fd(&self) -> RawFd {
+        self.sock.as_raw_fd()
+    }
+}
@@ -0,0 +1,60 @@
+//! Unix stream sockets, for local clients that shouldn't need a TCP port.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::io::OwnedFd;
+use std::path::{Path, PathBuf};
+
+use crate::sys::{accept_unix, bind_unix, evented_fd, recv_fd, send_fd};
+
+/// A non-blocking SOCK_STREAM Unix listener, unlinking its path on drop.
+pub struct UnixListener {
+    fd: OwnedFd,
+    path: PathBuf,
+}
+
+impl UnixListener {
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<UnixListener> {
+        let path = path.as_ref();
+        Ok(UnixListener {
+            fd: bind_unix(path, libc::SOCK_STREAM)?,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Accepts a connection, returning it with the peer's (pid, uid).
+    pub fn accept(&self) -> io::Result<(UnixStream, (i32, u32))> {
+        let (fd, cred) = accept_unix(&self.fd)?;
+        Ok((UnixStream { fd }, cred))
+    }
+}
+
+impl Drop for UnixListener {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// A connected, non-blocking SOCK_STREAM Unix socket.
+pub struct UnixStream {
+    fd: OwnedFd,
+}
+
+impl Read for UnixStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        recv_fd(&self.fd, buf, 0)
+    }
+}
+
+impl Write for UnixStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        send_fd(&self.fd, buf, libc::MSG_NOSIGNAL)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+evented_fd!(UnixListener);
+evented_fd!(UnixStream);
@@ -0,0 +1,190 @@
+//! Handing the listening sockets over to a new binary on SIGUSR2.
+//!
+//! The new process finds them by name in `MIO_ECHO_SERVER_FDS`, e.g.
+//! `listen=3,udp=4`, and writes a byte to the pipe of
+//! `MIO_ECHO_SERVER_READY_FD` once it serves. Both processes accept from
+//! the same sockets until then, so no connection is refused in between.
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+const FDS_VAR: &str = "MIO_ECHO_SERVER_FDS";
+const READY_VAR: &str = "MIO_ECHO_SERVER_READY_FD";
+
+/// The new process is killed if it doesn't serve by then.
+const READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Set by SIGUSR2, taken by `requested`.
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Starts noting SIGUSR2, see `requested`.
+pub fn watch_signal() -> io::Result<()> {
+    crate::signal::watch(libc::SIGUSR2, &REQUESTED)
+}
+
+/// Whether SIGUSR2 arrived since the last call.
+pub fn requested() -> bool {
+    REQUESTED.swap(false, Ordering::Relaxed)
+}
+
+/// The socket handed over as `name` by the process upgraded from.
+pub fn inherited<T: FromRawFd>(name: &str) -> Option<T> {
+    let fds = env::var(FDS_VAR).ok()?;
+    let fd = fds.split(',').find_map(|pair| match pair.split_once('=') {
+        Some((n, fd)) if n == name => fd.parse::<RawFd>().ok(),
+        _ => None,
+    })?;
+    info!("{}: serving the socket handed over as fd {}", name, fd);
+    Some(unsafe { T::from_raw_fd(fd) })
+}
+
+/// Tells the process upgraded from, if any, that this one serves so it
+/// can stop accepting.
+pub fn notify_ready() {
+    env::remove_var(FDS_VAR);
+    let fd = env::var(READY_VAR).ok().and_then(|fd| fd.parse::<RawFd>().ok());
+    env::remove_var(READY_VAR);
+    if let Some(fd) = fd {
+        let mut pipe = unsafe { File::from_raw_fd(fd) };
+        if let Err(e) = pipe.write_all(b"1") {
+            warn!("couldn't tell the old process to stop accepting: {}", e);
+        }
+    }
+}
+
+/// A pipe, the read end non-blocking, both ends closed on exec.
+pub fn pipe() -> io::Result<(File, OwnedFd)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (read, write) = unsafe { (File::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    set_flag(fds[0], libc::F_GETFD, libc::F_SETFD, libc::FD_CLOEXEC)?;
+    set_flag(fds[0], libc::F_GETFL, libc::F_SETFL, libc::O_NONBLOCK)?;
+    set_flag(fds[1], libc::F_GETFD, libc::F_SETFD, libc::FD_CLOEXEC)?;
+    Ok((read, write))
+}
+
+/// Runs `binary` with the arguments of this process, handing it `fds` by
+/// name, and `extra` descriptors it learns of some other way.
+pub fn command(binary: &Path, fds: &[(String, RawFd)], extra: &[RawFd]) -> Command {
+    let names: Vec<String> = fds.iter().map(|(name, fd)| format!("{}={}", name, fd)).collect();
+    let inherit: Vec<RawFd> = fds.iter().map(|&(_, fd)| fd).chain(extra.iter().copied()).collect();
+    let mut command = Command::new(binary);
+    command.args(env::args_os().skip(1)).env(FDS_VAR, names.join(","));
+    unsafe {
+        // Between fork and exec, only the child's descriptors change
+        command.pre_exec(move || {
+            for &fd in &inherit {
+                let flags = libc::fcntl(fd, libc::F_GETFD);
+                if flags < 0 || libc::fcntl(fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    command
+}
+
+/// How a started upgrade is going.
+pub enum Progress {
+    /// None is in flight.
+    Idle,
+    Waiting,
+    /// The new process, with this pid, serves.
+    Ready(u32),
+    Failed(String),
+}
+
+/// Starts the new binary and watches it until it serves.
+pub struct Upgrader {
+    binary: PathBuf,
+    spawned: Option<Spawned>,
+}
+
+struct Spawned {
+    child: Child,
+    /// Read end of the readiness pipe, non-blocking.
+    ready: File,
+    deadline: Instant,
+}
+
+impl Upgrader {
+    pub fn new(binary: PathBuf) -> Upgrader {
+        Upgrader { binary, spawned: None }
+    }
+
+    pub fn in_progress(&self) -> bool {
+        self.spawned.is_some()
+    }
+
+    /// Starts the binary with the arguments of this process, handing it
+    /// `fds` by name, and returns its pid.
+    pub fn start(&mut self, fds: &[(String, RawFd)], now: Instant) -> io::Result<u32> {
+        let (ready, write) = pipe()?;
+        let child = command(&self.binary, fds, &[write.as_raw_fd()])
+            .env(READY_VAR, write.as_raw_fd().to_string())
+            .spawn()?;
+        // Only the child holds the write end now, EOF means it's gone
+        drop(write);
+
+        let pid = child.id();
+        self.spawned = Some(Spawned {
+            child,
+            ready,
+            deadline: now + READY_TIMEOUT,
+        });
+        Ok(pid)
+    }
+
+    /// Checks on the new process, killing it once it's late.
+    pub fn poll(&mut self, now: Instant) -> Progress {
+        let spawned = match self.spawned {
+            Some(ref mut spawned) => spawned,
+            None => return Progress::Idle,
+        };
+        let mut byte = [0; 1];
+        let progress = match spawned.ready.read(&mut byte) {
+            Ok(1) => Progress::Ready(spawned.child.id()),
+            Ok(_) => match spawned.child.try_wait() {
+                Ok(Some(status)) => Progress::Failed(format!("it exited before serving: {}", status)),
+                _ => Progress::Failed(kill(&mut spawned.child, "it closed the readiness pipe")),
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                if now < spawned.deadline {
+                    return Progress::Waiting;
+                }
+                Progress::Failed(kill(&mut spawned.child, "it wasn't serving in time"))
+            }
+            Err(e) => Progress::Failed(kill(&mut spawned.child, &e.to_string())),
+        };
+        self.spawned = None;
+        progress
+    }
+}
+
+fn kill(child: &mut Child, why: &str) -> String {
+    let _ = child.kill();
+    let _ = child.wait();
+    format!("{}, killed it", why)
+}
+
+// Adds `flag` to the descriptor or status flags read by `get` and written
+// by `set`
+pub fn set_flag(fd: RawFd, get: libc::c_int, set: libc::c_int, flag: libc::c_int) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, get) };
+    if flags < 0 || unsafe { libc::fcntl(fd, set, flags | flag) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
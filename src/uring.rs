@@ -0,0 +1,538 @@
+//! Experimental io_uring backend, TCP only.
+//!
+//! Connections keep the `Client` buffering of the mio loop: each one sees
+//! a `Staged` socket, fed by completed receives and drained by sends.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::mem;
+use std::ptr;
+use std::time::{Duration, Instant};
+
+use io_uring::{cqueue, opcode, squeue, types, IoUring};
+use log::{debug, info, warn};
+use mio::event::Source;
+use mio::{Interest, Registry, Token};
+use slab::Slab;
+
+use crate::client::Client;
+use crate::config::Config;
+use crate::reactor::{self, CloseReason, Tick};
+use crate::refusal::{Audit, Load, Refusal};
+use crate::stats::{Stats, Transport};
+use crate::stream::{PeerAddr, Socket, TcpInfo};
+use crate::systemd::Watchdog;
+use crate::Error;
+
+const RING_ENTRIES: u32 = 1024;
+/// Receive buffers lent to the kernel and shared by every connection.
+const RECV_BUFS: u16 = 512;
+const RECV_BUF_SIZE: usize = 16 * 1024;
+const BUF_GROUP: u16 = 0;
+/// Queued echo gathered into one send.
+const MAX_SEND_SIZE: usize = 64 * 1024;
+
+// Operation of a completion, in the low bits of its user data, then the
+// slab index of the connection and the low bits of its id
+const ACCEPT: u64 = 0;
+const RECV: u64 = 1;
+const SEND: u64 = 2;
+const PROVIDE: u64 = 3;
+const OP_BITS: u32 = 2;
+const INDEX_BITS: u32 = 32;
+
+fn user_data(op: u64, index: usize, id: u64) -> u64 {
+    op | (index as u64) << OP_BITS | id << (OP_BITS + INDEX_BITS)
+}
+
+/// The socket of a connection as its `Client` sees it: reads return what
+/// the last receive completed, and writes stage the next send.
+struct Staged {
+    sock: TcpStream,
+    input: Vec<u8>,
+    pos: usize,
+    output: Vec<u8>,
+    /// Takes one write per send, so `Config::max_write_chunk` caps sends.
+    one_write: bool,
+}
+
+impl Read for Staged {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.input.len() - self.pos);
+        if len == 0 {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        buf[..len].copy_from_slice(&self.input[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+impl Write for Staged {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let room = if self.one_write && !self.output.is_empty() {
+            0
+        } else {
+            MAX_SEND_SIZE.saturating_sub(self.output.len())
+        };
+        if room == 0 {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let len = buf.len().min(room);
+        self.output.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Never registered, the ring does the waiting
+impl Source for Staged {
+    fn register(&mut self, _: &Registry, _: Token, _: Interest) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn reregister(&mut self, _: &Registry, _: Token, _: Interest) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn deregister(&mut self, _: &Registry) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Socket for Staged {
+    fn tcp_info(&self) -> Option<TcpInfo> {
+        crate::sys::tcp_info(&self.sock).ok()
+    }
+}
+
+struct Conn {
+    client: Client<Staged>,
+    receiving: bool,
+    /// The kernel reads the staged output until the send completes.
+    sending: bool,
+    /// Set once the connection is shut down, it is dropped when no send
+    /// is in flight.
+    closing: Option<CloseReason>,
+}
+
+/// Serves TCP echo from an io_uring: a multishot accept, receives into
+/// buffers provided to the kernel, one send in flight per connection.
+pub struct Uring {
+    ring: IoUring,
+    listener: TcpListener,
+    /// `RECV_BUFS` buffers of `RECV_BUF_SIZE` bytes, never reallocated.
+    pool: Vec<u8>,
+    conns: Slab<Conn>,
+    /// Receives that found no free buffer, retried as buffers come back.
+    starved: VecDeque<u64>,
+    /// Receives and sends whose completion is still due.
+    inflight: usize,
+    /// Taken from the ring each turn, kept to reuse its allocation.
+    completions: Vec<cqueue::Entry>,
+    config: Config,
+    tick: Option<Tick>,
+    /// The systemd watchdog and when to ping it next.
+    watchdog: Option<(Watchdog, Instant)>,
+    /// Logs the refused connections.
+    audit: Audit,
+    stats: Stats,
+    /// Connections accepted so far.
+    accepted: u64,
+    shutdown: bool,
+}
+
+impl Uring {
+    /// Binds the listener and starts accepting.
+    pub fn new(config: Config, tick: Option<Tick>) -> Result<Uring, Error> {
+        let addr = config
+            .listen
+            .as_ref()
+            .ok_or_else(|| Error::config("the uring backend needs listen"))?;
+        let listener = reactor::bind(addr, config.bind_retry, |addr| TcpListener::bind(addr))?;
+        let watchdog = Watchdog::from_env()?.map(|watchdog| {
+            info!("systemd watchdog enabled, pinging every {:?}", watchdog.interval());
+            watchdog.ping();
+            let at = Instant::now() + watchdog.interval();
+            (watchdog, at)
+        });
+        let ring = IoUring::new(RING_ENTRIES)?;
+        let stats = Stats {
+            max_clients: config.max_clients,
+            ..Stats::default()
+        };
+        let mut uring = Uring {
+            ring,
+            listener,
+            pool: vec![0; RECV_BUFS as usize * RECV_BUF_SIZE],
+            conns: Slab::with_capacity(config.max_clients),
+            starved: VecDeque::new(),
+            inflight: 0,
+            completions: Vec::new(),
+            config,
+            tick,
+            watchdog,
+            audit: Audit::default(),
+            stats,
+            accepted: 0,
+            shutdown: false,
+        };
+        let provide = opcode::ProvideBuffers::new(
+            uring.pool.as_mut_ptr(),
+            RECV_BUF_SIZE as i32,
+            RECV_BUFS,
+            BUF_GROUP,
+            0,
+        );
+        uring.push(provide.build().user_data(PROVIDE))?;
+        uring.accept()?;
+        Ok(uring)
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.local_addr().ok()
+    }
+
+    /// Runs the loop until an error occurs or a tick callback requests a
+    /// shutdown.
+    pub fn run(&mut self) -> Result<(), Error> {
+        while !self.shutdown {
+            self.turn(None)?;
+        }
+        Ok(())
+    }
+
+    /// Waits at most `timeout` for completions, or until the tick or the
+    /// watchdog is due when shorter or `None`, then handles them. Returns
+    /// the number of completions.
+    pub fn turn(&mut self, timeout: Option<Duration>) -> Result<usize, Error> {
+        let now = Instant::now();
+        let tick = self.tick.as_ref().map(Tick::deadline);
+        let deadline = tick.into_iter().chain(self.watchdog.as_ref().map(|&(_, at)| at)).min();
+        let timeout = timeout
+            .into_iter()
+            .chain(deadline.map(|deadline| deadline.saturating_duration_since(now)))
+            .min();
+        let waited = match timeout {
+            Some(timeout) => {
+                let timeout = types::Timespec::from(timeout);
+                let args = types::SubmitArgs::new().timespec(&timeout);
+                self.ring.submitter().submit_with_args(1, &args)
+            }
+            None => self.ring.submit_and_wait(1),
+        };
+        match waited {
+            Ok(_) => {}
+            Err(ref e) if e.raw_os_error() == Some(libc::ETIME) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+        let woken = Instant::now();
+        self.stats.event_loop.waiting += woken - now;
+
+        let mut completions = mem::take(&mut self.completions);
+        completions.extend(self.ring.completion());
+        let count = completions.len();
+        self.stats.event_loop.record_poll(count);
+        for completion in completions.drain(..) {
+            self.complete(&completion)?;
+        }
+        self.completions = completions;
+
+        let now = Instant::now();
+        if let Some(ref mut tick) = self.tick {
+            if tick.deadline() <= now {
+                self.shutdown |= tick.fire(&self.stats, now);
+            }
+        }
+        if let Some((ref watchdog, ref mut at)) = self.watchdog {
+            if *at <= now {
+                watchdog.ping();
+                *at = now + watchdog.interval();
+            }
+        }
+        self.stats.event_loop.busy += now - woken;
+        Ok(count)
+    }
+
+    /// Shuts every connection down and waits for the kernel to be done
+    /// with their buffers. Closing again does nothing.
+    pub fn close(&mut self) {
+        self.shutdown = true;
+        let indexes: Vec<usize> = self.conns.iter().map(|(index, _)| index).collect();
+        for index in indexes {
+            self.close_conn(index, CloseReason::Eof);
+        }
+        while self.inflight > 0 {
+            if let Err(e) = self.ring.submit_and_wait(1) {
+                if e.kind() != io::ErrorKind::Interrupted {
+                    // Leak the buffers rather than let the kernel write
+                    // into freed memory
+                    warn!("waiting for io_uring completions failed: {}", e);
+                    mem::forget(mem::take(&mut self.pool));
+                    for conn in self.conns.drain() {
+                        mem::forget(conn);
+                    }
+                    return;
+                }
+            }
+            let completions: Vec<_> = self.ring.completion().collect();
+            for completion in completions {
+                if let Err(e) = self.complete(&completion) {
+                    debug!("completion failed while closing: {}", e);
+                }
+            }
+        }
+    }
+
+    fn complete(&mut self, completion: &cqueue::Entry) -> Result<(), Error> {
+        let (data, res) = (completion.user_data(), completion.result());
+        match data & ((1 << OP_BITS) - 1) {
+            ACCEPT => {
+                if !cqueue::more(completion.flags()) && !self.shutdown {
+                    self.accept()?;
+                }
+                if res < 0 {
+                    return Err(io::Error::from_raw_os_error(-res).into());
+                }
+                // Safety: the accepted descriptor is ours alone
+                self.accepted(unsafe { TcpStream::from_raw_fd(res) })?;
+            }
+            RECV => {
+                self.inflight -= 1;
+                self.received(data, res, cqueue::buffer_select(completion.flags()))?;
+            }
+            SEND => {
+                self.inflight -= 1;
+                self.sent(data, res)?;
+            }
+            _ => {
+                if res < 0 {
+                    let e = io::Error::from_raw_os_error(-res);
+                    return Err(Error::system("providing receive buffers", e));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn accepted(&mut self, sock: TcpStream) -> Result<(), Error> {
+        let addr = match sock.peer_addr() {
+            Ok(addr) => PeerAddr::Inet(addr),
+            // Reset before it could be looked at
+            Err(_) => return Ok(()),
+        };
+        if self.conns.len() >= self.config.max_clients || self.shutdown {
+            // Dropping the socket refuses this client only
+            let reason = if self.shutdown { Refusal::Draining } else { Refusal::Capacity };
+            let load = Load {
+                clients: self.conns.len(),
+                max_clients: self.config.max_clients,
+                pending: 0,
+            };
+            self.audit.record(&mut self.stats, Instant::now(), &addr, reason, load);
+            self.stats.tcp.rejected += 1;
+            return Ok(());
+        }
+        self.accepted += 1;
+        info!("connection established : {} (#{})", addr, self.accepted);
+        self.stats.tcp.connections += 1;
+
+        let staged = Staged {
+            sock,
+            input: Vec::new(),
+            pos: 0,
+            output: Vec::new(),
+            one_write: self.config.max_write_chunk.is_some(),
+        };
+        let mut client = Client::new(staged, addr, Transport::Tcp, self.config.max_write_chunk, VecDeque::new());
+        client.dump_limit = self.config.dump_limit;
+        client.id = self.accepted;
+        if let Some(ref banner) = self.config.banner {
+            client.bufs.push_back(banner.clone());
+        }
+        let index = self.conns.insert(Conn {
+            client,
+            receiving: false,
+            sending: false,
+            closing: None,
+        });
+        self.recv(index)?;
+        self.flush(index)
+    }
+
+    fn received(&mut self, data: u64, res: i32, buf: Option<u16>) -> Result<(), Error> {
+        let index = match self.conn_index(data) {
+            Some(index) if self.conns[index].closing.is_none() => index,
+            // Shut down by us, the connection may already be gone
+            _ => {
+                if let Some(buf) = buf {
+                    self.provide(buf)?;
+                }
+                return Ok(());
+            }
+        };
+        let conn = &mut self.conns[index];
+        conn.receiving = false;
+        if res == -libc::ENOBUFS {
+            self.starved.push_back(data);
+            return Ok(());
+        }
+        if res < 0 {
+            let reason = reactor::io_error(&io::Error::from_raw_os_error(-res), &conn.client);
+            self.close_conn(index, reason);
+            return Ok(());
+        }
+        if res == 0 {
+            self.close_conn(index, CloseReason::Eof);
+            return Ok(());
+        }
+
+        let buf = buf.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "receive completed without a buffer"))?;
+        let start = buf as usize * RECV_BUF_SIZE;
+        let staged = conn.client.sock_mut();
+        staged.input.extend_from_slice(&self.pool[start..start + res as usize]);
+        staged.pos = 0;
+        // Staged reads never fail nor see the end of the stream
+        if let Ok(Some(len)) = conn.client.read() {
+            info!("read {} bytes : {}", len, conn.client.peer_addr());
+            self.stats.tcp.bytes_read += len as u64;
+        }
+        conn.client.sock_mut().input.clear();
+        self.provide(buf)?;
+        self.recv(index)?;
+        self.flush(index)
+    }
+
+    fn sent(&mut self, data: u64, res: i32) -> Result<(), Error> {
+        // A connection stays until its send completes
+        let index = match self.conn_index(data) {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        let conn = &mut self.conns[index];
+        conn.sending = false;
+        if conn.closing.is_some() {
+            self.close_conn(index, CloseReason::Eof);
+            return Ok(());
+        }
+        if res < 0 {
+            let reason = reactor::io_error(&io::Error::from_raw_os_error(-res), &conn.client);
+            self.close_conn(index, reason);
+            return Ok(());
+        }
+        let len = res as usize;
+        info!("write {} bytes : {}", len, conn.client.peer_addr());
+        self.stats.tcp.bytes_written += len as u64;
+        conn.client.sock_mut().output.drain(..len);
+        self.flush(index)
+    }
+
+    // Stages the next write of the client and sends it, unless a send is
+    // in flight
+    fn flush(&mut self, index: usize) -> Result<(), Error> {
+        let conn = &mut self.conns[index];
+        if conn.sending || conn.closing.is_some() {
+            return Ok(());
+        }
+        if conn.client.sock_mut().output.is_empty() {
+            // Staged writes never fail
+            let _ = conn.client.write(false);
+        }
+        let staged = conn.client.sock_mut();
+        if staged.output.is_empty() {
+            return Ok(());
+        }
+        let (fd, output) = (types::Fd(staged.sock.as_raw_fd()), &staged.output);
+        let send = opcode::Send::new(fd, output.as_ptr(), output.len() as u32);
+        let data = user_data(SEND, index, conn.client.id);
+        conn.sending = true;
+        self.inflight += 1;
+        self.push(send.build().user_data(data))
+    }
+
+    fn recv(&mut self, index: usize) -> Result<(), Error> {
+        let conn = &mut self.conns[index];
+        let fd = types::Fd(conn.client.sock_mut().sock.as_raw_fd());
+        let recv = opcode::Recv::new(fd, ptr::null_mut(), RECV_BUF_SIZE as u32).buf_group(BUF_GROUP);
+        let data = user_data(RECV, index, conn.client.id);
+        conn.receiving = true;
+        self.inflight += 1;
+        self.push(recv.build().flags(squeue::Flags::BUFFER_SELECT).user_data(data))
+    }
+
+    // Hands a receive buffer back to the kernel, and to a starved receive
+    fn provide(&mut self, buf: u16) -> Result<(), Error> {
+        let addr = self.pool[buf as usize * RECV_BUF_SIZE..].as_mut_ptr();
+        let provide = opcode::ProvideBuffers::new(addr, RECV_BUF_SIZE as i32, 1, BUF_GROUP, buf);
+        self.push(provide.build().user_data(PROVIDE))?;
+        while let Some(data) = self.starved.pop_front() {
+            if let Some(index) = self.conn_index(data) {
+                if self.conns[index].closing.is_none() {
+                    return self.recv(index);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn accept(&mut self) -> Result<(), Error> {
+        let accept = opcode::AcceptMulti::new(types::Fd(self.listener.as_raw_fd()));
+        self.push(accept.build().user_data(ACCEPT))
+    }
+
+    // Shuts the connection down, which completes its pending receive, and
+    // drops it once the kernel is done with its output
+    fn close_conn(&mut self, index: usize, reason: CloseReason) {
+        let conn = &mut self.conns[index];
+        if conn.closing.is_none() {
+            conn.closing = Some(reason);
+            let _ = conn.client.sock_mut().sock.shutdown(Shutdown::Both);
+        }
+        if conn.sending {
+            return;
+        }
+        let conn = self.conns.remove(index);
+        let reason = conn.closing.unwrap_or(reason);
+        reactor::record_close(&mut self.stats, &conn.client, reason);
+    }
+
+    fn conn_index(&self, data: u64) -> Option<usize> {
+        let index = (data >> OP_BITS) as u32 as usize;
+        match self.conns.get(index) {
+            Some(conn) if user_data(data & ((1 << OP_BITS) - 1), index, conn.client.id) == data => Some(index),
+            _ => None,
+        }
+    }
+
+    // Submits when the queue is full to make room
+    fn push(&mut self, entry: squeue::Entry) -> Result<(), Error> {
+        loop {
+            // Safety: the buffers of an entry are in the pool or in the
+            // staged output of a connection, both kept until it completes
+            if unsafe { self.ring.submission().push(&entry) }.is_ok() {
+                return Ok(());
+            }
+            self.ring.submit()?;
+        }
+    }
+}
+
+impl Drop for Uring {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
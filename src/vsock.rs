@@ -0,0 +1,90 @@
+//! AF_VSOCK sockets, which mio doesn't provide.
+
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+
+use crate::sys::{cvt, evented_fd, recv_fd, send_fd};
+
+fn sockaddr(cid: u32, port: u32) -> libc::sockaddr_vm {
+    // The struct has private padding, start from all zeroes
+    let mut addr: libc::sockaddr_vm = unsafe { mem::zeroed() };
+    addr.svm_family = libc::AF_VSOCK as libc::sa_family_t;
+    addr.svm_cid = cid;
+    addr.svm_port = port;
+    addr
+}
+
+/// A non-blocking vsock listener.
+pub struct VsockListener {
+    fd: OwnedFd,
+}
+
+impl VsockListener {
+    /// Listens on `port` of any local CID.
+    pub fn bind(port: u32) -> io::Result<VsockListener> {
+        let fd = cvt(unsafe {
+            libc::socket(
+                libc::AF_VSOCK,
+                libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                0,
+            )
+        })?;
+        let listener = VsockListener {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        };
+
+        let addr = sockaddr(libc::VMADDR_CID_ANY, port);
+        cvt(unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_vm as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t,
+            )
+        })?;
+        cvt(unsafe { libc::listen(fd, 1024) })?;
+        Ok(listener)
+    }
+
+    /// Accepts a connection, returning it with the peer's (cid, port).
+    pub fn accept(&self) -> io::Result<(VsockStream, (u32, u32))> {
+        let mut addr = sockaddr(0, 0);
+        let mut len = mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t;
+        let fd = cvt(unsafe {
+            libc::accept4(
+                self.fd.as_raw_fd(),
+                &mut addr as *mut libc::sockaddr_vm as *mut libc::sockaddr,
+                &mut len,
+                libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            )
+        })?;
+        let stream = VsockStream {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        };
+        Ok((stream, (addr.svm_cid, addr.svm_port)))
+    }
+}
+
+/// A connected, non-blocking vsock stream.
+pub struct VsockStream {
+    fd: OwnedFd,
+}
+
+impl Read for VsockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        recv_fd(&self.fd, buf, 0)
+    }
+}
+
+impl Write for VsockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        send_fd(&self.fd, buf, libc::MSG_NOSIGNAL)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+evented_fd!(VsockListener);
+evented_fd!(VsockStream);
@@ -0,0 +1,321 @@
+//! Serving from several worker processes sharing the listening sockets,
+//! see `Config::workers_processes`.
+//!
+//! The parent binds the sockets and starts the workers as copies of
+//! itself, with `MIO_ECHO_SERVER_WORKER` set and the sockets handed over
+//! as an upgrade would. Each worker writes its totals to the pipe of
+//! `MIO_ECHO_SERVER_SUMMARY_FD` as it exits, one line of `key=value`
+//! pairs.
+
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::path::Path;
+use std::process::Child;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+
+use crate::config::Config;
+use crate::reactor::{bind, bind_tcp, bind_udp};
+use crate::stats::Stats;
+use crate::systemd::Watchdog;
+use crate::upgrade;
+use crate::Error;
+
+const WORKER_VAR: &str = "MIO_ECHO_SERVER_WORKER";
+const SUMMARY_VAR: &str = "MIO_ECHO_SERVER_SUMMARY_FD";
+
+/// How often the parent checks on the workers and for signals.
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+/// Delay before restarting a failed worker, doubling with each failure
+/// up to the max.
+const RESPAWN_MIN: Duration = Duration::from_millis(100);
+const RESPAWN_MAX: Duration = Duration::from_secs(10);
+/// A worker failing after running this long is restarted after the
+/// shortest delay again.
+const STABLE_AFTER: Duration = Duration::from_secs(10);
+/// Workers still running this long after their drain timeout are killed.
+const KILL_GRACE: Duration = Duration::from_secs(5);
+
+/// Set by SIGTERM and SIGINT, taken by `terminate_requested`.
+static TERMINATE: AtomicBool = AtomicBool::new(false);
+
+/// Whether this process was started as a worker.
+pub fn is_worker() -> bool {
+    env::var_os(WORKER_VAR).is_some()
+}
+
+/// Starts noting SIGTERM and SIGINT, see `terminate_requested`.
+pub fn watch_terminate() -> io::Result<()> {
+    crate::signal::watch(libc::SIGTERM, &TERMINATE)?;
+    crate::signal::watch(libc::SIGINT, &TERMINATE)
+}
+
+/// Whether SIGTERM or SIGINT arrived since the last call.
+pub fn terminate_requested() -> bool {
+    TERMINATE.swap(false, Ordering::Relaxed)
+}
+
+/// Hands the totals of `stats` to the parent, when serving as a worker.
+pub fn report(stats: &Stats) {
+    let fd = match env::var(SUMMARY_VAR).ok().and_then(|fd| fd.parse::<RawFd>().ok()) {
+        Some(fd) => fd,
+        None => return,
+    };
+    let mut pipe = unsafe { File::from_raw_fd(fd) };
+    if let Err(e) = writeln!(pipe, "{}", Summary::of(stats).encode()) {
+        warn!("couldn't report to the parent process: {}", e);
+    }
+}
+
+/// What a worker served, over every transport.
+#[derive(Clone, Copy, Default)]
+struct Summary {
+    connections: u64,
+    datagrams: u64,
+    bytes_read: u64,
+    bytes_written: u64,
+    rejected: u64,
+    empty_accepts: u64,
+}
+
+impl Summary {
+    fn of(stats: &Stats) -> Summary {
+        let mut summary = Summary::default();
+        for transport in &[stats.tcp, stats.udp, stats.vsock, stats.seqpacket, stats.unix, stats.sctp, stats.pipe] {
+            summary.connections += transport.connections;
+            summary.datagrams += transport.datagrams;
+            summary.bytes_read += transport.bytes_read;
+            summary.bytes_written += transport.bytes_written;
+            summary.rejected += transport.rejected;
+        }
+        summary.empty_accepts = stats.event_loop.empty_accepts;
+        summary
+    }
+
+    fn encode(&self) -> String {
+        format!(
+            "connections={} datagrams={} bytes_read={} bytes_written={} rejected={} empty_accepts={}",
+            self.connections, self.datagrams, self.bytes_read, self.bytes_written, self.rejected, self.empty_accepts
+        )
+    }
+
+    // Unknown keys are skipped, `None` if nothing was understood
+    fn decode(line: &str) -> Option<Summary> {
+        let mut summary = Summary::default();
+        let mut known = false;
+        for pair in line.split_whitespace() {
+            let (key, value) = pair.split_once('=')?;
+            let value: u64 = value.parse().ok()?;
+            let field = match key {
+                "connections" => &mut summary.connections,
+                "datagrams" => &mut summary.datagrams,
+                "bytes_read" => &mut summary.bytes_read,
+                "bytes_written" => &mut summary.bytes_written,
+                "rejected" => &mut summary.rejected,
+                "empty_accepts" => &mut summary.empty_accepts,
+                _ => continue,
+            };
+            *field = value;
+            known = true;
+        }
+        Some(summary).filter(|_| known)
+    }
+
+    fn add(&mut self, other: &Summary) {
+        self.connections += other.connections;
+        self.datagrams += other.datagrams;
+        self.bytes_read += other.bytes_read;
+        self.bytes_written += other.bytes_written;
+        self.rejected += other.rejected;
+        self.empty_accepts += other.empty_accepts;
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} connections, {} datagrams, {} bytes read, {} bytes written, {} rejected, {} empty accepts",
+            self.connections, self.datagrams, self.bytes_read, self.bytes_written, self.rejected, self.empty_accepts
+        )
+    }
+}
+
+/// A worker slot of the parent.
+struct Worker {
+    index: usize,
+    running: Option<Running>,
+    /// When to start it again after a failure.
+    respawn_at: Option<Instant>,
+    backoff: Duration,
+}
+
+struct Running {
+    child: Child,
+    /// Read end of the summary pipe, non-blocking.
+    summary: File,
+    started: Instant,
+}
+
+/// Binds the sockets of `config`, serves them from
+/// `Config::workers_processes` workers until they all stop, and prints
+/// what they served.
+///
+/// A worker exiting with a failure is restarted, after a delay growing
+/// while it keeps failing. SIGTERM and SIGINT are forwarded to the
+/// workers as SIGTERM, which drains them; those still running
+/// `Config::drain_timeout` and a grace period later are killed.
+pub fn supervise(config: &Config) -> Result<(), Error> {
+    let binary = env::current_exe()?;
+    let sockets = bind_sockets(config)?;
+    let fds: Vec<(String, RawFd)> = sockets.iter().map(|(name, fd)| (name.clone(), fd.as_raw_fd())).collect();
+    watch_terminate()?;
+    // systemd watches this process, the workers don't get WATCHDOG_USEC
+    let watchdog = Watchdog::from_env()?;
+    if let Some(ref watchdog) = watchdog {
+        info!("systemd watchdog enabled, pinging every {:?}", watchdog.interval());
+    }
+    let mut ping_at = Instant::now();
+
+    let mut workers = Vec::new();
+    for index in 0..config.workers_processes.unwrap_or(1) {
+        workers.push(Worker {
+            index,
+            running: Some(spawn(&binary, &fds, index)?),
+            respawn_at: None,
+            backoff: RESPAWN_MIN,
+        });
+    }
+
+    let mut total = Summary::default();
+    // Set once terminating, when the remaining workers get killed
+    let mut kill_at: Option<Instant> = None;
+    loop {
+        let now = Instant::now();
+        if let Some(ref watchdog) = watchdog {
+            if now >= ping_at {
+                watchdog.ping();
+                ping_at = now + watchdog.interval();
+            }
+        }
+        if terminate_requested() && kill_at.is_none() {
+            info!("terminating, draining the workers");
+            for running in workers.iter().filter_map(|worker| worker.running.as_ref()) {
+                signal(&running.child, libc::SIGTERM);
+            }
+            kill_at = Some(now + config.drain_timeout + KILL_GRACE);
+        }
+        if kill_at.is_some_and(|deadline| now >= deadline) {
+            for running in workers.iter().filter_map(|worker| worker.running.as_ref()) {
+                warn!("worker pid {} still running, killing it", running.child.id());
+                signal(&running.child, libc::SIGKILL);
+            }
+        }
+
+        for worker in &mut workers {
+            if let Some(ref mut running) = worker.running {
+                let status = match running.child.try_wait()? {
+                    Some(status) => status,
+                    None => continue,
+                };
+                let summary = read_summary(&mut running.summary);
+                match summary {
+                    Some(ref summary) => {
+                        info!("worker {} (pid {}) exited: {}; {}", worker.index, running.child.id(), status, summary);
+                        total.add(summary);
+                    }
+                    None => info!("worker {} (pid {}) exited: {}", worker.index, running.child.id(), status),
+                }
+                let lived = now - running.started;
+                worker.running = None;
+                if status.success() || kill_at.is_some() {
+                    continue;
+                }
+                if lived >= STABLE_AFTER {
+                    worker.backoff = RESPAWN_MIN;
+                }
+                warn!("worker {} failed, restarting it in {:?}", worker.index, worker.backoff);
+                worker.respawn_at = Some(now + worker.backoff);
+                worker.backoff = (worker.backoff * 2).min(RESPAWN_MAX);
+            } else if let Some(at) = worker.respawn_at {
+                if kill_at.is_some() {
+                    worker.respawn_at = None;
+                } else if now >= at {
+                    match spawn(&binary, &fds, worker.index) {
+                        Ok(running) => {
+                            worker.running = Some(running);
+                            worker.respawn_at = None;
+                        }
+                        Err(e) => {
+                            error!("restarting worker {} failed: {}", worker.index, e);
+                            worker.respawn_at = Some(now + worker.backoff);
+                            worker.backoff = (worker.backoff * 2).min(RESPAWN_MAX);
+                        }
+                    }
+                }
+            }
+        }
+
+        if workers.iter().all(|worker| worker.running.is_none() && worker.respawn_at.is_none()) {
+            break;
+        }
+        thread::sleep(CHECK_INTERVAL);
+    }
+    println!("workers: {}", total);
+    Ok(())
+}
+
+// The sockets the workers share, by the names they look them up with
+fn bind_sockets(config: &Config) -> Result<Vec<(String, OwnedFd)>, Error> {
+    let owned = |fd: RawFd| unsafe { OwnedFd::from_raw_fd(fd) };
+    let mut sockets = Vec::new();
+    if let Some(ref addr) = config.listen {
+        let listener = bind(addr, config.bind_retry, |addr| bind_tcp(addr, config.freebind))?;
+        sockets.push(("listen".to_string(), owned(listener.into_raw_fd())));
+    }
+    for (index, entry) in config.listeners.iter().enumerate() {
+        let listener = bind(&entry.addr, config.bind_retry, |addr| bind_tcp(addr, config.freebind))?;
+        sockets.push((format!("listener.{}", index), owned(listener.into_raw_fd())));
+    }
+    if let Some(ref addr) = config.udp {
+        let sock = bind(addr, config.bind_retry, |addr| bind_udp(addr, config.freebind))?;
+        sockets.push(("udp".to_string(), owned(sock.into_raw_fd())));
+    }
+    Ok(sockets)
+}
+
+fn spawn(binary: &Path, fds: &[(String, RawFd)], index: usize) -> Result<Running, Error> {
+    let (summary, write) = upgrade::pipe()?;
+    let child = upgrade::command(binary, fds, &[write.as_raw_fd()])
+        .env(WORKER_VAR, index.to_string())
+        .env(SUMMARY_VAR, write.as_raw_fd().to_string())
+        .env_remove("WATCHDOG_USEC")
+        .spawn()?;
+    // Only the worker holds the write end now, EOF means it's gone
+    drop(write);
+    info!("worker {} started, pid {}", index, child.id());
+    Ok(Running {
+        child,
+        summary,
+        started: Instant::now(),
+    })
+}
+
+// What an exited worker wrote to its pipe, nothing if it died first
+fn read_summary(pipe: &mut File) -> Option<Summary> {
+    let mut line = String::new();
+    pipe.read_to_string(&mut line).ok()?;
+    Summary::decode(line.trim())
+}
+
+fn signal(child: &Child, signum: libc::c_int) {
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, signum);
+    }
+}
@@ -0,0 +1,118 @@
+//! Bookkeeping of the buffers written with MSG_ZEROCOPY, see
+//! `Config::zerocopy`.
+//!
+//! The kernel sends straight from such a buffer, so it must stay
+//! allocated and unchanged until the kernel reports, on the error queue of
+//! the socket, that it is done with every send that read from it. The
+//! kernel numbers the sends of a socket from 0 and reports them in ranges.
+
+use std::collections::VecDeque;
+use std::ops::Range;
+
+// A buffer the kernel may still read from
+struct Pinned {
+    /// `None` while it is still the front of the client's queue.
+    buf: Option<Vec<u8>>,
+    /// Sends that read from it.
+    sends: Range<u64>,
+    /// How many of them the kernel reported done.
+    done: u64,
+}
+
+impl Pinned {
+    fn released(&self) -> bool {
+        self.buf.is_some() && self.done == self.sends.end - self.sends.start
+    }
+}
+
+/// The zerocopy sends of a client and the buffers they pin.
+pub struct ZeroCopy {
+    /// Buffers at least this large are written with MSG_ZEROCOPY.
+    pub threshold: usize,
+    /// Sends made, which is also the number of the next one, the kernel
+    /// counting modulo 2^32.
+    sent: u64,
+    pinned: VecDeque<Pinned>,
+    /// Sends reported done which the kernel copied after all, e.g. over
+    /// the loopback, reset by their reader.
+    pub copied: u64,
+}
+
+impl ZeroCopy {
+    pub fn new(threshold: usize) -> ZeroCopy {
+        ZeroCopy {
+            threshold,
+            sent: 0,
+            pinned: VecDeque::new(),
+            copied: 0,
+        }
+    }
+
+    /// Records a send from the front buffer of the queue.
+    pub fn sent(&mut self) {
+        match self.pinned.back_mut() {
+            Some(front) if front.buf.is_none() => front.sends.end += 1,
+            _ => self.pinned.push_back(Pinned {
+                buf: None,
+                sends: self.sent..self.sent + 1,
+                done: 0,
+            }),
+        }
+        self.sent += 1;
+    }
+
+    /// Whether the front buffer of the queue was sent from, and so must
+    /// not be changed or freed.
+    pub fn front_pinned(&self) -> bool {
+        self.pinned.back().is_some_and(|front| front.buf.is_none())
+    }
+
+    /// Takes the front buffer once it is written, holding it until its
+    /// sends are done if any read from it.
+    pub fn retire(&mut self, buf: Vec<u8>) {
+        if let Some(front) = self.pinned.back_mut().filter(|front| front.buf.is_none()) {
+            front.buf = Some(buf);
+            self.release();
+        }
+    }
+
+    /// Records the kernel's report that sends `lo` to `hi` are done,
+    /// freeing the buffers no send reads from anymore.
+    pub fn complete(&mut self, lo: u32, hi: u32, copied: bool) {
+        if self.sent == 0 {
+            return;
+        }
+        let (lo, hi) = (self.unwrap(lo), self.unwrap(hi) + 1);
+        for pinned in &mut self.pinned {
+            let start = pinned.sends.start.max(lo);
+            let end = pinned.sends.end.min(hi);
+            if start < end {
+                pinned.done += end - start;
+            }
+        }
+        if copied {
+            self.copied += hi.saturating_sub(lo);
+        }
+        self.release();
+    }
+
+    // The send number `seq` was truncated from, the latest one it can be
+    fn unwrap(&self, seq: u32) -> u64 {
+        let last = self.sent - 1;
+        last.saturating_sub(u64::from((last as u32).wrapping_sub(seq)))
+    }
+
+    fn release(&mut self) {
+        self.pinned.retain(|pinned| !pinned.released());
+    }
+
+    /// Whether no buffer is pinned anymore.
+    pub fn is_empty(&self) -> bool {
+        self.pinned.is_empty()
+    }
+
+    /// Bytes of the buffers held for the kernel, the front one excluded.
+    pub fn held_bytes(&self) -> usize {
+        self.pinned.iter().filter_map(|pinned| pinned.buf.as_ref()).map(Vec::capacity).sum()
+    }
+}
@@ -0,0 +1,135 @@
+//! `Config::access_log`, read back after a few scripted connections.
+
+mod driver;
+
+use std::fs;
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use mio_echo_server::{AccessLogFormat, Config, ManualClock, Server};
+use socket2::SockRef;
+
+use driver::{connect, poll_until, receive, send};
+
+fn log_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("mio-echo-server-{}-{}.log", name, std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+fn echo(server: &mut Server, client: &mut TcpStream, data: &[u8]) {
+    send(server, client, data);
+    assert_eq!(receive(server, client, data.len()), data);
+}
+
+/// The fields of a line after the accept time: peer, duration, bytes in,
+/// bytes out, reason and connection id.
+type Line = (String, String, u64, u64, String, u64);
+
+// Three connections closed in turn, by EOF after 2.5s, by a reset and by
+// the server closing, logged to `path` in `format`. Returns the local
+// addresses of the clients.
+fn scripted(path: &Path, format: AccessLogFormat) -> Vec<String> {
+    let config = Config {
+        access_log: Some(path.to_path_buf()),
+        access_log_format: format,
+        ..Config::new("127.0.0.1:0")
+    };
+    let clock = ManualClock::new();
+    let mut server = Server::builder(config).clock(clock.clone()).build().unwrap();
+
+    let mut eof = connect(&server);
+    echo(&mut server, &mut eof, b"hello");
+    clock.advance(Duration::from_millis(2500));
+    echo(&mut server, &mut eof, b"again");
+    let mut peers = vec![eof.local_addr().unwrap().to_string()];
+    drop(eof);
+    for _ in 0..10 {
+        server.poll_once(Some(Duration::from_millis(10))).unwrap();
+    }
+
+    let mut reset = connect(&server);
+    echo(&mut server, &mut reset, b"abc");
+    peers.push(reset.local_addr().unwrap().to_string());
+    // Closing with SO_LINGER 0 sends a RST rather than a FIN
+    SockRef::from(&reset).set_linger(Some(Duration::from_secs(0))).unwrap();
+    drop(reset);
+    poll_until(&mut server, |server| Some(()).filter(|()| server.stats().tcp.resets == 1));
+
+    let mut left = connect(&server);
+    echo(&mut server, &mut left, b"ping");
+    peers.push(left.local_addr().unwrap().to_string());
+    clock.advance(Duration::from_secs(1));
+    // Joins the writer thread, which flushes
+    server.close();
+    peers
+}
+
+fn check(lines: &[Line], peers: &[String]) {
+    let fields: Vec<_> = lines.iter().map(|line| (&line.0[..], &line.1[..], line.2, line.3, &line.4[..])).collect();
+    assert_eq!(
+        fields,
+        [
+            (&peers[0][..], "2.500", 10, 10, "eof"),
+            (&peers[1][..], "0.000", 3, 3, "reset: connection reset"),
+            (&peers[2][..], "1.000", 4, 4, "shutdown"),
+        ]
+    );
+    let ids: Vec<_> = lines.iter().map(|line| line.5).collect();
+    assert!(ids[0] < ids[1] && ids[1] < ids[2], "ids out of order: {:?}", ids);
+}
+
+#[test]
+fn text_lines() {
+    let path = log_path("access-text");
+    let peers = scripted(&path, AccessLogFormat::Text);
+
+    let log = fs::read_to_string(&path).unwrap();
+    let lines: Vec<Line> = log
+        .lines()
+        .map(|line| {
+            let (fields, rest) = line.split_once(" \"").unwrap();
+            let (reason, id) = rest.split_once("\" ").unwrap();
+            let fields: Vec<_> = fields.split(' ').collect();
+            assert_eq!(fields.len(), 5, "{}", line);
+            assert!(fields[0].ends_with('Z'), "accepted at {}", fields[0]);
+            (
+                fields[1].to_string(),
+                fields[2].to_string(),
+                fields[3].parse().unwrap(),
+                fields[4].parse().unwrap(),
+                reason.to_string(),
+                id.parse().unwrap(),
+            )
+        })
+        .collect();
+    check(&lines, &peers);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn csv_lines() {
+    let path = log_path("access-csv");
+    let peers = scripted(&path, AccessLogFormat::Csv);
+
+    let log = fs::read_to_string(&path).unwrap();
+    let mut lines = log.lines();
+    assert_eq!(lines.next(), Some("accepted,peer,duration,bytes_in,bytes_out,reason,id"));
+    let lines: Vec<Line> = lines
+        .map(|line| {
+            let fields: Vec<_> = line.split(',').collect();
+            assert_eq!(fields.len(), 7, "{}", line);
+            (
+                fields[1].to_string(),
+                fields[2].to_string(),
+                fields[3].parse().unwrap(),
+                fields[4].parse().unwrap(),
+                fields[5].to_string(),
+                fields[6].parse().unwrap(),
+            )
+        })
+        .collect();
+    check(&lines, &peers);
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,138 @@
+//! The admin socket, driven over TCP as an operator would.
+
+#[path = "../benches/support/mod.rs"]
+mod support;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use mio_echo_server::{Config, ListenerConfig};
+use support::TestServer;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A connection to the admin socket, one reply line per command.
+struct Admin {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Admin {
+    fn connect(addr: SocketAddr) -> Admin {
+        let writer = TcpStream::connect(addr).unwrap();
+        writer.set_read_timeout(Some(TIMEOUT)).unwrap();
+        Admin {
+            reader: BufReader::new(writer.try_clone().unwrap()),
+            writer,
+        }
+    }
+
+    fn command(&mut self, line: &str) -> String {
+        writeln!(self.writer, "{}", line).unwrap();
+        let mut reply = String::new();
+        self.reader.read_line(&mut reply).unwrap();
+        assert!(reply.ends_with('\n'), "no reply to {}", line);
+        reply.trim_end().to_string()
+    }
+}
+
+fn with_admin() -> (TestServer, Admin) {
+    with_listeners(0)
+}
+
+// With `entries` entries in `Config::listeners`
+fn with_listeners(entries: usize) -> (TestServer, Admin) {
+    let mut config = Config::new("127.0.0.1:0");
+    config.admin_addr = Some("127.0.0.1:0".to_string());
+    config.listeners = (0..entries).map(|_| ListenerConfig::new("127.0.0.1:0")).collect();
+    let server = TestServer::with_config(config);
+    let admin = Admin::connect(server.admin_addr.expect("no admin socket"));
+    (server, admin)
+}
+
+// The number right before the first `suffix` of `line`
+fn number_before(line: &str, suffix: &str) -> u64 {
+    let end = line.find(suffix).unwrap_or_else(|| panic!("no {:?} in {:?}", suffix, line));
+    let start = line[..end].rfind(|c: char| !c.is_ascii_digit()).map_or(0, |i| i + 1);
+    line[start..end].parse().unwrap()
+}
+
+fn echo(stream: &mut TcpStream, message: &[u8]) {
+    stream.write_all(message).unwrap();
+    let mut reply = vec![0; message.len()];
+    stream.read_exact(&mut reply).unwrap();
+    assert_eq!(reply, message);
+}
+
+#[test]
+fn stats_follow_the_echo() {
+    let (server, mut admin) = with_admin();
+    let before = admin.command("stats");
+    assert_eq!(number_before(&before, " connections"), 0);
+    assert_eq!(number_before(&before, " bytes read"), 0);
+
+    let mut client = TcpStream::connect(server.addr).unwrap();
+    client.set_read_timeout(Some(TIMEOUT)).unwrap();
+    for _ in 0..20 {
+        echo(&mut client, b"0123456789");
+    }
+    let after = admin.command("stats");
+    assert_eq!(number_before(&after, " connections"), 1);
+    assert_eq!(number_before(&after, " bytes read"), 200);
+    assert_eq!(number_before(&after, " bytes written"), 200);
+
+    // The loop woke for each message at least, and found events each time
+    let polls = number_before(&after, " polls");
+    let events = number_before(&after, " events");
+    assert!(polls > number_before(&before, " polls"));
+    assert!(events >= number_before(&before, " events") + 20, "{} events", events);
+    assert!(polls >= number_before(&after, " empty)") + 20);
+
+    // The same stats as the server stops with, but for what came since
+    let stats = server.stop();
+    assert_eq!(stats.tcp.bytes_read, 200);
+    assert!(stats.event_loop.events >= events);
+}
+
+#[test]
+fn a_misspelt_command_gets_an_error() {
+    let (_server, mut admin) = with_admin();
+    assert_eq!(admin.command("stat"), "error: unknown command: stat");
+    assert!(admin.command("stats").starts_with("tcp: 0 connections"));
+}
+
+#[test]
+fn listener_stats_count_the_kicks_of_each_listener() {
+    let (server, mut admin) = with_listeners(2);
+    let addrs = &server.listener_addrs;
+    let mut clients: Vec<TcpStream> = [addrs[0], addrs[1], addrs[1]]
+        .iter()
+        .map(|&addr| TcpStream::connect(addr).unwrap())
+        .collect();
+    for client in &mut clients {
+        client.set_read_timeout(Some(TIMEOUT)).unwrap();
+        echo(client, b"hello");
+    }
+    assert_eq!(admin.command("kick-all"), "ok");
+    for client in &mut clients {
+        assert_eq!(client.read(&mut [0; 16]).unwrap(), 0);
+    }
+
+    assert_eq!(
+        admin.command("stats listeners"),
+        format!(
+            "listener 0 ({}): 1 connections, 5 bytes read, 5 bytes written, 0 rejected, 1 kicked; \
+             listener 1 ({}): 2 connections, 10 bytes read, 10 bytes written, 0 rejected, 2 kicked",
+            addrs[0], addrs[1]
+        )
+    );
+    assert!(admin.command("stats").contains("; admin: 3 kicks"));
+    assert_eq!(admin.command("stats clients"), "error: usage: stats [listeners]");
+}
+
+#[test]
+fn listener_stats_without_listeners() {
+    let (_server, mut admin) = with_admin();
+    assert_eq!(admin.command("stats listeners"), "none");
+}
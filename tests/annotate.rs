@@ -0,0 +1,143 @@
+//! `Config::annotate`, the headers parsed back as clients would.
+
+mod driver;
+
+use std::net::TcpStream;
+
+use mio_echo_server::{Config, Mode, Server};
+
+use driver::{connect, poll_until, read_available, send};
+
+fn annotating_server(mode: Mode) -> Server {
+    let config = Config {
+        mode,
+        annotate: true,
+        ..Config::new("127.0.0.1:0")
+    };
+    Server::from_config(config).unwrap()
+}
+
+/// A parsed header: server, connection, sequence number and length.
+#[derive(Debug, PartialEq)]
+struct Header {
+    server: String,
+    conn: u64,
+    seq: u64,
+    len: usize,
+}
+
+// Splits the header off the start of `data`, unless it's incomplete
+fn header(data: &[u8]) -> Result<(Header, &[u8]), ()> {
+    let end = data.iter().position(|&b| b == b']').ok_or(())? + 2;
+    if end > data.len() {
+        return Err(());
+    }
+    let text = std::str::from_utf8(&data[..end]).unwrap();
+    let fields: Vec<_> = text.strip_prefix("[srv ").unwrap().strip_suffix("] ").unwrap().split(' ').collect();
+    assert_eq!((fields[1], fields[3], fields[5]), ("conn", "seq", "len"), "{}", text);
+    let header = Header {
+        server: fields[0].to_string(),
+        conn: fields[2].parse().unwrap(),
+        seq: fields[4].parse().unwrap(),
+        len: fields[6].parse().unwrap(),
+    };
+    Ok((header, &data[end..]))
+}
+
+// Sends `data` and waits for the server to have read it, so that every
+// call makes a read, and a chunk, of its own
+fn send_read(server: &mut Server, client: &mut TcpStream, data: &[u8]) {
+    let total = server.stats().tcp.bytes_read + data.len() as u64;
+    send(server, client, data);
+    poll_until(server, |server| Some(()).filter(|()| server.stats().tcp.bytes_read == total));
+}
+
+#[test]
+fn chunks_are_numbered_and_measured() {
+    let mut server = annotating_server(Mode::Echo);
+    let addr = server.local_addr().unwrap().to_string();
+    let mut client = connect(&server);
+    let chunks: [&[u8]; 3] = [b"one", b"the second", b"3"];
+    let mut echoed = Vec::new();
+    for chunk in &chunks {
+        send_read(&mut server, &mut client, chunk);
+        // The header, then as much as it says
+        poll_until(&mut server, |_| {
+            echoed.extend(read_available(&mut client).0);
+            let start = echoed.len() - echoed.iter().rev().position(|&b| b == b'[')? - 1;
+            let (parsed, rest) = header(&echoed[start..]).ok()?;
+            Some(()).filter(|()| rest.len() == parsed.len)
+        });
+    }
+
+    let mut rest = &echoed[..];
+    for (i, chunk) in chunks.iter().enumerate() {
+        let (parsed, after) = header(rest).unwrap();
+        let conn = parsed.conn;
+        assert_eq!(parsed, Header { server: addr.clone(), conn, seq: i as u64 + 1, len: chunk.len() });
+        assert_eq!(&after[..chunk.len()], *chunk);
+        rest = &after[chunk.len()..];
+    }
+    assert!(rest.is_empty());
+    // Headers aren't payload
+    let payload: usize = chunks.iter().map(|chunk| chunk.len()).sum();
+    assert_eq!(server.stats().tcp.bytes_written, payload as u64);
+}
+
+#[test]
+fn the_header_goes_inside_the_frame() {
+    let mut server = annotating_server(Mode::Length);
+    let mut client = connect(&server);
+    let mut messages = Vec::new();
+    for payload in &[&b"first"[..], b"second message"] {
+        let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(payload);
+        messages.extend(frame);
+    }
+    // One read, two messages, two headers
+    send_read(&mut server, &mut client, &messages);
+
+    // Both frames, whole
+    let mut echoed = Vec::new();
+    let frames = poll_until(&mut server, |_| {
+        echoed.extend(read_available(&mut client).0);
+        let mut frames = Vec::new();
+        let mut rest = &echoed[..];
+        while rest.len() >= 4 {
+            let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            if rest.len() < 4 + len {
+                break;
+            }
+            frames.push(rest[4..4 + len].to_vec());
+            rest = &rest[4 + len..];
+        }
+        Some(frames).filter(|frames| frames.len() == 2)
+    });
+
+    let mut seq = 0;
+    for (frame, payload) in frames.iter().zip(&[&b"first"[..], b"second message"]) {
+        let (parsed, rest) = header(frame).unwrap();
+        assert!(parsed.seq > seq, "seq {} after {}", parsed.seq, seq);
+        seq = parsed.seq;
+        assert_eq!((parsed.len, rest), (payload.len(), *payload));
+    }
+}
+
+#[test]
+fn the_header_starts_the_line() {
+    let mut server = annotating_server(Mode::Line);
+    let mut client = connect(&server);
+    send_read(&mut server, &mut client, b"a line\nanother");
+    send_read(&mut server, &mut client, b" line\n");
+
+    let mut echoed = Vec::new();
+    poll_until(&mut server, |_| {
+        echoed.extend(read_available(&mut client).0);
+        Some(()).filter(|()| echoed.iter().filter(|&&b| b == b'\n').count() == 2)
+    });
+    let lines: Vec<_> = echoed.split(|&b| b == b'\n').filter(|line| !line.is_empty()).collect();
+    let (first, first_rest) = header(lines[0]).unwrap();
+    let (second, second_rest) = header(lines[1]).unwrap();
+    assert_eq!((first_rest, first.len, first.seq), (&b"a line"[..], 6, 1));
+    assert_eq!((second_rest, second.len, second.seq), (&b"another line"[..], 12, 2));
+}
@@ -0,0 +1,80 @@
+//! The same clients over each backend, stepped by the test thread.
+
+mod driver;
+
+use std::net::Shutdown;
+
+use mio_echo_server::Config;
+
+use driver::{backends, connect, receive, receive_to_close, send, server};
+
+#[test]
+fn echo_round_trip() {
+    for backend in backends() {
+        let mut server = server(backend, &Config::new("127.0.0.1:0"));
+        let mut client = connect(&server);
+        send(&mut server, &mut client, b"hello");
+        assert_eq!(receive(&mut server, &mut client, 5), b"hello", "{:?}", backend);
+        assert_eq!(server.stats().tcp.bytes_read, 5, "{:?}", backend);
+    }
+}
+
+#[test]
+fn large_echo_comes_back_whole() {
+    let data: Vec<u8> = (0..1 << 20).map(|i| (i % 251) as u8).collect();
+    for backend in backends() {
+        let mut server = server(backend, &Config::new("127.0.0.1:0"));
+        let mut client = connect(&server);
+        let mut echoed = Vec::new();
+        // Read back as it goes, the echo backing up otherwise
+        for chunk in data.chunks(64 << 10) {
+            send(&mut server, &mut client, chunk);
+            echoed.extend(receive(&mut server, &mut client, chunk.len()));
+        }
+        assert!(echoed == data, "{:?} echoed {} bytes that differ", backend, echoed.len());
+    }
+}
+
+#[test]
+fn clients_are_served_side_by_side() {
+    for backend in backends() {
+        let mut server = server(backend, &Config::new("127.0.0.1:0"));
+        let mut clients: Vec<_> = (0..20).map(|_| connect(&server)).collect();
+        for (i, client) in clients.iter_mut().enumerate() {
+            send(&mut server, client, format!("client {:02}", i).as_bytes());
+        }
+        for (i, client) in clients.iter_mut().enumerate() {
+            assert_eq!(receive(&mut server, client, 9), format!("client {:02}", i).as_bytes(), "{:?}", backend);
+        }
+        assert_eq!(server.stats().tcp.connections, 20, "{:?}", backend);
+    }
+}
+
+#[test]
+fn the_server_closes_after_the_client() {
+    for backend in backends() {
+        let mut server = server(backend, &Config::new("127.0.0.1:0"));
+        let mut client = connect(&server);
+        send(&mut server, &mut client, b"last words");
+        assert_eq!(receive(&mut server, &mut client, 10), b"last words");
+        client.shutdown(Shutdown::Write).unwrap();
+        assert_eq!(receive_to_close(&mut server, &mut client), b"", "{:?}", backend);
+    }
+}
+
+#[test]
+fn clients_past_the_limit_are_refused() {
+    for backend in backends() {
+        let config = Config {
+            max_clients: 1,
+            ..Config::new("127.0.0.1:0")
+        };
+        let mut server = server(backend, &config);
+        let mut first = connect(&server);
+        send(&mut server, &mut first, b"in");
+        assert_eq!(receive(&mut server, &mut first, 2), b"in");
+        let mut second = connect(&server);
+        assert_eq!(receive_to_close(&mut server, &mut second), b"", "{:?}", backend);
+        assert_eq!(server.stats().tcp.rejected, 1, "{:?}", backend);
+    }
+}
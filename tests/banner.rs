@@ -0,0 +1,45 @@
+//! `Config::banner`, sent before the client says anything.
+
+mod driver;
+
+use std::time::Duration;
+
+use mio_echo_server::{Config, Server};
+
+use driver::{connect, read_available, receive, send};
+
+#[test]
+fn the_banner_comes_first_then_the_echo() {
+    // As given on the command line, escapes and all
+    let args = ["127.0.0.1:0", "--banner", r"220 echo ready\r\n"];
+    let config = Config::from_args(args.iter().map(|arg| arg.to_string())).unwrap();
+    let mut server = Server::from_config(config).unwrap();
+    let mut client = connect(&server);
+    assert_eq!(receive(&mut server, &mut client, 16), b"220 echo ready\r\n");
+
+    send(&mut server, &mut client, b"hello");
+    assert_eq!(receive(&mut server, &mut client, 5), b"hello");
+}
+
+#[test]
+fn every_client_gets_it_once() {
+    let config = Config {
+        banner: Some(b"hi\n".to_vec()),
+        ..Config::new("127.0.0.1:0")
+    };
+    let mut server = Server::from_config(config).unwrap();
+    let mut clients: Vec<_> = (0..3).map(|_| connect(&server)).collect();
+    for client in &mut clients {
+        assert_eq!(receive(&mut server, client, 3), b"hi\n");
+    }
+    // Nothing more while they stay silent
+    for _ in 0..5 {
+        server.poll_once(Some(Duration::from_millis(10))).unwrap();
+    }
+    for client in &mut clients {
+        assert_eq!(read_available(client), (Vec::new(), false));
+    }
+    let stats = server.stats();
+    assert_eq!(stats.tcp.bytes_written, 9);
+    assert_eq!(stats.tcp.bytes_read, 0);
+}
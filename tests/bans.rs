@@ -0,0 +1,101 @@
+//! Temporary bans, from `Config::bans` or the admin socket, timed by a
+//! `ManualClock`.
+
+mod driver;
+
+use std::net::{IpAddr, TcpStream};
+use std::time::Duration;
+
+use mio_echo_server::{Config, ManualClock, Server};
+
+use driver::{connect, poll_until, read_available, receive, receive_to_close, send};
+
+const BAN: Duration = Duration::from_secs(10);
+
+fn banning_server(bans: Vec<(IpAddr, Duration)>) -> (Server, ManualClock) {
+    let config = Config {
+        admin_addr: Some("127.0.0.1:0".to_string()),
+        bans,
+        ..Config::new("127.0.0.1:0")
+    };
+    let clock = ManualClock::new();
+    let server = Server::builder(config).clock(clock.clone()).build().unwrap();
+    (server, clock)
+}
+
+fn advance(server: &mut Server, clock: &ManualClock, duration: Duration) {
+    clock.advance(duration);
+    server.poll_once(Some(Duration::ZERO)).unwrap();
+}
+
+fn admin(server: &Server) -> TcpStream {
+    let stream = TcpStream::connect(server.admin_addr().expect("no admin socket")).unwrap();
+    stream.set_nonblocking(true).unwrap();
+    stream
+}
+
+// The reply line to `line`
+fn command(server: &mut Server, admin: &mut TcpStream, line: &str) -> String {
+    send(server, admin, format!("{}\n", line).as_bytes());
+    let mut reply = Vec::new();
+    poll_until(server, |_| {
+        let (data, closed) = read_available(admin);
+        assert!(!closed, "admin socket closed");
+        reply.extend_from_slice(&data);
+        Some(()).filter(|()| reply.ends_with(b"\n"))
+    });
+    String::from_utf8(reply).unwrap().trim_end().to_string()
+}
+
+fn refused(server: &mut Server) {
+    let mut client = connect(server);
+    assert_eq!(receive_to_close(server, &mut client), b"");
+}
+
+fn served(server: &mut Server) {
+    let mut client = connect(server);
+    send(server, &mut client, b"ping");
+    assert_eq!(receive(server, &mut client, 4), b"ping");
+}
+
+#[test]
+fn a_ban_refuses_until_it_expires() {
+    let (mut server, clock) = banning_server(Vec::new());
+    let mut admin = admin(&server);
+    served(&mut server);
+    assert_eq!(command(&mut server, &mut admin, "ban 127.0.0.1 10"), "ok");
+
+    refused(&mut server);
+    refused(&mut server);
+    assert_eq!(server.stats().banned, 2);
+    assert_eq!(server.stats().tcp.connections, 1);
+    assert_eq!(command(&mut server, &mut admin, "bans"), "127.0.0.1 (10s left, 2 refused)");
+
+    advance(&mut server, &clock, BAN - Duration::from_secs(1));
+    refused(&mut server);
+    advance(&mut server, &clock, Duration::from_secs(1));
+    served(&mut server);
+    assert_eq!(command(&mut server, &mut admin, "bans"), "none");
+    assert_eq!(server.stats().banned, 3);
+}
+
+#[test]
+fn bans_from_the_config_apply_from_the_start() {
+    let (mut server, clock) = banning_server(vec![("127.0.0.1".parse().unwrap(), BAN)]);
+    let mut admin = admin(&server);
+    refused(&mut server);
+    assert_eq!(command(&mut server, &mut admin, "bans"), "127.0.0.1 (10s left, 1 refused)");
+    advance(&mut server, &clock, BAN);
+    served(&mut server);
+}
+
+#[test]
+fn unban_lifts_a_ban_early() {
+    let (mut server, _clock) = banning_server(Vec::new());
+    let mut admin = admin(&server);
+    assert_eq!(command(&mut server, &mut admin, "ban 127.0.0.1 10"), "ok");
+    refused(&mut server);
+    assert_eq!(command(&mut server, &mut admin, "unban 127.0.0.1"), "ok");
+    served(&mut server);
+    assert_eq!(command(&mut server, &mut admin, "unban 127.0.0.1"), "not-found");
+}
@@ -0,0 +1,56 @@
+//! `Config::bind_retry`, waiting for a busy address to be given up.
+
+mod driver;
+
+use std::io::ErrorKind;
+use std::net::TcpListener;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mio_echo_server::{Config, EchoError, Server};
+
+use driver::{connect, receive, send};
+
+#[test]
+fn a_busy_address_is_bound_once_released() {
+    let busy = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = busy.local_addr().unwrap();
+    let release = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(300));
+        drop(busy);
+    });
+    let config = Config {
+        bind_retry: Some(Duration::from_secs(5)),
+        ..Config::new(&addr.to_string())
+    };
+    let start = Instant::now();
+    let mut server = Server::from_config(config).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(300));
+    release.join().unwrap();
+
+    assert_eq!(server.local_addr(), Some(addr));
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"bound");
+    assert_eq!(receive(&mut server, &mut client, 5), b"bound");
+}
+
+#[test]
+fn retries_end_with_the_bind_error() {
+    let busy = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = busy.local_addr().unwrap();
+    for retry in [None, Some(Duration::from_millis(200))] {
+        let config = Config {
+            bind_retry: retry,
+            ..Config::new(&addr.to_string())
+        };
+        let start = Instant::now();
+        match Server::from_config(config) {
+            Err(EchoError::Bind { addr: failed, source }) => {
+                assert_eq!(failed, addr);
+                assert_eq!(source.kind(), ErrorKind::AddrInUse);
+            }
+            other => panic!("bound a busy address: {:?}", other.map(|server| server.local_addr())),
+        }
+        assert!(start.elapsed() >= retry.unwrap_or_default());
+    }
+}
@@ -0,0 +1,74 @@
+//! `Config::busy_message`, told to the connections refused at capacity.
+
+mod driver;
+
+use std::net::TcpStream;
+use std::time::Duration;
+
+use mio_echo_server::{Config, ManualClock, Server};
+
+use driver::{connect, receive, receive_to_close, send};
+
+fn full_server(message: &[u8]) -> (Server, ManualClock, TcpStream) {
+    let config = Config {
+        max_clients: 1,
+        busy_message: Some(message.to_vec()),
+        ..Config::new("127.0.0.1:0")
+    };
+    let clock = ManualClock::new();
+    let mut server = Server::builder(config).clock(clock.clone()).build().unwrap();
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"ping");
+    assert_eq!(receive(&mut server, &mut client, 4), b"ping");
+    (server, clock, client)
+}
+
+#[test]
+fn refused_connections_are_told_why() {
+    let (mut server, _clock, mut client) = full_server(b"server busy\r\n");
+    for _ in 0..3 {
+        let mut refused = connect(&server);
+        assert_eq!(receive_to_close(&mut server, &mut refused), b"server busy\r\n");
+    }
+    assert_eq!(server.stats().tcp.rejected, 3);
+    // Still served
+    send(&mut server, &mut client, b"pong");
+    assert_eq!(receive(&mut server, &mut client, 4), b"pong");
+}
+
+// Whether the server end of `client` is still among the descriptors of
+// this process
+#[cfg(target_os = "linux")]
+fn held(client: &TcpStream) -> bool {
+    use std::fs;
+    use std::mem::ManuallyDrop;
+    use std::os::unix::io::FromRawFd;
+
+    let (local, peer) = (client.local_addr().unwrap(), client.peer_addr().unwrap());
+    fs::read_dir("/proc/self/fd").unwrap().any(|entry| {
+        let fd = match entry.unwrap().file_name().to_str().and_then(|name| name.parse().ok()) {
+            Some(fd) => fd,
+            None => return false,
+        };
+        // Borrowed, closed by the server
+        let sock = ManuallyDrop::new(unsafe { TcpStream::from_raw_fd(fd) });
+        sock.local_addr().ok() == Some(peer) && sock.peer_addr().ok() == Some(local)
+    })
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn a_refused_connection_that_never_reads_is_given_up() {
+    // More than the sockets hold
+    let (mut server, clock, _client) = full_server(&vec![b'x'; 16 << 20]);
+    let refused = connect(&server);
+    for _ in 0..10 {
+        server.poll_once(Some(Duration::from_millis(10))).unwrap();
+    }
+    assert!(held(&refused), "the refused connection was dropped before its deadline");
+
+    clock.advance(Duration::from_secs(1));
+    server.poll_once(Some(Duration::ZERO)).unwrap();
+    assert!(!held(&refused), "the refused connection is still held");
+    assert_eq!(server.stats().tcp.rejected, 1);
+}
@@ -0,0 +1,110 @@
+//! The pcap file of `Config::capture`, read back after stepping the echo.
+
+mod driver;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use mio_echo_server::{Config, Server};
+
+use driver::{connect, receive, send};
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mio-echo-server-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A TCP packet of the capture: its ports, sequence number and payload.
+#[derive(Debug, PartialEq)]
+struct Packet {
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    payload: Vec<u8>,
+}
+
+// The IPv4 TCP packets of a pcap file
+fn packets(path: &Path) -> Vec<Packet> {
+    let file = fs::read(path).unwrap();
+    let (header, mut rest) = file.split_at(24);
+    assert_eq!(header[..4], 0xa1b2_c3d4u32.to_le_bytes());
+    assert_eq!(header[20..], 101u32.to_le_bytes(), "not raw IP");
+    let mut packets = Vec::new();
+    while !rest.is_empty() {
+        let len = u32::from_le_bytes([rest[8], rest[9], rest[10], rest[11]]) as usize;
+        let (packet, after) = rest[16..].split_at(len);
+        assert_eq!((packet[0] >> 4, packet[9]), (4, 6), "not IPv4 TCP");
+        let tcp = &packet[20..];
+        packets.push(Packet {
+            src_port: u16::from_be_bytes([tcp[0], tcp[1]]),
+            dst_port: u16::from_be_bytes([tcp[2], tcp[3]]),
+            seq: u32::from_be_bytes([tcp[4], tcp[5], tcp[6], tcp[7]]),
+            payload: tcp[20..].to_vec(),
+        });
+        rest = after;
+    }
+    packets
+}
+
+fn capturing(path: &Path, max_size: u64) -> Server {
+    let config = Config {
+        capture: Some(path.to_path_buf()),
+        capture_max_size: max_size,
+        ..Config::new("127.0.0.1:0")
+    };
+    Server::from_config(config).unwrap()
+}
+
+#[test]
+fn reads_and_writes_become_packets() {
+    let dir = scratch_dir("capture");
+    let path = dir.join("echo.pcap");
+    let mut server = capturing(&path, 1 << 20);
+    let mut client = connect(&server);
+    let (client_port, server_port) = (client.local_addr().unwrap().port(), server.local_addr().unwrap().port());
+    for message in [&b"hello"[..], b"world!"] {
+        send(&mut server, &mut client, message);
+        assert_eq!(receive(&mut server, &mut client, message.len()), message);
+    }
+    // Closing waits for the file to be written
+    server.close();
+
+    let packet = |src_port, dst_port, seq, payload: &[u8]| Packet {
+        src_port,
+        dst_port,
+        seq,
+        payload: payload.to_vec(),
+    };
+    assert_eq!(
+        packets(&path),
+        [
+            packet(client_port, server_port, 1, b"hello"),
+            packet(server_port, client_port, 1, b"hello"),
+            packet(client_port, server_port, 6, b"world!"),
+            packet(server_port, client_port, 6, b"world!"),
+        ]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_full_file_is_rotated() {
+    let dir = scratch_dir("capture-rotation");
+    let path = dir.join("echo.pcap");
+    // The header and a few packets
+    let mut server = capturing(&path, 300);
+    let mut client = connect(&server);
+    for _ in 0..10 {
+        send(&mut server, &mut client, b"0123456789");
+        assert_eq!(receive(&mut server, &mut client, 10), b"0123456789");
+    }
+    server.close();
+
+    let (current, rotated) = (packets(&path), packets(&dir.join("echo.pcap.1")));
+    assert!(!current.is_empty() && !rotated.is_empty());
+    assert!(current.len() + rotated.len() < 20, "none were rotated out");
+    assert!(fs::metadata(&path).unwrap().len() <= 300);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,54 @@
+//! A server chrooted by `Server::confine`, in a process of its own since
+//! the chroot is for good. Needs root, skipped otherwise.
+
+#![cfg(unix)]
+
+mod driver;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use mio_echo_server::{Config, Server};
+
+use driver::{connect, receive, send};
+
+const CHILD_VAR: &str = "MIO_ECHO_SERVER_CHROOT_TEST";
+
+#[test]
+fn a_chrooted_server_echoes_and_sees_only_its_directory() {
+    if env::var_os(CHILD_VAR).is_none() {
+        if unsafe { libc::geteuid() } != 0 {
+            eprintln!("skipped: chroot needs root");
+            return;
+        }
+        let dir = env::temp_dir().join(format!("mio-echo-server-chroot-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("inside"), b"").unwrap();
+        let status = Command::new(env::current_exe().unwrap())
+            .args(["a_chrooted_server_echoes_and_sees_only_its_directory", "--exact", "--test-threads=1"])
+            .env(CHILD_VAR, &dir)
+            .status()
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(status.success(), "ended with {}", status);
+        return;
+    }
+
+    let dir = env::var_os(CHILD_VAR).unwrap();
+    let config = Config {
+        chroot: Some(dir.clone().into()),
+        ..Config::new("127.0.0.1:0")
+    };
+    let mut server = Server::from_config(config).unwrap();
+    server.confine().unwrap();
+    assert!(Path::new("/inside").exists());
+    assert!(!Path::new(&dir).exists(), "the old root is still visible");
+    assert_eq!(env::current_dir().unwrap(), Path::new("/"));
+
+    // Bound before, served after
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"jailed");
+    assert_eq!(receive(&mut server, &mut client, 6), b"jailed");
+}
@@ -0,0 +1,80 @@
+//! Timeouts stepped through with a `ManualClock`, without sleeping.
+
+mod driver;
+
+use std::net::TcpStream;
+use std::time::Duration;
+
+use mio_echo_server::{Config, ManualClock, Server};
+
+use driver::{connect, poll_until, read_available, receive, receive_to_close, send};
+
+fn server(config: Config) -> (Server, ManualClock) {
+    let clock = ManualClock::new();
+    let server = Server::builder(config).clock(clock.clone()).build().unwrap();
+    (server, clock)
+}
+
+// Connects a client, polling until the server accepted it
+fn accepted(server: &mut Server) -> TcpStream {
+    let stream = connect(server);
+    let count = server.stats().tcp.connections + 1;
+    poll_until(server, |server| Some(()).filter(|()| server.stats().tcp.connections == count));
+    stream
+}
+
+// Moves the clock, then has the server handle whatever is due
+fn advance(server: &mut Server, clock: &ManualClock, duration: Duration) {
+    clock.advance(duration);
+    server.poll_once(Some(Duration::ZERO)).unwrap();
+}
+
+#[test]
+fn idle_timeout_closes_a_quiet_client() {
+    let mut config = Config::new("127.0.0.1:0");
+    config.idle_timeout = Some(Duration::from_secs(30));
+    let (mut server, clock) = server(config);
+    let mut stream = accepted(&mut server);
+
+    advance(&mut server, &clock, Duration::from_secs(29));
+    assert_eq!(read_available(&mut stream), (Vec::new(), false));
+    // Sending starts the wait over
+    send(&mut server, &mut stream, b"hello");
+    assert_eq!(receive(&mut server, &mut stream, 5), b"hello");
+    advance(&mut server, &clock, Duration::from_secs(29));
+    assert_eq!(read_available(&mut stream), (Vec::new(), false));
+    assert_eq!(server.stats().idle_closes, 0);
+
+    advance(&mut server, &clock, Duration::from_secs(2));
+    assert_eq!(receive_to_close(&mut server, &mut stream), b"");
+    assert_eq!(server.stats().idle_closes, 1);
+}
+
+#[test]
+fn heartbeat_goes_to_a_quiet_client() {
+    let mut config = Config::new("127.0.0.1:0");
+    config.heartbeat_interval = Some(Duration::from_secs(10));
+    config.heartbeat_payload = b"ping\n".to_vec();
+    let (mut server, clock) = server(config);
+    let mut stream = accepted(&mut server);
+
+    advance(&mut server, &clock, Duration::from_secs(9));
+    assert_eq!(read_available(&mut stream), (Vec::new(), false));
+    advance(&mut server, &clock, Duration::from_secs(2));
+    assert_eq!(receive(&mut server, &mut stream, 5), b"ping\n");
+
+    // Every interval while it stays quiet, not before
+    advance(&mut server, &clock, Duration::from_secs(9));
+    assert_eq!(read_available(&mut stream), (Vec::new(), false));
+    advance(&mut server, &clock, Duration::from_secs(1));
+    assert_eq!(receive(&mut server, &mut stream, 5), b"ping\n");
+
+    // Its data pushes the next one back
+    advance(&mut server, &clock, Duration::from_secs(5));
+    send(&mut server, &mut stream, b"hi");
+    assert_eq!(receive(&mut server, &mut stream, 2), b"hi");
+    advance(&mut server, &clock, Duration::from_secs(9));
+    assert_eq!(read_available(&mut stream), (Vec::new(), false));
+    advance(&mut server, &clock, Duration::from_secs(1));
+    assert_eq!(receive(&mut server, &mut stream, 5), b"ping\n");
+}
@@ -0,0 +1,28 @@
+//! `Stats::close_rtt`, filled from TCP_INFO as connections close.
+
+#![cfg(target_os = "linux")]
+
+mod driver;
+
+use mio_echo_server::{Config, Server, RTT_BUCKETS};
+
+use driver::{connect, poll_until, receive, send};
+
+#[test]
+fn loopback_connections_close_under_a_millisecond() {
+    let mut server = Server::from_config(Config::new("127.0.0.1:0")).unwrap();
+    for _ in 0..3 {
+        let mut client = connect(&server);
+        send(&mut server, &mut client, b"ping");
+        assert_eq!(receive(&mut server, &mut client, 4), b"ping");
+    }
+    poll_until(&mut server, |server| {
+        Some(()).filter(|()| server.stats().close_rtt.iter().sum::<u64>() == 3)
+    });
+
+    let close_rtt = server.stats().close_rtt;
+    assert_eq!(close_rtt.len(), RTT_BUCKETS);
+    // Bucket 10 starts at 1024µs
+    let slowest = close_rtt.iter().rposition(|&n| n > 0).unwrap();
+    assert!(slowest < 10, "a loopback rtt of {}us or more: {:?}", 1 << slowest, close_rtt);
+}
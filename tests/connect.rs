@@ -0,0 +1,152 @@
+//! The `connect` subcommand of the binary, stdin piped to a server
+//! running on a thread of the test.
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::process::{Child, Command, Output, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use mio_echo_server::{Config, Error, Server, ServerHandle, Stats};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An echo server on a thread of its own, stopped once dropped.
+struct Running {
+    addr: SocketAddr,
+    handle: ServerHandle,
+    thread: Option<JoinHandle<Result<Stats, Error>>>,
+}
+
+impl Running {
+    fn start() -> Running {
+        let server = Server::from_config(Config::new("127.0.0.1:0")).unwrap();
+        let addr = server.local_addr().unwrap();
+        let (handle, thread) = server.start().unwrap();
+        Running {
+            addr,
+            handle,
+            thread: Some(thread),
+        }
+    }
+
+    fn stop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.handle.shutdown().unwrap();
+            thread.join().unwrap().unwrap();
+        }
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn spawn(addr: SocketAddr, args: &[&str]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_mio-echo-server"))
+        .arg("connect")
+        .arg(addr.to_string())
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap()
+}
+
+// The output of `child`, which must exit within `TIMEOUT`
+fn wait(mut child: Child) -> Output {
+    let deadline = Instant::now() + TIMEOUT;
+    while child.try_wait().unwrap().is_none() {
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            panic!("connect didn't exit");
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    child.wait_with_output().unwrap()
+}
+
+// Runs the subcommand with `input` as its whole stdin, ended once
+// `echoed` bytes were printed: the server drops what it hasn't echoed yet
+// when the client closes its side
+fn session(addr: SocketAddr, args: &[&str], input: &[u8], echoed: usize) -> Output {
+    let mut child = spawn(addr, args);
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(input).unwrap();
+    let mut printed = vec![0; echoed];
+    child.stdout.as_mut().unwrap().read_exact(&mut printed).unwrap();
+    drop(stdin);
+    let mut output = wait(child);
+    printed.append(&mut output.stdout);
+    output.stdout = printed;
+    output
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).trim_end().to_string()
+}
+
+#[test]
+fn lines_are_echoed_and_counted() {
+    let server = Running::start();
+    let output = session(server.addr, &[], b"hello\nworld\n", 12);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(output.stdout, b"hello\nworld\n");
+    assert_eq!(stderr(&output), "connection closed by server, 12 bytes sent, 12 bytes received");
+}
+
+#[test]
+fn crlf_lines_as_a_hex_dump() {
+    let server = Running::start();
+    let output = session(server.addr, &["--crlf", "--hex"], b"hi\n", 10);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("00000000  68 69 0d 0a "), "{}", stdout);
+    assert!(stdout.trim_end().ends_with("|hi..|"), "{}", stdout);
+    assert_eq!(stderr(&output), "connection closed by server, 4 bytes sent, 4 bytes received");
+}
+
+#[test]
+fn an_idle_session_times_out() {
+    let server = Running::start();
+    let mut child = spawn(server.addr, &["--timeout", "300ms"]);
+    // Held open, the session only ends on the timeout
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"ping\n").unwrap();
+    let start = Instant::now();
+    let output = wait(child);
+    assert!(start.elapsed() >= Duration::from_millis(250), "{:?}", start.elapsed());
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(output.stdout, b"ping\n");
+    assert_eq!(stderr(&output), "session timeout, 5 bytes sent, 5 bytes received");
+    drop(stdin);
+}
+
+#[test]
+fn the_server_closing_mid_session_ends_it() {
+    let mut server = Running::start();
+    let mut child = spawn(server.addr, &[]);
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"before\n").unwrap();
+    let mut echo = [0; 7];
+    child.stdout.as_mut().unwrap().read_exact(&mut echo).unwrap();
+    assert_eq!(&echo, b"before\n");
+
+    server.stop();
+    let output = wait(child);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stderr(&output), "connection closed by server, 7 bytes sent, 7 bytes received");
+    drop(stdin);
+}
+
+#[test]
+fn a_refused_connection_fails() {
+    // Bound then closed, nothing listens there
+    let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let output = session(addr, &[], b"", 0);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("refused"), "{}", stderr(&output));
+}
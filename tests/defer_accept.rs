@@ -0,0 +1,40 @@
+//! `Config::defer_accept`, silent connections left to the kernel.
+
+#![cfg(target_os = "linux")]
+
+mod driver;
+
+use std::time::Duration;
+
+use mio_echo_server::{Config, Server};
+
+use driver::{connect, receive, send};
+
+// Enough polls for a connection to be accepted, were it ready
+fn settle(server: &mut Server) {
+    for _ in 0..10 {
+        server.poll_once(Some(Duration::from_millis(10))).unwrap();
+    }
+}
+
+#[test]
+fn connections_are_accepted_with_their_first_bytes() {
+    let config = Config {
+        defer_accept: Some(Duration::from_secs(30)),
+        ..Config::new("127.0.0.1:0")
+    };
+    let mut server = Server::from_config(config).unwrap();
+    let mut idle = connect(&server);
+    settle(&mut server);
+    assert_eq!(server.stats().tcp.connections, 0, "accepted before it sent anything");
+
+    // Not held up by the idle one
+    let mut talking = connect(&server);
+    send(&mut server, &mut talking, b"hello");
+    assert_eq!(receive(&mut server, &mut talking, 5), b"hello");
+    assert_eq!(server.stats().tcp.connections, 1);
+
+    send(&mut server, &mut idle, b"at last");
+    assert_eq!(receive(&mut server, &mut idle, 7), b"at last");
+    assert_eq!(server.stats().tcp.connections, 2);
+}
@@ -0,0 +1,137 @@
+//! `Config::deny_file`, rewritten under a running server and picked up
+//! on the periodic check, timed by a `ManualClock`.
+
+mod driver;
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use std::{env, process};
+
+use mio_echo_server::{Config, ManualClock, Server};
+
+use driver::{connect, poll_until, read_available, receive, receive_to_close, send};
+
+// Past the interval of the modification time check
+const CHECK: Duration = Duration::from_secs(1);
+
+struct DenyFile {
+    path: PathBuf,
+    // Bumped on every write, the file system may not tell writes in the
+    // same tick apart
+    modified: SystemTime,
+}
+
+impl DenyFile {
+    fn new(name: &str, text: &str) -> DenyFile {
+        let path = env::temp_dir().join(format!("mio-echo-server-deny-{}-{}", name, process::id()));
+        let mut file = DenyFile {
+            path,
+            modified: SystemTime::now() - Duration::from_secs(3600),
+        };
+        file.write(text);
+        file
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write(&mut self, text: &str) {
+        fs::write(&self.path, text).unwrap();
+        self.modified += Duration::from_secs(1);
+        File::options().write(true).open(&self.path).unwrap().set_modified(self.modified).unwrap();
+    }
+}
+
+impl Drop for DenyFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn deny_server(file: &DenyFile, deny_existing: bool) -> (Server, ManualClock) {
+    let config = Config {
+        deny_file: Some(file.path().to_path_buf()),
+        deny_existing,
+        ..Config::new("127.0.0.1:0")
+    };
+    let clock = ManualClock::new();
+    let server = Server::builder(config).clock(clock.clone()).build().unwrap();
+    (server, clock)
+}
+
+fn advance(server: &mut Server, clock: &ManualClock, duration: Duration) {
+    clock.advance(duration);
+    server.poll_once(Some(Duration::ZERO)).unwrap();
+}
+
+// Whether a new client is echoed, rather than refused
+fn echoed(server: &mut Server) -> bool {
+    let denied = server.stats().refusals.denied;
+    let mut client = connect(server);
+    send(server, &mut client, b"ping");
+    poll_until(server, |server| {
+        if server.stats().refusals.denied > denied {
+            return Some(false);
+        }
+        let (data, _) = read_available(&mut client);
+        Some(true).filter(|_| !data.is_empty())
+    })
+}
+
+#[test]
+fn a_newly_denied_network_is_refused_after_the_check() {
+    let mut file = DenyFile::new("refused", "10.0.0.0/8\n");
+    let (mut server, clock) = deny_server(&file, false);
+    assert!(echoed(&mut server));
+
+    file.write("10.0.0.0/8\n# loopback\n127.0.0.0/8\n");
+    assert!(echoed(&mut server), "reloaded before the check");
+    advance(&mut server, &clock, CHECK);
+    assert!(!echoed(&mut server));
+    let stats = server.stats();
+    assert_eq!((stats.deny_reloads, stats.refusals.denied), (1, 1));
+
+    file.write("10.0.0.0/8\n");
+    advance(&mut server, &clock, CHECK);
+    assert!(echoed(&mut server));
+    assert_eq!(server.stats().deny_reloads, 2);
+}
+
+#[test]
+fn a_file_that_does_not_parse_keeps_the_previous_list() {
+    let mut file = DenyFile::new("broken", "10.0.0.0/8\n");
+    let (mut server, clock) = deny_server(&file, false);
+    file.write("127.0.0.0/8\nnot a network\n");
+    advance(&mut server, &clock, CHECK);
+    assert!(echoed(&mut server));
+    let stats = server.stats();
+    assert_eq!((stats.deny_reloads, stats.deny_reload_errors), (0, 1));
+
+    // Reported once, not on every check
+    advance(&mut server, &clock, CHECK);
+    assert_eq!(server.stats().deny_reload_errors, 1);
+}
+
+#[test]
+fn existing_clients_are_kept_unless_asked_for() {
+    for deny_existing in [false, true] {
+        let mut file = DenyFile::new(&format!("existing-{}", deny_existing), "10.0.0.0/8\n");
+        let (mut server, clock) = deny_server(&file, deny_existing);
+        let mut client = connect(&server);
+        send(&mut server, &mut client, b"before");
+        assert_eq!(receive(&mut server, &mut client, 6), b"before");
+
+        file.write("127.0.0.0/8\n");
+        advance(&mut server, &clock, CHECK);
+        if deny_existing {
+            assert_eq!(receive_to_close(&mut server, &mut client), b"");
+            assert_eq!(server.stats().deny_closes, 1);
+        } else {
+            send(&mut server, &mut client, b"after");
+            assert_eq!(receive(&mut server, &mut client, 5), b"after");
+            assert_eq!(server.stats().deny_closes, 0);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Steps a `Server` from the test thread with `poll_once`, its clients
+//! being non-blocking sockets read in between.
+
+// Every test uses a different part of it
+#![allow(dead_code)]
+
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+
+use mio_echo_server::{Backend, Config, Server};
+
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+const STEP: Duration = Duration::from_millis(10);
+
+/// The backends compiled in, for the tests that run over each of them.
+pub fn backends() -> Vec<Backend> {
+    let mut backends = vec![Backend::Mio];
+    if cfg!(all(target_os = "linux", feature = "io-uring")) {
+        backends.push(Backend::Uring);
+    }
+    backends
+}
+
+/// A server of `config` on `backend`.
+pub fn server(backend: Backend, config: &Config) -> Server {
+    let config = Config {
+        backend,
+        ..config.clone()
+    };
+    Server::from_config(config).unwrap()
+}
+
+/// Connects a non-blocking client, accepted by the next poll.
+pub fn connect(server: &Server) -> TcpStream {
+    let stream = TcpStream::connect(server.local_addr().expect("no TCP listener")).unwrap();
+    stream.set_nonblocking(true).unwrap();
+    stream.set_nodelay(true).unwrap();
+    stream
+}
+
+/// Polls `server` until `done` returns something, panicking after
+/// `TIMEOUT`.
+pub fn poll_until<T, F>(server: &mut Server, mut done: F) -> T
+where
+    F: FnMut(&mut Server) -> Option<T>,
+{
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        server.poll_once(Some(STEP)).unwrap();
+        if let Some(result) = done(server) {
+            return result;
+        }
+        assert!(Instant::now() < deadline, "timed out");
+    }
+}
+
+/// Writes `data` whole to a non-blocking stream, polling `server` while
+/// the socket is full.
+pub fn send<S: Write>(server: &mut Server, stream: &mut S, data: &[u8]) {
+    let mut rest = data;
+    while !rest.is_empty() {
+        match stream.write(rest) {
+            Ok(n) => rest = &rest[n..],
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                server.poll_once(Some(STEP)).unwrap();
+            }
+            Err(e) => panic!("write failed: {}", e),
+        }
+    }
+}
+
+/// Polls `server` until `len` bytes came back on `stream`.
+pub fn receive<S: Read>(server: &mut Server, stream: &mut S, len: usize) -> Vec<u8> {
+    let mut received = Vec::new();
+    poll_until(server, |_| {
+        match read_available(stream) {
+            (data, false) => received.extend_from_slice(&data),
+            (_, true) => panic!("closed after {} bytes of {}", received.len(), len),
+        }
+        Some(()).filter(|()| received.len() >= len)
+    });
+    received
+}
+
+/// Polls `server` until it closed `stream`, returning what came before.
+pub fn receive_to_close<S: Read>(server: &mut Server, stream: &mut S) -> Vec<u8> {
+    let mut received = Vec::new();
+    poll_until(server, |_| {
+        let (data, closed) = read_available(stream);
+        received.extend_from_slice(&data);
+        Some(()).filter(|()| closed)
+    });
+    received
+}
+
+/// What `stream` has to read without blocking, and whether it is closed,
+/// by the server or reset.
+pub fn read_available<S: Read>(stream: &mut S) -> (Vec<u8>, bool) {
+    let mut data = Vec::new();
+    let mut buf = [0; 4096];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) => return (data, true),
+            Ok(n) => data.extend_from_slice(&buf[..n]),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return (data, false),
+            Err(ref e) if e.kind() == io::ErrorKind::ConnectionReset => return (data, true),
+            Err(e) => panic!("read failed: {}", e),
+        }
+    }
+}
+
+/// A non-blocking socket sending to the UDP socket of `server`.
+pub fn udp_client(server: &Server) -> UdpSocket {
+    let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sock.connect(server.udp_addr().expect("no UDP socket")).unwrap();
+    sock.set_nonblocking(true).unwrap();
+    sock
+}
+
+/// Polls `server` until a datagram came back on `sock`.
+pub fn receive_datagram(server: &mut Server, sock: &UdpSocket) -> Vec<u8> {
+    let mut buf = vec![0; 65536];
+    poll_until(server, |_| match sock.recv(&mut buf) {
+        Ok(len) => Some(buf[..len].to_vec()),
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => None,
+        Err(e) => panic!("recv failed: {}", e),
+    })
+}
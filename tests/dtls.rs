@@ -0,0 +1,218 @@
+//! DTLS peers of the UDP echo, with an OpenSSL client and a throwaway CA.
+
+#![cfg(feature = "dtls")]
+
+mod pki;
+#[path = "../benches/support/mod.rs"]
+mod support;
+
+use std::io::{self, Read, Write};
+use std::net::UdpSocket;
+use std::thread;
+use std::time::Duration;
+
+use openssl::pkey::PKey;
+use openssl::ssl::{HandshakeError, ShutdownResult, Ssl, SslContext, SslMethod, SslStream, SslVerifyMode};
+use openssl::x509::X509;
+
+use mio_echo_server::{Config, Stats};
+use pki::{Ca, Dir, Issued};
+use support::TestServer;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait for a reply that shouldn't come.
+const SILENCE: Duration = Duration::from_millis(300);
+
+/// A client socket, a datagram per read and write.
+struct Datagrams(UdpSocket);
+
+impl Read for Datagrams {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.recv(buf)
+    }
+}
+
+impl Write for Datagrams {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.send(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Keeps what the client sends, never receiving anything.
+#[derive(Default)]
+struct Sent(Vec<Vec<u8>>);
+
+impl Read for Sent {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::WouldBlock.into())
+    }
+}
+
+impl Write for Sent {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.push(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A server presenting a certificate of `ca` for localhost on its UDP
+/// socket.
+struct Setup {
+    ca: Ca,
+    _dir: Dir,
+    server: TestServer,
+}
+
+impl Setup {
+    /// Asks the peers for a certificate issued by `ca` if `client_ca`,
+    /// drops sessions quiet for `idle`.
+    fn new(client_ca: bool, idle: Duration) -> Setup {
+        let ca = Ca::new("Test CA");
+        let dir = Dir::new();
+        let server_cert = ca.issue("server", "localhost");
+        let mut config = Config::new("127.0.0.1:0");
+        config.udp = Some("127.0.0.1:0".into());
+        config.dtls_cert = Some(dir.write("server.pem", &server_cert.cert));
+        config.dtls_key = Some(dir.write("server.key", &server_cert.key));
+        if client_ca {
+            config.dtls_client_ca = Some(dir.write("ca.pem", &ca.cert.pem()));
+        }
+        config.dtls_session_idle = idle;
+        let server = TestServer::with_config(config);
+        Setup { ca, _dir: dir, server }
+    }
+
+    fn socket(&self) -> UdpSocket {
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sock.connect(self.server.udp_addr.expect("no UDP socket")).unwrap();
+        sock.set_read_timeout(Some(TIMEOUT)).unwrap();
+        sock
+    }
+
+    // A client trusting `ca` for localhost, presenting `cert`
+    fn client(&self, cert: Option<&Issued>) -> Ssl {
+        let mut ctx = SslContext::builder(SslMethod::dtls()).unwrap();
+        ctx.cert_store_mut().add_cert(X509::from_pem(self.ca.cert.pem().as_bytes()).unwrap()).unwrap();
+        ctx.set_verify(SslVerifyMode::PEER);
+        if let Some(cert) = cert {
+            ctx.set_certificate(&X509::from_pem(cert.cert.as_bytes()).unwrap()).unwrap();
+            ctx.set_private_key(&PKey::private_key_from_pem(cert.key.as_bytes()).unwrap()).unwrap();
+        }
+        let mut ssl = Ssl::new(&ctx.build()).unwrap();
+        ssl.param_mut().set_host("localhost").unwrap();
+        ssl.set_mtu(1200).unwrap();
+        ssl
+    }
+
+    /// Shakes hands over `sock` presenting `cert`, `None` if the server
+    /// broke off.
+    fn connect(&self, sock: UdpSocket, cert: Option<&Issued>) -> Option<SslStream<Datagrams>> {
+        match self.client(cert).connect(Datagrams(sock)) {
+            Ok(stream) => Some(stream),
+            Err(HandshakeError::Failure(_)) | Err(HandshakeError::WouldBlock(_)) => None,
+            Err(HandshakeError::SetupFailure(e)) => panic!("{}", e),
+        }
+    }
+
+    fn stop(self) -> Stats {
+        self.server.stop()
+    }
+}
+
+fn echo(stream: &mut SslStream<Datagrams>, message: &[u8]) -> Vec<u8> {
+    stream.ssl_write(message).unwrap();
+    let mut buf = [0; 2048];
+    let len = stream.ssl_read(&mut buf).unwrap();
+    buf[..len].to_vec()
+}
+
+#[test]
+fn handshake_then_echo_then_close() {
+    let setup = Setup::new(false, Duration::from_secs(60));
+    let mut stream = setup.connect(setup.socket(), None).expect("handshake failed");
+    assert_eq!(echo(&mut stream, b"hello"), b"hello");
+    let large = vec![b'x'; 1000];
+    assert_eq!(echo(&mut stream, &large), large);
+    // The server answers the close_notify with its own
+    assert_eq!(stream.shutdown().unwrap(), ShutdownResult::Sent);
+    assert_eq!(stream.shutdown().unwrap(), ShutdownResult::Received);
+
+    let stats = setup.stop();
+    assert_eq!(stats.dtls.handshakes, 1);
+    assert_eq!(stats.dtls.closed, 1);
+    assert_eq!(stats.dtls.sessions, 0);
+}
+
+#[test]
+fn peers_need_a_certificate_of_the_client_ca() {
+    let setup = Setup::new(true, Duration::from_secs(60));
+    let alice = setup.ca.issue("alice", "alice.example");
+    let mut stream = setup.connect(setup.socket(), Some(&alice)).expect("handshake failed");
+    assert_eq!(echo(&mut stream, b"hello"), b"hello");
+
+    assert!(setup.connect(setup.socket(), None).is_none());
+    let mallory = Ca::new("Other CA").issue("mallory", "mallory.example");
+    assert!(setup.connect(setup.socket(), Some(&mallory)).is_none());
+
+    let stats = setup.stop();
+    assert_eq!(stats.dtls.handshakes, 1);
+    assert_eq!(stats.dtls.handshake_failures, 2);
+}
+
+#[test]
+fn quiet_session_expires() {
+    let setup = Setup::new(false, Duration::from_millis(100));
+    let sock = setup.socket();
+    let again = sock.try_clone().unwrap();
+    let mut stream = setup.connect(sock, None).expect("handshake failed");
+    assert_eq!(echo(&mut stream, b"hello"), b"hello");
+
+    thread::sleep(Duration::from_millis(200));
+    stream.get_ref().0.set_read_timeout(Some(SILENCE)).unwrap();
+    stream.ssl_write(b"anyone?").unwrap();
+    assert!(stream.ssl_read(&mut [0; 64]).is_err(), "echoed over an expired session");
+
+    // The same address shakes hands anew
+    again.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let mut stream = setup.connect(again, None).expect("handshake failed");
+    assert_eq!(echo(&mut stream, b"hello"), b"hello");
+
+    let stats = setup.stop();
+    assert_eq!(stats.dtls.handshakes, 2);
+    assert_eq!(stats.dtls.expired, 1);
+    assert_eq!(stats.dtls.strays, 1);
+}
+
+#[test]
+fn unverified_hello_only_gets_a_cookie() {
+    let setup = Setup::new(false, Duration::from_secs(60));
+    // The ClientHello a client sends first, without a cookie
+    let hello = match setup.client(None).connect(Sent::default()) {
+        Err(HandshakeError::WouldBlock(mid)) => mid.get_ref().0[0].clone(),
+        _ => panic!("the client didn't wait for the server"),
+    };
+
+    let sock = setup.socket();
+    sock.send(&hello).unwrap();
+    let mut reply = [0; 2048];
+    let len = sock.recv(&mut reply).unwrap();
+    // A handshake record holding a HelloVerifyRequest, smaller than the
+    // ClientHello
+    assert_eq!(reply[0], 22);
+    assert_eq!(reply[13], 3);
+    assert!(len < hello.len(), "{} bytes replied to {}", len, hello.len());
+    sock.set_read_timeout(Some(SILENCE)).unwrap();
+    assert!(sock.recv(&mut reply).is_err(), "more than the HelloVerifyRequest");
+
+    let stats = setup.stop();
+    assert_eq!(stats.dtls.handshakes, 0);
+    assert_eq!(stats.dtls.sessions, 1);
+}
@@ -0,0 +1,72 @@
+//! `Config::duration`, the end of a run timed by a `ManualClock`.
+
+mod driver;
+
+use std::net::TcpStream;
+use std::time::Duration;
+
+use mio_echo_server::{Config, ManualClock, Server};
+
+use driver::{connect, poll_until, read_available, receive, send};
+
+const RUN: Duration = Duration::from_secs(60);
+const DRAIN: Duration = Duration::from_secs(5);
+
+fn timed_server() -> (Server, ManualClock) {
+    let config = Config {
+        duration: Some(RUN),
+        drain_timeout: DRAIN,
+        ..Config::new("127.0.0.1:0")
+    };
+    let clock = ManualClock::new();
+    let server = Server::builder(config).clock(clock.clone()).build().unwrap();
+    (server, clock)
+}
+
+fn advance(server: &mut Server, clock: &ManualClock, duration: Duration) {
+    clock.advance(duration);
+    server.poll_once(Some(Duration::ZERO)).unwrap();
+}
+
+#[test]
+fn an_idle_server_stops_at_the_end_of_the_run() {
+    let (mut server, clock) = timed_server();
+    advance(&mut server, &clock, RUN - Duration::from_secs(1));
+    assert!(!server.shutdown_requested());
+    advance(&mut server, &clock, Duration::from_secs(1));
+    assert!(server.shutdown_requested());
+}
+
+#[test]
+fn clients_are_served_until_they_leave() {
+    let (mut server, clock) = timed_server();
+    let addr = server.local_addr().unwrap();
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"before");
+    assert_eq!(receive(&mut server, &mut client, 6), b"before");
+
+    advance(&mut server, &clock, RUN);
+    assert!(!server.shutdown_requested());
+    // Draining: no one new, the one connected still echoed
+    assert!(TcpStream::connect(addr).is_err(), "still listening");
+    send(&mut server, &mut client, b"after");
+    assert_eq!(receive(&mut server, &mut client, 5), b"after");
+
+    drop(client);
+    poll_until(&mut server, |server| Some(()).filter(|()| server.shutdown_requested()));
+}
+
+#[test]
+fn clients_left_after_the_drain_timeout_are_closed() {
+    let (mut server, clock) = timed_server();
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"stay");
+    assert_eq!(receive(&mut server, &mut client, 4), b"stay");
+
+    advance(&mut server, &clock, RUN);
+    advance(&mut server, &clock, DRAIN);
+    assert!(server.shutdown_requested());
+    server.close();
+    // The FIN is there by the time the close returns
+    assert_eq!(read_available(&mut client), (Vec::new(), true));
+}
@@ -0,0 +1,165 @@
+//! The echo written against `Server::next_events` is the built-in one:
+//! the same workloads get the same replies, closes and stats from both.
+
+#[path = "../benches/support/mod.rs"]
+mod support;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use mio_echo_server::{CloseReason, Config, Overflow, Server, ServerEvent, Stats};
+use support::TestServer;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The echo of the `events` example, on its own thread.
+struct EventServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<(Stats, Vec<CloseReason>)>,
+}
+
+impl EventServer {
+    fn new(config: Config) -> EventServer {
+        let mut server = Server::builder(config).events().build().unwrap();
+        let addr = server.local_addr().unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopping = stop.clone();
+        let thread = thread::spawn(move || {
+            let mut open = 0;
+            let mut closed = Vec::new();
+            // Until the clients hung up, so that their closes are counted
+            while open > 0 || !stopping.load(Ordering::Relaxed) {
+                for event in server.next_events(Some(Duration::from_millis(10))).unwrap() {
+                    match event {
+                        ServerEvent::Accepted { .. } => open += 1,
+                        ServerEvent::Data { conn, bytes } => server.send(conn, bytes),
+                        ServerEvent::WritableAgain { .. } => {}
+                        ServerEvent::Closed { reason, .. } => {
+                            open -= 1;
+                            closed.push(reason);
+                        }
+                    }
+                }
+            }
+            (server.close(), closed)
+        });
+        EventServer { addr, stop, thread }
+    }
+
+    fn stop(self) -> (Stats, Vec<CloseReason>) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread.join().unwrap()
+    }
+}
+
+/// Runs `workload` against both echoes, checks that the clients got the
+/// same replies, and returns those with the stats of the built-in echo and
+/// of the event one.
+fn parity<F>(config: Config, workload: F) -> (Vec<Vec<u8>>, Stats, Stats)
+where
+    F: Fn(SocketAddr) -> Vec<Vec<u8>>,
+{
+    let builtin = TestServer::with_config(config.clone());
+    let expected = workload(builtin.addr);
+    let builtin = builtin.stop();
+
+    let events = EventServer::new(config);
+    let replies = workload(events.addr);
+    let (stats, _) = events.stop();
+
+    assert!(replies == expected, "the replies differ");
+    assert_eq!(stats.tcp.connections, builtin.tcp.connections);
+    (replies, builtin, stats)
+}
+
+// Both counted every byte of the echo
+fn same_bytes(builtin: &Stats, events: &Stats) {
+    assert_eq!(events.tcp.bytes_read, builtin.tcp.bytes_read);
+    assert_eq!(events.tcp.bytes_written, builtin.tcp.bytes_written);
+}
+
+fn connect(addr: SocketAddr) -> TcpStream {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    stream
+}
+
+// Writes `input` in `chunk` byte writes while reading its echo, then
+// hangs up
+fn echo(addr: SocketAddr, input: Vec<u8>, chunk: usize) -> Vec<u8> {
+    let mut stream = connect(addr);
+    let mut writer = stream.try_clone().unwrap();
+    let len = input.len();
+    let write = thread::spawn(move || {
+        for chunk in input.chunks(chunk) {
+            writer.write_all(chunk).unwrap();
+        }
+    });
+    let mut reply = vec![0; len];
+    stream.read_exact(&mut reply).unwrap();
+    write.join().unwrap();
+    reply
+}
+
+// Bytes that tell their position apart
+fn payload(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8 ^ seed).collect()
+}
+
+#[test]
+fn concurrent_clients_get_the_same_echo() {
+    let (replies, builtin, events) = parity(Config::new("127.0.0.1:0"), |addr| {
+        let clients: Vec<_> = (0..8u8)
+            .map(|i| thread::spawn(move || echo(addr, payload(50_000 + usize::from(i) * 999, i), 1 + usize::from(i) * 97)))
+            .collect();
+        clients.into_iter().map(|client| client.join().unwrap()).collect()
+    });
+    for (i, reply) in replies.iter().enumerate() {
+        assert!(*reply == payload(50_000 + i * 999, i as u8), "echo {} differs", i);
+    }
+    same_bytes(&builtin, &events);
+}
+
+#[test]
+fn backpressure_gets_everything_through() {
+    let mut config = Config::new("127.0.0.1:0");
+    config.max_queued = Some(16 << 10);
+    config.overflow = Overflow::Backpressure;
+    let (replies, builtin, events) = parity(config, |addr| vec![echo(addr, payload(4 << 20, 7), 64 << 10)]);
+    assert!(replies[0] == payload(4 << 20, 7));
+    same_bytes(&builtin, &events);
+}
+
+#[test]
+fn overflow_disconnect_closes_a_client_that_never_reads() {
+    let mut config = Config::new("127.0.0.1:0");
+    config.max_queued = Some(64 << 10);
+    config.overflow = Overflow::Disconnect;
+    let (_, builtin, events) = parity(config, |addr| {
+        let mut stream = connect(addr);
+        let chunk = payload(64 << 10, 3);
+        while stream.write_all(&chunk).is_ok() {}
+        // How much of the echo made it before the close depends on when
+        // the server read
+        let _ = stream.read_to_end(&mut Vec::new());
+        vec![]
+    });
+    assert_eq!(builtin.overflow.disconnects, 1);
+    assert_eq!(events.overflow.disconnects, 1);
+    // Handed out a queue's worth at a time, not as fast as it comes
+    assert!(events.tcp.bytes_read < 64 << 20, "read {} bytes", events.tcp.bytes_read);
+}
+
+#[test]
+fn event_echo_reports_the_close() {
+    let events = EventServer::new(Config::new("127.0.0.1:0"));
+    assert_eq!(echo(events.addr, b"hello".to_vec(), 2), b"hello");
+    let (stats, closed) = events.stop();
+    assert_eq!(closed, [CloseReason::Eof]);
+    assert_eq!(stats.tcp.connections, 1);
+}
@@ -0,0 +1,121 @@
+//! `Config::exclusive_accept`, the listener shared by the worker
+//! processes registered with EPOLLEXCLUSIVE. The workers are processes of
+//! their own, so these run the binary and read the summary it prints.
+
+#![cfg(target_os = "linux")]
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const WORKERS: &str = "4";
+const BURST: u64 = 200;
+
+/// The binary on a free port, killed once dropped.
+struct Binary {
+    child: Child,
+    port: u16,
+}
+
+impl Binary {
+    fn spawn(args: &[&str]) -> Binary {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let child = Command::new(env!("CARGO_BIN_EXE_mio-echo-server"))
+            .arg(format!("127.0.0.1:{}", port))
+            .args(["--workers-processes", WORKERS])
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        Binary { child, port }
+    }
+
+    fn connect(&self) -> TcpStream {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            match TcpStream::connect(("127.0.0.1", self.port)) {
+                Ok(client) => {
+                    client.set_read_timeout(Some(TIMEOUT)).unwrap();
+                    return client;
+                }
+                Err(e) => assert!(Instant::now() < deadline, "connecting: {}", e),
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    // Connects the burst at once, then echoes each client
+    fn burst(&self) {
+        let mut clients: Vec<TcpStream> = (0..BURST).map(|_| self.connect()).collect();
+        for (i, client) in clients.iter_mut().enumerate() {
+            let message = format!("client {}", i);
+            client.write_all(message.as_bytes()).unwrap();
+            let mut reply = vec![0; message.len()];
+            client.read_exact(&mut reply).unwrap();
+            assert_eq!(reply, message.as_bytes());
+        }
+    }
+
+    // Stops the workers with SIGTERM, returns the connections and the
+    // empty accepts of the summary the parent prints
+    fn stop(mut self) -> (u64, u64) {
+        assert_eq!(unsafe { libc::kill(self.child.id() as libc::pid_t, libc::SIGTERM) }, 0);
+        let deadline = Instant::now() + TIMEOUT;
+        let status = loop {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                break status;
+            }
+            assert!(Instant::now() < deadline, "timed out waiting for the exit");
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert!(status.success(), "exited with {}", status);
+        let mut out = String::new();
+        self.child.stdout.take().unwrap().read_to_string(&mut out).unwrap();
+        let line = out.lines().find(|line| line.starts_with("workers: ")).expect("no summary");
+        // `N connections, ..., N empty accepts`
+        let count = |what: &str| -> u64 {
+            let field = line["workers: ".len()..].split(", ").find(|field| field.ends_with(what)).unwrap();
+            field.split(' ').next().unwrap().parse().unwrap()
+        };
+        (count(" connections"), count(" empty accepts"))
+    }
+}
+
+impl Drop for Binary {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn exclusive_listeners_serve_a_burst_of_connections() {
+    let binary = Binary::spawn(&["--exclusive-accept"]);
+    binary.burst();
+    let (connections, empty) = binary.stop();
+    assert_eq!(connections, BURST);
+    assert!(empty <= BURST / 10, "{} empty accepts", empty);
+}
+
+// Every worker is woken by each connection without the flag, which only
+// shows with a CPU for each to run on
+#[test]
+#[ignore = "measures the thundering herd, needs several CPUs"]
+fn exclusive_listeners_wake_one_worker() {
+    let binary = Binary::spawn(&[]);
+    binary.burst();
+    let (connections, shared) = binary.stop();
+    assert_eq!(connections, BURST);
+
+    let binary = Binary::spawn(&["--exclusive-accept"]);
+    binary.burst();
+    let (connections, exclusive) = binary.stop();
+    assert_eq!(connections, BURST);
+    assert!(exclusive <= BURST / 10, "{} empty accepts with the flag", exclusive);
+    assert!(shared >= BURST / 2, "{} empty accepts without the flag", shared);
+}
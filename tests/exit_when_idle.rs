@@ -0,0 +1,72 @@
+//! `Config::exit_when_idle`, timed by a `ManualClock`.
+
+mod driver;
+
+use std::time::Duration;
+
+use mio_echo_server::{Config, ManualClock, Server};
+
+use driver::{connect, receive, send};
+
+const IDLE: Duration = Duration::from_secs(30);
+
+fn idle_server() -> (Server, ManualClock) {
+    let config = Config {
+        exit_when_idle: Some(IDLE),
+        ..Config::new("127.0.0.1:0")
+    };
+    let clock = ManualClock::new();
+    let server = Server::builder(config).clock(clock.clone()).build().unwrap();
+    (server, clock)
+}
+
+fn advance(server: &mut Server, clock: &ManualClock, duration: Duration) {
+    clock.advance(duration);
+    server.poll_once(Some(Duration::ZERO)).unwrap();
+}
+
+fn echo_and_leave(server: &mut Server) {
+    let mut client = connect(server);
+    send(server, &mut client, b"hello");
+    assert_eq!(receive(server, &mut client, 5), b"hello");
+    drop(client);
+    // The manual clock stands still meanwhile
+    for _ in 0..10 {
+        server.poll_once(Some(Duration::from_millis(10))).unwrap();
+    }
+}
+
+#[test]
+fn a_server_no_one_connects_to_stops() {
+    let (mut server, clock) = idle_server();
+    advance(&mut server, &clock, IDLE - Duration::from_secs(1));
+    assert!(!server.shutdown_requested());
+    advance(&mut server, &clock, Duration::from_secs(1));
+    assert!(server.shutdown_requested());
+}
+
+#[test]
+fn a_connection_before_the_deadline_resets_the_clock() {
+    let (mut server, clock) = idle_server();
+    advance(&mut server, &clock, IDLE - Duration::from_secs(1));
+    echo_and_leave(&mut server);
+
+    // Past the first deadline, short of the one the client left
+    advance(&mut server, &clock, IDLE - Duration::from_secs(1));
+    assert!(!server.shutdown_requested());
+    advance(&mut server, &clock, Duration::from_secs(1));
+    assert!(server.shutdown_requested());
+}
+
+#[test]
+fn a_connected_client_keeps_it_running() {
+    let (mut server, clock) = idle_server();
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"hello");
+    assert_eq!(receive(&mut server, &mut client, 5), b"hello");
+    // Connected for longer than the idle time
+    advance(&mut server, &clock, IDLE * 2);
+    assert!(!server.shutdown_requested());
+    send(&mut server, &mut client, b"still");
+    assert_eq!(receive(&mut server, &mut client, 5), b"still");
+}
@@ -0,0 +1,57 @@
+//! `Config::first_byte_timeout`, shorter than the idle timeout until a
+//! client says something, timed by a `ManualClock`.
+
+mod driver;
+
+use std::time::Duration;
+
+use mio_echo_server::{Config, ManualClock, Server};
+
+use driver::{connect, poll_until, read_available, receive, receive_to_close, send};
+
+const FIRST_BYTE: Duration = Duration::from_secs(5);
+const IDLE: Duration = Duration::from_secs(600);
+
+fn timeout_server() -> (Server, ManualClock) {
+    let config = Config {
+        first_byte_timeout: Some(FIRST_BYTE),
+        idle_timeout: Some(IDLE),
+        ..Config::new("127.0.0.1:0")
+    };
+    let clock = ManualClock::new();
+    let server = Server::builder(config).clock(clock.clone()).build().unwrap();
+    (server, clock)
+}
+
+fn advance(server: &mut Server, clock: &ManualClock, duration: Duration) {
+    clock.advance(duration);
+    server.poll_once(Some(Duration::ZERO)).unwrap();
+}
+
+#[test]
+fn a_silent_connection_is_dropped() {
+    let (mut server, clock) = timeout_server();
+    let mut silent = connect(&server);
+    poll_until(&mut server, |server| Some(()).filter(|()| server.stats().tcp.connections == 1));
+    advance(&mut server, &clock, FIRST_BYTE - Duration::from_secs(1));
+    assert_eq!(read_available(&mut silent), (Vec::new(), false), "dropped early");
+    advance(&mut server, &clock, Duration::from_secs(1));
+    assert_eq!(receive_to_close(&mut server, &mut silent), b"");
+    assert_eq!(server.stats().silent, 1);
+}
+
+#[test]
+fn a_byte_in_time_switches_to_the_idle_timeout() {
+    let (mut server, clock) = timeout_server();
+    let mut client = connect(&server);
+    poll_until(&mut server, |server| Some(()).filter(|()| server.stats().tcp.connections == 1));
+    advance(&mut server, &clock, Duration::from_secs(4));
+    send(&mut server, &mut client, b"x");
+    assert_eq!(receive(&mut server, &mut client, 1), b"x");
+
+    // Well past the first byte deadline, short of the idle one
+    advance(&mut server, &clock, IDLE - Duration::from_secs(1));
+    send(&mut server, &mut client, b"still");
+    assert_eq!(receive(&mut server, &mut client, 5), b"still");
+    assert_eq!(server.stats().silent, 0);
+}
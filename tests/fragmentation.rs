@@ -0,0 +1,118 @@
+//! Whatever way a payload is cut up, delayed and read back, the echo is the
+//! payload, in every stream mode.
+
+#[path = "../benches/support/mod.rs"]
+mod support;
+
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use proptest::prelude::*;
+use proptest::test_runner::{Config as RunnerConfig, TestRunner};
+
+use mio_echo_server::{Config, Mode};
+use support::TestServer;
+
+const CASES: u32 = 24;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One write of the client: how many bytes, then how long to wait.
+type Fragment = (usize, Duration);
+
+fn fragments() -> impl Strategy<Value = Vec<Fragment>> {
+    let sleep = (0..=2u64).prop_map(Duration::from_millis);
+    prop::collection::vec((1..512usize, sleep), 1..16)
+}
+
+fn read_sizes() -> impl Strategy<Value = Vec<usize>> {
+    prop::collection::vec(1..1024usize, 1..16)
+}
+
+/// Writes `input` cut at `fragments`, the rest at once, while reading back
+/// as many bytes in reads of `read_sizes`, cycled.
+fn echo(server: &TestServer, input: &[u8], fragments: &[Fragment], read_sizes: &[usize]) -> Vec<u8> {
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream.set_nodelay(true).unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let mut reader = stream.try_clone().unwrap();
+    let (expected, read_sizes) = (input.len(), read_sizes.to_vec());
+    let read = thread::spawn(move || {
+        let mut echo = Vec::with_capacity(expected);
+        for &size in read_sizes.iter().cycle() {
+            if echo.len() == expected {
+                break;
+            }
+            let mut chunk = vec![0; size.min(expected - echo.len())];
+            match reader.read(&mut chunk).expect("echo timed out") {
+                0 => break,
+                n => echo.extend_from_slice(&chunk[..n]),
+            }
+        }
+        echo
+    });
+
+    let mut rest = input;
+    for &(len, sleep) in fragments {
+        let (fragment, after) = rest.split_at(len.min(rest.len()));
+        stream.write_all(fragment).unwrap();
+        thread::sleep(sleep);
+        rest = after;
+    }
+    stream.write_all(rest).unwrap();
+    let echo = read.join().unwrap();
+    stream.shutdown(Shutdown::Both).unwrap();
+    echo
+}
+
+/// Checks the echo of what `frame` makes of random payloads on a server in
+/// `mode`.
+fn check(mode: Mode, frame: fn(Vec<Vec<u8>>) -> Vec<u8>) {
+    let mut config = Config::new("127.0.0.1:0");
+    config.mode = mode;
+    let server = TestServer::with_config(config);
+
+    let messages = prop::collection::vec(prop::collection::vec(any::<u8>(), 0..1024), 1..8);
+    let mut runner = TestRunner::new(RunnerConfig::with_cases(CASES));
+    runner
+        .run(
+            &(messages, fragments(), read_sizes()),
+            |(messages, fragments, read_sizes)| {
+                let input = frame(messages);
+                prop_assert_eq!(echo(&server, &input, &fragments, &read_sizes), input);
+                Ok(())
+            },
+        )
+        .unwrap();
+    server.stop();
+}
+
+#[test]
+fn echo_mode() {
+    check(Mode::Echo, |messages| messages.concat());
+}
+
+#[test]
+fn line_mode() {
+    check(Mode::Line, |messages| {
+        let mut input = Vec::new();
+        for message in messages {
+            input.extend(message.into_iter().filter(|&b| b != b'\n'));
+            input.push(b'\n');
+        }
+        input
+    });
+}
+
+#[test]
+fn length_mode() {
+    check(Mode::Length, |messages| {
+        let mut input = Vec::new();
+        for message in messages {
+            input.extend_from_slice(&(message.len() as u32).to_be_bytes());
+            input.extend_from_slice(&message);
+        }
+        input
+    });
+}
@@ -0,0 +1,49 @@
+//! `Config::freebind`, listening on an address no interface has yet.
+
+#![cfg(target_os = "linux")]
+
+mod driver;
+
+use std::io::ErrorKind;
+
+use mio_echo_server::{Config, Error, Server};
+
+use driver::{connect, receive, send};
+
+// TEST-NET-1, never configured here
+const UNASSIGNED: &str = "192.0.2.1:0";
+
+fn bind(listen: &str, udp: Option<&str>, freebind: bool) -> Result<Server, Error> {
+    let config = Config {
+        udp: udp.map(str::to_string),
+        freebind,
+        ..Config::new(listen)
+    };
+    Server::from_config(config)
+}
+
+#[test]
+fn an_unassigned_address_binds_only_with_freebind() {
+    for udp in [None, Some(UNASSIGNED)] {
+        let listen = if udp.is_some() { "127.0.0.1:0" } else { UNASSIGNED };
+        match bind(listen, udp, false) {
+            Err(Error::Bind { ref source, .. }) if source.kind() == ErrorKind::AddrNotAvailable => {}
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("bound {:?} without freebind", udp),
+        }
+
+        let server = bind(listen, udp, true).unwrap();
+        match udp {
+            Some(_) => assert_eq!(server.udp_addr().unwrap().ip().to_string(), "192.0.2.1"),
+            None => assert_eq!(server.local_addr().unwrap().ip().to_string(), "192.0.2.1"),
+        }
+    }
+}
+
+#[test]
+fn a_freebind_listener_on_a_local_address_echoes() {
+    let mut server = bind("127.0.0.1:0", None, true).unwrap();
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"ping");
+    assert_eq!(receive(&mut server, &mut client, 4), b"ping");
+}
@@ -0,0 +1,335 @@
+//! Handlers seen from the clients: what they reply, how they close
+//! connections and how their panics are contained.
+
+mod driver;
+
+use std::io::ErrorKind;
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use mio_echo_server::{Action, CloseReason, Config, Handler, HandlerContext, Server};
+use socket2::SockRef;
+
+use driver::{connect, poll_until, read_available, receive, receive_to_close, send};
+
+/// Replies in upper case, to tell it from the plain echo, and panics on
+/// `BOOM`.
+struct Shouter {
+    closed: Arc<Mutex<Vec<CloseReason>>>,
+}
+
+impl Handler for Shouter {
+    fn on_data(&mut self, _ctx: &mut HandlerContext, data: &[u8]) -> Action {
+        if data.windows(4).any(|w| w == b"BOOM") {
+            panic!("boom");
+        }
+        Action::Reply(data.to_ascii_uppercase())
+    }
+
+    fn on_disconnect(&mut self, _ctx: &mut HandlerContext, reason: CloseReason) {
+        self.closed.lock().unwrap().push(reason);
+    }
+}
+
+/// More than the socket buffers of both sides hold, so that the reply is
+/// only ever partly written until the client reads.
+const LARGE: usize = 8 << 20;
+
+/// Acts on commands: `BIG` and `BYE` get `LARGE` bytes, the latter then
+/// closing, `KILL` closes at once and `MUTE` silences. Anything else is
+/// echoed.
+struct Commands {
+    closed: Arc<Mutex<Vec<CloseReason>>>,
+}
+
+impl Handler for Commands {
+    fn on_data(&mut self, _ctx: &mut HandlerContext, data: &[u8]) -> Action {
+        match data {
+            b"BIG" => Action::Reply(vec![b'x'; LARGE]),
+            b"BYE" => Action::ReplyThenClose(vec![b'x'; LARGE]),
+            b"KILL" => Action::CloseNow,
+            b"MUTE" => Action::Silence,
+            _ => Action::Reply(data.to_vec()),
+        }
+    }
+
+    fn on_disconnect(&mut self, _ctx: &mut HandlerContext, reason: CloseReason) {
+        self.closed.lock().unwrap().push(reason);
+    }
+}
+
+fn commands_server() -> (Server, Arc<Mutex<Vec<CloseReason>>>) {
+    let closed = Arc::new(Mutex::new(Vec::new()));
+    let handler = Commands { closed: closed.clone() };
+    (Server::builder(Config::new("127.0.0.1:0")).handler(handler).build().unwrap(), closed)
+}
+
+// Sends a command, and polls until the server read it while the client
+// reads nothing
+fn command(server: &mut Server, stream: &mut TcpStream, command: &[u8]) {
+    let before = server.stats().tcp.bytes_read;
+    send(server, stream, command);
+    poll_until(server, |server| Some(()).filter(|()| server.stats().tcp.bytes_read == before + command.len() as u64));
+    // The server writes what the sockets take, then waits
+    for _ in 0..10 {
+        server.poll_once(Some(Duration::from_millis(1))).unwrap();
+    }
+}
+
+fn shouting_server(panic_limit: Option<u64>) -> (Server, Arc<Mutex<Vec<CloseReason>>>) {
+    let closed = Arc::new(Mutex::new(Vec::new()));
+    let mut builder = Server::builder(Config::new("127.0.0.1:0")).handler(Shouter { closed: closed.clone() });
+    if let Some(limit) = panic_limit {
+        builder = builder.handler_panic_limit(limit);
+    }
+    (builder.build().unwrap(), closed)
+}
+
+#[test]
+fn panic_closes_only_the_connection_handled() {
+    let (mut server, closed) = shouting_server(None);
+    let mut calm = connect(&server);
+    let mut reckless = connect(&server);
+    send(&mut server, &mut calm, b"hello");
+    assert_eq!(receive(&mut server, &mut calm, 5), b"HELLO");
+
+    send(&mut server, &mut reckless, b"BOOM");
+    assert_eq!(receive_to_close(&mut server, &mut reckless), b"");
+    assert_eq!(server.stats().handler_panics, 1);
+    assert_eq!(*closed.lock().unwrap(), [CloseReason::HandlerPanic]);
+
+    // Neither the other connection nor new ones notice
+    send(&mut server, &mut calm, b"still here");
+    assert_eq!(receive(&mut server, &mut calm, 10), b"STILL HERE");
+    let mut late = connect(&server);
+    send(&mut server, &mut late, b"late");
+    assert_eq!(receive(&mut server, &mut late, 4), b"LATE");
+    assert_eq!(server.stats().handler_panics, 1);
+    assert_eq!(server.stats().tcp.connections, 3);
+}
+
+#[test]
+fn panic_limit_falls_back_to_the_echo() {
+    let (mut server, closed) = shouting_server(Some(2));
+    let mut calm = connect(&server);
+    send(&mut server, &mut calm, b"hi");
+    assert_eq!(receive(&mut server, &mut calm, 2), b"HI");
+    for panics in 1..=2 {
+        let mut reckless = connect(&server);
+        send(&mut server, &mut reckless, b"BOOM");
+        receive_to_close(&mut server, &mut reckless);
+        assert_eq!(server.stats().handler_panics, panics);
+    }
+
+    // Retired by the second panic, the handler sees nothing more, not even
+    // the disconnection of the client that panicked it
+    send(&mut server, &mut calm, b"hi BOOM");
+    assert_eq!(receive(&mut server, &mut calm, 7), b"hi BOOM");
+    assert_eq!(server.close().handler_panics, 2);
+    assert_eq!(*closed.lock().unwrap(), [CloseReason::HandlerPanic]);
+}
+
+#[test]
+fn panic_on_connect_closes_only_that_connection() {
+    struct Picky(u32);
+
+    impl Handler for Picky {
+        fn on_connect(&mut self, _ctx: &mut HandlerContext) {
+            self.0 += 1;
+            assert!(self.0 != 2, "second connection");
+        }
+
+        fn on_data(&mut self, _ctx: &mut HandlerContext, data: &[u8]) -> Action {
+            Action::Reply(data.to_vec())
+        }
+    }
+
+    let mut server = Server::builder(Config::new("127.0.0.1:0")).handler(Picky(0)).build().unwrap();
+    let mut first = connect(&server);
+    send(&mut server, &mut first, b"one");
+    assert_eq!(receive(&mut server, &mut first, 3), b"one");
+    let mut second = connect(&server);
+    assert_eq!(receive_to_close(&mut server, &mut second), b"");
+    let mut third = connect(&server);
+    send(&mut server, &mut third, b"three");
+    assert_eq!(receive(&mut server, &mut third, 5), b"three");
+    send(&mut server, &mut first, b"one");
+    assert_eq!(receive(&mut server, &mut first, 3), b"one");
+    assert_eq!(server.stats().handler_panics, 1);
+}
+
+#[test]
+fn reply_then_close_writes_everything_first() {
+    let (mut server, closed) = commands_server();
+    let mut stream = connect(&server);
+    command(&mut server, &mut stream, b"BYE");
+    assert!(closed.lock().unwrap().is_empty(), "closed before the reply was written");
+    let reply = receive_to_close(&mut server, &mut stream);
+    assert_eq!(reply.len(), LARGE);
+    assert_eq!(*closed.lock().unwrap(), [CloseReason::HandlerClose]);
+    assert_eq!(server.stats().handler_closes, 1);
+}
+
+#[test]
+fn close_now_drops_the_reply_being_written() {
+    let (mut server, closed) = commands_server();
+    let mut stream = connect(&server);
+    command(&mut server, &mut stream, b"BIG");
+    send(&mut server, &mut stream, b"KILL");
+    poll_until(&mut server, |_| Some(()).filter(|()| !closed.lock().unwrap().is_empty()));
+    assert_eq!(*closed.lock().unwrap(), [CloseReason::HandlerAbort]);
+    let reply = receive_to_close(&mut server, &mut stream);
+    assert!(reply.len() < LARGE, "all {} bytes written", reply.len());
+    assert_eq!(server.stats().handler_aborts, 1);
+}
+
+#[test]
+fn silence_finishes_the_reply_being_written() {
+    let (mut server, closed) = commands_server();
+    let mut stream = connect(&server);
+    command(&mut server, &mut stream, b"BIG");
+    command(&mut server, &mut stream, b"MUTE");
+    command(&mut server, &mut stream, b"hello");
+    assert_eq!(receive(&mut server, &mut stream, LARGE).len(), LARGE);
+    // Nothing after it, and still open
+    for _ in 0..10 {
+        server.poll_once(Some(Duration::from_millis(1))).unwrap();
+    }
+    assert_eq!(read_available(&mut stream), (Vec::new(), false));
+    assert!(closed.lock().unwrap().is_empty());
+}
+
+#[test]
+fn reply_is_written_whole_over_partial_writes() {
+    let (mut server, _) = commands_server();
+    let mut stream = connect(&server);
+    command(&mut server, &mut stream, b"BIG");
+    command(&mut server, &mut stream, b"hello");
+    let reply = receive(&mut server, &mut stream, LARGE + 5);
+    assert_eq!(reply.len(), LARGE + 5);
+    assert!(reply[..LARGE].iter().all(|&b| b == b'x'));
+    assert_eq!(&reply[LARGE..], b"hello");
+}
+
+type Dropped = Arc<Mutex<Vec<u32>>>;
+/// Why each connection closed, and its count as `on_disconnect` sees it.
+type Closed = Arc<Mutex<Vec<(CloseReason, Option<u32>)>>>;
+
+/// Attached to every connection on connect, counting its messages and
+/// recording that count once dropped.
+struct Session {
+    messages: u32,
+    dropped: Dropped,
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.dropped.lock().unwrap().push(self.messages);
+    }
+}
+
+/// Replies with the number of messages of the connection so far, and
+/// panics on `BOOM` once it is counted.
+struct Sessions {
+    dropped: Dropped,
+    closed: Closed,
+}
+
+impl Handler for Sessions {
+    fn on_connect(&mut self, ctx: &mut HandlerContext) {
+        ctx.set_data(Session { messages: 0, dropped: self.dropped.clone() });
+    }
+
+    fn on_data(&mut self, ctx: &mut HandlerContext, data: &[u8]) -> Action {
+        let session = ctx.data::<Session>().expect("no session");
+        session.messages += 1;
+        if data == b"BOOM" {
+            panic!("boom");
+        }
+        Action::Reply(session.messages.to_string().into_bytes())
+    }
+
+    fn on_disconnect(&mut self, ctx: &mut HandlerContext, reason: CloseReason) {
+        let messages = ctx.data::<Session>().map(|session| session.messages);
+        self.closed.lock().unwrap().push((reason, messages));
+    }
+}
+
+fn sessions_server() -> (Server, Dropped, Closed) {
+    let dropped = Arc::new(Mutex::new(Vec::new()));
+    let closed = Arc::new(Mutex::new(Vec::new()));
+    let handler = Sessions { dropped: dropped.clone(), closed: closed.clone() };
+    (Server::builder(Config::new("127.0.0.1:0")).handler(handler).build().unwrap(), dropped, closed)
+}
+
+// Sends each message, checking it is counted
+fn count(server: &mut Server, stream: &mut TcpStream, messages: u32) {
+    for i in 1..=messages {
+        send(server, stream, b"hi");
+        assert_eq!(receive(server, stream, 1), i.to_string().as_bytes());
+    }
+}
+
+// Polls until a session was dropped, then a while longer for a second drop
+fn dropped_once(server: &mut Server, dropped: &Dropped) -> u32 {
+    poll_until(server, |_| Some(()).filter(|()| !dropped.lock().unwrap().is_empty()));
+    for _ in 0..10 {
+        server.poll_once(Some(Duration::from_millis(1))).unwrap();
+    }
+    let dropped = dropped.lock().unwrap();
+    assert_eq!(dropped.len(), 1, "dropped {:?}", *dropped);
+    dropped[0]
+}
+
+#[test]
+fn session_lasts_as_long_as_the_connection() {
+    let (mut server, dropped, closed) = sessions_server();
+    let mut stream = connect(&server);
+    count(&mut server, &mut stream, 3);
+    assert!(dropped.lock().unwrap().is_empty());
+
+    drop(stream);
+    assert_eq!(dropped_once(&mut server, &dropped), 3);
+    assert_eq!(*closed.lock().unwrap(), [(CloseReason::Eof, Some(3))]);
+}
+
+#[test]
+fn session_is_dropped_on_a_reset() {
+    let (mut server, dropped, closed) = sessions_server();
+    let mut stream = connect(&server);
+    count(&mut server, &mut stream, 2);
+    // Closing with a zero linger sends a reset
+    SockRef::from(&stream).set_linger(Some(Duration::ZERO)).unwrap();
+    drop(stream);
+    assert_eq!(dropped_once(&mut server, &dropped), 2);
+    assert_eq!(*closed.lock().unwrap(), [(CloseReason::Reset(ErrorKind::ConnectionReset), Some(2))]);
+}
+
+#[test]
+fn session_is_dropped_when_the_handler_panics() {
+    let (mut server, dropped, closed) = sessions_server();
+    let mut stream = connect(&server);
+    count(&mut server, &mut stream, 1);
+    send(&mut server, &mut stream, b"BOOM");
+    assert_eq!(receive_to_close(&mut server, &mut stream), b"");
+    // Counted before the panic, which left it attached
+    assert_eq!(dropped_once(&mut server, &dropped), 2);
+    assert_eq!(*closed.lock().unwrap(), [(CloseReason::HandlerPanic, Some(2))]);
+}
+
+#[test]
+fn sessions_left_are_dropped_on_close() {
+    let (mut server, dropped, closed) = sessions_server();
+    let mut first = connect(&server);
+    let mut second = connect(&server);
+    count(&mut server, &mut first, 1);
+    count(&mut server, &mut second, 2);
+    server.close();
+    let mut dropped = dropped.lock().unwrap().clone();
+    dropped.sort_unstable();
+    assert_eq!(dropped, [1, 2]);
+    let mut closed = closed.lock().unwrap().clone();
+    closed.sort_unstable_by_key(|&(_, messages)| messages);
+    assert_eq!(closed, [(CloseReason::Shutdown, Some(1)), (CloseReason::Shutdown, Some(2))]);
+}
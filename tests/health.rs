@@ -0,0 +1,84 @@
+//! The health listener, probed alongside echo clients.
+
+mod driver;
+
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use mio_echo_server::{Config, ManualClock, Server};
+
+use driver::{connect, receive, receive_to_close, send};
+
+const HEALTHY: &str = "HTTP/1.0 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+const DRAINING: &str = "HTTP/1.0 503 Service Unavailable\r\nContent-Length: 8\r\nConnection: close\r\n\r\ndraining";
+
+fn probe(server: &mut Server, addr: SocketAddr) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_nonblocking(true).unwrap();
+    send(server, &mut stream, b"GET /health HTTP/1.0\r\n\r\n");
+    String::from_utf8(receive_to_close(server, &mut stream)).unwrap()
+}
+
+fn health_config() -> Config {
+    Config {
+        health_addr: Some("127.0.0.1:0".to_string()),
+        ..Config::new("127.0.0.1:0")
+    }
+}
+
+#[test]
+fn a_serving_server_is_healthy() {
+    let mut server = Server::from_config(health_config()).unwrap();
+    let health = server.health_addr().expect("no health listener");
+    for _ in 0..3 {
+        assert_eq!(probe(&mut server, health), HEALTHY);
+    }
+}
+
+#[test]
+fn a_draining_server_is_not() {
+    let config = Config {
+        duration: Some(Duration::from_secs(60)),
+        drain_timeout: Duration::from_secs(5),
+        ..health_config()
+    };
+    let clock = ManualClock::new();
+    let mut server = Server::builder(config).clock(clock.clone()).build().unwrap();
+    let health = server.health_addr().expect("no health listener");
+    // Keeps the drain going
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"ping");
+    assert_eq!(receive(&mut server, &mut client, 4), b"ping");
+    assert_eq!(probe(&mut server, health), HEALTHY);
+
+    clock.advance(Duration::from_secs(60));
+    server.poll_once(Some(Duration::ZERO)).unwrap();
+    assert!(!server.shutdown_requested());
+    assert_eq!(probe(&mut server, health), DRAINING);
+}
+
+#[test]
+fn probes_are_not_echo_traffic() {
+    let config = Config {
+        max_clients: 1,
+        ..health_config()
+    };
+    let mut server = Server::from_config(config).unwrap();
+    let health = server.health_addr().expect("no health listener");
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"ping");
+    assert_eq!(receive(&mut server, &mut client, 4), b"ping");
+    let before = server.stats().tcp;
+
+    // Answered with the only client slot taken
+    for _ in 0..3 {
+        assert_eq!(probe(&mut server, health), HEALTHY);
+    }
+    let after = &server.stats().tcp;
+    assert_eq!(
+        (after.connections, after.bytes_read, after.bytes_written, after.rejected),
+        (before.connections, before.bytes_read, before.bytes_written, before.rejected)
+    );
+    send(&mut server, &mut client, b"pong");
+    assert_eq!(receive(&mut server, &mut client, 4), b"pong");
+}
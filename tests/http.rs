@@ -0,0 +1,136 @@
+//! `Mode::Http` and the health probes, as HTTP clients see them.
+
+mod driver;
+
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use mio_echo_server::{Config, Mode, Server};
+
+use driver::{connect, poll_until, read_available, receive, receive_to_close, send};
+
+fn http_server() -> Server {
+    let config = Config {
+        mode: Mode::Http,
+        ..Config::new("127.0.0.1:0")
+    };
+    Server::from_config(config).unwrap()
+}
+
+// What the server answers with, see `http::respond`
+fn response(status: &str, content_type: &str, body: &str, keep_alive: bool) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: {}\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        if keep_alive { "keep-alive" } else { "close" },
+        body
+    )
+}
+
+fn text(data: Vec<u8>) -> String {
+    String::from_utf8(data).unwrap()
+}
+
+#[test]
+fn keep_alive_serves_several_requests() {
+    let mut server = http_server();
+    let mut client = connect(&server);
+    for path in &["/a", "/b"] {
+        send(&mut server, &mut client, format!("GET {} HTTP/1.1\r\nHost: x\r\n\r\n", path).as_bytes());
+        let expected = response("200 OK", "text/plain", &format!("GET {} HTTP/1.1\nHost: x\n", path), true);
+        assert_eq!(text(receive(&mut server, &mut client, expected.len())), expected);
+    }
+    assert!(!read_available(&mut client).1, "closed after keep-alive requests");
+    assert_eq!(server.stats().tcp.connections, 1);
+}
+
+#[test]
+fn connection_close_ends_the_connection() {
+    let mut server = http_server();
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+    let expected = response("200 OK", "text/plain", "GET / HTTP/1.1\nConnection: close\n", false);
+    assert_eq!(text(receive_to_close(&mut server, &mut client)), expected);
+}
+
+#[test]
+fn a_body_split_across_reads() {
+    let mut server = http_server();
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"POST /echo HTTP/1.1\r\nContent-Le");
+    send(&mut server, &mut client, b"ngth: 11\r\n\r\nhello");
+    // Nothing until the body is whole
+    for _ in 0..10 {
+        server.poll_once(Some(Duration::from_millis(10))).unwrap();
+    }
+    assert_eq!(read_available(&mut client), (Vec::new(), false));
+    send(&mut server, &mut client, b" world");
+    let expected = response("200 OK", "application/octet-stream", "hello world", true);
+    assert_eq!(text(receive(&mut server, &mut client, expected.len())), expected);
+}
+
+#[test]
+fn pipelined_requests_are_answered_in_order() {
+    let mut server = http_server();
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"GET /1 HTTP/1.1\r\n\r\nGET /2 HTTP/1.0\r\n\r\n");
+    let expected = response("200 OK", "text/plain", "GET /1 HTTP/1.1\n", true)
+        + &response("200 OK", "text/plain", "GET /2 HTTP/1.0\n", false);
+    assert_eq!(text(receive_to_close(&mut server, &mut client)), expected);
+}
+
+#[test]
+fn requests_past_the_limits_are_refused() {
+    let oversized = format!("GET / HTTP/1.1\r\nX-Padding: {}\r\n\r\n", "x".repeat(8 << 10));
+    let cases = [
+        (oversized.as_str(), "400 Bad Request"),
+        ("GET /\r\n\r\n", "400 Bad Request"),
+        ("POST / HTTP/1.1\r\nContent-Length: 2000000\r\n\r\n", "413 Payload Too Large"),
+        ("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n", "411 Length Required"),
+    ];
+    let mut server = http_server();
+    for &(request, status) in &cases {
+        let mut client = connect(&server);
+        send(&mut server, &mut client, request.as_bytes());
+        let expected = response(status, "text/plain", status, false);
+        assert_eq!(text(receive_to_close(&mut server, &mut client)), expected);
+    }
+}
+
+// The answer to a health probe
+fn probe(server: &mut Server, addr: SocketAddr) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_nonblocking(true).unwrap();
+    send(server, &mut stream, b"GET /health HTTP/1.0\r\n\r\n");
+    text(receive_to_close(server, &mut stream))
+}
+
+#[test]
+fn health_probes_get_a_503_once_draining() {
+    let config = Config {
+        health_addr: Some("127.0.0.1:0".to_string()),
+        duration: Some(Duration::from_millis(200)),
+        // Held by the client until the test is over
+        drain_timeout: Duration::from_secs(60),
+        ..Config::new("127.0.0.1:0")
+    };
+    let mut server = Server::from_config(config).unwrap();
+    let health = server.health_addr().expect("no health listener");
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"ping");
+    assert_eq!(receive(&mut server, &mut client, 4), b"ping");
+
+    assert_eq!(probe(&mut server, health), "HTTP/1.0 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok");
+    let draining = poll_until(&mut server, |server| {
+        Some(probe(server, health)).filter(|answer| answer.contains("503"))
+    });
+    assert_eq!(
+        draining,
+        "HTTP/1.0 503 Service Unavailable\r\nContent-Length: 8\r\nConnection: close\r\n\r\ndraining"
+    );
+    // The client is still served meanwhile
+    send(&mut server, &mut client, b"pong");
+    assert_eq!(receive(&mut server, &mut client, 4), b"pong");
+}
@@ -0,0 +1,109 @@
+//! Running out of descriptors, in a child process with a low limit.
+
+#![cfg(target_os = "linux")]
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// Open file limit of the server.
+const FD_LIMIT: libc::rlim_t = 64;
+/// Descriptors the server inherits without knowing, more than its slack,
+/// so that it runs out before its client limit.
+const INHERITED_FDS: usize = 40;
+
+/// The binary on a free port under `FD_LIMIT`, killed once dropped.
+struct LimitedServer {
+    child: Child,
+    port: u16,
+}
+
+impl LimitedServer {
+    fn spawn() -> LimitedServer {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let mut command = Command::new(env!("CARGO_BIN_EXE_mio-echo-server"));
+        command.arg(format!("127.0.0.1:{}", port)).stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
+        unsafe {
+            command.pre_exec(|| {
+                for _ in 0..INHERITED_FDS {
+                    if libc::dup(0) < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                let limit = libc::rlimit {
+                    rlim_cur: FD_LIMIT,
+                    rlim_max: FD_LIMIT,
+                };
+                if libc::setrlimit(libc::RLIMIT_NOFILE, &limit) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let server = LimitedServer { child: command.spawn().unwrap(), port };
+        // Until it listens
+        let deadline = Instant::now() + TIMEOUT;
+        while server.connect().is_err() {
+            assert!(Instant::now() < deadline, "the server didn't start");
+            thread::sleep(Duration::from_millis(10));
+        }
+        server
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        TcpStream::connect(("127.0.0.1", self.port))
+    }
+
+    fn running(&mut self) -> bool {
+        self.child.try_wait().unwrap().is_none()
+    }
+}
+
+impl Drop for LimitedServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Whether `stream` gets `message` back within `wait`
+fn echoed(stream: &mut TcpStream, message: &[u8], wait: Duration) -> bool {
+    stream.set_read_timeout(Some(wait)).unwrap();
+    let mut reply = vec![0; message.len()];
+    stream.read_exact(&mut reply).is_ok() && reply == message
+}
+
+#[test]
+fn clients_outlive_running_out_of_descriptors() {
+    let mut server = LimitedServer::spawn();
+    let mut clients: Vec<TcpStream> = (0..40).map(|_| server.connect().unwrap()).collect();
+    for client in &mut clients {
+        client.write_all(b"ping").unwrap();
+    }
+    thread::sleep(Duration::from_millis(300));
+    let served: Vec<bool> =
+        clients.iter_mut().map(|client| echoed(client, b"ping", Duration::from_millis(50))).collect();
+    let count = served.iter().filter(|&&served| served).count();
+    assert!(count > 0 && count < clients.len(), "served {} of {}", count, clients.len());
+    // The first ones, in the order the backlog hands them out
+    assert!(served[..count].iter().all(|&served| served));
+
+    // Those accepted are still served, by the same process
+    assert!(server.running(), "the server exited");
+    for client in &mut clients[..count] {
+        client.write_all(b"again").unwrap();
+        assert!(echoed(client, b"again", TIMEOUT));
+    }
+
+    // Their leaving lets the ones waiting in the backlog in
+    let waiting = clients.split_off(count);
+    clients.truncate(count.saturating_sub(5));
+    for mut client in waiting.into_iter().take(5) {
+        assert!(echoed(&mut client, b"ping", TIMEOUT), "a waiting client wasn't served");
+    }
+    assert!(server.running(), "the server exited");
+}
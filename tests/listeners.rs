@@ -0,0 +1,76 @@
+//! Entries of `Config::listeners`, each serving its clients with its own
+//! settings.
+
+#[path = "../benches/support/mod.rs"]
+mod support;
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use mio_echo_server::{Config, ListenerConfig, Mode};
+use support::TestServer;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait for an echo that shouldn't come.
+const SILENCE: Duration = Duration::from_millis(200);
+
+fn connect(addr: SocketAddr) -> TcpStream {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    stream
+}
+
+fn read(stream: &mut TcpStream, len: usize) -> Vec<u8> {
+    let mut buf = vec![0; len];
+    stream.read_exact(&mut buf).unwrap();
+    buf
+}
+
+// Nothing to read for a while
+fn silent(stream: &mut TcpStream) -> bool {
+    stream.set_read_timeout(Some(SILENCE)).unwrap();
+    let result = stream.read(&mut [0; 64]);
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    matches!(result, Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut)
+}
+
+#[test]
+fn each_listener_serves_its_own_mode_and_limits() {
+    let mut config = Config::new("127.0.0.1:0");
+    config.listeners.push(ListenerConfig {
+        mode: Some(Mode::Line),
+        max_clients: Some(1),
+        ..ListenerConfig::new("127.0.0.1:0")
+    });
+    let server = TestServer::with_config(config);
+    let line_addr = server.listener_addrs[0];
+
+    // Echoed as it comes on the top-level listener
+    let mut plain = connect(server.addr);
+    plain.write_all(b"abc").unwrap();
+    assert_eq!(read(&mut plain, 3), b"abc");
+
+    // Held until the end of the line on the entry
+    let mut line = connect(line_addr);
+    line.write_all(b"abc").unwrap();
+    assert!(silent(&mut line), "echoed before the end of the line");
+    line.write_all(b"\n").unwrap();
+    assert_eq!(read(&mut line, 4), b"abc\n");
+
+    // Its single slot is taken, not those of the top-level listener
+    let mut refused = connect(line_addr);
+    assert_eq!(refused.read(&mut [0; 64]).unwrap_or(0), 0);
+    let mut other = connect(server.addr);
+    other.write_all(b"def").unwrap();
+    assert_eq!(read(&mut other, 3), b"def");
+
+    let stats = server.stop();
+    assert_eq!(stats.listeners[0].connections, 1);
+    assert_eq!(stats.listeners[0].bytes_read, 4);
+    assert_eq!(stats.listeners[0].bytes_written, 4);
+    assert_eq!(stats.listeners[0].rejected, 1);
+    // Counted for the server as a whole too
+    assert_eq!(stats.tcp.connections, 3);
+    assert_eq!(stats.tcp.bytes_read, 10);
+}
@@ -0,0 +1,46 @@
+//! `Config::max_connections_total`, counted exactly over a burst of
+//! connections accepted in one poll.
+
+mod driver;
+
+use std::time::Duration;
+
+use mio_echo_server::{Config, Server};
+
+use driver::{connect, poll_until, read_available, receive, send};
+
+#[test]
+fn the_first_ones_are_served_then_the_server_stops() {
+    let config = Config {
+        max_connections_total: Some(5),
+        ..Config::new("127.0.0.1:0")
+    };
+    let mut server = Server::from_config(config).unwrap();
+    // All in the backlog before the first poll
+    let mut clients: Vec<_> = (0..8).map(|_| connect(&server)).collect();
+    for client in &mut clients {
+        send(&mut server, client, b"ping");
+    }
+    for _ in 0..10 {
+        server.poll_once(Some(Duration::from_millis(10))).unwrap();
+    }
+
+    let (served, refused): (Vec<_>, Vec<_>) = clients.into_iter().partition(|client| {
+        let mut client = client.try_clone().unwrap();
+        read_available(&mut client) == (b"ping".to_vec(), false)
+    });
+    assert_eq!((served.len(), refused.len()), (5, 3));
+    assert_eq!(server.stats().tcp.connections, 5);
+    // The others got the listener's reset, or its close
+    for mut client in refused {
+        assert!(read_available(&mut client).1, "a refused client is still connected");
+    }
+
+    // Served until they leave
+    let mut served = served;
+    send(&mut server, &mut served[0], b"again");
+    assert_eq!(receive(&mut server, &mut served[0], 5), b"again");
+    assert!(!server.shutdown_requested());
+    drop(served);
+    poll_until(&mut server, |server| Some(()).filter(|()| server.shutdown_requested()));
+}
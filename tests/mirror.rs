@@ -0,0 +1,66 @@
+//! `Config::mirror`, a copy of the client traffic sent to a sink.
+
+mod driver;
+
+use std::net::{TcpListener, TcpStream};
+
+use mio_echo_server::{Config, Server};
+
+use driver::{connect, poll_until, read_available, receive, send};
+
+fn mirrored_server(sink: &TcpListener) -> Server {
+    let config = Config {
+        mirror: Some(sink.local_addr().unwrap().to_string()),
+        ..Config::new("127.0.0.1:0")
+    };
+    Server::from_config(config).unwrap()
+}
+
+// The mirror connection the server opened to `sink`
+fn accept_mirror(server: &mut Server, sink: &TcpListener) -> TcpStream {
+    sink.set_nonblocking(true).unwrap();
+    let conn = poll_until(server, |_| sink.accept().ok()).0;
+    conn.set_nonblocking(true).unwrap();
+    conn
+}
+
+#[test]
+fn the_sink_gets_what_the_client_sent() {
+    let sink = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut server = mirrored_server(&sink);
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"first");
+    assert_eq!(receive(&mut server, &mut client, 5), b"first");
+    let mut mirror = accept_mirror(&mut server, &sink);
+
+    let sent = b"first, then a longer second part";
+    send(&mut server, &mut client, &sent[5..]);
+    assert_eq!(receive(&mut server, &mut client, sent.len() - 5), &sent[5..]);
+    assert_eq!(receive(&mut server, &mut mirror, sent.len()), sent);
+
+    // Torn down with the client
+    drop(client);
+    poll_until(&mut server, |_| Some(()).filter(|()| read_available(&mut mirror).1));
+    assert_eq!((server.stats().mirror_dropped, server.stats().mirror_errors), (0, 0));
+}
+
+#[test]
+fn a_sink_gone_leaves_the_echo_alone() {
+    let sink = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut server = mirrored_server(&sink);
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"before");
+    assert_eq!(receive(&mut server, &mut client, 6), b"before");
+    let mirror = accept_mirror(&mut server, &sink);
+
+    drop(mirror);
+    drop(sink);
+    // Until the server notices, from a failed write or a reset
+    poll_until(&mut server, |server| {
+        send(server, &mut client, b"after");
+        assert_eq!(receive(server, &mut client, 5), b"after");
+        Some(()).filter(|()| server.stats().mirror_errors == 1)
+    });
+    send(&mut server, &mut client, b"still");
+    assert_eq!(receive(&mut server, &mut client, 5), b"still");
+}
@@ -0,0 +1,92 @@
+//! `Config::allow_mode_negotiation`, the transform a client picks with a
+//! `MODE` first line.
+
+mod driver;
+
+use std::net::TcpStream;
+use std::time::Duration;
+
+use mio_echo_server::{Config, ManualClock, Server};
+
+use driver::{connect, poll_until, read_available, receive, send};
+
+fn negotiating_server(allow: bool) -> (Server, ManualClock) {
+    let config = Config {
+        allow_mode_negotiation: allow,
+        ..Config::new("127.0.0.1:0")
+    };
+    let clock = ManualClock::new();
+    let server = Server::builder(config).clock(clock.clone()).build().unwrap();
+    (server, clock)
+}
+
+// Sends `data` and waits for the server to have read it all
+fn send_read(server: &mut Server, client: &mut TcpStream, data: &[u8]) {
+    let read = server.stats().tcp.bytes_read;
+    send(server, client, data);
+    poll_until(server, |server| Some(()).filter(|()| server.stats().tcp.bytes_read == read + data.len() as u64));
+}
+
+// Enough polls for any echo to have been sent
+fn settle(server: &mut Server) {
+    for _ in 0..10 {
+        server.poll_once(Some(Duration::from_millis(10))).unwrap();
+    }
+}
+
+#[test]
+fn a_command_split_across_reads_picks_the_transform() {
+    let (mut server, _clock) = negotiating_server(true);
+    let mut client = connect(&server);
+    for part in [&b"MO"[..], b"DE up", b"per"] {
+        send_read(&mut server, &mut client, part);
+    }
+    settle(&mut server);
+    assert_eq!(read_available(&mut client), (Vec::new(), false), "replied before the newline");
+    send(&mut server, &mut client, b"\nhello, World\n");
+    assert_eq!(receive(&mut server, &mut client, 16), b"OK\nHELLO, WORLD\n");
+
+    // Only the first line is a command
+    send(&mut server, &mut client, b"MODE echo\n");
+    assert_eq!(receive(&mut server, &mut client, 10), b"MODE ECHO\n");
+}
+
+#[test]
+fn an_unknown_mode_keeps_the_plain_echo() {
+    let (mut server, _clock) = negotiating_server(true);
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"MODE sideways\nhello\n");
+    assert_eq!(receive(&mut server, &mut client, 23), b"ERR unknown mode\nhello\n");
+
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"MODE delay 0\nhello\n");
+    assert_eq!(receive(&mut server, &mut client, 24), b"ERR invalid delay\nhello\n");
+}
+
+#[test]
+fn a_first_line_of_data_is_echoed() {
+    let (mut server, _clock) = negotiating_server(true);
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"MODEL upper\nmore\n");
+    assert_eq!(receive(&mut server, &mut client, 17), b"MODEL upper\nmore\n");
+
+    let (mut server, _clock) = negotiating_server(false);
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"MODE upper\nmore\n");
+    assert_eq!(receive(&mut server, &mut client, 16), b"MODE upper\nmore\n");
+}
+
+#[test]
+fn a_delay_holds_the_echo() {
+    let (mut server, clock) = negotiating_server(true);
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"MODE delay 200\n");
+    assert_eq!(receive(&mut server, &mut client, 3), b"OK\n");
+
+    send_read(&mut server, &mut client, b"later");
+    clock.advance(Duration::from_millis(199));
+    settle(&mut server);
+    assert_eq!(read_available(&mut client), (Vec::new(), false), "echoed early");
+    clock.advance(Duration::from_millis(1));
+    assert_eq!(receive(&mut server, &mut client, 5), b"later");
+}
@@ -0,0 +1,49 @@
+//! `Config::pipe_name`, the echo over `\\.\pipe\NAME`.
+
+#![cfg(windows)]
+
+mod driver;
+
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+use std::process;
+use std::thread;
+
+use mio_echo_server::{Config, Server};
+
+use driver::poll_until;
+
+#[test]
+fn concurrent_clients_get_their_own_echo() {
+    let name = format!("mio-echo-server-test-{}", process::id());
+    let config = Config {
+        pipe_name: Some(name.clone()),
+        ..Config::new("127.0.0.1:0")
+    };
+    let mut server = Server::from_config(config).unwrap();
+
+    // Blocking clients, on threads of their own while the server is polled
+    let clients: Vec<_> = (0..2)
+        .map(|i| {
+            let path = format!(r"\\.\pipe\{}", name);
+            thread::spawn(move || {
+                let mut pipe = OpenOptions::new().read(true).write(true).open(path).unwrap();
+                let mut echoed = Vec::new();
+                for round in 0..3 {
+                    let message = format!("client {} round {}", i, round);
+                    pipe.write_all(message.as_bytes()).unwrap();
+                    let mut reply = vec![0; message.len()];
+                    pipe.read_exact(&mut reply).unwrap();
+                    echoed.push(String::from_utf8(reply).unwrap());
+                }
+                echoed
+            })
+        })
+        .collect();
+    poll_until(&mut server, |_| Some(()).filter(|()| clients.iter().all(|client| client.is_finished())));
+
+    for (i, client) in clients.into_iter().enumerate() {
+        let expected: Vec<_> = (0..3).map(|round| format!("client {} round {}", i, round)).collect();
+        assert_eq!(client.join().unwrap(), expected);
+    }
+}
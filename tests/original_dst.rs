@@ -0,0 +1,98 @@
+//! `Config::log_original_dst`, the address a client dialed before an
+//! iptables REDIRECT, as the access log shows it.
+
+#![cfg(target_os = "linux")]
+
+mod driver;
+
+use std::fs;
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use mio_echo_server::{AccessLogFormat, Config, Server};
+
+use driver::{connect, receive, send};
+
+fn log_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("mio-echo-server-{}-{}.log", name, std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+fn logging_server(path: &Path, format: AccessLogFormat) -> Server {
+    let config = Config {
+        access_log: Some(path.to_path_buf()),
+        access_log_format: format,
+        log_original_dst: true,
+        ..Config::new("127.0.0.1:0")
+    };
+    Server::from_config(config).unwrap()
+}
+
+// Echoes once on `client` and closes it, returns the log once the
+// server is closed
+fn echo_and_close(mut server: Server, mut client: TcpStream, path: &Path) -> String {
+    send(&mut server, &mut client, b"ping");
+    assert_eq!(receive(&mut server, &mut client, 4), b"ping");
+    drop(client);
+    for _ in 0..10 {
+        server.poll_once(Some(Duration::from_millis(10))).unwrap();
+    }
+    // Joins the writer thread, which flushes
+    server.close();
+    let log = fs::read_to_string(path).unwrap();
+    fs::remove_file(path).unwrap();
+    log
+}
+
+#[test]
+fn connections_not_redirected_have_none() {
+    let path = log_path("original-dst-text");
+    let server = logging_server(&path, AccessLogFormat::Text);
+    let client = connect(&server);
+    let log = echo_and_close(server, client, &path);
+    let line = log.lines().next().expect("no line");
+    assert!(line.ends_with(" -"), "{}", line);
+
+    let path = log_path("original-dst-csv");
+    let server = logging_server(&path, AccessLogFormat::Csv);
+    let client = connect(&server);
+    let log = echo_and_close(server, client, &path);
+    let mut lines = log.lines();
+    assert!(lines.next().unwrap().ends_with(",id,original_dst"));
+    let line = lines.next().expect("no line");
+    assert!(line.ends_with(','), "{}", line);
+}
+
+fn iptables(args: &str) -> bool {
+    Command::new("iptables").args(args.split(' ')).status().is_ok_and(|status| status.success())
+}
+
+// In a network namespace of its own, so the rule only redirects the
+// connections of this test
+#[test]
+fn redirected_connections_log_the_dialed_address() {
+    if unsafe { libc::geteuid() } != 0 || Command::new("iptables").arg("--version").output().is_err() {
+        println!("skipped: needs root and iptables");
+        return;
+    }
+    // The thread of the test, and the processes and threads it starts
+    assert_eq!(unsafe { libc::unshare(libc::CLONE_NEWNET) }, 0, "unshare failed");
+    assert!(Command::new("ip").args(["link", "set", "lo", "up"]).status().unwrap().success());
+
+    let path = log_path("original-dst-redirect");
+    let server = logging_server(&path, AccessLogFormat::Text);
+    let port = server.local_addr().unwrap().port();
+    let dialed = SocketAddr::from((Ipv4Addr::LOCALHOST, 9));
+    assert!(iptables(&format!(
+        "-t nat -A OUTPUT -p tcp -d 127.0.0.1 --dport 9 -j REDIRECT --to-ports {}",
+        port
+    )));
+    let client = TcpStream::connect(dialed).unwrap();
+    client.set_nonblocking(true).unwrap();
+    let log = echo_and_close(server, client, &path);
+    let line = log.lines().next().expect("no line");
+    assert!(line.ends_with(&format!(" {}", dialed)), "{}", line);
+}
@@ -0,0 +1,134 @@
+//! `Config::overflow`, each policy against a client that doesn't read.
+
+mod driver;
+
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use mio_echo_server::{Config, Mode, Overflow, Server};
+
+use driver::{connect, poll_until, read_available, receive, receive_to_close};
+
+const LINES: usize = 50_000;
+const MAX_QUEUED: usize = 16 << 10;
+
+fn line(i: usize) -> String {
+    format!("{:08} {}\n", i, "x".repeat(90))
+}
+
+fn overflow_server(overflow: Overflow) -> Server {
+    let config = Config {
+        mode: Mode::Line,
+        max_queued: Some(MAX_QUEUED),
+        overflow,
+        // Most of the echo waits in the queue rather than in the socket
+        so_sndbuf: Some(4096),
+        ..Config::new("127.0.0.1:0")
+    };
+    Server::from_config(config).unwrap()
+}
+
+// Writes `LINES` lines without reading, until the server stops taking
+// them. Returns how many bytes went out.
+fn flood(server: &mut Server, client: &mut TcpStream) -> usize {
+    let data: String = (0..LINES).map(line).collect();
+    let mut written = 0;
+    let mut stalls = 0;
+    while written < data.len() && stalls < 20 {
+        match client.write(&data.as_bytes()[written..]) {
+            Ok(n) => {
+                written += n;
+                stalls = 0;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                server.poll_once(Some(Duration::from_millis(10))).unwrap();
+                stalls += 1;
+            }
+            // Closed by `Overflow::Disconnect`
+            Err(ref e) if e.kind() == io::ErrorKind::ConnectionReset => break,
+            Err(e) => panic!("write failed: {}", e),
+        }
+    }
+    written
+}
+
+// Reads what comes back until the server has nothing left to write
+fn drain(server: &mut Server, client: &mut TcpStream) -> Vec<String> {
+    let mut received = Vec::new();
+    let mut quiet = 0;
+    while quiet < 20 {
+        server.poll_once(Some(Duration::from_millis(10))).unwrap();
+        let (data, closed) = read_available(client);
+        assert!(!closed, "closed");
+        quiet = if data.is_empty() { quiet + 1 } else { 0 };
+        received.extend_from_slice(&data);
+    }
+    let text = String::from_utf8(received).unwrap();
+    assert!(text.is_empty() || text.ends_with('\n'), "a line cut short");
+    text.lines().map(|line| format!("{}\n", line)).collect()
+}
+
+// Whether every line is whole and in order
+fn in_order(lines: &[String]) -> bool {
+    lines.iter().all(|l| l.len() == line(0).len())
+        && lines.windows(2).all(|pair| pair[0][..8].parse::<usize>().unwrap() < pair[1][..8].parse().unwrap())
+}
+
+#[test]
+fn backpressure_stalls_the_sender() {
+    let mut server = overflow_server(Overflow::Backpressure);
+    let mut client = connect(&server);
+    let written = flood(&mut server, &mut client);
+    assert!(written < LINES * line(0).len(), "the sender never stalled");
+    assert!(server.stats().overflow.pauses > 0);
+
+    // Nothing dropped, every whole line sent comes back once the client
+    // reads
+    let whole = written / line(0).len() * line(0).len();
+    let sent: String = (0..LINES).map(line).collect();
+    assert_eq!(receive(&mut server, &mut client, whole), sent.as_bytes()[..whole]);
+    let overflow = server.stats().overflow;
+    assert_eq!((overflow.dropped_newest, overflow.dropped_oldest, overflow.disconnects), (0, 0, 0));
+}
+
+#[test]
+fn drop_newest_keeps_the_first_lines() {
+    let mut server = overflow_server(Overflow::DropNewest);
+    let mut client = connect(&server);
+    assert_eq!(flood(&mut server, &mut client), LINES * line(0).len());
+    let total = (LINES * line(0).len()) as u64;
+    poll_until(&mut server, |server| Some(()).filter(|()| server.stats().tcp.bytes_read == total));
+
+    let lines = drain(&mut server, &mut client);
+    assert_eq!(lines[0], line(0));
+    assert!(lines.len() < LINES && in_order(&lines));
+    let overflow = server.stats().overflow;
+    assert_eq!(overflow.dropped_newest as usize, LINES - lines.len());
+    assert_eq!(overflow.dropped_newest_bytes as usize, (LINES - lines.len()) * line(0).len());
+}
+
+#[test]
+fn drop_oldest_keeps_the_last_lines() {
+    let mut server = overflow_server(Overflow::DropOldest);
+    let mut client = connect(&server);
+    assert_eq!(flood(&mut server, &mut client), LINES * line(0).len());
+    let total = (LINES * line(0).len()) as u64;
+    poll_until(&mut server, |server| Some(()).filter(|()| server.stats().tcp.bytes_read == total));
+
+    let lines = drain(&mut server, &mut client);
+    assert_eq!(lines.last(), Some(&line(LINES - 1)));
+    assert!(lines.len() < LINES && in_order(&lines));
+    let overflow = server.stats().overflow;
+    assert_eq!(overflow.dropped_oldest as usize, LINES - lines.len());
+    assert_eq!(overflow.dropped_oldest_bytes as usize, (LINES - lines.len()) * line(0).len());
+}
+
+#[test]
+fn disconnect_closes_the_client() {
+    let mut server = overflow_server(Overflow::Disconnect);
+    let mut client = connect(&server);
+    flood(&mut server, &mut client);
+    receive_to_close(&mut server, &mut client);
+    assert_eq!(server.stats().overflow.disconnects, 1);
+}
@@ -0,0 +1,84 @@
+//! `Config::pending_queue`, connections parked at capacity until a slot
+//! frees, timed by a `ManualClock`.
+
+mod driver;
+
+use std::net::TcpStream;
+use std::time::Duration;
+
+use mio_echo_server::{Config, ManualClock, Server};
+
+use driver::{connect, poll_until, read_available, receive, receive_to_close, send};
+
+const PENDING_TIMEOUT: Duration = Duration::from_secs(2);
+
+// A server with a single slot, taken by the client returned
+fn full_server() -> (Server, ManualClock, TcpStream) {
+    let config = Config {
+        max_clients: 1,
+        pending_queue: Some(2),
+        pending_timeout: PENDING_TIMEOUT,
+        busy_message: Some(b"server busy\r\n".to_vec()),
+        ..Config::new("127.0.0.1:0")
+    };
+    let clock = ManualClock::new();
+    let mut server = Server::builder(config).clock(clock.clone()).build().unwrap();
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"first");
+    assert_eq!(receive(&mut server, &mut client, 5), b"first");
+    (server, clock, client)
+}
+
+fn deferred(server: &mut Server, message: &[u8]) -> TcpStream {
+    let deferred = server.stats().deferred;
+    let mut client = connect(server);
+    send(server, &mut client, message);
+    poll_until(server, |server| Some(()).filter(|()| server.stats().deferred == deferred + 1));
+    client
+}
+
+// Enough polls for a parked connection to be echoed, were it promoted
+fn settle(server: &mut Server) {
+    for _ in 0..10 {
+        server.poll_once(Some(Duration::from_millis(10))).unwrap();
+    }
+}
+
+#[test]
+fn parked_connections_are_promoted_oldest_first() {
+    let (mut server, _clock, first) = full_server();
+    let mut second = deferred(&mut server, b"second");
+    let mut third = deferred(&mut server, b"third");
+    // Beyond the queue
+    let mut refused = connect(&server);
+    assert_eq!(receive_to_close(&mut server, &mut refused), b"server busy\r\n");
+    settle(&mut server);
+    assert_eq!(read_available(&mut second), (Vec::new(), false));
+
+    drop(first);
+    assert_eq!(receive(&mut server, &mut second, 6), b"second");
+    settle(&mut server);
+    assert_eq!(read_available(&mut third), (Vec::new(), false), "promoted with no slot free");
+
+    drop(second);
+    assert_eq!(receive(&mut server, &mut third, 5), b"third");
+    let stats = server.stats();
+    assert_eq!((stats.deferred, stats.deferred_expired, stats.tcp.connections), (2, 0, 3));
+}
+
+#[test]
+fn parked_connections_are_refused_after_the_timeout() {
+    let (mut server, clock, mut first) = full_server();
+    let mut parked = deferred(&mut server, b"parked");
+
+    clock.advance(PENDING_TIMEOUT - Duration::from_millis(1));
+    settle(&mut server);
+    assert_eq!(read_available(&mut parked), (Vec::new(), false));
+    clock.advance(Duration::from_millis(1));
+    assert_eq!(receive_to_close(&mut server, &mut parked), b"server busy\r\n");
+    assert_eq!(server.stats().deferred_expired, 1);
+
+    // The slot holder never noticed
+    send(&mut server, &mut first, b"still");
+    assert_eq!(receive(&mut server, &mut first, 5), b"still");
+}
@@ -0,0 +1,46 @@
+//! `Stats::queue_latency`, how long echoed data waited in the server,
+//! timed by a `ManualClock`.
+
+mod driver;
+
+use std::time::Duration;
+
+use mio_echo_server::{Config, ManualClock, Server};
+
+use driver::{connect, poll_until, receive, send};
+
+const STALL: Duration = Duration::from_millis(500);
+const BURST: usize = 1 << 20;
+const QUICK: usize = 10;
+
+#[test]
+fn a_write_stall_shows_in_the_high_percentiles() {
+    let config = Config {
+        // Most of the burst waits in the queue rather than in the socket
+        so_sndbuf: Some(4096),
+        ..Config::new("127.0.0.1:0")
+    };
+    let clock = ManualClock::new();
+    let mut server = Server::builder(config).clock(clock.clone()).build().unwrap();
+    let mut client = connect(&server);
+    // Written back at once
+    for _ in 0..QUICK {
+        send(&mut server, &mut client, b"ping");
+        assert_eq!(receive(&mut server, &mut client, 4), b"ping");
+    }
+    assert!(server.stats().queue_latency_max < Duration::from_millis(1));
+
+    // Queued while the client doesn't read, and the clock moves on
+    send(&mut server, &mut client, &vec![b'x'; BURST]);
+    poll_until(&mut server, |server| {
+        Some(()).filter(|()| server.stats().tcp.bytes_read == (4 * QUICK + BURST) as u64)
+    });
+    clock.advance(STALL);
+    assert_eq!(receive(&mut server, &mut client, BURST).len(), BURST);
+
+    let stats = server.stats();
+    let summary = stats.queue_latency_summary().unwrap();
+    assert!(stats.queue_latency_percentile(50.0).unwrap() < Duration::from_millis(1), "{}", summary);
+    assert!(stats.queue_latency_percentile(99.0).unwrap() >= STALL, "{}", summary);
+    assert!(stats.queue_latency_max >= STALL, "{}", summary);
+}
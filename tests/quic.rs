@@ -0,0 +1,129 @@
+//! QUIC connections of the UDP socket, with a quinn client and a throwaway
+//! CA.
+
+#![cfg(feature = "quic")]
+
+mod pki;
+#[path = "../benches/support/mod.rs"]
+mod support;
+
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use quinn::crypto::rustls::QuicClientConfig;
+use quinn::{ClientConfig, Connection, Endpoint};
+use rustls::RootCertStore;
+use tokio::time::timeout;
+
+use mio_echo_server::{Config, Stats};
+use pki::{Ca, Dir};
+use support::TestServer;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A server presenting a certificate of `ca` for localhost on its UDP
+/// socket.
+struct Setup {
+    ca: Ca,
+    _dir: Dir,
+    server: TestServer,
+}
+
+impl Setup {
+    fn new(datagrams: bool) -> Setup {
+        let ca = Ca::new("Test CA");
+        let dir = Dir::new();
+        let server_cert = ca.issue("server", "localhost");
+        let mut config = Config::new("127.0.0.1:0");
+        config.udp = Some("127.0.0.1:0".into());
+        config.tls_cert = Some(dir.write("server.pem", &server_cert.cert));
+        config.tls_key = Some(dir.write("server.key", &server_cert.key));
+        config.quic = true;
+        config.quic_datagrams = datagrams;
+        let server = TestServer::with_config(config);
+        Setup { ca, _dir: dir, server }
+    }
+
+    /// A client trusting `ca`, connected to the server as localhost.
+    async fn connect(&self) -> (Endpoint, Connection) {
+        let mut roots = RootCertStore::empty();
+        roots.add(self.ca.cert.der().clone()).unwrap();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let crypto = rustls::ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let mut endpoint = Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        endpoint.set_default_client_config(ClientConfig::new(Arc::new(QuicClientConfig::try_from(crypto).unwrap())));
+        let connecting = endpoint.connect(self.server.udp_addr.expect("no UDP socket"), "localhost").unwrap();
+        let conn = timeout(TIMEOUT, connecting).await.expect("handshake timed out").unwrap();
+        (endpoint, conn)
+    }
+
+    fn stop(self) -> Stats {
+        self.server.stop()
+    }
+}
+
+// Sends `message` on a stream of its own, finishes it, and reads the echo
+// to the end
+async fn echo(conn: Connection, message: Vec<u8>) -> Vec<u8> {
+    let (mut send, mut recv) = conn.open_bi().await.unwrap();
+    send.write_all(&message).await.unwrap();
+    send.finish().unwrap();
+    timeout(TIMEOUT, recv.read_to_end(message.len() + 1)).await.expect("echo timed out").unwrap()
+}
+
+#[tokio::test]
+async fn concurrent_streams_are_echoed_then_closed() {
+    let setup = Setup::new(false);
+    let (endpoint, conn) = setup.connect().await;
+    // Larger than the flow control windows of the streams, so every echo
+    // waits on its reads being taken
+    let messages: Vec<Vec<u8>> = (0..8u8).map(|i| vec![b'a' + i; 100_000 + usize::from(i)]).collect();
+    let tasks: Vec<_> = messages.iter().map(|message| tokio::spawn(echo(conn.clone(), message.clone()))).collect();
+    for (task, message) in tasks.into_iter().zip(&messages) {
+        assert!(task.await.unwrap() == *message, "echo of {:?}... differs", &message[..1]);
+    }
+
+    conn.close(0u32.into(), b"done");
+    endpoint.wait_idle().await;
+
+    let stats = setup.stop();
+    assert_eq!(stats.quic.connections, 1);
+    assert_eq!(stats.quic.retries, 1);
+    assert_eq!(stats.quic.streams, 8);
+    assert_eq!(stats.quic.bytes_read, stats.quic.bytes_written);
+    assert_eq!(stats.quic.closed, 1);
+    assert_eq!(stats.quic.lost, 0);
+    assert_eq!(stats.quic.active, 0);
+}
+
+#[tokio::test]
+async fn datagrams_are_echoed_when_enabled() {
+    let setup = Setup::new(true);
+    let (endpoint, conn) = setup.connect().await;
+    assert!(conn.max_datagram_size().is_some());
+    conn.send_datagram(Bytes::from_static(b"hello")).unwrap();
+    let echo = timeout(TIMEOUT, conn.read_datagram()).await.expect("echo timed out").unwrap();
+    assert_eq!(&echo[..], b"hello");
+    conn.close(0u32.into(), b"done");
+    endpoint.wait_idle().await;
+
+    let stats = setup.stop();
+    assert_eq!(stats.quic.datagrams, 1);
+    assert_eq!(stats.quic.closed, 1);
+}
+
+#[tokio::test]
+async fn datagrams_are_refused_by_default() {
+    let setup = Setup::new(false);
+    let (endpoint, conn) = setup.connect().await;
+    assert_eq!(conn.max_datagram_size(), None);
+    conn.close(0u32.into(), b"done");
+    endpoint.wait_idle().await;
+    setup.stop();
+}
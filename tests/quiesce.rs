@@ -0,0 +1,66 @@
+//! `Config::quiesce`, the echo held until the client pauses, timed by a
+//! `ManualClock`.
+
+mod driver;
+
+use std::net::TcpStream;
+use std::time::Duration;
+
+use mio_echo_server::{Config, ManualClock, Server};
+
+use driver::{connect, poll_until, read_available, receive, send};
+
+const QUIESCE: Duration = Duration::from_millis(20);
+
+fn quiesce_server(max: usize) -> (Server, ManualClock) {
+    let config = Config {
+        quiesce: Some(QUIESCE),
+        quiesce_max: max,
+        ..Config::new("127.0.0.1:0")
+    };
+    let clock = ManualClock::new();
+    let server = Server::builder(config).clock(clock.clone()).build().unwrap();
+    (server, clock)
+}
+
+// Sends `data` and waits for the server to have read all of it
+fn send_read(server: &mut Server, client: &mut TcpStream, data: &[u8]) {
+    let total = server.stats().tcp.bytes_read + data.len() as u64;
+    send(server, client, data);
+    poll_until(server, |server| Some(()).filter(|()| server.stats().tcp.bytes_read == total));
+}
+
+#[test]
+fn fragments_are_echoed_together_after_a_pause() {
+    let (mut server, clock) = quiesce_server(64 << 10);
+    let mut client = connect(&server);
+    let mut sent = Vec::new();
+    for i in 0..10 {
+        let fragment = format!("fragment {};", i);
+        send_read(&mut server, &mut client, fragment.as_bytes());
+        sent.extend_from_slice(fragment.as_bytes());
+        // Each read pushes the deadline back
+        clock.advance(QUIESCE / 2);
+        server.poll_once(Some(Duration::ZERO)).unwrap();
+        assert_eq!(read_available(&mut client), (Vec::new(), false), "echoed before the pause");
+    }
+
+    clock.advance(QUIESCE / 2);
+    assert_eq!(receive(&mut server, &mut client, sent.len()), sent);
+    assert_eq!(server.stats().tcp.bytes_written, sent.len() as u64);
+}
+
+#[test]
+fn a_burst_past_the_cap_is_echoed_at_once() {
+    let (mut server, _clock) = quiesce_server(1024);
+    let mut client = connect(&server);
+    send_read(&mut server, &mut client, b"small");
+    assert_eq!(read_available(&mut client), (Vec::new(), false), "echoed before the pause");
+
+    // No time passes, the cap alone flushes
+    let burst = vec![b'x'; 4096];
+    send(&mut server, &mut client, &burst);
+    let mut expected = b"small".to_vec();
+    expected.extend_from_slice(&burst);
+    assert_eq!(receive(&mut server, &mut client, expected.len()), expected);
+}
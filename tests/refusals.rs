@@ -0,0 +1,111 @@
+//! The audit of refused connections, each reason counted in
+//! `Stats::refusals` and logged with its code.
+
+mod driver;
+
+use std::net::{IpAddr, TcpStream};
+use std::sync::Mutex;
+use std::time::Duration;
+use std::{env, fs, process};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use mio_echo_server::{Config, ManualClock, Refusal, Server};
+
+use driver::{connect, poll_until, receive, receive_to_close, send};
+
+// The warnings of every test of the file, told apart by peer address
+static LINES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct Capture;
+
+impl Log for Capture {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Warn
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            LINES.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn capture() {
+    let _ = log::set_logger(&Capture);
+    log::set_max_level(LevelFilter::Warn);
+}
+
+// Connects a client the server refuses, returns the audit line of it
+fn refused(server: &mut Server) -> String {
+    let mut client = connect(server);
+    let peer = format!("connection refused : {} ", client.local_addr().unwrap());
+    receive_to_close(server, &mut client);
+    let line = LINES.lock().unwrap().iter().find(|line| line.starts_with(&peer)).cloned();
+    line.unwrap_or_else(|| panic!("no audit line for {}", peer))
+}
+
+fn assert_reason(line: &str, reason: Refusal) {
+    assert!(line.contains(&format!(" reason={} ", reason.code())), "{}", line);
+}
+
+// A server whose only slot is taken by the client returned
+fn full_server(clock: &ManualClock) -> (Server, TcpStream) {
+    let config = Config { max_clients: 1, ..Config::new("127.0.0.1:0") };
+    let mut server = Server::builder(config).clock(clock.clone()).build().unwrap();
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"first");
+    assert_eq!(receive(&mut server, &mut client, 5), b"first");
+    (server, client)
+}
+
+#[test]
+fn capacity_refusals_are_audited() {
+    capture();
+    let (mut server, _client) = full_server(&ManualClock::new());
+    let line = refused(&mut server);
+    assert_reason(&line, Refusal::Capacity);
+    assert!(line.contains(" clients=1/1 pending=0 refused=1"), "{}", line);
+    let refusals = server.stats().refusals;
+    assert_eq!((refusals.capacity, refusals.total()), (1, 1));
+}
+
+#[test]
+fn banned_refusals_are_audited() {
+    capture();
+    let config = Config {
+        bans: vec![("127.0.0.1".parse::<IpAddr>().unwrap(), Duration::from_secs(60))],
+        ..Config::new("127.0.0.1:0")
+    };
+    let mut server = Server::builder(config).clock(ManualClock::new()).build().unwrap();
+    assert_reason(&refused(&mut server), Refusal::Banned);
+    assert_reason(&refused(&mut server), Refusal::Banned);
+    let stats = server.stats();
+    assert_eq!((stats.refusals.banned, stats.refusals.total(), stats.banned), (2, 2, 2));
+}
+
+#[test]
+fn denied_refusals_are_audited() {
+    capture();
+    let path = env::temp_dir().join(format!("mio-echo-server-refusals-{}", process::id()));
+    fs::write(&path, "# loopback\n127.0.0.0/8\n").unwrap();
+    let config = Config { deny_file: Some(path.clone()), ..Config::new("127.0.0.1:0") };
+    let mut server = Server::builder(config).clock(ManualClock::new()).build().unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_reason(&refused(&mut server), Refusal::Denied);
+    let refusals = server.stats().refusals;
+    assert_eq!((refusals.denied, refusals.total()), (1, 1));
+}
+
+#[test]
+fn a_flood_of_refusals_is_counted_but_not_all_logged() {
+    capture();
+    // The clock stands still, all in one second of the rate limit
+    let (mut server, _client) = full_server(&ManualClock::new());
+    let clients: Vec<TcpStream> = (0..25).map(|_| connect(&server)).collect();
+    poll_until(&mut server, |server| Some(()).filter(|()| server.stats().refusals.capacity == 25));
+    let refusals = server.stats().refusals;
+    assert_eq!((refusals.unlogged, refusals.total()), (5, 25));
+    drop(clients);
+}
@@ -0,0 +1,77 @@
+//! Peers that reset their connection or stop reading, counted as routine
+//! disconnects rather than errors.
+
+mod driver;
+
+use std::io::ErrorKind;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use mio_echo_server::{Action, CloseReason, Config, Handler, HandlerContext, Server};
+use socket2::SockRef;
+
+use driver::{connect, poll_until, receive, send};
+
+/// Echoes, `BIG` getting more back than the sockets hold, and records why
+/// each connection closed.
+struct Recorder {
+    closed: Arc<Mutex<Vec<CloseReason>>>,
+}
+
+impl Handler for Recorder {
+    fn on_data(&mut self, _ctx: &mut HandlerContext, data: &[u8]) -> Action {
+        match data {
+            b"BIG" => Action::Reply(vec![b'x'; 8 << 20]),
+            _ => Action::Reply(data.to_vec()),
+        }
+    }
+
+    fn on_disconnect(&mut self, _ctx: &mut HandlerContext, reason: CloseReason) {
+        self.closed.lock().unwrap().push(reason);
+    }
+}
+
+fn recording_server() -> (Server, Arc<Mutex<Vec<CloseReason>>>) {
+    let closed = Arc::new(Mutex::new(Vec::new()));
+    let handler = Recorder { closed: Arc::clone(&closed) };
+    let server = Server::builder(Config::new("127.0.0.1:0")).handler(handler).build().unwrap();
+    (server, closed)
+}
+
+fn closed_reason(server: &mut Server, closed: &Mutex<Vec<CloseReason>>) -> CloseReason {
+    poll_until(server, |_| closed.lock().unwrap().first().copied())
+}
+
+#[test]
+fn a_reset_is_counted_apart_from_errors() {
+    let (mut server, closed) = recording_server();
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"hello");
+    assert_eq!(receive(&mut server, &mut client, 5), b"hello");
+    // Closing with SO_LINGER 0 sends a RST rather than a FIN
+    SockRef::from(&client).set_linger(Some(Duration::from_secs(0))).unwrap();
+    drop(client);
+
+    assert_eq!(closed_reason(&mut server, &closed), CloseReason::Reset(ErrorKind::ConnectionReset));
+    let stats = server.stats();
+    assert_eq!((stats.tcp.resets, stats.tcp.errors), (1, 0));
+}
+
+#[test]
+fn writing_to_a_peer_gone_is_a_reset_too() {
+    let (mut server, closed) = recording_server();
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"BIG");
+    // Until the sockets are full and the rest waits in the queue
+    for _ in 0..10 {
+        server.poll_once(Some(Duration::from_millis(10))).unwrap();
+    }
+    drop(client);
+
+    match closed_reason(&mut server, &closed) {
+        CloseReason::Reset(ErrorKind::BrokenPipe) | CloseReason::Reset(ErrorKind::ConnectionReset) => {}
+        reason => panic!("closed on {}", reason),
+    }
+    let stats = server.stats();
+    assert_eq!((stats.tcp.resets, stats.tcp.errors), (1, 0));
+}
@@ -0,0 +1,168 @@
+//! `Config::resolve_peers` with a stub `Resolver`: the names in the log
+//! lines, their cache and its TTL, timed by a `ManualClock`, and an event
+//! loop that never waits on a lookup.
+
+#![cfg(target_os = "linux")]
+
+mod driver;
+
+use std::net::{IpAddr, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use mio_echo_server::{Config, ManualClock, Resolver, Server};
+
+use driver::{connect, poll_until, receive, send};
+
+const TTL: Duration = Duration::from_secs(60);
+const NAME: &str = "stub.test";
+
+// The info lines of every test of the file, told apart by peer address
+static LINES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct Capture;
+
+impl Log for Capture {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Info
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            LINES.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Answers `name` once let through by `gate`, recording every lookup.
+struct Stub {
+    name: Option<&'static str>,
+    lookups: Arc<Mutex<Vec<IpAddr>>>,
+    gate: Option<Mutex<Receiver<()>>>,
+}
+
+impl Resolver for Stub {
+    fn lookup(&self, ip: IpAddr) -> Option<String> {
+        self.lookups.lock().unwrap().push(ip);
+        if let Some(ref gate) = self.gate {
+            let _ = gate.lock().unwrap().recv();
+        }
+        self.name.map(str::to_string)
+    }
+}
+
+struct Resolving {
+    server: Server,
+    clock: ManualClock,
+    lookups: Arc<Mutex<Vec<IpAddr>>>,
+}
+
+impl Resolving {
+    fn new(name: Option<&'static str>, gate: Option<Receiver<()>>) -> Resolving {
+        let _ = log::set_logger(&Capture);
+        log::set_max_level(LevelFilter::Info);
+        let config = Config {
+            resolve_peers: true,
+            resolve_ttl: TTL,
+            ..Config::new("127.0.0.1:0")
+        };
+        let lookups = Arc::new(Mutex::new(Vec::new()));
+        let stub = Stub {
+            name,
+            lookups: lookups.clone(),
+            gate: gate.map(Mutex::new),
+        };
+        let clock = ManualClock::new();
+        let server = Server::builder(config).clock(clock.clone()).resolver(stub).build().unwrap();
+        Resolving { server, clock, lookups }
+    }
+
+    fn lookups(&self) -> usize {
+        self.lookups.lock().unwrap().len()
+    }
+
+    fn echoed_client(&mut self) -> TcpStream {
+        let mut client = connect(&self.server);
+        send(&mut self.server, &mut client, b"hello");
+        assert_eq!(receive(&mut self.server, &mut client, 5), b"hello");
+        client
+    }
+}
+
+// The captured line of `client` starting with `start`
+fn line(client: &TcpStream, start: &str) -> Option<String> {
+    let port = format!(":{})", client.local_addr().unwrap().port());
+    let bare = format!(":{} ", client.local_addr().unwrap().port());
+    let lines = LINES.lock().unwrap();
+    lines.iter().find(|line| line.starts_with(start) && (line.contains(&port) || line.contains(&bare))).cloned()
+}
+
+fn established(client: &TcpStream) -> String {
+    line(client, "connection established : ").expect("no accept line")
+}
+
+fn wait_resolved(resolving: &mut Resolving, client: &TcpStream) -> String {
+    poll_until(&mut resolving.server, |_| line(client, "peer resolved : "))
+}
+
+#[test]
+fn the_loop_serves_while_a_lookup_is_pending() {
+    let (release, gate): (Sender<()>, _) = mpsc::channel();
+    let mut resolving = Resolving::new(Some(NAME), Some(gate));
+    let client = resolving.echoed_client();
+    // Logged at once, bare
+    assert!(established(&client).starts_with("connection established : 127.0.0.1:"), "{}", established(&client));
+    // Blocked in the stub from here
+    let lookups = resolving.lookups.clone();
+    poll_until(&mut resolving.server, |_| Some(()).filter(|()| lookups.lock().unwrap().len() == 1));
+    let mut other = resolving.echoed_client();
+    send(&mut resolving.server, &mut other, b"still served");
+    assert_eq!(receive(&mut resolving.server, &mut other, 12), b"still served");
+    assert_eq!(line(&client, "peer resolved : "), None);
+    assert_eq!(resolving.lookups(), 1, "looked up twice while pending");
+
+    release.send(()).unwrap();
+    let resolved = wait_resolved(&mut resolving, &client);
+    let peer = client.local_addr().unwrap();
+    assert!(resolved.starts_with(&format!("peer resolved : {} ({})", NAME, peer)), "{}", resolved);
+}
+
+#[test]
+fn names_are_cached_until_the_ttl() {
+    let mut resolving = Resolving::new(Some(NAME), None);
+    let first = resolving.echoed_client();
+    wait_resolved(&mut resolving, &first);
+
+    let second = resolving.echoed_client();
+    let peer = second.local_addr().unwrap();
+    assert!(established(&second).starts_with(&format!("connection established : {} ({})", NAME, peer)));
+    assert_eq!(resolving.lookups(), 1);
+
+    resolving.clock.advance(TTL + Duration::from_secs(1));
+    let third = resolving.echoed_client();
+    assert!(established(&third).starts_with("connection established : 127.0.0.1:"), "{}", established(&third));
+    wait_resolved(&mut resolving, &third);
+    assert_eq!(resolving.lookups(), 2);
+}
+
+#[test]
+fn an_address_without_a_name_stays_bare() {
+    let mut resolving = Resolving::new(None, None);
+    let first = resolving.echoed_client();
+    let lookups = resolving.lookups.clone();
+    poll_until(&mut resolving.server, |_| Some(()).filter(|()| lookups.lock().unwrap().len() == 1));
+    // Enough polls for the answer to reach the loop
+    for _ in 0..10 {
+        resolving.server.poll_once(Some(Duration::from_millis(10))).unwrap();
+    }
+    assert_eq!(line(&first, "peer resolved : "), None);
+
+    // Cached as nameless
+    let second = resolving.echoed_client();
+    assert!(established(&second).starts_with("connection established : 127.0.0.1:"), "{}", established(&second));
+    assert_eq!(resolving.lookups(), 1);
+}
@@ -0,0 +1,133 @@
+//! Limits changed through a `ServerHandle` while the server runs, idle
+//! timers driven by a `ManualClock`.
+
+mod driver;
+
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use mio_echo_server::{Config, ManualClock, Server, ServerHandle};
+
+use driver::{connect, poll_until, receive, receive_to_close, send};
+
+const MAX_CLIENTS: usize = 8;
+
+fn limited_server() -> (Server, ManualClock) {
+    let config = Config {
+        max_clients: MAX_CLIENTS,
+        idle_timeout: Some(Duration::from_secs(600)),
+        ..Config::new("127.0.0.1:0")
+    };
+    let clock = ManualClock::new();
+    let server = Server::builder(config).clock(clock.clone()).build().unwrap();
+    (server, clock)
+}
+
+// Runs `request` on a thread of its own, the handle waiting for the loop
+// polled here to answer
+fn call<T, F>(server: &mut Server, request: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce(ServerHandle) -> T + Send + 'static,
+{
+    let handle = server.handle().unwrap();
+    let thread = thread::spawn(move || request(handle));
+    poll_until(server, |_| Some(()).filter(|()| thread.is_finished()));
+    thread.join().unwrap()
+}
+
+fn echo(server: &mut Server, client: &mut TcpStream, data: &[u8]) {
+    send(server, client, data);
+    assert_eq!(receive(server, client, data.len()), data);
+}
+
+fn echoed_client(server: &mut Server) -> TcpStream {
+    let mut client = connect(server);
+    echo(server, &mut client, b"hello");
+    client
+}
+
+fn refused(server: &mut Server) {
+    let rejected = server.stats().tcp.rejected;
+    let mut client = connect(server);
+    assert_eq!(receive_to_close(server, &mut client), b"");
+    assert_eq!(server.stats().tcp.rejected, rejected + 1);
+}
+
+#[test]
+fn a_lowered_idle_timeout_reaps_clients_already_idle() {
+    let (mut server, clock) = limited_server();
+    let mut idle = echoed_client(&mut server);
+    clock.advance(Duration::from_secs(10));
+    server.poll_once(Some(Duration::ZERO)).unwrap();
+    let mut active = echoed_client(&mut server);
+
+    call(&mut server, |handle| handle.set_idle_timeout(Some(Duration::from_secs(5)))).unwrap();
+    assert_eq!(receive_to_close(&mut server, &mut idle), b"");
+    let stats = server.stats();
+    assert_eq!((stats.idle_closes, stats.idle_timeout), (1, Some(Duration::from_secs(5))));
+    echo(&mut server, &mut active, b"not idle yet");
+
+    call(&mut server, |handle| handle.set_idle_timeout(None)).unwrap();
+    clock.advance(Duration::from_secs(3600));
+    server.poll_once(Some(Duration::ZERO)).unwrap();
+    echo(&mut server, &mut active, b"never idle");
+    assert_eq!(server.stats().idle_closes, 1);
+}
+
+#[test]
+fn a_lowered_limit_refuses_until_clients_leave() {
+    let (mut server, _clock) = limited_server();
+    let first = echoed_client(&mut server);
+    let mut second = echoed_client(&mut server);
+
+    call(&mut server, |handle| handle.set_max_clients(1, false)).unwrap();
+    assert_eq!(server.stats().max_clients, 1);
+    echo(&mut server, &mut second, b"kept");
+    refused(&mut server);
+
+    drop(first);
+    refused(&mut server);
+    drop(second);
+    // Enough polls for the server to see the close
+    for _ in 0..10 {
+        server.poll_once(Some(Duration::from_millis(10))).unwrap();
+    }
+    echoed_client(&mut server);
+}
+
+#[test]
+fn a_lowered_limit_can_evict_the_newest_clients() {
+    let (mut server, _clock) = limited_server();
+    let mut oldest = echoed_client(&mut server);
+    let mut newer: Vec<TcpStream> = (0..2).map(|_| echoed_client(&mut server)).collect();
+
+    call(&mut server, |handle| handle.set_max_clients(1, true)).unwrap();
+    for client in &mut newer {
+        assert_eq!(receive_to_close(&mut server, client), b"");
+    }
+    assert_eq!(server.stats().evictions, 2);
+    echo(&mut server, &mut oldest, b"oldest");
+    refused(&mut server);
+}
+
+#[test]
+fn the_limit_cannot_exceed_the_configured_one() {
+    let (mut server, _clock) = limited_server();
+    assert!(call(&mut server, |handle| handle.set_max_clients(MAX_CLIENTS + 1, false)).is_err());
+    call(&mut server, |handle| handle.set_max_clients(1, false)).unwrap();
+    call(&mut server, |handle| handle.set_max_clients(MAX_CLIENTS, false)).unwrap();
+    assert_eq!(server.stats().max_clients, MAX_CLIENTS);
+}
+
+#[test]
+fn the_rate_limit_shows_in_the_stats() {
+    let (mut server, _clock) = limited_server();
+    call(&mut server, |handle| handle.set_rate_limit(Some(1 << 20))).unwrap();
+    assert_eq!(server.stats().global_rate, Some(1 << 20));
+    let mut client = echoed_client(&mut server);
+    call(&mut server, |handle| handle.set_rate_limit(None)).unwrap();
+    assert_eq!(server.stats().global_rate, None);
+    echo(&mut server, &mut client, b"uncapped");
+}
@@ -0,0 +1,80 @@
+//! Listeners added and removed through a `ServerHandle` while the server
+//! runs.
+
+mod driver;
+
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+
+use mio_echo_server::{Config, Server, ServerHandle};
+
+use driver::{poll_until, receive, receive_to_close, send};
+
+// Runs `request` on a thread of its own, the handle waiting for the loop
+// polled here to answer
+fn call<T, F>(server: &mut Server, request: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce(ServerHandle) -> T + Send + 'static,
+{
+    let handle = server.handle().unwrap();
+    let thread = thread::spawn(move || request(handle));
+    poll_until(server, |_| Some(()).filter(|()| thread.is_finished()));
+    thread.join().unwrap()
+}
+
+fn connect_to(addr: SocketAddr) -> TcpStream {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream.set_nonblocking(true).unwrap();
+    stream
+}
+
+fn echo(server: &mut Server, client: &mut TcpStream, data: &[u8]) {
+    send(server, client, data);
+    assert_eq!(receive(server, client, data.len()), data);
+}
+
+#[test]
+fn an_added_listener_serves_until_removed() {
+    let mut server = Server::from_config(Config::new("127.0.0.1:0")).unwrap();
+    let id = call(&mut server, |handle| handle.add_listener("127.0.0.1:0")).unwrap();
+    let addr = id.local_addr();
+    assert_ne!(Some(addr), server.local_addr());
+    let mut client = connect_to(addr);
+    echo(&mut server, &mut client, b"added");
+
+    call(&mut server, move |handle| handle.remove_listener(id, false)).unwrap();
+    let refused = TcpStream::connect(addr).unwrap_err();
+    assert_eq!(refused.kind(), ErrorKind::ConnectionRefused);
+    // Its client stays, as do the other listeners
+    echo(&mut server, &mut client, b"still here");
+    let mut other = connect_to(server.local_addr().unwrap());
+    echo(&mut server, &mut other, b"startup listener");
+
+    // Already gone
+    assert!(call(&mut server, move |handle| handle.remove_listener(id, false)).is_err());
+}
+
+#[test]
+fn removing_a_listener_can_close_its_clients() {
+    let mut server = Server::from_config(Config::new("127.0.0.1:0")).unwrap();
+    let id = call(&mut server, |handle| handle.add_listener("127.0.0.1:0")).unwrap();
+    let mut added = connect_to(id.local_addr());
+    echo(&mut server, &mut added, b"added");
+    let mut kept = connect_to(server.local_addr().unwrap());
+    echo(&mut server, &mut kept, b"kept");
+
+    call(&mut server, move |handle| handle.remove_listener(id, true)).unwrap();
+    assert_eq!(receive_to_close(&mut server, &mut added), b"");
+    echo(&mut server, &mut kept, b"still kept");
+}
+
+#[test]
+fn a_listener_that_cannot_bind_is_an_error() {
+    let mut server = Server::from_config(Config::new("127.0.0.1:0")).unwrap();
+    let taken = server.local_addr().unwrap().to_string();
+    assert!(call(&mut server, move |handle| handle.add_listener(&taken)).is_err());
+    let mut client = connect_to(server.local_addr().unwrap());
+    echo(&mut server, &mut client, b"unharmed");
+}
@@ -0,0 +1,171 @@
+//! SCTP associations echoed message by message on their stream, when the
+//! kernel has SCTP.
+
+#![cfg(all(target_os = "linux", feature = "sctp"))]
+
+mod driver;
+
+use std::io;
+use std::mem;
+use std::net::SocketAddr;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::ptr;
+
+use mio_echo_server::{Config, Server};
+
+use driver::poll_until;
+
+// From linux/sctp.h, which libc doesn't cover
+const SOL_SCTP: libc::c_int = 132;
+const SCTP_RECVRCVINFO: libc::c_int = 32;
+const SCTP_SNDINFO: libc::c_int = 2;
+const SCTP_RCVINFO: libc::c_int = 3;
+
+#[repr(C)]
+#[derive(Default)]
+struct SndInfo {
+    sid: u16,
+    flags: u16,
+    ppid: u32,
+    context: u32,
+    assoc_id: i32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct RcvInfo {
+    sid: u16,
+    ssn: u16,
+    flags: u16,
+    ppid: u32,
+    tsn: u32,
+    cumtsn: u32,
+    context: u32,
+    assoc_id: i32,
+}
+
+// Room for one SCTP_SNDINFO or SCTP_RCVINFO header, aligned for cmsghdr
+type Control = [u64; 8];
+
+// A non-blocking one-to-one SCTP socket, `None` without kernel support
+fn socket() -> Option<OwnedFd> {
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, libc::IPPROTO_SCTP) };
+    if fd < 0 {
+        let e = io::Error::last_os_error();
+        assert_eq!(e.raw_os_error(), Some(libc::EPROTONOSUPPORT), "socket failed: {}", e);
+        return None;
+    }
+    Some(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn connect(sock: &OwnedFd, addr: SocketAddr) {
+    let fd = sock.as_raw_fd();
+    let addr = match addr {
+        SocketAddr::V4(addr) => libc::sockaddr_in {
+            sin_family: libc::AF_INET as libc::sa_family_t,
+            sin_port: addr.port().to_be(),
+            sin_addr: libc::in_addr {
+                s_addr: u32::from(*addr.ip()).to_be(),
+            },
+            sin_zero: [0; 8],
+        },
+        SocketAddr::V6(_) => unreachable!(),
+    };
+    let len = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+    unsafe {
+        let on: libc::c_int = 1;
+        let on_len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        assert_eq!(libc::setsockopt(fd, SOL_SCTP, SCTP_RECVRCVINFO, &on as *const _ as *const libc::c_void, on_len), 0);
+        let connected = libc::connect(fd, &addr as *const _ as *const libc::sockaddr, len);
+        assert_eq!(connected, 0, "connect failed: {}", io::Error::last_os_error());
+        assert_eq!(libc::fcntl(fd, libc::F_SETFL, libc::O_NONBLOCK), 0);
+    }
+}
+
+// Sends `data` as one message on stream `sid`
+fn send_on(sock: &OwnedFd, sid: u16, data: &[u8]) {
+    let info = SndInfo { sid, ..SndInfo::default() };
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    let mut control: Control = [0; 8];
+    unsafe {
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = libc::CMSG_SPACE(mem::size_of::<SndInfo>() as u32) as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = SOL_SCTP;
+        (*cmsg).cmsg_type = SCTP_SNDINFO;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<SndInfo>() as u32) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut SndInfo, info);
+        let sent = libc::sendmsg(sock.as_raw_fd(), &msg, 0);
+        assert_eq!(sent, data.len() as isize, "{}", io::Error::last_os_error());
+    }
+}
+
+// One message with its stream id, if there is one to read
+fn recv_on(sock: &OwnedFd) -> Option<(u16, Vec<u8>)> {
+    let mut buf = vec![0; 65536];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let mut control: Control = [0; 8];
+    unsafe {
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = mem::size_of::<Control>() as _;
+        let len = libc::recvmsg(sock.as_raw_fd(), &mut msg, 0);
+        if len < 0 {
+            let e = io::Error::last_os_error();
+            assert_eq!(e.kind(), io::ErrorKind::WouldBlock, "recvmsg failed: {}", e);
+            return None;
+        }
+        let mut sid = None;
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == SOL_SCTP && (*cmsg).cmsg_type == SCTP_RCVINFO {
+                sid = Some(ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const RcvInfo).sid);
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+        buf.truncate(len as usize);
+        Some((sid.expect("no SCTP_RCVINFO"), buf))
+    }
+}
+
+#[test]
+fn messages_come_back_whole_on_their_stream() {
+    let client = match socket() {
+        Some(sock) => sock,
+        None => {
+            eprintln!("skipped: no SCTP in this kernel");
+            return;
+        }
+    };
+    let addr: SocketAddr = format!("127.0.0.1:{}", 20_000 + std::process::id() % 40_000).parse().unwrap();
+    let config = Config {
+        listen: None,
+        sctp: Some(addr.to_string()),
+        ..Config::default()
+    };
+    let mut server = Server::from_config(config).unwrap();
+    // Completed by the kernel, accepted by the next poll
+    connect(&client, addr);
+
+    let messages: [(u16, &[u8]); 3] = [(0, b"first"), (1, b"second"), (0, b"third, after two")];
+    for &(sid, data) in &messages {
+        send_on(&client, sid, data);
+    }
+    for &(sid, data) in &messages {
+        // Never merged with the next one, unlike TCP
+        let (echoed_sid, echoed) = poll_until(&mut server, |_| recv_on(&client));
+        assert_eq!((echoed_sid, &echoed[..]), (sid, data));
+    }
+    assert_eq!(server.stats().sctp.connections, 1);
+}
@@ -0,0 +1,73 @@
+//! A server confined by `Config::seccomp`, each test in a process of its
+//! own since the filter can't be lifted.
+
+#![cfg(all(target_os = "linux", feature = "seccomp", any(target_arch = "x86_64", target_arch = "aarch64")))]
+
+mod driver;
+
+use std::env;
+use std::fs::File;
+use std::net::TcpStream;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command, ExitStatus};
+
+use mio_echo_server::{Config, Server};
+
+use driver::{connect, receive, send};
+
+const CHILD_VAR: &str = "MIO_ECHO_SERVER_SECCOMP_TEST";
+
+// Runs `test` in a process of its own and returns how it ended, `None`
+// when in it
+fn in_child(test: &str) -> Option<ExitStatus> {
+    if env::var_os(CHILD_VAR).is_some() {
+        return None;
+    }
+    let status = Command::new(env::current_exe().unwrap())
+        .args([test, "--exact", "--test-threads=1"])
+        .env(CHILD_VAR, "1")
+        .status()
+        .unwrap();
+    Some(status)
+}
+
+// A server confined after its client connected, which it couldn't once
+// confined
+fn confined() -> (Server, TcpStream) {
+    let config = Config {
+        seccomp: true,
+        ..Config::new("127.0.0.1:0")
+    };
+    let server = Server::from_config(config).unwrap();
+    let client = connect(&server);
+    server.confine().unwrap();
+    (server, client)
+}
+
+#[test]
+fn a_confined_server_echoes() {
+    if let Some(status) = in_child("a_confined_server_echoes") {
+        assert!(status.success(), "ended with {}", status);
+        return;
+    }
+    let (mut server, mut client) = confined();
+    for message in [&b"first"[..], b"second"] {
+        send(&mut server, &mut client, message);
+        assert_eq!(receive(&mut server, &mut client, message.len()), message);
+    }
+    drop(client);
+    server.close();
+}
+
+#[test]
+fn opening_a_file_kills_the_confined_process() {
+    if let Some(status) = in_child("opening_a_file_kills_the_confined_process") {
+        assert_eq!(status.signal(), Some(libc::SIGSYS), "ended with {}", status);
+        return;
+    }
+    let (mut server, mut client) = confined();
+    send(&mut server, &mut client, b"ping");
+    assert_eq!(receive(&mut server, &mut client, 4), b"ping");
+    let _ = File::open("/etc/hostname");
+    unreachable!("opened a file under seccomp");
+}
@@ -0,0 +1,71 @@
+//! SOCK_SEQPACKET clients, whose messages keep their boundaries.
+
+#![cfg(target_os = "linux")]
+
+mod driver;
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
+
+use mio_echo_server::{Config, Server};
+
+use driver::{poll_until, send};
+
+fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("mio-echo-server-{}-{}.sock", name, std::process::id()))
+}
+
+// A non-blocking client of the socket at `path`
+fn connect(path: &Path) -> File {
+    unsafe {
+        let fd = libc::socket(libc::AF_UNIX, libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC, 0);
+        assert!(fd >= 0, "{}", io::Error::last_os_error());
+        let file = File::from_raw_fd(fd);
+        let mut addr: libc::sockaddr_un = mem::zeroed();
+        addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+        for (dst, &src) in addr.sun_path.iter_mut().zip(path.as_os_str().as_bytes()) {
+            *dst = src as libc::c_char;
+        }
+        let len = mem::size_of::<libc::sockaddr_un>() as libc::socklen_t;
+        let connected = libc::connect(fd, &addr as *const _ as *const libc::sockaddr, len);
+        assert_eq!(connected, 0, "{}", io::Error::last_os_error());
+        assert_eq!(libc::fcntl(fd, libc::F_SETFL, libc::O_NONBLOCK), 0);
+        file
+    }
+}
+
+// Polls `server` until a packet came back on `client`
+fn receive_packet(server: &mut Server, client: &mut File) -> Vec<u8> {
+    let mut buf = [0; 4096];
+    poll_until(server, |_| match client.read(&mut buf) {
+        Ok(len) => Some(buf[..len].to_vec()),
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => None,
+        Err(e) => panic!("read failed: {}", e),
+    })
+}
+
+#[test]
+fn packets_come_back_one_by_one() {
+    let path = socket_path("seqpacket");
+    let config = Config {
+        listen: None,
+        unix_seqpacket: Some(path.to_str().unwrap().to_string()),
+        ..Config::default()
+    };
+    let mut server = Server::from_config(config).unwrap();
+    let mut client = connect(&path);
+    // Sent back to back, read apart
+    for packet in [&b"one"[..], b"two", b"three"] {
+        send(&mut server, &mut client, packet);
+    }
+    for packet in [&b"one"[..], b"two", b"three"] {
+        assert_eq!(receive_packet(&mut server, &mut client), packet);
+    }
+    let stats = server.close();
+    assert_eq!((stats.seqpacket.connections, stats.seqpacket.bytes_read), (1, 11));
+    assert!(!path.exists(), "the socket outlived the server");
+}
@@ -0,0 +1,53 @@
+//! `Config::shrink_after`, the buffers a burst grew given back once it
+//! drained, timed by a `ManualClock`.
+
+mod driver;
+
+use std::time::Duration;
+
+use mio_echo_server::{Config, ManualClock, Server};
+
+use driver::{connect, poll_until, receive, send};
+
+const GRACE: Duration = Duration::from_secs(2);
+const BURST: usize = 4 << 20;
+
+fn advance(server: &mut Server, clock: &ManualClock, duration: Duration) {
+    clock.advance(duration);
+    server.poll_once(Some(Duration::ZERO)).unwrap();
+}
+
+#[test]
+fn a_drained_queue_is_shrunk_after_the_grace_period() {
+    let config = Config {
+        shrink_after: GRACE,
+        // Most of the burst waits in the queue rather than in the socket
+        so_sndbuf: Some(4096),
+        ..Config::new("127.0.0.1:0")
+    };
+    let clock = ManualClock::new();
+    let mut server = Server::builder(config).clock(clock.clone()).build().unwrap();
+    let mut client = connect(&server);
+
+    // Queued while the client doesn't read
+    send(&mut server, &mut client, &vec![b'x'; BURST]);
+    poll_until(&mut server, |server| Some(()).filter(|()| server.stats().tcp.bytes_read == BURST as u64));
+    advance(&mut server, &clock, Duration::from_secs(1));
+    let inflated = server.stats().buffers;
+    assert!(inflated.used > (BURST / 2) as u64, "{:?}", inflated);
+
+    assert_eq!(receive(&mut server, &mut client, BURST).len(), BURST);
+    advance(&mut server, &clock, Duration::from_secs(1));
+    let drained = server.stats().buffers;
+    assert!(drained.used < drained.reserved / 16, "{:?}", drained);
+    assert_eq!(drained.shrinks, 0, "shrunk before the grace period");
+
+    advance(&mut server, &clock, GRACE);
+    let shrunk = server.stats().buffers;
+    assert!(shrunk.reserved < inflated.reserved / 16, "{:?} after {:?}", shrunk, inflated);
+    // The client's queue, and maybe the slots too
+    assert!(shrunk.shrinks >= 1);
+    // Still served
+    send(&mut server, &mut client, b"ping");
+    assert_eq!(receive(&mut server, &mut client, 4), b"ping");
+}
@@ -0,0 +1,141 @@
+//! What the binary does on each signal.
+
+#![cfg(unix)]
+
+use std::fs;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mio_echo_server::{Config, Server};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The binary on a free port, killed once dropped.
+struct Binary {
+    child: Child,
+    port: u16,
+}
+
+impl Binary {
+    fn spawn(args: &[&str]) -> Binary {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let child = Command::new(env!("CARGO_BIN_EXE_mio-echo-server"))
+            .arg(format!("127.0.0.1:{}", port))
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let binary = Binary { child, port };
+        wait_for("the server to start", || binary.connect().is_ok());
+        binary
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        TcpStream::connect(("127.0.0.1", self.port))
+    }
+
+    fn signal(&self, signum: libc::c_int) {
+        assert_eq!(unsafe { libc::kill(self.child.id() as libc::pid_t, signum) }, 0);
+    }
+
+    fn running(&mut self) -> bool {
+        self.child.try_wait().unwrap().is_none()
+    }
+}
+
+impl Drop for Binary {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn wait_for<F: FnMut() -> bool>(what: &str, mut done: F) {
+    let deadline = Instant::now() + TIMEOUT;
+    while !done() {
+        assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// A directory of its own for the files of a test
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mio-echo-server-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn contains(path: &Path, text: &str) -> bool {
+    fs::read_to_string(path).is_ok_and(|content| content.contains(text))
+}
+
+#[test]
+fn sighup_rotates_the_log_file() {
+    let dir = scratch_dir("log-file");
+    let log = dir.join("server.log");
+    let mut binary = Binary::spawn(&["--log-file", log.to_str().unwrap()]);
+    wait_for("the log", || contains(&log, "connection established"));
+
+    binary.signal(libc::SIGHUP);
+    // Rotated at the next line
+    let _client = binary.connect().unwrap();
+    let rotated = dir.join("server.log.1");
+    wait_for("the rotation", || contains(&rotated, "connection established") && contains(&log, "#2"));
+    let content = fs::read_to_string(&log).unwrap();
+    // Only what was logged after the signal
+    assert!(content.lines().filter(|line| line.contains("connection established")).all(|line| line.contains("#2")));
+    assert!(binary.running(), "the server exited on SIGHUP");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn sighup_reopens_the_access_log() {
+    let dir = scratch_dir("access-log");
+    let log = dir.join("access.log");
+    let mut binary = Binary::spawn(&["--access-log", log.to_str().unwrap()]);
+    wait_for("the access log", || contains(&log, "eof"));
+    let moved = dir.join("access.log.old");
+    fs::rename(&log, &moved).unwrap();
+
+    binary.signal(libc::SIGHUP);
+    // The writer reopens between two entries, those before go to the moved file
+    wait_for("the reopened access log", || {
+        drop(binary.connect().unwrap());
+        thread::sleep(Duration::from_millis(50));
+        contains(&log, "eof")
+    });
+    assert!(contains(&moved, "eof"));
+    assert!(binary.running(), "the server exited on SIGHUP");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_signal_wakes_the_poll() {
+    let mut server = Server::from_config(Config::new("127.0.0.1:0")).unwrap();
+    let signal = thread::spawn(|| {
+        thread::sleep(Duration::from_millis(100));
+        // Asking for a state dump, which changes nothing else
+        assert_eq!(unsafe { libc::kill(libc::getpid(), libc::SIGUSR1) }, 0);
+    });
+    let start = Instant::now();
+    server.poll_once(Some(TIMEOUT)).unwrap();
+    assert!(start.elapsed() < TIMEOUT / 2, "woken after {:?}", start.elapsed());
+    signal.join().unwrap();
+}
+
+#[test]
+fn an_idle_server_waits_for_its_timers() {
+    let (handle, thread) = Server::from_config(Config::new("127.0.0.1:0")).unwrap().start().unwrap();
+    thread::sleep(Duration::from_millis(1500));
+    handle.shutdown().unwrap();
+    let stats = thread.join().unwrap().unwrap();
+    // Once a second for the buffers, not every 100ms for the signals
+    assert!(stats.event_loop.polls <= 5, "polled {} times", stats.event_loop.polls);
+}
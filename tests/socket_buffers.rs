@@ -0,0 +1,61 @@
+//! `Config::so_rcvbuf` and `Config::so_sndbuf`, as set on the accepted
+//! socket.
+
+#![cfg(target_os = "linux")]
+
+mod driver;
+
+use std::fs;
+use std::mem::ManuallyDrop;
+use std::net::TcpStream;
+use std::os::unix::io::FromRawFd;
+
+use mio_echo_server::{Config, Server};
+use socket2::SockRef;
+
+use driver::{connect, receive, send};
+
+const RCVBUF: usize = 150_000;
+const SNDBUF: usize = 170_000;
+
+// What the kernel makes of a requested `size`: clamped to the `max` sysctl,
+// then doubled for its bookkeeping
+fn applied(size: usize, max: &str) -> usize {
+    let max: usize = fs::read_to_string(format!("/proc/sys/net/core/{}", max)).unwrap().trim().parse().unwrap();
+    size.min(max) * 2
+}
+
+// The (receive, send) buffer sizes of the server end of `client`, found
+// among the descriptors of this process
+fn accepted_buffers(client: &TcpStream) -> (usize, usize) {
+    let (local, peer) = (client.local_addr().unwrap(), client.peer_addr().unwrap());
+    for entry in fs::read_dir("/proc/self/fd").unwrap() {
+        let fd = match entry.unwrap().file_name().to_str().and_then(|name| name.parse().ok()) {
+            Some(fd) => fd,
+            None => continue,
+        };
+        // Borrowed, closed by the server
+        let sock = ManuallyDrop::new(unsafe { TcpStream::from_raw_fd(fd) });
+        if sock.local_addr().ok() == Some(peer) && sock.peer_addr().ok() == Some(local) {
+            let sock = SockRef::from(&*sock);
+            return (sock.recv_buffer_size().unwrap(), sock.send_buffer_size().unwrap());
+        }
+    }
+    panic!("no accepted socket for {}", local);
+}
+
+#[test]
+fn accepted_sockets_get_the_sizes() {
+    let config = Config {
+        so_rcvbuf: Some(RCVBUF),
+        so_sndbuf: Some(SNDBUF),
+        ..Config::new("127.0.0.1:0")
+    };
+    let mut server = Server::from_config(config).unwrap();
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"ping");
+    assert_eq!(receive(&mut server, &mut client, 4), b"ping");
+
+    // Not what autotuning would have come to
+    assert_eq!(accepted_buffers(&client), (applied(RCVBUF, "rmem_max"), applied(SNDBUF, "wmem_max")));
+}
@@ -0,0 +1,89 @@
+//! `Config::spin`, the event loop polling without blocking before it
+//! waits, and `Config::busy_poll`.
+
+mod driver;
+
+use std::time::{Duration, Instant};
+
+use mio_echo_server::{Config, Server};
+
+use driver::{connect, receive, send};
+
+fn spinning_server(spin: Option<Duration>) -> Server {
+    let config = Config { spin, ..Config::new("127.0.0.1:0") };
+    Server::from_config(config).unwrap()
+}
+
+#[test]
+fn events_are_harvested_by_the_spin() {
+    for spin in [None, Some(Duration::from_millis(50))] {
+        let mut server = spinning_server(spin);
+        let mut client = connect(&server);
+        for i in 0..10 {
+            let message = format!("message {}", i);
+            send(&mut server, &mut client, message.as_bytes());
+            assert_eq!(receive(&mut server, &mut client, message.len()), message.as_bytes());
+        }
+        let event_loop = &server.stats().event_loop;
+        if spin.is_some() {
+            assert!(event_loop.spun_events > 0, "nothing spun");
+            assert!(event_loop.spun_events <= event_loop.events);
+        } else {
+            assert_eq!(event_loop.spun_events, 0);
+        }
+    }
+}
+
+#[test]
+fn the_spin_ends_with_the_poll_timeout() {
+    let mut server = spinning_server(Some(Duration::from_secs(10)));
+    let start = Instant::now();
+    assert_eq!(server.poll_once(Some(Duration::from_millis(20))).unwrap(), 0);
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(20) && elapsed < Duration::from_secs(2), "{:?}", elapsed);
+    assert_eq!(server.stats().event_loop.spun_events, 0);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn accepted_sockets_get_so_busy_poll() {
+    use std::fs;
+    use std::mem::{self, ManuallyDrop};
+    use std::net::TcpStream;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    let config = Config {
+        busy_poll: Some(Duration::from_micros(50)),
+        ..Config::new("127.0.0.1:0")
+    };
+    let mut server = Server::from_config(config).unwrap();
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"ping");
+    assert_eq!(receive(&mut server, &mut client, 4), b"ping");
+
+    // The server end of `client`, among the descriptors of this process
+    let (local, peer) = (client.local_addr().unwrap(), client.peer_addr().unwrap());
+    let accepted = fs::read_dir("/proc/self/fd")
+        .unwrap()
+        .filter_map(|entry| entry.unwrap().file_name().to_str()?.parse().ok())
+        // Borrowed, closed by the server
+        .map(|fd| ManuallyDrop::new(unsafe { TcpStream::from_raw_fd(fd) }))
+        .find(|sock| sock.local_addr().ok() == Some(peer) && sock.peer_addr().ok() == Some(local))
+        .expect("no accepted socket");
+    let mut micros: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            accepted.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BUSY_POLL,
+            &mut micros as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    assert_eq!(ret, 0);
+    // Raising it takes CAP_NET_ADMIN, the server only warns without
+    if unsafe { libc::geteuid() } == 0 {
+        assert_eq!(micros, 50);
+    }
+}
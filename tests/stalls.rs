@@ -0,0 +1,78 @@
+//! `Config::stall_threshold`, the warning about an event loop iteration
+//! that took too long and the phase it blames.
+
+mod driver;
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use mio_echo_server::{Action, Config, HandlerContext, Server};
+
+use driver::{connect, receive, send};
+
+const THRESHOLD: Duration = Duration::from_millis(50);
+
+// The warnings of every test of the file
+static LINES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct Capture;
+
+impl Log for Capture {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Warn
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            LINES.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+// Echoes, taking twice the threshold over `slow`
+fn slow_server(stall_threshold: Duration) -> Server {
+    let _ = log::set_logger(&Capture);
+    log::set_max_level(LevelFilter::Warn);
+    let config = Config { stall_threshold, ..Config::new("127.0.0.1:0") };
+    let handler = |_: &mut HandlerContext, data: &[u8]| {
+        if data == b"slow" {
+            thread::sleep(THRESHOLD * 2);
+        }
+        Action::Reply(data.to_vec())
+    };
+    Server::builder(config).handler(handler).build().unwrap()
+}
+
+#[test]
+fn a_slow_handler_is_blamed_for_the_stall() {
+    let mut server = slow_server(THRESHOLD);
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"fast");
+    assert_eq!(receive(&mut server, &mut client, 4), b"fast");
+    let stalls = server.stats().event_loop.stalls;
+
+    send(&mut server, &mut client, b"slow");
+    assert_eq!(receive(&mut server, &mut client, 4), b"slow");
+    let event_loop = &server.stats().event_loop;
+    assert_eq!(event_loop.stalls, stalls + 1);
+    assert!(event_loop.max_iteration >= THRESHOLD * 2, "{:?}", event_loop.max_iteration);
+    let lines = LINES.lock().unwrap();
+    let blamed = |line: &&String| line.starts_with("event loop stalled") && line.contains(" mostly in handler (");
+    assert!(lines.iter().any(|line| blamed(&line)), "{:?}", lines);
+}
+
+#[test]
+fn a_zero_threshold_never_warns() {
+    let mut server = slow_server(Duration::ZERO);
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"slow");
+    assert_eq!(receive(&mut server, &mut client, 4), b"slow");
+    let event_loop = &server.stats().event_loop;
+    assert_eq!(event_loop.stalls, 0);
+    // Still measured
+    assert!(event_loop.max_iteration >= THRESHOLD * 2, "{:?}", event_loop.max_iteration);
+}
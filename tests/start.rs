@@ -0,0 +1,78 @@
+//! `Server::start`, a server on a thread of its own stopped through its
+//! `ServerHandle`.
+
+mod driver;
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mio_echo_server::{Config, Error, Server};
+
+use driver::TIMEOUT;
+
+fn blocking_client(addr: SocketAddr) -> TcpStream {
+    let client = TcpStream::connect(addr).unwrap();
+    client.set_read_timeout(Some(TIMEOUT)).unwrap();
+    client
+}
+
+// Everything `client` gets until the server closes it
+fn read_to_close(client: &mut TcpStream) -> Vec<u8> {
+    let mut data = Vec::new();
+    match client.read_to_end(&mut data) {
+        Ok(_) => {}
+        Err(ref e) if e.kind() == ErrorKind::ConnectionReset => {}
+        Err(e) => panic!("read failed: {}", e),
+    }
+    data
+}
+
+#[test]
+fn a_started_server_stops_on_shutdown() {
+    let server = Server::from_config(Config::new("127.0.0.1:0")).unwrap();
+    let addr = server.local_addr().unwrap();
+    let (handle, thread) = server.start().unwrap();
+    let mut client = blocking_client(addr);
+    client.write_all(b"hello").unwrap();
+    let mut echo = [0; 5];
+    client.read_exact(&mut echo).unwrap();
+    assert_eq!(&echo, b"hello");
+
+    // Queued before the shutdown, written before the close
+    let queued: Vec<u8> = (0..64 << 10).map(|i| i as u8).collect();
+    client.write_all(&queued).unwrap();
+    let mut first = [0; 1];
+    client.read_exact(&mut first).unwrap();
+    handle.shutdown().unwrap();
+    let stats = thread.join().unwrap().unwrap();
+    let mut rest = read_to_close(&mut client);
+    rest.insert(0, first[0]);
+    assert!(rest == queued, "{} of {} bytes", rest.len(), queued.len());
+    assert_eq!((stats.tcp.connections, stats.tcp.bytes_read), (1, 5 + queued.len() as u64));
+
+    assert_eq!(TcpStream::connect(addr).unwrap_err().kind(), ErrorKind::ConnectionRefused);
+    assert!(matches!(handle.shutdown(), Err(Error::ServerGone)));
+}
+
+#[test]
+fn a_second_shutdown_closes_the_clients_at_once() {
+    let server = Server::from_config(Config::new("127.0.0.1:0")).unwrap();
+    let addr = server.local_addr().unwrap();
+    let (handle, thread) = server.start().unwrap();
+    let mut client = blocking_client(addr);
+    // Never read, the echo can't drain
+    let mut writer = client.try_clone().unwrap();
+    let flood = thread::spawn(move || while writer.write_all(&[b'x'; 64 << 10]).is_ok() {});
+    // Long enough for the socket buffers to fill
+    thread::sleep(Duration::from_millis(300));
+
+    let start = Instant::now();
+    handle.shutdown().unwrap();
+    handle.shutdown().unwrap();
+    thread.join().unwrap().unwrap();
+    assert!(start.elapsed() < TIMEOUT, "waited for the drain");
+    read_to_close(&mut client);
+    flood.join().unwrap();
+}
@@ -0,0 +1,58 @@
+//! Stats pushed to a statsd agent, timed by a `ManualClock`.
+
+mod driver;
+
+use std::io::ErrorKind;
+use std::net::UdpSocket;
+use std::time::Duration;
+
+use mio_echo_server::{Config, ManualClock, Server};
+
+use driver::{connect, poll_until, receive, send};
+
+// Polls `server` until the agent got a push, its metrics one per line
+fn pushed(server: &mut Server, agent: &UdpSocket) -> Vec<String> {
+    let mut buf = [0; 4096];
+    let len = poll_until(server, |_| match agent.recv(&mut buf) {
+        Ok(len) => Some(len),
+        Err(ref e) if e.kind() == ErrorKind::WouldBlock => None,
+        Err(e) => panic!("recv failed: {}", e),
+    });
+    String::from_utf8_lossy(&buf[..len]).lines().map(String::from).collect()
+}
+
+#[test]
+fn counters_go_as_changes_and_gauges_as_values() {
+    let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+    agent.set_nonblocking(true).unwrap();
+    let config = Config {
+        statsd: Some(agent.local_addr().unwrap().to_string()),
+        statsd_prefix: "test".to_string(),
+        stats_interval: Duration::from_secs(10),
+        ..Config::new("127.0.0.1:0")
+    };
+    let clock = ManualClock::new();
+    let mut server = Server::builder(config).clock(clock.clone()).build().unwrap();
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"hello");
+    assert_eq!(receive(&mut server, &mut client, 5), b"hello");
+
+    clock.advance(Duration::from_secs(10));
+    let first = pushed(&mut server, &agent);
+    for metric in &[
+        "test.connections.active:1|g",
+        "test.connections.accepted:1|c",
+        "test.bytes.read:5|c",
+        "test.bytes.written:5|c",
+        "test.rejected:0|c",
+    ] {
+        assert!(first.iter().any(|line| line == metric), "no {} in {:?}", metric, first);
+    }
+
+    // Nothing new since
+    clock.advance(Duration::from_secs(10));
+    let second = pushed(&mut server, &agent);
+    assert!(second.contains(&"test.connections.active:1|g".to_string()), "{:?}", second);
+    assert!(second.contains(&"test.bytes.read:0|c".to_string()), "{:?}", second);
+    assert_eq!(server.stats().statsd_errors, 0);
+}
@@ -0,0 +1,131 @@
+//! `Config::log_syslog`, received by a fake daemon. The sink is set up by
+//! the binary, so these run it rather than a `Server`.
+
+#![cfg(unix)]
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::os::unix::net::UnixDatagram;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The binary on a free port, killed once dropped.
+struct Binary {
+    child: Child,
+    port: u16,
+}
+
+impl Binary {
+    fn spawn(args: &[&str]) -> Binary {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let child = Command::new(env!("CARGO_BIN_EXE_mio-echo-server"))
+            .arg(format!("127.0.0.1:{}", port))
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        Binary { child, port }
+    }
+
+    // A client echoed once then gone, returning its port
+    fn echo_once(&self) -> u16 {
+        let deadline = Instant::now() + TIMEOUT;
+        let mut client = loop {
+            match TcpStream::connect(("127.0.0.1", self.port)) {
+                Ok(client) => break client,
+                Err(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+                Err(e) => panic!("the server didn't start: {}", e),
+            }
+        };
+        client.set_read_timeout(Some(TIMEOUT)).unwrap();
+        client.write_all(b"ping").unwrap();
+        let mut reply = [0; 4];
+        client.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"ping");
+        client.local_addr().unwrap().port()
+    }
+}
+
+impl Drop for Binary {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Messages received until one contains `text`
+fn messages_until<F: FnMut(&mut [u8]) -> usize>(text: &str, mut recv: F) -> Vec<String> {
+    let mut messages = Vec::new();
+    let mut buf = [0; 2048];
+    while !messages.last().is_some_and(|message: &String| message.contains(text)) {
+        let len = recv(&mut buf);
+        messages.push(String::from_utf8(buf[..len].to_vec()).unwrap());
+    }
+    messages
+}
+
+// The message about `event` of the client on `port`
+fn find<'a>(messages: &'a [String], event: &str, port: u16) -> &'a str {
+    let about = format!("{} : 127.0.0.1:{} ", event, port);
+    messages
+        .iter()
+        .find(|message| message.contains(&about))
+        .unwrap_or_else(|| panic!("no {:?} in {:?}", about, messages))
+}
+
+#[test]
+fn rfc5424_over_udp() {
+    let daemon = UdpSocket::bind("127.0.0.1:0").unwrap();
+    daemon.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let target = format!("udp:{}", daemon.local_addr().unwrap());
+    let binary = Binary::spawn(&["--log-syslog", &target, "--syslog-facility", "local0"]);
+    let port = binary.echo_once();
+
+    let closed = format!("connection closed : 127.0.0.1:{} ", port);
+    let messages = messages_until(&closed, |buf| daemon.recv(buf).expect("no syslog message"));
+    // local0 is 16, info 6
+    let header_end = format!(" mio-echo-server {} - - ", binary.child.id());
+    for event in &["connection established", "connection closed"] {
+        let message = find(&messages, event, port);
+        let (header, text) = message.split_once(&header_end).unwrap_or_else(|| panic!("{}", message));
+        assert!(text.starts_with(event), "{}", message);
+        let fields: Vec<_> = header.split(' ').collect();
+        assert_eq!(fields.len(), 3, "{}", message);
+        assert_eq!(fields[0], "<134>1");
+        assert!(fields[1].len() == 24 && fields[1].ends_with('Z'), "timestamp {}", fields[1]);
+        assert!(!fields[2].is_empty(), "no hostname");
+    }
+}
+
+#[test]
+fn rfc3164_over_a_unix_socket() {
+    let path = std::env::temp_dir().join(format!("mio-echo-server-syslog-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let daemon = UnixDatagram::bind(&path).unwrap();
+    daemon.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let target = format!("unix:{}", path.display());
+    let binary = Binary::spawn(&["--log-syslog", &target, "--syslog-rfc3164"]);
+    let port = binary.echo_once();
+
+    let closed = format!("connection closed : 127.0.0.1:{} ", port);
+    let messages = messages_until(&closed, |buf| daemon.recv(buf).expect("no syslog message"));
+    // daemon is 3, info 6
+    let tag = format!(" mio-echo-server[{}]: ", binary.child.id());
+    for event in &["connection established", "connection closed"] {
+        let message = find(&messages, event, port);
+        let (header, text) = message.split_once(&tag).unwrap_or_else(|| panic!("{}", message));
+        assert!(text.starts_with(event), "{}", message);
+        // "<30>Jun  1 12:00:00 host"
+        let (pri, rest) = header.split_at(4);
+        assert_eq!(pri, "<30>");
+        let (timestamp, host) = rest.split_at(15);
+        assert_eq!((&timestamp[3..4], &timestamp[6..7], &timestamp[9..10]), (" ", " ", ":"), "{}", message);
+        assert!(host.len() > 1 && host.starts_with(' '), "no hostname: {}", message);
+    }
+    std::fs::remove_file(&path).unwrap();
+}
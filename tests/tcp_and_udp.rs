@@ -0,0 +1,50 @@
+//! TCP clients and UDP peers served side by side by one loop.
+
+mod driver;
+
+use std::net::TcpListener;
+
+use mio_echo_server::{Config, Server};
+
+use driver::{connect, receive, receive_datagram, send, udp_client};
+
+fn server(listen: &str, udp: &str) -> Server {
+    let config = Config {
+        udp: Some(udp.to_string()),
+        ..Config::new(listen)
+    };
+    Server::from_config(config).unwrap()
+}
+
+#[test]
+fn both_are_echoed_in_turn() {
+    let mut server = server("127.0.0.1:0", "127.0.0.1:0");
+    let mut client = connect(&server);
+    let peer = udp_client(&server);
+    for i in 0..5 {
+        let message = format!("message {}", i);
+        send(&mut server, &mut client, message.as_bytes());
+        peer.send(message.as_bytes()).unwrap();
+        assert_eq!(receive_datagram(&mut server, &peer), message.as_bytes());
+        assert_eq!(receive(&mut server, &mut client, message.len()), message.as_bytes());
+    }
+    let stats = server.stats();
+    assert_eq!((stats.tcp.connections, stats.tcp.bytes_read), (1, 45));
+    assert_eq!((stats.udp.datagrams, stats.udp.bytes_read), (5, 45));
+}
+
+#[test]
+fn both_on_one_port() {
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let addr = format!("127.0.0.1:{}", port);
+    let mut server = server(&addr, &addr);
+    assert_eq!(server.local_addr().unwrap().port(), port);
+    assert_eq!(server.udp_addr().unwrap().port(), port);
+
+    let peer = udp_client(&server);
+    peer.send(b"datagram").unwrap();
+    assert_eq!(receive_datagram(&mut server, &peer), b"datagram");
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"stream");
+    assert_eq!(receive(&mut server, &mut client, 6), b"stream");
+}
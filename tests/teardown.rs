@@ -0,0 +1,51 @@
+//! Servers give back every descriptor when closed or dropped.
+//!
+//! Alone in its binary, so that no other test opens descriptors meanwhile.
+
+#![cfg(target_os = "linux")]
+
+mod driver;
+
+use std::fs;
+use std::net::TcpStream;
+
+use mio_echo_server::{Config, Server};
+
+use driver::{connect, receive, send};
+
+fn open_fds() -> usize {
+    fs::read_dir("/proc/self/fd").unwrap().count()
+}
+
+// A server on `listen` that echoed to a few clients, those still connected
+fn served(listen: &str) -> (Server, Vec<TcpStream>) {
+    let mut server = Server::from_config(Config::new(listen)).unwrap();
+    let mut clients: Vec<_> = (0..3).map(|_| connect(&server)).collect();
+    for client in &mut clients {
+        send(&mut server, client, b"ping");
+        assert_eq!(receive(&mut server, client, 4), b"ping");
+    }
+    (server, clients)
+}
+
+#[test]
+fn servers_on_one_port_leak_no_descriptors() {
+    let (server, clients) = served("127.0.0.1:0");
+    let listen = server.local_addr().unwrap().to_string();
+    server.close();
+    drop(clients);
+    let before = open_fds();
+
+    for i in 0..50 {
+        // Rebinding right away, the closed ones out of the way
+        let (server, clients) = served(&listen);
+        if i % 2 == 0 {
+            let stats = server.close();
+            assert_eq!(stats.tcp.connections, 3);
+        } else {
+            drop(server);
+        }
+        drop(clients);
+    }
+    assert_eq!(open_fds(), before);
+}
@@ -0,0 +1,48 @@
+//! Telnet negotiation split across the reads of the server.
+
+mod driver;
+
+use std::time::Duration;
+
+use mio_echo_server::{Config, Server};
+
+use driver::{connect, receive, send};
+
+const SE: u8 = 240;
+const SB: u8 = 250;
+const WILL: u8 = 251;
+const WONT: u8 = 252;
+const DO: u8 = 253;
+const DONT: u8 = 254;
+const IAC: u8 = 255;
+
+fn telnet_server() -> Server {
+    let config = Config {
+        telnet: true,
+        ..Config::new("127.0.0.1:0")
+    };
+    Server::from_config(config).unwrap()
+}
+
+#[test]
+fn negotiations_split_at_every_point() {
+    let cases: [(&[u8], &[u8]); 4] = [
+        (&[b'a', IAC, WILL, 1, b'b'], &[b'a', IAC, DONT, 1, b'b']),
+        (&[b'a', IAC, DO, 3, b'b'], &[b'a', IAC, WONT, 3, b'b']),
+        (&[b'a', IAC, SB, 24, 1, IAC, SE, b'b'], b"ab"),
+        (b"a\r\nb", b"a\r\nb"),
+    ];
+    let mut server = telnet_server();
+    let mut client = connect(&server);
+    for &(input, echo) in &cases {
+        for split in 1..input.len() {
+            send(&mut server, &mut client, &input[..split]);
+            // Read by the server before the rest is sent
+            for _ in 0..5 {
+                server.poll_once(Some(Duration::from_millis(10))).unwrap();
+            }
+            send(&mut server, &mut client, &input[split..]);
+            assert_eq!(receive(&mut server, &mut client, echo.len()), echo, "{:?} split at {}", input, split);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! `Config::global_rate` measured from clients echoing as fast as they can.
+
+#[path = "../benches/support/mod.rs"]
+mod support;
+
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mio_echo_server::Config;
+use support::TestServer;
+
+/// How long the echo is measured, after the burst of the bucket.
+const MEASURED: Duration = Duration::from_secs(1);
+const WARMUP: Duration = Duration::from_millis(200);
+
+// Bytes echoed to a client writing as fast as it can over `MEASURED`
+fn echo_flood(addr: SocketAddr) -> usize {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    // Stopped by the shutdown once measured
+    let write = thread::spawn(move || while writer.write_all(&[b'x'; 64 << 10]).is_ok() {});
+
+    let mut buf = vec![0; 64 << 10];
+    let start = Instant::now();
+    let mut bytes = 0;
+    loop {
+        let len = stream.read(&mut buf).unwrap();
+        let elapsed = start.elapsed();
+        if elapsed >= WARMUP + MEASURED {
+            break;
+        }
+        if elapsed >= WARMUP {
+            bytes += len;
+        }
+    }
+    stream.shutdown(Shutdown::Both).unwrap();
+    write.join().unwrap();
+    bytes
+}
+
+// Echo rate `writers` clients get together under a cap of `rate`, with
+// `idle` other clients connected that send nothing
+fn measure(rate: u64, writers: usize, idle: usize) -> f64 {
+    let mut config = Config::new("127.0.0.1:0");
+    config.global_rate = Some(rate);
+    // Backpressure, the clients writing faster than the echo
+    config.max_queued = Some(256 << 10);
+    let server = TestServer::with_config(config);
+    let addr = server.addr;
+    let _idle: Vec<TcpStream> = (0..idle).map(|_| TcpStream::connect(addr).unwrap()).collect();
+    let writers: Vec<_> = (0..writers).map(|_| thread::spawn(move || echo_flood(addr))).collect();
+    let bytes: usize = writers.into_iter().map(|writer| writer.join().unwrap()).sum();
+    server.stop();
+    bytes as f64 / MEASURED.as_secs_f64()
+}
+
+// Within 5% of the cap
+fn assert_near(measured: f64, rate: u64) {
+    let ratio = measured / rate as f64;
+    assert!((0.95..=1.05).contains(&ratio), "{:.0} B/s under a cap of {} B/s", measured, rate);
+}
+
+#[test]
+fn one_megabyte_per_second_is_delivered() {
+    assert_near(measure(1 << 20, 1, 0), 1 << 20);
+}
+
+#[test]
+fn ten_megabytes_per_second_are_delivered() {
+    assert_near(measure(10 << 20, 1, 0), 10 << 20);
+}
+
+#[test]
+fn writers_share_the_cap_whatever_the_idle_clients() {
+    assert_near(measure(10 << 20, 4, 100), 10 << 20);
+}
@@ -0,0 +1,85 @@
+//! `ServerBuilder::tick` callbacks, called from the stepped loop.
+
+mod driver;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use mio_echo_server::{Config, Server};
+
+use driver::{connect, poll_until, receive, send, TIMEOUT};
+
+#[test]
+fn ticks_see_the_stats_of_the_echo() {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let mut server = Server::builder(Config::new("127.0.0.1:0"))
+        .tick(Duration::from_millis(20), {
+            let seen = Arc::clone(&seen);
+            move |ctx| seen.lock().unwrap().push(ctx.stats().tcp.bytes_read)
+        })
+        .build()
+        .unwrap();
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"tick");
+    assert_eq!(receive(&mut server, &mut client, 4), b"tick");
+    poll_until(&mut server, |_| seen.lock().unwrap().last().filter(|&&read| read == 4).map(|_| ()));
+}
+
+#[test]
+fn an_idle_poll_waits_for_the_next_tick_only() {
+    let ticks = Arc::new(AtomicUsize::new(0));
+    let mut server = Server::builder(Config::new("127.0.0.1:0"))
+        .tick(Duration::from_millis(50), {
+            let ticks = Arc::clone(&ticks);
+            move |_| {
+                ticks.fetch_add(1, Ordering::Relaxed);
+            }
+        })
+        .build()
+        .unwrap();
+    let start = Instant::now();
+    while ticks.load(Ordering::Relaxed) < 3 {
+        server.poll_once(None).unwrap();
+        assert!(start.elapsed() < TIMEOUT, "the ticks stopped");
+    }
+    assert!(start.elapsed() >= Duration::from_millis(150));
+}
+
+#[test]
+fn a_tick_stops_the_server() {
+    let mut server = Server::builder(Config::new("127.0.0.1:0"))
+        .tick(Duration::from_millis(10), |ctx| {
+            if ctx.stats().tcp.connections > 0 {
+                ctx.shutdown();
+            }
+        })
+        .build()
+        .unwrap();
+    for _ in 0..5 {
+        server.poll_once(Some(Duration::from_millis(10))).unwrap();
+    }
+    assert!(!server.shutdown_requested());
+    let _client = connect(&server);
+    poll_until(&mut server, |server| Some(()).filter(|()| server.shutdown_requested()));
+}
+
+#[test]
+fn a_panicking_tick_is_called_again() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let mut server = Server::builder(Config::new("127.0.0.1:0"))
+        .tick(Duration::from_millis(10), {
+            let calls = Arc::clone(&calls);
+            move |_| {
+                if calls.fetch_add(1, Ordering::Relaxed) == 0 {
+                    panic!("first tick");
+                }
+            }
+        })
+        .build()
+        .unwrap();
+    poll_until(&mut server, |_| Some(()).filter(|()| calls.load(Ordering::Relaxed) >= 3));
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"alive");
+    assert_eq!(receive(&mut server, &mut client, 5), b"alive");
+}
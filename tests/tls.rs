@@ -0,0 +1,167 @@
+//! TLS clients authenticated by certificate, against a throwaway CA.
+
+#![cfg(feature = "tls")]
+
+mod pki;
+#[path = "../benches/support/mod.rs"]
+mod support;
+
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use mio_echo_server::{ClientAuth, Config, ListenerConfig, Stats};
+use pki::{Ca, Dir, Issued};
+use support::TestServer;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A server presenting a certificate of `ca` for localhost, asking for
+/// client certificates issued by `ca` too.
+struct Setup {
+    ca: Ca,
+    dir: Dir,
+    server: TestServer,
+}
+
+impl Setup {
+    fn new(auth: ClientAuth) -> Setup {
+        let ca = Ca::new("Test CA");
+        let dir = Dir::new();
+        let server_cert = ca.issue("server", "localhost");
+        let mut config = Config::new("127.0.0.1:0");
+        config.tls_cert = Some(dir.write("server.pem", &server_cert.cert));
+        config.tls_key = Some(dir.write("server.key", &server_cert.key));
+        config.tls_client_ca = Some(dir.write("ca.pem", &ca.cert.pem()));
+        config.tls_client_auth = auth;
+        config.access_log = Some(dir.0.join("access.log"));
+        let server = TestServer::with_config(config);
+        Setup { ca, dir, server }
+    }
+
+    fn echo(&self, cert: Option<&Issued>) -> Option<Vec<u8>> {
+        echo(&self.ca, self.server.addr, cert)
+    }
+
+    /// Stops the server, returns its stats and access log.
+    fn stop(self) -> (Stats, String) {
+        let stats = self.server.stop();
+        let log = fs::read_to_string(self.dir.0.join("access.log")).unwrap();
+        (stats, log)
+    }
+}
+
+/// Sends `hello` to `addr` as a client trusting `ca` and presenting `cert`,
+/// returns the echo or `None` if the server broke off.
+fn echo(ca: &Ca, addr: SocketAddr, cert: Option<&Issued>) -> Option<Vec<u8>> {
+    let mut roots = RootCertStore::empty();
+    roots.add(ca.cert.der().clone()).unwrap();
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots);
+    let config = match cert {
+        Some(cert) => {
+            let chain = CertificateDer::pem_slice_iter(cert.cert.as_bytes()).collect::<Result<_, _>>().unwrap();
+            let key = PrivateKeyDer::from_pem_slice(cert.key.as_bytes()).unwrap();
+            builder.with_client_auth_cert(chain, key).unwrap()
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let name = ServerName::try_from("localhost").unwrap();
+    let conn = ClientConnection::new(Arc::new(config), name).unwrap();
+    let sock = TcpStream::connect(addr).unwrap();
+    sock.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let mut stream = StreamOwned::new(conn, sock);
+    // The client is done with its side of the handshake before the
+    // server checks its certificate
+    stream.write_all(b"hello").ok()?;
+    let mut echo = [0; 5];
+    stream.read_exact(&mut echo).ok()?;
+    Some(echo.to_vec())
+}
+
+#[test]
+fn valid_certificate_is_echoed_and_logged() {
+    let setup = Setup::new(ClientAuth::Required);
+    let alice = setup.ca.issue("alice", "alice.example");
+    assert_eq!(setup.echo(Some(&alice)).as_deref(), Some(&b"hello"[..]));
+    let (stats, log) = setup.stop();
+    assert_eq!(stats.tls_handshake_failures, 0);
+    assert!(log.contains("\"CN=alice, O=Lab, DNS:alice.example\""), "{}", log);
+}
+
+#[test]
+fn missing_certificate_fails_the_handshake() {
+    let setup = Setup::new(ClientAuth::Required);
+    assert_eq!(setup.echo(None), None);
+    let (stats, log) = setup.stop();
+    assert_eq!(stats.tls_handshake_failures, 1);
+    assert!(log.contains("\"tls handshake failed\" 1 -"), "{}", log);
+}
+
+#[test]
+fn certificate_of_another_ca_fails_the_handshake() {
+    let setup = Setup::new(ClientAuth::Required);
+    let mallory = Ca::new("Other CA").issue("mallory", "mallory.example");
+    assert_eq!(setup.echo(Some(&mallory)), None);
+    let (stats, _) = setup.stop();
+    assert_eq!(stats.tls_handshake_failures, 1);
+}
+
+#[test]
+fn optional_authentication_lets_everyone_through() {
+    let setup = Setup::new(ClientAuth::Optional);
+    let alice = setup.ca.issue("alice", "alice.example");
+    let mallory = Ca::new("Other CA").issue("mallory", "mallory.example");
+    assert_eq!(setup.echo(Some(&alice)).as_deref(), Some(&b"hello"[..]));
+    assert_eq!(setup.echo(None).as_deref(), Some(&b"hello"[..]));
+    assert_eq!(setup.echo(Some(&mallory)).as_deref(), Some(&b"hello"[..]));
+    let (stats, log) = setup.stop();
+    assert_eq!(stats.tls_handshake_failures, 0);
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines.len(), 3, "{}", log);
+    assert!(lines.iter().any(|line| line.ends_with("\"CN=alice, O=Lab, DNS:alice.example\"")), "{}", log);
+    assert!(lines.iter().any(|line| line.ends_with(" -")), "{}", log);
+    assert!(
+        lines.iter().any(|line| line.contains("\"CN=mallory, O=Lab, DNS:mallory.example (unverified: ")),
+        "{}",
+        log
+    );
+}
+
+#[test]
+fn listener_with_a_certificate_of_its_own() {
+    let ca = Ca::new("Test CA");
+    let dir = Dir::new();
+    let server_cert = ca.issue("server", "localhost");
+    let mut config = Config::new("127.0.0.1:0");
+    config.listeners.push(ListenerConfig {
+        tls_cert: Some(dir.write("server.pem", &server_cert.cert)),
+        tls_key: Some(dir.write("server.key", &server_cert.key)),
+        ..ListenerConfig::new("127.0.0.1:0")
+    });
+    let server = TestServer::with_config(config);
+
+    assert_eq!(echo(&ca, server.listener_addrs[0], None).as_deref(), Some(&b"hello"[..]));
+    // In clear on the top-level listener, which has no certificate
+    assert_eq!(echo(&ca, server.addr, None), None);
+    let mut plain = TcpStream::connect(server.addr).unwrap();
+    plain.set_read_timeout(Some(TIMEOUT)).unwrap();
+    plain.write_all(b"hello").unwrap();
+    let mut echo = [0; 5];
+    plain.read_exact(&mut echo).unwrap();
+    assert_eq!(&echo, b"hello");
+
+    let stats = server.stop();
+    assert_eq!(stats.listeners[0].connections, 1);
+    assert_eq!(stats.tcp.connections, 3);
+}
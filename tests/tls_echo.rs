@@ -0,0 +1,98 @@
+//! TLS terminated on the TCP listeners, clients driven by the test over
+//! non-blocking sockets.
+
+#![cfg(feature = "tls")]
+
+mod driver;
+mod pki;
+
+use std::convert::TryFrom;
+use std::net::TcpStream;
+use std::sync::Arc;
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned, SupportedProtocolVersion};
+
+use mio_echo_server::{Config, Server};
+use pki::{Ca, Dir};
+
+use driver::{connect, receive, receive_to_close, send};
+
+type TlsClient = StreamOwned<ClientConnection, TcpStream>;
+
+/// A server presenting a certificate of `ca` for localhost.
+struct Setup {
+    ca: Ca,
+    // Holds the certificate files
+    _dir: Dir,
+    server: Server,
+}
+
+impl Setup {
+    fn new(banner: Option<&[u8]>) -> Setup {
+        let ca = Ca::new("Test CA");
+        let dir = Dir::new();
+        let cert = ca.issue("server", "localhost");
+        let config = Config {
+            tls_cert: Some(dir.write("server.pem", &cert.cert)),
+            tls_key: Some(dir.write("server.key", &cert.key)),
+            banner: banner.map(<[u8]>::to_vec),
+            ..Config::new("127.0.0.1:0")
+        };
+        let server = Server::from_config(config).unwrap();
+        Setup { ca, _dir: dir, server }
+    }
+
+    // A client of the server offering `versions`, its handshake made as
+    // the driver polls
+    fn client(&self, versions: &[&'static SupportedProtocolVersion]) -> TlsClient {
+        let mut roots = RootCertStore::empty();
+        roots.add(self.ca.cert.der().clone()).unwrap();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(versions)
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let name = ServerName::try_from("localhost").unwrap();
+        let conn = ClientConnection::new(Arc::new(config), name).unwrap();
+        StreamOwned::new(conn, connect(&self.server))
+    }
+}
+
+#[test]
+fn data_is_echoed_over_tls() {
+    for versions in [&[&rustls::version::TLS13][..], &[&rustls::version::TLS12]] {
+        let mut setup = Setup::new(None);
+        let mut client = setup.client(versions);
+        send(&mut setup.server, &mut client, b"hello");
+        assert_eq!(receive(&mut setup.server, &mut client, 5), b"hello");
+        assert_eq!(client.conn.protocol_version(), Some(versions[0].version));
+
+        // Many records each way
+        let large: Vec<u8> = (0..1_000_000u32).map(|i| (i % 251) as u8).collect();
+        send(&mut setup.server, &mut client, &large);
+        assert!(receive(&mut setup.server, &mut client, large.len()) == large, "echo corrupted");
+        // Counted decrypted
+        assert_eq!(setup.server.stats().tcp.bytes_read, 5 + large.len() as u64);
+    }
+}
+
+#[test]
+fn a_banner_follows_the_handshake() {
+    let mut setup = Setup::new(Some(b"welcome\n"));
+    let mut client = setup.client(&[&rustls::version::TLS13]);
+    // Nothing goes out before the client speaks TLS
+    send(&mut setup.server, &mut client, b"ping");
+    assert_eq!(receive(&mut setup.server, &mut client, 12), b"welcome\nping");
+}
+
+#[test]
+fn a_plaintext_client_gets_an_alert() {
+    let mut setup = Setup::new(None);
+    let mut client = connect(&setup.server);
+    send(&mut setup.server, &mut client, b"GET / HTTP/1.0\r\n\r\n");
+    let reply = receive_to_close(&mut setup.server, &mut client);
+    // An alert record, not an echo
+    assert_eq!(reply.first(), Some(&0x15), "{:?}", reply);
+}
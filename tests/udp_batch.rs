@@ -0,0 +1,62 @@
+//! `Config::udp_batch`, datagrams received and echoed several to a
+//! syscall, each reply going back to its own source.
+
+mod driver;
+
+use std::net::UdpSocket;
+
+use mio_echo_server::{Config, Server};
+
+use driver::{poll_until, udp_client};
+
+const PEERS: usize = 50;
+const ROUNDS: usize = 20;
+const PER_ROUND: usize = 5;
+
+fn batch_server(udp_batch: usize) -> Server {
+    let config = Config {
+        udp: Some("127.0.0.1:0".to_string()),
+        udp_batch,
+        ..Config::new("127.0.0.1:0")
+    };
+    Server::from_config(config).unwrap()
+}
+
+// Every peer sends its tagged datagrams before the server polls, so that
+// they are read in batches mixing the sources
+fn check_pairing(udp_batch: usize) {
+    let mut server = batch_server(udp_batch);
+    let peers: Vec<UdpSocket> = (0..PEERS).map(|_| udp_client(&server)).collect();
+    let mut buf = [0; 64];
+    for round in 0..ROUNDS {
+        for (i, peer) in peers.iter().enumerate() {
+            for j in 0..PER_ROUND {
+                peer.send(format!("peer {} round {} datagram {}", i, round, j).as_bytes()).unwrap();
+            }
+        }
+        let mut replies = vec![Vec::new(); PEERS];
+        poll_until(&mut server, |_| {
+            for (peer, replies) in peers.iter().zip(&mut replies) {
+                while let Ok(len) = peer.recv(&mut buf) {
+                    replies.push(String::from_utf8(buf[..len].to_vec()).unwrap());
+                }
+            }
+            Some(()).filter(|()| replies.iter().all(|replies| replies.len() >= PER_ROUND))
+        });
+        for (i, replies) in replies.iter().enumerate() {
+            let expected: Vec<_> =
+                (0..PER_ROUND).map(|j| format!("peer {} round {} datagram {}", i, round, j)).collect();
+            assert_eq!(*replies, expected, "batch of {}", udp_batch);
+        }
+    }
+    let total = (PEERS * ROUNDS * PER_ROUND) as u64;
+    assert_eq!(server.stats().udp.datagrams, total);
+}
+
+#[test]
+fn replies_go_back_to_their_sources() {
+    // One datagram a syscall, batches splitting the rounds, the default
+    for udp_batch in [1, 7, 64] {
+        check_pairing(udp_batch);
+    }
+}
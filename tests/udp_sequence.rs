@@ -0,0 +1,153 @@
+//! `Config::udp_sequence`, the loss, duplicates and reorders of a source
+//! counted from the sequence numbers of its datagrams. A forwarder
+//! between the client and the server drops, repeats and reorders them.
+
+mod driver;
+
+use std::io::ErrorKind;
+use std::net::UdpSocket;
+use std::time::Duration;
+
+use mio_echo_server::{Config, ManualClock, Server};
+
+use driver::{poll_until, udp_client};
+
+const SOURCE_IDLE: Duration = Duration::from_secs(30);
+
+fn sequence_server() -> (Server, ManualClock) {
+    let config = Config {
+        udp: Some("127.0.0.1:0".to_string()),
+        udp_sequence: true,
+        udp_source_idle: SOURCE_IDLE,
+        ..Config::new("127.0.0.1:0")
+    };
+    let clock = ManualClock::new();
+    let server = Server::builder(config).clock(clock.clone()).build().unwrap();
+    (server, clock)
+}
+
+fn datagram(seq: u64) -> Vec<u8> {
+    let mut datagram = seq.to_be_bytes().to_vec();
+    datagram.extend_from_slice(format!("datagram {}", seq).as_bytes());
+    datagram
+}
+
+/// Relays what the client sent in the order of `pattern`, as indexes of
+/// the datagrams received, and the echoes back to the client.
+struct Forwarder {
+    // Connected to the server
+    upstream: UdpSocket,
+    // Bound for the client
+    downstream: UdpSocket,
+}
+
+impl Forwarder {
+    fn new(server: &Server) -> Forwarder {
+        let downstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        downstream.set_read_timeout(Some(driver::TIMEOUT)).unwrap();
+        Forwarder {
+            upstream: udp_client(server),
+            downstream,
+        }
+    }
+
+    fn client(&self) -> UdpSocket {
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(self.downstream.local_addr().unwrap()).unwrap();
+        client.set_read_timeout(Some(driver::TIMEOUT)).unwrap();
+        client
+    }
+
+    // Reads `count` datagrams of the client, sends them on per `pattern`
+    // and returns the echoes to the client
+    fn relay(&self, server: &mut Server, count: usize, pattern: &[usize]) {
+        let mut buf = [0; 1500];
+        let mut received = Vec::new();
+        let mut client = None;
+        for _ in 0..count {
+            let (len, from) = self.downstream.recv_from(&mut buf).unwrap();
+            received.push(buf[..len].to_vec());
+            client = Some(from);
+        }
+        for &i in pattern {
+            self.upstream.send(&received[i]).unwrap();
+        }
+        let mut echoes = 0;
+        poll_until(server, |_| {
+            loop {
+                match self.upstream.recv(&mut buf) {
+                    Ok(len) => {
+                        self.downstream.send_to(&buf[..len], client.unwrap()).unwrap();
+                        echoes += 1;
+                    }
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) => panic!("recv failed: {}", e),
+                }
+            }
+            Some(()).filter(|()| echoes == pattern.len())
+        });
+    }
+}
+
+#[test]
+fn loss_duplicates_and_reorders_are_counted() {
+    let (mut server, _clock) = sequence_server();
+    let forwarder = Forwarder::new(&server);
+    let client = forwarder.client();
+    let mut sent: Vec<Vec<u8>> = (0..10).map(datagram).collect();
+    // Too short for a sequence number
+    sent.push(b"hi".to_vec());
+    for datagram in &sent {
+        client.send(datagram).unwrap();
+    }
+    // 5 and 6 lost, 4 before 3, 3 twice
+    let pattern = [0, 1, 2, 4, 3, 3, 7, 8, 9, 10];
+    forwarder.relay(&mut server, sent.len(), &pattern);
+
+    // Echoed unchanged, in the order they reached the server
+    let mut buf = [0; 1500];
+    for &i in &pattern {
+        let len = client.recv(&mut buf).unwrap();
+        assert_eq!(buf[..len], sent[i][..]);
+    }
+    let sequence = server.stats().sequence;
+    assert_eq!(
+        (sequence.sources, sequence.lost, sequence.duplicates, sequence.reorders, sequence.malformed),
+        (1, 2, 1, 1, 1)
+    );
+    assert_eq!(server.stats().udp.datagrams, pattern.len() as u64);
+}
+
+#[test]
+fn a_late_datagram_is_no_longer_lost() {
+    let (mut server, _clock) = sequence_server();
+    let forwarder = Forwarder::new(&server);
+    let client = forwarder.client();
+    for seq in 0..4 {
+        client.send(&datagram(seq)).unwrap();
+    }
+    forwarder.relay(&mut server, 4, &[0, 2, 3]);
+    assert_eq!(server.stats().sequence.lost, 1);
+
+    client.send(&datagram(1)).unwrap();
+    forwarder.relay(&mut server, 1, &[0]);
+    let sequence = server.stats().sequence;
+    assert_eq!((sequence.lost, sequence.reorders, sequence.duplicates), (0, 1, 0));
+}
+
+#[test]
+fn a_quiet_source_is_forgotten() {
+    let (mut server, clock) = sequence_server();
+    let forwarder = Forwarder::new(&server);
+    let client = forwarder.client();
+    client.send(&datagram(7)).unwrap();
+    forwarder.relay(&mut server, 1, &[0]);
+
+    clock.advance(SOURCE_IDLE + Duration::from_secs(1));
+    server.poll_once(Some(Duration::ZERO)).unwrap();
+    // Starting over, not a reorder of the same source
+    client.send(&datagram(0)).unwrap();
+    forwarder.relay(&mut server, 1, &[0]);
+    let sequence = server.stats().sequence;
+    assert_eq!((sequence.sources, sequence.reorders), (2, 0));
+}
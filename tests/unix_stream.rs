@@ -0,0 +1,70 @@
+//! `Config::unix_stream`, clients of a Unix stream socket served by the
+//! same loop as the TCP ones.
+
+#![cfg(target_os = "linux")]
+
+mod driver;
+
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+use mio_echo_server::{Config, Server};
+
+use driver::{connect, read_available, receive, send};
+
+fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("mio-echo-server-{}-{}.sock", name, std::process::id()))
+}
+
+fn unix_server(path: &Path, listen: Option<String>) -> Server {
+    let config = Config {
+        listen,
+        unix_stream: Some(path.to_str().unwrap().to_string()),
+        ..Config::default()
+    };
+    Server::from_config(config).unwrap()
+}
+
+fn unix_connect(path: &Path) -> UnixStream {
+    let stream = UnixStream::connect(path).unwrap();
+    stream.set_nonblocking(true).unwrap();
+    stream
+}
+
+#[test]
+fn unix_clients_are_echoed_next_to_tcp_ones() {
+    let path = socket_path("unix-stream");
+    let mut server = unix_server(&path, Some("127.0.0.1:0".to_string()));
+    let mut unix = unix_connect(&path);
+    let mut tcp = connect(&server);
+    let data: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+    send(&mut server, &mut unix, &data);
+    send(&mut server, &mut tcp, b"tcp");
+    assert_eq!(receive(&mut server, &mut unix, 5000), data);
+    assert_eq!(receive(&mut server, &mut tcp, 3), b"tcp");
+
+    drop(unix);
+    let mut unix = unix_connect(&path);
+    send(&mut server, &mut unix, b"again");
+    assert_eq!(receive(&mut server, &mut unix, 5), b"again");
+    let stats = server.close();
+    assert_eq!((stats.unix.connections, stats.unix.bytes_read), (2, 5005));
+    assert_eq!(stats.tcp.connections, 1);
+    assert!(!path.exists(), "the socket outlived the server");
+    // Closed with the server
+    assert_eq!(read_available(&mut unix), (Vec::new(), true));
+}
+
+#[test]
+fn a_stale_socket_is_replaced() {
+    let path = socket_path("unix-stale");
+    // Left behind by a process that died, nobody listens on it
+    drop(UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+    let mut server = unix_server(&path, None);
+    let mut client = unix_connect(&path);
+    send(&mut server, &mut client, b"fresh");
+    assert_eq!(receive(&mut server, &mut client, 5), b"fresh");
+    drop(server);
+    assert!(!path.exists());
+}
@@ -0,0 +1,56 @@
+//! `Config::tcp_user_timeout`, a peer that stops acknowledging the echo
+//! dropped within the bound.
+
+#![cfg(target_os = "linux")]
+
+mod driver;
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use mio_echo_server::{Action, CloseReason, Config, Handler, HandlerContext, Server};
+
+use driver::{connect, poll_until, send};
+
+const USER_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Answers more than the sockets hold, and records why each connection
+/// closed.
+struct Recorder {
+    closed: Arc<Mutex<Vec<CloseReason>>>,
+}
+
+impl Handler for Recorder {
+    fn on_data(&mut self, _ctx: &mut HandlerContext, _data: &[u8]) -> Action {
+        Action::Reply(vec![b'x'; 16 << 20])
+    }
+
+    fn on_disconnect(&mut self, _ctx: &mut HandlerContext, reason: CloseReason) {
+        self.closed.lock().unwrap().push(reason);
+    }
+}
+
+#[test]
+fn a_peer_that_stops_reading_times_out() {
+    let closed = Arc::new(Mutex::new(Vec::new()));
+    let config = Config {
+        tcp_user_timeout: Some(USER_TIMEOUT),
+        ..Config::new("127.0.0.1:0")
+    };
+    let handler = Recorder { closed: Arc::clone(&closed) };
+    let mut server = Server::builder(config).handler(handler).build().unwrap();
+    // Never read, the window closes once the sockets are full
+    let mut client = connect(&server);
+    send(&mut server, &mut client, b"go");
+    let started = Instant::now();
+
+    let reason = poll_until(&mut server, |_| closed.lock().unwrap().first().copied());
+    let elapsed = started.elapsed();
+    assert_eq!(reason, CloseReason::TimedOut);
+    assert!(elapsed >= USER_TIMEOUT, "timed out after {:?}", elapsed);
+    // At the first zero window probe past it
+    assert!(elapsed < USER_TIMEOUT * 6, "timed out after {:?}", elapsed);
+    let stats = server.stats();
+    assert_eq!((stats.tcp.timeouts, stats.tcp.resets, stats.tcp.errors), (1, 0, 0));
+    drop(client);
+}
@@ -0,0 +1,63 @@
+//! vsock clients over the loopback transport, when the kernel has it.
+
+#![cfg(all(target_os = "linux", feature = "vsock"))]
+
+mod driver;
+
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::os::unix::io::FromRawFd;
+use std::path::Path;
+
+use mio_echo_server::{Config, Server};
+
+use driver::{receive, send};
+
+// Whether connections to the local CID reach the local listeners
+fn loopback() -> bool {
+    Path::new("/sys/module/vsock_loopback").exists()
+}
+
+// A non-blocking client of `port` on the local CID
+fn connect(port: u32) -> io::Result<File> {
+    unsafe {
+        let fd = libc::socket(libc::AF_VSOCK, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let file = File::from_raw_fd(fd);
+        let mut addr: libc::sockaddr_vm = mem::zeroed();
+        addr.svm_family = libc::AF_VSOCK as libc::sa_family_t;
+        addr.svm_cid = libc::VMADDR_CID_LOCAL;
+        addr.svm_port = port;
+        let len = mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t;
+        if libc::connect(fd, &addr as *const _ as *const libc::sockaddr, len) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if libc::fcntl(fd, libc::F_SETFL, libc::O_NONBLOCK) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(file)
+    }
+}
+
+#[test]
+fn vsock_clients_are_echoed() {
+    if !loopback() {
+        eprintln!("skipped: no vsock_loopback module");
+        return;
+    }
+    let port = 20_000 + std::process::id() % 40_000;
+    let config = Config {
+        listen: None,
+        vsock_port: Some(port),
+        ..Config::default()
+    };
+    let mut server = Server::from_config(config).unwrap();
+    // Completed by the kernel, accepted by the next poll
+    let mut client = connect(port).unwrap();
+    send(&mut server, &mut client, b"over vsock");
+    assert_eq!(receive(&mut server, &mut client, 10), b"over vsock");
+    assert_eq!(server.stats().vsock.connections, 1);
+}
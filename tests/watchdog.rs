@@ -0,0 +1,62 @@
+//! The systemd watchdog, with `NOTIFY_SOCKET` and `WATCHDOG_USEC` faked
+//! by a datagram socket of the test and the pings timed by a
+//! `ManualClock`. The variables are process wide, so this file holds a
+//! single test.
+
+#![cfg(unix)]
+
+use std::env;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+use mio_echo_server::{Config, ManualClock, Server};
+
+// WATCHDOG_USEC of 200ms, pinged every 100ms
+const INTERVAL: Duration = Duration::from_millis(100);
+
+fn pings(notify: &UnixDatagram) -> usize {
+    let mut buf = [0; 64];
+    let mut pings = 0;
+    while let Ok(len) = notify.recv(&mut buf) {
+        assert_eq!(&buf[..len], b"WATCHDOG=1");
+        pings += 1;
+    }
+    pings
+}
+
+#[test]
+fn pings_at_half_the_interval_until_the_loop_stalls() {
+    let dir = env::temp_dir().join(format!("mio-echo-server-watchdog-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("notify");
+    let _ = std::fs::remove_file(&path);
+    let notify = UnixDatagram::bind(&path).unwrap();
+    notify.set_nonblocking(true).unwrap();
+    env::set_var("NOTIFY_SOCKET", &path);
+    env::set_var("WATCHDOG_USEC", "200000");
+    env::remove_var("WATCHDOG_PID");
+
+    let clock = ManualClock::new();
+    let mut server = Server::builder(Config::new("127.0.0.1:0")).clock(clock.clone()).build().unwrap();
+    assert_eq!(pings(&notify), 1, "no ping on startup");
+
+    server.poll_once(Some(Duration::ZERO)).unwrap();
+    clock.advance(INTERVAL - Duration::from_millis(1));
+    server.poll_once(Some(Duration::ZERO)).unwrap();
+    assert_eq!(pings(&notify), 0, "pinged early");
+    for _ in 0..5 {
+        clock.advance(INTERVAL);
+        server.poll_once(Some(Duration::ZERO)).unwrap();
+        assert_eq!(pings(&notify), 1);
+    }
+
+    // A stalled loop sends nothing, however late it is, and a single ping
+    // once it runs again
+    clock.advance(INTERVAL * 10);
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(pings(&notify), 0, "pinged while stalled");
+    server.poll_once(Some(Duration::ZERO)).unwrap();
+    assert_eq!(pings(&notify), 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
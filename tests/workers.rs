@@ -0,0 +1,124 @@
+//! `Config::workers_processes`, the binary supervising its workers. They
+//! are processes of their own, so these run the binary rather than a
+//! `Server`.
+
+#![cfg(target_os = "linux")]
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The binary on a free port, killed once dropped.
+struct Binary {
+    child: Child,
+    port: u16,
+}
+
+impl Binary {
+    fn spawn(args: &[&str]) -> Binary {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let child = Command::new(env!("CARGO_BIN_EXE_mio-echo-server"))
+            .arg(format!("127.0.0.1:{}", port))
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        Binary { child, port }
+    }
+
+    // A client echoed once, whichever worker accepts it
+    fn echo(&self, message: &[u8]) {
+        let mut client = TcpStream::connect(("127.0.0.1", self.port)).unwrap();
+        client.set_read_timeout(Some(TIMEOUT)).unwrap();
+        client.write_all(message).unwrap();
+        let mut reply = vec![0; message.len()];
+        client.read_exact(&mut reply).unwrap();
+        assert_eq!(reply, message);
+    }
+
+    // The pids of the workers, from the parent pid in /proc/PID/stat
+    fn workers(&self) -> Vec<u32> {
+        let parent = self.child.id();
+        let mut pids = Vec::new();
+        for entry in fs::read_dir("/proc").unwrap() {
+            let pid: u32 = match entry.unwrap().file_name().to_str().and_then(|name| name.parse().ok()) {
+                Some(pid) => pid,
+                None => continue,
+            };
+            // Gone since the listing
+            let stat = match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+                Ok(stat) => stat,
+                Err(_) => continue,
+            };
+            // pid (comm) state ppid ..., comm may hold spaces
+            let after_comm = &stat[stat.rfind(')').unwrap() + 2..];
+            let fields: Vec<_> = after_comm.split(' ').collect();
+            // Not the zombie of a killed worker
+            if fields[1].parse() == Ok(parent) && fields[0] != "Z" {
+                pids.push(pid);
+            }
+        }
+        pids.sort_unstable();
+        pids
+    }
+}
+
+impl Drop for Binary {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn wait_for<T, F: FnMut() -> Option<T>>(what: &str, mut done: F) -> T {
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        if let Some(value) = done() {
+            return value;
+        }
+        assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn a_killed_worker_is_replaced_while_the_others_serve() {
+    let mut binary = Binary::spawn(&["--workers-processes", "2"]);
+    let workers = wait_for("the workers", || Some(binary.workers()).filter(|pids| pids.len() == 2));
+    wait_for("the server to start", || TcpStream::connect(("127.0.0.1", binary.port)).ok());
+    for i in 0..10 {
+        binary.echo(format!("before {}", i).as_bytes());
+    }
+
+    let killed = workers[0];
+    assert_eq!(unsafe { libc::kill(killed as libc::pid_t, libc::SIGKILL) }, 0);
+    // The survivor takes every connection meanwhile
+    for i in 0..10 {
+        binary.echo(format!("after {}", i).as_bytes());
+    }
+    let respawned = wait_for("the replacement", || {
+        Some(binary.workers()).filter(|pids| pids.len() == 2 && !pids.contains(&killed))
+    });
+    assert!(respawned.contains(&workers[1]), "the survivor was restarted too");
+    for i in 0..10 {
+        binary.echo(format!("respawned {}", i).as_bytes());
+    }
+
+    // SIGTERM drains the workers, the parent prints their totals
+    assert_eq!(unsafe { libc::kill(binary.child.id() as libc::pid_t, libc::SIGTERM) }, 0);
+    let status = wait_for("the exit", || binary.child.try_wait().unwrap());
+    assert!(status.success(), "exited with {}", status);
+    let mut out = String::new();
+    binary.child.stdout.take().unwrap().read_to_string(&mut out).unwrap();
+    let line = out.lines().find(|line| line.starts_with("workers: ")).expect("no summary");
+    let connections: u64 = line["workers: ".len()..].split(' ').next().unwrap().parse().unwrap();
+    // At least those since the kill, the killed worker reports nothing
+    assert!(connections >= 20, "{}", line);
+}
@@ -0,0 +1,53 @@
+//! `Config::write_budget`, a deep queue written a budget at a time while
+//! other clients are served in between.
+
+mod driver;
+
+use std::time::Duration;
+
+use mio_echo_server::{Config, Server};
+
+use driver::{connect, poll_until, read_available, receive, send};
+
+const BUDGET: usize = 64 << 10;
+const BACKLOG: usize = 8 << 20;
+
+#[test]
+fn a_small_echo_is_not_held_up_by_a_backlog() {
+    let config = Config {
+        write_budget: BUDGET,
+        ..Config::new("127.0.0.1:0")
+    };
+    let mut server = Server::from_config(config).unwrap();
+    let mut big = connect(&server);
+    let mut small = connect(&server);
+    send(&mut server, &mut small, b"hello");
+    assert_eq!(receive(&mut server, &mut small, 5), b"hello");
+
+    // Read whole, most of it still queued
+    send(&mut server, &mut big, &vec![b'x'; BACKLOG]);
+    poll_until(&mut server, |server| {
+        Some(()).filter(|()| server.stats().tcp.bytes_read == (5 + BACKLOG) as u64)
+    });
+    let written = server.stats().tcp.bytes_written;
+    assert!(written < (BACKLOG / 2) as u64, "{} written already", written);
+
+    // Echoed by the next round, which writes a budget or so of the backlog
+    send(&mut server, &mut small, b"ping");
+    let mut polls = 0;
+    let mut echoed = Vec::new();
+    while echoed.len() < 4 {
+        let before = server.stats().tcp.bytes_written;
+        server.poll_once(Some(Duration::from_millis(10))).unwrap();
+        let round = server.stats().tcp.bytes_written - before;
+        assert!(round <= (2 * BUDGET + 4) as u64, "{} bytes in a round", round);
+        echoed.extend(read_available(&mut small).0);
+        polls += 1;
+    }
+    assert_eq!(echoed, b"ping");
+    assert!(polls <= 2, "echoed after {} rounds", polls);
+    assert!(server.stats().write_parks > 0);
+
+    // The backlog keeps going without new readiness events
+    assert_eq!(receive(&mut server, &mut big, BACKLOG).len(), BACKLOG);
+}
@@ -0,0 +1,45 @@
+//! `Config::max_write_chunk` and `Config::inter_chunk_delay`, the echo cut
+//! into small writes the client has to reassemble.
+
+mod driver;
+
+use std::time::{Duration, Instant};
+
+use mio_echo_server::{Config, Server};
+
+use driver::{connect, poll_until, read_available, receive, send};
+
+fn chunked_server(chunk: usize, delay: Option<Duration>) -> Server {
+    let config = Config {
+        max_write_chunk: Some(chunk),
+        inter_chunk_delay: delay,
+        ..Config::new("127.0.0.1:0")
+    };
+    Server::from_config(config).unwrap()
+}
+
+#[test]
+fn one_byte_writes_round_trip_a_payload() {
+    let payload: Vec<u8> = (0..4096).map(|i| (i % 251) as u8).collect();
+    let mut server = chunked_server(1, None);
+    let mut client = connect(&server);
+    send(&mut server, &mut client, &payload);
+    assert!(receive(&mut server, &mut client, payload.len()) == payload);
+    assert_eq!(server.stats().tcp.bytes_written, 4096);
+}
+
+#[test]
+fn delayed_chunks_come_one_at_a_time() {
+    let delay = Duration::from_millis(20);
+    let mut server = chunked_server(100, Some(delay));
+    let mut client = connect(&server);
+    send(&mut server, &mut client, &[b'x'; 1000]);
+
+    let first = poll_until(&mut server, |_| Some(read_available(&mut client).0).filter(|data| !data.is_empty()));
+    assert!(first.len() <= 100, "{} bytes at once", first.len());
+    let start = Instant::now();
+    let rest = receive(&mut server, &mut client, 1000 - first.len());
+    assert_eq!(first.len() + rest.len(), 1000);
+    // Nine more chunks, a delay before each
+    assert!(start.elapsed() >= delay * 8, "the rest came in {:?}", start.elapsed());
+}
@@ -0,0 +1,127 @@
+//! `Config::zerocopy`, large echoes written with MSG_ZEROCOPY, their
+//! buffers pinned until the kernel is done with them.
+
+#![cfg(all(target_os = "linux", feature = "zerocopy"))]
+
+mod driver;
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::time::Duration;
+
+use mio_echo_server::{Config, ManualClock, Server};
+
+use driver::{connect, poll_until, receive, receive_to_close};
+
+const THRESHOLD: usize = 64 << 10;
+
+fn zerocopy_server() -> (Server, ManualClock) {
+    let config = Config {
+        zerocopy: Some(THRESHOLD),
+        ..Config::new("127.0.0.1:0")
+    };
+    let clock = ManualClock::new();
+    let server = Server::builder(config).clock(clock.clone()).build().unwrap();
+    (server, clock)
+}
+
+// No two nearby chunks alike, so that a buffer reused before the kernel
+// sent it shows as misplaced data
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i / 1000 * 7 + i) as u8).collect()
+}
+
+// Writes `data` while reading the echo, half-closing after it with
+// `shutdown`, returns the echo once it is as long, or the connection closed
+fn pump(server: &mut Server, client: &mut TcpStream, data: &[u8], shutdown: bool) -> Vec<u8> {
+    let mut written = 0;
+    let mut echo = Vec::new();
+    let mut buf = vec![0; 256 << 10];
+    poll_until(server, |server| {
+        loop {
+            if written == data.len() {
+                break;
+            }
+            match client.write(&data[written..(written + THRESHOLD * 4).min(data.len())]) {
+                Ok(n) => written += n,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => panic!("write: {}", e),
+            }
+            if written == data.len() && shutdown {
+                client.shutdown(Shutdown::Write).unwrap();
+            }
+        }
+        loop {
+            match client.read(&mut buf) {
+                Ok(0) => return Some(()),
+                Ok(n) => echo.extend_from_slice(&buf[..n]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => panic!("read: {}", e),
+            }
+        }
+        server.poll_once(Some(Duration::ZERO)).unwrap();
+        Some(()).filter(|()| !shutdown && echo.len() >= data.len())
+    });
+    echo
+}
+
+#[test]
+fn large_echoes_are_written_with_zerocopy() {
+    let (mut server, _clock) = zerocopy_server();
+    let mut client = connect(&server);
+    client.set_nodelay(true).unwrap();
+    let data = payload(16 << 20);
+    for _ in 0..2 {
+        let echo = pump(&mut server, &mut client, &data, false);
+        assert!(echo == data, "echo corrupted");
+    }
+    let zerocopy = server.stats().zerocopy;
+    assert!(zerocopy.writes > 0, "{:?}", zerocopy);
+    assert!(zerocopy.bytes >= data.len() as u64 && zerocopy.bytes <= 2 * data.len() as u64, "{:?}", zerocopy);
+    assert_eq!((zerocopy.unsupported, zerocopy.abandoned), (0, 0));
+}
+
+#[test]
+fn small_echoes_are_written_as_usual() {
+    let (mut server, _clock) = zerocopy_server();
+    let mut client = connect(&server);
+    for i in 0..10 {
+        let message = format!("message {}", i);
+        client.write_all(message.as_bytes()).unwrap();
+        assert_eq!(receive(&mut server, &mut client, message.len()), message.as_bytes());
+    }
+    assert_eq!(server.stats().zerocopy.writes, 0);
+}
+
+#[test]
+fn a_closed_client_keeps_its_socket_until_the_sends_complete() {
+    let (mut server, clock) = zerocopy_server();
+    let mut client = connect(&server);
+    let data = payload(8 << 20);
+    // Unread, the echo sent so far stays pinned in the receive queue of the
+    // client
+    let mut written = 0;
+    for _ in 0..50 {
+        match client.write(&data[written..]) {
+            Ok(n) => written += n,
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => panic!("write: {}", e),
+        }
+        server.poll_once(Some(Duration::from_millis(10))).unwrap();
+    }
+    assert!(server.stats().zerocopy.writes > 0, "{:?}", server.stats().zerocopy);
+    // The server closes on the half-close, dropping the echo it hadn't
+    // sent yet
+    client.shutdown(Shutdown::Write).unwrap();
+    poll_until(&mut server, |server| Some(()).filter(|()| server.stats().zerocopy.lingered == 1));
+
+    let echo = receive_to_close(&mut server, &mut client);
+    assert!(!echo.is_empty() && data[..written].starts_with(&echo), "echo corrupted");
+    // Freed on a check once the reads completed the sends, long before it
+    // would be reset
+    for _ in 0..61 {
+        clock.advance(Duration::from_secs(1));
+        server.poll_once(Some(Duration::ZERO)).unwrap();
+    }
+    assert_eq!(server.stats().zerocopy.abandoned, 0);
+}